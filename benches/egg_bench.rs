@@ -0,0 +1,85 @@
+//! Runs egg's [`BenchReport`] scenarios, printing the report as JSON.
+//!
+//! ```text
+//! cargo bench --features bench --bench egg-bench -- [options]
+//!
+//!   --seed N           the seed the scenarios are made from (default 42)
+//!   --samples N        how many times to run each scenario (default 5)
+//!   --out FILE         write the JSON report to FILE instead of stdout
+//!   --baseline FILE    compare against the JSON report in FILE, and exit
+//!                      with an error if any metric regressed
+//!   --threshold P      how many percent a metric may regress (default 10)
+//! ```
+
+use std::process::exit;
+
+use egg::*;
+
+fn main() {
+    let mut seed = 42;
+    let mut samples = 5;
+    let mut out = None;
+    let mut baseline = None;
+    let mut threshold = 10.0;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().unwrap_or_else(|| {
+                eprintln!("Missing value for {}", arg);
+                exit(2)
+            })
+        };
+        match arg.as_str() {
+            "--seed" => seed = parse(&value()),
+            "--samples" => samples = parse(&value()),
+            "--out" => out = Some(value()),
+            "--baseline" => baseline = Some(value()),
+            "--threshold" => threshold = parse(&value()),
+            // passed by cargo bench
+            "--bench" => {}
+            _ => eprintln!("Ignoring unknown argument {}", arg),
+        }
+    }
+
+    let report = BenchReport::run(seed, samples);
+    eprint!("{}", report);
+    match &out {
+        Some(path) => std::fs::write(path, report.to_json()).unwrap_or_else(|e| {
+            eprintln!("Couldn't write {}: {}", path, e);
+            exit(2)
+        }),
+        None => println!("{}", report.to_json()),
+    }
+
+    if let Some(path) = baseline {
+        let json = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("Couldn't read {}: {}", path, e);
+            exit(2)
+        });
+        let baseline = BenchReport::from_json(&json).unwrap_or_else(|e| {
+            eprintln!("Couldn't load {}: {}", path, e);
+            exit(2)
+        });
+        let regressions = report.regressions(&baseline, threshold);
+        for regression in &regressions {
+            eprintln!("{}", regression);
+        }
+        if !regressions.is_empty() {
+            eprintln!(
+                "{} metrics regressed by more than {}%",
+                regressions.len(),
+                threshold
+            );
+            exit(1);
+        }
+        eprintln!("No metric regressed by more than {}%", threshold);
+    }
+}
+
+fn parse<T: std::str::FromStr>(s: &str) -> T {
+    s.parse().unwrap_or_else(|_| {
+        eprintln!("Couldn't parse {}", s);
+        exit(2)
+    })
+}
@@ -0,0 +1,418 @@
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use crate::*;
+
+/// The associative-commutative operators of an [`EGraph`], set with
+/// [`EGraph::with_flattening`].
+pub(crate) struct Flattening<L> {
+    /// The operators, by [`op_display`](Language::op_display). There
+    /// are only ever a few, so a scan beats hashing the names.
+    ops: Vec<String>,
+    /// The flattened patterns instantiated so far, so each is only
+    /// flattened once rather than once per match.
+    patterns: Mutex<HashMap<Vec<ENodeOrVar<L>>, Arc<[ENodeOrVar<L>]>>>,
+}
+
+impl<L: Clone> Clone for Flattening<L> {
+    fn clone(&self) -> Self {
+        Self {
+            ops: self.ops.clone(),
+            patterns: Mutex::new(self.patterns.lock().unwrap().clone()),
+        }
+    }
+}
+
+/// The nodes of a pattern, [flattened](EGraph::with_flattening) if the
+/// egraph flattens, as [`EGraph::flattened_pattern`] returns them.
+pub(crate) enum FlatPattern<'a, L> {
+    /// The pattern as it was, since the egraph doesn't flatten.
+    Borrowed(&'a [ENodeOrVar<L>]),
+    /// The cached flattening of the pattern.
+    Cached(Arc<[ENodeOrVar<L>]>),
+}
+
+impl<'a, L> Deref for FlatPattern<'a, L> {
+    type Target = [ENodeOrVar<L>];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            FlatPattern::Borrowed(nodes) => nodes,
+            FlatPattern::Cached(nodes) => nodes,
+        }
+    }
+}
+
+impl<L: Language> Flattening<L> {
+    pub(crate) fn new<I, S>(ops: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            ops: ops.into_iter().map(Into::into).collect(),
+            patterns: Default::default(),
+        }
+    }
+
+    /// Whether `node` is an application of one of the operators.
+    pub(crate) fn is_flattened(&self, node: &L) -> bool {
+        !node.is_leaf() && self.ops.iter().any(|op| op == node.op_display())
+    }
+
+    /// Sorts the children of `node`, if it's an application of one of
+    /// the operators.
+    pub(crate) fn sort(&self, node: &mut L) {
+        if self.is_flattened(node) {
+            node.children_mut().sort_unstable();
+        }
+    }
+
+    /// Flattens the nodes of a term, whose children are indices of
+    /// earlier nodes, where `enode` gives the [`Language`] node of a
+    /// term node, if it has one.
+    ///
+    /// Each application of an operator takes the children of those of
+    /// its children that apply the same operator, so the result has the
+    /// same nodes at the same indices, but the flattened ones are
+    /// n-ary.
+    /// Nodes that [`Language::with_children`] can't rebuild with the
+    /// new arity are kept as they are.
+    pub(crate) fn flatten_term<T: Language>(
+        &self,
+        nodes: &[T],
+        enode: impl Fn(&T) -> Option<&L>,
+        wrap: impl Fn(L) -> T,
+    ) -> Vec<T> {
+        let mut flat: Vec<T> = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            let flattened = enode(node).filter(|n| self.is_flattened(n)).and_then(|n| {
+                let op = n.op_display();
+                let mut children = vec![];
+                for &child in n.children() {
+                    match enode(&flat[usize::from(child)]) {
+                        Some(c) if self.is_flattened(c) && c.op_display() == op => {
+                            children.extend_from_slice(c.children())
+                        }
+                        _ => children.push(child),
+                    }
+                }
+                n.with_children(children)
+            });
+            flat.push(flattened.map_or_else(|| node.clone(), &wrap));
+        }
+        flat
+    }
+
+    /// [Flattens](Flattening::flatten_term) an expression.
+    pub(crate) fn flatten_expr(&self, nodes: &[L]) -> Vec<L> {
+        self.flatten_term(nodes, |n| Some(n), |n| n)
+    }
+
+    /// [Flattens](Flattening::flatten_term) a pattern.
+    pub(crate) fn flatten_pattern(&self, nodes: &[ENodeOrVar<L>]) -> Vec<ENodeOrVar<L>> {
+        self.flatten_term(nodes, pattern_enode, ENodeOrVar::ENode)
+    }
+
+    /// [Flattens](Flattening::flatten_term) a pattern, or returns its
+    /// flattening from an earlier call.
+    pub(crate) fn flatten_pattern_cached(&self, nodes: &[ENodeOrVar<L>]) -> Arc<[ENodeOrVar<L>]> {
+        let mut patterns = self.patterns.lock().unwrap();
+        if let Some(flat) = patterns.get(nodes) {
+            return flat.clone();
+        }
+        let flat: Arc<[ENodeOrVar<L>]> = self.flatten_pattern(nodes).into();
+        patterns.insert(nodes.to_vec(), flat.clone());
+        flat
+    }
+
+    /// Flattens an enode of eclass `class` whose children are
+    /// canonical, taking in place of each child the children of an
+    /// application of the same operator in that child's eclass, where
+    /// `nodes` gives the enodes of an eclass and `find` canonicalizes
+    /// their children.
+    ///
+    /// Applications with the child itself as one of their children are
+    /// skipped.
+    /// Returns `None` if no child other than `class` itself has such an
+    /// application, or [`Language::with_children`] can't rebuild the
+    /// operator with the new arity.
+    pub(crate) fn flatten_node<'a>(
+        &self,
+        node: &L,
+        class: Id,
+        nodes: impl Fn(Id) -> &'a [L],
+        find: impl Fn(Id) -> Id,
+    ) -> Option<L>
+    where
+        L: 'a,
+    {
+        if !self.is_flattened(node) {
+            return None;
+        }
+        let op = node.op_display();
+        let mut spliced = false;
+        let mut children = vec![];
+        for &child in node.children() {
+            // an application with the child in it, like x = (+ x 0),
+            // would only grow the enode each time the child is merged
+            let same_op = nodes(child)
+                .iter()
+                .find(|n| !n.is_leaf() && n.op_display() == op && !n.any(|c| find(c) == child));
+            match same_op {
+                Some(n) if child != class => {
+                    children.extend_from_slice(n.children());
+                    spliced = true;
+                }
+                _ => children.push(child),
+            }
+        }
+        if !spliced {
+            return None;
+        }
+        let mut flat = node.with_children(children)?;
+        self.sort(&mut flat);
+        Some(flat)
+    }
+
+    /// Every reordering of the children of the operator applications in
+    /// the subpattern at `i`, each with its root last.
+    fn orderings(&self, nodes: &[ENodeOrVar<L>], i: Id) -> Vec<Vec<ENodeOrVar<L>>> {
+        let node = &nodes[usize::from(i)];
+        let children = node.children();
+        let child_orderings: Vec<_> = children
+            .iter()
+            .map(|&child| self.orderings(nodes, child))
+            .collect();
+        let orders = match node {
+            ENodeOrVar::ENode(n) if self.is_flattened(n) => permutations(children.len()),
+            _ => vec![(0..children.len()).collect()],
+        };
+
+        let mut seen = HashSet::default();
+        let mut result = vec![];
+        for order in orders {
+            // pick an ordering of each child, laying them out one after
+            // another and keeping the index of each one's root
+            let mut partial: Vec<(Vec<ENodeOrVar<L>>, Vec<Id>)> = vec![(vec![], vec![])];
+            for &c in &order {
+                let mut next = vec![];
+                for (prefix, roots) in &partial {
+                    for child in &child_orderings[c] {
+                        let offset = prefix.len();
+                        let mut prefix = prefix.clone();
+                        prefix.extend(child.iter().map(|n| {
+                            n.clone()
+                                .map_children(|id| Id::from(usize::from(id) + offset))
+                        }));
+                        let mut roots = roots.clone();
+                        roots.push(Id::from(prefix.len() - 1));
+                        next.push((prefix, roots));
+                    }
+                }
+                partial = next;
+            }
+            for (mut prefix, roots) in partial {
+                let mut roots = roots.into_iter();
+                prefix.push(node.clone().map_children(|_| roots.next().unwrap()));
+                if seen.insert(prefix.clone()) {
+                    result.push(prefix);
+                }
+            }
+        }
+        result
+    }
+}
+
+fn pattern_enode<L>(node: &ENodeOrVar<L>) -> Option<&L> {
+    match node {
+        ENodeOrVar::ENode(n) => Some(n),
+        ENodeOrVar::Var(_) => None,
+    }
+}
+
+/// All the orders of `0..n`.
+fn permutations(n: usize) -> Vec<Vec<usize>> {
+    let mut perms = vec![vec![]];
+    for i in 0..n {
+        perms = perms
+            .into_iter()
+            .flat_map(|p: Vec<usize>| {
+                (0..=p.len()).map(move |at| {
+                    let mut p = p.clone();
+                    p.insert(at, i);
+                    p
+                })
+            })
+            .collect();
+    }
+    perms
+}
+
+/// A [`Searcher`] for [flattened](EGraph::with_flattening) egraphs that
+/// matches a [`Pattern`] up to associativity and commutativity.
+///
+/// Flattening turns `(+ a (+ b c))` into the single enode `(+ a b c)`,
+/// with its children sorted by eclass, so a plain [`Pattern`] like
+/// `(+ ?x (+ ?y 0))` matches neither that enode nor, in general, any
+/// enode whose children are in an order other than the one it's
+/// written in.
+/// An [`AcPattern`] flattens the pattern the same way, to
+/// `(+ ?x ?y 0)`, and tries every order of the children of each
+/// flattened operator, so it matches wherever some order does.
+///
+/// Matching is by arity: `(+ ?x 0)` matches `(+ a 0)` but not
+/// `(+ a b 0)`, since there is no variable for "the rest" of the
+/// children.
+/// The number of orders is the product of the factorials of the
+/// arities, so this is meant for patterns with a few small operator
+/// applications.
+///
+/// # Example
+/// ```
+/// use egg::{*, SymbolLang as S};
+/// let mut egraph = EGraph::<S, ()>::default().with_flattening(vec!["+"]);
+/// let root = egraph.add_expr(&"(+ x (+ 0 y))".parse().unwrap());
+/// egraph.rebuild();
+///
+/// let zero = AcPattern::new("(+ (+ ?a 0) ?b)".parse().unwrap(), vec!["+"]);
+/// let matches = zero.search(&egraph);
+/// assert_eq!(matches.len(), 1);
+/// assert_eq!(matches[0].eclass, root);
+/// // ?a and ?b are x and y both ways round
+/// assert_eq!(matches[0].substs.len(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AcPattern<L> {
+    pattern: Pattern<L>,
+    orderings: Vec<Pattern<L>>,
+}
+
+impl<L: Language> AcPattern<L> {
+    /// Creates an [`AcPattern`] that matches `pattern` up to
+    /// associativity and commutativity of `ops`, which should be the
+    /// operators the egraph is [flattened](EGraph::with_flattening) on.
+    pub fn new<I, S>(pattern: Pattern<L>, ops: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let flattening = Flattening::new(ops);
+        let flat = flattening.flatten_pattern(pattern.ast.as_ref());
+        let orderings = match flat.len() {
+            0 => vec![],
+            n => flattening
+                .orderings(&flat, Id::from(n - 1))
+                .into_iter()
+                .map(|nodes| Pattern::new(RecExpr::from(nodes)))
+                .collect(),
+        };
+        Self { pattern, orderings }
+    }
+
+    /// The pattern as it was written.
+    pub fn pattern(&self) -> &Pattern<L> {
+        &self.pattern
+    }
+
+    /// The flattened patterns this one searches for, one per order of
+    /// the operators' children.
+    pub fn orderings(&self) -> &[Pattern<L>] {
+        &self.orderings
+    }
+}
+
+impl<L: Language, N: Analysis<L>> Searcher<L, N> for AcPattern<L> {
+    fn search(&self, egraph: &EGraph<L, N>) -> Vec<SearchMatches<L>> {
+        match self.pattern.ast.as_ref().last() {
+            Some(ENodeOrVar::ENode(e)) => {
+                #[allow(clippy::mem_discriminant_non_enum)]
+                let key = std::mem::discriminant(e);
+                match egraph.classes_by_op.get(&key) {
+                    None => vec![],
+                    Some(ids) => ids
+                        .iter()
+                        .filter_map(|&id| self.search_eclass(egraph, id))
+                        .collect(),
+                }
+            }
+            Some(ENodeOrVar::Var(_)) => egraph
+                .classes()
+                .filter_map(|e| self.search_eclass(egraph, e.id))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn search_eclass(&self, egraph: &EGraph<L, N>, eclass: Id) -> Option<SearchMatches<L>> {
+        self.search_eclass_with_limit(egraph, eclass, usize::MAX)
+    }
+
+    fn search_eclass_with_limit(
+        &self,
+        egraph: &EGraph<L, N>,
+        eclass: Id,
+        limit: usize,
+    ) -> Option<SearchMatches<L>> {
+        let mut seen = HashSet::default();
+        let mut substs = vec![];
+        for pattern in &self.orderings {
+            if substs.len() >= limit {
+                break;
+            }
+            let found = pattern.search_eclass_with_limit(egraph, eclass, limit - substs.len());
+            for subst in found.into_iter().flat_map(|m| m.substs) {
+                // different orders can find the same match
+                if seen.insert(HashableCanonicalSubst::new(&subst, egraph)) {
+                    substs.push(subst);
+                }
+            }
+        }
+        if substs.is_empty() {
+            None
+        } else {
+            Some(SearchMatches {
+                eclass: egraph.find(eclass),
+                substs,
+                ast: None,
+            })
+        }
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.pattern.vars()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolLang as S, *};
+
+    #[test]
+    fn orderings_are_flattened_and_deduped() {
+        let pattern = |s: &str| s.parse::<Pattern<S>>().unwrap();
+        let ac = AcPattern::new(pattern("(+ ?a (+ ?b (* ?c ?d)))"), vec!["+"]);
+        // 3! orders of the sum, and the product is left alone
+        assert_eq!(ac.orderings().len(), 6);
+        assert!(ac.orderings().contains(&pattern("(+ (* ?c ?d) ?a ?b)")));
+        assert!(!ac.orderings().contains(&pattern("(+ ?a ?b (* ?d ?c))")));
+
+        let ac = AcPattern::new(pattern("(+ ?a ?a)"), vec!["+"]);
+        assert_eq!(ac.orderings(), &[pattern("(+ ?a ?a)")]);
+        let ac = AcPattern::new(pattern("(+ (+ ?a ?b) (+ ?c ?d))"), vec!["+"]);
+        assert_eq!(ac.orderings().len(), 24);
+    }
+
+    #[test]
+    fn patterns_are_flattened_once() {
+        let egraph = EGraph::<S, ()>::default().with_flattening(vec!["+"]);
+        let pattern: PatternAst<S> = "(+ ?a (+ ?b ?c))".parse().unwrap();
+        let flat = |egraph: &EGraph<S, ()>| match egraph.flattened_pattern(pattern.as_ref()) {
+            FlatPattern::Cached(nodes) => nodes,
+            FlatPattern::Borrowed(_) => panic!("The egraph flattens"),
+        };
+        let first = flat(&egraph);
+        assert_eq!(first.last().unwrap().len(), 3);
+        assert!(std::sync::Arc::ptr_eq(&first, &flat(&egraph)));
+    }
+}
@@ -0,0 +1,306 @@
+use std::any::{type_name, Any, TypeId};
+use std::fmt::{self, Debug};
+
+use crate::*;
+
+/// Metadata attached to eclasses, independent of the [`Analysis`].
+///
+/// Each type of annotation is a separate layer, registered with
+/// [`EGraph::annotate`]; layers of different types coexist.
+/// Set and read an eclass's annotation with
+/// [`EGraph::set_annotation`] and [`EGraph::get_annotation`].
+/// Eclasses don't need to have one.
+///
+/// Annotations are not part of the egraph's
+/// [serialization](EGraph::write_binary).
+///
+/// # Example
+/// ```
+/// use egg::{*, SymbolLang as S};
+///
+/// /// Eclasses rewrites should leave alone.
+/// #[derive(Clone, Debug, PartialEq)]
+/// struct DoNotTouch(bool);
+/// impl ClassAnnotation for DoNotTouch {
+///     fn merge(winner: &mut Self, loser: Self) {
+///         winner.0 |= loser.0;
+///     }
+/// }
+///
+/// let mut egraph = EGraph::<S, ()>::default();
+/// egraph.annotate::<DoNotTouch>();
+/// let (x, y) = (egraph.add(S::leaf("x")), egraph.add(S::leaf("y")));
+/// egraph.set_annotation(x, DoNotTouch(true));
+/// egraph.union(x, y);
+/// assert_eq!(egraph.get_annotation(y), Some(&DoNotTouch(true)));
+/// ```
+pub trait ClassAnnotation: Any + Clone + Send + Sync {
+    /// Merge the annotation of eclass `loser` into that of `winner`,
+    /// when they are unioned.
+    ///
+    /// This is only called if both eclasses have an annotation of this
+    /// type; otherwise the unioned eclass keeps the one it has, if any.
+    fn merge(winner: &mut Self, loser: Self);
+}
+
+/// The annotations of one type, type-erased.
+trait AnnotationLayer: Send + Sync {
+    fn merge(&mut self, winner: Id, loser: Id);
+    fn clear(&mut self);
+    fn clone_layer(&self) -> Box<dyn AnnotationLayer>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// The annotations of type `T`, by canonical eclass id.
+struct Layer<T>(HashMap<Id, T>);
+
+impl<T: ClassAnnotation> AnnotationLayer for Layer<T> {
+    fn merge(&mut self, winner: Id, loser: Id) {
+        if let Some(loser) = self.0.remove(&loser) {
+            match self.0.get_mut(&winner) {
+                Some(winner) => T::merge(winner, loser),
+                None => {
+                    self.0.insert(winner, loser);
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    fn clone_layer(&self) -> Box<dyn AnnotationLayer> {
+        Box::new(Layer(self.0.clone()))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// The [annotation](ClassAnnotation) layers of an [`EGraph`].
+#[derive(Default)]
+pub(crate) struct Annotations {
+    layers: HashMap<TypeId, (&'static str, Box<dyn AnnotationLayer>)>,
+}
+
+impl Clone for Annotations {
+    fn clone(&self) -> Self {
+        let layers = self.layers.iter();
+        Self {
+            layers: layers
+                .map(|(&ty, (name, layer))| (ty, (*name, layer.clone_layer())))
+                .collect(),
+        }
+    }
+}
+
+impl Debug for Annotations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.layers.values().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl Annotations {
+    /// Forget every annotation, keeping the layers registered.
+    pub(crate) fn clear(&mut self) {
+        for (_, layer) in self.layers.values_mut() {
+            layer.clear();
+        }
+    }
+
+    fn layer<T: ClassAnnotation>(&self) -> Option<&HashMap<Id, T>> {
+        let (_, layer) = self.layers.get(&TypeId::of::<T>())?;
+        layer.as_any().downcast_ref::<Layer<T>>().map(|l| &l.0)
+    }
+
+    fn layer_mut<T: ClassAnnotation>(&mut self) -> Option<&mut HashMap<Id, T>> {
+        let (_, layer) = self.layers.get_mut(&TypeId::of::<T>())?;
+        layer
+            .as_any_mut()
+            .downcast_mut::<Layer<T>>()
+            .map(|l| &mut l.0)
+    }
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+    /// Register a layer of annotations of type `T`, so eclasses can be
+    /// [annotated](EGraph::set_annotation) with it.
+    ///
+    /// Registering a layer again keeps its annotations.
+    pub fn annotate<T: ClassAnnotation>(&mut self) {
+        self.annotations
+            .layers
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| (type_name::<T>(), Box::new(Layer::<T>(Default::default()))));
+    }
+
+    /// Set the annotation of type `T` of eclass `id`, returning the old
+    /// one.
+    ///
+    /// Panics if there's no layer of `T`; register it with
+    /// [`annotate`](EGraph::annotate) first.
+    /// See [`try_set_annotation`](EGraph::try_set_annotation) for a
+    /// version that fails instead.
+    pub fn set_annotation<T: ClassAnnotation>(&mut self, id: Id, value: T) -> Option<T> {
+        self.try_set_annotation(id, value)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Set the annotation of type `T` of eclass `id`, returning the old
+    /// one, or fail with [`Error::NoAnnotationLayer`] if there's no layer
+    /// of `T`.
+    pub fn try_set_annotation<T: ClassAnnotation>(
+        &mut self,
+        id: Id,
+        value: T,
+    ) -> Result<Option<T>, Error> {
+        let id = self.find(id);
+        let layer = self
+            .annotations
+            .layer_mut::<T>()
+            .ok_or_else(|| Error::NoAnnotationLayer(type_name::<T>()))?;
+        Ok(layer.insert(id, value))
+    }
+
+    /// Returns the annotation of type `T` of eclass `id`, if it has one.
+    pub fn get_annotation<T: ClassAnnotation>(&self, id: Id) -> Option<&T> {
+        self.annotations.layer::<T>()?.get(&self.find(id))
+    }
+
+    /// Merge the annotations of eclass `loser` into `winner`, which it
+    /// is being unioned into.
+    pub(crate) fn merge_annotations(&mut self, winner: Id, loser: Id) {
+        for (_, layer) in self.annotations.layers.values_mut() {
+            layer.merge(winner, loser);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Color(u8);
+    impl ClassAnnotation for Color {
+        fn merge(winner: &mut Self, loser: Self) {
+            winner.0 = winner.0.min(loser.0);
+        }
+    }
+
+    /// A source span, and how many eclasses were merged into it.
+    #[derive(Clone, Debug, PartialEq)]
+    struct Span {
+        start: usize,
+        end: usize,
+        merged: usize,
+    }
+    impl ClassAnnotation for Span {
+        fn merge(winner: &mut Self, loser: Self) {
+            winner.start = winner.start.min(loser.start);
+            winner.end = winner.end.max(loser.end);
+            winner.merged += loser.merged;
+        }
+    }
+
+    fn span(start: usize, end: usize) -> Span {
+        Span {
+            start,
+            end,
+            merged: 1,
+        }
+    }
+
+    #[test]
+    fn two_layers_merge_on_union() {
+        let mut egraph = EGraph::<S, ()>::default();
+        egraph.annotate::<Color>();
+        egraph.annotate::<Span>();
+        let (a, b) = (egraph.add(S::leaf("a")), egraph.add(S::leaf("b")));
+        let fa = egraph.add(S::new("f", vec![a]));
+        let fb = egraph.add(S::new("f", vec![b]));
+        egraph.set_annotation(a, Color(3));
+        egraph.set_annotation(b, Color(1));
+        egraph.set_annotation(fa, span(0, 5));
+        egraph.set_annotation(fb, span(10, 12));
+        assert_eq!(egraph.set_annotation(fb, span(8, 12)), Some(span(10, 12)));
+
+        egraph.union(a, b);
+        assert_eq!(egraph.get_annotation(a), Some(&Color(1)));
+        assert_eq!(egraph.get_annotation::<Span>(a), None);
+        // f(a) and f(b) are only merged by congruence
+        assert_eq!(egraph.get_annotation(fa), Some(&span(0, 5)));
+        egraph.rebuild();
+        let merged = Span {
+            start: 0,
+            end: 12,
+            merged: 2,
+        };
+        assert_eq!(egraph.get_annotation(fb), Some(&merged));
+        assert_eq!(egraph.get_annotation::<Color>(fa), None);
+
+        // registering again keeps the annotations
+        egraph.annotate::<Color>();
+        assert_eq!(egraph.get_annotation(b), Some(&Color(1)));
+    }
+
+    #[test]
+    fn annotations_merge_through_runs() {
+        let mut egraph = EGraph::<S, ()>::default();
+        egraph.annotate::<Span>();
+        egraph.annotate::<Color>();
+        let exprs = ["(+ a b)", "(+ b a)", "(+ (+ a b) c)", "(+ a (+ b c))"];
+        for (i, expr) in exprs.iter().enumerate() {
+            let id = egraph.add_expr(&expr.parse().unwrap());
+            egraph.set_annotation(id, span(i, i + 1));
+            egraph.set_annotation(id, Color(i as u8));
+        }
+        let n_annotated = egraph.number_of_classes();
+        for class in egraph.classes().map(|c| c.id).collect::<Vec<_>>() {
+            if egraph.get_annotation::<Span>(class).is_none() {
+                egraph.set_annotation(class, span(100, 100));
+            }
+        }
+
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rw!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rw!("assoc"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+        ];
+        let runner = Runner::default().with_egraph(egraph).run(&rules);
+        let egraph = &runner.egraph;
+
+        // no span was lost, and the equal roots' were merged
+        let spans = egraph
+            .classes()
+            .filter_map(|c| egraph.get_annotation::<Span>(c.id));
+        assert_eq!(spans.map(|s| s.merged).sum::<usize>(), n_annotated);
+        let merged = |start, end| Span {
+            start,
+            end,
+            merged: 2,
+        };
+        let ab = egraph.lookup_expr(&exprs[0].parse().unwrap()).unwrap();
+        assert_eq!(egraph.get_annotation(ab), Some(&merged(0, 2)));
+        assert_eq!(egraph.get_annotation(ab), Some(&Color(0)));
+        let abc = egraph.lookup_expr(&exprs[3].parse().unwrap()).unwrap();
+        assert_eq!(egraph.get_annotation(abc), Some(&merged(2, 4)));
+        assert_eq!(egraph.get_annotation(abc), Some(&Color(2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "No annotation layer")]
+    fn unregistered_layer_panics() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let a = egraph.add(S::leaf("a"));
+        egraph.set_annotation(a, Color(0));
+    }
+}
@@ -0,0 +1,307 @@
+use std::hash::Hash;
+
+use crate::*;
+
+/// The least general [`Pattern`] that matches two terms, from
+/// [`anti_unify`] or [`anti_unify_classes`], and what its variables
+/// stand for on each side.
+///
+/// The variables are named `?v0`, `?v1`, ... in the order they first
+/// appear in the pattern, and the bindings list them in that order too.
+#[derive(Debug, Clone)]
+pub struct Generalization<L, B> {
+    /// The pattern that matches both sides.
+    pub pattern: Pattern<L>,
+    /// What the variables stand for to match the left side.
+    pub left: B,
+    /// What the variables stand for to match the right side.
+    pub right: B,
+}
+
+fn fresh_var(i: usize) -> Var {
+    format!("?v{}", i).parse().unwrap()
+}
+
+/// The state of an anti-unification, generic over what the two sides'
+/// positions are.
+struct AntiUnifier<L, K> {
+    ast: PatternAst<L>,
+    /// The variable of each pair of mismatched positions, so the same
+    /// pair gets the same variable, in the order they were made.
+    vars: IndexMap<(K, K), Var>,
+}
+
+impl<L: Language, K: Hash + Eq> AntiUnifier<L, K> {
+    fn new() -> Self {
+        Self {
+            ast: Default::default(),
+            vars: Default::default(),
+        }
+    }
+
+    fn var(&mut self, a: K, b: K) -> Id {
+        let n = self.vars.len();
+        let var = *self.vars.entry((a, b)).or_insert_with(|| fresh_var(n));
+        self.ast.add(ENodeOrVar::Var(var))
+    }
+
+    /// Generalize the nodes `a` and `b`, calling `child` on each pair of
+    /// their children if they match, or making a variable for the
+    /// positions `keys` returns if they don't.
+    fn node(
+        &mut self,
+        a: &L,
+        b: &L,
+        keys: impl FnOnce() -> (K, K),
+        mut child: impl FnMut(&mut Self, Id, Id) -> Id,
+    ) -> Id {
+        if !a.matches(b) {
+            let (ka, kb) = keys();
+            return self.var(ka, kb);
+        }
+        let mut children = b.children().iter();
+        let node = a.clone().map_children(|ca| {
+            let cb = *children.next().unwrap();
+            child(self, ca, cb)
+        });
+        self.ast.add(ENodeOrVar::ENode(node))
+    }
+}
+
+/// Anti-unify the terms `a` and `b`: find the least general [`Pattern`]
+/// that matches both.
+///
+/// The pattern keeps every enode the two terms share from the root
+/// down, and has a variable wherever they differ, either in the
+/// operator or in the number of children.
+/// The same pair of differing subterms gets the same variable, so
+/// `(+ (f x) (f x))` and `(+ (g y) (g y))` generalize to `(+ ?v0 ?v0)`.
+///
+/// # Panics
+///
+/// Panics if `a` or `b` is empty.
+///
+/// # Example
+/// ```
+/// use egg::{*, SymbolLang as S};
+/// let a: RecExpr<S> = "(+ (* x 2) (* x 2))".parse().unwrap();
+/// let b: RecExpr<S> = "(+ (* y 2) (* y 2))".parse().unwrap();
+/// let g = anti_unify(&a, &b);
+/// assert_eq!(g.pattern.to_string(), "(+ (* ?v0 2) (* ?v0 2))");
+/// let v0: Var = "?v0".parse().unwrap();
+/// let (x, y): (RecExpr<S>, RecExpr<S>) = ("x".parse().unwrap(), "y".parse().unwrap());
+/// assert_eq!(g.left, vec![(v0, x)]);
+/// assert_eq!(g.right, vec![(v0, y)]);
+/// ```
+pub fn anti_unify<L: Language>(
+    a: &RecExpr<L>,
+    b: &RecExpr<L>,
+) -> Generalization<L, Vec<(Var, RecExpr<L>)>> {
+    fn go<L: Language>(
+        au: &mut AntiUnifier<L, RecExpr<L>>,
+        a: &RecExpr<L>,
+        ia: Id,
+        b: &RecExpr<L>,
+        ib: Id,
+    ) -> Id {
+        // subterms are keyed by their structure, not their position
+        au.node(
+            &a[ia],
+            &b[ib],
+            || (a.extract(ia), b.extract(ib)),
+            |au, ca, cb| go(au, a, ca, b, cb),
+        )
+    }
+
+    let root = |e: &RecExpr<L>| {
+        let n = e.as_ref().len();
+        assert!(n > 0, "can't anti-unify an empty expression");
+        Id::from(n - 1)
+    };
+    let mut au = AntiUnifier::new();
+    go(&mut au, a, root(a), b, root(b));
+
+    let mut left = Vec::with_capacity(au.vars.len());
+    let mut right = Vec::with_capacity(au.vars.len());
+    for ((a, b), var) in au.vars {
+        left.push((var, a));
+        right.push((var, b));
+    }
+    Generalization {
+        pattern: Pattern::from(au.ast),
+        left,
+        right,
+    }
+}
+
+/// Anti-unify the eclasses `a` and `b` of `egraph`, like [`anti_unify`]
+/// on their smallest terms.
+///
+/// Each eclass is represented by its smallest enode, by [`AstSize`], and
+/// the pattern is at most `depth_limit` enodes deep: deeper positions
+/// become variables even if the two sides agree.
+/// The variables are bound to the canonical eclasses they stand for on
+/// each side, so each binding is a [`Subst`] for the pattern, and the
+/// same pair of eclasses gets the same variable.
+///
+/// # Panics
+///
+/// Panics if a representative can't be extracted, or if `egraph` isn't
+/// [clean](EGraph::rebuild).
+///
+/// # Example
+/// ```
+/// use egg::{*, SymbolLang as S};
+/// let mut egraph = EGraph::<S, ()>::default();
+/// let a = egraph.add_expr(&"(f (g x) (g x))".parse().unwrap());
+/// let b = egraph.add_expr(&"(f (h y) (h y))".parse().unwrap());
+/// egraph.rebuild();
+///
+/// let g = anti_unify_classes(&egraph, a, b, 10);
+/// assert_eq!(g.pattern.to_string(), "(f ?v0 ?v0)");
+/// let v0: Var = "?v0".parse().unwrap();
+/// assert_eq!(Some(g.left[v0]), egraph.lookup_expr(&"(g x)".parse().unwrap()));
+/// assert_eq!(Some(g.right[v0]), egraph.lookup_expr(&"(h y)".parse().unwrap()));
+///
+/// let g = anti_unify_classes(&egraph, a, b, 0);
+/// assert_eq!(g.pattern.to_string(), "?v0");
+/// ```
+pub fn anti_unify_classes<L, N>(
+    egraph: &EGraph<L, N>,
+    a: Id,
+    b: Id,
+    depth_limit: usize,
+) -> Generalization<L, Subst>
+where
+    L: Language,
+    N: Analysis<L>,
+{
+    fn go<L: Language, N: Analysis<L>>(
+        au: &mut AntiUnifier<L, Id>,
+        extractor: &Extractor<AstSize, L, N>,
+        egraph: &EGraph<L, N>,
+        (a, b): (Id, Id),
+        depth: usize,
+    ) -> Id {
+        if depth == 0 {
+            return au.var(a, b);
+        }
+        au.node(
+            extractor.find_best_node(a),
+            extractor.find_best_node(b),
+            || (a, b),
+            |au, ca, cb| {
+                let children = (egraph.find(ca), egraph.find(cb));
+                go(au, extractor, egraph, children, depth - 1)
+            },
+        )
+    }
+
+    assert!(
+        egraph.clean,
+        "Tried to anti-unify in a dirty e-graph! Rebuild first."
+    );
+    let extractor = Extractor::new(egraph, AstSize);
+    let mut au = AntiUnifier::new();
+    let roots = (egraph.find(a), egraph.find(b));
+    go(&mut au, &extractor, egraph, roots, depth_limit);
+
+    let mut left = Subst::with_capacity(au.vars.len());
+    let mut right = Subst::with_capacity(au.vars.len());
+    for ((a, b), var) in au.vars {
+        left.insert(var, a);
+        right.insert(var, b);
+    }
+    Generalization {
+        pattern: Pattern::from(au.ast),
+        left,
+        right,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolLang as S, *};
+
+    fn generalize(a: &str, b: &str) -> (String, Vec<String>, Vec<String>) {
+        let (a, b): (RecExpr<S>, RecExpr<S>) = (a.parse().unwrap(), b.parse().unwrap());
+        let g = anti_unify(&a, &b);
+
+        // the pattern matches both sides, with their bindings
+        let mut egraph = EGraph::<S, ()>::default();
+        let (ia, ib) = (egraph.add_expr(&a), egraph.add_expr(&b));
+        egraph.rebuild();
+        for (root, bindings) in vec![(ia, &g.left), (ib, &g.right)] {
+            let matches = g.pattern.search_eclass(&egraph, root).unwrap();
+            let expected: Vec<(Var, Id)> = bindings
+                .iter()
+                .map(|(v, e)| (*v, egraph.lookup_expr(e).unwrap()))
+                .collect();
+            assert!(matches
+                .substs
+                .iter()
+                .any(|s| s.iter().eq(expected.iter().copied())));
+        }
+
+        let show = |bindings: &[(Var, RecExpr<S>)]| -> Vec<String> {
+            bindings
+                .iter()
+                .map(|(v, e)| format!("{}={}", v, e))
+                .collect()
+        };
+        (g.pattern.to_string(), show(&g.left), show(&g.right))
+    }
+
+    #[test]
+    fn anti_unify_terms() {
+        let same = generalize("(+ x (f 1))", "(+ x (f 1))");
+        assert_eq!(same, ("(+ x (f 1))".into(), vec![], vec![]));
+
+        // payloads and arities that differ become variables
+        let (pattern, left, right) = generalize("(+ x (f 1 2))", "(+ y (f 1))");
+        assert_eq!(pattern, "(+ ?v0 ?v1)");
+        assert_eq!(left, vec!["?v0=x", "?v1=(f 1 2)"]);
+        assert_eq!(right, vec!["?v0=y", "?v1=(f 1)"]);
+
+        // the same pair of subterms reuses its variable, even in
+        // different positions, but not when paired differently
+        let (pattern, left, right) = generalize("(g (h a) b (h a) a)", "(g c b c (k d))");
+        assert_eq!(pattern, "(g ?v0 b ?v0 ?v1)");
+        assert_eq!(left, vec!["?v0=(h a)", "?v1=a"]);
+        assert_eq!(right, vec!["?v0=c", "?v1=(k d)"]);
+        let (pattern, _, _) = generalize("(g a a)", "(g b c)");
+        assert_eq!(pattern, "(g ?v0 ?v1)");
+
+        let (pattern, _, _) = generalize("x", "(f x)");
+        assert_eq!(pattern, "?v0");
+    }
+
+    #[test]
+    fn anti_unify_eclasses() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let a = egraph.add_expr(&"(+ (* x 2) (* x 2))".parse().unwrap());
+        let b = egraph.add_expr(&"(+ (* y 2) (* y 2))".parse().unwrap());
+        // the smaller term represents the eclass
+        let big = egraph.add_expr(&"(f (g (h x)))".parse().unwrap());
+        let x = egraph.add_expr(&"x".parse().unwrap());
+        egraph.union(big, x);
+        egraph.rebuild();
+        let y = egraph.lookup_expr(&"y".parse().unwrap()).unwrap();
+        let v0: Var = "?v0".parse().unwrap();
+
+        let g = anti_unify_classes(&egraph, a, b, 3);
+        assert_eq!(g.pattern.to_string(), "(+ (* ?v0 2) (* ?v0 2))");
+        assert_eq!((g.left[v0], g.right[v0]), (egraph.find(x), y));
+        for (root, subst) in vec![(a, &g.left), (b, &g.right)] {
+            let matches = g.pattern.search_eclass(&egraph, root).unwrap();
+            assert!(matches.substs.contains(subst));
+        }
+
+        // shallower, the shared 2 is generalized too
+        let g = anti_unify_classes(&egraph, a, b, 2);
+        assert_eq!(g.pattern.to_string(), "(+ (* ?v0 ?v1) (* ?v0 ?v1))");
+        let g = anti_unify_classes(&egraph, a, a, 1);
+        assert_eq!(g.pattern.to_string(), "(+ ?v0 ?v0)");
+        assert_eq!(g.left, g.right);
+    }
+}
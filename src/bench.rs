@@ -0,0 +1,820 @@
+use std::fmt::{self, Display, Formatter};
+
+use ::serde::{Deserialize, Serialize};
+
+use crate::{rewrite as rw, SymbolLang as S, *};
+
+/// The format version written by [`BenchReport::to_json`].
+pub const BENCH_REPORT_VERSION: u32 = 1;
+
+/// A named, deterministic benchmark workload, run by
+/// [`BenchReport::run`].
+///
+/// Each scenario builds its input from a seed, so the same seed does
+/// the same work on every machine, and only the times vary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchScenario {
+    /// Saturates arithmetic rules on a random expression, then extracts
+    /// it.
+    MathSaturation,
+    /// Merges many leaves under a wide layer of parents, so rebuilding
+    /// restores a lot of congruence, then runs a rule over the parents.
+    WideCongruence,
+    /// Searches a deep pattern with exponentially many matches.
+    DeepSearch,
+    /// Saturates arithmetic rules, then extracts every eclass.
+    ExtractionHeavy,
+    /// Saturates arithmetic rules, then searches each rule's pattern
+    /// over the saturated egraph, timing only those searches.
+    SaturatedSearch,
+    /// Like [`SaturatedSearch`](BenchScenario::SaturatedSearch), but
+    /// with a new machine for each eclass, as searches were before
+    /// they reused one, to compare against.
+    SaturatedSearchFresh,
+    /// Like [`SaturatedSearch`](BenchScenario::SaturatedSearch), but
+    /// searching into one [`MatchArena`] instead of allocating a
+    /// [`Subst`] for each match.
+    ArenaSearch,
+    /// Grows an egraph for many iterations while tracking the best
+    /// term of its root, timing only the extractions.
+    BestTermTracking,
+    /// Saturates arithmetic rules, then searches the patterns of the
+    /// commutativity rules, which match without the machine.
+    CommutativeSearch,
+    /// Like [`CommutativeSearch`](BenchScenario::CommutativeSearch),
+    /// but running the machine, as those patterns did before they
+    /// skipped it, to compare against.
+    CommutativeSearchMachine,
+    /// Searches one big eclass of lists and calls of many arities for
+    /// a few of them, with enodes ordered by `Ord`.
+    PayloadSearch,
+    /// Like [`PayloadSearch`](BenchScenario::PayloadSearch), but with
+    /// enodes ordered by shape (see [`Language::search_cmp`]), so each
+    /// search only scans enodes of its pattern's shape.
+    PayloadSearchByShape,
+    /// Saturates commutativity and associativity rules, whose late
+    /// iterations only find matches already in the egraph.
+    RedundantApply,
+    /// Like [`RedundantApply`](BenchScenario::RedundantApply), but
+    /// [skipping](Runner::with_redundant_match_skipping) those matches
+    /// without adding anything.
+    RedundantApplySkipping,
+    /// Saturates arithmetic rules, then writes and reads the egraph with
+    /// [`EGraph::write_binary`] and [`EGraph::read_binary`].
+    BinaryRoundTrip,
+    /// Like [`BinaryRoundTrip`](BenchScenario::BinaryRoundTrip), but
+    /// with JSON, to compare against.
+    JsonRoundTrip,
+}
+
+impl BenchScenario {
+    /// Every scenario, in the order [`BenchReport::run`] runs them.
+    pub const ALL: [BenchScenario; 16] = [
+        BenchScenario::MathSaturation,
+        BenchScenario::WideCongruence,
+        BenchScenario::DeepSearch,
+        BenchScenario::ExtractionHeavy,
+        BenchScenario::SaturatedSearch,
+        BenchScenario::SaturatedSearchFresh,
+        BenchScenario::ArenaSearch,
+        BenchScenario::BestTermTracking,
+        BenchScenario::CommutativeSearch,
+        BenchScenario::CommutativeSearchMachine,
+        BenchScenario::PayloadSearch,
+        BenchScenario::PayloadSearchByShape,
+        BenchScenario::RedundantApply,
+        BenchScenario::RedundantApplySkipping,
+        BenchScenario::BinaryRoundTrip,
+        BenchScenario::JsonRoundTrip,
+    ];
+
+    /// The name of this scenario in a [`BenchReport`].
+    pub fn name(self) -> &'static str {
+        match self {
+            BenchScenario::MathSaturation => "math_saturation",
+            BenchScenario::WideCongruence => "wide_congruence",
+            BenchScenario::DeepSearch => "deep_search",
+            BenchScenario::ExtractionHeavy => "extraction_heavy",
+            BenchScenario::SaturatedSearch => "saturated_search",
+            BenchScenario::SaturatedSearchFresh => "saturated_search_fresh",
+            BenchScenario::ArenaSearch => "arena_search",
+            BenchScenario::BestTermTracking => "best_term_tracking",
+            BenchScenario::CommutativeSearch => "commutative_search",
+            BenchScenario::CommutativeSearchMachine => "commutative_search_machine",
+            BenchScenario::PayloadSearch => "payload_search",
+            BenchScenario::PayloadSearchByShape => "payload_search_by_shape",
+            BenchScenario::RedundantApply => "redundant_apply",
+            BenchScenario::RedundantApplySkipping => "redundant_apply_skipping",
+            BenchScenario::BinaryRoundTrip => "binary_round_trip",
+            BenchScenario::JsonRoundTrip => "json_round_trip",
+        }
+    }
+
+    /// Runs this scenario once with inputs made from `seed`.
+    pub fn run(self, seed: u64) -> BenchMetrics {
+        let mut rng = Rng::new(seed);
+        let mut metrics = BenchMetrics {
+            scenario: self.name().into(),
+            ..Default::default()
+        };
+        match self {
+            BenchScenario::MathSaturation => math_saturation(&mut rng, &mut metrics),
+            BenchScenario::WideCongruence => wide_congruence(&mut rng, &mut metrics),
+            BenchScenario::DeepSearch => deep_search(&mut rng, &mut metrics),
+            BenchScenario::ExtractionHeavy => extraction_heavy(&mut rng, &mut metrics),
+            BenchScenario::SaturatedSearch => saturated_search(&mut rng, &mut metrics),
+            BenchScenario::SaturatedSearchFresh => saturated_search_fresh(&mut rng, &mut metrics),
+            BenchScenario::ArenaSearch => arena_search(&mut rng, &mut metrics),
+            BenchScenario::BestTermTracking => best_term_tracking(&mut rng, &mut metrics),
+            BenchScenario::CommutativeSearch => commutative_search(&mut rng, &mut metrics, false),
+            BenchScenario::CommutativeSearchMachine => {
+                commutative_search(&mut rng, &mut metrics, true)
+            }
+            BenchScenario::PayloadSearch => payload_search::<Payload>(&mut rng, &mut metrics),
+            BenchScenario::PayloadSearchByShape => {
+                payload_search::<ShapePayload>(&mut rng, &mut metrics)
+            }
+            BenchScenario::RedundantApply => redundant_apply(&mut rng, &mut metrics, false),
+            BenchScenario::RedundantApplySkipping => redundant_apply(&mut rng, &mut metrics, true),
+            BenchScenario::BinaryRoundTrip => round_trip(&mut rng, &mut metrics, true),
+            BenchScenario::JsonRoundTrip => round_trip(&mut rng, &mut metrics, false),
+        }
+        metrics
+    }
+}
+
+/// What one [`BenchScenario`] measured.
+///
+/// Times are in seconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BenchMetrics {
+    /// The [name](BenchScenario::name) of the scenario.
+    pub scenario: String,
+    /// Time spent searching.
+    pub search_time: f64,
+    /// Time spent applying.
+    pub apply_time: f64,
+    /// Time spent rebuilding.
+    pub rebuild_time: f64,
+    /// Time spent extracting.
+    pub extract_time: f64,
+    /// Time spent writing and reading egraphs.
+    #[serde(default)]
+    pub io_time: f64,
+    /// The most enodes the egraph had.
+    pub peak_nodes: usize,
+    /// The most eclasses the egraph had.
+    pub peak_classes: usize,
+}
+
+impl BenchMetrics {
+    /// Every metric, by name, in the order they're compared.
+    pub fn metrics(&self) -> [(&'static str, f64); 7] {
+        [
+            ("search_time", self.search_time),
+            ("apply_time", self.apply_time),
+            ("rebuild_time", self.rebuild_time),
+            ("extract_time", self.extract_time),
+            ("io_time", self.io_time),
+            ("peak_nodes", self.peak_nodes as f64),
+            ("peak_classes", self.peak_classes as f64),
+        ]
+    }
+
+    /// Adds the times of a run's iterations, and its egraph sizes.
+    fn add_runner<N: Analysis<S>>(&mut self, runner: &Runner<S, N>) {
+        for iteration in &runner.iterations {
+            self.search_time += iteration.search_time;
+            self.apply_time += iteration.apply_time;
+            self.rebuild_time += iteration.rebuild_time;
+            self.add_sizes(iteration.egraph_nodes, iteration.egraph_classes);
+        }
+        let egraph = &runner.egraph;
+        self.add_sizes(egraph.total_size(), egraph.number_of_classes());
+    }
+
+    fn add_sizes(&mut self, nodes: usize, classes: usize) {
+        self.peak_nodes = self.peak_nodes.max(nodes);
+        self.peak_classes = self.peak_classes.max(classes);
+    }
+
+    /// Keeps the fastest of each time, since noise only slows runs
+    /// down.
+    fn keep_fastest(&mut self, other: &BenchMetrics) {
+        debug_assert_eq!(
+            (self.peak_nodes, self.peak_classes),
+            (other.peak_nodes, other.peak_classes),
+            "Scenario {} isn't deterministic",
+            self.scenario
+        );
+        self.search_time = self.search_time.min(other.search_time);
+        self.apply_time = self.apply_time.min(other.apply_time);
+        self.rebuild_time = self.rebuild_time.min(other.rebuild_time);
+        self.extract_time = self.extract_time.min(other.extract_time);
+        self.io_time = self.io_time.min(other.io_time);
+    }
+}
+
+/// The results of running every [`BenchScenario`], which can be saved
+/// as JSON and compared against a baseline.
+///
+/// The `egg-bench` benchmark (`cargo bench --features bench`) runs
+/// this, and exits with an error if it regressed from a baseline; see
+/// its source for the options.
+///
+/// # Example
+/// ```no_run
+/// use egg::*;
+/// let baseline = BenchReport::run(42, 3);
+/// let json = baseline.to_json();
+///
+/// let report = BenchReport::run(42, 3);
+/// let baseline = BenchReport::from_json(&json).unwrap();
+/// for regression in report.regressions(&baseline, 10.0) {
+///     println!("{}", regression);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchReport {
+    /// The format version, [`BENCH_REPORT_VERSION`] when written.
+    pub version: u32,
+    /// The seed the scenarios were made from.
+    pub seed: u64,
+    /// The number of times each scenario ran.
+    pub samples: usize,
+    /// The metrics of each scenario, keeping the fastest time of all
+    /// samples.
+    pub scenarios: Vec<BenchMetrics>,
+}
+
+impl BenchReport {
+    /// Runs every [`BenchScenario`] `samples` times from `seed`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `samples` is zero.
+    pub fn run(seed: u64, samples: usize) -> Self {
+        assert!(samples > 0, "Can't benchmark with no samples");
+        let scenarios = BenchScenario::ALL
+            .iter()
+            .map(|scenario| {
+                let mut metrics = scenario.run(seed);
+                for _ in 1..samples {
+                    metrics.keep_fastest(&scenario.run(seed));
+                }
+                metrics
+            })
+            .collect();
+        Self {
+            version: BENCH_REPORT_VERSION,
+            seed,
+            samples,
+            scenarios,
+        }
+    }
+
+    /// Serializes this report to JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Deserializes a report written by
+    /// [`to_json`](BenchReport::to_json), checking its version.
+    pub fn from_json(json: &str) -> Result<Self, BenchError> {
+        let report: Self = serde_json::from_str(json)?;
+        if report.version != BENCH_REPORT_VERSION {
+            return Err(BenchError::Version(report.version));
+        }
+        Ok(report)
+    }
+
+    /// Returns every metric that got worse than in `baseline` by more
+    /// than `threshold` percent.
+    ///
+    /// Only scenarios in both reports are compared, and metrics that
+    /// were zero in `baseline` never regress.
+    /// The reports should be made from the same seed; otherwise the
+    /// scenarios did different work, so this only returns a regression
+    /// of the `"seed"` metric.
+    pub fn regressions(&self, baseline: &BenchReport, threshold: f64) -> Vec<BenchRegression> {
+        if self.seed != baseline.seed {
+            return vec![BenchRegression {
+                scenario: String::new(),
+                metric: "seed".into(),
+                baseline: baseline.seed as f64,
+                current: self.seed as f64,
+            }];
+        }
+        let mut regressions = vec![];
+        for current in &self.scenarios {
+            let old = baseline
+                .scenarios
+                .iter()
+                .find(|m| m.scenario == current.scenario);
+            let old = match old {
+                Some(old) => old,
+                None => continue,
+            };
+            for (&(metric, now), &(_, before)) in current.metrics().iter().zip(&old.metrics()) {
+                if before > 0.0 && now > before * (1.0 + threshold / 100.0) {
+                    regressions.push(BenchRegression {
+                        scenario: current.scenario.clone(),
+                        metric: metric.into(),
+                        baseline: before,
+                        current: now,
+                    });
+                }
+            }
+        }
+        regressions
+    }
+}
+
+impl Display for BenchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<18} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8} {:>8}",
+            "scenario", "search", "apply", "rebuild", "extract", "io", "nodes", "classes"
+        )?;
+        for m in &self.scenarios {
+            writeln!(
+                f,
+                "{:<18} {:>10.6} {:>10.6} {:>10.6} {:>10.6} {:>10.6} {:>8} {:>8}",
+                m.scenario,
+                m.search_time,
+                m.apply_time,
+                m.rebuild_time,
+                m.extract_time,
+                m.io_time,
+                m.peak_nodes,
+                m.peak_classes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A metric that regressed, as found by [`BenchReport::regressions`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchRegression {
+    /// The scenario that regressed.
+    pub scenario: String,
+    /// The name of the metric that regressed.
+    pub metric: String,
+    /// The metric in the baseline.
+    pub baseline: f64,
+    /// The metric now.
+    pub current: f64,
+}
+
+impl Display for BenchRegression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let change = (self.current / self.baseline - 1.0) * 100.0;
+        write!(
+            f,
+            "{} {} regressed by {:.1}%: {} -> {}",
+            self.scenario, self.metric, change, self.baseline, self.current
+        )
+    }
+}
+
+/// An error from [`BenchReport::from_json`].
+#[derive(Debug, thiserror::Error)]
+pub enum BenchError {
+    /// The input isn't a valid report.
+    #[error("invalid bench report: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The report was written in a format this version can't read.
+    #[error(
+        "unsupported bench report version {0}, expected {}",
+        BENCH_REPORT_VERSION
+    )]
+    Version(u32),
+}
+
+/// A tiny xorshift generator, so the scenarios are the same everywhere.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck at zero
+        Rng(seed.max(1))
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+fn math_rules() -> Vec<Rewrite<S, ()>> {
+    vec![
+        rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+        rw!("comm-mul"; "(* ?a ?b)" => "(* ?b ?a)"),
+        rw!("assoc-add"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+        rw!("assoc-mul"; "(* ?a (* ?b ?c))" => "(* (* ?a ?b) ?c)"),
+        rw!("distribute"; "(* ?a (+ ?b ?c))" => "(+ (* ?a ?b) (* ?a ?c))"),
+        rw!("factor"; "(+ (* ?a ?b) (* ?a ?c))" => "(* ?a (+ ?b ?c))"),
+        rw!("add-0"; "(+ ?a 0)" => "?a"),
+        rw!("mul-0"; "(* ?a 0)" => "0"),
+        rw!("mul-1"; "(* ?a 1)" => "?a"),
+    ]
+}
+
+/// A random arithmetic expression of the given depth.
+fn random_expr(rng: &mut Rng, depth: usize) -> RecExpr<S> {
+    fn add(rng: &mut Rng, expr: &mut RecExpr<S>, depth: usize) -> Id {
+        if depth == 0 || rng.below(4) == 0 {
+            let leaf = ["a", "b", "c", "d", "0", "1"][rng.below(6)];
+            expr.add(S::leaf(leaf))
+        } else {
+            let op = ["+", "*"][rng.below(2)];
+            let a = add(rng, expr, depth - 1);
+            let b = add(rng, expr, depth - 1);
+            expr.add(S::new(op, vec![a, b]))
+        }
+    }
+    let mut expr = RecExpr::default();
+    add(rng, &mut expr, depth);
+    expr
+}
+
+fn saturate(rng: &mut Rng, metrics: &mut BenchMetrics, depth: usize) -> Runner<S, ()> {
+    let runner = Runner::default()
+        .with_scheduler(SimpleScheduler)
+        .with_iter_limit(5)
+        .with_node_limit(20_000)
+        .with_time_limit(Duration::from_secs(60))
+        .with_expr(&random_expr(rng, depth))
+        .run(&math_rules());
+    metrics.add_runner(&runner);
+    runner
+}
+
+fn math_saturation(rng: &mut Rng, metrics: &mut BenchMetrics) {
+    let runner = saturate(rng, metrics, 5);
+    let start = Instant::now();
+    Extractor::new(&runner.egraph, AstSize).find_best(runner.roots[0]);
+    metrics.extract_time += start.elapsed().as_secs_f64();
+}
+
+fn wide_congruence(rng: &mut Rng, metrics: &mut BenchMetrics) {
+    let width = 1000;
+    let mut egraph = EGraph::<S, ()>::default();
+    let leaves: Vec<Id> = (0..width)
+        .map(|i| egraph.add(S::leaf(format!("x{}", i))))
+        .collect();
+    for i in 0..width {
+        let f = egraph.add(S::new("f", vec![leaves[i]]));
+        egraph.add(S::new("g", vec![f, leaves[(i + 1) % width]]));
+    }
+    for _ in 0..width / 2 {
+        egraph.union(leaves[rng.below(width)], leaves[rng.below(width)]);
+    }
+    metrics.add_sizes(egraph.total_size(), egraph.number_of_classes());
+    let start = Instant::now();
+    egraph.rebuild();
+    metrics.rebuild_time += start.elapsed().as_secs_f64();
+
+    let rules: Vec<Rewrite<S, ()>> = vec![rw!("swap"; "(g (f ?x) ?y)" => "(g (f ?y) ?x)")];
+    let runner = Runner::default()
+        .with_scheduler(SimpleScheduler)
+        .with_iter_limit(3)
+        .with_egraph(egraph)
+        .run(&rules);
+    metrics.add_runner(&runner);
+}
+
+fn deep_search(rng: &mut Rng, metrics: &mut BenchMetrics) {
+    // each level's eclass has (f a<i> next), (f b<i> next), and some
+    // (g c<i>) that never match
+    let depth = 12;
+    let mut egraph = EGraph::<S, ()>::default();
+    let mut next = egraph.add(S::leaf("z"));
+    for i in 0..depth {
+        let a = egraph.add(S::leaf(format!("a{}", i)));
+        let b = egraph.add(S::leaf(format!("b{}", i)));
+        let fa = egraph.add(S::new("f", vec![a, next]));
+        let fb = egraph.add(S::new("f", vec![b, next]));
+        egraph.union(fa, fb);
+        for j in 0..rng.below(8) {
+            let c = egraph.add(S::leaf(format!("c{}_{}", i, j)));
+            let g = egraph.add(S::new("g", vec![c]));
+            egraph.union(fa, g);
+        }
+        next = egraph.find(fa);
+    }
+    let start = Instant::now();
+    egraph.rebuild();
+    metrics.rebuild_time += start.elapsed().as_secs_f64();
+    metrics.add_sizes(egraph.total_size(), egraph.number_of_classes());
+
+    let mut pattern = "?z".to_string();
+    for i in 0..depth {
+        pattern = format!("(f ?v{} {})", i, pattern);
+    }
+    let pattern: Pattern<S> = pattern.parse().unwrap();
+    let start = Instant::now();
+    let matches = pattern.search(&egraph);
+    metrics.search_time += start.elapsed().as_secs_f64();
+    debug_assert_eq!(
+        matches.iter().map(|m| m.substs.len()).sum::<usize>(),
+        1 << depth
+    );
+}
+
+fn extraction_heavy(rng: &mut Rng, metrics: &mut BenchMetrics) {
+    let runner = saturate(rng, metrics, 6);
+    let egraph = &runner.egraph;
+    let start = Instant::now();
+    let size = Extractor::new(egraph, AstSize);
+    let depth = Extractor::new(egraph, AstDepth);
+    for class in egraph.classes() {
+        size.find_best(class.id);
+        depth.find_best(class.id);
+    }
+    metrics.extract_time += start.elapsed().as_secs_f64();
+}
+
+fn saturated_search(rng: &mut Rng, metrics: &mut BenchMetrics) {
+    // only the searches below count, not the saturation's
+    let runner = saturate(rng, &mut BenchMetrics::default(), 5);
+    let egraph = &runner.egraph;
+    metrics.add_sizes(egraph.total_size(), egraph.number_of_classes());
+    let rules = math_rules();
+    let start = Instant::now();
+    for _ in 0..10 {
+        for rule in &rules {
+            rule.search(egraph);
+        }
+    }
+    metrics.search_time += start.elapsed().as_secs_f64();
+}
+
+fn saturated_search_fresh(rng: &mut Rng, metrics: &mut BenchMetrics) {
+    let runner = saturate(rng, &mut BenchMetrics::default(), 5);
+    let egraph = &runner.egraph;
+    metrics.add_sizes(egraph.total_size(), egraph.number_of_classes());
+    let patterns: Vec<Pattern<S>> = math_rules()
+        .iter()
+        .map(|rule| Pattern::new(rule.searcher.get_pattern_ast().unwrap().clone()))
+        .collect();
+    let start = Instant::now();
+    for _ in 0..10 {
+        for pattern in &patterns {
+            for eclass in pattern.candidate_classes(egraph) {
+                pattern.program.run(egraph, eclass);
+            }
+        }
+    }
+    metrics.search_time += start.elapsed().as_secs_f64();
+}
+
+fn arena_search(rng: &mut Rng, metrics: &mut BenchMetrics) {
+    let runner = saturate(rng, &mut BenchMetrics::default(), 5);
+    let egraph = &runner.egraph;
+    metrics.add_sizes(egraph.total_size(), egraph.number_of_classes());
+    let rules = math_rules();
+    let mut arena = MatchArena::new();
+    let start = Instant::now();
+    for _ in 0..10 {
+        arena.clear();
+        for rule in &rules {
+            rule.search_into(egraph, &mut arena);
+        }
+    }
+    metrics.search_time += start.elapsed().as_secs_f64();
+}
+
+fn best_term_tracking(rng: &mut Rng, metrics: &mut BenchMetrics) {
+    // each iteration wraps every f's argument in another g, so the
+    // egraph never saturates
+    let rules: Vec<Rewrite<S, ()>> = vec![rw!("wrap"; "(f ?a)" => "(f (g ?a))")];
+    let start = Instant::now();
+    let runner = Runner::default()
+        .with_scheduler(SimpleScheduler)
+        .with_iter_limit(300)
+        .with_node_limit(100_000)
+        .with_time_limit(Duration::from_secs(60))
+        .with_best_term_tracking(AstSize)
+        .with_expr(&format!("(f {})", random_expr(rng, 4)).parse().unwrap())
+        .run(&rules);
+    let total = start.elapsed().as_secs_f64();
+    metrics.add_runner(&runner);
+    // the rest of the run is the extractions
+    metrics.extract_time += total - metrics.search_time - metrics.apply_time - metrics.rebuild_time;
+}
+
+fn commutative_search(rng: &mut Rng, metrics: &mut BenchMetrics, machine: bool) {
+    let runner = saturate(rng, &mut BenchMetrics::default(), 5);
+    let egraph = &runner.egraph;
+    metrics.add_sizes(egraph.total_size(), egraph.number_of_classes());
+    let patterns: Vec<Pattern<S>> = ["(+ ?a ?b)", "(* ?a ?b)"]
+        .iter()
+        .map(|p| {
+            let mut pattern: Pattern<S> = p.parse().unwrap();
+            if machine {
+                pattern.program = pattern.program.general();
+            }
+            pattern
+        })
+        .collect();
+    let start = Instant::now();
+    for _ in 0..10 {
+        for pattern in &patterns {
+            for eclass in pattern.candidate_classes(egraph) {
+                pattern.program.run(egraph, eclass);
+            }
+        }
+    }
+    metrics.search_time += start.elapsed().as_secs_f64();
+}
+
+define_language! {
+    /// Operators that carry data, ordered by `Ord`.
+    enum Payload {
+        "list" = List(Box<[Id]>),
+        Num(i32),
+        Call(Symbol, Vec<Id>),
+    }
+}
+
+define_language! {
+    /// Like [`Payload`], but ordered by shape.
+    enum ShapePayload {
+        "list" = List(Box<[Id]>),
+        Num(i32),
+        Call(Symbol, Vec<Id>),
+    }
+    search_by_shape
+}
+
+fn payload_search<L: Language + FromOp>(rng: &mut Rng, metrics: &mut BenchMetrics) {
+    let mut egraph = EGraph::<L, ()>::default();
+    let root = egraph.add_expr(&"0".parse().unwrap());
+    for i in 0..1000 {
+        let children: Vec<String> = (0..i % 7).map(|_| rng.below(5).to_string()).collect();
+        let op = if i % 2 == 0 {
+            "list".to_string()
+        } else {
+            format!("f{}", i % 4)
+        };
+        let expr = if children.is_empty() {
+            op
+        } else {
+            format!("({} {})", op, children.join(" "))
+        };
+        let id = egraph.add_expr(&expr.parse().unwrap());
+        egraph.union(root, id);
+    }
+    egraph.rebuild();
+    metrics.add_sizes(egraph.total_size(), egraph.number_of_classes());
+
+    let root = egraph.find(root);
+    let patterns: Vec<Pattern<L>> = [
+        "(list ?a ?b)",
+        "(list ?a ?b ?c)",
+        "list",
+        "(f1 ?a ?b ?c)",
+        "(f3 ?a)",
+    ]
+    .iter()
+    .map(|p| p.parse().unwrap())
+    .collect();
+    let start = Instant::now();
+    for _ in 0..100 {
+        for pattern in &patterns {
+            pattern.search_eclass(&egraph, root);
+        }
+    }
+    metrics.search_time += start.elapsed().as_secs_f64();
+}
+
+fn redundant_apply(rng: &mut Rng, metrics: &mut BenchMetrics, skip_redundant: bool) {
+    let ac = ["comm-add", "comm-mul", "assoc-add", "assoc-mul"];
+    let rules: Vec<Rewrite<S, ()>> = math_rules()
+        .into_iter()
+        .filter(|rw| ac.contains(&rw.name.as_str()))
+        .collect();
+    let runner = Runner::default()
+        .with_scheduler(SimpleScheduler)
+        .with_iter_limit(30)
+        .with_node_limit(20_000)
+        .with_time_limit(Duration::from_secs(60))
+        .with_redundant_match_skipping(skip_redundant)
+        .with_expr(&random_expr(rng, 3))
+        .run(&rules);
+    metrics.add_runner(&runner);
+}
+
+fn round_trip(rng: &mut Rng, metrics: &mut BenchMetrics, binary: bool) {
+    // only writing and reading count, not the saturation
+    let runner = saturate(rng, &mut BenchMetrics::default(), 5);
+    let egraph = &runner.egraph;
+    metrics.add_sizes(egraph.total_size(), egraph.number_of_classes());
+    let start = Instant::now();
+    for _ in 0..10 {
+        if binary {
+            let mut bytes = vec![];
+            egraph.write_binary(&mut bytes).unwrap();
+            EGraph::<S, ()>::read_binary(&bytes[..], ()).unwrap();
+        } else {
+            let json = serde_json::to_string(egraph).unwrap();
+            let mut read: EGraph<S, ()> = serde_json::from_str(&json).unwrap();
+            read.rebuild();
+        }
+    }
+    metrics.io_time += start.elapsed().as_secs_f64();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The full report takes a while, so it's left to `cargo bench`.
+
+    #[test]
+    fn scenarios_are_deterministic() {
+        let scenario = BenchScenario::WideCongruence;
+        let (a, b) = (scenario.run(7), scenario.run(7));
+        assert_eq!(a.scenario, scenario.name());
+        assert!(a.peak_nodes > 0);
+        assert_eq!(
+            (a.peak_nodes, a.peak_classes),
+            (b.peak_nodes, b.peak_classes)
+        );
+        let seeded = |seed| random_expr(&mut Rng::new(seed), 5);
+        assert_eq!(seeded(1), seeded(1));
+        assert_ne!(seeded(1), seeded(2));
+    }
+
+    fn report(seed: u64) -> BenchReport {
+        let metrics = |scenario: &str| BenchMetrics {
+            scenario: scenario.into(),
+            search_time: 0.5,
+            rebuild_time: 0.25,
+            peak_nodes: 100,
+            peak_classes: 50,
+            ..Default::default()
+        };
+        BenchReport {
+            version: BENCH_REPORT_VERSION,
+            seed,
+            samples: 1,
+            scenarios: vec![
+                metrics("math_saturation"),
+                metrics("wide_congruence"),
+                metrics("deep_search"),
+            ],
+        }
+    }
+
+    #[test]
+    fn regressions_past_the_threshold() {
+        let baseline = report(3);
+        assert!(baseline.regressions(&baseline, 0.0).is_empty());
+
+        let json = baseline.to_json();
+        assert_eq!(BenchReport::from_json(&json).unwrap(), baseline);
+
+        let mut report = baseline.clone();
+        report.scenarios[1].rebuild_time = baseline.scenarios[1].rebuild_time * 1.5;
+        report.scenarios[2].peak_nodes = 130;
+        // never regresses from zero
+        report.scenarios[0].extract_time = 1.0;
+        let regressions = report.regressions(&baseline, 20.0);
+        let found: Vec<_> = regressions
+            .iter()
+            .map(|r| (r.scenario.as_str(), r.metric.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("wide_congruence", "rebuild_time"),
+                ("deep_search", "peak_nodes")
+            ]
+        );
+        assert!(regressions[0]
+            .to_string()
+            .starts_with("wide_congruence rebuild_time regressed by"));
+        // within the threshold
+        report.scenarios[2].peak_nodes = 110;
+        assert_eq!(report.regressions(&baseline, 20.0).len(), 1);
+
+        report.seed += 1;
+        assert_eq!(report.regressions(&baseline, 20.0)[0].metric, "seed");
+    }
+
+    #[test]
+    fn reports_check_their_version() {
+        let mut report = report(3);
+        report.version += 1;
+        assert!(matches!(
+            BenchReport::from_json(&report.to_json()),
+            Err(BenchError::Version(_))
+        ));
+    }
+}
@@ -0,0 +1,594 @@
+use std::fmt::Display;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use thiserror::Error;
+
+use crate::*;
+
+/// The magic bytes at the start of a binary egraph.
+const MAGIC: &[u8; 4] = b"EGGB";
+
+/// The format version written by [`EGraph::write_binary`].
+pub const BINARY_VERSION: u32 = 1;
+
+/// How ids are written, whatever the size of an [`Id`] in memory, which
+/// includes its tag with the `tagged-ids` feature.
+type IdRepr = u32;
+
+const ID_WIDTH: u8 = std::mem::size_of::<IdRepr>() as u8;
+
+/// An error from [`EGraph::read_binary`] or [`EGraph::write_binary`].
+#[derive(Debug, Error)]
+pub enum BinaryError {
+    /// Reading or writing failed, or the input ended early.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The egraph to write isn't [`clean`](EGraph::clean).
+    #[error("can't write an egraph that isn't clean")]
+    NotClean,
+    /// The input isn't a binary egraph.
+    #[error("not a binary egraph")]
+    BadMagic,
+    /// The egraph was written in a format this version can't read.
+    #[error("unsupported binary egraph version {0}, expected {}", BINARY_VERSION)]
+    Version(u32),
+    /// The egraph was written with a different [`Id`] width.
+    #[error("binary egraph has {0}-byte ids, expected {}", ID_WIDTH)]
+    IdWidth(u8),
+    /// The egraph was written for a language with different
+    /// [operators](Language::op_names).
+    #[error(
+        "binary egraph was written for a different language \
+         (fingerprint {found:#018x}, expected {expected:#018x})"
+    )]
+    LanguageMismatch {
+        /// The fingerprint of the reading program's language.
+        expected: u64,
+        /// The fingerprint in the input.
+        found: u64,
+    },
+    /// The input is malformed.
+    #[error("corrupt binary egraph: {0}")]
+    Corrupt(String),
+}
+
+/// FNV-1a of the operator names, which unlike [`std::hash::Hash`] is
+/// stable across platforms and versions.
+fn fingerprint<L: Language>() -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for name in L::op_names() {
+        for &byte in name.as_bytes().iter().chain(&[0]) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+fn write_u32(w: &mut impl Write, n: u32) -> io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+fn write_len(w: &mut impl Write, len: usize) -> io::Result<()> {
+    w.write_all(&(len as u64).to_le_bytes())
+}
+
+fn write_id(w: &mut impl Write, id: impl Into<usize>) -> io::Result<()> {
+    w.write_all(&(id.into() as IdRepr).to_le_bytes())
+}
+
+fn write_ids(w: &mut impl Write, ids: &[Id]) -> io::Result<()> {
+    write_len(w, ids.len())?;
+    ids.iter().try_for_each(|&id| write_id(w, id))
+}
+
+fn write_node<L: Language>(w: &mut impl Write, node: &L, op: u32) -> io::Result<()> {
+    write_u32(w, op)?;
+    write_u32(w, node.len() as u32)?;
+    node.try_for_each(|id| write_id(w, id))
+}
+
+struct Reader<R> {
+    r: R,
+    /// The number of ids in the union-find, once it's been read.
+    ids: usize,
+}
+
+impl<R: Read> Reader<R> {
+    fn bytes(&mut self, buf: &mut [u8]) -> Result<(), BinaryError> {
+        Ok(self.r.read_exact(buf)?)
+    }
+
+    fn u32(&mut self) -> Result<u32, BinaryError> {
+        let mut buf = [0; 4];
+        self.bytes(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> Result<u64, BinaryError> {
+        let mut buf = [0; 8];
+        self.bytes(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Reads an id written by `write_id`, without checking its range.
+    fn raw_id(&mut self) -> Result<usize, BinaryError> {
+        let mut buf = [0; ID_WIDTH as usize];
+        self.bytes(&mut buf)?;
+        Ok(IdRepr::from_le_bytes(buf) as usize)
+    }
+
+    fn id(&mut self) -> Result<Id, BinaryError> {
+        let id = self.raw_id()?;
+        if id >= self.ids {
+            return Err(BinaryError::Corrupt(format!("id {} out of range", id)));
+        }
+        Ok(Id::from(id))
+    }
+
+    /// Reads a length-prefixed array, without trusting the length for
+    /// allocation.
+    fn many<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> Result<T, BinaryError>,
+    ) -> Result<Vec<T>, BinaryError> {
+        let len = self.u64()?;
+        let mut items = Vec::with_capacity(len.min(1 << 16) as usize);
+        for _ in 0..len {
+            items.push(f(self)?);
+        }
+        Ok(items)
+    }
+
+    fn string(&mut self) -> Result<String, BinaryError> {
+        let len = self.u64()?;
+        let mut buf = vec![];
+        (&mut self.r).take(len).read_to_end(&mut buf)?;
+        if buf.len() as u64 != len {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        String::from_utf8(buf).map_err(|e| BinaryError::Corrupt(e.to_string()))
+    }
+
+    fn node<L: FromOp>(&mut self, ops: &[String]) -> Result<L, BinaryError> {
+        let op = self.u32()? as usize;
+        let op = ops
+            .get(op)
+            .ok_or_else(|| BinaryError::Corrupt(format!("operator {} out of range", op)))?;
+        let len = self.u32()?;
+        let mut children = Vec::with_capacity(len.min(1 << 16) as usize);
+        for _ in 0..len {
+            children.push(self.id()?);
+        }
+        L::from_op(op, children).map_err(|e| BinaryError::Corrupt(format!("{:?}", e)))
+    }
+}
+
+impl<L, N> EGraph<L, N>
+where
+    L: Language + Display + FromOp,
+    N: Analysis<L>,
+{
+    /// Writes this egraph in a compact, versioned binary format.
+    ///
+    /// The output starts with a header holding the format version, the
+    /// width of [`Id`]s, and a fingerprint of the language's
+    /// [operators](Language::op_names).
+    /// It then holds a table of the enodes' [`Display`]ed operators, and
+    /// length-prefixed arrays of the union-find, the eclasses' enodes,
+    /// and the memo, with every enode as an index into the table and its
+    /// children.
+    /// The same egraph is always written the same way.
+    ///
+    /// Only the structure of the egraph is written:
+    /// [`read_binary`](EGraph::read_binary) recomputes the analysis data,
+    /// and settings like [explanations](EGraph::with_explanations_enabled)
+    /// or [provenance](EGraph::with_provenance) aren't kept.
+    ///
+    /// Fails with [`BinaryError::NotClean`] if the egraph isn't
+    /// [`clean`](EGraph::clean).
+    ///
+    /// # Example
+    /// ```
+    /// use egg::*;
+    /// let mut egraph = EGraph::<SymbolLang, ()>::default();
+    /// let a = egraph.add_expr(&"(+ x (* y 2))".parse().unwrap());
+    /// let b = egraph.add_expr(&"(+ x z)".parse().unwrap());
+    /// egraph.union(a, b);
+    /// egraph.rebuild();
+    ///
+    /// let mut bytes = vec![];
+    /// egraph.write_binary(&mut bytes).unwrap();
+    /// let read = EGraph::<SymbolLang, ()>::read_binary(&bytes[..], ()).unwrap();
+    /// assert_eq!(read.find(a), read.find(b));
+    /// assert_eq!(read.total_size(), egraph.total_size());
+    /// ```
+    pub fn write_binary(&self, w: impl Write) -> Result<(), BinaryError> {
+        if !self.clean {
+            return Err(BinaryError::NotClean);
+        }
+        let mut classes: Vec<&EClass<L, N::Data>> = self.classes.values().collect();
+        classes.sort_unstable_by_key(|class| class.id);
+        let mut memo: Vec<(&L, &Id)> = self.memo.iter().collect();
+        memo.sort_unstable();
+        let mut aliases: Vec<(&ENodeId, &ENodeId)> = self.enode_aliases.iter().collect();
+        aliases.sort_unstable();
+        let mut frozen: Vec<Id> = self.frozen.iter().copied().collect();
+        frozen.sort_unstable();
+
+        // intern the operators first, remembering each enode's index
+        let mut table = IndexSet::default();
+        let class_nodes = classes.iter().flat_map(|class| &class.nodes);
+        let ops: Vec<u32> = class_nodes
+            .chain(memo.iter().map(|(node, _)| *node))
+            .map(|node| table.insert_full(node.to_string()).0 as u32)
+            .collect();
+        let mut ops = ops.into_iter();
+
+        let mut w = BufWriter::new(w);
+        w.write_all(MAGIC)?;
+        write_u32(&mut w, BINARY_VERSION)?;
+        w.write_all(&[ID_WIDTH])?;
+        w.write_all(&fingerprint::<L>().to_le_bytes())?;
+
+        write_len(&mut w, table.len())?;
+        for op in &table {
+            write_len(&mut w, op.len())?;
+            w.write_all(op.as_bytes())?;
+        }
+
+        write_ids(&mut w, self.unionfind.parents())?;
+        write_ids(&mut w, &self.enode_classes)?;
+        write_len(&mut w, aliases.len())?;
+        for (&dup, &kept) in aliases {
+            write_id(&mut w, dup)?;
+            write_id(&mut w, kept)?;
+        }
+
+        write_len(&mut w, classes.len())?;
+        for class in classes {
+            write_id(&mut w, class.id)?;
+            write_len(&mut w, class.len())?;
+            for (node, &enode_id) in class.nodes.iter().zip(&class.node_ids) {
+                write_id(&mut w, enode_id)?;
+                write_node(&mut w, node, ops.next().unwrap())?;
+            }
+        }
+
+        write_len(&mut w, memo.len())?;
+        for (node, &id) in memo {
+            write_node(&mut w, node, ops.next().unwrap())?;
+            write_id(&mut w, id)?;
+        }
+
+        write_ids(&mut w, &frozen)?;
+        Ok(w.flush()?)
+    }
+
+    /// Reads an egraph written by [`write_binary`](EGraph::write_binary),
+    /// with the given analysis.
+    ///
+    /// Fails loudly if the egraph was written for a language whose
+    /// [operators](Language::op_names) differ from `L`'s, e.g. because
+    /// its [`define_language!`] changed.
+    ///
+    /// The analysis data is recomputed bottom-up, then
+    /// [`Analysis::on_deserialize`] is called, and the returned egraph
+    /// is [`clean`](EGraph::clean) unless that modified it.
+    pub fn read_binary(r: impl Read, analysis: N) -> Result<Self, BinaryError> {
+        let mut r = Reader {
+            r: BufReader::new(r),
+            ids: usize::MAX,
+        };
+        let mut magic = [0; 4];
+        r.bytes(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(BinaryError::BadMagic);
+        }
+        let version = r.u32()?;
+        if version != BINARY_VERSION {
+            return Err(BinaryError::Version(version));
+        }
+        let mut width = [0];
+        r.bytes(&mut width)?;
+        if width[0] != ID_WIDTH {
+            return Err(BinaryError::IdWidth(width[0]));
+        }
+        let (found, expected) = (r.u64()?, fingerprint::<L>());
+        if found != expected {
+            return Err(BinaryError::LanguageMismatch { expected, found });
+        }
+
+        let table = r.many(Reader::string)?;
+        let parents = r.many(Reader::id)?;
+        r.ids = parents.len();
+        if parents.iter().any(|&id| usize::from(id) >= r.ids) {
+            return Err(BinaryError::Corrupt("union-find out of range".into()));
+        }
+        let enode_classes = r.many(Reader::id)?;
+        let enode_id =
+            |r: &mut Reader<_>| -> Result<ENodeId, BinaryError> { Ok(ENodeId::from(r.raw_id()?)) };
+        let aliases = r.many(|r| Ok((enode_id(r)?, enode_id(r)?)))?;
+        let classes = r.many(|r| {
+            let id = r.id()?;
+            let nodes = r.many(|r| Ok((enode_id(r)?, r.node(&table)?)))?;
+            Ok((id, nodes))
+        })?;
+        let memo = r.many(|r| Ok((r.node(&table)?, r.id()?)))?;
+        let frozen = r.many(Reader::id)?;
+
+        let mut egraph = Self::new(analysis);
+        egraph.unionfind = UnionFind::from_parents(parents);
+        egraph.enode_classes = enode_classes;
+        egraph.enode_aliases = aliases.into_iter().collect();
+        egraph.memo = memo.into_iter().collect();
+        egraph.frozen = frozen.into_iter().collect();
+        egraph.insert_read_classes(classes)?;
+        egraph.loaded = true;
+        egraph.rebuild();
+        Ok(egraph)
+    }
+
+    /// Inserts the eclasses read by [`read_binary`](EGraph::read_binary),
+    /// each one as soon as one of its enodes has all of its children
+    /// inserted, so its analysis data can be made.
+    ///
+    /// All the enodes are then queued to merge in their analysis data
+    /// at the next rebuild.
+    #[allow(clippy::type_complexity)]
+    fn insert_read_classes(
+        &mut self,
+        classes: Vec<(Id, Vec<(ENodeId, L)>)>,
+    ) -> Result<(), BinaryError> {
+        let index: HashMap<Id, usize> = classes
+            .iter()
+            .enumerate()
+            .map(|(i, (id, _))| (*id, i))
+            .collect();
+        // the enodes waiting on each eclass, and how many distinct
+        // children each enode still waits on
+        let mut waiting: HashMap<Id, Vec<(usize, usize)>> = HashMap::default();
+        let mut missing: Vec<Vec<usize>> = Vec::with_capacity(classes.len());
+        let mut ready = vec![];
+        for (i, (_, nodes)) in classes.iter().enumerate() {
+            let mut counts = Vec::with_capacity(nodes.len());
+            for (j, (_, node)) in nodes.iter().enumerate() {
+                let mut children = node.children().to_vec();
+                children.sort_unstable();
+                children.dedup();
+                if let Some(child) = children.iter().find(|&c| !index.contains_key(c)) {
+                    let msg = format!("enode {:?} has non-canonical child {}", node, child);
+                    return Err(BinaryError::Corrupt(msg));
+                }
+                for &child in &children {
+                    waiting.entry(child).or_default().push((i, j));
+                }
+                if children.is_empty() {
+                    ready.push((i, j));
+                }
+                counts.push(children.len());
+            }
+            missing.push(counts);
+        }
+
+        let mut classes: Vec<_> = classes.into_iter().map(Some).collect();
+        while let Some((i, j)) = ready.pop() {
+            let (id, nodes) = match classes[i].take() {
+                Some(class) => class,
+                None => continue,
+            };
+            let data = N::make(self, &nodes[j].1);
+            let (node_ids, nodes) = nodes.into_iter().unzip();
+            let class = EClass {
+                id,
+                nodes,
+                node_ids,
+                data,
+                parents: vec![],
+                changed: true,
+            };
+            self.classes.insert(id, class);
+            for &(i, j) in waiting.get(&id).into_iter().flatten() {
+                missing[i][j] -= 1;
+                if missing[i][j] == 0 {
+                    ready.push((i, j));
+                }
+            }
+        }
+        if let Some((id, _)) = classes.into_iter().flatten().next() {
+            let msg = format!("eclass {} has no finite term", id);
+            return Err(BinaryError::Corrupt(msg));
+        }
+
+        let mut ids: Vec<Id> = index.into_iter().map(|(id, _)| id).collect();
+        ids.sort_unstable();
+        for id in ids {
+            let nodes = self.classes[&id].nodes.clone();
+            for node in nodes {
+                for &child in node.children() {
+                    let parents = &mut self.classes.get_mut(&child).unwrap().parents;
+                    parents.push((node.clone(), id));
+                }
+                self.analysis_pending.insert((node, id));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    /// The size of the smallest term of each eclass.
+    #[derive(Default)]
+    struct MinSize;
+    impl Analysis<S> for MinSize {
+        type Data = usize;
+        fn make(egraph: &EGraph<S, Self>, enode: &S) -> Self::Data {
+            enode.fold(1, |size, id| size + egraph[id].data)
+        }
+        fn merge(&mut self, a: &mut Self::Data, b: Self::Data) -> DidMerge {
+            merge_min(a, b)
+        }
+    }
+
+    fn random_egraph(seed: u64) -> EGraph<S, MinSize> {
+        // xorshift, so the test doesn't need a dependency
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        let mut next = move |n: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize % n
+        };
+        let mut egraph = EGraph::<S, MinSize>::default();
+        let mut ids = vec![];
+        for leaf in &["a", "b", "c", "1", "2"] {
+            ids.push(egraph.add(S::leaf(*leaf)));
+        }
+        for _ in 0..200 {
+            match next(10) {
+                0..=5 => {
+                    let (op, arity) = [("f", 1), ("g", 2), ("h", 3)][next(3)];
+                    let children = (0..arity).map(|_| ids[next(ids.len())]).collect();
+                    ids.push(egraph.add(S::new(op, children)));
+                }
+                6..=8 => {
+                    egraph.union(ids[next(ids.len())], ids[next(ids.len())]);
+                }
+                _ => {
+                    egraph.rebuild();
+                }
+            }
+        }
+        egraph.freeze(ids[next(ids.len())]);
+        egraph.rebuild();
+        egraph
+    }
+
+    #[test]
+    fn random_egraphs_round_trip() {
+        for seed in 0..30 {
+            let egraph = random_egraph(seed);
+            let mut bytes = vec![];
+            egraph.write_binary(&mut bytes).unwrap();
+            let read = EGraph::<S, MinSize>::read_binary(&bytes[..], MinSize).unwrap();
+
+            // the same egraph is written the same way
+            let mut again = vec![];
+            read.write_binary(&mut again).unwrap();
+            assert!(bytes == again, "seed {} didn't round trip", seed);
+
+            assert_eq!(read.number_of_classes(), egraph.number_of_classes());
+            assert_eq!(read.total_size(), egraph.total_size());
+            for class in egraph.classes() {
+                let read_class = &read[class.id];
+                assert_eq!(read_class.id, class.id);
+                assert_eq!(read_class.nodes, class.nodes);
+                assert_eq!(read_class.data, class.data, "seed {}", seed);
+                assert_eq!(read.is_frozen(class.id), egraph.is_frozen(class.id));
+            }
+            for i in 0..egraph.unionfind.size() {
+                assert_eq!(read.find(Id::from(i)), egraph.find(Id::from(i)));
+            }
+        }
+    }
+
+    #[test]
+    fn round_tripped_egraph_keeps_working() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rw!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rw!("assoc"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+        ];
+        let expr = "(+ a (+ b (+ c d)))".parse().unwrap();
+        let runner = Runner::default()
+            .with_expr(&expr)
+            .with_iter_limit(2)
+            .run(&rules);
+        let mut bytes = vec![];
+        runner.egraph.write_binary(&mut bytes).unwrap();
+        let read = EGraph::<S, ()>::read_binary(&bytes[..], ()).unwrap();
+
+        let resumed = Runner::default().with_egraph(read).run(&rules);
+        let fresh = Runner::default().with_expr(&expr).run(&rules);
+        assert_eq!(resumed.egraph.total_size(), fresh.egraph.total_size());
+        assert_eq!(
+            resumed.egraph.number_of_classes(),
+            fresh.egraph.number_of_classes()
+        );
+    }
+
+    define_language! {
+        enum Arith {
+            "+" = Add([Id; 2]),
+            Num(i32),
+        }
+    }
+
+    define_language! {
+        enum ArithMul {
+            "+" = Add([Id; 2]),
+            "*" = Mul([Id; 2]),
+            Num(i32),
+        }
+    }
+
+    #[test]
+    fn bad_input_fails_loudly() {
+        let mut egraph = EGraph::<Arith, ()>::default();
+        egraph.add_expr(&"(+ 1 (+ 2 3))".parse().unwrap());
+        egraph.rebuild();
+        let mut bytes = vec![];
+        egraph.write_binary(&mut bytes).unwrap();
+        assert!(EGraph::<Arith, ()>::read_binary(&bytes[..], ()).is_ok());
+
+        let err = EGraph::<ArithMul, ()>::read_binary(&bytes[..], ()).unwrap_err();
+        assert!(matches!(err, BinaryError::LanguageMismatch { .. }));
+        assert!(err.to_string().contains("different language"), "{}", err);
+        let err = EGraph::<S, ()>::read_binary(&bytes[..], ()).unwrap_err();
+        assert!(matches!(err, BinaryError::LanguageMismatch { .. }));
+
+        let read = |bytes: &[u8]| EGraph::<Arith, ()>::read_binary(bytes, ()).unwrap_err();
+        assert!(matches!(read(&b"JSON{}"[..]), BinaryError::BadMagic));
+        let mut future = bytes.clone();
+        future[4] = 2;
+        assert!(matches!(read(&future[..]), BinaryError::Version(2)));
+        assert!(matches!(
+            read(&bytes[..bytes.len() - 1]),
+            BinaryError::Io(_)
+        ));
+        let mut corrupt = bytes.clone();
+        let last = corrupt.len() - 9;
+        corrupt[last] = 0xff;
+        assert!(matches!(read(&corrupt[..]), BinaryError::Corrupt(_)));
+
+        egraph.add_expr(&"(* 2 3)".parse().unwrap());
+        let err = egraph.write_binary(std::io::sink()).unwrap_err();
+        assert!(matches!(err, BinaryError::NotClean));
+    }
+
+    /// The `binary_round_trip` and `json_round_trip` bench scenarios
+    /// compare their speed.
+    #[cfg(feature = "reports")]
+    #[test]
+    fn binary_is_smaller_than_json() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rw!("assoc-add"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+            rw!("comm-mul"; "(* ?a ?b)" => "(* ?b ?a)"),
+            rw!("dist"; "(* ?a (+ ?b ?c))" => "(+ (* ?a ?b) (* ?a ?c))"),
+        ];
+        let egraph = Runner::default()
+            .with_expr(&"(* x (+ a (+ b (+ c (+ d e)))))".parse().unwrap())
+            .with_node_limit(20_000)
+            .with_iter_limit(8)
+            .run(&rules)
+            .egraph;
+
+        let mut binary = vec![];
+        egraph.write_binary(&mut binary).unwrap();
+        let json = serde_json::to_string(&egraph).unwrap();
+        assert!(binary.len() < json.len());
+    }
+}
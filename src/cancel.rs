@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// How many units of work the cancellable operations do between checks
+/// of their token, where a unit is cheap, like a step of a search.
+pub(crate) const CANCEL_CHECK_INTERVAL: usize = 1024;
+
+/// A flag to cancel long operations from another thread.
+///
+/// Clones of a token share their flag, so one clone can be handed to
+/// the operation and another kept to [`cancel`](CancellationToken::cancel)
+/// it.
+/// The cancellable operations, [`Program::run_cancellable`],
+/// [`Extractor::new_cancellable`] and [`EGraph::rebuild_cancellable`],
+/// check the flag between units of work, so they stop soon after it is
+/// set, and fail with [`Error::Cancelled`].
+/// Once cancelled, a token stays cancelled.
+///
+/// [`Program::run_cancellable`]: crate::Program::run_cancellable
+/// [`Extractor::new_cancellable`]: crate::Extractor::new_cancellable
+/// [`EGraph::rebuild_cancellable`]: crate::EGraph::rebuild_cancellable
+/// [`Error::Cancelled`]: crate::Error::Cancelled
+///
+/// # Example
+/// ```
+/// use egg::{*, SymbolLang as S};
+/// let mut egraph = EGraph::<S, ()>::default();
+/// let x = egraph.add(S::leaf("x"));
+/// let y = egraph.add(S::leaf("y"));
+/// egraph.union(x, y);
+///
+/// let token = CancellationToken::new();
+/// token.clone().cancel();
+/// assert!(matches!(egraph.rebuild_cancellable(&token), Err(Error::Cancelled)));
+/// assert!(!egraph.clean);
+/// // a later rebuild picks up where the cancelled one stopped
+/// egraph.rebuild();
+/// assert!(egraph.clean);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    /// Create a token that isn't cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the operations using this token or any of its clones.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,291 @@
+use crate::*;
+
+type Class<L, N> = EClass<L, <N as Analysis<L>>::Data>;
+
+/// What an [`EGraph`] needs to roll back to an earlier state, so a
+/// [`Runner`] with [panic isolation](Runner::with_panic_isolation) can
+/// undo an iteration.
+///
+/// Eclasses and hashcons entries are copied just before they first
+/// change, and the union-find keeps the entries it overwrites, so a
+/// checkpoint grows with the changes, not the size of the egraph.
+/// Enodes, enode aliases, provenance and recorded unions are only added
+/// to, so they are rolled back by forgetting what was added.
+/// The rest is copied when the checkpoint is made: the analysis, the
+/// frozen eclasses, sort checking, annotation layers, pending work, and
+/// explanations, if enabled.
+pub(crate) struct Checkpoint<L: Language, N: Analysis<L>> {
+    /// Copies an eclass; a function pointer so that the egraph can save
+    /// eclasses without the bounds that copying needs.
+    copy_class: fn(&Class<L, N>) -> Class<L, N>,
+    /// Copies the egraph, if it is cleared.
+    copy_egraph: fn(&EGraph<L, N>) -> EGraph<L, N>,
+    /// The eclasses as they were before they first changed, or `None`
+    /// for eclasses made since.
+    classes: HashMap<Id, Option<Class<L, N>>>,
+    /// The hashcons entries as they were before they first changed.
+    memo: HashMap<L, Option<Id>>,
+    unionfind_size: usize,
+    enode_classes_len: usize,
+    /// The enode aliases added since.
+    enode_aliases: Vec<ENodeId>,
+    origins_len: usize,
+    recorded_unions_len: usize,
+    analysis: N,
+    explain: Option<Explain<L>>,
+    frozen: HashSet<Id>,
+    sorts: Option<SortChecker<L>>,
+    annotations: Annotations,
+    pending: Vec<(L, Id)>,
+    analysis_pending: IndexSet<(L, Id)>,
+    /// The frozen, deduplicated and redundant skip counts, and the
+    /// number of dirty unions.
+    counts: [usize; 4],
+    clean: bool,
+    loaded: bool,
+    /// The whole egraph, copied before it was
+    /// [cleared](EGraph::clear_keep_capacity).
+    whole: Option<Box<EGraph<L, N>>>,
+}
+
+impl<L: Language, N: Analysis<L> + Clone> Clone for Checkpoint<L, N> {
+    fn clone(&self) -> Self {
+        let copy_class = self.copy_class;
+        Self {
+            copy_class,
+            copy_egraph: self.copy_egraph,
+            classes: self
+                .classes
+                .iter()
+                .map(|(&id, class)| (id, class.as_ref().map(copy_class)))
+                .collect(),
+            memo: self.memo.clone(),
+            unionfind_size: self.unionfind_size,
+            enode_classes_len: self.enode_classes_len,
+            enode_aliases: self.enode_aliases.clone(),
+            origins_len: self.origins_len,
+            recorded_unions_len: self.recorded_unions_len,
+            analysis: self.analysis.clone(),
+            explain: self.explain.clone(),
+            frozen: self.frozen.clone(),
+            sorts: self.sorts.clone(),
+            annotations: self.annotations.clone(),
+            pending: self.pending.clone(),
+            analysis_pending: self.analysis_pending.clone(),
+            counts: self.counts,
+            clean: self.clean,
+            loaded: self.loaded,
+            whole: self.whole.as_ref().map(|w| Box::new((self.copy_egraph)(w))),
+        }
+    }
+}
+
+impl<L: Language, N: Analysis<L>> Checkpoint<L, N> {
+    /// Save an eclass that is about to change, or to be made if `class`
+    /// is `None`, unless it was saved already.
+    pub(crate) fn save_class(&mut self, id: Id, class: Option<&Class<L, N>>) {
+        let copy = self.copy_class;
+        self.classes.entry(id).or_insert_with(|| class.map(copy));
+    }
+
+    /// Save a hashcons entry that is about to change, unless it was
+    /// saved already.
+    pub(crate) fn save_memo(&mut self, node: &L, id: Option<Id>) {
+        if !self.memo.contains_key(node) {
+            self.memo.insert(node.clone(), id);
+        }
+    }
+
+    pub(crate) fn add_alias(&mut self, alias: ENodeId) {
+        self.enode_aliases.push(alias);
+    }
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+    /// Start a [`Checkpoint`] to [roll back](EGraph::roll_back) to,
+    /// replacing any earlier one.
+    pub(crate) fn checkpoint(&mut self)
+    where
+        N: Clone,
+        N::Data: Clone,
+    {
+        self.unionfind.start_undo();
+        self.checkpoint = Some(Checkpoint {
+            copy_class: EClass::clone,
+            copy_egraph: EGraph::clone,
+            classes: Default::default(),
+            memo: Default::default(),
+            unionfind_size: self.unionfind.size(),
+            enode_classes_len: self.enode_classes.len(),
+            enode_aliases: vec![],
+            origins_len: self.provenance.as_ref().map_or(0, |p| p.origins.len()),
+            recorded_unions_len: self.recorded_unions.len(),
+            analysis: self.analysis.clone(),
+            explain: self.explain.clone(),
+            frozen: self.frozen.clone(),
+            sorts: self.sorts.clone(),
+            annotations: self.annotations.clone(),
+            pending: self.pending.clone(),
+            analysis_pending: self.analysis_pending.clone(),
+            counts: [
+                self.frozen_skips,
+                self.deduped,
+                self.redundant_skips,
+                self.dirty_unions,
+            ],
+            clean: self.clean,
+            loaded: self.loaded,
+            whole: None,
+        });
+    }
+
+    /// Forget the checkpoint, if there is one, keeping every change.
+    pub(crate) fn discard_checkpoint(&mut self) {
+        self.unionfind.stop_undo();
+        self.checkpoint = None;
+    }
+
+    /// Copy the whole egraph into the checkpoint, if there is one, before
+    /// it is cleared.
+    pub(crate) fn save_whole(&mut self) {
+        let mut checkpoint = match self.checkpoint.take() {
+            Some(checkpoint) => checkpoint,
+            None => return,
+        };
+        if checkpoint.whole.is_none() {
+            checkpoint.whole = Some(Box::new((checkpoint.copy_egraph)(self)));
+        }
+        self.checkpoint = Some(checkpoint);
+    }
+
+    /// Undo every change since the [checkpoint](EGraph::checkpoint),
+    /// returning `false` if there is none.
+    pub(crate) fn roll_back(&mut self) -> bool {
+        let checkpoint = match self.checkpoint.take() {
+            Some(checkpoint) => checkpoint,
+            None => return false,
+        };
+        if let Some(whole) = checkpoint.whole {
+            *self = *whole;
+            self.discard_checkpoint();
+            return true;
+        }
+
+        for (id, class) in checkpoint.classes {
+            match class {
+                Some(class) => self.classes.insert(id, class),
+                None => self.classes.remove(&id),
+            };
+        }
+        for (node, id) in checkpoint.memo {
+            match id {
+                Some(id) => self.memo.insert(node, id),
+                None => self.memo.remove(&node),
+            };
+        }
+        self.unionfind.roll_back(checkpoint.unionfind_size);
+        self.enode_classes.truncate(checkpoint.enode_classes_len);
+        for alias in &checkpoint.enode_aliases {
+            self.enode_aliases.remove(alias);
+        }
+        if let Some(provenance) = &mut self.provenance {
+            provenance.origins.truncate(checkpoint.origins_len);
+            provenance.set_current(None);
+        }
+        self.recorded_unions
+            .truncate(checkpoint.recorded_unions_len);
+
+        self.analysis = checkpoint.analysis;
+        self.explain = checkpoint.explain;
+        self.frozen = checkpoint.frozen;
+        self.sorts = checkpoint.sorts;
+        self.annotations = checkpoint.annotations;
+        self.pending = checkpoint.pending;
+        self.analysis_pending = checkpoint.analysis_pending;
+        let [frozen_skips, deduped, redundant_skips, dirty_unions] = checkpoint.counts;
+        self.frozen_skips = frozen_skips;
+        self.deduped = deduped;
+        self.redundant_skips = redundant_skips;
+        self.dirty_unions = dirty_unions;
+        self.clean = checkpoint.clean;
+        self.loaded = checkpoint.loaded;
+        self.current_rule = None;
+        self.current_subst = None;
+
+        // the op index is only kept up to date by rebuilding
+        self.classes_by_op.values_mut().for_each(|ids| ids.clear());
+        for class in self.classes.values() {
+            for node in &class.nodes {
+                #[allow(clippy::mem_discriminant_non_enum)]
+                self.classes_by_op
+                    .entry(std::mem::discriminant(node))
+                    .or_default()
+                    .insert(class.id);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolLang as S, *};
+
+    #[test]
+    fn rolling_back_undoes_adds_unions_and_rebuilds() {
+        let mut egraph = EGraph::<S, ()>::default().with_provenance(true);
+        let fa = egraph.add_expr(&"(f a)".parse().unwrap());
+        let fb = egraph.add_expr(&"(f b)".parse().unwrap());
+        egraph.rebuild();
+        let before = egraph.clone();
+
+        egraph.checkpoint();
+        let a = egraph.add(S::leaf("a"));
+        let b = egraph.add(S::leaf("b"));
+        let c = egraph.add_expr(&"(g c)".parse().unwrap());
+        egraph.union(a, b);
+        egraph.union(fa, c);
+        egraph.rebuild();
+        assert_eq!(egraph.find(fa), egraph.find(fb));
+        egraph.retain_nodes(fa, |n| n.op.as_str() == "g");
+
+        assert!(egraph.roll_back());
+        assert!(!egraph.roll_back());
+        egraph.check_invariants();
+        assert_eq!(egraph.total_size(), before.total_size());
+        assert_eq!(egraph.number_of_classes(), before.number_of_classes());
+        assert_ne!(egraph.find(fa), egraph.find(fb));
+        assert_eq!(egraph.lookup_expr(&"(g c)".parse().unwrap()), None);
+        for class in before.classes() {
+            assert_eq!(egraph[class.id].nodes, class.nodes);
+            assert_eq!(egraph[class.id].parents, class.parents);
+        }
+        assert_eq!(egraph.enode_classes, before.enode_classes);
+        assert_eq!(
+            egraph.provenance.as_ref().unwrap().origins.len(),
+            before.enode_classes.len()
+        );
+
+        // the rolled back egraph can keep going
+        let c = egraph.add_expr(&"(g c)".parse().unwrap());
+        egraph.union(fb, c);
+        egraph.rebuild();
+        egraph.check_invariants();
+        let fb = egraph.lookup_expr(&"(f b)".parse().unwrap());
+        assert_eq!(fb, Some(egraph.find(c)));
+    }
+
+    #[test]
+    fn rolling_back_a_cleared_egraph() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let sum = egraph.add_expr(&"(+ x y)".parse().unwrap());
+        egraph.rebuild();
+
+        egraph.checkpoint();
+        egraph.clear_keep_capacity();
+        egraph.add(S::leaf("z"));
+        assert!(egraph.roll_back());
+        assert_eq!(egraph.total_size(), 3);
+        assert_eq!(egraph.lookup_expr(&"(+ x y)".parse().unwrap()), Some(sum));
+    }
+}
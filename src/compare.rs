@@ -0,0 +1,321 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::*;
+
+use log::*;
+
+/// The limits [`compare_rulesets`] runs both rule sets with.
+///
+/// The defaults are the same as [`Runner`]'s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ComparisonLimits {
+    /// The iteration limit of each run.
+    pub iter_limit: usize,
+    /// The enode limit of each run.
+    pub node_limit: usize,
+    /// The time limit of each run.
+    pub time_limit: Duration,
+}
+
+impl Default for ComparisonLimits {
+    fn default() -> Self {
+        Self {
+            iter_limit: 30,
+            node_limit: 10_000,
+            time_limit: Duration::from_secs(5),
+        }
+    }
+}
+
+/// How one rule set did on one obligation of a
+/// [`RulesetComparison`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
+pub struct ObligationRun {
+    /// Whether the two sides ended up in the same eclass.
+    pub proved: bool,
+    /// The number of iterations it took to prove the obligation, or
+    /// that ran before giving up.
+    pub iterations: usize,
+}
+
+/// An equivalence obligation of a [`RulesetComparison`], and how each
+/// rule set did on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
+pub struct ObligationComparison {
+    /// The lefthand side of the obligation.
+    pub lhs: String,
+    /// The righthand side of the obligation.
+    pub rhs: String,
+    /// How the old rule set did.
+    pub old: ObligationRun,
+    /// How the new rule set did.
+    pub new: ObligationRun,
+}
+
+/// The result of [`compare_rulesets`].
+///
+/// The [`Display`] implementation summarizes it for logs, listing the
+/// regressions and improvements first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
+pub struct RulesetComparison {
+    /// Every obligation, in the order of the corpus.
+    pub obligations: Vec<ObligationComparison>,
+    /// The indices of the obligations the old rule set proved but the
+    /// new one didn't.
+    pub regressions: Vec<usize>,
+    /// The indices of the obligations the new rule set proved but the
+    /// old one didn't.
+    pub improvements: Vec<usize>,
+}
+
+impl RulesetComparison {
+    /// Returns `true` if the new rule set proves everything the old one
+    /// did.
+    pub fn is_regression_free(&self) -> bool {
+        self.regressions.is_empty()
+    }
+}
+
+impl Display for ObligationRun {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.proved {
+            write!(f, "proved in {} iterations", self.iterations)
+        } else {
+            write!(f, "not proved after {} iterations", self.iterations)
+        }
+    }
+}
+
+impl Display for ObligationComparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} = {}: old {}, new {}",
+            self.lhs, self.rhs, self.old, self.new
+        )
+    }
+}
+
+impl Display for RulesetComparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let proved = |run: fn(&ObligationComparison) -> &ObligationRun| {
+            self.obligations.iter().filter(|o| run(o).proved).count()
+        };
+        writeln!(
+            f,
+            "Compared {} obligations: old proved {}, new proved {}",
+            self.obligations.len(),
+            proved(|o| &o.old),
+            proved(|o| &o.new)
+        )?;
+        for (what, indices) in &[
+            ("Regressions", &self.regressions),
+            ("Improvements", &self.improvements),
+        ] {
+            writeln!(f, "{}: {}", what, indices.len())?;
+            for &i in indices.iter() {
+                writeln!(f, "  #{} {}", i, self.obligations[i])?;
+            }
+        }
+        writeln!(f, "All obligations:")?;
+        for (i, obligation) in self.obligations.iter().enumerate() {
+            writeln!(f, "  #{} {}", i, obligation)?;
+        }
+        Ok(())
+    }
+}
+
+/// The stop reason of a run that proved its obligation.
+const PROVED: &str = "Obligation proved";
+
+/// Run the `old` and `new` rule sets over a corpus of equivalence
+/// obligations, reporting which of them each one proves.
+///
+/// Each obligation is proved when its two sides end up in the same
+/// eclass.
+/// Both rule sets run with the same `limits` and the
+/// [`SimpleScheduler`], so the comparison is deterministic up to the
+/// time limit; each run stops as soon as its obligation is proved.
+/// The egraph with an obligation's two sides is built once and cloned
+/// for each rule set.
+///
+/// Use it in tests to catch a refactored rule set losing proving power.
+///
+/// # Example
+/// ```
+/// use egg::{rewrite as rw, *};
+/// let old: Vec<Rewrite<SymbolLang, ()>> = vec![
+///     rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+///     rw!("add-0"; "(+ ?a 0)" => "?a"),
+/// ];
+/// let new: Vec<Rewrite<SymbolLang, ()>> = vec![rw!("add-0"; "(+ ?a 0)" => "?a")];
+/// let corpus = vec![
+///     ("(+ x 0)".parse().unwrap(), "x".parse().unwrap()),
+///     ("(+ 0 x)".parse().unwrap(), "x".parse().unwrap()),
+/// ];
+///
+/// let comparison = compare_rulesets(&old, &new, &corpus, ComparisonLimits::default());
+/// assert_eq!(comparison.regressions, vec![1]);
+/// assert!(!comparison.is_regression_free());
+/// println!("{}", comparison);
+/// ```
+pub fn compare_rulesets<L, N>(
+    old: &[Rewrite<L, N>],
+    new: &[Rewrite<L, N>],
+    corpus: &[(RecExpr<L>, RecExpr<L>)],
+    limits: ComparisonLimits,
+) -> RulesetComparison
+where
+    L: Language + Display + 'static,
+    N: Analysis<L> + Default + 'static,
+    EGraph<L, N>: Clone,
+{
+    let mut comparison = RulesetComparison {
+        obligations: Vec::with_capacity(corpus.len()),
+        regressions: vec![],
+        improvements: vec![],
+    };
+    for (i, (lhs, rhs)) in corpus.iter().enumerate() {
+        let mut egraph = EGraph::<L, N>::default();
+        let (a, b) = (egraph.add_expr(lhs), egraph.add_expr(rhs));
+        egraph.rebuild();
+
+        let old = prove(egraph.clone(), a, b, old, limits);
+        let new = prove(egraph, a, b, new, limits);
+        debug!("Obligation {}: old {}, new {}", i, old, new);
+        match (old.proved, new.proved) {
+            (true, false) => comparison.regressions.push(i),
+            (false, true) => comparison.improvements.push(i),
+            _ => {}
+        }
+        comparison.obligations.push(ObligationComparison {
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+            old,
+            new,
+        });
+    }
+    comparison
+}
+
+/// Run `rules` on `egraph` until `a` and `b` are equal, or a limit is
+/// hit.
+fn prove<L, N>(
+    egraph: EGraph<L, N>,
+    a: Id,
+    b: Id,
+    rules: &[Rewrite<L, N>],
+    limits: ComparisonLimits,
+) -> ObligationRun
+where
+    L: Language + 'static,
+    N: Analysis<L> + Default + 'static,
+{
+    let runner: Runner<L, N, ()> = Runner::default()
+        .with_egraph(egraph)
+        .with_scheduler(SimpleScheduler)
+        .with_iter_limit(limits.iter_limit)
+        .with_node_limit(limits.node_limit)
+        .with_time_limit(limits.time_limit)
+        .with_hook(move |runner| {
+            if runner.egraph.find(a) == runner.egraph.find(b) {
+                Err(PROVED.into())
+            } else {
+                Ok(())
+            }
+        })
+        .run(rules);
+
+    let mut iterations = runner.iterations.len();
+    // the iteration whose hook noticed the proof didn't run the rules
+    if matches!(&runner.stop_reason, Some(StopReason::Other(s)) if s == PROVED) {
+        iterations -= 1;
+    }
+    ObligationRun {
+        proved: runner.egraph.find(a) == runner.egraph.find(b),
+        iterations,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    fn corpus() -> Vec<(RecExpr<S>, RecExpr<S>)> {
+        let pairs = [
+            ("(+ a b)", "(+ b a)"),
+            ("(+ a (+ b c))", "(+ (+ a b) c)"),
+            ("(* a b)", "(* b a)"),
+            ("(+ (+ x 0) 0)", "x"),
+            ("a", "a"),
+            ("a", "b"),
+        ];
+        let parse = |s: &str| s.parse().unwrap();
+        pairs.iter().map(|(l, r)| (parse(l), parse(r))).collect()
+    }
+
+    fn compare() -> RulesetComparison {
+        let old: Vec<Rewrite<S, ()>> = vec![
+            rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rw!("assoc-add"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+            rw!("add-0"; "(+ ?a 0)" => "?a"),
+        ];
+        // drops associativity, adds commuting multiplication
+        let new: Vec<Rewrite<S, ()>> = vec![
+            rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rw!("add-0"; "(+ ?a 0)" => "?a"),
+            rw!("comm-mul"; "(* ?a ?b)" => "(* ?b ?a)"),
+        ];
+        compare_rulesets(&old, &new, &corpus(), ComparisonLimits::default())
+    }
+
+    #[test]
+    fn comparison_finds_regressions_and_improvements() {
+        let comparison = compare();
+        assert_eq!(comparison.regressions, vec![1]);
+        assert_eq!(comparison.improvements, vec![2]);
+        assert!(!comparison.is_regression_free());
+
+        let runs: Vec<_> = comparison
+            .obligations
+            .iter()
+            .map(|o| {
+                (
+                    o.old.proved,
+                    o.old.iterations,
+                    o.new.proved,
+                    o.new.iterations,
+                )
+            })
+            .collect();
+        assert_eq!(runs[0], (true, 1, true, 1));
+        assert_eq!(runs[3], (true, 1, true, 1));
+        assert_eq!(runs[4], (true, 0, true, 0));
+        // neither proves a = b, and both saturate
+        assert!(!runs[5].0 && !runs[5].2);
+        assert_eq!((runs[5].1, runs[5].3), (1, 1));
+
+        // the runs are deterministic
+        assert_eq!(compare(), comparison);
+
+        let display = comparison.to_string();
+        assert!(display.starts_with("Compared 6 obligations: old proved 4, new proved 4\n"));
+        assert!(display.contains(
+            "Regressions: 1\n  #1 (+ a (+ b c)) = (+ (+ a b) c): old proved in 1 iterations, \
+             new not proved after 2 iterations\n"
+        ));
+        assert!(display.contains("Improvements: 1\n  #2 (* a b) = (* b a)"));
+    }
+
+    #[cfg(feature = "reports")]
+    #[test]
+    fn comparison_round_trips_through_json() {
+        let comparison = compare();
+        let json = serde_json::to_string(&comparison).unwrap();
+        let read: RulesetComparison = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, comparison);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::*;
+
+/// Evidence that two eclasses can never be equal, returned by
+/// [`EGraph::provably_distinct`].
+///
+/// The analysis data of the two eclasses are
+/// [distinct](Analysis::distinct).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistinctnessWitness<D> {
+    /// The canonical id of the first eclass.
+    pub a: Id,
+    /// The analysis data of the first eclass.
+    pub a_data: D,
+    /// The canonical id of the second eclass.
+    pub b: Id,
+    /// The analysis data of the second eclass.
+    pub b_data: D,
+}
+
+/// Whether two terms are equal, as far as an [`EGraph`] knows.
+/// Returned by [`EGraph::prove_equiv`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Equivalence<D> {
+    /// The terms are in the same eclass.
+    Equal,
+    /// The terms can never be equal.
+    Distinct(DistinctnessWitness<D>),
+    /// The terms are not known to be equal, but they may be.
+    Unknown,
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N>
+where
+    N::Data: Clone,
+{
+    /// Check whether the eclasses `a` and `b` can never be equal,
+    /// according to [`Analysis::distinct`].
+    ///
+    /// Returns `None` if they are the same eclass, or if the analysis
+    /// can't tell them apart.
+    pub fn provably_distinct(&self, a: Id, b: Id) -> Option<DistinctnessWitness<N::Data>> {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return None;
+        }
+        let (a_data, b_data) = (&self[a].data, &self[b].data);
+        if N::distinct(a_data, b_data) {
+            Some(DistinctnessWitness {
+                a,
+                a_data: a_data.clone(),
+                b,
+                b_data: b_data.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Check whether the terms `a` and `b` are equal, can never be
+    /// equal, or neither as far as this egraph knows.
+    ///
+    /// Terms that aren't in the egraph are [`Equivalence::Unknown`].
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    ///
+    /// // leaves with different operators are never equal
+    /// #[derive(Default)]
+    /// struct Leaves;
+    /// impl Analysis<S> for Leaves {
+    ///     type Data = Option<Symbol>;
+    ///     fn make(_: &EGraph<S, Self>, enode: &S) -> Self::Data {
+    ///         Some(enode.op).filter(|_| enode.is_leaf())
+    ///     }
+    ///     fn merge(&mut self, a: &mut Self::Data, b: Self::Data) -> DidMerge {
+    ///         merge_max(a, b)
+    ///     }
+    ///     fn distinct(a: &Self::Data, b: &Self::Data) -> bool {
+    ///         matches!((a, b), (Some(a), Some(b)) if a != b)
+    ///     }
+    /// }
+    ///
+    /// let mut egraph = EGraph::<S, Leaves>::default();
+    /// let exprs: Vec<RecExpr<S>> = ["x", "y", "(f x)"]
+    ///     .iter()
+    ///     .map(|e| e.parse().unwrap())
+    ///     .collect();
+    /// for expr in &exprs {
+    ///     egraph.add_expr(expr);
+    /// }
+    /// egraph.rebuild();
+    ///
+    /// assert_eq!(egraph.prove_equiv(&exprs[0], &exprs[0]), Equivalence::Equal);
+    /// assert!(matches!(egraph.prove_equiv(&exprs[0], &exprs[1]), Equivalence::Distinct(_)));
+    /// assert_eq!(egraph.prove_equiv(&exprs[0], &exprs[2]), Equivalence::Unknown);
+    /// ```
+    pub fn prove_equiv(&self, a: &RecExpr<L>, b: &RecExpr<L>) -> Equivalence<N::Data> {
+        let (a, b) = match (self.lookup_expr(a), self.lookup_expr(b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Equivalence::Unknown,
+        };
+        if self.find(a) == self.find(b) {
+            Equivalence::Equal
+        } else if let Some(witness) = self.provably_distinct(a, b) {
+            Equivalence::Distinct(witness)
+        } else {
+            Equivalence::Unknown
+        }
+    }
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+    /// Enable or disable checking that no union merges eclasses that
+    /// are [provably distinct](EGraph::provably_distinct).
+    ///
+    /// When enabled, such a union panics with a message naming the rule
+    /// that caused it, turning analysis contradictions from unsound
+    /// rules into loud failures.
+    /// This is off by default.
+    pub fn with_distinctness_checks(mut self, enabled: bool) -> Self {
+        self.distinctness_checks = enabled;
+        self
+    }
+
+    /// Check if [distinctness checks](EGraph::with_distinctness_checks)
+    /// are enabled.
+    pub fn are_distinctness_checks_enabled(&self) -> bool {
+        self.distinctness_checks
+    }
+
+    /// Panic if the canonical eclasses `id1` and `id2`, which are about
+    /// to be unioned because of `rule`, are distinct.
+    pub(crate) fn check_distinct_union(&self, id1: Id, id2: Id, rule: Option<&Justification>) {
+        if let Some(e) = self.distinct_union_error(id1, id2, rule) {
+            panic!("{}", e);
+        }
+    }
+
+    /// The [`Error::AnalysisConflict`] of unioning the canonical eclasses
+    /// `id1` and `id2` because of `rule`, if they are distinct.
+    pub(crate) fn distinct_union_error(
+        &self,
+        id1: Id,
+        id2: Id,
+        rule: Option<&Justification>,
+    ) -> Option<Error> {
+        let (a, b) = (&self.classes[&id1].data, &self.classes[&id2].data);
+        if !N::distinct(a, b) {
+            return None;
+        }
+        let cause = match (rule, self.current_rule.as_ref()) {
+            (Some(Justification::Rule(name)), _) | (None, Some(name)) => {
+                format!("rule '{}'", name)
+            }
+            (Some(Justification::Congruence), _) => "congruence".to_string(),
+            (None, None) => "a direct union".to_string(),
+        };
+        let budget = TermBudget::default();
+        Some(Error::AnalysisConflict {
+            a: id1,
+            a_term: self.class_term(id1, &budget),
+            a_data: format!("{:?}", a),
+            b: id2,
+            b_term: self.class_term(id2, &budget),
+            b_data: format!("{:?}", b),
+            cause,
+            policy: self.analysis.overflow_policy(),
+        })
+    }
+}
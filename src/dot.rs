@@ -11,7 +11,11 @@ use std::fmt::{self, Debug, Display, Formatter};
 use std::io::{Error, ErrorKind, Result, Write};
 use std::path::Path;
 
-use crate::{egraph::EGraph, Analysis, Language};
+use std::hash::{Hash, Hasher};
+
+use crate::{
+    egraph::EGraph, Analysis, EClass, ENodeId, HashMap, HashSet, Id, Language, Origin, TermBudget,
+};
 
 /**
 A wrapper for an [`EGraph`] that can output [GraphViz] for
@@ -43,6 +47,29 @@ egraph.dot().to_svg("target/foo.svg").unwrap();
 // egraph.dot().to_dot("target/foo.dot").unwrap();
 ```
 
+To make a big egraph readable, a `Dot` can show only some of it, like
+the eclasses reachable from a root, and label each eclass, for example
+with its analysis data:
+```
+use egg::*;
+
+let mut egraph = EGraph::<SymbolLang, ()>::default();
+let root = egraph.add_expr(&"(+ x (* y 2))".parse().unwrap());
+egraph.add_expr(&"(- z 1)".parse().unwrap());
+egraph.rebuild();
+
+let dot = egraph
+    .dot()
+    .with_reachable_from(vec![root])
+    .with_node_filter(|node| node.op.as_str() != "2")
+    .with_class_label(|class| format!("{} nodes", class.len()))
+    .to_string();
+assert!(dot.contains("label = \"*\""));
+assert!(!dot.contains("label = \"-\""));
+assert!(!dot.contains("label = \"2\""));
+assert!(dot.contains("label = \"1 nodes\""));
+```
+
 Note that self-edges (from an enode to its containing eclass) will be
 rendered improperly due to a deficiency in GraphViz.
 So the example above will render with an from the "+" enode to itself
@@ -52,13 +79,24 @@ instead of to its own eclass.
 **/
 pub struct Dot<'a, L: Language, N: Analysis<L>> {
     pub(crate) egraph: &'a EGraph<L, N>,
+    pub(crate) class_label: Option<ClassFn<'a, L, N, String>>,
+    pub(crate) class_filter: Option<ClassFn<'a, L, N, bool>>,
+    pub(crate) node_filter: Option<Box<dyn Fn(&L) -> bool + 'a>>,
+    pub(crate) roots: Option<Vec<Id>>,
     /// A list of strings to be output top part of the dot file.
     pub config: Vec<String>,
     /// Whether or not to anchor the edges in the output.
     /// True by default.
     pub use_anchors: bool,
+    /// Whether or not to color enodes by the rule that created them.
+    /// This only has an effect if the egraph has
+    /// [provenance](EGraph::with_provenance) enabled.
+    /// False by default.
+    pub rule_colors: bool,
 }
 
+pub(crate) type ClassFn<'a, L, N, T> = Box<dyn Fn(&EClass<L, <N as Analysis<L>>::Data>) -> T + 'a>;
+
 impl<'a, L, N> Dot<'a, L, N>
 where
     L: Language + Display,
@@ -84,6 +122,56 @@ where
         self
     }
 
+    /// Set whether or not to color enodes by the rule that created them.
+    /// Enodes that weren't created by a rule are left uncolored.
+    pub fn with_rule_colors(mut self, rule_colors: bool) -> Self {
+        self.rule_colors = rule_colors;
+        self
+    }
+
+    /// Label each eclass with what `label` returns for it, like its
+    /// analysis data.
+    pub fn with_class_label(mut self, label: impl Fn(&EClass<L, N::Data>) -> String + 'a) -> Self {
+        self.class_label = Some(Box::new(label));
+        self
+    }
+
+    /// Label each eclass with one of its terms, cut short within
+    /// `budget` (see [`EGraph::class_term`]).
+    ///
+    /// This replaces a label set with
+    /// [`with_class_label`](Dot::with_class_label).
+    pub fn with_class_terms(self, budget: TermBudget) -> Self {
+        let egraph = self.egraph;
+        self.with_class_label(move |class| egraph.class_term(class.id, &budget))
+    }
+
+    /// Only show the eclasses for which `filter` returns true.
+    pub fn with_class_filter(mut self, filter: impl Fn(&EClass<L, N::Data>) -> bool + 'a) -> Self {
+        self.class_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Only show the enodes for which `filter` returns true.
+    ///
+    /// An eclass with no enodes to show isn't shown either, and neither
+    /// are the edges to it.
+    pub fn with_node_filter(mut self, filter: impl Fn(&L) -> bool + 'a) -> Self {
+        self.node_filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Only show the eclasses reachable from `roots` through the shown
+    /// eclasses and enodes.
+    ///
+    /// Ids are [canonicalized](EGraph::find), so this works on an egraph
+    /// with pending unions too, though it's only exact after a
+    /// [rebuild](EGraph::rebuild).
+    pub fn with_reachable_from(mut self, roots: impl IntoIterator<Item = Id>) -> Self {
+        self.roots = Some(roots.into_iter().collect());
+        self
+    }
+
     /// Renders the `Dot` to a .png file with the given filename.
     /// Requires a `dot` binary to be on your `$PATH`.
     pub fn to_png(&self, filename: impl AsRef<Path>) -> Result<()> {
@@ -131,11 +219,22 @@ where
         I: IntoIterator<Item = S2>,
     {
         use std::process::{Command, Stdio};
+        let program = program.as_ref();
         let mut child = Command::new(program)
             .args(args)
             .stdin(Stdio::piped())
             .stdout(Stdio::null())
-            .spawn()?;
+            .spawn()
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "couldn't find {}, is GraphViz installed and on your $PATH?",
+                        program.to_string_lossy()
+                    ),
+                ),
+                _ => e,
+            })?;
         let stdin = child.stdin.as_mut().expect("Failed to open stdin");
         write!(stdin, "{}", self)?;
         match child.wait()?.code() {
@@ -151,6 +250,76 @@ where
         }
     }
 
+    fn node_shown(&self, node: &L) -> bool {
+        self.node_filter.as_ref().map_or(true, |f| f(node))
+    }
+
+    fn class_shown(&self, class: &EClass<L, N::Data>) -> bool {
+        self.class_filter.as_ref().map_or(true, |f| f(class))
+    }
+
+    // the eclasses reachable from the roots through the shown ones, if
+    // there are roots
+    fn reachable(&self) -> Option<HashSet<Id>> {
+        let roots = self.roots.as_ref()?;
+        let mut reachable = HashSet::default();
+        let mut todo: Vec<Id> = roots.iter().map(|&id| self.egraph.find(id)).collect();
+        while let Some(id) = todo.pop() {
+            let class = &self.egraph[id];
+            if !self.class_shown(class) || !reachable.insert(id) {
+                continue;
+            }
+            for node in class.iter().filter(|node| self.node_shown(node)) {
+                todo.extend(node.children().iter().map(|&c| self.egraph.find(c)));
+            }
+        }
+        Some(reachable)
+    }
+
+    // the index of the first shown enode of each shown eclass, which
+    // edges to the eclass point to
+    fn shown(&self) -> HashMap<Id, usize> {
+        let reachable = self.reachable();
+        self.egraph
+            .classes()
+            .filter(|class| reachable.as_ref().map_or(true, |r| r.contains(&class.id)))
+            .filter(|class| self.class_shown(class))
+            .filter_map(|class| {
+                let first = class.iter().position(|node| self.node_shown(node))?;
+                Some((class.id, first))
+            })
+            .collect()
+    }
+
+    // gives back the node attributes for coloring by rule, if any
+    fn rule_color(&self, id: ENodeId) -> String {
+        const COLORS: &[&str] = &[
+            "lightblue",
+            "lightpink",
+            "palegreen",
+            "khaki",
+            "plum",
+            "lightsalmon",
+            "paleturquoise",
+            "wheat",
+        ];
+        if !self.rule_colors || !self.egraph.is_provenance_enabled() {
+            return String::new();
+        }
+        match self.egraph.provenance(id) {
+            Origin::Input => String::new(),
+            Origin::Rule { rule, .. } => {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                rule.as_str().hash(&mut hasher);
+                let color = COLORS[hasher.finish() as usize % COLORS.len()];
+                format!(
+                    ", style = filled, fillcolor = {}, tooltip = \"{}\"",
+                    color, rule
+                )
+            }
+        }
+    }
+
     // gives back the appropriate label and anchor
     fn edge(&self, i: usize, len: usize) -> (String, String) {
         assert!(i < len);
@@ -192,23 +361,45 @@ where
             writeln!(f, "  {}", line)?;
         }
 
+        let shown = self.shown();
+        let classes = || {
+            self.egraph
+                .classes()
+                .filter(|class| shown.contains_key(&class.id))
+        };
+
         // define all the nodes, clustered by eclass
-        for class in self.egraph.classes() {
+        for class in classes() {
             writeln!(f, "  subgraph cluster_{} {{", class.id)?;
             writeln!(f, "    style=dotted")?;
-            for (i, node) in class.iter().enumerate() {
-                writeln!(f, "    {}.{}[label = \"{}\"]", class.id, i, node)?;
+            if let Some(label) = &self.class_label {
+                writeln!(f, "    label = \"{}\"", label(class).replace('"', "\\\""))?;
+            }
+            for (i, (enode_id, node)) in class.nodes_with_ids().enumerate() {
+                if !self.node_shown(node) {
+                    continue;
+                }
+                let color = self.rule_color(enode_id);
+                writeln!(f, "    {}.{}[label = \"{}\"{}]", class.id, i, node, color)?;
             }
             writeln!(f, "  }}")?;
         }
 
-        for class in self.egraph.classes() {
+        for class in classes() {
             for (i_in_class, node) in class.iter().enumerate() {
+                if !self.node_shown(node) {
+                    continue;
+                }
                 let mut arg_i = 0;
                 node.try_for_each(|child| {
                     // write the edge to the child, but clip it to the eclass with lhead
                     let (anchor, label) = self.edge(arg_i, node.len());
                     let child_leader = self.egraph.find(child);
+                    arg_i += 1;
+                    let first = match shown.get(&child_leader) {
+                        Some(&first) => first,
+                        None => return Ok(()),
+                    };
 
                     if child_leader == class.id {
                         writeln!(
@@ -220,12 +411,11 @@ where
                     } else {
                         writeln!(
                             f,
-                            // {}.0 to pick an arbitrary node in the cluster
-                            "  {}.{}{} -> {}.0 [lhead = cluster_{}, {}]",
-                            class.id, i_in_class, anchor, child, child_leader, label
+                            // the first shown node stands for the cluster
+                            "  {}.{}{} -> {}.{} [lhead = cluster_{}, {}]",
+                            class.id, i_in_class, anchor, child_leader, first, child_leader, label
                         )?;
                     }
-                    arg_i += 1;
                     Ok(())
                 })?;
             }
@@ -234,3 +424,99 @@ where
         write!(f, "}}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolLang as S, *};
+
+    fn count(dot: &str, needle: &str) -> usize {
+        dot.matches(needle).count()
+    }
+
+    #[test]
+    fn filtered_and_labelled() {
+        #[derive(Default)]
+        struct Depth;
+        impl Analysis<S> for Depth {
+            type Data = usize;
+            fn make(egraph: &EGraph<S, Self>, enode: &S) -> usize {
+                enode.fold(0, |d, child| d.max(egraph[child].data + 1))
+            }
+            fn merge(&mut self, a: &mut usize, b: usize) -> DidMerge {
+                merge_min(a, b)
+            }
+        }
+
+        let mut egraph = EGraph::<S, Depth>::default();
+        let root = egraph.add_expr(&"(f (g a) (h b))".parse().unwrap());
+        let other = egraph.add_expr(&"(k c)".parse().unwrap());
+        egraph.rebuild();
+
+        let all = egraph.dot().to_string();
+        assert_eq!(count(&all, "subgraph"), 7);
+        assert_eq!(count(&all, "->"), 5);
+
+        let dot = egraph
+            .dot()
+            .with_class_label(|class| format!("depth \"{}\"", class.data))
+            .to_string();
+        assert_eq!(count(&dot, "label = \"depth \\\"0\\\"\""), 3);
+        assert_eq!(count(&dot, "label = \"depth \\\"2\\\"\""), 1);
+
+        let shallow = TermBudget {
+            max_depth: 0,
+            ..TermBudget::default()
+        };
+        let dot = egraph.dot().with_class_terms(shallow).to_string();
+        assert!(dot.contains("label = \"(f (...) (...))\""));
+        assert!(dot.contains("label = \"(g (...))\""));
+        assert!(dot.contains("    label = \"a\""));
+
+        let dot = egraph.dot().with_reachable_from(vec![root]).to_string();
+        assert_eq!(count(&dot, "subgraph"), 5);
+        assert!(!dot.contains(&format!("cluster_{} ", other)));
+
+        // hiding (h b) hides b, which is only reachable through it
+        let dot = egraph
+            .dot()
+            .with_reachable_from(vec![root])
+            .with_node_filter(|node| node.op.as_str() != "h")
+            .to_string();
+        assert_eq!(count(&dot, "subgraph"), 3);
+        assert_eq!(count(&dot, "->"), 2);
+
+        let dot = egraph
+            .dot()
+            .with_class_filter(|class| class.data > 0)
+            .to_string();
+        assert_eq!(count(&dot, "subgraph"), 4);
+        assert_eq!(count(&dot, "->"), 2);
+
+        // unions are followed before a rebuild
+        let c = egraph.lookup(S::leaf("c")).unwrap();
+        let a = egraph.lookup(S::leaf("a")).unwrap();
+        egraph.union(a, c);
+        let dot = egraph.dot().with_reachable_from(vec![root]).to_string();
+        assert!(dot.contains("label = \"c\""));
+    }
+
+    #[test]
+    fn missing_program_is_reported() {
+        let egraph = EGraph::<S, ()>::default();
+        let missing = egraph.dot().run("egg-no-such-dot", &["-Tsvg"]).unwrap_err();
+        assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+        assert!(missing.to_string().contains("egg-no-such-dot"));
+    }
+
+    // needs GraphViz
+    #[test]
+    #[ignore]
+    fn renders_with_graphviz() {
+        let mut egraph = EGraph::<S, ()>::default();
+        egraph.add_expr(&"(f (g a) (h b))".parse().unwrap());
+        egraph.rebuild();
+        let dir = std::env::temp_dir();
+        egraph.dot().to_svg(dir.join("egg-dot-test.svg")).unwrap();
+        egraph.dot().to_pdf(dir.join("egg-dot-test.pdf")).unwrap();
+    }
+}
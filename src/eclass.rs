@@ -3,6 +3,43 @@ use std::iter::ExactSizeIterator;
 
 use crate::{Id, Language};
 
+/// A stable identifier for an enode within an [`EGraph`](crate::EGraph).
+///
+/// Each enode gets an [`ENodeId`] the first time it is added to the
+/// egraph, and keeps it regardless of how
+/// [`rebuild`](crate::EGraph::rebuild) reorders its eclass.
+/// When rebuilding finds that two enodes have become identical, the
+/// older [`ENodeId`] is kept, and the newer one becomes an alias for it
+/// (see [`EGraph::canonical_enode_id`](crate::EGraph::canonical_enode_id)).
+#[derive(Clone, Copy, Default, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde-1", serde(transparent))]
+pub struct ENodeId(u32);
+
+impl From<usize> for ENodeId {
+    fn from(n: usize) -> ENodeId {
+        ENodeId(n as u32)
+    }
+}
+
+impl From<ENodeId> for usize {
+    fn from(id: ENodeId) -> usize {
+        id.0 as usize
+    }
+}
+
+impl std::fmt::Debug for ENodeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::fmt::Display for ENodeId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// An equivalence class of enodes.
 #[non_exhaustive]
 #[derive(Debug, Clone)]
@@ -11,11 +48,21 @@ pub struct EClass<L, D> {
     /// This eclass's id.
     pub id: Id,
     /// The equivalent enodes in this equivalence class.
+    ///
+    /// If you change the length of this `Vec` directly, the enodes of
+    /// this class will be given fresh [`ENodeId`]s at the next
+    /// [`rebuild`](crate::EGraph::rebuild).
     pub nodes: Vec<L>,
+    /// The stable ids of the enodes in `nodes`, in the same order.
+    pub(crate) node_ids: Vec<ENodeId>,
     /// The analysis data associated with this eclass.
     pub data: D,
     /// The parent enodes and their original Ids.
     pub(crate) parents: Vec<(L, Id)>,
+    /// Whether this eclass is new, absorbed another in a union, or had
+    /// its enodes repaired since the last rebuild.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) changed: bool,
 }
 
 impl<L, D> EClass<L, D> {
@@ -33,6 +80,21 @@ impl<L, D> EClass<L, D> {
     pub fn iter(&self) -> impl ExactSizeIterator<Item = &L> {
         self.nodes.iter()
     }
+
+    /// Iterates over the enodes in this eclass along with their
+    /// [`ENodeId`]s.
+    ///
+    /// If [`nodes`](EClass::nodes) was changed directly since the last
+    /// [`rebuild`](crate::EGraph::rebuild), its enodes have no ids until
+    /// the next one, so this yields nothing.
+    pub fn nodes_with_ids(&self) -> impl ExactSizeIterator<Item = (ENodeId, &L)> {
+        let ids: &[ENodeId] = if self.node_ids.len() == self.nodes.len() {
+            &self.node_ids
+        } else {
+            &[]
+        };
+        ids.iter().copied().zip(&self.nodes)
+    }
 }
 
 impl<L: Language, D> EClass<L, D> {
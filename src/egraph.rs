@@ -1,6 +1,7 @@
 use crate::*;
 use std::{
-    borrow::BorrowMut,
+    borrow::{BorrowMut, Cow},
+    collections::{BTreeMap, BTreeSet},
     fmt::{self, Debug, Display},
 };
 
@@ -39,6 +40,11 @@ and
 Enabling the `serde-1` feature on this crate will allow you to
 de/serialize [`EGraph`]s using [`serde`](https://serde.rs/).
 You must call [`EGraph::rebuild`] after deserializing an e-graph!
+The per-eclass analysis data is serialized along with it, so it isn't
+made again; the rebuild calls [`Analysis::on_deserialize`] to restore
+anything else the analysis keeps.
+[Flattening](EGraph::with_flattening) isn't serialized, so it is off in
+the loaded e-graph.
 
 [`add`]: EGraph::add()
 [`union`]: EGraph::union()
@@ -56,16 +62,16 @@ pub struct EGraph<L: Language, N: Analysis<L>> {
     pub analysis: N,
     /// The `Explain` used to explain equivalences in this `EGraph`.
     pub(crate) explain: Option<Explain<L>>,
-    unionfind: UnionFind,
+    pub(crate) unionfind: UnionFind,
     /// Stores each enode's `Id`, not the `Id` of the eclass.
     /// Enodes in the memo are canonicalized at each rebuild, but after rebuilding new
     /// unions can cause them to become out of date.
     #[cfg_attr(feature = "serde-1", serde(with = "vectorize"))]
-    memo: HashMap<L, Id>,
+    pub(crate) memo: HashMap<L, Id>,
     /// Nodes which need to be processed for rebuilding. The `Id` is the `Id` of the enode,
     /// not the canonical id of the eclass.
-    pending: Vec<(L, Id)>,
-    analysis_pending: IndexSet<(L, Id)>,
+    pub(crate) pending: Vec<(L, Id)>,
+    pub(crate) analysis_pending: IndexSet<(L, Id)>,
     #[cfg_attr(
         feature = "serde-1",
         serde(bound(
@@ -73,10 +79,76 @@ pub struct EGraph<L: Language, N: Analysis<L>> {
             deserialize = "N::Data: for<'a> Deserialize<'a>",
         ))
     )]
-    classes: HashMap<Id, EClass<L, N::Data>>,
+    pub(crate) classes: HashMap<Id, EClass<L, N::Data>>,
     #[cfg_attr(feature = "serde-1", serde(skip))]
     #[cfg_attr(feature = "serde-1", serde(default = "default_classes_by_op"))]
     pub(crate) classes_by_op: HashMap<std::mem::Discriminant<L>, HashSet<Id>>,
+    /// The (uncanonicalized) eclass each enode was created in, indexed by
+    /// [`ENodeId`].
+    pub(crate) enode_classes: Vec<Id>,
+    /// Maps the [`ENodeId`]s of enodes removed as duplicates during
+    /// rebuilding to the [`ENodeId`] of the enode that was kept.
+    #[cfg_attr(feature = "serde-1", serde(with = "vectorize"))]
+    pub(crate) enode_aliases: HashMap<ENodeId, ENodeId>,
+    /// The origin of each enode, if provenance is enabled.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) provenance: Option<Provenance<L>>,
+    /// Whether instantiating a pattern tries to reuse eclasses through
+    /// [`Analysis::canonicalize_node`].
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    canonical_reuse: bool,
+    /// The associative-commutative operators, if
+    /// [flattening](EGraph::with_flattening).
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) flattening: Option<Flattening<L>>,
+    /// Whether unioning eclasses that are [provably
+    /// distinct](EGraph::provably_distinct) panics.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) distinctness_checks: bool,
+    /// The rule currently being applied, if any.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) current_rule: Option<Symbol>,
+    /// The canonical ids of the [frozen](EGraph::freeze) eclasses.
+    #[cfg_attr(feature = "serde-1", serde(default))]
+    pub(crate) frozen: HashSet<Id>,
+    /// The number of unions skipped because of frozen eclasses.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) frozen_skips: usize,
+    /// The canonical target eclass and instantiated term hash of each
+    /// application in the current apply phase, if
+    /// [deduplicating](Runner::with_apply_dedup).
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    applications: Option<HashSet<(Id, u64)>>,
+    /// The number of applications skipped as duplicates.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) deduped: usize,
+    /// Whether [`Pattern`] appliers skip matches whose instantiation is
+    /// already in the matched eclass.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    skip_redundant: bool,
+    /// The number of matches skipped as redundant.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) redundant_skips: usize,
+    /// The sort checking state, if enabled.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) sorts: Option<SortChecker<L>>,
+    /// The [annotation](ClassAnnotation) layers.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) annotations: Annotations,
+    /// Whether this egraph was loaded and [`Analysis::on_deserialize`]
+    /// is yet to be called, at the next rebuild.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    #[cfg_attr(feature = "serde-1", serde(default = "default_loaded"))]
+    pub(crate) loaded: bool,
+    /// Whether unions are performed or only recorded.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) union_mode: UnionMode,
+    /// The unions recorded in [`UnionMode::Record`].
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) recorded_unions: Vec<RecordedUnion>,
+    /// The match currently being applied, kept while recording unions.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) current_subst: Option<Subst>,
     /// Whether or not reading operation are allowed on this e-graph.
     /// Mutating operations will set this to `false`, and
     /// [`EGraph::rebuild`] will set it to true.
@@ -84,6 +156,16 @@ pub struct EGraph<L: Language, N: Analysis<L>> {
     /// Only manually set it if you know what you're doing.
     #[cfg_attr(feature = "serde-1", serde(skip))]
     pub clean: bool,
+    /// The number of unions since the last rebuild.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) dirty_unions: usize,
+    /// What changed since a [`HistoryRecorder`] last recorded this
+    /// egraph, if one has.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) change_log: Option<ChangeLog<L>>,
+    /// What to roll back to, if a [`Runner`] isolates panics.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub(crate) checkpoint: Option<Checkpoint<L, N>>,
 }
 
 #[cfg(feature = "serde-1")]
@@ -91,6 +173,11 @@ fn default_classes_by_op<K>() -> HashMap<K, HashSet<Id>> {
     HashMap::default()
 }
 
+#[cfg(feature = "serde-1")]
+fn default_loaded() -> bool {
+    true
+}
+
 impl<L: Language, N: Analysis<L> + Default> Default for EGraph<L, N> {
     fn default() -> Self {
         Self::new(N::default())
@@ -113,13 +200,36 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         Self {
             analysis,
             classes: Default::default(),
-            unionfind: Default::default(),
-            clean: false,
+            unionfind: UnionFind::tagged(),
+            // there's nothing to rebuild yet
+            clean: true,
             explain: None,
             pending: Default::default(),
             memo: Default::default(),
             analysis_pending: Default::default(),
             classes_by_op: Default::default(),
+            enode_classes: Default::default(),
+            enode_aliases: Default::default(),
+            provenance: None,
+            canonical_reuse: false,
+            flattening: None,
+            distinctness_checks: false,
+            current_rule: None,
+            frozen: Default::default(),
+            frozen_skips: 0,
+            applications: None,
+            deduped: 0,
+            skip_redundant: false,
+            redundant_skips: 0,
+            sorts: None,
+            annotations: Default::default(),
+            loaded: false,
+            union_mode: UnionMode::Perform,
+            recorded_unions: vec![],
+            current_subst: None,
+            dirty_unions: 0,
+            change_log: None,
+            checkpoint: None,
         }
     }
 
@@ -130,6 +240,14 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
 
     /// Returns an mutating iterator over the eclasses in the egraph.
     pub fn classes_mut(&mut self) -> impl ExactSizeIterator<Item = &mut EClass<L, N::Data>> {
+        if let Some(log) = &mut self.change_log {
+            log.classes.extend(self.classes.keys());
+        }
+        if let Some(checkpoint) = &mut self.checkpoint {
+            for class in self.classes.values() {
+                checkpoint.save_class(class.id, Some(class));
+            }
+        }
         self.classes.values_mut()
     }
 
@@ -146,6 +264,71 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         self.memo.is_empty()
     }
 
+    /// Removes every eclass and enode, keeping the allocations for
+    /// reuse, so filling the egraph again doesn't reallocate.
+    ///
+    /// Settings like [explanations](EGraph::with_explanations_enabled),
+    /// [provenance](EGraph::with_provenance), sort checking, the
+    /// [`UnionMode`] and the registered [annotation](EGraph::annotate)
+    /// layers are kept, but everything they recorded is forgotten, as
+    /// are [frozen](EGraph::freeze) eclasses and
+    /// [recorded unions](EGraph::recorded_unions).
+    /// The [`Analysis`] is kept as is.
+    ///
+    /// Ids from before clearing must not be used afterwards.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// egraph.add_expr(&"(+ x y)".parse().unwrap());
+    /// egraph.rebuild();
+    ///
+    /// egraph.clear_keep_capacity();
+    /// assert!(egraph.is_empty());
+    /// assert_eq!(egraph.number_of_classes(), 0);
+    /// let x = egraph.add(S::leaf("x"));
+    /// assert_eq!(egraph.lookup(S::leaf("x")), Some(x));
+    /// ```
+    pub fn clear_keep_capacity(&mut self) {
+        self.save_whole();
+        if let Some(explain) = &mut self.explain {
+            explain.clear();
+        }
+        self.unionfind.clear();
+        self.memo.clear();
+        self.pending.clear();
+        self.analysis_pending.clear();
+        self.classes.clear();
+        self.classes_by_op.values_mut().for_each(|ids| ids.clear());
+        self.enode_classes.clear();
+        self.enode_aliases.clear();
+        if let Some(provenance) = &mut self.provenance {
+            provenance.clear();
+        }
+        self.current_rule = None;
+        self.frozen.clear();
+        self.frozen_skips = 0;
+        if let Some(applications) = &mut self.applications {
+            applications.clear();
+        }
+        self.deduped = 0;
+        self.redundant_skips = 0;
+        if let Some(sorts) = &mut self.sorts {
+            sorts.clear();
+        }
+        self.annotations.clear();
+        self.loaded = false;
+        self.recorded_unions.clear();
+        self.current_subst = None;
+        self.clean = true;
+        self.dirty_unions = 0;
+        if let Some(log) = &mut self.change_log {
+            *log = ChangeLog::default();
+            log.cleared = true;
+        }
+    }
+
     /// Returns the number of enodes in the `EGraph`.
     ///
     /// Actually returns the size of the hashcons index.
@@ -176,6 +359,287 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         self.classes.len()
     }
 
+    /// Returns the number of enodes with each operator, and the number
+    /// of eclasses containing at least one of them, keyed by
+    /// [`Language::op_display`].
+    ///
+    /// On a [`clean`](EGraph::clean) egraph this only walks the op index,
+    /// one operator at a time; otherwise it makes a single pass over
+    /// every eclass.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let x = egraph.add(S::leaf("x"));
+    /// let fx = egraph.add(S::new("f", vec![x]));
+    /// let ffx = egraph.add(S::new("f", vec![fx]));
+    /// egraph.union(fx, ffx);
+    /// egraph.rebuild();
+    ///
+    /// let stats = egraph.op_stats();
+    /// assert_eq!(stats["f"], OpStats { nodes: 2, classes: 1 });
+    /// assert_eq!(stats["x"], OpStats { nodes: 1, classes: 1 });
+    /// ```
+    pub fn op_stats(&self) -> BTreeMap<String, OpStats> {
+        fn count<'a, L: Language + 'a>(
+            stats: &mut BTreeMap<String, OpStats>,
+            nodes: impl Iterator<Item = &'a L>,
+        ) {
+            let mut ops: Vec<&str> = nodes.map(|n| n.op_display()).collect();
+            for op in &ops {
+                stats.entry(op.to_string()).or_default().nodes += 1;
+            }
+            ops.sort_unstable();
+            ops.dedup();
+            for op in &ops {
+                stats.get_mut(*op).unwrap().classes += 1;
+            }
+        }
+
+        let mut stats: BTreeMap<String, OpStats> = BTreeMap::new();
+        if self.clean {
+            for (key, ids) in &self.classes_by_op {
+                for &id in ids {
+                    #[allow(clippy::mem_discriminant_non_enum)]
+                    let nodes = self[id]
+                        .iter()
+                        .filter(|n| std::mem::discriminant(*n) == *key);
+                    count(&mut stats, nodes);
+                }
+            }
+        } else {
+            for class in self.classes() {
+                count(&mut stats, class.iter());
+            }
+        }
+        stats
+    }
+
+    /// Returns the enodes that [`match`](Language::matches) `op`, as
+    /// tuples of their eclass and their children.
+    ///
+    /// This views the egraph as a database, where each operator names a
+    /// relation, so `(+ a b)` in eclass `c` is the tuple `(c, [a, b])` of
+    /// the `+` relation.
+    /// Only the eclasses the op index has for `op` are visited, and the
+    /// tuples come in no particular order.
+    /// The egraph must be [`clean`](EGraph::clean), like for searching,
+    /// so all the ids are canonical.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let x = egraph.add(S::leaf("x"));
+    /// let y = egraph.add(S::leaf("y"));
+    /// let xy = egraph.add(S::new("+", vec![x, y]));
+    /// let yx = egraph.add(S::new("+", vec![y, x]));
+    /// egraph.union(xy, yx);
+    /// egraph.rebuild();
+    ///
+    /// let op = S::new("+", vec![x, x]);
+    /// let mut tuples: Vec<_> = egraph.relation(&op).collect();
+    /// tuples.sort();
+    /// let xy = egraph.find(xy);
+    /// assert_eq!(tuples, vec![(xy, vec![x, y]), (xy, vec![y, x])]);
+    /// ```
+    pub fn relation<'a>(&'a self, op: &'a L) -> impl Iterator<Item = (Id, Vec<Id>)> + 'a {
+        assert!(
+            self.clean,
+            "Tried to read a relation of a dirty e-graph! Use EGraph::with_clean_view."
+        );
+        #[allow(clippy::mem_discriminant_non_enum)]
+        let key = std::mem::discriminant(op);
+        self.classes_by_op
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .flat_map(move |&id| {
+                self[id]
+                    .iter()
+                    .filter(move |n| op.matches(n))
+                    .map(move |n| (id, n.children().to_vec()))
+            })
+    }
+
+    /// Returns every operator in the egraph with the number of tuples
+    /// in its [`relation`](EGraph::relation), sorted by
+    /// [`Language::op_display`].
+    ///
+    /// Each operator is an enode of it with all its children
+    /// `Id::from(0)`, to pass to [`relation`](EGraph::relation).
+    pub fn relations(&self) -> Vec<(L, usize)> {
+        let mut counts: HashMap<L, usize> = HashMap::default();
+        for class in self.classes() {
+            for node in class.iter() {
+                let op = node.clone().map_children(|_| Id::from(0));
+                *counts.entry(op).or_default() += 1;
+            }
+        }
+        let mut relations: Vec<(L, usize)> = counts.into_iter().collect();
+        relations.sort_unstable_by(|(a, _), (b, _)| {
+            let by_op = a.op_display().cmp(b.op_display());
+            by_op
+                .then_with(|| a.len().cmp(&b.len()))
+                .then_with(|| a.cmp(b))
+        });
+        relations
+    }
+
+    /// Returns the eclasses that may have an enode of the same variant as
+    /// `op`: the ones the op index has for it if the egraph is
+    /// [`clean`](EGraph::clean), and every eclass otherwise.
+    pub(crate) fn classes_with_op<'a>(
+        &'a self,
+        op: &L,
+    ) -> Box<dyn Iterator<Item = &'a EClass<L, N::Data>> + 'a> {
+        if !self.clean {
+            return Box::new(self.classes());
+        }
+        #[allow(clippy::mem_discriminant_non_enum)]
+        let key = std::mem::discriminant(op);
+        let ids = self.classes_by_op.get(&key).into_iter().flatten();
+        Box::new(ids.map(move |&id| &self[id]))
+    }
+
+    /// Returns true if some eclass has an enode that
+    /// [`matches`](Language::matches) `enode`, as of the last rebuild.
+    pub(crate) fn has_op(&self, enode: &L) -> bool {
+        #[allow(clippy::mem_discriminant_non_enum)]
+        let key = std::mem::discriminant(enode);
+        self.classes_by_op.get(&key).map_or(false, |ids| {
+            ids.iter()
+                .any(|&id| self[id].iter().any(|n| enode.matches(n)))
+        })
+    }
+
+    /// Enable or disable reusing existing eclasses when instantiating
+    /// patterns.
+    ///
+    /// When enabled, each subterm that an [`Applier`] or
+    /// [`ConditionEqual`] instantiates from a [`Pattern`] and that isn't
+    /// in the egraph yet is first normalized with
+    /// [`Analysis::canonicalize_node`].
+    /// If the normalized enode is in the egraph, its eclass is used
+    /// instead of adding a new one, avoiding classes that would only be
+    /// merged later.
+    /// The root of the pattern is always added as is, since it is put
+    /// in the matched eclass anyway.
+    /// This is off by default, and it is ignored when explanations are
+    /// enabled, since they need the exact terms that rules produce.
+    pub fn with_canonical_reuse(mut self, enabled: bool) -> Self {
+        self.canonical_reuse = enabled;
+        self
+    }
+
+    /// Check if [canonical reuse](EGraph::with_canonical_reuse) is enabled.
+    pub fn is_canonical_reuse_enabled(&self) -> bool {
+        self.canonical_reuse
+    }
+
+    /// Normalize the associative-commutative operators `ops`.
+    ///
+    /// Adding a term, with [`add_expr`](EGraph::add_expr) or by
+    /// instantiating a pattern, flattens nested applications of the same
+    /// operator into one n-ary enode, so `(+ a (+ b c))` and
+    /// `(+ (+ c b) a)` both add `(+ a b c)`, and the children of every
+    /// application of the operators are kept sorted by eclass, at adds,
+    /// lookups and [`rebuild`](EGraph::rebuild)s.
+    /// The operators are named by [`op_display`](Language::op_display),
+    /// and an application is only flattened if
+    /// [`with_children`](Language::with_children) can rebuild the
+    /// operator with the new arity, as it can for [`SymbolLang`];
+    /// the others are only sorted.
+    ///
+    /// [`add`](EGraph::add) of a single enode only sorts it, since its
+    /// children are eclasses rather than terms.
+    /// A rebuild re-sorts enodes whose children were merged, and
+    /// flattens them again: when `x` is merged with `(+ b c)`, the
+    /// eclass of `(+ a x)` gets `(+ a b c)` too.
+    /// Patterns match the sorted, flattened enodes as they are, so
+    /// rules over the operators should search with an [`AcPattern`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the egraph isn't empty or explanations are enabled,
+    /// since they need the exact terms that were added.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default().with_flattening(vec!["+"]);
+    /// let a = egraph.add_expr(&"(+ a (+ b c))".parse().unwrap());
+    /// let b = egraph.add_expr(&"(+ (+ c b) a)".parse().unwrap());
+    /// egraph.rebuild();
+    /// assert_eq!(a, b);
+    /// assert_eq!(egraph[a].nodes.len(), 1);
+    /// assert_eq!(egraph[a].nodes[0].len(), 3);
+    /// ```
+    pub fn with_flattening<I, S>(mut self, ops: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        assert!(
+            self.explain.is_none(),
+            "Flattening can't be used with explanations."
+        );
+        assert!(
+            self.total_size() == 0,
+            "Need to set flattening before adding any expressions to the egraph."
+        );
+        self.flattening = Some(Flattening::new(ops));
+        self
+    }
+
+    /// Check if [flattening](EGraph::with_flattening) is enabled.
+    pub fn is_flattening_enabled(&self) -> bool {
+        self.flattening.is_some()
+    }
+
+    /// The nodes of `expr`, [flattened](EGraph::with_flattening) if
+    /// enabled.
+    fn flattened_expr<'a>(&self, expr: &'a RecExpr<L>) -> Cow<'a, [L]> {
+        match &self.flattening {
+            Some(flattening) => Cow::Owned(flattening.flatten_expr(expr.as_ref())),
+            None => Cow::Borrowed(expr.as_ref()),
+        }
+    }
+
+    /// The nodes of `pat`, [flattened](EGraph::with_flattening) if
+    /// enabled.
+    ///
+    /// Each pattern is only flattened once, and looked up after that.
+    pub(crate) fn flattened_pattern<'a>(&self, pat: &'a [ENodeOrVar<L>]) -> FlatPattern<'a, L> {
+        match &self.flattening {
+            Some(flattening) => FlatPattern::Cached(flattening.flatten_pattern_cached(pat)),
+            None => FlatPattern::Borrowed(pat),
+        }
+    }
+
+    /// Look up the [canonical form](Analysis::canonicalize_node) of an
+    /// enode that is being instantiated from a pattern, if canonical
+    /// reuse is enabled.
+    pub(crate) fn lookup_canonical(&self, enode: &L) -> Option<Id> {
+        if !self.canonical_reuse || self.explain.is_some() {
+            return None;
+        }
+        N::canonicalize_node(self, enode).and_then(|node| self.lookup(node))
+    }
+
+    /// Add an enode that is being instantiated from a pattern, reusing
+    /// the eclass of its [canonical form](Analysis::canonicalize_node)
+    /// if canonical reuse is enabled and the enode itself is new.
+    pub(crate) fn add_instantiated_node(&mut self, enode: L) -> Id {
+        if self.canonical_reuse && self.lookup(enode.clone()).is_none() {
+            if let Some(id) = self.lookup_canonical(&enode) {
+                return id;
+            }
+        }
+        self.add(enode)
+    }
+
     /// Enable explanations for this `EGraph`.
     /// This allows the egraph to explain why two expressions are
     /// equivalent with the [`explain_equivalence`](EGraph::explain_equivalence) function.
@@ -186,6 +650,10 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         if self.total_size() > 0 {
             panic!("Need to set explanations enabled before adding any expressions to the egraph.");
         }
+        assert!(
+            self.flattening.is_none(),
+            "Explanations can't be used with flattening."
+        );
         self.explain = Some(Explain::new());
         self
     }
@@ -291,25 +759,118 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         self.unionfind.find_mut(id)
     }
 
+    /// Returns the eclass of `id`, or [`Error::InvalidId`] if `id`
+    /// isn't an eclass of this egraph.
+    ///
+    /// This is the fallible version of indexing with `egraph[id]`.
+    pub fn try_get(&self, id: Id) -> Result<&EClass<L, N::Data>, Error> {
+        self.unionfind.check_tag(id);
+        if usize::from(id) >= self.unionfind.size() {
+            return Err(Error::InvalidId(id));
+        }
+        self.classes.get(&self.find(id)).ok_or(Error::InvalidId(id))
+    }
+
     /// Creates a [`Dot`] to visualize this egraph. See [`Dot`].
     ///
     pub fn dot(&self) -> Dot<L, N> {
         Dot {
             egraph: self,
+            class_label: None,
+            class_filter: None,
+            node_filter: None,
+            roots: None,
             config: vec![],
             use_anchors: true,
+            rule_colors: false,
         }
     }
 }
 
+/// The number of enodes with an operator, and the number of eclasses
+/// containing them, as returned by [`EGraph::op_stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-1", derive(Serialize))]
+pub struct OpStats {
+    /// The number of enodes with this operator.
+    pub nodes: usize,
+    /// The number of eclasses with at least one enode with this operator.
+    pub classes: usize,
+}
+
+/// What a call to [`EGraph::rebuild`] did.
+///
+/// Times are in seconds.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde-1", derive(Serialize))]
+pub struct RebuildReport {
+    /// The number of unions made to restore congruence.
+    pub unions: usize,
+    /// The number of eclasses whose enodes changed when canonicalized,
+    /// or that had duplicate enodes removed.
+    pub repaired_classes: usize,
+    /// The number of enodes removed as duplicates.
+    pub trimmed_nodes: usize,
+    /// The number of eclasses whose set of enodes changed since the
+    /// last rebuild: new ones, ones that absorbed another eclass in a
+    /// union, and repaired ones.
+    pub changed_classes: usize,
+    /// The number of enodes canonicalized and reinserted into the
+    /// hashcons.
+    pub memo_reinserts: usize,
+    /// The number of times an enode's analysis data changed the data of
+    /// its eclass.
+    pub analysis_merges: usize,
+    /// The number of rounds of congruence and analysis repair until
+    /// nothing was left to do.
+    pub passes: usize,
+    /// Time spent restoring congruence.
+    pub congruence_time: f64,
+    /// Time spent propagating analysis data.
+    pub analysis_time: f64,
+    /// Time spent canonicalizing and deduplicating eclasses.
+    pub classes_time: f64,
+    /// Total time spent rebuilding.
+    pub total_time: f64,
+}
+
+/// The change in an operator's [`OpStats`] over an [`Iteration`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-1", derive(Serialize))]
+pub struct OpDelta {
+    /// The change in the number of enodes with this operator.
+    pub nodes: isize,
+    /// The change in the number of eclasses with this operator.
+    pub classes: isize,
+}
+
+impl OpDelta {
+    /// Computes the change of every operator from `before` to `after`,
+    /// leaving out operators that didn't change.
+    pub fn between(
+        before: &BTreeMap<String, OpStats>,
+        after: &BTreeMap<String, OpStats>,
+    ) -> BTreeMap<String, OpDelta> {
+        let ops: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+        ops.into_iter()
+            .filter_map(|op| {
+                let b = before.get(op).copied().unwrap_or_default();
+                let a = after.get(op).copied().unwrap_or_default();
+                let delta = OpDelta {
+                    nodes: a.nodes as isize - b.nodes as isize,
+                    classes: a.classes as isize - b.classes as isize,
+                };
+                (delta != OpDelta::default()).then(|| (op.clone(), delta))
+            })
+            .collect()
+    }
+}
+
 /// Given an `Id` using the `egraph[id]` syntax, retrieve the e-class.
 impl<L: Language, N: Analysis<L>> std::ops::Index<Id> for EGraph<L, N> {
     type Output = EClass<L, N::Data>;
     fn index(&self, id: Id) -> &Self::Output {
-        let id = self.find(id);
-        self.classes
-            .get(&id)
-            .unwrap_or_else(|| panic!("Invalid id {}", id))
+        self.try_get(id).unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
@@ -318,9 +879,10 @@ impl<L: Language, N: Analysis<L>> std::ops::Index<Id> for EGraph<L, N> {
 impl<L: Language, N: Analysis<L>> std::ops::IndexMut<Id> for EGraph<L, N> {
     fn index_mut(&mut self, id: Id) -> &mut Self::Output {
         let id = self.find_mut(id);
+        self.log_class(id);
         self.classes
             .get_mut(&id)
-            .unwrap_or_else(|| panic!("Invalid id {}", id))
+            .unwrap_or_else(|| panic!("{}", Error::InvalidId(id)))
     }
 }
 
@@ -338,54 +900,87 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
     /// assert_eq!(plus, egraph.add_expr(&plus_recexpr));
     /// ```
     ///
+    /// # Panics
+    ///
+    /// Panics if `expr` is empty.
+    ///
     /// [`add_expr`]: EGraph::add_expr()
     pub fn add_expr(&mut self, expr: &RecExpr<L>) -> Id {
+        assert!(!expr.as_ref().is_empty(), "Can't add an empty expression");
         let id = self.add_expr_internal(expr);
         self.find(id)
     }
 
     /// Adds an expr to the egraph, and returns the uncanonicalized id of the top enode.
     fn add_expr_internal(&mut self, expr: &RecExpr<L>) -> Id {
-        let nodes = expr.as_ref();
+        let nodes = self.flattened_expr(expr);
         let mut new_ids = Vec::with_capacity(nodes.len());
         let mut new_node_q = Vec::with_capacity(nodes.len());
-        for node in nodes {
-            let new_node = node.clone().map_children(|i| new_ids[usize::from(i)]);
-            let size_before = self.unionfind.size();
-            let next_id = self.add_internal(new_node);
-            if self.unionfind.size() > size_before {
-                new_node_q.push(true);
-            } else {
-                new_node_q.push(false);
-            }
-            if let Some(explain) = &mut self.explain {
-                node.for_each(|child| {
-                    // Set the existance reason for new nodes to their parent node.
-                    if new_node_q[usize::from(child)] {
-                        explain.set_existance_reason(new_ids[usize::from(child)], next_id);
-                    }
-                });
-            }
-            new_ids.push(next_id);
+        for node in nodes.iter() {
+            self.add_expr_node(node, &mut new_ids, &mut new_node_q);
         }
         *new_ids.last().unwrap()
     }
 
+    /// Adds the next `node` of an expression whose children are indices
+    /// into `new_ids`, the uncanonicalized ids of the nodes before it,
+    /// and `new_node_q`, whether each of them made a new eclass.
+    pub(crate) fn add_expr_node(
+        &mut self,
+        node: &L,
+        new_ids: &mut Vec<Id>,
+        new_node_q: &mut Vec<bool>,
+    ) {
+        let new_node = node.clone().map_children(|i| new_ids[usize::from(i)]);
+        let size_before = self.unionfind.size();
+        let next_id = self.add_internal(new_node);
+        if self.unionfind.size() > size_before {
+            new_node_q.push(true);
+        } else {
+            new_node_q.push(false);
+        }
+        if let Some(explain) = &mut self.explain {
+            node.for_each(|child| {
+                // Set the existance reason for new nodes to their parent node.
+                if new_node_q[usize::from(child)] {
+                    explain.set_existance_reason(new_ids[usize::from(child)], next_id);
+                }
+            });
+        }
+        new_ids.push(next_id);
+    }
+
     /// Adds a [`Pattern`] and a substitution to the [`EGraph`], returning
     /// the eclass of the instantiated pattern.
-    pub fn add_instantiation(&mut self, pat: &PatternAst<L>, subst: &Subst) -> Id {
+    ///
+    /// The substitution can be a [`Subst`] or a [`PackedSubst`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pat` is empty.
+    pub fn add_instantiation<B>(&mut self, pat: &PatternAst<L>, subst: &B) -> Id
+    where
+        B: Bindings + ?Sized,
+    {
+        assert!(
+            !pat.as_ref().is_empty(),
+            "Can't instantiate an empty pattern"
+        );
         let id = self.add_instantiation_internal(pat, subst);
         self.find(id)
     }
 
-    fn add_instantiation_internal(&mut self, pat: &PatternAst<L>, subst: &Subst) -> Id {
-        let nodes = pat.as_ref().as_ref();
+    fn add_instantiation_internal<B>(&mut self, pat: &PatternAst<L>, subst: &B) -> Id
+    where
+        B: Bindings + ?Sized,
+    {
+        let nodes = self.flattened_pattern(pat.as_ref());
         let mut new_ids = Vec::with_capacity(nodes.len());
         let mut new_node_q = Vec::with_capacity(nodes.len());
-        for node in nodes {
+        for node in nodes.iter() {
             match node {
                 ENodeOrVar::Var(var) => {
-                    let id = subst[*var];
+                    let id = subst.id_of(*var);
                     new_ids.push(id);
                     new_node_q.push(false);
                 }
@@ -451,6 +1046,9 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
     {
         let enode = enode.borrow_mut();
         enode.update_children(|id| self.find(id));
+        if let Some(flattening) = &self.flattening {
+            flattening.sort(enode);
+        }
         self.memo.get(enode).copied()
     }
 
@@ -464,9 +1062,9 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
 
     /// Lookup the eclasses of all the nodes in the given [`RecExpr`].
     pub fn lookup_expr_ids(&self, expr: &RecExpr<L>) -> Option<Vec<Id>> {
-        let nodes = expr.as_ref();
+        let nodes = self.flattened_expr(expr);
         let mut new_ids = Vec::with_capacity(nodes.len());
-        for node in nodes {
+        for node in nodes.iter() {
             let node = node.clone().map_children(|i| new_ids[usize::from(i)]);
             let id = self.lookup(node)?;
             new_ids.push(id)
@@ -511,7 +1109,11 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
                 existing_id
             }
         } else {
+            let poisoned = self.check_sorts_add(&enode);
             let id = self.make_new_eclass(enode);
+            if poisoned {
+                self.poison_class(id);
+            }
             if let Some(explain) = self.explain.as_mut() {
                 explain.add(original, id, id);
             }
@@ -527,11 +1129,15 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
     fn make_new_eclass(&mut self, enode: L) -> Id {
         let id = self.unionfind.make_set();
         log::trace!("  ...adding to {}", id);
+        let enode_id = ENodeId::from(self.enode_classes.len());
+        self.enode_classes.push(id);
         let class = EClass {
             id,
             nodes: vec![enode.clone()],
+            node_ids: vec![enode_id],
             data: N::make(self, &enode),
             parents: Default::default(),
+            changed: true,
         };
 
         // add this enode to the parent lists of its children
@@ -543,12 +1149,37 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         // TODO is this needed?
         self.pending.push((enode.clone(), id));
 
+        self.log_class(id);
+        self.log_memo(&enode);
         self.classes.insert(id, class);
         assert!(self.memo.insert(enode, id).is_none());
+        self.record_provenance();
 
         id
     }
 
+    /// Notes that an eclass is about to change in the change log, and
+    /// saves it in the checkpoint, if there are either.
+    fn log_class(&mut self, id: Id) {
+        if let Some(log) = &mut self.change_log {
+            log.classes.insert(id);
+        }
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint.save_class(id, self.classes.get(&id));
+        }
+    }
+
+    /// Notes that a hashcons entry is about to change in the change log,
+    /// and saves it in the checkpoint, if there are either.
+    fn log_memo(&mut self, node: &L) {
+        if let Some(log) = &mut self.change_log {
+            log.memo.insert(node.clone());
+        }
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint.save_memo(node, self.memo.get(node).copied());
+        }
+    }
+
     /// Checks whether two [`RecExpr`]s are equivalent.
     /// Returns a list of id where both expression are represented.
     /// In most cases, there will none or exactly one id.
@@ -575,6 +1206,149 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         equiv_eclasses
     }
 
+    /// Lazily enumerates every distinct term represented by the eclass `id`
+    /// whose [`AstSize`] is at most `max_size`.
+    ///
+    /// Terms are yielded in order of increasing size. Cycles in the e-graph
+    /// are handled by the size bound, so this always terminates.
+    /// Each size level is computed the first time the iterator reaches it
+    /// and memoized (per class) for the larger levels.
+    ///
+    /// Be careful: the number of terms can grow exponentially in
+    /// `max_size` (consider an eclass containing both `a` and `b` under a
+    /// binary operator). Use [`count_terms`](EGraph::count_terms) to check
+    /// how many terms there are before materializing them.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let a = egraph.add(S::leaf("a"));
+    /// let b = egraph.add(S::leaf("b"));
+    /// let f = egraph.add(S::new("f", vec![a]));
+    /// egraph.union(a, b);
+    /// egraph.rebuild();
+    ///
+    /// let terms: Vec<String> = egraph.enumerate_terms(f, 2).map(|t| t.to_string()).collect();
+    /// assert_eq!(terms, vec!["(f a)", "(f b)"]);
+    /// assert_eq!(egraph.count_terms(f, 2), 2);
+    /// ```
+    pub fn enumerate_terms(
+        &self,
+        id: Id,
+        max_size: usize,
+    ) -> impl Iterator<Item = RecExpr<L>> + '_ {
+        let id = self.find(id);
+        let mut memo = HashMap::default();
+        (1..=max_size).flat_map(move |size| {
+            let terms = self.terms_of_size(&mut memo, id, size);
+            terms.as_ref().clone().into_iter()
+        })
+    }
+
+    /// Counts the distinct terms represented by the eclass `id` whose
+    /// [`AstSize`] is at most `max_size`, without materializing them.
+    ///
+    /// This uses the same recurrence as
+    /// [`enumerate_terms`](EGraph::enumerate_terms): the terms of size `s` in
+    /// a class are, for each enode, the ways of splitting `s - 1` among the
+    /// enode's children. The count saturates at [`u128::MAX`].
+    pub fn count_terms(&self, id: Id, max_size: usize) -> u128 {
+        let id = self.find(id);
+        let mut memo = HashMap::default();
+        (1..=max_size).fold(0u128, |total, size| {
+            total.saturating_add(self.count_terms_of_size(&mut memo, id, size))
+        })
+    }
+
+    fn terms_of_size(
+        &self,
+        memo: &mut HashMap<(Id, usize), std::rc::Rc<Vec<RecExpr<L>>>>,
+        id: Id,
+        size: usize,
+    ) -> std::rc::Rc<Vec<RecExpr<L>>> {
+        if let Some(terms) = memo.get(&(id, size)) {
+            return terms.clone();
+        }
+
+        let mut terms = IndexSet::default();
+        for node in &self[id].nodes {
+            let children: Vec<Id> = node.children().iter().map(|&c| self.find(c)).collect();
+            if size < 1 + children.len() {
+                continue;
+            }
+            for split in size_splits(size - 1, children.len()) {
+                let child_terms: Vec<_> = children
+                    .iter()
+                    .zip(&split)
+                    .map(|(&c, &s)| self.terms_of_size(memo, c, s))
+                    .collect();
+                if child_terms.iter().any(|ts| ts.is_empty()) {
+                    continue;
+                }
+
+                // walk the cartesian product of the children's terms
+                let mut choice = vec![0; children.len()];
+                loop {
+                    let mut i = 0;
+                    let term = node.join_recexprs(|_| {
+                        let expr = &child_terms[i][choice[i]];
+                        i += 1;
+                        expr
+                    });
+                    terms.insert(term);
+
+                    match (0..choice.len())
+                        .rev()
+                        .find(|&j| choice[j] + 1 < child_terms[j].len())
+                    {
+                        Some(j) => {
+                            choice[j] += 1;
+                            choice[j + 1..].iter_mut().for_each(|c| *c = 0);
+                        }
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        let terms = std::rc::Rc::new(terms.into_iter().collect::<Vec<_>>());
+        memo.insert((id, size), terms.clone());
+        terms
+    }
+
+    fn count_terms_of_size(
+        &self,
+        memo: &mut HashMap<(Id, usize), u128>,
+        id: Id,
+        size: usize,
+    ) -> u128 {
+        if let Some(&count) = memo.get(&(id, size)) {
+            return count;
+        }
+
+        let mut count = 0u128;
+        for node in &self[id].nodes {
+            let children: Vec<Id> = node.children().iter().map(|&c| self.find(c)).collect();
+            if size < 1 + children.len() {
+                continue;
+            }
+            for split in size_splits(size - 1, children.len()) {
+                let ways = children.iter().zip(&split).fold(1u128, |ways, (&c, &s)| {
+                    if ways == 0 {
+                        0
+                    } else {
+                        ways.saturating_mul(self.count_terms_of_size(memo, c, s))
+                    }
+                });
+                count = count.saturating_add(ways);
+            }
+        }
+
+        memo.insert((id, size), count);
+        count
+    }
+
     /// Given two patterns and a substitution, add the patterns
     /// and union them.
     ///
@@ -609,6 +1383,14 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
     /// The given ids need not be canonical.
     /// The returned `bool` indicates whether a union is necessary,
     /// so it's `false` if they were already equivalent.
+    /// Unioning an eclass with itself (or with an id already in it) is a
+    /// no-op: it leaves the egraph untouched, and [`clean`](EGraph::clean)
+    /// if it was.
+    ///
+    /// Either eclass may be an ancestor of the other, like `a` and
+    /// `(f a)`; the union then makes a cycle, which is legal, and leaves the
+    /// parent lists consistent right away (see
+    /// [`check_invariants`](EGraph::check_invariants)).
     ///
     /// When explanations are enabled, this function is not available.
     /// Instead, use [`union_instantiations`](EGraph::union_instantiations).
@@ -623,19 +1405,44 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         self.perform_union(id1, id2, None, false)
     }
 
-    fn perform_union(
+    /// Like [`union`](EGraph::union), but fails instead of unioning if
+    /// either id is invalid, or if the eclasses are
+    /// [provably distinct](EGraph::provably_distinct).
+    ///
+    /// The distinctness check happens whether or not
+    /// [distinctness checks](EGraph::with_distinctness_checks) are
+    /// enabled.
+    pub fn try_union(&mut self, id1: Id, id2: Id) -> Result<bool, Error> {
+        let (id1, id2) = (self.try_get(id1)?.id, self.try_get(id2)?.id);
+        if let Some(e) = self.distinct_union_error(id1, id2, None) {
+            return Err(e);
+        }
+        Ok(self.union(id1, id2))
+    }
+
+    pub(crate) fn perform_union(
         &mut self,
         enode_id1: Id,
         enode_id2: Id,
         rule: Option<Justification>,
         any_new_rhs: bool,
     ) -> bool {
-        self.clean = false;
         let mut id1 = self.find_mut(enode_id1);
         let mut id2 = self.find_mut(enode_id2);
         if id1 == id2 {
             return false;
         }
+        let congruence = matches!(rule, Some(Justification::Congruence));
+        if !congruence && (self.frozen.contains(&id1) || self.frozen.contains(&id2)) {
+            debug!("Skipping union of {} and {}, one is frozen", id1, id2);
+            self.frozen_skips += 1;
+            return false;
+        }
+        if !congruence && !self.check_sorts_union(id1, id2) {
+            return false;
+        }
+        // skipped unions leave the egraph as it was
+        self.clean = false;
         // make sure class2 has fewer parents
         let class1_parents = self.classes[&id1].parents.len();
         let class2_parents = self.classes[&id2].parents.len();
@@ -643,7 +1450,18 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
             std::mem::swap(&mut id1, &mut id2);
         }
 
+        if self.distinctness_checks {
+            self.check_distinct_union(id1, id2, rule.as_ref());
+        }
+
+        if !congruence && self.union_mode == UnionMode::Record {
+            self.record_union(enode_id1, enode_id2, id1);
+            return true;
+        }
+
         N::pre_union(self, id1, id2);
+        self.analysis
+            .pre_merge(&self.classes[&id1], &self.classes[&id2]);
 
         if let Some(explain) = &mut self.explain {
             explain.union(enode_id1, enode_id2, rule.unwrap(), any_new_rhs);
@@ -651,6 +1469,17 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
 
         // make id1 the new root
         self.unionfind.union(id1, id2);
+        self.dirty_unions += 1;
+        self.log_class(id1);
+        self.log_class(id2);
+        if let Some(log) = &mut self.change_log {
+            log.parents.insert(id2);
+        }
+        if self.frozen.remove(&id2) {
+            self.frozen.insert(id1);
+        }
+        self.merge_poison(id1, id2);
+        self.merge_annotations(id1, id2);
 
         assert_ne!(id1, id2);
         let class2 = self.classes.remove(&id2).unwrap();
@@ -658,6 +1487,14 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         assert_eq!(id1, class1.id);
 
         self.pending.extend(class2.parents.iter().cloned());
+        // the root's parents may have a flatter form now, too
+        if let Some(flattening) = &self.flattening {
+            let flattened = class1
+                .parents
+                .iter()
+                .filter(|(n, _)| flattening.is_flattened(n));
+            self.pending.extend(flattened.cloned());
+        }
         let did_merge = self.analysis.merge(&mut class1.data, class2.data);
         if did_merge.0 {
             self.analysis_pending.extend(class1.parents.iter().cloned());
@@ -666,39 +1503,332 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
             self.analysis_pending.extend(class2.parents.iter().cloned());
         }
 
+        // concat_vecs decides the order based on length, so these stay aligned
         concat_vecs(&mut class1.nodes, class2.nodes);
+        concat_vecs(&mut class1.node_ids, class2.node_ids);
         concat_vecs(&mut class1.parents, class2.parents);
+        class1.changed = true;
 
         N::modify(self, id1);
         true
     }
 
-    /// Returns a more debug-able representation of the egraph.
+    /// Freezes the eclass `id`, protecting it from further growth.
     ///
-    /// [`EGraph`]s implement [`Debug`], but it ain't pretty. It
-    /// prints a lot of stuff you probably don't care about.
-    /// This method returns a wrapper that implements [`Debug`] in a
-    /// slightly nicer way, just dumping enodes in each eclass.
+    /// A frozen eclass can still be searched, so rules may match in and
+    /// below it, but writes to it become no-ops:
+    /// [`union`](EGraph::union)ing it (including through
+    /// [`union_instantiations`](EGraph::union_instantiations), an
+    /// [`Applier`] or [`Analysis::modify`]) with another eclass does
+    /// nothing and returns `false`.
+    /// Since [`add`](EGraph::add) only ever puts a new enode in a new
+    /// eclass, an applier can only grow a frozen eclass through such a
+    /// union, so adding enodes is unaffected.
+    /// Each skipped union is counted in
+    /// [`frozen_skips`](EGraph::frozen_skips).
     ///
-    /// [`Debug`]: std::fmt::Debug
-    pub fn dump(&self) -> impl Debug + '_ {
-        EGraphDump(self)
-    }
-}
-
-impl<L: Language + Display, N: Analysis<L>> EGraph<L, N> {
-    /// Panic if the given eclass doesn't contain the given patterns
+    /// Unioning eclasses that are already equal is never skipped or
+    /// counted, frozen or not.
+    /// Congruence is also still maintained:
+    /// [`rebuild`](EGraph::rebuild) merges a frozen eclass with any
+    /// eclass that became congruent to it, and the result stays frozen.
     ///
-    /// Useful for testing.
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let x = egraph.add(S::leaf("x"));
+    /// let y = egraph.add(S::leaf("y"));
+    /// egraph.freeze(x);
+    /// assert!(!egraph.union(x, y));
+    /// assert_eq!(egraph.frozen_skips(), 1);
+    ///
+    /// egraph.unfreeze(x);
+    /// assert!(egraph.union(x, y));
+    /// ```
+    pub fn freeze(&mut self, id: Id) {
+        let id = self.find_mut(id);
+        self.frozen.insert(id);
+    }
+
+    /// Unfreezes the eclass `id`, see [`freeze`](EGraph::freeze).
+    pub fn unfreeze(&mut self, id: Id) {
+        let id = self.find_mut(id);
+        self.frozen.remove(&id);
+    }
+
+    /// Returns true if the eclass `id` is [frozen](EGraph::freeze).
+    pub fn is_frozen(&self, id: Id) -> bool {
+        self.frozen.contains(&self.find(id))
+    }
+
+    /// Returns the number of unions skipped because one of the eclasses
+    /// was [frozen](EGraph::freeze).
+    pub fn frozen_skips(&self) -> usize {
+        self.frozen_skips
+    }
+
+    /// Removes the enodes of eclass `id` that `keep` returns false for,
+    /// returning how many were removed.
+    ///
+    /// This is for [`Analysis::modify`] to drop enodes that are dead
+    /// weight, like the rest of an eclass that was folded to a
+    /// constant, so searching and extracting don't visit them.
+    /// The removed enodes are also removed from their children's
+    /// parents, so [`rebuild`](EGraph::rebuild) doesn't repair them,
+    /// and from the hashcons, so adding one again makes a new eclass.
+    /// The next rebuild may put back the ones added since the last
+    /// rebuild in the hashcons, which is still sound, since they are
+    /// still equal to this eclass.
+    /// Their [`ENodeId`]s are no longer valid for [`EGraph::enode`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `keep` would remove every enode of the eclass.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let sum = egraph.add_expr(&"(+ 1 1)".parse().unwrap());
+    /// let two = egraph.add(S::leaf("2"));
+    /// egraph.union(sum, two);
+    /// egraph.rebuild();
+    ///
+    /// assert_eq!(egraph.retain_nodes(sum, |n| n.is_leaf()), 1);
+    /// assert_eq!(egraph[sum].nodes, vec![S::leaf("2")]);
+    /// assert_eq!(egraph.lookup_expr(&"(+ 1 1)".parse().unwrap()), None);
+    /// egraph.check_invariants();
+    /// ```
+    pub fn retain_nodes(&mut self, id: Id, mut keep: impl FnMut(&L) -> bool) -> usize {
+        let id = self.find_mut(id);
+        self.log_class(id);
+        let class = self.classes.get_mut(&id).unwrap();
+        let kept: Vec<bool> = class.nodes.iter().map(&mut keep).collect();
+        assert!(
+            kept.contains(&true),
+            "Tried to remove every enode of eclass {}",
+            id
+        );
+        let removed: Vec<L> = class
+            .nodes
+            .iter()
+            .zip(&kept)
+            .filter(|(_, &keep)| !keep)
+            .map(|(node, _)| node.clone())
+            .collect();
+        let mut flags = kept.iter();
+        class.nodes.retain(|_| *flags.next().unwrap());
+        // the ids are out of step if the nodes were changed directly, and
+        // are renumbered at the next rebuild anyway
+        if class.node_ids.len() == kept.len() {
+            let mut flags = kept.iter();
+            class.node_ids.retain(|_| *flags.next().unwrap());
+        }
+        if removed.is_empty() {
+            return 0;
+        }
+        class.changed = true;
+
+        let canon = |egraph: &Self, node: &L| {
+            let mut node = node.clone().map_children(|child| egraph.find(child));
+            if let Some(flattening) = &egraph.flattening {
+                flattening.sort(&mut node);
+            }
+            node
+        };
+        for node in &removed {
+            let node = canon(self, node);
+            if self.memo.get(&node).map(|&memo_id| self.find(memo_id)) == Some(id) {
+                self.log_memo(&node);
+                self.memo.remove(&node);
+            }
+            for &child in node.children() {
+                let mut parents = std::mem::take(&mut self[child].parents);
+                parents.retain(|(parent, parent_id)| {
+                    self.find(*parent_id) != id || canon(self, parent) != node
+                });
+                self[child].parents = parents;
+            }
+        }
+        removed.len()
+    }
+
+    /// Start or stop skipping redundant matches (see
+    /// [`Runner::with_redundant_match_skipping`]).
+    pub(crate) fn set_skip_redundant(&mut self, enabled: bool) {
+        self.skip_redundant = enabled;
+    }
+
+    /// Returns the number of matches skipped because their
+    /// instantiation was already in the matched eclass.
+    pub(crate) fn redundant_skips(&self) -> usize {
+        self.redundant_skips
+    }
+
+    /// Check if applying `pat` with `subst` to `eclass` is a no-op
+    /// because the whole instantiation is already in `eclass`, counting
+    /// it if so.
+    /// Always false unless skipping redundant matches.
+    pub(crate) fn is_redundant_application<B: Bindings + ?Sized>(
+        &mut self,
+        pat: &[ENodeOrVar<L>],
+        eclass: Id,
+        subst: &B,
+    ) -> bool {
+        if !self.skip_redundant {
+            return false;
+        }
+        match crate::pattern::lookup_instantiation(pat, self, subst) {
+            Some(id) if self.find(id) == self.find(eclass) => {
+                self.redundant_skips += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Start or stop deduplicating the applications of [`Pattern`]
+    /// appliers, forgetting the applications seen so far.
+    pub(crate) fn set_apply_dedup(&mut self, enabled: bool) {
+        self.applications = if enabled {
+            Some(Default::default())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the number of applications skipped because an identical
+    /// one already happened in the same apply phase.
+    pub(crate) fn deduped(&self) -> usize {
+        self.deduped
+    }
+
+    /// Check if applying `pat` with `subst` to `eclass` would repeat an
+    /// application [recorded](EGraph::record_application) in this apply
+    /// phase, counting it if so.
+    ///
+    /// This only looks terms up, so instantiations with new subterms
+    /// are never duplicates.
+    pub(crate) fn is_duplicate_application<B: Bindings + ?Sized>(
+        &mut self,
+        pat: &[ENodeOrVar<L>],
+        eclass: Id,
+        subst: &B,
+    ) -> bool {
+        let applications = match &self.applications {
+            Some(applications) => applications,
+            None => return false,
+        };
+        let hash = match crate::pattern::instantiation_hash(pat, self, subst) {
+            Some(hash) => hash,
+            None => return false,
+        };
+        if applications.contains(&(self.find(eclass), hash)) {
+            self.deduped += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Record that `pat` was just applied with `subst` to `eclass`.
+    pub(crate) fn record_application<B: Bindings + ?Sized>(
+        &mut self,
+        pat: &[ENodeOrVar<L>],
+        eclass: Id,
+        subst: &B,
+    ) {
+        if self.applications.is_none() {
+            return;
+        }
+        if let Some(hash) = crate::pattern::instantiation_hash(pat, self, subst) {
+            let key = (self.find(eclass), hash);
+            self.applications.as_mut().unwrap().insert(key);
+        }
+    }
+
+    /// Returns a more debug-able representation of the egraph.
+    ///
+    /// [`EGraph`]s implement [`Debug`], but it ain't pretty. It
+    /// prints a lot of stuff you probably don't care about.
+    /// This method returns a wrapper that implements [`Debug`] in a
+    /// slightly nicer way, just dumping enodes in each eclass.
+    ///
+    /// [`Debug`]: std::fmt::Debug
+    pub fn dump(&self) -> impl Debug + '_ {
+        EGraphDump(self)
+    }
+
+    /// Resolves an [`ENodeId`] that was removed as a duplicate during
+    /// rebuilding to the [`ENodeId`] of the enode that replaced it.
+    /// Returns `id` itself if it was never removed.
+    pub fn canonical_enode_id(&self, mut id: ENodeId) -> ENodeId {
+        while let Some(&kept) = self.enode_aliases.get(&id) {
+            id = kept;
+        }
+        id
+    }
+
+    /// Returns the canonical id of the eclass containing the enode
+    /// with the given [`ENodeId`], along with the enode itself.
+    ///
+    /// Aliased ids are resolved with
+    /// [`canonical_enode_id`](EGraph::canonical_enode_id).
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let x = egraph.add(S::leaf("x"));
+    /// let y = egraph.add(S::leaf("y"));
+    /// let fx = egraph.add(S::new("f", vec![x]));
+    /// let fy = egraph.add(S::new("f", vec![y]));
+    /// let (fx_node, _) = egraph[fx].nodes_with_ids().next().unwrap();
+    /// let (fy_node, _) = egraph[fy].nodes_with_ids().next().unwrap();
+    ///
+    /// egraph.union(x, y);
+    /// egraph.rebuild();
+    ///
+    /// // (f x) and (f y) are now the same enode, which keeps the older id
+    /// assert_eq!(egraph.canonical_enode_id(fy_node), fx_node);
+    /// let (class, node) = egraph.enode(fy_node);
+    /// assert_eq!(class, egraph.find(fx));
+    /// assert_eq!(node.op, Symbol::from("f"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` did not come from this egraph.
+    pub fn enode(&self, id: ENodeId) -> (Id, &L) {
+        let id = self.canonical_enode_id(id);
+        let class_id = self.find(self.enode_classes[usize::from(id)]);
+        let class = &self[class_id];
+        let i = class
+            .node_ids
+            .iter()
+            .position(|&n| n == id)
+            .unwrap_or_else(|| panic!("ENodeId {} is not in eclass {}", id, class_id));
+        (class_id, &class.nodes[i])
+    }
+}
+
+impl<L: Language + Display, N: Analysis<L>> EGraph<L, N> {
+    /// Panic if the given eclass doesn't contain the given patterns
+    ///
+    /// Useful for testing.
+    ///
+    /// The terms printed are cut short within the default
+    /// [`TermBudget`].
     pub fn check_goals(&self, id: Id, goals: &[Pattern<L>]) {
+        let budget = TermBudget::default();
         let (cost, best) = Extractor::new(self, AstSize).find_best(id);
-        println!("End ({}): {}", cost, best.pretty(80));
+        println!("End ({}): {}", cost, expr_term(&best, &budget));
 
         for (i, goal) in goals.iter().enumerate() {
             println!("Trying to prove goal {}: {}", i, goal.pretty(40));
             let matches = goal.search_eclass(&self, id);
             if matches.is_none() {
-                let best = Extractor::new(&self, AstSize).find_best(id).1;
                 panic!(
                     "Could not prove goal {}:\n\
                      {}\n\
@@ -706,7 +1836,7 @@ impl<L: Language + Display, N: Analysis<L>> EGraph<L, N> {
                      {}",
                     i,
                     goal.pretty(40),
-                    best.pretty(40),
+                    expr_term(&best, &budget),
                 );
             }
         }
@@ -715,24 +1845,98 @@ impl<L: Language + Display, N: Analysis<L>> EGraph<L, N> {
 
 // All the rebuilding stuff
 impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+    /// Canonicalizes and deduplicates the enodes of every eclass,
+    /// returning the number of eclasses repaired, the number of enodes
+    /// removed, and the number of eclasses that
+    /// [changed](RebuildReport::changed_classes), clearing their flags.
     #[inline(never)]
-    fn rebuild_classes(&mut self) -> usize {
+    fn rebuild_classes(&mut self) -> (usize, usize, usize) {
         let mut classes_by_op = std::mem::take(&mut self.classes_by_op);
         classes_by_op.values_mut().for_each(|ids| ids.clear());
 
         let mut trimmed = 0;
+        let mut repaired = 0;
+        let mut n_changed = 0;
         let uf = &mut self.unionfind;
+        let enode_classes = &mut self.enode_classes;
+        let enode_aliases = &mut self.enode_aliases;
+        let provenance = &mut self.provenance;
+        let flattening = &self.flattening;
+        let change_log = &mut self.change_log;
+        let checkpoint = &mut self.checkpoint;
+        let mut buf: Vec<(L, ENodeId)> = vec![];
 
         for class in self.classes.values_mut() {
             let old_len = class.len();
-            class
-                .nodes
-                .iter_mut()
-                .for_each(|n| n.update_children(|id| uf.find_mut(id)));
-            class.nodes.sort_unstable();
-            class.nodes.dedup();
+            if class.node_ids.len() != old_len {
+                log::warn!(
+                    "Nodes of eclass {} were changed directly, renumbering",
+                    class.id
+                );
+                class.node_ids.clear();
+                for _ in 0..old_len {
+                    class.node_ids.push(ENodeId::from(enode_classes.len()));
+                    enode_classes.push(class.id);
+                    if let Some(provenance) = provenance.as_mut() {
+                        provenance.origins.push(Origin::Input);
+                    }
+                }
+            }
+
+            // an eclass that didn't change since the last rebuild is
+            // only changed here if a child was merged
+            if let Some(checkpoint) = checkpoint.as_mut() {
+                let stale = |n: &L| n.children().iter().any(|&c| uf.find(c) != c);
+                if class.changed || class.nodes.iter().any(stale) {
+                    checkpoint.save_class(class.id, Some(&*class));
+                }
+            }
+            buf.extend(class.nodes.drain(..).zip(class.node_ids.drain(..)));
+            let mut changed = false;
+            buf.iter_mut().for_each(|(n, _)| {
+                n.update_children(|id| {
+                    let canon = uf.find_mut(id);
+                    changed |= canon != id;
+                    canon
+                });
+                if let Some(flattening) = flattening {
+                    flattening.sort(n);
+                }
+            });
+            // sorting by ENodeId last means dedup keeps the oldest one
+            buf.sort_unstable_by(|(a, a_id), (b, b_id)| {
+                let by_node = a.search_cmp(b).then_with(|| a.cmp(b));
+                by_node.then_with(|| a_id.cmp(b_id))
+            });
+            buf.dedup_by(|(n, dup), (kept_n, kept)| {
+                let same = n == kept_n;
+                if same {
+                    enode_aliases.insert(*dup, *kept);
+                    if let Some(log) = change_log.as_mut() {
+                        log.enode_aliases.push(*dup);
+                    }
+                    if let Some(checkpoint) = checkpoint.as_mut() {
+                        checkpoint.add_alias(*dup);
+                    }
+                }
+                same
+            });
+            for (n, enode_id) in buf.drain(..) {
+                class.nodes.push(n);
+                class.node_ids.push(enode_id);
+            }
 
             trimmed += old_len - class.nodes.len();
+            if changed || old_len != class.nodes.len() {
+                repaired += 1;
+                class.changed = true;
+            }
+            if std::mem::take(&mut class.changed) {
+                n_changed += 1;
+                if let Some(log) = change_log.as_mut() {
+                    log.classes.insert(class.id);
+                }
+            }
 
             let mut add = |n: &L| {
                 #[allow(clippy::mem_discriminant_non_enum)]
@@ -763,7 +1967,7 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         }
 
         self.classes_by_op = classes_by_op;
-        trimmed
+        (repaired, trimmed, n_changed)
     }
 
     #[inline(never)]
@@ -800,13 +2004,127 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
         true
     }
 
-    #[inline(never)]
-    fn process_unions(&mut self) -> usize {
-        let mut n_unions = 0;
+    /// Panics if the egraph's internal invariants don't hold.
+    ///
+    /// These always hold, even between [`rebuild`](EGraph::rebuild)s:
+    /// - every eclass is stored under its canonical id;
+    /// - every child of an enode is the id of an eclass;
+    /// - every enode is in the parent list of each of its children's
+    ///   eclasses, and every parent list entry is an enode of the eclass
+    ///   it points to, both up to canonicalization.
+    ///
+    /// When the egraph is [`clean`](EGraph::clean), every enode is also
+    /// canonical, and the hashcons maps it to its eclass and only its
+    /// eclass.
+    ///
+    /// Cycles are fine: an enode may be a parent of its own eclass, like
+    /// `(f a)` once it's unioned with `a`.
+    /// Useful for testing.
+    pub fn check_invariants(&self) {
+        let canon = |node: &L| {
+            let mut node = node.clone().map_children(|id| self.find(id));
+            if let Some(flattening) = &self.flattening {
+                flattening.sort(&mut node);
+            }
+            node
+        };
+        let mut nodes = HashSet::default();
+        for (&id, class) in &self.classes {
+            assert_eq!(self.find(id), id, "eclass {} isn't canonical", id);
+            assert_eq!(class.id, id, "eclass {} is stored under {}", class.id, id);
+            for node in &class.nodes {
+                node.for_each(|child| {
+                    assert!(
+                        self.classes.contains_key(&self.find(child)),
+                        "{:?} in eclass {} has a dangling child {}",
+                        node,
+                        id,
+                        child
+                    )
+                });
+                if self.clean {
+                    assert_eq!(
+                        node,
+                        &canon(node),
+                        "{:?} in eclass {} isn't canonical",
+                        node,
+                        id
+                    );
+                }
+                nodes.insert((canon(node), id));
+            }
+        }
+
+        let mut parents = HashSet::default();
+        for (&id, class) in &self.classes {
+            for (parent, parent_id) in &class.parents {
+                let entry = (canon(parent), self.find(*parent_id));
+                assert!(
+                    nodes.contains(&entry),
+                    "eclass {} has a parent {:?} that isn't in eclass {}",
+                    id,
+                    parent,
+                    entry.1
+                );
+                parents.insert((id, entry));
+            }
+        }
+        for (node, id) in nodes {
+            node.for_each(|child| {
+                let entry = (child, (node.clone(), id));
+                assert!(
+                    parents.contains(&entry),
+                    "{:?} in eclass {} is missing from the parents of eclass {}",
+                    node,
+                    id,
+                    child
+                );
+            });
+        }
+
+        if self.clean {
+            self.check_memo();
+        }
+    }
 
+    /// Restores congruence and the analysis data, checking `cancel`, if
+    /// any, before each pass and every `CANCEL_CHECK_INTERVAL`
+    /// reinserted enodes.
+    ///
+    /// Each step finishes the work of one pending enode, so stopping
+    /// between two leaves the rest pending for the next call.
+    #[inline(never)]
+    fn process_unions(
+        &mut self,
+        report: &mut RebuildReport,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<(), Error> {
+        let cancelled = || cancel.map_or(false, |token| token.is_cancelled());
         while !self.pending.is_empty() || !self.analysis_pending.is_empty() {
+            if cancelled() {
+                return Err(Error::Cancelled);
+            }
+            report.passes += 1;
+
+            let start = Instant::now();
             while let Some((mut node, class)) = self.pending.pop() {
+                if report.memo_reinserts % CANCEL_CHECK_INTERVAL == CANCEL_CHECK_INTERVAL - 1
+                    && cancelled()
+                {
+                    self.pending.push((node, class));
+                    report.congruence_time += start.elapsed().as_secs_f64();
+                    return Err(Error::Cancelled);
+                }
                 node.update_children(|id| self.find_mut(id));
+                let mut flat = None;
+                if let Some(flattening) = &self.flattening {
+                    flattening.sort(&mut node);
+                    let (uf, classes) = (&self.unionfind, &self.classes);
+                    let nodes = |id: Id| classes[&id].nodes.as_slice();
+                    flat = flattening.flatten_node(&node, uf.find(class), nodes, |id| uf.find(id));
+                }
+                report.memo_reinserts += 1;
+                self.log_memo(&node);
                 if let Some(memo_class) = self.memo.insert(node, class) {
                     let did_something = self.perform_union(
                         memo_class,
@@ -814,27 +2132,42 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
                         Some(Justification::Congruence),
                         false,
                     );
-                    n_unions += did_something as usize;
+                    report.unions += did_something as usize;
+                }
+                // a child was merged with an application of the same
+                // operator, so the enode has a flatter form
+                if let Some(flat) = flat {
+                    let added = self.add(flat);
+                    report.unions += self.perform_union(added, class, None, false) as usize;
                 }
             }
+            report.congruence_time += start.elapsed().as_secs_f64();
 
+            let start = Instant::now();
             while let Some((node, class_id)) = self.analysis_pending.pop() {
                 let class_id = self.find_mut(class_id);
                 let node_data = N::make(self, &node);
+                if let Some(checkpoint) = &mut self.checkpoint {
+                    checkpoint.save_class(class_id, self.classes.get(&class_id));
+                }
                 let class = self.classes.get_mut(&class_id).unwrap();
 
                 let did_merge = self.analysis.merge(&mut class.data, node_data);
                 if did_merge.0 {
+                    if let Some(log) = &mut self.change_log {
+                        log.classes.insert(class_id);
+                    }
+                    report.analysis_merges += 1;
                     self.analysis_pending.extend(class.parents.iter().cloned());
                     N::modify(self, class_id)
                 }
             }
+            report.analysis_time += start.elapsed().as_secs_f64();
         }
 
         assert!(self.pending.is_empty());
         assert!(self.analysis_pending.is_empty());
-
-        n_unions
+        Ok(())
     }
 
     /// Restores the egraph invariants of congruence and enode uniqueness.
@@ -874,16 +2207,48 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
     /// assert_eq!(egraph.number_of_classes(), 3);
     /// assert_eq!(egraph.find(ax), egraph.find(ay));
     /// ```
-    pub fn rebuild(&mut self) -> usize {
+    ///
+    /// Returns a [`RebuildReport`] of what the rebuild did.
+    pub fn rebuild(&mut self) -> RebuildReport {
+        self.rebuild_in(None)
+            .expect("rebuilding without a token can't be cancelled")
+    }
+
+    /// Like [`rebuild`](EGraph::rebuild), but stops early with
+    /// [`Error::Cancelled`] once `token` is
+    /// [cancelled](CancellationToken::cancel).
+    ///
+    /// The token is checked between batches of repairs, each of which
+    /// is finished before stopping, so a cancelled rebuild leaves the
+    /// egraph as if some of the pending repairs had been done by hand:
+    /// it isn't [`clean`](EGraph::clean), the invariants that hold
+    /// between rebuilds hold (see
+    /// [`check_invariants`](EGraph::check_invariants)), and the next
+    /// rebuild, cancellable or not, does the rest of the work.
+    pub fn rebuild_cancellable(
+        &mut self,
+        token: &CancellationToken,
+    ) -> Result<RebuildReport, Error> {
+        self.rebuild_in(Some(token))
+    }
+
+    fn rebuild_in(&mut self, cancel: Option<&CancellationToken>) -> Result<RebuildReport, Error> {
         let old_hc_size = self.memo.len();
         let old_n_eclasses = self.number_of_classes();
 
         let start = Instant::now();
 
-        let n_unions = self.process_unions();
-        let trimmed_nodes = self.rebuild_classes();
+        let mut report = RebuildReport::default();
+        self.process_unions(&mut report, cancel)?;
+        let classes_start = Instant::now();
+        let (repaired_classes, trimmed_nodes, changed_classes) = self.rebuild_classes();
+        report.repaired_classes = repaired_classes;
+        report.trimmed_nodes = trimmed_nodes;
+        report.changed_classes = changed_classes;
+        report.classes_time = classes_start.elapsed().as_secs_f64();
 
         let elapsed = start.elapsed();
+        report.total_time = elapsed.as_secs_f64();
         info!(
             concat!(
                 "REBUILT! in {}.{:03}s\n",
@@ -897,13 +2262,106 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
             old_n_eclasses,
             self.memo.len(),
             self.number_of_classes(),
-            n_unions,
+            report.unions,
             trimmed_nodes,
         );
 
         debug_assert!(self.check_memo());
         self.clean = true;
-        n_unions
+        self.dirty_unions = 0;
+        if std::mem::take(&mut self.loaded) {
+            N::on_deserialize(self);
+        }
+        Ok(report)
+    }
+
+    /// Calls `f` with this egraph after [`rebuild`](EGraph::rebuild)ing
+    /// it if it isn't [`clean`](EGraph::clean).
+    ///
+    /// Searching, for example with [`Searcher::search`] or
+    /// [`Pattern::search_eclass`], needs a clean egraph and panics
+    /// otherwise.
+    /// During the apply phase of a [`Runner`] iteration the egraph is
+    /// usually dirty, so [`Condition::check_mut`] and [`Applier`]s that
+    /// run nested searches should do so in here; they then see the same answers as
+    /// they would after the iteration's rebuild.
+    /// Rebuilding in the middle of the apply phase is safe, since
+    /// appliers canonicalize the ids of their matches when they use them.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let x = egraph.add(S::leaf("x"));
+    /// let y = egraph.add(S::leaf("y"));
+    /// let fx = egraph.add_expr(&"(f x)".parse().unwrap());
+    /// egraph.add_expr(&"(f y)".parse().unwrap());
+    /// egraph.rebuild();
+    ///
+    /// egraph.union(x, y);
+    /// assert!(!egraph.clean);
+    /// let pattern: Pattern<S> = "(f y)".parse().unwrap();
+    /// let matches = egraph.with_clean_view(|clean| pattern.search(clean));
+    /// assert_eq!(matches.len(), 1);
+    /// assert_eq!(matches[0].eclass, egraph.find(fx));
+    /// ```
+    pub fn with_clean_view<R>(&mut self, f: impl FnOnce(&Self) -> R) -> R {
+        if !self.clean {
+            self.rebuild();
+        }
+        f(self)
+    }
+
+    /// Returns the number of unions performed since the last
+    /// [`rebuild`](EGraph::rebuild).
+    ///
+    /// While this isn't zero, searches made through
+    /// [`with_dirty_view`](EGraph::with_dirty_view) may miss matches.
+    pub fn dirty_unions_count(&self) -> usize {
+        self.dirty_unions
+    }
+
+    /// Calls `f` with this egraph as it is, letting it search without
+    /// [`rebuild`](EGraph::rebuild)ing first, even if it isn't
+    /// [`clean`](EGraph::clean).
+    ///
+    /// This is much cheaper than [`with_clean_view`](EGraph::with_clean_view)
+    /// when there are only a few pending unions, but searching an egraph
+    /// with pending unions only guarantees the following:
+    /// - Every id in a match's [`Subst`] and every matched eclass is
+    ///   canonical when the search runs.
+    /// - Every match is sound: the pattern, instantiated with the
+    ///   match's subst, is in the matched eclass once the egraph is
+    ///   rebuilt.
+    ///
+    /// It doesn't guarantee that every match is found, since the
+    /// congruences the pending unions imply aren't restored yet, nor that
+    /// each match is found only once, since merged eclasses may still hold
+    /// duplicate enodes.
+    /// Use [`dirty_unions_count`](EGraph::dirty_unions_count) to tell
+    /// whether there are any pending unions.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let x = egraph.add(S::leaf("x"));
+    /// let y = egraph.add(S::leaf("y"));
+    /// egraph.add_expr(&"(f x)".parse().unwrap());
+    /// egraph.rebuild();
+    ///
+    /// egraph.union(x, y);
+    /// assert_eq!(egraph.dirty_unions_count(), 1);
+    /// let pattern: Pattern<S> = "(f ?a)".parse().unwrap();
+    /// let matches = egraph.with_dirty_view(|dirty| pattern.search(dirty));
+    /// assert_eq!(matches[0].substs[0]["?a".parse().unwrap()], egraph.find(x));
+    /// assert!(!egraph.clean);
+    /// ```
+    pub fn with_dirty_view<R>(&mut self, f: impl FnOnce(&Self) -> R) -> R {
+        let clean = std::mem::replace(&mut self.clean, true);
+        let result = f(self);
+        self.clean = clean;
+        result
     }
 
     pub(crate) fn check_each_explain(&self, rules: &[&Rewrite<L, N>]) -> bool {
@@ -915,6 +2373,21 @@ impl<L: Language, N: Analysis<L>> EGraph<L, N> {
     }
 }
 
+/// All the ways to split `total` into `parts` sizes, each at least 1.
+fn size_splits(total: usize, parts: usize) -> Vec<Vec<usize>> {
+    if parts == 0 {
+        return if total == 0 { vec![vec![]] } else { vec![] };
+    }
+    let mut splits = vec![];
+    for first in 1..=total.saturating_sub(parts - 1) {
+        for mut rest in size_splits(total - first, parts - 1) {
+            rest.insert(0, first);
+            splits.push(rest);
+        }
+    }
+    splits
+}
+
 struct EGraphDump<'a, L: Language, N: Analysis<L>>(&'a EGraph<L, N>);
 
 impl<'a, L: Language, N: Analysis<L>> Debug for EGraphDump<'a, L, N> {
@@ -957,6 +2430,337 @@ mod tests {
         egraph.dot().to_dot("target/foo.dot").unwrap();
     }
 
+    #[test]
+    fn stable_enode_ids() {
+        use SymbolLang as S;
+
+        let mut egraph = EGraph::<S, ()>::default();
+        let exprs = ["(g x)", "z", "(f y)", "a", "(f x)"];
+        let ops = ["g", "z", "f", "a"];
+        let mut ids = vec![];
+        for e in &exprs {
+            let id = egraph.add_expr(&e.parse().unwrap());
+            let (enode_id, _) = egraph[id].nodes_with_ids().next().unwrap();
+            ids.push((id, enode_id));
+        }
+        for &(id, _) in &ids[1..] {
+            egraph.union(ids[0].0, id);
+        }
+        let x = egraph.lookup(S::leaf("x")).unwrap();
+        let y = egraph.lookup(S::leaf("y")).unwrap();
+        egraph.union(x, y);
+        egraph.rebuild();
+
+        let class = egraph.find(ids[0].0);
+        // (f y) and (f x) merged, keeping the older id of (f y)
+        assert_eq!(egraph[class].len(), 4);
+        assert_eq!(egraph.canonical_enode_id(ids[4].1), ids[2].1);
+        for (i, &(_, enode_id)) in ids.iter().enumerate().take(4) {
+            let (found_class, node) = egraph.enode(enode_id);
+            assert_eq!(found_class, class);
+            assert_eq!(node.op, Symbol::from(ops[i]));
+        }
+        let from_class: HashSet<ENodeId> = egraph[class].nodes_with_ids().map(|(i, _)| i).collect();
+        let expected: HashSet<ENodeId> = ids[..4].iter().map(|&(_, i)| i).collect();
+        assert_eq!(from_class, expected);
+
+        // changed directly, the nodes have no ids until the next rebuild
+        egraph[class]
+            .nodes
+            .retain(|node| node.op != Symbol::from("z"));
+        assert_eq!(egraph[class].nodes_with_ids().len(), 0);
+        egraph.rebuild();
+        assert_eq!(egraph[class].nodes_with_ids().len(), 3);
+    }
+
+    #[test]
+    fn rebuild_reports_congruence() {
+        use SymbolLang as S;
+        let mut egraph = EGraph::<S, ()>::default();
+        let a = egraph.add(S::leaf("a"));
+        let b = egraph.add(S::leaf("b"));
+        egraph.add(S::new("f", vec![a]));
+        egraph.add(S::new("f", vec![b]));
+        egraph.rebuild();
+
+        egraph.union(a, b);
+        let report = egraph.rebuild();
+        assert_eq!(report.unions, 1);
+        assert_eq!(report.trimmed_nodes, 1);
+        assert!(report.repaired_classes >= 1);
+        assert!(report.memo_reinserts >= 2);
+        assert!(report.passes >= 1);
+        assert_eq!(report.analysis_merges, 0);
+        assert!(report.total_time >= report.congruence_time);
+
+        let report = egraph.rebuild();
+        assert_eq!(report.unions + report.repaired_classes + report.passes, 0);
+    }
+
+    #[test]
+    fn frozen_classes_skip_unions() {
+        crate::init_logger();
+        use SymbolLang as S;
+        let mut egraph = EGraph::<S, ()>::default();
+        let x = egraph.add(S::leaf("x"));
+        let y = egraph.add(S::leaf("y"));
+        let z = egraph.add(S::leaf("z"));
+        let w = egraph.add(S::leaf("w"));
+        egraph.freeze(x);
+        egraph.freeze(y);
+        egraph.rebuild();
+
+        // frozen with unfrozen, either way around
+        assert!(!egraph.union(x, z));
+        assert!(!egraph.union(z, y));
+        // both frozen
+        assert!(!egraph.union(x, y));
+        assert_eq!(egraph.frozen_skips(), 3);
+        // skipped unions don't need a rebuild
+        assert!(egraph.clean);
+        // unfrozen classes still merge
+        assert!(egraph.union(z, w));
+        egraph.rebuild();
+        assert_eq!(egraph.number_of_classes(), 3);
+
+        // already equal is not a skip
+        assert!(!egraph.union(x, x));
+        assert_eq!(egraph.frozen_skips(), 3);
+
+        egraph.unfreeze(y);
+        assert!(egraph.union(y, z));
+        assert!(!egraph.is_frozen(z));
+    }
+
+    #[test]
+    fn frozen_classes_keep_congruence() {
+        use SymbolLang as S;
+        let mut egraph = EGraph::<S, ()>::default();
+        let fa = egraph.add_expr(&"(f a)".parse().unwrap());
+        let fb = egraph.add_expr(&"(f b)".parse().unwrap());
+        let a = egraph.lookup(S::leaf("a")).unwrap();
+        let b = egraph.lookup(S::leaf("b")).unwrap();
+        egraph.freeze(fa);
+
+        assert!(egraph.union(a, b));
+        egraph.rebuild();
+        assert_eq!(egraph.find(fa), egraph.find(fb));
+        assert!(egraph.is_frozen(fb));
+        assert_eq!(egraph.frozen_skips(), 0);
+    }
+
+    #[test]
+    fn folded_classes_can_be_pruned() {
+        define_language! {
+            enum Math {
+                "+" = Add([Id; 2]),
+                "*" = Mul([Id; 2]),
+                Num(i64),
+                Var(Symbol),
+            }
+        }
+
+        #[derive(Default)]
+        struct Fold;
+        impl Analysis<Math> for Fold {
+            type Data = Option<i64>;
+            fn make(egraph: &EGraph<Math, Self>, enode: &Math) -> Self::Data {
+                let x = |i: &Id| egraph[*i].data;
+                match enode {
+                    Math::Num(n) => Some(*n),
+                    Math::Add([a, b]) => Some(x(a)? + x(b)?),
+                    Math::Mul([a, b]) => Some(x(a)? * x(b)?),
+                    Math::Var(_) => None,
+                }
+            }
+            fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge {
+                merge_max(to, from)
+            }
+            fn modify(egraph: &mut EGraph<Math, Self>, id: Id) {
+                if let Some(n) = egraph[id].data {
+                    let added = egraph.add(Math::Num(n));
+                    egraph.union(id, added);
+                    egraph.retain_nodes(id, |n| n.is_leaf());
+                }
+            }
+        }
+
+        let mut egraph = EGraph::<Math, Fold>::default();
+        let root = egraph.add_expr(&"(+ 1 (* 2 3))".parse().unwrap());
+        egraph.rebuild();
+        egraph.check_invariants();
+        assert!(egraph
+            .classes()
+            .all(|c| c.nodes.len() == 1 && c.nodes[0].is_leaf()));
+        assert_eq!(egraph[root].nodes, vec![Math::Num(7)]);
+
+        let search = |egraph: &EGraph<Math, Fold>, pattern: &str| -> Vec<Id> {
+            let pattern: Pattern<Math> = pattern.parse().unwrap();
+            pattern.search(egraph).iter().map(|m| m.eclass).collect()
+        };
+        assert_eq!(search(&egraph, "7"), vec![egraph.find(root)]);
+        assert!(search(&egraph, "(* ?a ?b)").is_empty());
+
+        // the folded subterm is still matched by the pattern around it
+        let root = egraph.add_expr(&"(+ x (* 2 3))".parse().unwrap());
+        egraph.rebuild();
+        egraph.check_invariants();
+        assert_eq!(egraph[root].len(), 1);
+        assert_eq!(search(&egraph, "(+ x 6)"), vec![egraph.find(root)]);
+        assert_eq!(search(&egraph, "(+ ?a 6)"), vec![egraph.find(root)]);
+        // and adding a pruned enode again folds it into the same eclass
+        let six = egraph.lookup(Math::Num(6)).unwrap();
+        let product = egraph.add_expr(&"(* 2 3)".parse().unwrap());
+        egraph.rebuild();
+        assert_eq!(egraph.find(product), egraph.find(six));
+        assert_eq!(egraph[six].nodes, vec![Math::Num(6)]);
+    }
+
+    #[test]
+    fn pre_merge_sees_both_classes() {
+        use SymbolLang as S;
+
+        /// Records each merge's winner, loser, and the loser's enodes.
+        #[derive(Default)]
+        struct MergeLog(Vec<(Id, Id, Vec<S>)>);
+        impl Analysis<S> for MergeLog {
+            type Data = ();
+            fn make(_: &EGraph<S, Self>, _: &S) {}
+            fn merge(&mut self, _: &mut (), _: ()) -> DidMerge {
+                DidMerge(false, false)
+            }
+            fn pre_merge(&mut self, winner: &EClass<S, ()>, loser: &EClass<S, ()>) {
+                self.0.push((winner.id, loser.id, loser.nodes.clone()));
+            }
+        }
+
+        let mut egraph = EGraph::<S, MergeLog>::default();
+        let fa = egraph.add_expr(&"(f a)".parse().unwrap());
+        let fb = egraph.add_expr(&"(f b)".parse().unwrap());
+        let a = egraph.lookup(S::leaf("a")).unwrap();
+        let b = egraph.lookup(S::leaf("b")).unwrap();
+        egraph.freeze(fa);
+        assert!(!egraph.union(fa, a));
+        assert!(egraph.analysis.0.is_empty());
+
+        assert!(egraph.union(a, b));
+        let (winner, loser, nodes) = egraph.analysis.0[0].clone();
+        let (leaf, other) = if winner == a { ("b", b) } else { ("a", a) };
+        assert_eq!(winner, egraph.find(a));
+        assert_eq!(loser, other);
+        assert_eq!(nodes, vec![S::leaf(leaf)]);
+        assert!(egraph.classes().all(|c| c.id != loser));
+        assert!(egraph[winner].nodes.contains(&nodes[0]));
+
+        // rebuild's congruence union is a merge too
+        egraph.rebuild();
+        assert_eq!(egraph.analysis.0.len(), 2);
+        let (winner, loser, nodes) = egraph.analysis.0[1].clone();
+        assert_eq!(egraph.find(fa), winner);
+        assert!(loser == fa || loser == fb);
+        assert_eq!(nodes.len(), 1);
+        assert!(egraph.classes().all(|c| c.id != loser));
+        assert_eq!(egraph[winner].len(), 1);
+    }
+
+    #[test]
+    fn enumerate_and_count_terms() {
+        use SymbolLang as S;
+
+        let mut egraph = EGraph::<S, ()>::default();
+        let a = egraph.add(S::leaf("a"));
+        let b = egraph.add(S::leaf("b"));
+        let f = egraph.add(S::new("f", vec![a, a]));
+        egraph.union(a, b);
+        egraph.rebuild();
+
+        // the class of `a` is {a, b}, so f has 2 * 2 terms of size 3
+        assert_eq!(egraph.count_terms(f, 2), 0);
+        assert_eq!(egraph.count_terms(f, 3), 4);
+        let mut terms: Vec<String> = egraph
+            .enumerate_terms(f, 3)
+            .map(|t| t.to_string())
+            .collect();
+        terms.sort();
+        assert_eq!(terms, vec!["(f a a)", "(f a b)", "(f b a)", "(f b b)"]);
+
+        // a cyclic class: x = (g x)
+        let x = egraph.add(S::leaf("x"));
+        let gx = egraph.add(S::new("g", vec![x]));
+        egraph.union(x, gx);
+        egraph.rebuild();
+
+        let terms: Vec<String> = egraph
+            .enumerate_terms(x, 3)
+            .map(|t| t.to_string())
+            .collect();
+        assert_eq!(terms, vec!["x", "(g x)", "(g (g x))"]);
+        assert_eq!(egraph.count_terms(x, 3), 3);
+        assert_eq!(egraph.count_terms(x, 100), 100);
+    }
+
+    #[test]
+    fn dirty_searches_yield_canonical_ids() {
+        use SymbolLang as S;
+
+        let mut egraph = EGraph::<S, ()>::default();
+        for expr in &["(f (g a) b)", "(f (g c) d)", "(h a d)"] {
+            egraph.add_expr(&expr.parse().unwrap());
+        }
+        egraph.rebuild();
+        assert_eq!(egraph.dirty_unions_count(), 0);
+
+        let leaf = |egraph: &EGraph<S, ()>, s: &str| egraph.lookup(S::leaf(s)).unwrap();
+        let (a, b, c, d) = (
+            leaf(&egraph, "a"),
+            leaf(&egraph, "b"),
+            leaf(&egraph, "c"),
+            leaf(&egraph, "d"),
+        );
+        egraph.union(a, c);
+        egraph.union(d, b);
+        assert_eq!(egraph.dirty_unions_count(), 2);
+        let stale = egraph
+            .classes()
+            .flat_map(|class| class.iter())
+            .flat_map(|node| node.children())
+            .any(|&id| id != egraph.find(id));
+        assert!(stale);
+
+        let patterns: Vec<Pattern<S>> = ["(f (g ?x) ?y)", "(h ?x ?y)", "(g ?x)"]
+            .iter()
+            .map(|p| p.parse().unwrap())
+            .collect();
+        let mut arena = MatchArena::default();
+        egraph.with_dirty_view(|egraph| {
+            let canonical = |id: Id| assert_eq!(id, egraph.find(id));
+            for pattern in &patterns {
+                let matches = pattern.search(egraph);
+                assert!(!matches.is_empty(), "{}", pattern);
+                for m in &matches {
+                    canonical(m.eclass);
+                    m.substs
+                        .iter()
+                        .flat_map(|s| s.iter())
+                        .for_each(|(_, id)| canonical(id));
+                    for packed in pattern.search_eclass_packed(egraph, m.eclass) {
+                        packed.iter().for_each(|(_, id)| canonical(id));
+                    }
+                }
+                for m in pattern.search_into(egraph, &mut arena) {
+                    canonical(m.eclass);
+                    for subst in arena.iter(&m.substs) {
+                        subst.iter().for_each(|(_, id)| canonical(id));
+                    }
+                }
+            }
+        });
+        assert!(!egraph.clean);
+
+        egraph.rebuild();
+        assert_eq!(egraph.dirty_unions_count(), 0);
+    }
+
     #[cfg(all(feature = "serde-1", feature = "serde_json"))]
     #[test]
     fn test_serde() {
@@ -971,4 +2775,217 @@ mod tests {
         let json_rep = serde_json::to_string_pretty(&egraph).unwrap();
         println!("{}", json_rep);
     }
+
+    #[cfg(all(feature = "serde-1", feature = "serde_json"))]
+    #[test]
+    fn analysis_data_survives_serde() {
+        use std::cell::Cell;
+        use SymbolLang as S;
+
+        thread_local! {
+            static MAKES: Cell<usize> = Cell::new(0);
+        }
+
+        /// Constant folding that also maps each constant to an eclass.
+        #[derive(Default, Serialize, Deserialize)]
+        struct Folding {
+            #[serde(skip)]
+            constants: HashMap<i64, Id>,
+        }
+        impl Analysis<S> for Folding {
+            type Data = Option<i64>;
+            fn make(egraph: &EGraph<S, Self>, enode: &S) -> Self::Data {
+                MAKES.with(|makes| makes.set(makes.get() + 1));
+                let c = |i: usize| egraph[enode.children[i]].data;
+                match (enode.op.as_str(), enode.len()) {
+                    ("+", 2) => Some(c(0)? + c(1)?),
+                    ("*", 2) => Some(c(0)? * c(1)?),
+                    (op, 0) => op.parse().ok(),
+                    _ => None,
+                }
+            }
+            fn merge(&mut self, a: &mut Self::Data, b: Self::Data) -> DidMerge {
+                merge_max(a, b)
+            }
+            fn modify(egraph: &mut EGraph<S, Self>, id: Id) {
+                if let Some(c) = egraph[id].data {
+                    egraph.analysis.constants.insert(c, id);
+                }
+            }
+            fn on_deserialize(egraph: &mut EGraph<S, Self>) {
+                let constants = egraph.classes().filter_map(|c| Some((c.data?, c.id)));
+                egraph.analysis.constants = constants.collect();
+            }
+        }
+
+        let mut egraph = EGraph::<S, Folding>::default();
+        let root = egraph.add_expr(&"(+ x (* 2 (+ 1 2)))".parse().unwrap());
+        egraph.rebuild();
+        let json = serde_json::to_string(&egraph).unwrap();
+
+        MAKES.with(|makes| makes.set(0));
+        let mut loaded: EGraph<S, Folding> = serde_json::from_str(&json).unwrap();
+        assert!(loaded.analysis.constants.is_empty());
+        loaded.rebuild();
+        assert_eq!(MAKES.with(Cell::get), 0);
+        for class in egraph.classes() {
+            assert_eq!(loaded[class.id].data, class.data);
+        }
+        assert_eq!(loaded[root].data, None);
+        let mut constants: Vec<_> = loaded.analysis.constants.keys().copied().collect();
+        constants.sort_unstable();
+        assert_eq!(constants, vec![1, 2, 3, 6]);
+        let six = loaded.lookup_expr(&"(* 2 (+ 1 2))".parse().unwrap());
+        assert_eq!(
+            six.map(|id| loaded.find(id)),
+            Some(loaded.analysis.constants[&6])
+        );
+
+        // new enodes are still made
+        let seven = loaded.add_expr(&"(+ 6 1)".parse().unwrap());
+        assert_eq!(loaded[seven].data, Some(7));
+        assert!(MAKES.with(Cell::get) > 0);
+    }
+
+    #[cfg(all(feature = "serde-1", feature = "serde_json"))]
+    #[test]
+    fn saturated_egraph_round_trips() {
+        use SymbolLang as S;
+
+        let rules: Vec<Rewrite<S, ()>> = [
+            ("commute-add", "(+ ?a ?b)", "(+ ?b ?a)"),
+            ("add-0", "(+ ?a 0)", "?a"),
+            ("mul-1", "(* ?a 1)", "?a"),
+            ("distribute", "(* ?a (+ ?b ?c))", "(+ (* ?a ?b) (* ?a ?c))"),
+        ]
+        .iter()
+        .map(|(name, lhs, rhs)| {
+            let lhs: Pattern<S> = lhs.parse().unwrap();
+            let rhs: Pattern<S> = rhs.parse().unwrap();
+            Rewrite::new(*name, lhs, rhs).unwrap()
+        })
+        .collect();
+        let start: RecExpr<S> = "(* x (+ (* y 1) (+ 0 z)))".parse().unwrap();
+        let runner = Runner::default()
+            .with_expr(&start)
+            .with_iter_limit(5)
+            .run(&rules);
+        let egraph = runner.egraph;
+        let root = runner.roots[0];
+
+        let json = serde_json::to_string(&egraph).unwrap();
+        let mut loaded: EGraph<S, ()> = serde_json::from_str(&json).unwrap();
+        loaded.rebuild();
+        loaded.check_invariants();
+        assert_eq!(loaded.number_of_classes(), egraph.number_of_classes());
+        for class in egraph.classes() {
+            assert_eq!(loaded.find(class.id), class.id);
+        }
+
+        // expressions and patterns round-trip as their strings
+        let rhs: RecExpr<S> = "(+ (* x z) (* x y))".parse().unwrap();
+        let json = serde_json::to_string(&rhs).unwrap();
+        assert_eq!(json, "\"(+ (* x z) (* x y))\"");
+        assert_eq!(serde_json::from_str::<RecExpr<S>>(&json).unwrap(), rhs);
+        assert!(serde_json::from_str::<RecExpr<S>>("\"(+ x\"").is_err());
+        let pattern: Pattern<S> = "(+ (* ?a ?b) ?c)".parse().unwrap();
+        let json = serde_json::to_string(&pattern).unwrap();
+        let pattern: Pattern<S> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(loaded.equivs(&start, &rhs), egraph.equivs(&start, &rhs));
+        assert_eq!(loaded.equivs(&start, &rhs), vec![loaded.find(root)]);
+        let best = |egraph: &EGraph<S, ()>| Extractor::new(egraph, AstSize).find_best(root);
+        assert_eq!(best(&loaded), best(&egraph));
+        let matches = |egraph: &EGraph<S, ()>| -> Vec<(Id, Vec<Subst>)> {
+            let mut matches: Vec<_> = pattern
+                .search(egraph)
+                .into_iter()
+                .map(|m| (m.eclass, m.substs))
+                .collect();
+            matches.sort();
+            matches
+        };
+        assert!(!matches(&egraph).is_empty());
+        assert_eq!(matches(&loaded), matches(&egraph));
+
+        // adding finds the same eclasses, and new terms get new ones
+        let mut egraph = egraph;
+        for expr in &["(* x z)", "(+ (* x y) (* x z))", "(f x)"] {
+            let expr: RecExpr<S> = expr.parse().unwrap();
+            assert_eq!(loaded.add_expr(&expr), egraph.add_expr(&expr));
+        }
+    }
+
+    #[test]
+    fn explain_one_rewrite() {
+        use SymbolLang as S;
+        let rules: Vec<Rewrite<S, ()>> = vec![rewrite!("add-0"; "(+ ?a 0)" => "?a")];
+        let start: RecExpr<S> = "(+ a 0)".parse().unwrap();
+        let goal: RecExpr<S> = "a".parse().unwrap();
+        let mut runner = Runner::default()
+            .with_explanations_enabled()
+            .with_expr(&start)
+            .run(&rules);
+
+        let mut explanation = runner.explain_equivalence(&start, &goal);
+        let flat = explanation.make_flat_explanation().clone();
+        assert_eq!(flat.len(), 2);
+        assert_eq!(flat[0].to_string(), "(+ a 0)");
+        assert_eq!(flat[1].to_string(), "(Rewrite=> add-0 a)");
+        assert_eq!(flat[1].forward_rule, Some("add-0".into()));
+        assert_eq!(flat[1].backward_rule, None);
+        // each step replays as one match of its rule
+        explanation.check_proof(&rules);
+
+        let mut back = runner.explain_equivalence(&goal, &start);
+        let flat = back.make_flat_explanation();
+        assert_eq!(flat.len(), 2);
+        assert_eq!(flat[1].backward_rule, Some("add-0".into()));
+        back.check_proof(&rules);
+    }
+
+    #[test]
+    fn relations_stay_canonical() {
+        use SymbolLang as S;
+        let mut egraph = EGraph::<S, ()>::default();
+        let a = egraph.add(S::leaf("a"));
+        let b = egraph.add(S::leaf("b"));
+        egraph.add_expr(&"(g (f a) (f b))".parse().unwrap());
+        egraph.add_expr(&"(g (f b) (f b))".parse().unwrap());
+        egraph.rebuild();
+        let f = S::new("f", vec![a]);
+        let g = S::new("g", vec![a, a]);
+        assert_eq!(egraph.relation(&f).count(), 2);
+        assert_eq!(egraph.relation(&g).count(), 2);
+        assert_eq!(egraph.relation(&S::leaf("h")).count(), 0);
+
+        // congruence merges the fs and then the gs
+        egraph.union(a, b);
+        egraph.rebuild();
+        let fs: Vec<_> = egraph.relation(&f).collect();
+        let gs: Vec<_> = egraph.relation(&g).collect();
+        let (ab, fab) = (egraph.find(a), fs[0].0);
+        assert_eq!(fs, vec![(fab, vec![ab])]);
+        assert_eq!(gs.len(), 1);
+        assert_eq!(gs[0].1, vec![fab, fab]);
+        for (id, children) in fs.iter().chain(&gs) {
+            assert_eq!(egraph.find(*id), *id);
+            assert!(children.iter().all(|&c| egraph.find(c) == c));
+        }
+
+        let counts: Vec<(String, usize)> = egraph
+            .relations()
+            .into_iter()
+            .map(|(op, n)| (op.to_string(), n))
+            .collect();
+        let expected = [("a", 1), ("b", 1), ("f", 1), ("g", 1)];
+        let expected: Vec<(String, usize)> = expected
+            .iter()
+            .map(|&(op, n)| (op.to_string(), n))
+            .collect();
+        assert_eq!(counts, expected);
+        for (op, n) in egraph.relations() {
+            assert_eq!(egraph.relation(&op).count(), n);
+        }
+    }
 }
@@ -0,0 +1,377 @@
+use std::fmt::{Debug, Display};
+use std::io;
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::subst::VarParseError;
+use crate::*;
+
+/** The error type of `egg`'s fallible operations.
+
+The panicking conveniences, like indexing an [`EGraph`] or
+[`Extractor::find_best`], have fallible counterparts that return
+this, like [`EGraph::try_get`] and [`Extractor::try_find_best`].
+The conveniences panic with this error's message.
+
+The errors of `egg`'s other fallible operations, like parsing
+expressions, patterns and rules, [reading](EGraph::read_binary) a binary
+egraph or [validating](validate_rules) rules, convert into it, so `?`
+works on all of them.
+
+```
+use egg::*;
+
+fn simplify(expr: &str) -> Result<String, egg::Error> {
+    let rules: Vec<Rewrite<SymbolLang, ()>> = vec![Rewrite::try_new(
+        "mul-1",
+        "(* ?x 1)".parse::<Pattern<SymbolLang>>()?,
+        "?x".parse::<Pattern<SymbolLang>>()?,
+    )?];
+    let runner = Runner::default()
+        .with_iter_limit(10)
+        .with_expr(&expr.parse::<RecExpr<SymbolLang>>()?)
+        .run(&rules);
+    runner.ensure_within_limits()?;
+    let extractor = Extractor::new(&runner.egraph, AstSize);
+    let (_, best) = extractor.try_find_best(runner.roots[0])?;
+    Ok(best.to_string())
+}
+
+assert_eq!(simplify("(+ (* a 1) b)").unwrap(), "(+ a b)");
+assert!(matches!(simplify("(+ a"), Err(egg::Error::Parse(_))));
+```
+**/
+#[derive(Debug, Clone, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// An expression, pattern, variable or rule didn't parse.
+    #[error("Parse error: {0}")]
+    Parse(String),
+    /// A rewrite's applier uses a variable its searcher doesn't bind.
+    #[error("Rewrite {rule} refers to unbound var {var}")]
+    UnboundVar {
+        /// The name of the rewrite.
+        rule: Symbol,
+        /// The unbound variable.
+        var: Var,
+    },
+    /// The id isn't an eclass of the egraph.
+    #[error("Invalid id {0}")]
+    InvalidId(Id),
+    /// The eclass has no finite term to extract, e.g. because all its
+    /// enodes are cyclic.
+    #[error("Can't extract eclass {0}, it has no finite term")]
+    Unextractable(Id),
+    /// A [`Runner`] stopped because it hit a limit, not because it
+    /// saturated.
+    #[error("Runner stopped early: {0:?}")]
+    LimitExceeded(StopReason),
+    /// Two eclasses whose analysis data are
+    /// [distinct](Analysis::distinct) were unioned.
+    #[error(
+        "Unioned provably distinct eclasses {a} {a_term} ({a_data}) and {b} {b_term} ({b_data}) by {cause}{}",
+        .policy.map_or_else(String::new, |p| format!(", folding with {:?}", p))
+    )]
+    AnalysisConflict {
+        /// The canonical id of the first eclass.
+        a: Id,
+        /// A term of the first eclass, cut short within the default
+        /// [`TermBudget`].
+        a_term: String,
+        /// The analysis data of the first eclass, debug-formatted.
+        a_data: String,
+        /// The canonical id of the second eclass.
+        b: Id,
+        /// A term of the second eclass, like `a_term`.
+        b_term: String,
+        /// The analysis data of the second eclass, debug-formatted.
+        b_data: String,
+        /// What caused the union, e.g. `rule 'foo'`.
+        cause: String,
+        /// The analysis's [`OverflowPolicy`], if it folds integer
+        /// arithmetic; see [`Analysis::overflow_policy`].
+        policy: Option<OverflowPolicy>,
+    },
+    /// The operation needs [provenance](EGraph::with_provenance), which
+    /// isn't enabled.
+    #[error("Provenance is not enabled. Use with_provenance(true).")]
+    ProvenanceDisabled,
+    /// The operation was cancelled through its
+    /// [`CancellationToken`](crate::CancellationToken).
+    #[error("Cancelled")]
+    Cancelled,
+    /// A pattern doesn't have the variable.
+    #[error("Variable {0} is not in the pattern")]
+    UnknownVar(Var),
+    /// There's no root with the given index, e.g. in a `shell::Session`.
+    #[error("No root {index}, there are {roots} roots")]
+    NoRoot {
+        /// The index of the root.
+        index: usize,
+        /// The number of roots.
+        roots: usize,
+    },
+    /// An [`Extractor`] choice is of an enode that isn't in the eclass,
+    /// or contradicts an earlier choice.
+    #[error("Invalid extraction choice for eclass {class}: {reason}")]
+    InvalidChoice {
+        /// The canonical id of the eclass.
+        class: Id,
+        /// Why the choice is invalid.
+        reason: String,
+    },
+    /// A rule can't be written by [`format_rules`].
+    #[error("Rule {rule} can't be written in a rule file: {reason}")]
+    UnwritableRule {
+        /// The name of the rule.
+        rule: Symbol,
+        /// Why it can't be written.
+        reason: &'static str,
+    },
+    /// See [`RuleValidationError`].
+    #[error("Invalid rules: {0}")]
+    InvalidRules(RuleValidationError),
+    /// See [`ProgramValidationError`].
+    #[error("Invalid program: {0}")]
+    InvalidProgram(ProgramValidationError),
+    /// See [`StreamError`].
+    #[error("Invalid stream of nodes: {0}")]
+    InvalidStream(StreamError),
+    /// There's no annotation layer of the type with this name; register
+    /// it with [`EGraph::annotate`].
+    #[error("No annotation layer for {0}, register it with EGraph::annotate")]
+    NoAnnotationLayer(&'static str),
+    /// The operation needs a [`clean`](EGraph::clean) egraph; rebuild it
+    /// first.
+    #[error("The egraph isn't clean, rebuild it first")]
+    NotClean,
+    /// Reading or writing failed.
+    #[error("I/O error: {0}")]
+    Io(Arc<io::Error>),
+    /// Serialized input, like a binary egraph or a JSON report, is
+    /// malformed or of an unsupported version.
+    #[error("Malformed input: {0}")]
+    Malformed(String),
+}
+
+impl<E: Display> From<RecExprParseError<E>> for Error {
+    fn from(e: RecExprParseError<E>) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl From<RuleParseError> for Error {
+    fn from(e: RuleParseError) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl From<VarParseError> for Error {
+    fn from(e: VarParseError) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl<E: Display> From<MultiPatternParseError<E>> for Error {
+    fn from(e: MultiPatternParseError<E>) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl From<UnknownVar> for Error {
+    fn from(UnknownVar(var): UnknownVar) -> Self {
+        Error::UnknownVar(var)
+    }
+}
+
+impl<L: Debug> From<ExtractChoiceError<L>> for Error {
+    fn from(e: ExtractChoiceError<L>) -> Self {
+        match e {
+            ExtractChoiceError::NotInClass { class, .. }
+            | ExtractChoiceError::Conflict { class, .. } => Error::InvalidChoice {
+                class,
+                reason: e.to_string(),
+            },
+            ExtractChoiceError::Unsatisfiable { class } => Error::Unextractable(class),
+        }
+    }
+}
+
+impl From<RuleValidationError> for Error {
+    fn from(e: RuleValidationError) -> Self {
+        Error::InvalidRules(e)
+    }
+}
+
+impl From<ProgramValidationError> for Error {
+    fn from(e: ProgramValidationError) -> Self {
+        Error::InvalidProgram(e)
+    }
+}
+
+impl From<StreamError> for Error {
+    fn from(e: StreamError) -> Self {
+        Error::InvalidStream(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(Arc::new(e))
+    }
+}
+
+impl From<BinaryError> for Error {
+    fn from(e: BinaryError) -> Self {
+        match e {
+            BinaryError::Io(e) => e.into(),
+            BinaryError::NotClean => Error::NotClean,
+            e => Error::Malformed(e.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "reports")]
+impl From<RuleStatsError> for Error {
+    fn from(e: RuleStatsError) -> Self {
+        Error::Malformed(e.to_string())
+    }
+}
+
+#[cfg(feature = "bench")]
+impl From<BenchError> for Error {
+    fn from(e: BenchError) -> Self {
+        Error::Malformed(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    /// Leaves with different operators are distinct.
+    #[derive(Default)]
+    struct Leaves;
+    impl Analysis<S> for Leaves {
+        type Data = Option<Symbol>;
+        fn make(_: &EGraph<S, Self>, enode: &S) -> Self::Data {
+            Some(enode.op).filter(|_| enode.is_leaf())
+        }
+        fn merge(&mut self, a: &mut Self::Data, b: Self::Data) -> DidMerge {
+            merge_max(a, b)
+        }
+        fn distinct(a: &Self::Data, b: &Self::Data) -> bool {
+            matches!((a, b), (Some(a), Some(b)) if a != b)
+        }
+    }
+
+    #[test]
+    fn every_variant_is_reachable() {
+        let err = "(+ a".parse::<Pattern<S>>().map_err(Error::from);
+        assert!(matches!(err, Err(Error::Parse(_))));
+        let err = parse_rules::<S, ()>("(+ ?x ?y) =>").map_err(Error::from);
+        assert!(matches!(err, Err(Error::Parse(_))));
+
+        let lhs: Pattern<S> = "(* ?x 1)".parse().unwrap();
+        let rhs: Pattern<S> = "?y".parse().unwrap();
+        let err = Rewrite::<S, ()>::try_new("bad", lhs, rhs).unwrap_err();
+        assert_eq!(err.to_string(), "Rewrite bad refers to unbound var ?y");
+        assert!(matches!(err, Error::UnboundVar { rule, .. } if rule.as_str() == "bad"));
+
+        let mut egraph = EGraph::<S, ()>::default();
+        let a = egraph.add_expr(&"(f a)".parse().unwrap());
+        egraph.rebuild();
+        assert!(egraph.try_get(a).is_ok());
+        let bogus = Id::from(100);
+        assert!(matches!(egraph.try_get(bogus), Err(Error::InvalidId(id)) if id == bogus));
+
+        // with its only enode forbidden, (f a) has no term
+        let f = egraph[a].nodes[0].clone();
+        let extractor = Extractor::new(&egraph, AstSize);
+        assert!(extractor.try_find_best(a).is_ok());
+        let extractor = extractor.with_forbidden_choice(a, f).unwrap();
+        assert!(matches!(
+            extractor.try_find_best(a),
+            Err(Error::Unextractable(id)) if id == a
+        ));
+        assert!(matches!(
+            extractor.try_find_best(bogus),
+            Err(Error::InvalidId(_))
+        ));
+
+        let runner = Runner::<S, ()>::default()
+            .with_expr(&"(+ a b)".parse().unwrap())
+            .with_iter_limit(1)
+            .run(&[rw!("comm"; "(+ ?x ?y)" => "(+ ?y ?x)")]);
+        assert!(matches!(
+            runner.ensure_within_limits(),
+            Err(Error::LimitExceeded(StopReason::IterationLimit(1)))
+        ));
+
+        let mut egraph = EGraph::<S, Leaves>::default();
+        let (x, y) = (egraph.add(S::leaf("x")), egraph.add(S::leaf("y")));
+        let err = egraph.try_union(x, y).unwrap_err();
+        assert!(matches!(err, Error::AnalysisConflict { .. }));
+        assert!(err.to_string().contains("by a direct union"), "{}", err);
+        assert!(err.to_string().contains(" x ("), "{}", err);
+        assert_ne!(egraph.find(x), egraph.find(y));
+        let fx = egraph.add(S::new("f", vec![x]));
+        assert!(egraph.try_union(x, fx).unwrap());
+
+        assert!(matches!(
+            egraph.growth_attribution(),
+            Err(Error::ProvenanceDisabled)
+        ));
+    }
+
+    /// A layer that's never registered.
+    #[derive(Clone)]
+    struct Unregistered;
+    impl ClassAnnotation for Unregistered {
+        fn merge(_: &mut Self, _: Self) {}
+    }
+
+    #[test]
+    fn other_errors_convert() {
+        let pattern: Pattern<S> = "(f ?x)".parse().unwrap();
+        let z: Var = "?z".parse().unwrap();
+        let err = pattern.with_var_order(&[z]).map_err(Error::from);
+        assert!(matches!(err, Err(Error::UnknownVar(var)) if var == z));
+
+        let mut egraph = EGraph::<S, ()>::default();
+        let fa = egraph.add_expr(&"(f a)".parse().unwrap());
+        egraph.rebuild();
+        let err = Extractor::new(&egraph, AstSize)
+            .with_required_choice(fa, S::leaf("b"))
+            .map(|_| ())
+            .map_err(Error::from);
+        assert!(matches!(err, Err(Error::InvalidChoice { class, .. }) if class == fa));
+
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rw!("a:b"; "(f ?x)" => "(g ?x)"),
+            rw!("a:b"; "(g ?x)" => "(f ?x)"),
+        ];
+        let err = format_rules(&rules).unwrap_err();
+        assert!(matches!(err, Error::UnwritableRule { rule, .. } if rule.as_str() == "a:b"));
+        let err = validate_rules(&rules).map_err(Error::from);
+        assert!(matches!(err, Err(Error::InvalidRules(_))));
+
+        let x: Var = "?x".parse().unwrap();
+        let err = Program::<S>::from_parts(vec![], vec![(x, Reg(3))]).map_err(Error::from);
+        assert!(matches!(err, Err(Error::InvalidProgram(_))));
+        let err = egraph.add_from_postorder(vec![]).map_err(Error::from);
+        assert!(matches!(err, Err(Error::InvalidStream(StreamError::Empty))));
+
+        let err = egraph.try_set_annotation(fa, Unregistered).unwrap_err();
+        assert!(matches!(err, Error::NoAnnotationLayer(_)));
+        assert!(err.to_string().contains("Unregistered"), "{}", err);
+
+        let read = |bytes: &[u8]| EGraph::<S, ()>::read_binary(bytes, ()).map_err(Error::from);
+        assert!(matches!(read(&b"EG"[..]), Err(Error::Io(_))));
+        assert!(matches!(read(&b"JSON{}"[..]), Err(Error::Malformed(_))));
+        egraph.add(S::leaf("b"));
+        let err = egraph.write_binary(std::io::sink()).map_err(Error::from);
+        assert!(matches!(err, Err(Error::NotClean)));
+    }
+}
@@ -1,7 +1,7 @@
 use crate::Symbol;
 use crate::{
-    util::pretty_print, Analysis, ENodeOrVar, HashMap, HashSet, Id, Language, PatternAst, Rewrite,
-    Var,
+    flat_term, util::pretty_print, Analysis, ENodeOrVar, HashMap, HashSet, Id, Language,
+    PatternAst, Rewrite, TermBudget, Var,
 };
 use std::fmt::{self, Debug, Display, Formatter};
 use std::rc::Rc;
@@ -715,9 +715,13 @@ impl<L: Language> FlatTerm<L> {
             ENodeOrVar::Var(var) => {
                 if let Some(existing) = bindings.get(var) {
                     if existing != &self {
+                        let budget = TermBudget::default();
                         panic!(
-                            "Invalid proof: binding for variable {:?} does not match between {:?} \n and \n {:?}",
-                            var, existing, self);
+                            "Invalid proof: binding for variable {:?} does not match between {} \n and \n {}",
+                            var,
+                            flat_term(existing, &budget),
+                            flat_term(self, &budget)
+                        );
                     }
                 } else {
                     bindings.insert(*var, self);
@@ -824,6 +828,11 @@ impl<L: Language> Explain<L> {
         }
     }
 
+    pub(crate) fn clear(&mut self) {
+        self.explainfind.clear();
+        self.uncanon_memo.clear();
+    }
+
     pub(crate) fn set_existance_reason(&mut self, node: Id, existance_node: Id) {
         self.explainfind[usize::from(node)].existance_node = existance_node;
     }
@@ -0,0 +1,141 @@
+use std::ops::Index;
+
+use crate::*;
+
+/// Hash-consed storage for many terms, which share their common
+/// subterms.
+///
+/// Each distinct enode is stored once, and a term is the [`Id`] of its
+/// root in the arena.
+/// Extracting into an arena with [`Extractor::find_best_into`] adds only
+/// the enodes the arena doesn't have yet, so extracting many roots, or
+/// the same roots again and again, stores the shared structure once
+/// rather than in a fresh [`RecExpr`] each time.
+///
+/// # Example
+/// ```
+/// use egg::*;
+/// let mut arena = ExprArena::<SymbolLang>::new();
+/// let a = arena.add_expr(&"(+ (* x y) 1)".parse().unwrap());
+/// let b = arena.add_expr(&"(- (* x y) 1)".parse().unwrap());
+/// // x, y, (* x y) and 1 are shared
+/// assert_eq!(arena.len(), 6);
+/// assert_eq!(arena.to_recexpr(b).to_string(), "(- (* x y) 1)");
+/// assert_eq!(arena.add_expr(&"(+ (* x y) 1)".parse().unwrap()), a);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExprArena<L> {
+    nodes: IndexSet<L>,
+}
+
+impl<L> Default for ExprArena<L> {
+    fn default() -> Self {
+        Self {
+            nodes: Default::default(),
+        }
+    }
+}
+
+impl<L: Language> ExprArena<L> {
+    /// Create an empty arena.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of distinct enodes in the arena.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Returns `true` if the arena has no enodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Add `node`, whose children must be ids of this arena, returning
+    /// its id, which is the id it already had if it's in the arena.
+    pub fn add(&mut self, node: L) -> Id {
+        debug_assert!(
+            node.all(|id| usize::from(id) < self.nodes.len()),
+            "node {:?} has children that aren't in the arena",
+            node
+        );
+        Id::from(self.nodes.insert_full(node).0)
+    }
+
+    /// Add every enode of `expr`, returning the id of its root.
+    ///
+    /// Panics if `expr` is empty.
+    pub fn add_expr(&mut self, expr: &RecExpr<L>) -> Id {
+        let nodes = expr.as_ref();
+        assert!(!nodes.is_empty(), "can't add an empty expression");
+        let mut ids = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            let node = node.clone().map_children(|id| ids[usize::from(id)]);
+            ids.push(self.add(node));
+        }
+        *ids.last().unwrap()
+    }
+
+    /// Copy the term rooted at `id` out of the arena.
+    pub fn to_recexpr(&self, id: Id) -> RecExpr<L> {
+        self[id].build_recexpr(|child| self[child].clone())
+    }
+
+    /// Forget all the enodes, keeping the allocated memory.
+    ///
+    /// The ids of the arena are invalidated.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+    }
+}
+
+impl<L> Index<Id> for ExprArena<L> {
+    type Output = L;
+    fn index(&self, id: Id) -> &L {
+        &self.nodes[usize::from(id)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    #[test]
+    fn repeated_extractions_share_structure() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rw!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rw!("zero"; "(+ ?a 0)" => "?a"),
+        ];
+        let mut runner = Runner::default();
+        for expr in &["(+ (+ (* x y) 0) z)", "(* (+ 0 (* x y)) z)", "(+ z 0)"] {
+            runner = runner.with_expr(&expr.parse().unwrap());
+        }
+        let runner = runner.run(&rules);
+        let extractor = Extractor::new(&runner.egraph, AstSize);
+
+        let mut arena = ExprArena::new();
+        let mut ids = vec![];
+        for &root in &runner.roots {
+            let (cost, id) = extractor.find_best_into(&mut arena, root);
+            let (best_cost, best) = extractor.find_best(root);
+            assert_eq!(cost, best_cost);
+            assert_eq!(arena.to_recexpr(id), best);
+            ids.push(id);
+        }
+        // x, y, z, (* x y), and a sum and a product of it and z
+        assert_eq!(arena.len(), 6);
+        assert_eq!(arena.to_recexpr(ids[2]).to_string(), "z");
+
+        // extracting the same root again adds nothing
+        let root = runner.roots[0];
+        assert_eq!(extractor.find_best_into(&mut arena, root).1, ids[0]);
+        assert_eq!(arena.len(), 6);
+
+        let bogus = Id::from(1000);
+        assert!(matches!(
+            extractor.try_find_best_into(&mut arena, bogus),
+            Err(Error::InvalidId(_))
+        ));
+    }
+}
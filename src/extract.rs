@@ -1,8 +1,12 @@
 use std::cmp::Ordering;
+use std::collections::BTreeSet;
 use std::fmt::Debug;
+use std::rc::Rc;
 
-use crate::util::HashMap;
-use crate::{Analysis, EClass, EGraph, Id, Language, RecExpr};
+use thiserror::Error;
+
+use crate::util::{HashMap, HashSet};
+use crate::{Analysis, CancellationToken, EClass, EGraph, Error, ExprArena, Id, Language, RecExpr};
 
 /** Extracting a single [`RecExpr`] from an [`EGraph`].
 
@@ -42,6 +46,43 @@ pub struct Extractor<'a, CF: CostFunction<L>, L: Language, N: Analysis<L>> {
     cost_function: CF,
     costs: HashMap<Id, (CF::Cost, L)>,
     egraph: &'a EGraph<L, N>,
+    required: HashMap<Id, L>,
+    forbidden: HashMap<Id, Vec<L>>,
+    overrides: HashMap<Id, CF::Cost>,
+    materialize: bool,
+}
+
+/// An error from pinning the choices of an [`Extractor`] with
+/// [`with_required_choice`](Extractor::with_required_choice) or
+/// [`with_forbidden_choice`](Extractor::with_forbidden_choice).
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ExtractChoiceError<L> {
+    /// The enode isn't in the eclass.
+    #[error("enode {node:?} is not in eclass {class}")]
+    NotInClass {
+        /// The canonical id of the eclass.
+        class: Id,
+        /// The canonicalized enode.
+        node: L,
+    },
+    /// The choice contradicts an earlier one: the eclass already has a
+    /// different required enode, or the enode is both required and
+    /// forbidden.
+    #[error("choice of enode {node:?} for eclass {class} conflicts with an earlier choice")]
+    Conflict {
+        /// The canonical id of the eclass.
+        class: Id,
+        /// The canonicalized enode.
+        node: L,
+    },
+    /// The required choices leave this eclass without any finite term,
+    /// because they form a cycle or need an eclass whose enodes are
+    /// all forbidden.
+    #[error("the required enode for eclass {class} can't be extracted")]
+    Unsatisfiable {
+        /// The canonical id of the eclass.
+        class: Id,
+    },
 }
 
 /** A cost function that can be used by an [`Extractor`].
@@ -78,13 +119,13 @@ assert_eq!(AstDepth.cost_rec(&e), 2);
 ```
 
 If you'd like to access the [`Analysis`] data or anything else in the e-graph,
-you can put a reference to the e-graph in your [`CostFunction`]:
+you can put an [`EGraphView`] of the e-graph in your [`CostFunction`]:
 
 ```
 # use egg::*;
 # type MyAnalysis = ();
 struct EGraphCostFn<'a> {
-    egraph: &'a EGraph<SymbolLang, MyAnalysis>,
+    egraph: &'a dyn EGraphView<SymbolLang, MyAnalysis>,
 }
 
 impl<'a> CostFunction<SymbolLang> for EGraphCostFn<'a> {
@@ -146,7 +187,7 @@ assert_eq!(AstSize.cost_rec(&e), 4);
 ```
 
 **/
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct AstSize;
 impl<L: Language> CostFunction<L> for AstSize {
     type Cost = usize;
@@ -167,7 +208,7 @@ assert_eq!(AstDepth.cost_rec(&e), 2);
 ```
 
 **/
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct AstDepth;
 impl<L: Language> CostFunction<L> for AstDepth {
     type Cost = usize;
@@ -179,6 +220,166 @@ impl<L: Language> CostFunction<L> for AstDepth {
     }
 }
 
+/** A [`CostFunction`] that compares by one cost function, then
+breaks ties by another.
+
+The [`Cost`](CostFunction::Cost) is the pair of both costs, compared
+lexicographically.
+
+This is only monotonic if a child can't get cheaper by the first cost
+while the parent stays equally cheap by it.
+For example, `Lexicographic(AstDepth, AstSize)` isn't: a shallower child
+can leave its parent just as deep (because of a deeper sibling) while
+making it larger.
+The [`Extractor`] then still picks shallowest terms, but breaks ties by
+size only approximately.
+If the first cost is strictly monotonic, like [`AstSize`], the
+combination is monotonic too.
+
+```
+# use egg::*;
+let e: RecExpr<SymbolLang> = "(do_it foo bar baz)".parse().unwrap();
+assert_eq!(Lexicographic(AstDepth, AstSize).cost_rec(&e), (2, 4));
+```
+
+**/
+#[derive(Debug, Clone)]
+pub struct Lexicographic<C1, C2>(pub C1, pub C2);
+impl<L, C1, C2> CostFunction<L> for Lexicographic<C1, C2>
+where
+    L: Language,
+    C1: CostFunction<L>,
+    C2: CostFunction<L>,
+{
+    type Cost = (C1::Cost, C2::Cost);
+    fn cost<C>(&mut self, enode: &L, mut costs: C) -> Self::Cost
+    where
+        C: FnMut(Id) -> Self::Cost,
+    {
+        let first = self.0.cost(enode, |id| costs(id).0);
+        let second = self.1.cost(enode, |id| costs(id).1);
+        (first, second)
+    }
+}
+
+/// A cost that can be converted to an `f64`, so it can be used in a
+/// [`WeightedSum`].
+pub trait CostAsF64 {
+    /// Convert this cost to an `f64`.
+    fn as_f64(&self) -> f64;
+}
+
+macro_rules! impl_cost_as_f64 {
+    ($($t:ty),*) => {
+        $(impl CostAsF64 for $t {
+            fn as_f64(&self) -> f64 {
+                *self as f64
+            }
+        })*
+    };
+}
+
+impl_cost_as_f64!(usize, u8, u16, u32, u64, isize, i8, i16, i32, i64, f32);
+
+impl CostAsF64 for f64 {
+    fn as_f64(&self) -> f64 {
+        *self
+    }
+}
+
+/// The [`Cost`](CostFunction::Cost) of a [`WeightedSum`]: the weighted
+/// `total`, along with the costs it was computed from.
+///
+/// Costs are compared by their `total` only.
+#[derive(Debug, Clone)]
+pub struct WeightedCost<A, B> {
+    /// The weighted sum of both costs.
+    pub total: f64,
+    /// The cost by the first cost function.
+    pub first: A,
+    /// The cost by the second cost function.
+    pub second: B,
+}
+
+impl<A, B> PartialEq for WeightedCost<A, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.total == other.total
+    }
+}
+
+impl<A, B> PartialOrd for WeightedCost<A, B> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.total.partial_cmp(&other.total)
+    }
+}
+
+/** A [`CostFunction`] that adds up two cost functions, each multiplied
+by a weight.
+
+Both cost functions still see their own costs for the children, so
+non-linear costs like [`AstDepth`] combine correctly.
+
+With non-negative weights, this is monotonic whenever both cost
+functions are.
+A negative weight makes it non-monotonic, and the [`Extractor`] may
+then not find the cheapest term.
+
+```
+# use egg::*;
+let e: RecExpr<SymbolLang> = "(do_it foo bar baz)".parse().unwrap();
+let mut cost = WeightedSum::new(AstDepth, 10.0, AstSize, 1.0);
+assert_eq!(cost.cost_rec(&e).total, 24.0);
+```
+
+**/
+#[derive(Debug, Clone)]
+pub struct WeightedSum<C1, C2> {
+    /// The first cost function.
+    pub first: C1,
+    /// The weight of the first cost function.
+    pub first_weight: f64,
+    /// The second cost function.
+    pub second: C2,
+    /// The weight of the second cost function.
+    pub second_weight: f64,
+}
+
+impl<C1, C2> WeightedSum<C1, C2> {
+    /// Create a [`WeightedSum`] of `first` and `second` with the given
+    /// weights.
+    pub fn new(first: C1, first_weight: f64, second: C2, second_weight: f64) -> Self {
+        Self {
+            first,
+            first_weight,
+            second,
+            second_weight,
+        }
+    }
+}
+
+impl<L, C1, C2> CostFunction<L> for WeightedSum<C1, C2>
+where
+    L: Language,
+    C1: CostFunction<L>,
+    C2: CostFunction<L>,
+    C1::Cost: CostAsF64,
+    C2::Cost: CostAsF64,
+{
+    type Cost = WeightedCost<C1::Cost, C2::Cost>;
+    fn cost<C>(&mut self, enode: &L, mut costs: C) -> Self::Cost
+    where
+        C: FnMut(Id) -> Self::Cost,
+    {
+        let first = self.first.cost(enode, |id| costs(id).first);
+        let second = self.second.cost(enode, |id| costs(id).second);
+        WeightedCost {
+            total: self.first_weight * first.as_f64() + self.second_weight * second.as_f64(),
+            first,
+            second,
+        }
+    }
+}
+
 fn cmp<T: PartialOrd>(a: &Option<T>, b: &Option<T>) -> Ordering {
     // None is high
     match (a, b) {
@@ -207,29 +408,383 @@ where
             costs,
             egraph,
             cost_function,
+            required: HashMap::default(),
+            forbidden: HashMap::default(),
+            overrides: HashMap::default(),
+            materialize: true,
         };
         extractor.find_costs();
 
         extractor
     }
 
+    /// Like [`new`](Extractor::new), but stops early with
+    /// [`Error::Cancelled`] once `token` is
+    /// [cancelled](CancellationToken::cancel).
+    ///
+    /// The token is checked before the costs of each eclass are
+    /// computed; the egraph isn't modified either way.
+    pub fn new_cancellable(
+        egraph: &'a EGraph<L, N>,
+        cost_function: CF,
+        token: &CancellationToken,
+    ) -> Result<Self, Error> {
+        let mut extractor = Extractor {
+            costs: HashMap::default(),
+            egraph,
+            cost_function,
+            required: HashMap::default(),
+            forbidden: HashMap::default(),
+            overrides: HashMap::default(),
+            materialize: true,
+        };
+        extractor.find_costs_in(Some(token))?;
+        Ok(extractor)
+    }
+
+    /// Require the extracted terms to use `node` for eclass `class`
+    /// wherever that eclass appears, and recompute the costs.
+    ///
+    /// A required enode is picked regardless of its cost, and the rest
+    /// of each term is still optimized around it.
+    ///
+    /// Fails if `node` isn't in the eclass, if it conflicts with an
+    /// earlier choice, or if the required choices leave some of them
+    /// without a finite term, e.g. because they form a cycle.
+    ///
+    /// ```
+    /// # use egg::*;
+    /// let mut egraph = EGraph::<SymbolLang, ()>::default();
+    /// let fma = egraph.add_expr(&"(fma a b c)".parse().unwrap());
+    /// let sum = egraph.add_expr(&"(+ (* a b) c)".parse().unwrap());
+    /// egraph.union(fma, sum);
+    /// egraph.rebuild();
+    ///
+    /// // (fma a b c) is smaller, but pin the other form
+    /// let add = egraph[sum].iter().find(|n| n.op.as_str() == "+").unwrap().clone();
+    /// let extractor = Extractor::new(&egraph, AstSize)
+    ///     .with_required_choice(sum, add)
+    ///     .unwrap();
+    /// assert_eq!(extractor.find_best(fma).1.to_string(), "(+ (* a b) c)");
+    /// ```
+    pub fn with_required_choice(
+        mut self,
+        class: Id,
+        node: L,
+    ) -> Result<Self, ExtractChoiceError<L>> {
+        let (class, node) = self.canonical_choice(class, node)?;
+        let conflict = match self.required.get(&class) {
+            Some(required) => *required != node,
+            None => self.is_forbidden(class, &node),
+        };
+        if conflict {
+            return Err(ExtractChoiceError::Conflict { class, node });
+        }
+        self.required.insert(class, node);
+        self.recompute()?;
+        Ok(self)
+    }
+
+    /// Forbid the extracted terms from using `node` for eclass `class`,
+    /// and recompute the costs.
+    ///
+    /// A forbidden enode is treated as having infinite cost, so an
+    /// eclass whose enodes are all forbidden has no term.
+    ///
+    /// Fails if `node` isn't in the eclass, if it is
+    /// [required](Extractor::with_required_choice), or if forbidding it
+    /// leaves a required choice without a finite term.
+    pub fn with_forbidden_choice(
+        mut self,
+        class: Id,
+        node: L,
+    ) -> Result<Self, ExtractChoiceError<L>> {
+        let (class, node) = self.canonical_choice(class, node)?;
+        if self.required.get(&class) == Some(&node) {
+            return Err(ExtractChoiceError::Conflict { class, node });
+        }
+        if !self.is_forbidden(class, &node) {
+            self.forbidden.entry(class).or_default().push(node);
+            self.recompute()?;
+        }
+        Ok(self)
+    }
+
+    /// Use the given costs for eclasses instead of computing them from
+    /// their enodes, and recompute the costs of the rest.
+    ///
+    /// This is for costs from elsewhere, like a model of some eclasses'
+    /// latency, with the cost function as the fallback for the others.
+    /// An overridden eclass costs its override wherever it appears, so
+    /// its parents' costs are computed from it.
+    /// If `materialize` is true, the overridden eclasses' enodes are
+    /// still picked by the cost function, so their terms are extracted
+    /// as usual.
+    /// If it is false, they are leaves: their enodes aren't looked at,
+    /// so they have a cost even if they have no finite term, but there
+    /// is no term to extract from them either, and extracting a term
+    /// that goes through one fails with [`Error::Unextractable`].
+    ///
+    /// These replace any earlier overrides.
+    /// The ids needn't be canonical; if several are of one eclass, the
+    /// cheapest override wins.
+    /// [`find_best_k`](Extractor::find_best_k) doesn't use the overrides.
+    ///
+    /// ```
+    /// # use egg::*;
+    /// let mut egraph = EGraph::<SymbolLang, ()>::default();
+    /// let double = egraph.add_expr(&"(* a 2)".parse().unwrap());
+    /// let root = egraph.add_expr(&"(f (* a 2))".parse().unwrap());
+    /// let other = egraph.add_expr(&"(g b)".parse().unwrap());
+    /// egraph.union(root, other);
+    /// egraph.rebuild();
+    /// let best = Extractor::new(&egraph, AstSize).find_best(root).1;
+    /// assert_eq!(best.to_string(), "(g b)");
+    ///
+    /// // say the doubling is free
+    /// let extractor = Extractor::new(&egraph, AstSize);
+    /// let (cost, best) = extractor
+    ///     .with_cost_overrides(vec![(double, 0)], true)
+    ///     .find_best(root);
+    /// assert_eq!((cost, best.to_string()), (1, "(f (* a 2))".to_string()));
+    /// ```
+    pub fn with_cost_overrides(
+        mut self,
+        overrides: impl IntoIterator<Item = (Id, CF::Cost)>,
+        materialize: bool,
+    ) -> Self {
+        self.overrides.clear();
+        for (id, cost) in overrides {
+            let id = self.egraph.find(id);
+            match self.overrides.get(&id) {
+                Some(old) if *old <= cost => (),
+                _ => {
+                    self.overrides.insert(id, cost);
+                }
+            }
+        }
+        self.materialize = materialize;
+        self.costs.clear();
+        self.find_costs();
+        self
+    }
+
+    fn canonical_choice(&self, class: Id, node: L) -> Result<(Id, L), ExtractChoiceError<L>> {
+        let class = self.egraph.find(class);
+        let node = node.map_children(|id| self.egraph.find(id));
+        if self.egraph[class].nodes.contains(&node) {
+            Ok((class, node))
+        } else {
+            Err(ExtractChoiceError::NotInClass { class, node })
+        }
+    }
+
+    fn is_forbidden(&self, class: Id, node: &L) -> bool {
+        self.forbidden
+            .get(&class)
+            .map_or(false, |nodes| nodes.contains(node))
+    }
+
+    fn recompute(&mut self) -> Result<(), ExtractChoiceError<L>> {
+        self.costs.clear();
+        self.find_costs();
+        let unsatisfied = self
+            .required
+            .keys()
+            .filter(|class| !self.costs.contains_key(class))
+            .min();
+        match unsatisfied {
+            Some(&class) => Err(ExtractChoiceError::Unsatisfiable { class }),
+            None => Ok(()),
+        }
+    }
+
     /// Find the cheapest (lowest cost) represented `RecExpr` in the
     /// given eclass.
     pub fn find_best(&self, eclass: Id) -> (CF::Cost, RecExpr<L>) {
-        let (cost, root) = self.costs[&self.egraph.find(eclass)].clone();
-        let expr = root.build_recexpr(|id| self.find_best_node(id).clone());
-        (cost, expr)
+        self.try_find_best(eclass)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [`find_best`](Extractor::find_best), but fails instead of
+    /// panicking if `eclass` is invalid or has no term to extract.
+    pub fn try_find_best(&self, eclass: Id) -> Result<(CF::Cost, RecExpr<L>), Error> {
+        let cost = self.try_find_best_cost(eclass)?;
+        let root = self.try_best_node(eclass)?;
+        let expr = root.try_build_recexpr(|id| self.try_best_node(id).map(L::clone))?;
+        Ok((cost, expr))
+    }
+
+    /// Like [`find_best`](Extractor::find_best), but add the term to
+    /// `arena`, sharing the subterms it already has, and return its id
+    /// in the arena instead of a fresh [`RecExpr`].
+    pub fn find_best_into(&self, arena: &mut ExprArena<L>, eclass: Id) -> (CF::Cost, Id) {
+        self.try_find_best_into(arena, eclass)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [`find_best_into`](Extractor::find_best_into), but fails
+    /// instead of panicking if `eclass` is invalid or has no term to
+    /// extract.
+    pub fn try_find_best_into(
+        &self,
+        arena: &mut ExprArena<L>,
+        eclass: Id,
+    ) -> Result<(CF::Cost, Id), Error> {
+        let cost = self.try_find_best_cost(eclass)?;
+        let mut ids = HashMap::<Id, Id>::default();
+        let mut todo = vec![self.egraph.find(eclass)];
+        while let Some(&class) = todo.last() {
+            if ids.contains_key(&class) {
+                todo.pop();
+                continue;
+            }
+            // the children of a best enode have terms too
+            let node = self.try_best_node(class)?;
+            let mut has_all_children = true;
+            for &child in node.children() {
+                let child = self.egraph.find(child);
+                if !ids.contains_key(&child) {
+                    has_all_children = false;
+                    todo.push(child);
+                }
+            }
+            if has_all_children {
+                let node = node.clone().map_children(|id| ids[&self.egraph.find(id)]);
+                ids.insert(class, arena.add(node));
+                todo.pop();
+            }
+        }
+        Ok((cost, ids[&self.egraph.find(eclass)]))
     }
 
     /// Find the cheapest e-node in the given e-class.
     pub fn find_best_node(&self, eclass: Id) -> &L {
-        &self.costs[&self.egraph.find(eclass)].1
+        self.try_best_node(eclass)
+            .unwrap_or_else(|e| panic!("{}", e))
     }
 
     /// Find the cost of the term that would be extracted from this e-class.
     pub fn find_best_cost(&self, eclass: Id) -> CF::Cost {
-        let (cost, _) = &self.costs[&self.egraph.find(eclass)];
-        cost.clone()
+        self.try_find_best_cost(eclass)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [`find_best_cost`](Extractor::find_best_cost), but fails
+    /// instead of panicking if `eclass` is invalid or has no term to
+    /// extract.
+    pub fn try_find_best_cost(&self, eclass: Id) -> Result<CF::Cost, Error> {
+        let (cost, _) = self.try_best(eclass)?;
+        let id = self.egraph.find(eclass);
+        Ok(self.overrides.get(&id).unwrap_or(cost).clone())
+    }
+
+    /// Find up to `k` distinct terms in the given eclass, cheapest first.
+    ///
+    /// There are fewer than `k` only if the eclass doesn't have that
+    /// many terms; an eclass in a cycle, like `x` once it's unioned
+    /// with `(* x 1)`, has infinitely many, of which this finds the `k`
+    /// cheapest.
+    /// Terms are distinct as [`RecExpr`]s, and the costs are of the
+    /// terms as trees, like [`find_best`](Extractor::find_best)'s.
+    /// [Required](Extractor::with_required_choice) and
+    /// [forbidden](Extractor::with_forbidden_choice) choices hold for
+    /// every term, but [cost overrides](Extractor::with_cost_overrides)
+    /// are ignored.
+    ///
+    /// This keeps the `k` cheapest terms of each eclass the root can
+    /// reach, improving them to a fixpoint like [`Extractor::new`] does,
+    /// and tries the combinations of the children's terms under each
+    /// enode cheapest first, stopping after `k`.
+    /// For the order to be right, the cost function should be monotonic
+    /// in each child's cost, and, for an enode with the same eclass as
+    /// several children, ask for their costs in order, like
+    /// [`Language::fold`] does.
+    ///
+    /// ```
+    /// # use egg::*;
+    /// let mut egraph = EGraph::<SymbolLang, ()>::default();
+    /// let double = egraph.add_expr(&"(double a)".parse().unwrap());
+    /// let mul = egraph.add_expr(&"(* a 2)".parse().unwrap());
+    /// let add = egraph.add_expr(&"(+ a (+ 0 a))".parse().unwrap());
+    /// egraph.union(double, mul);
+    /// egraph.union(double, add);
+    /// egraph.rebuild();
+    ///
+    /// let extractor = Extractor::new(&egraph, AstSize);
+    /// let best: Vec<_> = extractor
+    ///     .find_best_k(double, 5)
+    ///     .into_iter()
+    ///     .map(|(cost, expr)| (cost, expr.to_string()))
+    ///     .collect();
+    /// assert_eq!(
+    ///     best,
+    ///     vec![
+    ///         (2, "(double a)".to_string()),
+    ///         (3, "(* a 2)".to_string()),
+    ///         (5, "(+ a (+ 0 a))".to_string()),
+    ///     ]
+    /// );
+    /// ```
+    pub fn find_best_k(&self, eclass: Id, k: usize) -> Vec<(CF::Cost, RecExpr<L>)>
+    where
+        CF: Clone,
+    {
+        self.try_find_best_k(eclass, k)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [`find_best_k`](Extractor::find_best_k), but fails instead
+    /// of panicking if `eclass` is invalid or has no term to extract.
+    pub fn try_find_best_k(
+        &self,
+        eclass: Id,
+        k: usize,
+    ) -> Result<Vec<(CF::Cost, RecExpr<L>)>, Error>
+    where
+        CF: Clone,
+    {
+        let eclass = self.egraph.try_get(eclass)?.id;
+        self.try_best(eclass)?;
+        if k == 0 {
+            return Ok(vec![]);
+        }
+        let mut search = KBest {
+            extractor: self,
+            cost_function: self.cost_function.clone(),
+            k,
+            arena: ExprArena::new(),
+            terms: HashMap::default(),
+        };
+        search.run(eclass);
+        let terms = &search.terms[&eclass];
+        Ok(terms
+            .iter()
+            .map(|(cost, id)| (cost.clone(), search.arena.to_recexpr(*id)))
+            .collect())
+    }
+
+    /// Whether terms may use `node` for `class`.
+    fn is_choice(&self, class: Id, node: &L) -> bool {
+        match self.required.get(&class) {
+            Some(required) => node == required,
+            None => !self.is_forbidden(class, node),
+        }
+    }
+
+    fn try_best(&self, eclass: Id) -> Result<&(CF::Cost, L), Error> {
+        let id = self.egraph.try_get(eclass)?.id;
+        self.costs.get(&id).ok_or(Error::Unextractable(id))
+    }
+
+    fn try_best_node(&self, eclass: Id) -> Result<&L, Error> {
+        let (_, node) = self.try_best(eclass)?;
+        let id = self.egraph.find(eclass);
+        if !self.materialize && self.overrides.contains_key(&id) {
+            // a leaf, whose node is only a placeholder
+            return Err(Error::Unextractable(id));
+        }
+        Ok(node)
     }
 
     fn node_total_cost(&mut self, node: &L) -> Option<CF::Cost> {
@@ -237,7 +792,11 @@ where
         let has_cost = |id| self.costs.contains_key(&eg.find(id));
         if node.all(has_cost) {
             let costs = &self.costs;
-            let cost_f = |id| costs[&eg.find(id)].0.clone();
+            let overrides = &self.overrides;
+            let cost_f = |id| {
+                let id = eg.find(id);
+                overrides.get(&id).unwrap_or(&costs[&id].0).clone()
+            };
             Some(self.cost_function.cost(&node, cost_f))
         } else {
             None
@@ -245,11 +804,19 @@ where
     }
 
     fn find_costs(&mut self) {
+        self.find_costs_in(None)
+            .expect("finding costs without a token can't be cancelled")
+    }
+
+    fn find_costs_in(&mut self, cancel: Option<&CancellationToken>) -> Result<(), Error> {
         let mut did_something = true;
         while did_something {
             did_something = false;
 
             for class in self.egraph.classes() {
+                if cancel.map_or(false, |token| token.is_cancelled()) {
+                    return Err(Error::Cancelled);
+                }
                 let pass = self.make_pass(class);
                 match (self.costs.get(&class.id), pass) {
                     (None, Some(new)) => {
@@ -274,14 +841,1068 @@ where
                 )
             }
         }
+        Ok(())
     }
 
+    /// The best enode of `eclass` and its cost by the cost function,
+    /// which for an overridden eclass is only used to pick the enode,
+    /// or `None` if it has no finite cost, e.g. because it's empty.
     fn make_pass(&mut self, eclass: &EClass<L, N::Data>) -> Option<(CF::Cost, L)> {
+        if !self.materialize {
+            if let Some(cost) = self.overrides.get(&eclass.id) {
+                let node = eclass.nodes.first()?.clone();
+                return Some((cost.clone(), node));
+            }
+        }
+        if let Some(node) = self.required.get(&eclass.id) {
+            let node = node.clone();
+            return self.node_total_cost(&node).map(|c| (c, node));
+        }
         let (cost, node) = eclass
             .iter()
-            .map(|n| (self.node_total_cost(n), n))
-            .min_by(|a, b| cmp(&a.0, &b.0))
-            .unwrap_or_else(|| panic!("Can't extract, eclass is empty: {:#?}", eclass));
+            .map(|n| {
+                if self.is_forbidden(eclass.id, n) {
+                    (None, n)
+                } else {
+                    (self.node_total_cost(n), n)
+                }
+            })
+            .min_by(|a, b| cmp(&a.0, &b.0))?;
         cost.map(|c| (c, node.clone()))
     }
 }
+
+/// The state of [`Extractor::find_best_k`]: the `k` cheapest terms
+/// found so far for each eclass, as ids in `arena`, cheapest first.
+struct KBest<'e, 'a, CF: CostFunction<L>, L: Language, N: Analysis<L>> {
+    extractor: &'e Extractor<'a, CF, L, N>,
+    cost_function: CF,
+    k: usize,
+    arena: ExprArena<L>,
+    terms: HashMap<Id, Vec<(CF::Cost, Id)>>,
+}
+
+impl<'e, 'a, CF, L, N> KBest<'e, 'a, CF, L, N>
+where
+    CF: CostFunction<L>,
+    L: Language,
+    N: Analysis<L>,
+{
+    fn run(&mut self, root: Id) {
+        let extractor = self.extractor;
+        let egraph = extractor.egraph;
+        let choices = move |class: Id| {
+            egraph[class]
+                .iter()
+                .filter(move |n| extractor.is_choice(class, n))
+        };
+
+        let mut classes = vec![root];
+        let mut reached: HashSet<Id> = classes.iter().copied().collect();
+        let mut i = 0;
+        while let Some(&class) = classes.get(i) {
+            for node in choices(class) {
+                for &child in node.children() {
+                    let child = egraph.find(child);
+                    if reached.insert(child) {
+                        classes.push(child);
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        let mut did_something = true;
+        while did_something {
+            did_something = false;
+            for &class in &classes {
+                let old = self.terms.get(&class).cloned().unwrap_or_default();
+                let mut new = old.clone();
+                for node in choices(class) {
+                    new.extend(self.node_terms(node));
+                }
+                // the sort is stable, so the terms found earlier win ties
+                // and only something cheaper changes the list
+                new.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                let mut seen = HashSet::default();
+                new.retain(|(_, id)| seen.insert(*id));
+                new.truncate(self.k);
+                if new.iter().map(|t| t.1).ne(old.iter().map(|t| t.1)) {
+                    self.terms.insert(class, new);
+                    did_something = true;
+                }
+            }
+        }
+    }
+
+    /// The up to `k` cheapest terms under `node`, made from the terms of
+    /// its children so far, adding them to the arena.
+    fn node_terms(&mut self, node: &L) -> Vec<(CF::Cost, Id)> {
+        let egraph = self.extractor.egraph;
+        let mut lists = Vec::with_capacity(node.len());
+        for &child in node.children() {
+            match self.terms.get(&egraph.find(child)) {
+                Some(terms) => lists.push(terms.clone()),
+                None => return vec![],
+            }
+        }
+
+        // pick the cheapest combination left and queue the ones that
+        // take the next term of one child, which cost no less
+        let first = vec![0; lists.len()];
+        let mut queued = HashSet::default();
+        queued.insert(first.clone());
+        let mut frontier = vec![(self.pick_cost(node, &lists, &first), first)];
+        let mut found = vec![];
+        while found.len() < self.k && !frontier.is_empty() {
+            let cheapest = (0..frontier.len())
+                .min_by(|&a, &b| frontier[a].0.partial_cmp(&frontier[b].0).unwrap())
+                .unwrap();
+            let (cost, pick) = frontier.swap_remove(cheapest);
+            for position in 0..pick.len() {
+                if pick[position] + 1 < lists[position].len() {
+                    let mut next = pick.clone();
+                    next[position] += 1;
+                    if queued.insert(next.clone()) {
+                        frontier.push((self.pick_cost(node, &lists, &next), next));
+                    }
+                }
+            }
+            let mut term = node.clone();
+            for (position, child) in term.children_mut().iter_mut().enumerate() {
+                *child = lists[position][pick[position]].1;
+            }
+            found.push((cost, self.arena.add(term)));
+        }
+        found
+    }
+
+    /// The cost of `node` with the `pick[i]`th term of `lists[i]` for
+    /// its `i`th child.
+    fn pick_cost(&mut self, node: &L, lists: &[Vec<(CF::Cost, Id)>], pick: &[usize]) -> CF::Cost {
+        let children = node.children();
+        // an eclass can be several of the children, which the cost
+        // function asks for in turn
+        let mut asked: HashMap<Id, usize> = HashMap::default();
+        self.cost_function.cost(node, |id| {
+            let positions: Vec<usize> =
+                (0..children.len()).filter(|&i| children[i] == id).collect();
+            let n = asked.entry(id).or_default();
+            let position = *positions
+                .get(*n % positions.len().max(1))
+                .unwrap_or_else(|| panic!("{} isn't a child of {:?}", id, node));
+            *n += 1;
+            lists[position][pick[position]].0.clone()
+        })
+    }
+}
+
+/** Extracting a [`RecExpr`] whose cost counts shared subterms at a
+discount.
+
+The [`Extractor`] minimizes the cost of the extracted term as a tree,
+so a subterm used twice is paid for twice.
+A `SharedCostExtractor` instead charges only `discount` times the cost of
+a subterm that is already part of the selection:
+`0.0` makes reuse free (minimizing the cost of the term as a DAG),
+and `1.0` gives the same costs as the [`Extractor`].
+
+Like the [`Extractor`], this runs a greedy bottom-up pass to a fixpoint,
+but each selection also remembers which subterms it has already paid
+for.
+Sharing breaks optimal substructure, so the result is a heuristic: it
+is not guaranteed to be the cheapest term, and the passes are capped by
+`max_iterations` in case they don't settle.
+The [`DagExtractor`] finds the cheapest term with no discount exactly,
+at a higher price.
+Each selection stores the eclasses beneath it, so this uses
+more memory than the [`Extractor`].
+
+```
+use egg::*;
+
+let mut egraph = EGraph::<SymbolLang, ()>::default();
+let shared = egraph.add_expr(&"(+ (f a b c d) (f a b c d))".parse().unwrap());
+let tree = egraph.add_expr(&"(k w1 w2 w3 w4 w5 w6 w7 w8)".parse().unwrap());
+egraph.union(shared, tree);
+egraph.rebuild();
+
+let (tree_cost, best) = Extractor::new(&egraph, AstSize).find_best(shared);
+assert_eq!(tree_cost, 9);
+assert_eq!(best.to_string(), "(k w1 w2 w3 w4 w5 w6 w7 w8)");
+
+let extractor = SharedCostExtractor::new(&egraph, |_: &SymbolLang| 1.0, 0.0, 100);
+let (dag_cost, best) = extractor.find_best(shared);
+assert_eq!(dag_cost, 6.0);
+assert_eq!(best.to_string(), "(+ (f a b c d) (f a b c d))");
+```
+**/
+#[derive(Debug)]
+pub struct SharedCostExtractor<'a, L: Language, N: Analysis<L>> {
+    egraph: &'a EGraph<L, N>,
+    choices: HashMap<Id, Rc<Choice<L>>>,
+    iterations: usize,
+    converged: bool,
+}
+
+/// A selection made by a [`SharedCostExtractor`]: an enode along with
+/// the selections for its children, which may be shared with others.
+#[derive(Debug)]
+struct Choice<L> {
+    eclass: Id,
+    node: L,
+    children: Vec<Rc<Choice<L>>>,
+    cost: f64,
+    /// the own cost of the choice of every eclass in this sub-DAG,
+    /// including this one, keyed by eclass so there's one entry per
+    /// eclass however often its choice was replaced
+    paid: HashMap<Id, f64>,
+}
+
+impl<'a, L, N> SharedCostExtractor<'a, L, N>
+where
+    L: Language,
+    N: Analysis<L>,
+{
+    /// Create a new `SharedCostExtractor` given an `EGraph`, the cost of
+    /// each enode on its own (not counting its children), and the
+    /// fraction of a subterm's cost to charge when it is reused.
+    ///
+    /// Like [`Extractor::new`], this does all the work on creation,
+    /// running at most `max_iterations` passes over the egraph.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `discount` is not between `0.0` and `1.0`.
+    pub fn new<NC>(
+        egraph: &'a EGraph<L, N>,
+        mut node_cost: NC,
+        discount: f64,
+        max_iterations: usize,
+    ) -> Self
+    where
+        NC: FnMut(&L) -> f64,
+    {
+        assert!(
+            (0.0..=1.0).contains(&discount),
+            "discount must be between 0 and 1, got {}",
+            discount
+        );
+        let mut extractor = SharedCostExtractor {
+            egraph,
+            choices: HashMap::default(),
+            iterations: 0,
+            converged: false,
+        };
+
+        while extractor.iterations < max_iterations {
+            extractor.iterations += 1;
+            let mut did_something = false;
+            for class in egraph.classes() {
+                let best = class
+                    .iter()
+                    .filter_map(|n| extractor.node_choice(n, &mut node_cost, discount))
+                    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+                let (node, cost, children, paid) = match best {
+                    Some(best) => best,
+                    None => continue,
+                };
+                if let Some(old) = extractor.choices.get(&class.id) {
+                    if cost >= old.cost {
+                        continue;
+                    }
+                }
+
+                let mut paid = paid;
+                paid.insert(class.id, node_cost(&node));
+                let choice = Choice {
+                    eclass: class.id,
+                    node,
+                    children,
+                    cost,
+                    paid,
+                };
+                extractor.choices.insert(class.id, Rc::new(choice));
+                did_something = true;
+            }
+            if !did_something {
+                extractor.converged = true;
+                break;
+            }
+        }
+
+        for class in egraph.classes() {
+            if !extractor.choices.contains_key(&class.id) {
+                log::warn!(
+                    "Failed to compute cost for eclass {}: {:?}",
+                    class.id,
+                    class.nodes
+                )
+            }
+        }
+        extractor
+    }
+
+    /// Find the cheapest `RecExpr` in the given eclass that was found,
+    /// with its cost counting shared subterms at a discount.
+    ///
+    /// Shared subterms appear once in the returned `RecExpr`, and so
+    /// does each eclass, as the cost assumes.
+    pub fn find_best(&self, eclass: Id) -> (f64, RecExpr<L>) {
+        fn build<L: Language>(
+            choice: &Choice<L>,
+            memo: &mut HashMap<Id, Id>,
+            expr: &mut RecExpr<L>,
+        ) -> Id {
+            if let Some(&id) = memo.get(&choice.eclass) {
+                return id;
+            }
+            let mut children = choice.children.iter();
+            let node = choice
+                .node
+                .clone()
+                .map_children(|_| build(children.next().unwrap(), memo, expr));
+            let id = expr.add(node);
+            memo.insert(choice.eclass, id);
+            id
+        }
+
+        let choice = self.choice(eclass);
+        let mut expr = RecExpr::default();
+        build(choice, &mut HashMap::default(), &mut expr);
+        (choice.cost, expr)
+    }
+
+    /// Find the cost of the term that would be extracted from this
+    /// eclass.
+    pub fn find_best_cost(&self, eclass: Id) -> f64 {
+        self.choice(eclass).cost
+    }
+
+    fn choice(&self, eclass: Id) -> &Choice<L> {
+        let id = match self.egraph.try_get(eclass) {
+            Ok(class) => class.id,
+            Err(e) => panic!("{}", e),
+        };
+        match self.choices.get(&id) {
+            Some(choice) => choice,
+            None => panic!("{}", Error::Unextractable(id)),
+        }
+    }
+
+    /// The number of passes over the egraph that were run.
+    pub fn iterations(&self) -> usize {
+        self.iterations
+    }
+
+    /// Whether the passes reached a fixpoint before hitting
+    /// `max_iterations`.
+    pub fn converged(&self) -> bool {
+        self.converged
+    }
+
+    /// The cost of picking `node` given the current choices for its
+    /// children, or `None` if some child has no choice yet.
+    #[allow(clippy::type_complexity)]
+    fn node_choice<NC>(
+        &self,
+        node: &L,
+        node_cost: &mut NC,
+        discount: f64,
+    ) -> Option<(L, f64, Vec<Rc<Choice<L>>>, HashMap<Id, f64>)>
+    where
+        NC: FnMut(&L) -> f64,
+    {
+        let mut cost = node_cost(node);
+        let mut children = Vec::with_capacity(node.len());
+        let mut paid = HashMap::<Id, f64>::default();
+        for &child in node.children() {
+            let choice = self.choices.get(&self.egraph.find(child))?;
+            let overlap: f64 = choice
+                .paid
+                .iter()
+                .filter(|(eclass, _)| paid.contains_key(eclass))
+                .map(|(_, own_cost)| own_cost)
+                .sum();
+            cost += choice.cost - (1.0 - discount) * overlap;
+            paid.extend(choice.paid.iter().map(|(&s, &c)| (s, c)));
+            children.push(choice.clone());
+        }
+        Some((node.clone(), cost, children, paid))
+    }
+}
+
+/** Extracting the [`RecExpr`] that is cheapest as a DAG, counting each
+eclass in it once.
+
+This minimizes the same cost as a [`SharedCostExtractor`] with a
+`discount` of `0.0`: the sum of the costs of the term's distinct enodes.
+The [`SharedCostExtractor`] gets there greedily, so it may miss the
+cheapest term; a `DagExtractor` instead searches the choices of one
+enode per eclass under the root exhaustively, by branch and bound,
+starting from the [`Extractor`]'s tree-optimal term.
+If the search finishes within the
+[step limit](DagExtractor::with_step_limit), the term is the cheapest
+there is, and [`DagExtraction::optimal`] says so; otherwise it is the
+cheapest found so far, which is never worse than the tree-optimal one.
+The search is exponential in the worst case, so this suits terms of a
+modest number of eclasses, like circuits where sharing matters.
+
+```
+use egg::*;
+
+let mut egraph = EGraph::<SymbolLang, ()>::default();
+let mul = egraph.add_expr(&"(* (+ a b) (+ a b))".parse().unwrap());
+let pow = egraph.add_expr(&"(pow (+ a b) 2)".parse().unwrap());
+egraph.union(mul, pow);
+egraph.rebuild();
+
+let (tree_cost, best) = Extractor::new(&egraph, AstSize).find_best(mul);
+assert_eq!((tree_cost, best.to_string()), (5, "(pow (+ a b) 2)".into()));
+
+let best = DagExtractor::new(&egraph, |_: &SymbolLang| 1.0).find_best(mul);
+assert_eq!((best.cost, best.expr.to_string()), (4.0, "(* (+ a b) (+ a b))".into()));
+assert!(best.optimal);
+```
+**/
+#[derive(Debug)]
+pub struct DagExtractor<'a, L: Language, N: Analysis<L>> {
+    egraph: &'a EGraph<L, N>,
+    /// the canonical enodes of each eclass with their own costs,
+    /// cheapest first
+    options: HashMap<Id, Vec<(f64, L)>>,
+    /// the tree-optimal choices, which the search starts from
+    seed: HashMap<Id, L>,
+    step_limit: usize,
+}
+
+/// A term found by a [`DagExtractor`].
+#[derive(Debug, Clone)]
+pub struct DagExtraction<L> {
+    /// The sum of the costs of the distinct enodes of `expr`.
+    pub cost: f64,
+    /// The term, with each of its eclasses in it once.
+    pub expr: RecExpr<L>,
+    /// Whether the search finished, so no term of the eclass is cheaper
+    /// as a DAG.
+    pub optimal: bool,
+}
+
+/// An enode's own cost plus its children's, to seed a [`DagExtractor`].
+struct OwnCosts<'c, L>(&'c HashMap<L, f64>);
+
+impl<L: Language> CostFunction<L> for OwnCosts<'_, L> {
+    type Cost = f64;
+    fn cost<C>(&mut self, enode: &L, mut costs: C) -> f64
+    where
+        C: FnMut(Id) -> f64,
+    {
+        enode.fold(self.0[enode], |sum, id| sum + costs(id))
+    }
+}
+
+impl<'a, L, N> DagExtractor<'a, L, N>
+where
+    L: Language,
+    N: Analysis<L>,
+{
+    /// Create a new `DagExtractor` given an `EGraph` and the cost of
+    /// each enode on its own (not counting its children).
+    ///
+    /// The step limit defaults to 100,000 enodes tried per search.
+    ///
+    /// # Panics
+    ///
+    /// Panics if some enode's cost is negative or `NaN`.
+    pub fn new<NC>(egraph: &'a EGraph<L, N>, mut node_cost: NC) -> Self
+    where
+        NC: FnMut(&L) -> f64,
+    {
+        let mut own = HashMap::default();
+        let mut options = HashMap::default();
+        for class in egraph.classes() {
+            let mut nodes: Vec<(f64, L)> = class
+                .iter()
+                .map(|n| {
+                    let cost = node_cost(n);
+                    assert!(cost >= 0.0, "{:?} has a bad cost {}", n, cost);
+                    own.insert(n.clone(), cost);
+                    (cost, n.clone().map_children(|id| egraph.find(id)))
+                })
+                .collect();
+            nodes.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            options.insert(class.id, nodes);
+        }
+
+        let tree = Extractor::new(egraph, OwnCosts(&own));
+        let seed = egraph
+            .classes()
+            .filter_map(|class| {
+                let (_, node) = tree.try_best(class.id).ok()?;
+                Some((class.id, node.clone().map_children(|id| egraph.find(id))))
+            })
+            .collect();
+
+        DagExtractor {
+            egraph,
+            options,
+            seed,
+            step_limit: 100_000,
+        }
+    }
+
+    /// Sets the number of enodes each search may try before it settles
+    /// for the cheapest term found so far.
+    pub fn with_step_limit(mut self, step_limit: usize) -> Self {
+        self.step_limit = step_limit;
+        self
+    }
+
+    /// Find the cheapest term of the given eclass as a DAG, as far as
+    /// the step limit allows.
+    ///
+    /// Panics if the eclass is invalid or has no finite term.
+    pub fn find_best(&self, eclass: Id) -> DagExtraction<L> {
+        self.try_find_best(eclass)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like [`find_best`](DagExtractor::find_best), but fails instead
+    /// of panicking if `eclass` is invalid or has no finite term.
+    pub fn try_find_best(&self, eclass: Id) -> Result<DagExtraction<L>, Error> {
+        let root = self.egraph.try_get(eclass)?.id;
+        if !self.seed.contains_key(&root) {
+            return Err(Error::Unextractable(root));
+        }
+
+        let mut best = HashMap::default();
+        let mut todo = vec![root];
+        while let Some(id) = todo.pop() {
+            if !best.contains_key(&id) {
+                let node = &self.seed[&id];
+                todo.extend(node.children());
+                best.insert(id, node.clone());
+            }
+        }
+        let mut search = DagSearch {
+            extractor: self,
+            best_cost: best.iter().map(|(&id, n)| self.own_cost(id, n)).sum(),
+            best,
+            chosen: HashMap::default(),
+            steps: 0,
+        };
+        let optimal = search.search(0.0, &std::iter::once(root).collect());
+
+        let mut expr = RecExpr::default();
+        let mut built = HashMap::default();
+        build_dag(&search.best, root, &mut built, &mut expr);
+        Ok(DagExtraction {
+            cost: search.best_cost,
+            expr,
+            optimal,
+        })
+    }
+
+    fn own_cost(&self, class: Id, node: &L) -> f64 {
+        let options = &self.options[&class];
+        options.iter().find(|(_, n)| n == node).unwrap().0
+    }
+}
+
+/// The state of one [`DagExtractor`] search.
+struct DagSearch<'e, 'a, L: Language, N: Analysis<L>> {
+    extractor: &'e DagExtractor<'a, L, N>,
+    best: HashMap<Id, L>,
+    best_cost: f64,
+    /// the enode picked for each eclass so far
+    chosen: HashMap<Id, L>,
+    steps: usize,
+}
+
+impl<'e, 'a, L: Language, N: Analysis<L>> DagSearch<'e, 'a, L, N> {
+    /// Try each enode for an eclass of `frontier`, the eclasses the
+    /// chosen enodes need that don't have one yet, returning whether
+    /// the search finished within the step limit.
+    fn search(&mut self, cost: f64, frontier: &BTreeSet<Id>) -> bool {
+        let extractor = self.extractor;
+        let class = match frontier.iter().next() {
+            Some(&class) => class,
+            None => {
+                if cost < self.best_cost {
+                    self.best_cost = cost;
+                    self.best = self.chosen.clone();
+                }
+                return true;
+            }
+        };
+        // every eclass of the frontier needs at least its cheapest enode
+        let bound: f64 = frontier.iter().map(|id| extractor.options[id][0].0).sum();
+        if cost + bound >= self.best_cost {
+            return true;
+        }
+
+        for (own, node) in &extractor.options[&class] {
+            if self.steps >= extractor.step_limit {
+                return false;
+            }
+            self.steps += 1;
+            if node.any(|child| self.reaches(child, class)) {
+                continue;
+            }
+            let mut next = frontier.clone();
+            next.remove(&class);
+            next.extend(
+                node.children()
+                    .iter()
+                    .filter(|child| !self.chosen.contains_key(child)),
+            );
+            self.chosen.insert(class, node.clone());
+            let finished = self.search(cost + own, &next);
+            self.chosen.remove(&class);
+            if !finished {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether the chosen enodes lead from `from` to `to`, so picking an
+    /// enode of `to` with `from` as a child would make a cycle.
+    fn reaches(&self, from: Id, to: Id) -> bool {
+        let mut seen = HashSet::default();
+        let mut todo = vec![from];
+        while let Some(id) = todo.pop() {
+            if id == to {
+                return true;
+            }
+            if let Some(node) = self.chosen.get(&id) {
+                if seen.insert(id) {
+                    todo.extend(node.children());
+                }
+            }
+        }
+        false
+    }
+}
+
+/// Add the term picked by `choices` for `class` to `expr`, once per
+/// eclass.
+fn build_dag<L: Language>(
+    choices: &HashMap<Id, L>,
+    class: Id,
+    built: &mut HashMap<Id, Id>,
+    expr: &mut RecExpr<L>,
+) -> Id {
+    if let Some(&id) = built.get(&class) {
+        return id;
+    }
+    let node = choices[&class]
+        .clone()
+        .map_children(|child| build_dag(choices, child, built, expr));
+    let id = expr.add(node);
+    built.insert(class, id);
+    id
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    #[test]
+    fn lexicographic_prefers_shallow_terms() {
+        let mut egraph = EGraph::<SymbolLang, ()>::default();
+        let deep = egraph.add_expr(&"(f (g (h a)))".parse().unwrap());
+        let wide = egraph.add_expr(&"(k a a a a a)".parse().unwrap());
+        egraph.union(deep, wide);
+        egraph.rebuild();
+
+        let (cost, best) = Extractor::new(&egraph, AstSize).find_best(deep);
+        assert_eq!((cost, best.to_string()), (4, "(f (g (h a)))".into()));
+
+        let lex = Lexicographic(AstDepth, AstSize);
+        let (cost, best) = Extractor::new(&egraph, lex).find_best(deep);
+        assert_eq!((cost, best.to_string()), ((2, 6), "(k a a a a a)".into()));
+
+        // mostly size, but depth counts a lot
+        let sum = WeightedSum::new(AstDepth, 2.0, AstSize, 1.0);
+        let (cost, best) = Extractor::new(&egraph, sum).find_best(deep);
+        assert_eq!(
+            (cost.total, best.to_string()),
+            (10.0, "(k a a a a a)".into())
+        );
+        let sum = WeightedSum::new(AstDepth, 0.5, AstSize, 1.0);
+        let (cost, best) = Extractor::new(&egraph, sum).find_best(deep);
+        assert_eq!(
+            (cost.total, best.to_string()),
+            (6.0, "(f (g (h a)))".into())
+        );
+    }
+
+    #[test]
+    fn pinned_choices_are_respected() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let root = egraph.add_expr(&"(f x y)".parse().unwrap());
+        let x = egraph.add_expr(&"x".parse().unwrap());
+        let g = egraph.add_expr(&"(g u v)".parse().unwrap());
+        let u = egraph.add_expr(&"u".parse().unwrap());
+        let k = egraph.add_expr(&"(k w)".parse().unwrap());
+        let y = egraph.add_expr(&"y".parse().unwrap());
+        let h = egraph.add_expr(&"(h r)".parse().unwrap());
+        egraph.union(x, g);
+        egraph.union(u, k);
+        egraph.union(y, h);
+        egraph.rebuild();
+        let node = |id: Id, op: &str| {
+            let class = &egraph[id];
+            class.iter().find(|n| n.op.as_str() == op).unwrap().clone()
+        };
+
+        let (cost, best) = Extractor::new(&egraph, AstSize).find_best(root);
+        assert_eq!((cost, best.to_string()), (3, "(f x y)".into()));
+
+        // the pinned node is suboptimal, its children are still optimized
+        let g_node = node(x, "g");
+        let extractor = Extractor::new(&egraph, AstSize)
+            .with_required_choice(x, g_node.clone())
+            .unwrap();
+        let (cost, best) = extractor.find_best(root);
+        assert_eq!((cost, best.to_string()), (5, "(f (g u v) y)".into()));
+
+        let y_node = node(y, "y");
+        let extractor = extractor.with_forbidden_choice(y, y_node.clone()).unwrap();
+        let (cost, best) = extractor.find_best(root);
+        assert_eq!((cost, best.to_string()), (6, "(f (g u v) (h r))".into()));
+
+        // conflicts
+        let extractor = Extractor::new(&egraph, AstSize)
+            .with_required_choice(g, g_node.clone())
+            .unwrap();
+        let x_node = node(x, "x");
+        assert!(matches!(
+            extractor.with_required_choice(x, x_node),
+            Err(ExtractChoiceError::Conflict { .. })
+        ));
+        let extractor = Extractor::new(&egraph, AstSize)
+            .with_required_choice(g, g_node.clone())
+            .unwrap();
+        assert!(matches!(
+            extractor.with_forbidden_choice(g, g_node.clone()),
+            Err(ExtractChoiceError::Conflict { .. })
+        ));
+        assert!(matches!(
+            Extractor::new(&egraph, AstSize).with_required_choice(y, g_node),
+            Err(ExtractChoiceError::NotInClass { .. })
+        ));
+
+        // forbidding all of y leaves the pinned root without a term
+        let h_node = node(y, "h");
+        let f_node = node(root, "f");
+        let result = Extractor::new(&egraph, AstSize)
+            .with_required_choice(root, f_node)
+            .unwrap()
+            .with_forbidden_choice(y, y_node)
+            .unwrap()
+            .with_forbidden_choice(y, h_node);
+        let root = egraph.find(root);
+        assert_eq!(
+            result.err(),
+            Some(ExtractChoiceError::Unsatisfiable { class: root })
+        );
+    }
+
+    #[test]
+    fn cyclic_required_choice_is_unsatisfiable() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let a = egraph.add_expr(&"a".parse().unwrap());
+        let times_one = egraph.add_expr(&"(* a 1)".parse().unwrap());
+        egraph.union(a, times_one);
+        egraph.rebuild();
+        let a = egraph.find(a);
+        let node = egraph[a]
+            .iter()
+            .find(|n| n.op.as_str() == "*")
+            .unwrap()
+            .clone();
+
+        let result = Extractor::new(&egraph, AstSize).with_required_choice(a, node);
+        assert_eq!(
+            result.err(),
+            Some(ExtractChoiceError::Unsatisfiable { class: a })
+        );
+    }
+
+    #[test]
+    fn cost_overrides_change_the_best_term() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let root = egraph.add_expr(&"(f (big p q r s))".parse().unwrap());
+        let big = egraph.add_expr(&"(big p q r s)".parse().unwrap());
+        let alt = egraph.add_expr(&"(alt p q r s t)".parse().unwrap());
+        let small = egraph.add_expr(&"(g (small c))".parse().unwrap());
+        egraph.union(big, alt);
+        egraph.union(root, small);
+        egraph.rebuild();
+        let stale = if egraph.find(big) == big { alt } else { big };
+        assert_ne!(stale, egraph.find(stale));
+
+        let (cost, best) = Extractor::new(&egraph, AstSize).find_best(root);
+        assert_eq!((cost, best.to_string()), (3, "(g (small c))".into()));
+
+        // the override makes the big class cheap, and it's still
+        // extracted as its smaller enode
+        let extractor =
+            Extractor::new(&egraph, AstSize).with_cost_overrides(vec![(stale, 1)], true);
+        let (cost, best) = extractor.find_best(root);
+        assert_eq!((cost, best.to_string()), (2, "(f (big p q r s))".into()));
+        assert_eq!(extractor.find_best_cost(big), 1);
+        assert_eq!(extractor.find_best(big).1.to_string(), "(big p q r s)");
+
+        // the cheapest of several overrides of a class wins, and
+        // overrides replace the earlier ones
+        let extractor = extractor.with_cost_overrides(vec![(big, 7), (alt, 1)], true);
+        let (cost, best) = extractor.find_best(root);
+        assert_eq!((cost, best.to_string()), (2, "(f (big p q r s))".into()));
+        let extractor = extractor.with_cost_overrides(vec![(big, 3)], true);
+        let (cost, best) = extractor.find_best(root);
+        assert_eq!((cost, best.to_string()), (3, "(g (small c))".into()));
+
+        // leaves have costs but no terms
+        let extractor = Extractor::new(&egraph, AstSize).with_cost_overrides(vec![(big, 1)], false);
+        let big = egraph.find(big);
+        assert_eq!(extractor.find_best_cost(root), 2);
+        assert_eq!(extractor.find_best_cost(big), 1);
+        assert!(matches!(
+            extractor.try_find_best(root),
+            Err(Error::Unextractable(id)) if id == big
+        ));
+        let mut arena = ExprArena::new();
+        assert!(matches!(
+            extractor.try_find_best_into(&mut arena, root),
+            Err(Error::Unextractable(id)) if id == big
+        ));
+
+        // even if they have no finite term
+        let mut egraph = EGraph::<S, ()>::default();
+        let x = egraph.add(S::leaf("x"));
+        let fx = egraph.add(S::new("f", vec![x]));
+        let gx = egraph.add(S::new("g", vec![x]));
+        egraph.union(x, fx);
+        egraph.rebuild();
+        egraph
+            .classes_mut()
+            .for_each(|c| c.nodes.retain(|n| !n.is_leaf()));
+        let extractor = Extractor::new(&egraph, AstSize);
+        assert!(extractor.try_find_best_cost(gx).is_err());
+        let extractor = extractor.with_cost_overrides(vec![(x, 5)], false);
+        assert_eq!(extractor.find_best_cost(gx), 6);
+    }
+
+    #[test]
+    fn shared_cost_no_discount_matches_tree_cost() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rw!("assoc-add"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+            rw!("double"; "(+ ?a ?a)" => "(* 2 ?a)"),
+            rw!("dist"; "(* ?a (+ ?b ?c))" => "(+ (* ?a ?b) (* ?a ?c))"),
+        ];
+        let runner = Runner::default()
+            .with_expr(&"(+ (* x (+ y z)) (+ (* x y) (* x z)))".parse().unwrap())
+            .with_iter_limit(4)
+            .run(&rules);
+        let egraph = &runner.egraph;
+
+        let tree = Extractor::new(egraph, AstSize);
+        let shared = SharedCostExtractor::new(egraph, |_: &S| 1.0, 1.0, 100);
+        assert!(shared.converged());
+        for class in egraph.classes() {
+            let (cost, expr) = shared.find_best(class.id);
+            assert!(cost <= tree.find_best_cost(class.id) as f64);
+            assert_eq!(AstSize.cost_rec(&expr) as f64, cost);
+        }
+    }
+
+    #[test]
+    fn shared_cost_free_reuse_finds_diamond() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let x = egraph.add_expr(&"(g (f a b) (f a b))".parse().unwrap());
+        let y = egraph.add_expr(&"(h (f a b) c)".parse().unwrap());
+        let diamond = egraph.add_expr(&"(op (g (f a b) (f a b)) (h (f a b) c))".parse().unwrap());
+        let flat = egraph.add_expr(&"(flat p q r s t u v w)".parse().unwrap());
+        egraph.union(diamond, flat);
+        egraph.rebuild();
+        let dag_size = |e: &RecExpr<S>| e.as_ref().len() as f64;
+
+        let (tree_cost, tree_best) = Extractor::new(&egraph, AstSize).find_best(diamond);
+        assert_eq!(tree_cost, 9);
+
+        let shared = SharedCostExtractor::new(&egraph, |_: &S| 1.0, 0.0, 100);
+        let (cost, best) = shared.find_best(diamond);
+        assert!(cost < dag_size(&tree_best));
+        assert_eq!(cost, dag_size(&best));
+        assert_eq!(best.to_string(), "(op (g (f a b) (f a b)) (h (f a b) c))");
+        assert_eq!(shared.find_best_cost(x), 4.0);
+        assert_eq!(shared.find_best_cost(y), 5.0);
+
+        // partial reuse is in between
+        let partial = SharedCostExtractor::new(&egraph, |_: &S| 1.0, 0.5, 100);
+        assert_eq!(partial.find_best_cost(x), 5.5);
+    }
+
+    #[test]
+    fn dag_extraction_differs_from_tree_extraction() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let mul = egraph.add_expr(&"(* (+ a b) (+ a b))".parse().unwrap());
+        let pow = egraph.add_expr(&"(pow (+ a b) 2)".parse().unwrap());
+        egraph.union(mul, pow);
+        egraph.rebuild();
+
+        let (cost, best) = Extractor::new(&egraph, AstSize).find_best(mul);
+        assert_eq!((cost, best.to_string()), (5, "(pow (+ a b) 2)".into()));
+        let dag = DagExtractor::new(&egraph, |_: &S| 1.0);
+        let best = dag.find_best(mul);
+        assert_eq!(best.expr.to_string(), "(* (+ a b) (+ a b))");
+        assert_eq!(best.cost, 4.0);
+        assert_eq!(best.expr.as_ref().len(), 4);
+        assert!(best.optimal);
+        assert!(matches!(
+            dag.try_find_best(Id::from(100)),
+            Err(Error::InvalidId(_))
+        ));
+    }
+
+    #[test]
+    fn dag_extraction_beats_the_greedy_shared_cost() {
+        // (r X (q S)), where X is cheaper on its own as (k u v), but
+        // (p S) shares S with (q S)
+        let mut egraph = EGraph::<S, ()>::default();
+        let root = egraph.add_expr(&"(r (k u v) (q (s (t w))))".parse().unwrap());
+        let k = egraph.add_expr(&"(k u v)".parse().unwrap());
+        let p = egraph.add_expr(&"(p (s (t w)))".parse().unwrap());
+        egraph.union(k, p);
+        egraph.rebuild();
+
+        let greedy = SharedCostExtractor::new(&egraph, |_: &S| 1.0, 0.0, 100);
+        let (cost, best) = greedy.find_best(root);
+        assert_eq!(
+            (cost, best.to_string()),
+            (8.0, "(r (k u v) (q (s (t w))))".into())
+        );
+
+        let dag = DagExtractor::new(&egraph, |_: &S| 1.0);
+        let best = dag.find_best(root);
+        assert_eq!(best.expr.to_string(), "(r (p (s (t w))) (q (s (t w))))");
+        assert_eq!(best.cost, 6.0);
+        assert!(best.optimal);
+
+        // without any steps, all there is is the tree-optimal term
+        let best = DagExtractor::new(&egraph, |_: &S| 1.0)
+            .with_step_limit(0)
+            .find_best(root);
+        assert_eq!(best.expr.to_string(), "(r (k u v) (q (s (t w))))");
+        assert_eq!(best.cost, 8.0);
+        assert!(!best.optimal);
+
+        // cycles are never picked
+        let x = egraph.add_expr(&"(f (r (k u v) (q (s (t w)))))".parse().unwrap());
+        egraph.union(x, root);
+        egraph.rebuild();
+        let best = DagExtractor::new(
+            &egraph,
+            |n: &S| if n.op.as_str() == "f" { 0.0 } else { 1.0 },
+        )
+        .find_best(root);
+        assert_eq!(best.cost, 6.0);
+        assert!(best.expr.as_ref().iter().all(|n| n.op.as_str() != "f"));
+    }
+
+    fn k_best(extractor: &Extractor<AstSize, S, ()>, id: Id, k: usize) -> Vec<(usize, String)> {
+        let best = extractor.find_best_k(id, k);
+        best.into_iter()
+            .map(|(cost, expr)| (cost, expr.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn k_best_terms() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let x = egraph.add_expr(&"x".parse().unwrap());
+        let x0 = egraph.add_expr(&"(+ x 0)".parse().unwrap());
+        let double = egraph.add_expr(&"(double x)".parse().unwrap());
+        let mul = egraph.add_expr(&"(* x 2)".parse().unwrap());
+        egraph.union(x, x0);
+        egraph.union(double, mul);
+        egraph.rebuild();
+
+        let extractor = Extractor::new(&egraph, AstSize);
+        assert_eq!(
+            k_best(&extractor, double, 3),
+            vec![
+                (2, "(double x)".into()),
+                (3, "(* x 2)".into()),
+                (4, "(double (+ x 0))".into()),
+            ]
+        );
+        assert_eq!(
+            k_best(&extractor, double, 1)[0].1,
+            extractor.find_best(double).1.to_string()
+        );
+        assert!(extractor.find_best_k(double, 0).is_empty());
+
+        let extractor = extractor
+            .with_forbidden_choice(double, S::new("double", vec![x]))
+            .unwrap();
+        assert_eq!(
+            k_best(&extractor, double, 2),
+            vec![(3, "(* x 2)".into()), (5, "(* (+ x 0) 2)".into())]
+        );
+
+        // each occurrence of a class picks its own term
+        let mut egraph = EGraph::<S, ()>::default();
+        let sum = egraph.add_expr(&"(+ a a)".parse().unwrap());
+        let a = egraph.add_expr(&"a".parse().unwrap());
+        let fb = egraph.add_expr(&"(f b)".parse().unwrap());
+        egraph.union(a, fb);
+        egraph.rebuild();
+        let extractor = Extractor::new(&egraph, AstSize);
+        let best = k_best(&extractor, sum, 10);
+        assert_eq!(best.len(), 4);
+        assert_eq!(best[0], (3, "(+ a a)".into()));
+        assert_eq!(best[3], (5, "(+ (f b) (f b))".into()));
+        let mut middle: Vec<_> = best[1..3].to_vec();
+        middle.sort();
+        assert_eq!(
+            middle,
+            vec![(4, "(+ (f b) a)".into()), (4, "(+ a (f b))".into())]
+        );
+    }
+
+    #[test]
+    fn k_best_in_a_cycle() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let x = egraph.add_expr(&"x".parse().unwrap());
+        let mul = egraph.add_expr(&"(* x 1)".parse().unwrap());
+        egraph.union(x, mul);
+        egraph.rebuild();
+
+        let extractor = Extractor::new(&egraph, AstSize);
+        assert_eq!(
+            k_best(&extractor, x, 3),
+            vec![
+                (1, "x".into()),
+                (3, "(* x 1)".into()),
+                (5, "(* (* x 1) 1)".into()),
+            ]
+        );
+        let best = k_best(&extractor, x, 6);
+        let costs: Vec<usize> = best.iter().map(|b| b.0).collect();
+        assert_eq!(costs, vec![1, 3, 5, 7, 9, 11]);
+
+        // 1 isn't in the cycle, so it only has the one term
+        let one = egraph.lookup_expr(&"1".parse().unwrap()).unwrap();
+        assert_eq!(k_best(&extractor, one, 6), vec![(1, "1".into())]);
+        assert!(matches!(
+            extractor.try_find_best_k(Id::from(100), 3),
+            Err(Error::InvalidId(_))
+        ));
+    }
+}
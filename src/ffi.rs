@@ -0,0 +1,387 @@
+/*!
+A C API for embedding `egg` in other languages.
+
+The API covers a fixed instantiation, an [`EGraph`] of [`SymbolLang`]
+with no analysis, behind an opaque [`EggEGraph`] handle:
+creating and freeing it, adding expressions and unioning eclasses,
+rebuilding, running rules written as in [`parse_rules`], extracting the
+smallest term of an eclass (by [`AstSize`]), and the [`Report`] of the
+last run as JSON.
+
+This module is only available with the `ffi` feature.
+To call it from another language, build a `cdylib` or `staticlib` crate
+that depends on `egg` with the feature, and `pub use egg::ffi::*;` so
+the functions are exported.
+
+# Conventions
+
+- Every function but [`egg_egraph_new`], [`egg_egraph_free`],
+  [`egg_string_free`] and [`egg_last_error`] returns an [`EggStatus`],
+  and writes its results through out-pointers only when it returns
+  [`EggStatus::Ok`].
+- When a function fails, [`egg_last_error`] describes why, until the
+  next failure on the same thread.
+- Strings passed in are NUL-terminated UTF-8 and stay owned by the
+  caller.
+  Strings passed out are owned by the caller, who must free each one
+  with [`egg_string_free`], exactly once.
+- A handle from [`egg_egraph_new`] must be freed with
+  [`egg_egraph_free`], exactly once, and not used afterwards.
+  A handle may be moved between threads, but not used by two at once.
+- Eclass ids are `uint32_t`s, and stay valid for the life of the
+  handle, though a union can make two of them the same eclass.
+- Panics don't unwind into the caller: they fail with
+  [`EggStatus::Panic`], after which the handle may only be freed.
+
+# Example
+
+Driving the API from Rust the way a C caller would:
+```
+use egg::ffi::*;
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+unsafe {
+    let egraph = egg_egraph_new();
+    let expr = CString::new("(+ x 0)").unwrap();
+    let mut id = 0;
+    assert_eq!(egg_add_expr(egraph, expr.as_ptr(), &mut id), EggStatus::Ok);
+
+    let rules = CString::new("add-0: (+ ?a 0) => ?a").unwrap();
+    assert_eq!(egg_run_rules(egraph, rules.as_ptr(), 10), EggStatus::Ok);
+
+    let mut best = ptr::null_mut();
+    let mut cost = 0;
+    assert_eq!(egg_extract_best(egraph, id, &mut best, &mut cost), EggStatus::Ok);
+    assert_eq!(CStr::from_ptr(best).to_str().unwrap(), "x");
+    assert_eq!(cost, 1);
+
+    egg_string_free(best);
+    egg_egraph_free(egraph);
+}
+```
+!*/
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::*;
+
+/// The result of a call to the C API.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EggStatus {
+    /// The call succeeded.
+    Ok = 0,
+    /// A pointer that must not be null was null.
+    NullPointer = 1,
+    /// A string passed in isn't UTF-8, or a string to pass out has a
+    /// NUL byte.
+    InvalidString = 2,
+    /// An expression or a rule didn't parse.
+    Parse = 3,
+    /// An eclass id isn't one of the egraph's.
+    InvalidId = 4,
+    /// The eclass has no finite term to extract.
+    Unextractable = 5,
+    /// There is no report because rules haven't been run yet.
+    NoReport = 6,
+    /// `egg` panicked.
+    Panic = 7,
+    /// `egg` returned some other error; the handle is still usable.
+    Failed = 8,
+}
+
+/// An opaque handle to an egraph and the report of its last run.
+pub struct EggEGraph {
+    egraph: EGraph<SymbolLang, ()>,
+    report: Option<Report>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+struct Failure(EggStatus, String);
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Self {
+        let status = match e {
+            Error::Parse(_) | Error::UnboundVar { .. } => EggStatus::Parse,
+            Error::InvalidId(_) => EggStatus::InvalidId,
+            Error::Unextractable(_) => EggStatus::Unextractable,
+            _ => EggStatus::Failed,
+        };
+        Failure(status, e.to_string())
+    }
+}
+
+/// Run `f`, catching panics, and record the failure, if any, for
+/// [`egg_last_error`].
+fn guard(f: impl FnOnce() -> Result<(), Failure>) -> EggStatus {
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(s) => s.to_string(),
+            None => match payload.downcast_ref::<String>() {
+                Some(s) => s.clone(),
+                None => "unknown panic".into(),
+            },
+        };
+        Err(Failure(EggStatus::Panic, message))
+    });
+    match result {
+        Ok(()) => EggStatus::Ok,
+        Err(Failure(status, message)) => {
+            let message = CString::new(message.replace('\0', " ")).unwrap();
+            LAST_ERROR.with(|last| *last.borrow_mut() = message);
+            status
+        }
+    }
+}
+
+fn null(what: &str) -> Failure {
+    Failure(EggStatus::NullPointer, format!("{} is null", what))
+}
+
+unsafe fn handle<'a>(egraph: *mut EggEGraph) -> Result<&'a mut EggEGraph, Failure> {
+    egraph.as_mut().ok_or_else(|| null("egraph"))
+}
+
+unsafe fn input<'a>(s: *const c_char, what: &str) -> Result<&'a str, Failure> {
+    if s.is_null() {
+        return Err(null(what));
+    }
+    CStr::from_ptr(s).to_str().map_err(|e| {
+        let message = format!("{} isn't UTF-8: {}", what, e);
+        Failure(EggStatus::InvalidString, message)
+    })
+}
+
+fn output_string(s: String) -> Result<*mut c_char, Failure> {
+    CString::new(s)
+        .map(CString::into_raw)
+        .map_err(|e| Failure(EggStatus::InvalidString, e.to_string()))
+}
+
+fn class(egraph: &EGraph<SymbolLang, ()>, id: u32) -> Result<Id, Failure> {
+    Ok(egraph.try_get(Id::from(id as usize))?.id)
+}
+
+/// Create an empty egraph.
+///
+/// Free it with [`egg_egraph_free`].
+#[no_mangle]
+pub extern "C" fn egg_egraph_new() -> *mut EggEGraph {
+    Box::into_raw(Box::new(EggEGraph {
+        egraph: EGraph::default(),
+        report: None,
+    }))
+}
+
+/// Free an egraph made by [`egg_egraph_new`]; `NULL` is ignored.
+///
+/// # Safety
+///
+/// `egraph` must be `NULL` or a handle that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn egg_egraph_free(egraph: *mut EggEGraph) {
+    if !egraph.is_null() {
+        drop(Box::from_raw(egraph));
+    }
+}
+
+/// Add the expression `expr`, an s-expression, and write the id of its
+/// eclass to `out_id`.
+///
+/// # Safety
+///
+/// `egraph` must be a live handle, `expr` a NUL-terminated string, and
+/// `out_id` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn egg_add_expr(
+    egraph: *mut EggEGraph,
+    expr: *const c_char,
+    out_id: *mut u32,
+) -> EggStatus {
+    guard(|| {
+        let handle = handle(egraph)?;
+        if out_id.is_null() {
+            return Err(null("out_id"));
+        }
+        let expr: RecExpr<SymbolLang> = input(expr, "expr")?.parse().map_err(Error::from)?;
+        let id = handle.egraph.add_expr(&expr);
+        out_id.write(usize::from(id) as u32);
+        Ok(())
+    })
+}
+
+/// Union the eclasses `a` and `b`, writing whether they were different
+/// to `out_changed`, which may be `NULL`.
+///
+/// Call [`egg_rebuild`] before relying on the consequences.
+///
+/// # Safety
+///
+/// `egraph` must be a live handle, and `out_changed` `NULL` or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn egg_union(
+    egraph: *mut EggEGraph,
+    a: u32,
+    b: u32,
+    out_changed: *mut bool,
+) -> EggStatus {
+    guard(|| {
+        let handle = handle(egraph)?;
+        let a = class(&handle.egraph, a)?;
+        let b = class(&handle.egraph, b)?;
+        let changed = handle.egraph.union(a, b);
+        if !out_changed.is_null() {
+            out_changed.write(changed);
+        }
+        Ok(())
+    })
+}
+
+/// Restore the egraph's invariants after [`egg_add_expr`]s and
+/// [`egg_union`]s, like [`EGraph::rebuild`].
+///
+/// # Safety
+///
+/// `egraph` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn egg_rebuild(egraph: *mut EggEGraph) -> EggStatus {
+    guard(|| {
+        handle(egraph)?.egraph.rebuild();
+        Ok(())
+    })
+}
+
+/// Run the rules in `rules`, written as for [`parse_rules`], for at most
+/// `iter_limit` iterations and the other limits of [`Runner::default`],
+/// keeping the [report](egg_run_report_json).
+///
+/// # Safety
+///
+/// `egraph` must be a live handle, and `rules` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn egg_run_rules(
+    egraph: *mut EggEGraph,
+    rules: *const c_char,
+    iter_limit: u32,
+) -> EggStatus {
+    guard(|| {
+        let handle = handle(egraph)?;
+        let rules: Vec<Rewrite<SymbolLang, ()>> =
+            parse_rules(input(rules, "rules")?).map_err(Error::from)?;
+        let runner = Runner::default()
+            .with_egraph(std::mem::take(&mut handle.egraph))
+            .with_iter_limit(iter_limit as usize)
+            .run(&rules);
+        handle.report = Some(runner.report());
+        handle.egraph = runner.egraph;
+        Ok(())
+    })
+}
+
+/// Extract the smallest term of eclass `id`, rebuilding first if
+/// needed, and write it as an s-expression to `out_expr` and its size
+/// to `out_cost`, which may be `NULL`.
+///
+/// Free the string with [`egg_string_free`].
+///
+/// # Safety
+///
+/// `egraph` must be a live handle, `out_expr` valid for writes, and
+/// `out_cost` `NULL` or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn egg_extract_best(
+    egraph: *mut EggEGraph,
+    id: u32,
+    out_expr: *mut *mut c_char,
+    out_cost: *mut usize,
+) -> EggStatus {
+    guard(|| {
+        let handle = handle(egraph)?;
+        if out_expr.is_null() {
+            return Err(null("out_expr"));
+        }
+        if !handle.egraph.clean {
+            handle.egraph.rebuild();
+        }
+        let id = class(&handle.egraph, id)?;
+        let (cost, best) = Extractor::new(&handle.egraph, AstSize).try_find_best(id)?;
+        out_expr.write(output_string(best.to_string())?);
+        if !out_cost.is_null() {
+            out_cost.write(cost);
+        }
+        Ok(())
+    })
+}
+
+/// Write the [`Report`] of the last [`egg_run_rules`] as JSON to
+/// `out_json`.
+///
+/// Free the string with [`egg_string_free`].
+///
+/// # Safety
+///
+/// `egraph` must be a live handle, and `out_json` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn egg_run_report_json(
+    egraph: *mut EggEGraph,
+    out_json: *mut *mut c_char,
+) -> EggStatus {
+    guard(|| {
+        let handle = handle(egraph)?;
+        if out_json.is_null() {
+            return Err(null("out_json"));
+        }
+        let report = handle.report.as_ref().ok_or_else(|| {
+            let message = "no rules have been run".to_string();
+            Failure(EggStatus::NoReport, message)
+        })?;
+        let json = serde_json::to_string(report).unwrap();
+        out_json.write(output_string(json)?);
+        Ok(())
+    })
+}
+
+/// Free a string returned by this API; `NULL` is ignored.
+///
+/// # Safety
+///
+/// `s` must be `NULL` or a string returned by this API that hasn't been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn egg_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// The message of the last failed call on this thread, or an empty
+/// string if none has failed.
+///
+/// The string is owned by the API, and stays valid until the next
+/// failure on this thread.
+#[no_mangle]
+pub extern "C" fn egg_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_map_to_statuses() {
+        let status = |e: Error| Failure::from(e).0;
+        assert_eq!(status(Error::Parse("(".into())), EggStatus::Parse);
+        assert_eq!(status(Error::InvalidId(Id::from(3))), EggStatus::InvalidId);
+        // recoverable, unlike a panic
+        assert_eq!(status(Error::Cancelled), EggStatus::Failed);
+    }
+}
@@ -0,0 +1,513 @@
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+use crate::*;
+
+/// The eclasses that differ between two snapshots of a
+/// [`HistoryRecorder`].
+///
+/// See [`HistoryRecorder::diff`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EGraphDiff {
+    /// Eclasses in the later egraph that aren't in the earlier one.
+    pub added_classes: Vec<Id>,
+    /// Eclasses in the earlier egraph that aren't in the later one,
+    /// usually because they were merged into another eclass.
+    pub removed_classes: Vec<Id>,
+    /// Eclasses in both egraphs whose enodes, parents, or analysis data
+    /// changed.
+    pub changed_classes: Vec<Id>,
+}
+
+type Class<L, N> = Arc<EClass<L, <N as Analysis<L>>::Data>>;
+
+/// What changed in an egraph since a [`HistoryRecorder`] last recorded
+/// it, kept by the egraph once it has been recorded.
+///
+/// Union-find entries and enodes added since then aren't listed, since
+/// they are the tail of their vectors.
+#[derive(Debug, Clone)]
+pub(crate) struct ChangeLog<L> {
+    /// Eclasses that were added, changed, or removed.
+    pub(crate) classes: HashSet<Id>,
+    /// Union-find roots that were given a parent.
+    pub(crate) parents: HashSet<Id>,
+    /// Hashcons entries that were added, changed, or removed.
+    pub(crate) memo: HashSet<L>,
+    pub(crate) enode_aliases: Vec<ENodeId>,
+    /// Whether the egraph was cleared, so everything changed.
+    pub(crate) cleared: bool,
+}
+
+impl<L> Default for ChangeLog<L> {
+    fn default() -> Self {
+        Self {
+            classes: Default::default(),
+            parents: Default::default(),
+            memo: Default::default(),
+            enode_aliases: vec![],
+            cleared: false,
+        }
+    }
+}
+
+/// What changed in the egraph since the previous snapshot.
+struct Snapshot<L: Language, N: Analysis<L>> {
+    analysis: N,
+    /// Whether this snapshot holds the whole egraph, rather than what
+    /// changed, because it is the first or the egraph was cleared.
+    full: bool,
+    /// Eclasses that are new or changed, shared with later snapshots
+    /// until they change again.
+    classes: Vec<(Id, Class<L, N>)>,
+    removed_classes: Vec<Id>,
+    /// The union-find entries that changed or were added.
+    parents: Vec<(usize, Id)>,
+    memo: Vec<(L, Id)>,
+    removed_memo: Vec<L>,
+    /// The eclasses of the enodes added since the previous snapshot.
+    enode_classes: Vec<Id>,
+    enode_aliases: Vec<(ENodeId, ENodeId)>,
+}
+
+/// The state of an egraph, rebuilt by replaying snapshots.
+struct Replayed<L: Language, N: Analysis<L>> {
+    classes: HashMap<Id, Class<L, N>>,
+    parents: Vec<Id>,
+    memo: HashMap<L, Id>,
+    enode_classes: Vec<Id>,
+    enode_aliases: HashMap<ENodeId, ENodeId>,
+}
+
+impl<L: Language, N: Analysis<L>> Default for Replayed<L, N> {
+    fn default() -> Self {
+        Self {
+            classes: Default::default(),
+            parents: vec![],
+            memo: Default::default(),
+            enode_classes: vec![],
+            enode_aliases: Default::default(),
+        }
+    }
+}
+
+impl<L: Language, N: Analysis<L>> Replayed<L, N> {
+    fn apply(&mut self, snapshot: &Snapshot<L, N>) {
+        if snapshot.full {
+            *self = Self::default();
+        }
+        for id in &snapshot.removed_classes {
+            self.classes.remove(id);
+        }
+        for (id, class) in &snapshot.classes {
+            self.classes.insert(*id, class.clone());
+        }
+        for &(i, parent) in &snapshot.parents {
+            if i == self.parents.len() {
+                self.parents.push(parent);
+            } else {
+                self.parents[i] = parent;
+            }
+        }
+        for node in &snapshot.removed_memo {
+            self.memo.remove(node);
+        }
+        self.memo.extend(snapshot.memo.iter().cloned());
+        self.enode_classes.extend(&snapshot.enode_classes);
+        self.enode_aliases
+            .extend(snapshot.enode_aliases.iter().copied());
+    }
+}
+
+/**
+Records a snapshot of an [`EGraph`] after each iteration of a
+[`Runner`], so a run can be stepped through backwards.
+
+Storing a full copy of the egraph for every iteration takes a lot of
+memory, so each snapshot only stores what changed since the previous
+one: the changed entries of the union-find and the hashcons, and the
+eclasses that are new or changed.
+Unchanged eclasses share their storage with earlier snapshots, so the
+memory used grows with the size of the changes, not the size of the
+egraph.
+Once recorded, the egraph logs what it changes, so recording a snapshot
+takes time in the size of the changes too; only the first snapshot,
+and the first after [`clear_keep_capacity`](EGraph::clear_keep_capacity),
+copy the whole egraph.
+An egraph should only be recorded by one recorder, since recording
+takes the log.
+
+[`egraph_at`](HistoryRecorder::egraph_at) rebuilds the egraph
+at any snapshot, and [`diff`](HistoryRecorder::diff) compares two.
+Snapshots don't include explanations or
+[provenance](EGraph::with_provenance), so rebuilt egraphs have neither.
+
+Use [`Runner::with_history`] to have a [`Runner`] record one.
+
+# Example
+```
+use egg::{rewrite as rw, *};
+
+let rules: &[Rewrite<SymbolLang, ()>] = &[
+    rw!("commute-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+    rw!("add-0"; "(+ ?a 0)" => "?a"),
+];
+let runner = Runner::default()
+    .with_history()
+    .with_expr(&"(+ 0 (+ x 0))".parse().unwrap())
+    .run(rules);
+let history = runner.history.as_ref().unwrap();
+
+// one snapshot before the first iteration, and one after each
+assert_eq!(history.len(), runner.iterations.len() + 1);
+let start = history.egraph_at(0);
+assert_eq!(start.number_of_classes(), runner.iterations[0].egraph_classes);
+let end = history.egraph_at(history.len() - 1);
+assert_eq!(end.number_of_classes(), runner.egraph.number_of_classes());
+
+// the first iteration added enodes to existing eclasses
+let diff = history.diff(0, 1);
+assert!(!diff.changed_classes.is_empty());
+```
+**/
+pub struct HistoryRecorder<L: Language, N: Analysis<L>> {
+    snapshots: Vec<Snapshot<L, N>>,
+    /// The number of union-find entries recorded so far.
+    parents_len: usize,
+    /// The number of enodes recorded so far.
+    enode_classes_len: usize,
+    /// Records a snapshot; a function pointer so that [`Runner`] can
+    /// call it without the bounds that recording needs.
+    record_fn: fn(&mut Self, &mut EGraph<L, N>),
+}
+
+impl<L: Language, N: Analysis<L>> Debug for HistoryRecorder<L, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HistoryRecorder")
+            .field("snapshots", &self.snapshots.len())
+            .finish()
+    }
+}
+
+impl<L, N> Default for HistoryRecorder<L, N>
+where
+    L: Language,
+    N: Analysis<L> + Clone,
+    N::Data: Clone + PartialEq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L, N> HistoryRecorder<L, N>
+where
+    L: Language,
+    N: Analysis<L> + Clone,
+    N::Data: Clone + PartialEq,
+{
+    /// Create an empty `HistoryRecorder`.
+    pub fn new() -> Self {
+        Self {
+            snapshots: vec![],
+            parents_len: 0,
+            enode_classes_len: 0,
+            record_fn: Self::record_snapshot,
+        }
+    }
+
+    fn record_snapshot(&mut self, egraph: &mut EGraph<L, N>) {
+        let log = match egraph.change_log.replace(ChangeLog::default()) {
+            Some(log) if !log.cleared => log,
+            _ => return self.record_full(egraph),
+        };
+        let mut snapshot = Snapshot {
+            analysis: egraph.analysis.clone(),
+            full: false,
+            classes: vec![],
+            removed_classes: vec![],
+            parents: vec![],
+            memo: vec![],
+            removed_memo: vec![],
+            enode_classes: egraph.enode_classes[self.enode_classes_len..].to_vec(),
+            enode_aliases: vec![],
+        };
+
+        for id in log.classes {
+            match egraph.classes.get(&id) {
+                Some(class) => snapshot.classes.push((id, Arc::new(class.clone()))),
+                None => snapshot.removed_classes.push(id),
+            }
+        }
+        snapshot.classes.sort_unstable_by_key(|(id, _)| *id);
+        snapshot.removed_classes.sort_unstable();
+
+        let parents = egraph.unionfind.parents();
+        let changed = log.parents.into_iter().map(usize::from);
+        snapshot.parents = changed
+            .filter(|&i| i < self.parents_len)
+            .chain(self.parents_len..parents.len())
+            .map(|i| (i, parents[i]))
+            .collect();
+
+        for node in log.memo {
+            match egraph.memo.get(&node) {
+                Some(&id) => snapshot.memo.push((node, id)),
+                None => snapshot.removed_memo.push(node),
+            }
+        }
+
+        // aliases can be forgotten again by rolling back
+        snapshot.enode_aliases = log
+            .enode_aliases
+            .into_iter()
+            .filter_map(|alias| Some((alias, *egraph.enode_aliases.get(&alias)?)))
+            .collect();
+
+        self.push(snapshot, egraph);
+    }
+
+    /// Record the whole egraph, and have it log its changes from now on.
+    fn record_full(&mut self, egraph: &EGraph<L, N>) {
+        let mut classes: Vec<_> = egraph
+            .classes()
+            .map(|class| (class.id, Arc::new(class.clone())))
+            .collect();
+        classes.sort_unstable_by_key(|(id, _)| *id);
+        let parents = egraph.unionfind.parents();
+        let snapshot = Snapshot {
+            analysis: egraph.analysis.clone(),
+            full: true,
+            classes,
+            removed_classes: vec![],
+            parents: parents.iter().copied().enumerate().collect(),
+            memo: egraph.memo.iter().map(|(n, &id)| (n.clone(), id)).collect(),
+            removed_memo: vec![],
+            enode_classes: egraph.enode_classes.clone(),
+            enode_aliases: egraph.enode_aliases.iter().map(|(&a, &k)| (a, k)).collect(),
+        };
+        self.push(snapshot, egraph);
+    }
+
+    fn push(&mut self, snapshot: Snapshot<L, N>, egraph: &EGraph<L, N>) {
+        self.parents_len = egraph.unionfind.parents().len();
+        self.enode_classes_len = egraph.enode_classes.len();
+        self.snapshots.push(snapshot);
+    }
+
+    /// Rebuild the egraph as it was at the given snapshot.
+    ///
+    /// Snapshot 0 is the egraph before the first iteration, and
+    /// snapshot `i` is the egraph after `i` iterations.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no such snapshot.
+    pub fn egraph_at(&self, snapshot: usize) -> EGraph<L, N> {
+        let replayed = self.replay(snapshot);
+        let mut egraph = EGraph::new(self.snapshots[snapshot].analysis.clone());
+        for class in replayed.classes.values() {
+            for node in &class.nodes {
+                #[allow(clippy::mem_discriminant_non_enum)]
+                egraph
+                    .classes_by_op
+                    .entry(std::mem::discriminant(node))
+                    .or_default()
+                    .insert(class.id);
+            }
+        }
+        egraph.classes = replayed
+            .classes
+            .into_iter()
+            .map(|(id, class)| (id, EClass::clone(&class)))
+            .collect();
+        egraph.unionfind = UnionFind::from_parents(replayed.parents);
+        egraph.memo = replayed.memo;
+        egraph.enode_classes = replayed.enode_classes;
+        egraph.enode_aliases = replayed.enode_aliases;
+        egraph.clean = true;
+        egraph
+    }
+
+    /// Compare the eclasses of two snapshots, from `from` to `to`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either snapshot doesn't exist.
+    pub fn diff(&self, from: usize, to: usize) -> EGraphDiff {
+        let from = self.replay(from).classes;
+        let to = self.replay(to).classes;
+        let mut diff = EGraphDiff::default();
+        for (id, class) in &to {
+            match from.get(id) {
+                None => diff.added_classes.push(*id),
+                Some(old) if !Arc::ptr_eq(old, class) => {
+                    let same = old.nodes == class.nodes
+                        && old.parents == class.parents
+                        && old.data == class.data;
+                    if !same {
+                        diff.changed_classes.push(*id)
+                    }
+                }
+                Some(_) => (),
+            }
+        }
+        diff.removed_classes = from
+            .keys()
+            .filter(|id| !to.contains_key(id))
+            .copied()
+            .collect();
+        diff.added_classes.sort_unstable();
+        diff.removed_classes.sort_unstable();
+        diff.changed_classes.sort_unstable();
+        diff
+    }
+}
+
+impl<L: Language, N: Analysis<L>> HistoryRecorder<L, N> {
+    /// Record a snapshot of the given egraph, which should be clean.
+    ///
+    /// The egraph starts logging its changes for the next snapshot, if it
+    /// wasn't already.
+    pub fn record(&mut self, egraph: &mut EGraph<L, N>) {
+        (self.record_fn)(self, egraph)
+    }
+
+    /// Returns the number of snapshots recorded.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Returns `true` if no snapshots have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Returns roughly how much a snapshot stores: the number of enodes
+    /// and parents in its new or changed eclasses, plus its union-find
+    /// and hashcons entries.
+    ///
+    /// Compare with [`full_size`](HistoryRecorder::full_size).
+    pub fn snapshot_size(&self, snapshot: usize) -> usize {
+        let s = &self.snapshots[snapshot];
+        let classes: usize = s.classes.iter().map(|(_, c)| class_size(c)).sum();
+        classes + s.removed_classes.len() + s.parents.len() + s.memo.len() + s.removed_memo.len()
+    }
+
+    /// Returns how much a full copy of the egraph at a snapshot would
+    /// store, measured like [`snapshot_size`](HistoryRecorder::snapshot_size).
+    pub fn full_size(&self, snapshot: usize) -> usize {
+        let replayed = self.replay(snapshot);
+        let classes: usize = replayed.classes.values().map(|c| class_size(c)).sum();
+        classes + replayed.parents.len() + replayed.memo.len()
+    }
+
+    fn replay(&self, snapshot: usize) -> Replayed<L, N> {
+        assert!(
+            snapshot < self.snapshots.len(),
+            "No snapshot {}, there are {}",
+            snapshot,
+            self.snapshots.len()
+        );
+        let mut replayed = Replayed::default();
+        for s in &self.snapshots[..=snapshot] {
+            replayed.apply(s);
+        }
+        replayed
+    }
+}
+
+fn class_size<L, D>(class: &EClass<L, D>) -> usize {
+    class.nodes.len() + class.parents.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    #[test]
+    fn history_rebuilds_each_iteration() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rw!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rw!("assoc"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+        ];
+        let expr: RecExpr<S> = "(+ a (+ b (+ c d)))".parse().unwrap();
+
+        // record by hand, keeping a full copy of each iteration to compare
+        let mut recorder = HistoryRecorder::new();
+        let mut copies = vec![];
+        let mut egraph = EGraph::default();
+        egraph.add_expr(&expr);
+        egraph.rebuild();
+        for _ in 0..4 {
+            recorder.record(&mut egraph);
+            copies.push(egraph.clone());
+            for rule in &rules {
+                rule.run(&mut egraph);
+            }
+            egraph.rebuild();
+        }
+        assert_eq!(recorder.len(), 4);
+
+        for (i, copy) in copies.iter().enumerate() {
+            let mut rebuilt = recorder.egraph_at(i);
+            assert_eq!(rebuilt.number_of_classes(), copy.number_of_classes());
+            assert_eq!(rebuilt.total_size(), copy.total_size());
+            for class in copy.classes() {
+                assert_eq!(rebuilt[class.id].nodes, class.nodes);
+                assert_eq!(rebuilt.find(class.id), class.id);
+            }
+            assert_eq!(rebuilt.lookup_expr(&expr), copy.lookup_expr(&expr));
+
+            // the rebuilt egraph can keep going
+            for rule in &rules {
+                rule.run(&mut rebuilt);
+            }
+            rebuilt.rebuild();
+        }
+
+        let ids = |egraph: &EGraph<S, ()>| -> Vec<Id> {
+            let mut ids: Vec<Id> = egraph.classes().map(|c| c.id).collect();
+            ids.sort();
+            ids
+        };
+        let only_in = |a: &EGraph<S, ()>, b: &EGraph<S, ()>| -> Vec<Id> {
+            let b = ids(b);
+            ids(a).into_iter().filter(|id| !b.contains(id)).collect()
+        };
+        let diff = recorder.diff(0, 1);
+        assert!(!diff.added_classes.is_empty());
+        assert_eq!(diff.added_classes, only_in(&copies[1], &copies[0]));
+        assert_eq!(diff.removed_classes, only_in(&copies[0], &copies[1]));
+        assert_eq!(recorder.diff(2, 2), EGraphDiff::default());
+    }
+
+    #[test]
+    fn history_follows_removed_enodes_and_clearing() {
+        let mut recorder = HistoryRecorder::new();
+        let mut egraph = EGraph::<S, ()>::default();
+        let sum = egraph.add_expr(&"(+ 1 1)".parse().unwrap());
+        let two = egraph.add(S::leaf("2"));
+        egraph.union(sum, two);
+        egraph.rebuild();
+        recorder.record(&mut egraph);
+
+        egraph.retain_nodes(sum, |n| n.is_leaf());
+        egraph.rebuild();
+        recorder.record(&mut egraph);
+        // only the eclasses that lost an enode or a parent were stored
+        assert_eq!(recorder.snapshot_size(1), 2 + 1);
+        let rebuilt = recorder.egraph_at(1);
+        assert_eq!(rebuilt.lookup_expr(&"(+ 1 1)".parse().unwrap()), None);
+        assert_eq!(rebuilt[sum].nodes, vec![S::leaf("2")]);
+
+        egraph.clear_keep_capacity();
+        let x = egraph.add(S::leaf("x"));
+        egraph.rebuild();
+        recorder.record(&mut egraph);
+        let rebuilt = recorder.egraph_at(2);
+        assert_eq!(rebuilt.number_of_classes(), 1);
+        assert_eq!(rebuilt.lookup(S::leaf("x")), Some(x));
+        assert_eq!(recorder.egraph_at(1).total_size(), 2);
+    }
+}
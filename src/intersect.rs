@@ -0,0 +1,205 @@
+use crate::*;
+
+/// The intersection of two egraphs, from [`EGraph::intersect`].
+#[derive(Debug, Clone)]
+pub struct Intersection<L: Language, N: Analysis<L>> {
+    /// The egraph of the terms and equivalences the two egraphs share.
+    pub egraph: EGraph<L, N>,
+    /// The eclass of `egraph` of each pair of eclasses that share a
+    /// term.
+    classes: HashMap<(Id, Id), Id>,
+}
+
+impl<L: Language, N: Analysis<L>> Intersection<L, N> {
+    /// The eclass of the terms that are in both eclass `a` of the first
+    /// egraph and eclass `b` of the second, if they share any.
+    ///
+    /// `a` and `b` must be canonical in their egraphs, as found by
+    /// [`EGraph::find`].
+    pub fn class(&self, a: Id, b: Id) -> Option<Id> {
+        self.classes.get(&(a, b)).map(|&id| self.egraph.find(id))
+    }
+
+    /// Each pair of an eclass of the first egraph and one of the second
+    /// that share a term, with the eclass of `egraph` of those terms.
+    pub fn classes(&self) -> impl Iterator<Item = ((Id, Id), Id)> + '_ {
+        self.classes
+            .iter()
+            .map(move |(&pair, &id)| (pair, self.egraph.find(id)))
+    }
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+    /// Intersect this egraph with `other`: make an egraph of the terms
+    /// in both, where two terms are equivalent only if they are in both.
+    ///
+    /// This is the product construction: each eclass of the result is a
+    /// pair of an eclass of this egraph and one of `other` that share a
+    /// term, and has the enodes whose children are such pairs.
+    /// The pairs are found bottom up, in passes over this egraph until
+    /// one finds no new enode, rather than by recursion, so cycles like
+    /// `x = (+ x 0)` are fine.
+    /// The result uses `analysis`, and is [rebuilt](EGraph::rebuild).
+    /// Use [`Intersection::class`] to find the eclass of a pair of
+    /// eclasses, like the roots of the two egraphs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either egraph isn't [clean](EGraph::rebuild).
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let egraph = |equal: &[(&str, &str)]| {
+    ///     let mut egraph = EGraph::<S, ()>::default();
+    ///     for (a, b) in equal {
+    ///         let a = egraph.add_expr(&a.parse().unwrap());
+    ///         let b = egraph.add_expr(&b.parse().unwrap());
+    ///         egraph.union(a, b);
+    ///     }
+    ///     egraph.rebuild();
+    ///     egraph
+    /// };
+    /// let left = egraph(&[("a", "b"), ("b", "c")]);
+    /// let right = egraph(&[("a", "c"), ("b", "d")]);
+    /// let both = left.intersect(&right, ());
+    ///
+    /// let lookup = |s: &str| both.egraph.lookup_expr(&s.parse().unwrap());
+    /// assert_eq!(lookup("a"), lookup("c"));
+    /// assert_ne!(lookup("a"), lookup("b"));
+    /// assert_eq!(lookup("d"), None);
+    /// ```
+    pub fn intersect<M: Analysis<L>>(
+        &self,
+        other: &EGraph<L, M>,
+        analysis: N,
+    ) -> Intersection<L, N> {
+        assert!(
+            self.clean && other.clean,
+            "Tried to intersect a dirty e-graph! Rebuild first."
+        );
+        // keyed by the enode with its children zeroed, so each bucket only
+        // has the enodes that match it
+        let zeroed = |node: &L| node.clone().map_children(|_| Id::from(0));
+        let mut by_op: HashMap<L, Vec<(Id, &L)>> = Default::default();
+        for class in other.classes() {
+            for node in class.iter() {
+                by_op
+                    .entry(zeroed(node))
+                    .or_default()
+                    .push((class.id, node));
+            }
+        }
+
+        let mut egraph = EGraph::new(analysis);
+        let mut classes: HashMap<(Id, Id), Id> = Default::default();
+        loop {
+            let mut changed = false;
+            for class in self.classes() {
+                for node in class.iter() {
+                    let candidates = match by_op.get(&zeroed(node)) {
+                        Some(candidates) => candidates,
+                        None => continue,
+                    };
+                    for &(other_id, other_node) in candidates {
+                        if !node.matches(other_node) {
+                            continue;
+                        }
+                        // the product enode, if all its children's pairs are known
+                        let children: Option<Vec<Id>> = node
+                            .children()
+                            .iter()
+                            .zip(other_node.children())
+                            .map(|(&a, &b)| classes.get(&(a, b)).copied())
+                            .collect();
+                        let mut children = match children {
+                            Some(children) => children.into_iter(),
+                            None => continue,
+                        };
+                        let product = node.clone().map_children(|_| children.next().unwrap());
+
+                        let id = match egraph.lookup(product.clone()) {
+                            Some(id) => id,
+                            None => {
+                                changed = true;
+                                egraph.add(product)
+                            }
+                        };
+                        match classes.get(&(class.id, other_id)) {
+                            Some(&pair) => {
+                                egraph.union(pair, id);
+                            }
+                            None => {
+                                changed = true;
+                                classes.insert((class.id, other_id), id);
+                            }
+                        }
+                    }
+                }
+            }
+            // so the next pass looks up canonical enodes
+            egraph.rebuild();
+            if !changed {
+                break;
+            }
+        }
+
+        Intersection { egraph, classes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    fn saturate(expr: &str, rules: &[Rewrite<S, ()>]) -> (EGraph<S, ()>, Id) {
+        let runner = Runner::default()
+            .with_expr(&expr.parse().unwrap())
+            .run(rules);
+        let root = runner.egraph.find(runner.roots[0]);
+        (runner.egraph, root)
+    }
+
+    #[test]
+    fn common_equivalences() {
+        let expr = "(f (+ x 0) (* x 1) x)";
+        // both prove (+ x 0) = (* x 1), but only the left one proves they're x
+        let (left, left_root) = saturate(
+            expr,
+            &[
+                rw!("add-0"; "(+ ?a 0)" => "?a"),
+                rw!("mul-1"; "(* ?a 1)" => "?a"),
+            ],
+        );
+        let (right, right_root) = saturate(expr, &[rw!("add-mul"; "(+ ?a 0)" => "(* ?a 1)")]);
+        let both = left.intersect(&right, ());
+        both.egraph.check_invariants();
+
+        let lookup = |s: &str| both.egraph.lookup_expr(&s.parse().unwrap()).unwrap();
+        assert_eq!(lookup("(+ x 0)"), lookup("(* x 1)"));
+        assert_ne!(lookup("(+ x 0)"), lookup("x"));
+        assert_eq!(both.class(left_root, right_root), Some(lookup(expr)));
+
+        let x = |egraph: &EGraph<S, ()>| egraph.lookup_expr(&"x".parse().unwrap()).unwrap();
+        assert_eq!(both.class(x(&left), x(&right)), Some(lookup("x")));
+        // x of the left and (+ x 0) of the right share (+ x 0)
+        let add = right.lookup_expr(&"(+ x 0)".parse().unwrap()).unwrap();
+        assert_eq!(both.class(x(&left), add), Some(lookup("(+ x 0)")));
+        assert_eq!(both.classes().count(), both.egraph.number_of_classes());
+    }
+
+    #[test]
+    fn cyclic_classes() {
+        // x = (+ x 0) on both sides, so each has infinitely many terms
+        let rules = &[rw!("add-0"; "(+ ?a 0)" => "?a")];
+        let (left, _) = saturate("(+ x 0)", rules);
+        let (right, _) = saturate("(g (+ x 0))", rules);
+        let both = left.intersect(&right, ());
+
+        let lookup = |s: &str| both.egraph.lookup_expr(&s.parse().unwrap());
+        let x = lookup("x").unwrap();
+        assert_eq!(lookup("(+ (+ x 0) 0)"), Some(x));
+        assert_eq!(lookup("(g x)"), None);
+        assert_eq!(both.egraph.number_of_classes(), 2);
+    }
+}
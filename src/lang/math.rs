@@ -0,0 +1,433 @@
+/*!
+Arithmetic over the reals, with derivatives and integrals.
+
+[`Math`] has the usual operators, `d` for the derivative of its second
+child by its first, and `i` for the integral of its first child by its
+second.
+[`ConstantFold`] folds `+`, `-`, `*` and `/` of constants, and
+[`rules`] is a rule set for simplifying, differentiating and
+integrating.
+
+# Example
+
+```
+use egg::{lang::math::*, *};
+
+let start: RecExpr<Math> = "(d x (+ 1 (* 2 x)))".parse().unwrap();
+let runner = Runner::default().with_expr(&start).run(&rules());
+let extractor = Extractor::new(&runner.egraph, MathCostFn);
+let (_, best) = extractor.find_best(runner.roots[0]);
+assert_eq!(best.to_string(), "2");
+```
+!*/
+
+use crate::{rewrite as rw, *};
+
+pub use ordered_float::NotNan;
+
+/// An [`EGraph`](crate::EGraph) of [`Math`] with [`ConstantFold`].
+pub type EGraph = crate::EGraph<Math, ConstantFold>;
+/// A [`Rewrite`](crate::Rewrite) of [`Math`] with [`ConstantFold`].
+pub type Rewrite = crate::Rewrite<Math, ConstantFold>;
+
+/// The constants of [`Math`].
+pub type Constant = NotNan<f64>;
+
+define_language! {
+    /// Arithmetic over the reals.
+    pub enum Math {
+        "d" = Diff([Id; 2]),
+        "i" = Integral([Id; 2]),
+
+        "+" = Add([Id; 2]),
+        "-" = Sub([Id; 2]),
+        "*" = Mul([Id; 2]),
+        "/" = Div([Id; 2]),
+        "pow" = Pow([Id; 2]),
+        "ln" = Ln(Id),
+        "sqrt" = Sqrt(Id),
+
+        "sin" = Sin(Id),
+        "cos" = Cos(Id),
+
+        Constant(Constant),
+        Symbol(Symbol),
+    }
+}
+
+/// A [`CostFunction`] like [`AstSize`], but where `d` and `i` cost 100,
+/// so extraction gets rid of them if any term can.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MathCostFn;
+
+impl CostFunction<Math> for MathCostFn {
+    type Cost = usize;
+    fn cost<C>(&mut self, enode: &Math, mut costs: C) -> Self::Cost
+    where
+        C: FnMut(Id) -> Self::Cost,
+    {
+        let op_cost = match enode {
+            Math::Diff(..) | Math::Integral(..) => 100,
+            _ => 1,
+        };
+        enode.fold(op_cost, |sum, i| sum + costs(i))
+    }
+}
+
+/// An [`Analysis`] that folds `+`, `-`, `*` and `/` of constants.
+///
+/// The data of an eclass is the constant it folds to, if any, with the
+/// term it folded, like `(+ 1 2)`, for explanations.
+/// An eclass that folds gets the constant's enode, and loses its
+/// other enodes but its leaves.
+/// Division by zero and results that aren't finite aren't folded.
+///
+/// [`distinct`](Analysis::distinct) is true for eclasses that fold to
+/// different constants.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstantFold;
+
+impl Analysis<Math> for ConstantFold {
+    type Data = Option<(Constant, PatternAst<Math>)>;
+
+    fn make(egraph: &EGraph, enode: &Math) -> Self::Data {
+        let x = |i: &Id| egraph[*i].data.as_ref().map(|d| d.0.into_inner());
+        let (a, b) = match enode {
+            Math::Constant(c) => return Some((*c, c.to_string().parse().unwrap())),
+            Math::Add([a, b]) | Math::Sub([a, b]) | Math::Mul([a, b]) | Math::Div([a, b]) => {
+                (x(a)?, x(b)?)
+            }
+            _ => return None,
+        };
+        let c = match enode {
+            Math::Add(_) => a + b,
+            Math::Sub(_) => a - b,
+            Math::Mul(_) => a * b,
+            Math::Div(_) if b != 0.0 => a / b,
+            _ => return None,
+        };
+        if !c.is_finite() {
+            return None;
+        }
+        let folded = format!("({} {} {})", enode, a, b).parse().unwrap();
+        Some((NotNan::new(c).unwrap(), folded))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the eclasses fold to different constants, since only
+    /// an unsound rule or union could make them equal.
+    fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge {
+        let from = match from {
+            Some(from) => from,
+            None => return DidMerge(false, to.is_some()),
+        };
+        match to {
+            Some((c, _)) => {
+                assert_eq!(*c, from.0, "Merged eclasses with different constants");
+                DidMerge(false, false)
+            }
+            None => {
+                *to = Some(from);
+                DidMerge(true, false)
+            }
+        }
+    }
+
+    fn modify(egraph: &mut EGraph, id: Id) {
+        let (c, folded) = match &egraph[id].data {
+            Some(data) => data.clone(),
+            None => return,
+        };
+        if egraph.are_explanations_enabled() {
+            egraph.union_instantiations(
+                &folded,
+                &c.to_string().parse().unwrap(),
+                &Default::default(),
+                "constant_fold",
+            );
+        } else {
+            let added = egraph.add(Math::Constant(c));
+            egraph.union(id, added);
+        }
+        // a frozen or sort-checked eclass may have kept out the constant,
+        // and then it has no leaf to keep
+        if egraph.lookup(Math::Constant(c)) == Some(egraph.find(id)) {
+            egraph.retain_nodes(id, |n| n.is_leaf());
+        }
+    }
+
+    fn distinct(a: &Self::Data, b: &Self::Data) -> bool {
+        matches!((a, b), (Some((x, _)), Some((y, _))) if x != y)
+    }
+}
+
+fn constant(egraph: &dyn EGraphView<Math, ConstantFold>, id: Id) -> Option<f64> {
+    egraph.data(id).as_ref().map(|d| d.0.into_inner())
+}
+
+/// The eclass of `var` folds to a constant satisfying `predicate`.
+fn is_const_where(
+    var: &str,
+    predicate: impl Fn(f64) -> bool,
+) -> impl Fn(&dyn EGraphView<Math, ConstantFold>, Id, &Subst) -> bool {
+    let var = var.parse().unwrap();
+    move |egraph, _, subst| constant(egraph, subst[var]).map_or(false, &predicate)
+}
+
+fn is_const(var: &str) -> impl Fn(&dyn EGraphView<Math, ConstantFold>, Id, &Subst) -> bool {
+    is_const_where(var, |_| true)
+}
+
+fn is_nonzero(var: &str) -> impl Fn(&dyn EGraphView<Math, ConstantFold>, Id, &Subst) -> bool {
+    is_const_where(var, |c| c != 0.0)
+}
+
+fn is_positive(var: &str) -> impl Fn(&dyn EGraphView<Math, ConstantFold>, Id, &Subst) -> bool {
+    is_const_where(var, |c| c > 0.0)
+}
+
+fn is_sym(var: &str) -> impl Fn(&dyn EGraphView<Math, ConstantFold>, Id, &Subst) -> bool {
+    let var = var.parse().unwrap();
+    move |egraph, _, subst| {
+        egraph
+            .class(subst[var])
+            .nodes
+            .iter()
+            .any(|n| matches!(n, Math::Symbol(..)))
+    }
+}
+
+fn is_const_or_distinct_var(
+    v: &str,
+    w: &str,
+) -> impl Fn(&dyn EGraphView<Math, ConstantFold>, Id, &Subst) -> bool {
+    let v = v.parse().unwrap();
+    let w = w.parse().unwrap();
+    move |egraph, _, subst| {
+        egraph.find(subst[v]) != egraph.find(subst[w])
+            && (egraph.data(subst[v]).is_some()
+                || egraph
+                    .class(subst[v])
+                    .nodes
+                    .iter()
+                    .any(|n| matches!(n, Math::Symbol(..))))
+    }
+}
+
+/// A rule set for simplifying, differentiating and integrating
+/// [`Math`] terms.
+///
+/// `/`, `ln`, `sqrt` and `pow` are partial, so a rule that would
+/// equate a term with one that is defined where it isn't, like
+/// `(/ ?a ?a)` with `1`, only applies when [`ConstantFold`] shows the
+/// term is defined: here, that the divisor or base is a nonzero or
+/// positive constant.
+/// Rules whose sides are undefined at the same points, like
+/// `(/ ?a ?b)` and `(* ?a (pow ?b -1))`, apply unconditionally.
+/// So `(/ x x)` isn't simplified to `1`; add a rule with that
+/// assumption if `x` is known to be nonzero.
+///
+/// The exceptions are `zero-mul` and `cancel-sub`, which simplify
+/// `(* ?a 0)` and `(- ?a ?a)` to `0` even where `?a` is undefined, like
+/// `(* (/ 1 0) 0)`: they assume `?a` is defined, as simplifiers usually
+/// do.
+#[rustfmt::skip]
+pub fn rules() -> Vec<Rewrite> { vec![
+    rw!("comm-add";  "(+ ?a ?b)"        => "(+ ?b ?a)"),
+    rw!("comm-mul";  "(* ?a ?b)"        => "(* ?b ?a)"),
+    rw!("assoc-add"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+    rw!("assoc-mul"; "(* ?a (* ?b ?c))" => "(* (* ?a ?b) ?c)"),
+
+    rw!("sub-canon"; "(- ?a ?b)" => "(+ ?a (* -1 ?b))"),
+    // both sides are undefined when ?b is 0
+    rw!("div-canon"; "(/ ?a ?b)" => "(* ?a (pow ?b -1))"),
+
+    rw!("zero-add"; "(+ ?a 0)" => "?a"),
+    // assumes ?a is defined
+    rw!("zero-mul"; "(* ?a 0)" => "0"),
+    rw!("one-mul";  "(* ?a 1)" => "?a"),
+
+    rw!("add-zero"; "?a" => "(+ ?a 0)"),
+    rw!("mul-one";  "?a" => "(* ?a 1)"),
+
+    // assumes ?a is defined
+    rw!("cancel-sub"; "(- ?a ?a)" => "0"),
+    rw!("cancel-div"; "(/ ?a ?a)" => "1" if is_nonzero("?a")),
+
+    rw!("distribute"; "(* ?a (+ ?b ?c))"        => "(+ (* ?a ?b) (* ?a ?c))"),
+    rw!("factor"    ; "(+ (* ?a ?b) (* ?a ?c))" => "(* ?a (+ ?b ?c))"),
+
+    // (* (pow 0 -1) (pow 0 1)) is undefined, (pow 0 0) isn't
+    rw!("pow-mul"; "(* (pow ?a ?b) (pow ?a ?c))" => "(pow ?a (+ ?b ?c))" if is_positive("?a")),
+    rw!("pow0"; "(pow ?x 0)" => "1" if is_nonzero("?x")),
+    rw!("pow1"; "(pow ?x 1)" => "?x"),
+    rw!("pow2"; "(pow ?x 2)" => "(* ?x ?x)"),
+    rw!("pow-recip"; "(pow ?x -1)" => "(/ 1 ?x)"),
+    rw!("recip-mul-div"; "(* ?x (/ 1 ?x))" => "1" if is_nonzero("?x")),
+
+    rw!("d-variable"; "(d ?x ?x)" => "1" if is_sym("?x")),
+    rw!("d-constant"; "(d ?x ?c)" => "0" if is_sym("?x") if is_const_or_distinct_var("?c", "?x")),
+
+    rw!("d-add"; "(d ?x (+ ?a ?b))" => "(+ (d ?x ?a) (d ?x ?b))"),
+    rw!("d-mul"; "(d ?x (* ?a ?b))" => "(+ (* ?a (d ?x ?b)) (* ?b (d ?x ?a)))"),
+
+    rw!("d-sin"; "(d ?x (sin ?x))" => "(cos ?x)"),
+    rw!("d-cos"; "(d ?x (cos ?x))" => "(* -1 (sin ?x))"),
+
+    rw!("d-ln"; "(d ?x (ln ?x))" => "(/ 1 ?x)"),
+
+    rw!("d-power-const";
+        "(d ?x (pow ?f ?c))" =>
+        "(* ?c (* (pow ?f (- ?c 1)) (d ?x ?f)))"
+        if is_const("?c")
+    ),
+    // this divides by and takes the log of ?f
+    rw!("d-power";
+        "(d ?x (pow ?f ?g))" =>
+        "(* (pow ?f ?g)
+            (+ (* (d ?x ?f)
+                  (/ ?g ?f))
+               (* (d ?x ?g)
+                  (ln ?f))))"
+        if is_positive("?f")
+    ),
+
+    rw!("i-one"; "(i 1 ?x)" => "?x"),
+    // (+ ?c 1) is a divisor
+    rw!("i-power-const"; "(i (pow ?x ?c) ?x)" =>
+        "(/ (pow ?x (+ ?c 1)) (+ ?c 1))" if is_const_where("?c", |c| c + 1.0 != 0.0)),
+    rw!("i-cos"; "(i (cos ?x) ?x)" => "(sin ?x)"),
+    rw!("i-sin"; "(i (sin ?x) ?x)" => "(* -1 (cos ?x))"),
+    rw!("i-sum"; "(i (+ ?f ?g) ?x)" => "(+ (i ?f ?x) (i ?g ?x))"),
+    rw!("i-dif"; "(i (- ?f ?g) ?x)" => "(- (i ?f ?x) (i ?g ?x))"),
+    rw!("i-parts"; "(i (* ?a ?b) ?x)" =>
+        "(- (* ?a (i ?b ?x)) (i (* (d ?x ?a) (i ?b ?x)) ?x))"),
+]}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the rules prove `start` equal to `goal`, within limits
+    /// small enough that failing to takes little time.
+    fn proves(start: &str, goal: &str) -> bool {
+        let start: RecExpr<Math> = start.parse().unwrap();
+        let goal: RecExpr<Math> = goal.parse().unwrap();
+        let runner = Runner::default()
+            .with_iter_limit(12)
+            .with_node_limit(10_000)
+            .with_time_limit(std::time::Duration::from_secs(1))
+            .with_expr(&start)
+            .with_goals(&[goal])
+            .run(&rules());
+        matches!(runner.stop_reason, Some(StopReason::GoalsReached))
+    }
+
+    #[test]
+    fn constants_fold() {
+        let mut egraph = EGraph::default();
+        let id = egraph.add_expr(&"(- (+ 1 (* 2 3)) (/ 1 4))".parse().unwrap());
+        egraph.rebuild();
+        assert_eq!(constant(&egraph, id), Some(6.75));
+        // only the constant is left
+        assert_eq!(egraph[id].nodes.len(), 1);
+
+        for unfoldable in &["(/ 1 0)", "(/ 0 0)", "(* 1e300 1e300)", "(+ x 1)"] {
+            let id = egraph.add_expr(&unfoldable.parse().unwrap());
+            egraph.rebuild();
+            assert_eq!(constant(&egraph, id), None, "{}", unfoldable);
+        }
+
+        let (one, two) = (
+            egraph.add_expr(&"1".parse().unwrap()),
+            egraph.add_expr(&"2".parse().unwrap()),
+        );
+        assert!(ConstantFold::distinct(&egraph[one].data, &egraph[two].data));
+    }
+
+    #[test]
+    fn frozen_classes_keep_their_nodes() {
+        let mut egraph = EGraph::default();
+        let sum = egraph.add_expr(&"(+ a 2)".parse().unwrap());
+        egraph.freeze(sum);
+        let (a, one) = (
+            egraph.add_expr(&"a".parse().unwrap()),
+            egraph.add_expr(&"1".parse().unwrap()),
+        );
+        egraph.union(a, one);
+        egraph.rebuild();
+        // the sum folds to 3, but can't be unioned with it
+        assert_eq!(constant(&egraph, sum), Some(3.0));
+        assert_eq!(egraph[sum].nodes.len(), 1);
+        assert_ne!(
+            egraph.lookup_expr(&"3".parse().unwrap()),
+            Some(egraph.find(sum))
+        );
+    }
+
+    #[test]
+    fn merges_report_changes() {
+        let mut egraph = EGraph::default();
+        let one = egraph.add_expr(&"1".parse().unwrap());
+        let one = egraph[one].data.clone();
+        let merge = |to: &mut Option<_>, from: Option<_>| {
+            let DidMerge(a, b) = ConstantFold.merge(to, from);
+            (a, b)
+        };
+
+        let mut to = None;
+        assert_eq!(merge(&mut to, None), (false, false));
+        assert_eq!(merge(&mut to, one.clone()), (true, false));
+        assert_eq!(to.as_ref().map(|d| d.0), Some(NotNan::new(1.0).unwrap()));
+        assert_eq!(merge(&mut to, one), (false, false));
+        assert_eq!(merge(&mut to, None), (false, true));
+    }
+
+    #[test]
+    #[should_panic(expected = "different constants")]
+    fn merging_different_constants_panics() {
+        let mut egraph = EGraph::default();
+        let one = egraph.add_expr(&"1".parse().unwrap());
+        let two = egraph.add_expr(&"2".parse().unwrap());
+        egraph.union(one, two);
+    }
+
+    #[test]
+    fn simplifies() {
+        assert!(proves("(+ x (+ x (+ x x)))", "(* 4 x)"));
+        assert!(proves("(* (pow 2 x) (pow 2 y))", "(pow 2 (+ x y))"));
+        assert!(proves("(+ 1 (- a (* (- 2 1) a)))", "1"));
+        assert!(proves("(/ 3 3)", "1"));
+    }
+
+    #[test]
+    fn differentiates_and_integrates() {
+        assert!(proves("(d x x)", "1"));
+        assert!(proves("(d x y)", "0"));
+        assert!(proves("(d x (+ 1 (* 2 x)))", "2"));
+        assert!(proves("(d x (ln x))", "(/ 1 x)"));
+        assert!(proves("(d x (pow x 3))", "(* 3 (pow x 2))"));
+        assert!(proves("(i (cos x) x)", "(sin x)"));
+        assert!(proves("(i (pow x 1) x)", "(/ (pow x 2) 2)"));
+    }
+
+    #[test]
+    fn division_rules_are_guarded() {
+        // x may be 0
+        assert!(!proves("(/ x x)", "1"));
+        assert!(!proves("(* x (/ 1 x))", "1"));
+        assert!(!proves("(pow x 0)", "1"));
+        assert!(!proves("(* (pow x -1) (pow x 1))", "(pow x 0)"));
+
+        // the power rule for integrals doesn't divide by zero
+        let runner = Runner::default()
+            .with_iter_limit(5)
+            .with_expr(&"(i (pow x -1) x)".parse().unwrap())
+            .run(&rules());
+        let div = runner
+            .egraph
+            .lookup_expr(&"(/ (pow x 0) 0)".parse().unwrap());
+        assert_eq!(div, None);
+    }
+}
@@ -0,0 +1,32 @@
+/*!
+Ready-made languages, each with an analysis, a rule set, and a cost
+function, to build on or to try `egg` out with.
+
+- [`math`]: arithmetic over the reals, with derivatives and integrals.
+- [`prop`]: propositional logic.
+
+Both fold constants in their analysis.
+The rules of [`prop`] are equivalences, and those of [`math`] only
+equate terms that are equal wherever both are defined; see
+[`math::rules`] for the two that assume more.
+The rule sets and cost functions may grow in minor releases, but the
+languages and analyses only change in major ones.
+
+This module is only available with the `lang` feature.
+
+# Example
+
+```
+use egg::{lang::prop::*, *};
+
+// a tautology, once simplified
+let start: RecExpr<Prop> = "(-> (& x true) (~ (~ x)))".parse().unwrap();
+let runner = Runner::default().with_expr(&start).run(&rules());
+let extractor = Extractor::new(&runner.egraph, PropCostFn);
+let (_, best) = extractor.find_best(runner.roots[0]);
+assert_eq!(best.to_string(), "true");
+```
+!*/
+
+pub mod math;
+pub mod prop;
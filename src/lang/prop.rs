@@ -0,0 +1,259 @@
+/*!
+Propositional logic.
+
+[`Prop`] has `true`, `false`, variables, and the connectives `&`, `|`,
+`~` and `->`.
+[`ConstantFold`] folds connectives of `true` and `false`, and
+[`rules`] is a rule set of logical equivalences.
+
+# Example
+
+```
+use egg::{lang::prop::*, *};
+
+let start: RecExpr<Prop> = "(-> x y)".parse().unwrap();
+let goal: RecExpr<Prop> = "(-> (~ y) (~ x))".parse().unwrap();
+let runner = Runner::default()
+    .with_expr(&start)
+    .with_goals(&[goal])
+    .run(&rules());
+assert!(matches!(runner.stop_reason, Some(StopReason::GoalsReached)));
+```
+!*/
+
+use crate::{rewrite as rw, *};
+
+/// An [`EGraph`](crate::EGraph) of [`Prop`] with [`ConstantFold`].
+pub type EGraph = crate::EGraph<Prop, ConstantFold>;
+/// A [`Rewrite`](crate::Rewrite) of [`Prop`] with [`ConstantFold`].
+pub type Rewrite = crate::Rewrite<Prop, ConstantFold>;
+
+define_language! {
+    /// Propositional logic.
+    pub enum Prop {
+        Bool(bool),
+        "&" = And([Id; 2]),
+        "~" = Not(Id),
+        "|" = Or([Id; 2]),
+        "->" = Implies([Id; 2]),
+        Symbol(Symbol),
+    }
+}
+
+/// A [`CostFunction`] like [`AstSize`], but where `->` costs 3, more
+/// than its definition `(| (~ a) b)`, so extracted terms only use `&`,
+/// `|` and `~`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PropCostFn;
+
+impl CostFunction<Prop> for PropCostFn {
+    type Cost = usize;
+    fn cost<C>(&mut self, enode: &Prop, mut costs: C) -> Self::Cost
+    where
+        C: FnMut(Id) -> Self::Cost,
+    {
+        let op_cost = match enode {
+            Prop::Implies(..) => 3,
+            _ => 1,
+        };
+        enode.fold(op_cost, |sum, i| sum + costs(i))
+    }
+}
+
+/// An [`Analysis`] that folds connectives of `true` and `false`.
+///
+/// The data of an eclass is the truth value it folds to, if any, with
+/// the term it folded, like `(& true false)`, for explanations.
+/// An eclass that folds gets the truth value's enode, and loses its
+/// other enodes but its leaves.
+///
+/// [`distinct`](Analysis::distinct) is true for eclasses that fold to
+/// different truth values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConstantFold;
+
+impl Analysis<Prop> for ConstantFold {
+    type Data = Option<(bool, PatternAst<Prop>)>;
+
+    fn make(egraph: &EGraph, enode: &Prop) -> Self::Data {
+        let x = |i: &Id| egraph[*i].data.as_ref().map(|d| d.0);
+        let c = match enode {
+            Prop::Bool(c) => return Some((*c, c.to_string().parse().unwrap())),
+            Prop::Symbol(_) => return None,
+            Prop::Not(a) => {
+                let folded = format!("(~ {})", x(a)?).parse().unwrap();
+                return Some((!x(a)?, folded));
+            }
+            Prop::And([a, b]) | Prop::Or([a, b]) | Prop::Implies([a, b]) => (x(a)?, x(b)?),
+        };
+        let value = match enode {
+            Prop::And(_) => c.0 && c.1,
+            Prop::Or(_) => c.0 || c.1,
+            _ => !c.0 || c.1,
+        };
+        let folded = format!("({} {} {})", enode, c.0, c.1).parse().unwrap();
+        Some((value, folded))
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the eclasses fold to different truth values, since only
+    /// an unsound rule or union could make them equal.
+    fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge {
+        let from = match from {
+            Some(from) => from,
+            None => return DidMerge(false, to.is_some()),
+        };
+        match to {
+            Some((c, _)) => {
+                assert_eq!(*c, from.0, "Merged eclasses with different truth values");
+                DidMerge(false, false)
+            }
+            None => {
+                *to = Some(from);
+                DidMerge(true, false)
+            }
+        }
+    }
+
+    fn modify(egraph: &mut EGraph, id: Id) {
+        let (c, folded) = match &egraph[id].data {
+            Some(data) => data.clone(),
+            None => return,
+        };
+        if egraph.are_explanations_enabled() {
+            egraph.union_instantiations(
+                &folded,
+                &c.to_string().parse().unwrap(),
+                &Default::default(),
+                "constant_fold",
+            );
+        } else {
+            let added = egraph.add(Prop::Bool(c));
+            egraph.union(id, added);
+        }
+        // a frozen or sort-checked eclass may have kept out the constant,
+        // and then it has no leaf to keep
+        if egraph.lookup(Prop::Bool(c)) == Some(egraph.find(id)) {
+            egraph.retain_nodes(id, |n| n.is_leaf());
+        }
+    }
+
+    fn distinct(a: &Self::Data, b: &Self::Data) -> bool {
+        matches!((a, b), (Some((x, _)), Some((y, _))) if x != y)
+    }
+}
+
+/// A rule set of logical equivalences over [`Prop`].
+///
+/// Every rule is an equivalence, so it can be used in both directions.
+/// Rules that only hold one way, like the case split
+/// `(& (-> ?a ?b) (-> (~ ?a) ?c))` to `(| ?b ?c)`, aren't included,
+/// since the egraph would make the two sides equal.
+#[rustfmt::skip]
+pub fn rules() -> Vec<Rewrite> {
+    let mut rules = vec![
+        rw!("double-neg";   "(~ (~ ?a))"       => "?a"),
+        rw!("assoc-or";     "(| ?a (| ?b ?c))" => "(| (| ?a ?b) ?c)"),
+        rw!("assoc-and";    "(& ?a (& ?b ?c))" => "(& (& ?a ?b) ?c)"),
+        rw!("comm-or";      "(| ?a ?b)"        => "(| ?b ?a)"),
+        rw!("comm-and";     "(& ?a ?b)"        => "(& ?b ?a)"),
+        rw!("dist-and-or";  "(& ?a (| ?b ?c))" => "(| (& ?a ?b) (& ?a ?c))"),
+        rw!("dist-or-and";  "(| ?a (& ?b ?c))" => "(& (| ?a ?b) (| ?a ?c))"),
+        rw!("lem";          "(| ?a (~ ?a))"    => "true"),
+        rw!("contradict";   "(& ?a (~ ?a))"    => "false"),
+        rw!("or-true";      "(| ?a true)"      => "true"),
+        rw!("or-false";     "(| ?a false)"     => "?a"),
+        rw!("and-true";     "(& ?a true)"      => "?a"),
+        rw!("and-false";    "(& ?a false)"     => "false"),
+        rw!("contrapositive"; "(-> ?a ?b)"     => "(-> (~ ?b) (~ ?a))"),
+    ];
+    rules.extend(rw!("def-imply"; "(-> ?a ?b)" <=> "(| (~ ?a) ?b)"));
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the rules prove `start` equal to `goal`, within limits
+    /// small enough that failing to takes little time.
+    fn proves(start: &str, goal: &str) -> bool {
+        let start: RecExpr<Prop> = start.parse().unwrap();
+        let goal: RecExpr<Prop> = goal.parse().unwrap();
+        let runner = Runner::default()
+            .with_iter_limit(12)
+            .with_node_limit(5_000)
+            .with_time_limit(std::time::Duration::from_secs(1))
+            .with_expr(&start)
+            .with_goals(&[goal])
+            .run(&rules());
+        matches!(runner.stop_reason, Some(StopReason::GoalsReached))
+    }
+
+    #[test]
+    fn constants_fold() {
+        let mut egraph = EGraph::default();
+        for &(expr, value) in &[
+            ("(| (& false true) (& true false))", false),
+            ("(~ (& true false))", true),
+            ("(-> true false)", false),
+            ("(-> false true)", true),
+            ("(-> false false)", true),
+        ] {
+            let id = egraph.add_expr(&expr.parse().unwrap());
+            egraph.rebuild();
+            assert_eq!(
+                egraph[id].data.as_ref().map(|d| d.0),
+                Some(value),
+                "{}",
+                expr
+            );
+            assert_eq!(egraph[id].nodes.len(), 1);
+        }
+        let id = egraph.add_expr(&"(& x true)".parse().unwrap());
+        assert!(egraph[id].data.is_none());
+    }
+
+    #[test]
+    fn merges_report_changes() {
+        let mut egraph = EGraph::default();
+        let t = egraph.add(Prop::Bool(true));
+        let t = egraph[t].data.clone();
+        let merge = |to: &mut Option<_>, from: Option<_>| {
+            let DidMerge(a, b) = ConstantFold.merge(to, from);
+            (a, b)
+        };
+
+        let mut to = None;
+        assert_eq!(merge(&mut to, None), (false, false));
+        assert_eq!(merge(&mut to, t.clone()), (true, false));
+        assert_eq!(merge(&mut to, t), (false, false));
+        assert_eq!(merge(&mut to, None), (false, true));
+    }
+
+    #[test]
+    #[should_panic(expected = "different truth values")]
+    fn merging_true_and_false_panics() {
+        let mut egraph = EGraph::default();
+        let t = egraph.add(Prop::Bool(true));
+        let f = egraph.add(Prop::Bool(false));
+        egraph.union(t, f);
+    }
+
+    #[test]
+    fn proves_equivalences() {
+        assert!(proves("(-> x y)", "(| (~ x) y)"));
+        assert!(proves("(-> x y)", "(-> (~ y) (~ x))"));
+        assert!(proves("(| x (~ x))", "true"));
+        assert!(proves("(& x (| y false))", "(& y x)"));
+        assert!(proves("(-> (& x true) (~ (~ x)))", "true"));
+    }
+
+    #[test]
+    fn one_way_rules_are_left_out() {
+        // the left sides imply the right ones, but not the other way
+        assert!(!proves("(& (-> x y) (-> (~ x) z))", "(| y z)"));
+        assert!(!proves("(& (-> x y) (-> y z))", "(-> x z)"));
+    }
+}
@@ -67,6 +67,87 @@ pub trait Language: Debug + Clone + Eq + Ord + Hash {
         self.fold(0, |len, _| len + 1)
     }
 
+    /// Returns the name of this enode's operator, without any data it
+    /// carries, for grouping enodes by operator as
+    /// [`EGraph::op_stats`] does.
+    ///
+    /// [`define_language!`] implements this with the operator's string,
+    /// or the variant's name for variants that hold data.
+    /// The default implementation returns the name of the language's
+    /// type, which puts every enode in the same group.
+    fn op_display(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Returns the [`op_display`](Language::op_display) names of all of
+    /// this language's operators, in order.
+    ///
+    /// [`EGraph::write_binary`] fingerprints the language with these, so
+    /// [`EGraph::read_binary`] can tell when it was written for a
+    /// different language.
+    /// [`define_language!`] implements this with the variants in order
+    /// of declaration.
+    /// The default implementation returns the name of the language's
+    /// type.
+    fn op_names() -> Vec<&'static str> {
+        vec![std::any::type_name::<Self>()]
+    }
+
+    /// Returns false if this enode's operator can never appear in an
+    /// [`EGraph`] of this language.
+    ///
+    /// Some languages can parse operators that they never construct, so
+    /// rules that search for them can never match.
+    /// [`Rewrite::is_applicable_to`] uses this to find such rules.
+    /// The children `Id`s should be ignored.
+    ///
+    /// The default implementation returns true.
+    fn op_exists(&self) -> bool {
+        true
+    }
+
+    /// Returns this enode's operator applied to `children`, which may
+    /// be more or fewer than it has, or `None` if the operator can't
+    /// take that many.
+    ///
+    /// [Flattening](EGraph::with_flattening) uses this to build the
+    /// n-ary applications of an operator.
+    /// [`define_language!`] implements this with [`FromOp::from_op`] of
+    /// the [`op_display`](Language::op_display), and [`SymbolLang`]
+    /// takes any number of children.
+    /// The default implementation returns `None`, so the operators of
+    /// other languages are only sorted.
+    #[allow(unused_variables)]
+    fn with_children(&self, children: Vec<Id>) -> Option<Self> {
+        None
+    }
+
+    /// Compares enodes for ordering them within an eclass.
+    ///
+    /// [`rebuild`](EGraph::rebuild) sorts each eclass's enodes by this,
+    /// breaking ties with [`Ord`], and searching an eclass for an
+    /// operator binary searches that order.
+    /// So this must keep enodes with different discriminants apart.
+    /// If [`SEARCH_BY_SHAPE`](Language::SEARCH_BY_SHAPE) is set, it
+    /// must also compare enodes that [`matches`](Language::matches)
+    /// each other as equal, and searching only scans the run of enodes
+    /// equal to the operator's; otherwise searching scans every enode
+    /// with the operator's discriminant.
+    ///
+    /// The default implementation is the enode's [`Ord`].
+    /// For languages declared `search_by_shape`, [`define_language!`]
+    /// compares the variants and then the numbers of children,
+    /// ignoring the data the enodes carry.
+    fn search_cmp(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+
+    /// Whether [`search_cmp`](Language::search_cmp) compares enodes
+    /// that [`matches`](Language::matches) each other as equal, so
+    /// searching can skip the other enodes with the same
+    /// discriminant. The default is `false`.
+    const SEARCH_BY_SHAPE: bool = false;
+
     /// Returns true if this enode has no children.
     fn is_leaf(&self) -> bool {
         self.all(|_| false)
@@ -282,6 +363,30 @@ impl FromOpError {
     }
 }
 
+/// Resolve an unqualified operator to the one [namespaced](Symbol::namespace)
+/// operator in `ops` with that name, for [`define_language!`].
+///
+/// `op` is returned as is if it's qualified, if it's one of `ops`
+/// itself, or if none or several of `ops` have that name.
+#[doc(hidden)]
+pub fn resolve_op<'a>(op: &'a str, ops: &[&'a str]) -> &'a str {
+    if namespace_split(op).is_some() || ops.contains(&op) {
+        return op;
+    }
+    let mut found = None;
+    for &qualified in ops {
+        if let Some(i) = namespace_split(qualified) {
+            if &qualified[i + 1..] == op {
+                if found.map_or(false, |found| found != qualified) {
+                    return op;
+                }
+                found = Some(qualified);
+            }
+        }
+    }
+    found.unwrap_or(op)
+}
+
 /// A marker that defines acceptable children types for [`define_language!`].
 ///
 /// See [`define_language!`] for more details.
@@ -363,7 +468,11 @@ impl LanguageChildren for Id {
 /// elements that come before it in the list.
 ///
 /// If the `serde-1` feature is enabled, this implements
-/// [`serde::Serialize`][https://docs.rs/serde/latest/serde/trait.Serialize.html].
+/// [`serde::Serialize`][https://docs.rs/serde/latest/serde/trait.Serialize.html]
+/// and
+/// [`serde::Deserialize`][https://docs.rs/serde/latest/serde/trait.Deserialize.html],
+/// as the s-expression string it [displays](Display) as and
+/// [parses](FromStr) from.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct RecExpr<L> {
     nodes: Vec<L>,
@@ -380,6 +489,18 @@ impl<L: Language + Display> serde::Serialize for RecExpr<L> {
     }
 }
 
+#[cfg(feature = "serde-1")]
+impl<'de, L: FromOp> serde::Deserialize<'de> for RecExpr<L> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|e| serde::de::Error::custom(format!("{:?}", e)))
+    }
+}
+
 impl<L> Default for RecExpr<L> {
     fn default() -> Self {
         Self::from(vec![])
@@ -483,7 +604,7 @@ impl<L: Language + Display> RecExpr<L> {
             let mut vec = vec![op];
             for child in node.children().iter().map(|i| usize::from(*i)) {
                 vec.push(if let Some(s) = f(child) {
-                    return Sexp::String(s);
+                    Sexp::String(s)
                 } else if child < i {
                     self.to_sexp_rec(child, f)
                 } else {
@@ -515,6 +636,124 @@ impl<L: Language + Display> RecExpr<L> {
         pretty_print(&mut buf, &sexp, width, 1).unwrap();
         buf
     }
+
+    /// Print as an s-expression that binds each subterm used more than
+    /// once to a name with `let`, so the output is proportional to the
+    /// number of distinct subterms rather than the size of the tree.
+    ///
+    /// Equal subterms count as the same subterm, whether or not they are
+    /// the same node of the [`RecExpr`].
+    /// The bindings are sequential, each one can use the ones before it,
+    /// and come in one `let` around the whole term:
+    /// `(let ((v0 e0) (v1 e1) ...) body)`.
+    /// They are named `v0`, `v1`, and so on in that order, with `v`
+    /// followed by as many `_` as it takes for no leaf of the term to be
+    /// named like a binding.
+    /// Leaves aren't bound, so a term without shared subterms, like a
+    /// single leaf, prints as it [displays](Display).
+    ///
+    /// [Parsing](FromStr) reads the result back: `let` is only read as
+    /// a binding form when its first argument is a list of bindings,
+    /// which no other term can have, so languages with a `let` operator
+    /// parse as before.
+    ///
+    /// # Example
+    /// ```
+    /// # use egg::*;
+    /// let e: RecExpr<SymbolLang> = "(+ (* (f x) (f x)) (* (f x) (f x)))".parse().unwrap();
+    /// let lets = e.to_sexp_with_lets();
+    /// assert_eq!(lets, "(let ((v0 (f x)) (v1 (* v0 v0))) (+ v1 v1))");
+    ///
+    /// let parsed: RecExpr<SymbolLang> = lets.parse().unwrap();
+    /// assert_eq!(parsed.as_ref().len(), 4);
+    /// assert_eq!(parsed.to_string(), e.to_string());
+    /// ```
+    pub fn to_sexp_with_lets(&self) -> String {
+        if self.nodes.is_empty() {
+            return "()".into();
+        }
+        self.to_let_sexp().to_string()
+    }
+
+    /// Pretty print [with `let`s](RecExpr::to_sexp_with_lets) with a
+    /// maximum line length, like [`pretty`](RecExpr::pretty).
+    pub fn pretty_with_lets(&self, width: usize) -> String {
+        if self.nodes.is_empty() {
+            return "()".into();
+        }
+        let sexp = self.to_let_sexp();
+
+        let mut buf = String::new();
+        pretty_print(&mut buf, &sexp, width, 1).unwrap();
+        buf
+    }
+
+    fn to_let_sexp(&self) -> Sexp {
+        if !self.is_dag() {
+            return self.to_sexp();
+        }
+
+        // hash-cons, so equal subterms are one node
+        let mut ids: Vec<Id> = Vec::with_capacity(self.nodes.len());
+        let mut set = IndexSet::default();
+        for node in &self.nodes {
+            let node = node.clone().map_children(|id| ids[usize::from(id)]);
+            ids.push(Id::from(set.insert_full(node).0));
+        }
+        let root = usize::from(*ids.last().unwrap());
+        let mut nodes: Vec<L> = set.into_iter().collect();
+        // nothing after the root is part of it
+        nodes.truncate(root + 1);
+        let expr = RecExpr::from(nodes);
+
+        let mut uses = vec![0usize; root + 1];
+        let mut reachable = vec![false; root + 1];
+        reachable[root] = true;
+        for (i, node) in expr.nodes.iter().enumerate().rev() {
+            if reachable[i] {
+                for &child in node.children() {
+                    uses[usize::from(child)] += 1;
+                    reachable[usize::from(child)] = true;
+                }
+            }
+        }
+
+        let leaves: HashSet<String> = expr
+            .nodes
+            .iter()
+            .filter(|n| n.is_leaf())
+            .map(|n| n.to_string())
+            .collect();
+        let mut prefix = String::from("v");
+        let clashes = |prefix: &str| {
+            leaves.iter().any(|leaf| {
+                leaf.strip_prefix(prefix).map_or(false, |n| {
+                    !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())
+                })
+            })
+        };
+        while clashes(&prefix) {
+            prefix.push('_');
+        }
+
+        let mut names: Vec<Option<String>> = vec![None; root + 1];
+        let mut bindings = vec![];
+        for (i, node) in expr.nodes[..root].iter().enumerate() {
+            if uses[i] > 1 && !node.is_leaf() {
+                let name = format!("{}{}", prefix, bindings.len());
+                let value = expr.to_sexp_rec(i, &mut |child| names[child].clone());
+                bindings.push(Sexp::List(vec![Sexp::String(name.clone()), value]));
+                names[i] = Some(name);
+            }
+        }
+        let body = expr.to_sexp_rec(root, &mut |child| names[child].clone());
+        if bindings.is_empty() {
+            body
+        } else {
+            let op = Sexp::String("let".into());
+            Sexp::List(vec![op, Sexp::List(bindings), body])
+        }
+    }
 }
 
 /// An error type for failures when attempting to parse an s-expression as a
@@ -547,35 +786,74 @@ impl<L: FromOp> FromStr for RecExpr<L> {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use RecExprParseError::*;
 
-        fn parse_sexp_into<L: FromOp>(
-            sexp: &Sexp,
+        /// The bindings of a `let` form `(let ((v0 e0) ...) body)`, if
+        /// `list` is one.
+        fn let_bindings(list: &[Sexp]) -> Option<Vec<(&str, &Sexp)>> {
+            match list {
+                [Sexp::String(op), Sexp::List(bindings), _] if op == "let" => bindings
+                    .iter()
+                    .map(|binding| match binding {
+                        Sexp::List(binding) => match binding.as_slice() {
+                            [Sexp::String(name), value] => Some((name.as_str(), value)),
+                            _ => None,
+                        },
+                        _ => None,
+                    })
+                    .collect(),
+                _ => None,
+            }
+        }
+
+        fn parse_sexp_into<'a, L: FromOp>(
+            sexp: &'a Sexp,
             expr: &mut RecExpr<L>,
+            bound: &mut HashMap<&'a str, Id>,
         ) -> Result<Id, RecExprParseError<L::Error>> {
             match sexp {
                 Sexp::Empty => Err(EmptySexp),
                 Sexp::String(s) => {
+                    if let Some(&id) = bound.get(s.as_str()) {
+                        return Ok(id);
+                    }
                     let node = L::from_op(s, vec![]).map_err(BadOp)?;
                     Ok(expr.add(node))
                 }
                 Sexp::List(list) if list.is_empty() => Err(EmptySexp),
-                Sexp::List(list) => match &list[0] {
-                    Sexp::Empty => unreachable!("Cannot be in head position"),
-                    list @ Sexp::List(..) => Err(HeadList(list.to_owned())),
-                    Sexp::String(op) => {
-                        let arg_ids: Vec<Id> = list[1..]
-                            .iter()
-                            .map(|s| parse_sexp_into(s, expr))
-                            .collect::<Result<_, _>>()?;
-                        let node = L::from_op(op, arg_ids).map_err(BadOp)?;
-                        Ok(expr.add(node))
+                Sexp::List(list) => {
+                    if let Some(bindings) = let_bindings(list) {
+                        let mut shadowed = vec![];
+                        for (name, value) in bindings {
+                            let id = parse_sexp_into(value, expr, bound)?;
+                            shadowed.push((name, bound.insert(name, id)));
+                        }
+                        let id = parse_sexp_into(&list[2], expr, bound)?;
+                        for (name, old) in shadowed.into_iter().rev() {
+                            match old {
+                                Some(old) => bound.insert(name, old),
+                                None => bound.remove(name),
+                            };
+                        }
+                        return Ok(id);
                     }
-                },
+                    match &list[0] {
+                        Sexp::Empty => unreachable!("Cannot be in head position"),
+                        list @ Sexp::List(..) => Err(HeadList(list.to_owned())),
+                        Sexp::String(op) => {
+                            let arg_ids: Vec<Id> = list[1..]
+                                .iter()
+                                .map(|s| parse_sexp_into(s, expr, bound))
+                                .collect::<Result<_, _>>()?;
+                            let node = L::from_op(op, arg_ids).map_err(BadOp)?;
+                            Ok(expr.add(node))
+                        }
+                    }
+                }
             }
         }
 
         let mut expr = RecExpr::default();
         let sexp = symbolic_expressions::parser::parse_str(s.trim()).map_err(BadSexp)?;
-        parse_sexp_into(&sexp, &mut expr)?;
+        parse_sexp_into(&sexp, &mut expr, &mut HashMap::default())?;
         Ok(expr)
     }
 }
@@ -610,6 +888,8 @@ One common one is constant folding, a kind of partial evaluation.
 In that case, the metadata is basically `Option<L>`, storing
 the cheapest constant expression (if any) that's equivalent to the
 enodes in this eclass.
+An [`OverflowPolicy`] decides what folding does when the arithmetic
+overflows.
 See the test files [`math.rs`] and [`prop.rs`] for more complex
 examples on this usage of [`Analysis`].
 
@@ -645,12 +925,17 @@ impl Analysis<SimpleMath> for ConstantFolding {
         let x = |i: &Id| egraph[*i].data;
         match enode {
             SimpleMath::Num(n) => Some(*n),
-            SimpleMath::Add([a, b]) => Some(x(a)? + x(b)?),
-            SimpleMath::Mul([a, b]) => Some(x(a)? * x(b)?),
+            // don't fold arithmetic that overflows
+            SimpleMath::Add([a, b]) => OverflowPolicy::RefuseToFold.add(x(a)?, x(b)?),
+            SimpleMath::Mul([a, b]) => OverflowPolicy::RefuseToFold.mul(x(a)?, x(b)?),
             _ => None,
         }
     }
 
+    fn overflow_policy(&self) -> Option<OverflowPolicy> {
+        Some(OverflowPolicy::RefuseToFold)
+    }
+
     fn modify(egraph: &mut EGraph<SimpleMath, Self>, id: Id) {
         if let Some(i) = egraph[id].data {
             let added = egraph.add(SimpleMath::Num(i));
@@ -697,6 +982,29 @@ pub trait Analysis<L: Language>: Sized {
     #[allow(unused_variables)]
     fn pre_union(egraph: &EGraph<L, Self>, id1: Id, id2: Id) {}
 
+    /// An optional hook that allows inspection of both eclasses right
+    /// before a [`union`] merges them.
+    ///
+    /// It's called after [`pre_union`](Analysis::pre_union), once the
+    /// union is known to happen and `winner` (the eclass whose id stays
+    /// canonical) and `loser` (the eclass merged into it) are decided,
+    /// but before the union-find, the enodes, or the data of either are
+    /// touched; so both eclasses are intact, and `loser` is about to
+    /// stop being an eclass of its own.
+    /// This includes the congruence unions of [`rebuild`], but not
+    /// unions that are skipped, like those of
+    /// [frozen](EGraph::freeze) eclasses, or only recorded (see
+    /// [`UnionMode::Record`]).
+    /// Like [`merge`](Analysis::merge), this may modify the
+    /// [`Analysis`], for [`modify`](Analysis::modify) to act on.
+    ///
+    /// By default it does nothing.
+    ///
+    /// [`union`]: EGraph::union()
+    /// [`rebuild`]: EGraph::rebuild()
+    #[allow(unused_variables)]
+    fn pre_merge(&mut self, winner: &EClass<L, Self::Data>, loser: &EClass<L, Self::Data>) {}
+
     /// Defines how to merge two `Data`s when their containing
     /// [`EClass`]es merge.
     ///
@@ -726,9 +1034,70 @@ pub trait Analysis<L: Language>: Sized {
     /// By default this does nothing.
     ///
     /// This function is called immediately following
-    /// `Analysis::merge` when unions are performed.
+    /// `Analysis::make` when an eclass is added, and `Analysis::merge`
+    /// when unions are performed or a rebuild changes an eclass's data.
+    /// It may add enodes and union them into the eclass, like the
+    /// constant folding in the example of [`Analysis`], and remove the
+    /// eclass's other enodes with [`EGraph::retain_nodes`], so searches
+    /// and extraction don't visit enodes that are dead weight.
     #[allow(unused_variables)]
     fn modify(egraph: &mut EGraph<L, Self>, id: Id) {}
+
+    /// An optional hook that normalizes an enode that is about to be
+    /// added for a subterm of an instantiated pattern, for example by
+    /// sorting the children of a commutative operator.
+    ///
+    /// When [canonical reuse](EGraph::with_canonical_reuse) is enabled
+    /// and the enode isn't already in the egraph, the returned enode is
+    /// looked up as well, and its eclass is used instead of adding a
+    /// new one.
+    /// The returned enode must be equivalent to the given one.
+    ///
+    /// By default this returns `None`.
+    #[allow(unused_variables)]
+    fn canonicalize_node(egraph: &EGraph<L, Self>, enode: &L) -> Option<L> {
+        None
+    }
+
+    /// An optional hook that says whether eclasses with data `a` and `b`
+    /// can never be equal, for example because they fold to different
+    /// constants.
+    ///
+    /// This must only return true if unioning the two eclasses would be
+    /// unsound. See [`EGraph::provably_distinct`].
+    ///
+    /// By default this returns false.
+    #[allow(unused_variables)]
+    fn distinct(a: &Self::Data, b: &Self::Data) -> bool {
+        false
+    }
+
+    /// The [`OverflowPolicy`] this analysis folds integer arithmetic
+    /// with, if it does.
+    ///
+    /// It's reported in [`Error::AnalysisConflict`] when
+    /// [distinct](Analysis::distinct) eclasses are unioned, since a
+    /// policy other than [`RefuseToFold`](OverflowPolicy::RefuseToFold)
+    /// can fold terms that aren't equal to the same constant.
+    ///
+    /// By default this returns `None`.
+    fn overflow_policy(&self) -> Option<OverflowPolicy> {
+        None
+    }
+
+    /// A hook called once an [`EGraph`] has been loaded, with serde or
+    /// [`EGraph::read_binary`], at the first [`rebuild`] afterwards.
+    ///
+    /// The per-eclass data is restored with the egraph, but anything
+    /// else the analysis keeps, like a map from constants to the
+    /// eclasses they fold in, can be rebuilt from it here.
+    /// If this modifies the egraph, it must be rebuilt again.
+    ///
+    /// By default it does nothing.
+    ///
+    /// [`rebuild`]: EGraph::rebuild()
+    #[allow(unused_variables)]
+    fn on_deserialize(egraph: &mut EGraph<L, Self>) {}
 }
 
 impl<L: Language> Analysis<L> for () {
@@ -803,6 +1172,17 @@ impl Language for SymbolLang {
     fn children_mut(&mut self) -> &mut [Id] {
         &mut self.children
     }
+
+    fn op_display(&self) -> &str {
+        self.op.as_str()
+    }
+
+    fn with_children(&self, children: Vec<Id>) -> Option<Self> {
+        Some(Self {
+            op: self.op,
+            children,
+        })
+    }
 }
 
 impl Display for SymbolLang {
@@ -821,3 +1201,76 @@ impl FromOp for SymbolLang {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolLang as S, *};
+
+    /// `x` to the `2^n`, by squaring `n` times.
+    fn power(n: usize) -> RecExpr<S> {
+        let mut expr = RecExpr::default();
+        let mut id = expr.add(S::leaf("x"));
+        for _ in 0..n {
+            id = expr.add(S::new("*", vec![id, id]));
+        }
+        expr
+    }
+
+    #[test]
+    fn lets_are_proportional_to_the_dag() {
+        let expr = power(16);
+        let plain = expr.to_string();
+        let lets = expr.to_sexp_with_lets();
+        assert!(lets.len() < 500, "{}", lets);
+        // with 2^16 leaves
+        assert!(plain.len() > 100_000);
+        assert!(lets.starts_with("(let ((v0 (* x x)) (v1 (* v0 v0)) "));
+        assert!(lets.ends_with(" (v14 (* v13 v13))) (* v14 v14))"));
+
+        let from_plain: RecExpr<S> = plain.parse().unwrap();
+        let from_lets: RecExpr<S> = lets.parse().unwrap();
+        // each x of the let form is a node
+        assert_eq!(from_lets.as_ref().len(), 18);
+        assert_eq!(from_lets.to_sexp_with_lets(), lets);
+        assert_eq!(from_lets.to_string(), plain);
+        let pretty = expr.pretty_with_lets(40);
+        assert!(pretty.lines().count() > 1);
+        let from_pretty: RecExpr<S> = pretty.parse().unwrap();
+        assert_eq!(from_pretty.to_sexp_with_lets(), lets);
+
+        let mut egraph = EGraph::<S, ()>::default();
+        let a = egraph.add_expr(&from_plain);
+        let b = egraph.add_expr(&from_lets);
+        assert_eq!(a, b);
+        assert_eq!(egraph.number_of_classes(), 17);
+    }
+
+    #[test]
+    fn let_names_and_leaves() {
+        let parse = |s: &str| s.parse::<RecExpr<S>>().unwrap();
+
+        // leaves aren't bound, even if the whole expression is one
+        assert_eq!(parse("x").to_sexp_with_lets(), "x");
+        let shared_leaf = RecExpr::from(vec![S::leaf("x"), S::leaf("x")]);
+        assert_eq!(shared_leaf.to_sexp_with_lets(), "x");
+        assert_eq!(parse("(+ x x)").to_sexp_with_lets(), "(+ x x)");
+        assert_eq!(RecExpr::<S>::default().to_sexp_with_lets(), "()");
+
+        // the names don't clash with leaves
+        let e = parse("(+ (f v0 v_1) (f v0 v_1))");
+        let lets = e.to_sexp_with_lets();
+        assert_eq!(lets, "(let ((v__0 (f v0 v_1))) (+ v__0 v__0))");
+        assert_eq!(parse(&lets).to_string(), e.to_string());
+
+        // bindings are scoped, and a `let` operator is left alone
+        let nested = parse("(let ((a (f x))) (g a (let ((a (h a))) a) a))");
+        assert_eq!(nested.to_string(), "(g (f x) (h (f x)) (f x))");
+        let op = parse("(let x (f y) x)");
+        assert_eq!(op.as_ref().len(), 5);
+        assert_eq!(op.to_string(), "(let x (f y) x)");
+        assert!(matches!(
+            "(let ((a)) a)".parse::<RecExpr<S>>(),
+            Err(RecExprParseError::HeadList(_))
+        ));
+    }
+}
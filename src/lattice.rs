@@ -0,0 +1,275 @@
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+
+use crate::{DidMerge, Language};
+
+/// A join semilattice, the data of one field of an analysis made with
+/// [`define_analysis!`](crate::define_analysis).
+///
+/// The analysis merges two eclasses' data field by field, joining each
+/// field with the other's, so each field only has to get its own join
+/// right.
+/// A field without its own `make` function is made from the enode
+/// with [`make_leaf`](Lattice::make_leaf), or from the values of its
+/// children with [`make_node`](Lattice::make_node).
+pub trait Lattice: Sized {
+    /// The value of a leaf enode.
+    fn make_leaf<L: Language>(enode: &L) -> Self;
+
+    /// The value of an enode with children, given their values in
+    /// order.
+    fn make_node<L: Language>(enode: &L, children: &[&Self]) -> Self;
+
+    /// Join `other` into `self`, reporting whether the result differs
+    /// from `self` and `other` like [`Analysis::merge`](crate::Analysis::merge).
+    fn join(&mut self, other: Self) -> DidMerge;
+}
+
+/// A value that's only known for some eclasses, like a constant.
+///
+/// `None` is the bottom, and joining two known values keeps `self`'s,
+/// which must be equal to `other`'s.
+/// Enodes are unknown unless a `make` function says otherwise.
+///
+/// # Panics
+///
+/// Joining two different known values panics, since only an unsound
+/// rule or union could make their eclasses equal.
+impl<T: Eq + Debug> Lattice for Option<T> {
+    fn make_leaf<L: Language>(_: &L) -> Self {
+        None
+    }
+
+    fn make_node<L: Language>(_: &L, _: &[&Self]) -> Self {
+        None
+    }
+
+    fn join(&mut self, other: Self) -> DidMerge {
+        match (self.as_ref(), other) {
+            (None, None) => DidMerge(false, false),
+            (None, other @ Some(_)) => {
+                *self = other;
+                DidMerge(true, false)
+            }
+            (Some(_), None) => DidMerge(false, true),
+            (Some(a), Some(b)) => {
+                assert_eq!(*a, b, "Joined different known values");
+                DidMerge(false, false)
+            }
+        }
+    }
+}
+
+/// A set of facts that holds if it holds of any enode, joined by union.
+///
+/// A leaf has no facts, and an enode has those of its children.
+/// This is best kept small, since it's copied into every eclass.
+impl<T: Ord + Clone> Lattice for BTreeSet<T> {
+    fn make_leaf<L: Language>(_: &L) -> Self {
+        BTreeSet::new()
+    }
+
+    fn make_node<L: Language>(_: &L, children: &[&Self]) -> Self {
+        children
+            .iter()
+            .flat_map(|set| set.iter().cloned())
+            .collect()
+    }
+
+    fn join(&mut self, mut other: Self) -> DidMerge {
+        let (len, other_len) = (self.len(), other.len());
+        self.append(&mut other);
+        DidMerge(self.len() > len, self.len() > other_len)
+    }
+}
+
+/// A flag that holds of an eclass if it holds of any of its enodes,
+/// joined by `||`.
+///
+/// It doesn't hold of a leaf, and holds of an enode if it holds of any
+/// of its children.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AnyTrue(pub bool);
+
+impl Lattice for AnyTrue {
+    fn make_leaf<L: Language>(_: &L) -> Self {
+        AnyTrue(false)
+    }
+
+    fn make_node<L: Language>(_: &L, children: &[&Self]) -> Self {
+        AnyTrue(children.iter().any(|c| c.0))
+    }
+
+    fn join(&mut self, other: Self) -> DidMerge {
+        let did_merge = DidMerge(!self.0 && other.0, self.0 && !other.0);
+        self.0 |= other.0;
+        did_merge
+    }
+}
+
+/// A flag that holds of an eclass only if it holds of all of its
+/// enodes, joined by `&&`.
+///
+/// It holds of a leaf, and of an enode if it holds of all of its
+/// children.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AllTrue(pub bool);
+
+impl Lattice for AllTrue {
+    fn make_leaf<L: Language>(_: &L) -> Self {
+        AllTrue(true)
+    }
+
+    fn make_node<L: Language>(_: &L, children: &[&Self]) -> Self {
+        AllTrue(children.iter().all(|c| c.0))
+    }
+
+    fn join(&mut self, other: Self) -> DidMerge {
+        let did_merge = DidMerge(self.0 && !other.0, !self.0 && other.0);
+        self.0 &= other.0;
+        did_merge
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolLang as S, *};
+    use std::collections::BTreeSet;
+
+    fn joined<T: Lattice + Clone>(a: &T, b: &T) -> (T, DidMerge) {
+        let mut a = a.clone();
+        let did_merge = a.join(b.clone());
+        (a, did_merge)
+    }
+
+    #[test]
+    fn joins_report_changes() {
+        let (a, d) = joined(&None, &Some(1));
+        assert_eq!((a, d.0, d.1), (Some(1), true, false));
+        let (a, d) = joined(&Some(1), &None);
+        assert_eq!((a, d.0, d.1), (Some(1), false, true));
+        let (a, d) = joined(&Some(1), &Some(1));
+        assert_eq!((a, d.0, d.1), (Some(1), false, false));
+
+        let set = |xs: &[u8]| xs.iter().copied().collect::<BTreeSet<u8>>();
+        let (a, d) = joined(&set(&[1, 2]), &set(&[2, 3]));
+        assert_eq!((a, d.0, d.1), (set(&[1, 2, 3]), true, true));
+        let (a, d) = joined(&set(&[1, 2]), &set(&[2]));
+        assert_eq!((a, d.0, d.1), (set(&[1, 2]), false, true));
+
+        let (a, d) = joined(&AnyTrue(false), &AnyTrue(true));
+        assert_eq!((a, d.0, d.1), (AnyTrue(true), true, false));
+        let (a, d) = joined(&AllTrue(false), &AllTrue(true));
+        assert_eq!((a, d.0, d.1), (AllTrue(false), false, true));
+        let (a, d) = joined(&AllTrue(true), &AllTrue(true));
+        assert_eq!((a, d.0, d.1), (AllTrue(true), false, false));
+    }
+
+    #[test]
+    #[should_panic(expected = "different known values")]
+    fn joining_different_values_panics() {
+        joined(&Some(1), &Some(2));
+    }
+
+    /// The depth of the shallowest term of an eclass.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Depth(usize);
+
+    impl Lattice for Depth {
+        fn make_leaf<L: Language>(_: &L) -> Self {
+            Depth(0)
+        }
+
+        fn make_node<L: Language>(_: &L, children: &[&Self]) -> Self {
+            Depth(1 + children.iter().map(|c| c.0).max().unwrap_or(0))
+        }
+
+        fn join(&mut self, other: Self) -> DidMerge {
+            let did_merge = DidMerge(other.0 < self.0, self.0 < other.0);
+            self.0 = self.0.min(other.0);
+            did_merge
+        }
+    }
+
+    #[derive(Default)]
+    struct Facts;
+
+    define_analysis! {
+        /// What's known about an eclass of [`SymbolLang`].
+        #[derive(Debug)]
+        struct FactsData {
+            number: Option<i64> = make_number, modify_number;
+            has_var: AnyTrue = make_has_var;
+            all_leaves: AllTrue = make_all_leaves;
+            ops: BTreeSet<Symbol> = make_ops;
+            depth: Depth;
+        }
+        impl Analysis<S> for Facts {}
+    }
+
+    fn make_number(_: &EGraph<S, Facts>, enode: &S) -> Option<i64> {
+        enode.op.as_str().parse().ok()
+    }
+
+    fn make_has_var(_: &EGraph<S, Facts>, enode: &S) -> AnyTrue {
+        AnyTrue(enode.op.as_str().starts_with('?'))
+    }
+
+    fn make_all_leaves(_: &EGraph<S, Facts>, enode: &S) -> AllTrue {
+        AllTrue(enode.is_leaf())
+    }
+
+    fn make_ops(_: &EGraph<S, Facts>, enode: &S) -> BTreeSet<Symbol> {
+        std::iter::once(enode.op).collect()
+    }
+
+    fn modify_number(egraph: &mut EGraph<S, Facts>, id: Id) {
+        if let Some(n) = egraph[id].data.number {
+            let leaf = egraph.add(S::leaf(n.to_string()));
+            egraph.union(id, leaf);
+        }
+    }
+
+    #[test]
+    fn defined_analyses_join_fieldwise() {
+        let mut egraph = EGraph::<S, Facts>::default();
+        let x = egraph.add(S::leaf("?x"));
+        let one = egraph.add(S::leaf("1"));
+        let f = egraph.add(S::new("f", vec![x]));
+        assert_eq!(egraph[f].data.number, None);
+        assert_eq!(egraph[x].data.has_var, AnyTrue(true));
+        assert_eq!(egraph[f].data.depth, Depth(1));
+
+        egraph.union(one, f);
+        egraph.rebuild();
+        let data = &egraph[f].data;
+        assert_eq!(data.number, Some(1));
+        assert_eq!(data.has_var, AnyTrue(false));
+        assert_eq!(data.all_leaves, AllTrue(false));
+        assert_eq!(data.depth, Depth(0));
+        let mut ops: Vec<&str> = data.ops.iter().map(|op| op.as_str()).collect();
+        ops.sort_unstable();
+        assert_eq!(ops, vec!["1", "f"]);
+
+        egraph.union(x, f);
+        egraph.rebuild();
+        assert_eq!(egraph[x].data.has_var, AnyTrue(true));
+        assert_eq!(egraph[x].data.ops.len(), 3);
+    }
+
+    #[test]
+    fn defined_analyses_run_field_hooks() {
+        let mut egraph = EGraph::<S, Facts>::default();
+        let y = egraph.add(S::leaf("y"));
+        let plus_two = egraph.add(S::leaf("+2"));
+        egraph.rebuild();
+        let two = egraph.lookup(S::leaf("2")).unwrap();
+        assert_eq!(egraph.find(plus_two), egraph.find(two));
+
+        egraph.union(y, plus_two);
+        egraph.rebuild();
+        assert_eq!(egraph[y].data.number, Some(2));
+        assert_eq!(egraph.number_of_classes(), 1);
+        assert_eq!(egraph.total_size(), 3);
+    }
+}
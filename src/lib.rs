@@ -30,30 +30,83 @@ mod macros;
 
 pub mod tutorials;
 
+#[cfg(feature = "shell")]
+pub mod shell;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "lang")]
+pub mod lang;
+
+mod ac;
+mod annotation;
+mod anti_unify;
+#[cfg(feature = "bench")]
+mod bench;
+mod binary;
+mod cancel;
+mod checkpoint;
+mod compare;
+mod distinct;
 mod dot;
 mod eclass;
 mod egraph;
+mod error;
 mod explain;
+mod expr_arena;
 mod extract;
+mod history;
+mod intersect;
 mod language;
+mod lattice;
 mod machine;
+mod multipattern;
+mod normalize;
+mod overflow;
 mod pattern;
+mod postorder;
+mod provenance;
+mod record;
 mod rewrite;
+mod rule_parser;
+mod rule_stats;
 mod run;
+mod selectivity;
+mod sort;
 mod subst;
+mod tag;
+mod term_budget;
 mod unionfind;
 mod util;
+mod view;
 
 /// A key to identify [`EClass`]es within an
 /// [`EGraph`].
+///
+/// With the `tagged-ids` feature, in debug builds, the ids an
+/// [`EGraph`] hands out remember which egraph they came from, and
+/// giving one to another egraph (to index it, [`union`](EGraph::union),
+/// [`find`](EGraph::find), search or extract from) panics.
+/// Ids made with `Id::from` are accepted by every egraph, and egraphs
+/// that were loaded or made by
+/// [`HistoryRecorder::egraph_at`] accept every id.
+/// Otherwise, and in release builds, an id is just its index.
 #[derive(Clone, Copy, Default, Ord, PartialOrd, Eq, PartialEq, Hash)]
 #[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "serde-1", serde(transparent))]
-pub struct Id(u32);
+pub struct Id(u32, #[cfg_attr(feature = "serde-1", serde(skip))] IdTag);
+
+impl Id {
+    /// This id, carrying `tag`.
+    pub(crate) fn tagged(self, tag: IdTag) -> Id {
+        Id(self.0, tag)
+    }
+}
 
 impl From<usize> for Id {
     fn from(n: usize) -> Id {
-        Id(n as u32)
+        Id(n as u32, IdTag::NONE)
     }
 }
 
@@ -75,20 +128,77 @@ impl std::fmt::Display for Id {
     }
 }
 
-pub(crate) use {explain::Explain, explain::Justification, unionfind::UnionFind};
+pub(crate) use {
+    ac::{FlatPattern, Flattening},
+    annotation::Annotations,
+    cancel::CANCEL_CHECK_INTERVAL,
+    checkpoint::Checkpoint,
+    explain::Explain,
+    explain::Justification,
+    history::ChangeLog,
+    provenance::Provenance,
+    sort::SortChecker,
+    tag::IdTag,
+    term_budget::{expr_term, flat_term},
+    unionfind::UnionFind,
+};
+
+#[cfg(feature = "reports")]
+pub use rule_stats::RuleStatsError;
+
+#[cfg(feature = "bench")]
+pub use bench::{
+    BenchError, BenchMetrics, BenchRegression, BenchReport, BenchScenario, BENCH_REPORT_VERSION,
+};
 
 pub use {
+    ac::AcPattern,
+    annotation::ClassAnnotation,
+    anti_unify::{anti_unify, anti_unify_classes, Generalization},
+    binary::{BinaryError, BINARY_VERSION},
+    cancel::CancellationToken,
+    compare::{
+        compare_rulesets, ComparisonLimits, ObligationComparison, ObligationRun, RulesetComparison,
+    },
+    distinct::{DistinctnessWitness, Equivalence},
     dot::Dot,
-    eclass::EClass,
-    egraph::EGraph,
+    eclass::{EClass, ENodeId},
+    egraph::{EGraph, OpDelta, OpStats, RebuildReport},
+    error::Error,
     explain::{Explanation, FlatExplanation, FlatTerm, TreeExplanation, TreeTerm},
+    expr_arena::ExprArena,
     extract::*,
+    history::{EGraphDiff, HistoryRecorder},
+    intersect::Intersection,
     language::*,
-    pattern::{ENodeOrVar, Pattern, PatternAst, SearchMatches},
-    rewrite::{Applier, Condition, ConditionEqual, ConditionalApplier, Rewrite, Searcher},
+    lattice::{AllTrue, AnyTrue, Lattice},
+    machine::{
+        ENodeOrReg, Instruction, MachineBuffers, Program, ProgramSet, ProgramValidationError, Reg,
+        SearchAction,
+    },
+    multipattern::{MultiPattern, MultiPatternParseError},
+    normalize::{normalize, normalize_with, NormalizeLimits, NormalizeReport, Normalizer},
+    overflow::{OverflowInt, OverflowPolicy},
+    pattern::{ArenaMatches, ENodeOrVar, Pattern, PatternAst, SearchMatches, UnknownVar},
+    postorder::StreamError,
+    provenance::{NodeCount, Origin},
+    record::{RecordedUnion, UnionMode},
+    rewrite::{
+        validate_rules, AnalysisCondition, Applier, BudgetedApplier, Condition, ConditionEqual,
+        ConditionalApplier, ExceptSearcher, FactSearcher, MatchRanker, Rewrite, RuleSet,
+        RuleValidationError, Searcher,
+    },
+    rule_parser::{format_rules, parse_rules, RuleParseError},
+    rule_stats::{RuleStatsEntry, RuleStatsSnapshot, RULE_STATS_VERSION},
     run::*,
-    subst::{Subst, Var},
+    sort::{SortId, SortPolicy, SortViolation, SortedLanguage},
+    subst::{
+        ArenaSubst, Bindings, HashableCanonicalSubst, MatchArena, PackedSubst, Subst, SubstRange,
+        Var,
+    },
+    term_budget::{CyclePolicy, TermBudget},
     util::*,
+    view::{ClassView, EGraphView, RecordingView},
 };
 
 #[cfg(test)]
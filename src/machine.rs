@@ -1,34 +1,380 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter, Write};
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::pattern::default_var_order;
+use crate::selectivity::Selectivity;
 use crate::*;
 
-#[derive(Default)]
-struct Machine {
+/// Scratch space for running [`Program`]s, to reuse across runs with
+/// [`Program::run_with_buffers`].
+///
+/// The buffers grow to fit the largest program run with them, and are
+/// overwritten rather than reallocated by each run.
+#[derive(Debug, Default)]
+pub struct MachineBuffers {
+    machine: Machine,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Machine {
+    // sized once per program, see `start`
     reg: Vec<Id>,
+    // the high-water mark: how many registers hold the current bindings
+    written: usize,
+    // the highest register the program being run writes
+    max_reg: usize,
     // a buffer to re-use for lookups
     lookup: Vec<Id>,
+    // how many enodes have been bound by Bind instructions
+    n_binds: usize,
+    // how many matches have been yielded, to tell which candidates of a
+    // Bind led to none
+    yields: usize,
+    // when profiling, the counts of each Bind of the program being run,
+    // indexed by how many instructions follow it; see `count_bind`
+    pub(crate) bind_counts: Option<Vec<BindProfile>>,
+    // the counts rolled up by pattern node, see `Program::roll_up_profile`
+    pub(crate) bind_profile: HashMap<Id, BindProfile>,
+    // how many more matches to yield before stopping early
+    remaining: usize,
+    // how many more steps the searches using this machine may take, if
+    // limited, see `step`
+    pub(crate) step_budget: Option<usize>,
+    // whether a search stopped early because the step budget ran out
+    pub(crate) out_of_steps: bool,
+    // checked each time the step budget runs out, which refills it
+    // unless the token was cancelled, see `run_cancellable`
+    cancel: Option<CancellationToken>,
 }
 
+/// How many candidate enodes a `Bind` bound, and how many of them the
+/// machine backtracked from without yielding a match.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BindProfile {
+    pub(crate) candidates: usize,
+    pub(crate) backtracks: usize,
+}
+
+/// A register of the machine that runs a [`Program`], which holds an
+/// eclass id.
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
-struct Reg(u32);
+pub struct Reg(pub u32);
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "r{}", self.0)
+    }
+}
+
+/// Whether a search should keep going after a match, returned by the
+/// callback of [`Pattern::search_eclass_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchAction {
+    /// Keep searching for more matches.
+    Continue,
+    /// Stop searching; no more enodes are bound, and no more matches
+    /// are yielded.
+    Stop,
+}
 
+/// A compiled pattern, run by a backtracking machine to search an
+/// eclass.
+///
+/// [`Pattern`]s compile to programs, or one can be written by hand from
+/// [`Instruction`]s with [`from_parts`](Program::from_parts).
+/// A program is a [`Searcher`] itself, so it can be the lefthand side
+/// of a [`Rewrite`].
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Program<L> {
     instructions: Vec<Instruction<L>>,
     subst: Subst,
+    /// The variables of `subst`, in order, shared by the
+    /// [`PackedSubst`]s this program produces.
+    schema: Arc<[Var]>,
+    /// A faster way to run this program, if the pattern is simple enough.
+    simple: Option<SimpleProgram<L>>,
+    /// The highest register the instructions write, known statically
+    /// from the `Bind`s, so the machine's registers are sized once.
+    max_reg: usize,
+    /// The pattern node each instruction binds, for programs compiled
+    /// from a single pattern, so profiles can name the subterm.
+    positions: Vec<Option<Id>>,
 }
 
+/// A program for a pattern that is a single enode whose children are
+/// all distinct variables, like `(+ ?a ?b)`.
+///
+/// Matching one doesn't need the machine at all: each enode in the
+/// eclass with the right operator is a match, binding each variable to
+/// the corresponding child.
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum Instruction<L> {
-    Bind { node: L, i: Reg, out: Reg },
-    Compare { i: Reg, j: Reg },
-    Lookup { term: Vec<ENodeOrReg<L>>, i: Reg },
+struct SimpleProgram<L> {
+    /// The operator, with its children zeroed out as in a `Bind`.
+    node: L,
+    /// The variable for each child.
+    vars: Vec<Var>,
 }
 
+impl<L: Language> SimpleProgram<L> {
+    fn new(pattern: &PatternAst<L>) -> Option<Self> {
+        let nodes = pattern.as_ref();
+        let root = match nodes.last()? {
+            ENodeOrVar::ENode(root) => root,
+            ENodeOrVar::Var(_) => return None,
+        };
+        let mut vars = Vec::with_capacity(root.len());
+        for &child in root.children() {
+            match &nodes[usize::from(child)] {
+                ENodeOrVar::Var(v) if !vars.contains(v) => vars.push(*v),
+                _ => return None,
+            }
+        }
+        let node = root.clone().map_children(|_| Id::from(0));
+        Some(Self { node, vars })
+    }
+
+    fn run<A>(
+        &self,
+        machine: &mut Machine,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        matches: &mut Vec<Subst>,
+    ) where
+        A: Analysis<L>,
+    {
+        for_each_matching_node(&egraph[eclass], &self.node, |matched| {
+            // each candidate is a step, so cancellation and step budgets
+            // reach the fast path too
+            if machine.remaining == 0 || !machine.step() {
+                return;
+            }
+            machine.remaining -= 1;
+            machine.n_binds += 1;
+            let vec = self
+                .vars
+                .iter()
+                .copied()
+                .zip(matched.children().iter().map(|&id| egraph.find(id)));
+            matches.push(Subst { vec: vec.collect() });
+        });
+    }
+}
+
+/// An instruction of a [`Program`].
+///
+/// A program runs on a machine whose registers each hold an eclass id,
+/// starting with `r0` holding the searched eclass and no other register
+/// written.
+/// The instructions run in order.
+/// One that binds runs the rest of the program once for each
+/// candidate it finds, in turn, like a nested loop, and one that checks
+/// abandons the current candidates if the check fails.
+/// There's no yield instruction: when the current candidates pass the
+/// last instruction, the program yields a match, binding each variable
+/// to the [canonical](EGraph::find) eclass in its register, so each
+/// combination of candidates yields at most once.
+///
+/// A register may only be read once it's written, and a bind may only
+/// write registers above all those already written, which then hold
+/// their values for the rest of the program; see
+/// [`Program::from_parts`].
+/// A search stops early at its match limit, or when its arena's
+/// [step budget](MatchArena::set_step_budget) runs out, which binding an
+/// enode, scanning an eclass and running a check take a step of each.
+///
+/// New instructions may be added in minor releases, so matches on an
+/// [`Instruction`] need a wildcard arm.
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum ENodeOrReg<L> {
+#[non_exhaustive]
+pub enum Instruction<L> {
+    /// For each enode in the eclass in `i` that
+    /// [matches](Language::matches) `node`, write its children to the
+    /// registers from `out` on, in order, and run the rest of the
+    /// program.
+    ///
+    /// Only the operator and arity of `node` matter.
+    Bind {
+        /// The operator to bind; its children are ignored.
+        node: L,
+        /// The register of the eclass to bind an enode of.
+        i: Reg,
+        /// The first register the children are written to.
+        out: Reg,
+    },
+    /// Pass if the registers `i` and `j` hold the same eclass.
+    Compare {
+        /// The first register.
+        i: Reg,
+        /// The second register.
+        j: Reg,
+    },
+    /// Pass if `term` is in the egraph, in the eclass in `i`.
+    ///
+    /// The term is like a [`RecExpr`]: each enode's children are the
+    /// indices of earlier entries, and a register entry stands for the
+    /// eclass it holds.
+    /// Each enode is [looked up](EGraph::lookup) in turn, failing if
+    /// one isn't in the egraph, and the last entry is compared to `i`.
+    Lookup {
+        /// The term to look up, the root last.
+        term: Vec<ENodeOrReg<L>>,
+        /// The register of the eclass the term should be in.
+        i: Reg,
+    },
+    /// For each eclass of the egraph, write it to `out` and run the rest
+    /// of the program; how a [`MultiPattern`] starts matching a pattern
+    /// whose variable isn't bound yet.
+    Scan {
+        /// The register each eclass is written to.
+        out: Reg,
+    },
+    /// Never pass; the program of an empty pattern.
+    Fail,
+}
+
+/// An entry of the term of an [`Instruction::Lookup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ENodeOrReg<L> {
+    /// An enode, whose children index earlier entries of the term.
     ENode(L),
+    /// The eclass held by a register.
     Reg(Reg),
 }
 
+/// Why [`Program::from_parts`] rejected a program.
+///
+/// Instructions are numbered from zero.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ProgramValidationError {
+    /// An instruction reads a register before any instruction writes
+    /// it.
+    #[error("instruction {instruction} reads {reg} before it's written")]
+    UnwrittenRegister {
+        /// The instruction.
+        instruction: usize,
+        /// The register it reads.
+        reg: Reg,
+    },
+    /// A bind or scan writes from a register at or below one that's
+    /// already written, which would overwrite or forget its binding.
+    #[error(
+        "instruction {instruction} writes from {reg}, over registers that are already written"
+    )]
+    OverlappingWrite {
+        /// The instruction.
+        instruction: usize,
+        /// The first register it writes.
+        reg: Reg,
+    },
+    /// An enode of a lookup term has a child that isn't an earlier entry
+    /// of the term.
+    #[error(
+        "entry {entry} of the term of instruction {instruction} has a child that isn't before it"
+    )]
+    ForwardChild {
+        /// The lookup instruction.
+        instruction: usize,
+        /// The index of the enode in the term.
+        entry: usize,
+    },
+    /// A variable is yielded from a register that no instruction writes.
+    #[error("{var} is yielded from {reg}, which is never written")]
+    UnwrittenVar {
+        /// The variable.
+        var: Var,
+        /// Its register.
+        reg: Reg,
+    },
+    /// A variable is given more than one register.
+    #[error("{0} is yielded more than once")]
+    DuplicateVar(Var),
+}
+
+/// Check that `instructions` only read registers they've written and
+/// only write above them, and that each variable's register is
+/// written, returning the highest register written.
+fn validate<L: Language>(
+    instructions: &[Instruction<L>],
+    v2r: impl IntoIterator<Item = (Var, Reg)>,
+) -> Result<usize, ProgramValidationError> {
+    use ProgramValidationError::*;
+    // registers below this are bound, those at it and above aren't
+    let mut written = 1;
+    let mut gaps = HashSet::default();
+    for (n, instruction) in instructions.iter().enumerate() {
+        let read = |reg: Reg| {
+            let r = reg.0 as usize;
+            if r < written && !gaps.contains(&r) {
+                Ok(())
+            } else {
+                Err(UnwrittenRegister {
+                    instruction: n,
+                    reg,
+                })
+            }
+        };
+        let (out, len) = match instruction {
+            Instruction::Bind { node, i, out } => {
+                read(*i)?;
+                (*out, node.len())
+            }
+            Instruction::Scan { out } => (*out, 1),
+            Instruction::Compare { i, j } => {
+                read(*i)?;
+                read(*j)?;
+                continue;
+            }
+            Instruction::Lookup { term, i } => {
+                read(*i)?;
+                for (entry, node) in term.iter().enumerate() {
+                    match node {
+                        ENodeOrReg::Reg(r) => read(*r)?,
+                        ENodeOrReg::ENode(node) => {
+                            if !node.all(|child| usize::from(child) < entry) {
+                                return Err(ForwardChild {
+                                    instruction: n,
+                                    entry,
+                                });
+                            }
+                        }
+                    }
+                }
+                continue;
+            }
+            Instruction::Fail => continue,
+        };
+        let start = out.0 as usize;
+        if start < written {
+            return Err(OverlappingWrite {
+                instruction: n,
+                reg: out,
+            });
+        }
+        gaps.extend(written..start);
+        written = start + len;
+    }
+
+    let mut seen = HashSet::default();
+    for (var, reg) in v2r {
+        if !seen.insert(var) {
+            return Err(DuplicateVar(var));
+        }
+        let r = reg.0 as usize;
+        if r >= written || gaps.contains(&r) {
+            return Err(UnwrittenVar { var, reg });
+        }
+    }
+    Ok(written - 1)
+}
+
+/// The order [`rebuild`](EGraph::rebuild) sorts an eclass's enodes in.
+fn node_order<L: Language>(a: &L, b: &L) -> Ordering {
+    a.search_cmp(b).then_with(|| a.cmp(b))
+}
+
 #[inline(always)]
 fn for_each_matching_node<L, D>(eclass: &EClass<L, D>, node: &L, mut f: impl FnMut(&L))
 where
@@ -37,10 +383,31 @@ where
     #[allow(clippy::mem_discriminant_non_enum)]
     if eclass.nodes.len() < 50 {
         eclass.nodes.iter().filter(|n| node.matches(n)).for_each(f)
+    } else if L::SEARCH_BY_SHAPE {
+        debug_assert!(eclass
+            .nodes
+            .windows(2)
+            .all(|w| node_order(&w[0], &w[1]) == Ordering::Less));
+        // the first enode that isn't less than the operator
+        let start = eclass
+            .nodes
+            .binary_search_by(|n| n.search_cmp(node).then(Ordering::Greater))
+            .unwrap_or_else(|i| i);
+        eclass.nodes[start..]
+            .iter()
+            .take_while(|n| n.search_cmp(node) == Ordering::Equal)
+            .filter(|n| node.matches(n))
+            .for_each(f)
     } else {
         debug_assert!(node.all(|id| id == Id::from(0)));
-        debug_assert!(eclass.nodes.windows(2).all(|w| w[0] < w[1]));
-        let mut start = eclass.nodes.binary_search(node).unwrap_or_else(|i| i);
+        debug_assert!(eclass
+            .nodes
+            .windows(2)
+            .all(|w| node_order(&w[0], &w[1]) == Ordering::Less));
+        let mut start = eclass
+            .nodes
+            .binary_search_by(|n| node_order(n, node))
+            .unwrap_or_else(|i| i);
         let discrim = std::mem::discriminant(node);
         while start > 0 {
             if std::mem::discriminant(&eclass.nodes[start - 1]) == discrim {
@@ -74,75 +441,296 @@ where
 impl Machine {
     #[inline(always)]
     fn reg(&self, reg: Reg) -> Id {
+        debug_assert!(
+            (reg.0 as usize) < self.written,
+            "Read r{} past the bound registers",
+            reg.0
+        );
         self.reg[reg.0 as usize]
     }
 
+    /// Get ready to run a program writing registers up to `max_reg`
+    /// on `eclass`, growing the registers if they're too few.
+    fn start(&mut self, eclass: Id, max_reg: usize) {
+        if self.reg.len() <= max_reg {
+            self.reg.resize(max_reg + 1, Id::from(0));
+        }
+        self.reg[0] = eclass;
+        self.written = 1;
+        self.max_reg = max_reg;
+    }
+
+    /// Bind the children of `node` to the registers from `out` on,
+    /// forgetting the bindings after them.
+    #[inline(always)]
+    fn bind<L: Language>(&mut self, out: Reg, node: &L) {
+        let (out, children) = (out.0 as usize, node.children());
+        let end = out + children.len();
+        debug_assert!(
+            end <= self.max_reg + 1,
+            "Bind wrote r{} past max_reg r{}",
+            end - 1,
+            self.max_reg
+        );
+        self.reg[out..end].copy_from_slice(children);
+        self.written = end;
+    }
+
+    /// Bind `eclass` to the register `out`, forgetting the bindings after
+    /// it.
+    #[inline(always)]
+    fn scan(&mut self, out: Reg, eclass: Id) {
+        let out = out.0 as usize;
+        debug_assert!(out <= self.max_reg, "Scan wrote r{} past max_reg", out);
+        self.reg[out] = eclass;
+        self.written = out + 1;
+    }
+
+    /// Count a candidate of the `Bind` followed by `rest` instructions,
+    /// if profiling, as a backtrack if it led to no match.
+    #[inline(always)]
+    fn count_bind(&mut self, rest: usize, yields_before: usize) {
+        if let Some(counts) = &mut self.bind_counts {
+            if counts.len() <= rest {
+                counts.resize(rest + 1, BindProfile::default());
+            }
+            counts[rest].candidates += 1;
+            if self.yields == yields_before {
+                counts[rest].backtracks += 1;
+            }
+        }
+    }
+
+    /// Take a step of the budget, stopping the search if it's spent.
+    ///
+    /// Binding an enode, scanning an eclass and running a check are a
+    /// step each.
+    #[inline(always)]
+    fn step(&mut self) -> bool {
+        match &mut self.step_budget {
+            None => true,
+            Some(0) => {
+                if let Some(token) = &self.cancel {
+                    if !token.is_cancelled() {
+                        self.step_budget = Some(CANCEL_CHECK_INTERVAL - 1);
+                        return true;
+                    }
+                }
+                self.remaining = 0;
+                self.out_of_steps = true;
+                false
+            }
+            Some(steps) => {
+                *steps -= 1;
+                true
+            }
+        }
+    }
+
     fn run<L, N>(
         &mut self,
         egraph: &EGraph<L, N>,
         instructions: &[Instruction<L>],
         subst: &Subst,
-        yield_fn: &mut impl FnMut(&Self, &Subst),
+        yield_fn: &mut impl FnMut(&Self, &Subst) -> SearchAction,
     ) where
         L: Language,
         N: Analysis<L>,
     {
+        if self.remaining == 0 {
+            return;
+        }
         let mut instructions = instructions.iter();
         while let Some(instruction) = instructions.next() {
             match instruction {
                 Instruction::Bind { i, out, node } => {
                     let remaining_instructions = instructions.as_slice();
                     return for_each_matching_node(&egraph[self.reg(*i)], node, |matched| {
-                        self.reg.truncate(out.0 as usize);
-                        matched.for_each(|id| self.reg.push(id));
-                        self.run(egraph, remaining_instructions, subst, yield_fn)
+                        if self.remaining == 0 || !self.step() {
+                            return;
+                        }
+                        self.n_binds += 1;
+                        self.bind(*out, matched);
+                        let yields = self.yields;
+                        self.run(egraph, remaining_instructions, subst, yield_fn);
+                        self.count_bind(remaining_instructions.len(), yields);
                     });
                 }
-                Instruction::Compare { i, j } => {
-                    if egraph.find(self.reg(*i)) != egraph.find(self.reg(*j)) {
+                Instruction::Scan { out } => {
+                    let remaining_instructions = instructions.as_slice();
+                    for class in egraph.classes() {
+                        if self.remaining == 0 || !self.step() {
+                            return;
+                        }
+                        self.scan(*out, class.id);
+                        self.run(egraph, remaining_instructions, subst, yield_fn)
+                    }
+                    return;
+                }
+                Instruction::Compare { .. } | Instruction::Lookup { .. } | Instruction::Fail => {
+                    if !self.step() || !self.check(egraph, instruction) {
                         return;
                     }
                 }
-                Instruction::Lookup { term, i } => {
-                    self.lookup.clear();
-                    for node in term {
-                        match node {
-                            ENodeOrReg::ENode(node) => {
-                                let look = |i| self.lookup[usize::from(i)];
-                                match egraph.lookup(node.clone().map_children(look)) {
-                                    Some(id) => self.lookup.push(id),
-                                    None => return,
-                                }
-                            }
-                            ENodeOrReg::Reg(r) => {
-                                self.lookup.push(egraph.find(self.reg(*r)));
+            }
+        }
+
+        self.remaining -= 1;
+        self.yields += 1;
+        if yield_fn(self, subst) == SearchAction::Stop {
+            self.remaining = 0;
+        }
+    }
+
+    /// Run a [`Compare`](Instruction::Compare) or
+    /// [`Lookup`](Instruction::Lookup), returning whether it passed.
+    fn check<L, N>(&mut self, egraph: &EGraph<L, N>, instruction: &Instruction<L>) -> bool
+    where
+        L: Language,
+        N: Analysis<L>,
+    {
+        match instruction {
+            Instruction::Bind { .. } | Instruction::Scan { .. } => {
+                unreachable!("{:?} is not a check", instruction)
+            }
+            Instruction::Fail => false,
+            Instruction::Compare { i, j } => egraph.find(self.reg(*i)) == egraph.find(self.reg(*j)),
+            Instruction::Lookup { term, i } => {
+                self.lookup.clear();
+                for node in term {
+                    match node {
+                        ENodeOrReg::ENode(node) => {
+                            let look = |i| self.lookup[usize::from(i)];
+                            match egraph.lookup(node.clone().map_children(look)) {
+                                Some(id) => self.lookup.push(id),
+                                None => return false,
                             }
                         }
+                        ENodeOrReg::Reg(r) => {
+                            self.lookup.push(egraph.find(self.reg(*r)));
+                        }
                     }
+                }
 
-                    let id = egraph.find(self.reg(*i));
-                    if self.lookup.last().copied() != Some(id) {
-                        return;
+                let id = egraph.find(self.reg(*i));
+                self.lookup.last().copied() == Some(id)
+            }
+        }
+    }
+
+    fn run_trie<L, N>(
+        &mut self,
+        egraph: &EGraph<L, N>,
+        trie: &[TrieNode<L>],
+        node: usize,
+        yield_fn: &mut impl FnMut(&Self, usize),
+    ) where
+        L: Language,
+        N: Analysis<L>,
+    {
+        let trie_node = &trie[node];
+        for &pattern in &trie_node.yields {
+            yield_fn(self, pattern);
+        }
+
+        for (instruction, child) in &trie_node.children {
+            match instruction {
+                Instruction::Bind { i, out, node } => {
+                    for_each_matching_node(&egraph[self.reg(*i)], node, |matched| {
+                        self.n_binds += 1;
+                        self.bind(*out, matched);
+                        self.run_trie(egraph, trie, *child, yield_fn)
+                    });
+                }
+                _ => {
+                    if self.check(egraph, instruction) {
+                        self.run_trie(egraph, trie, *child, yield_fn)
                     }
                 }
             }
         }
+    }
+}
 
-        yield_fn(self, subst)
+/// Display the `index`th node of a [`Lookup`](Instruction::Lookup)
+/// term as an s-expression, with registers as `r<n>`.
+fn display_term<L: Language>(term: &[ENodeOrReg<L>], index: usize) -> String {
+    match &term[index] {
+        ENodeOrReg::Reg(r) => format!("r{}", r.0),
+        ENodeOrReg::ENode(node) if node.is_leaf() => node.op_display().to_string(),
+        ENodeOrReg::ENode(node) => {
+            let mut s = format!("({}", node.op_display());
+            for &child in node.children() {
+                s.push(' ');
+                s.push_str(&display_term(term, usize::from(child)));
+            }
+            s.push(')');
+            s
+        }
     }
 }
 
+/// Build a match from the registers of a machine that just finished
+/// running a program with the given subst.
+///
+/// The ids are canonicalized, since the registers of a machine searching
+/// an egraph with pending unions may hold stale ones.
+fn make_subst<L, A>(egraph: &EGraph<L, A>, machine: &Machine, subst: &Subst) -> Subst
+where
+    L: Language,
+    A: Analysis<L>,
+{
+    let subst_vec = subst
+        .vec
+        .iter()
+        // HACK we are reusing Ids here, this is bad
+        .map(|(v, reg_id)| (*v, canonical_reg(egraph, machine, *reg_id)))
+        .collect();
+    Subst { vec: subst_vec }
+}
+
+/// The canonical id in the register a subst maps a variable to.
+fn canonical_reg<L, A>(egraph: &EGraph<L, A>, machine: &Machine, reg_id: Id) -> Id
+where
+    L: Language,
+    A: Analysis<L>,
+{
+    egraph.find(machine.reg(Reg(usize::from(reg_id) as u32)))
+}
+
 struct Compiler<'a, L> {
+    /// The pattern being compiled; a multi-pattern loads each of its
+    /// patterns in turn.
     pattern: &'a PatternAst<L>,
     v2r: IndexMap<Var, Reg>,
     free_vars: Vec<HashSet<Var>>,
     subtree_size: Vec<usize>,
     todo_nodes: HashMap<(Id, Reg), L>,
     instructions: Vec<Instruction<L>>,
+    /// The index of each `Bind` instruction and the pattern node it binds.
+    binds: Vec<(usize, Id)>,
+    next_out: Reg,
 }
 
 impl<'a, L: Language> Compiler<'a, L> {
     fn new(pattern: &'a PatternAst<L>) -> Self {
+        let mut compiler = Self {
+            pattern,
+            free_vars: Default::default(),
+            subtree_size: Default::default(),
+            v2r: Default::default(),
+            todo_nodes: Default::default(),
+            instructions: Default::default(),
+            binds: Default::default(),
+            next_out: Reg(1),
+        };
+        compiler.load_pattern(pattern);
+        compiler
+    }
+
+    /// Start compiling `pattern`, keeping the variables bound so far.
+    fn load_pattern(&mut self, pattern: &'a PatternAst<L>) {
+        debug_assert!(self.todo_nodes.is_empty());
         let len = pattern.as_ref().len();
         let mut free_vars: Vec<HashSet<Var>> = Vec::with_capacity(len);
         let mut subtree_size = Vec::with_capacity(len);
@@ -166,14 +754,9 @@ impl<'a, L: Language> Compiler<'a, L> {
             subtree_size.push(size);
         }
 
-        Self {
-            pattern,
-            free_vars,
-            subtree_size,
-            v2r: Default::default(),
-            todo_nodes: Default::default(),
-            instructions: Default::default(),
-        }
+        self.pattern = pattern;
+        self.free_vars = free_vars;
+        self.subtree_size = subtree_size;
     }
 
     fn add_todo(&mut self, id: Id, reg: Reg) {
@@ -194,8 +777,10 @@ impl<'a, L: Language> Compiler<'a, L> {
     fn next(&mut self) -> Option<((Id, Reg), L)> {
         // we take the max todo according to this key
         // - prefer grounded
-        // - prefer more free variables
+        // - prefer fewer unbound variables
         // - prefer smaller term
+        // the key depends on the variables bound so far, so it's recomputed
+        // for every pick rather than sorting the todos once
         let key = |(id, _): &&(Id, Reg)| {
             let i = usize::from(*id);
             let n_bound = self.free_vars[i]
@@ -204,7 +789,7 @@ impl<'a, L: Language> Compiler<'a, L> {
                 .count();
             let n_free = self.free_vars[i].len() - n_bound;
             let size = self.subtree_size[i] as isize;
-            (n_free == 0, n_free, -size)
+            (n_free == 0, -(n_free as isize), -size)
         };
 
         self.todo_nodes
@@ -222,14 +807,18 @@ impl<'a, L: Language> Compiler<'a, L> {
             .all(|v| self.v2r.contains_key(v))
     }
 
-    fn compile(mut self) -> Program<L> {
-        let last_i = self.pattern.as_ref().len() - 1;
-        let mut next_out = Reg(1);
-
-        self.add_todo(Id::from(last_i), Reg(0));
+    /// Compile the loaded pattern to match the eclass in `root`.
+    fn compile_pattern(&mut self, root: Reg) {
+        match self.pattern.as_ref().len().checked_sub(1) {
+            Some(last_i) => self.add_todo(Id::from(last_i), root),
+            // an empty pattern matches nothing
+            None => self.instructions.push(Instruction::Fail),
+        }
 
         while let Some(((id, reg), node)) = self.next() {
-            if self.is_ground_now(id) && !node.is_leaf() {
+            // ground terms, even leaves, are looked up in the hashcons
+            // rather than found among the enodes of the register's eclass
+            if self.is_ground_now(id) {
                 let extracted = self.pattern.extract(id);
                 self.instructions.push(Instruction::Lookup {
                     i: reg,
@@ -243,11 +832,12 @@ impl<'a, L: Language> Compiler<'a, L> {
                         .collect(),
                 });
             } else {
-                let out = next_out;
-                next_out.0 += node.len() as u32;
+                let out = self.next_out;
+                self.next_out.0 += node.len() as u32;
 
                 // zero out the children so Bind can use it to sort
                 let op = node.clone().map_children(|_| Id::from(0));
+                self.binds.push((self.instructions.len(), id));
                 self.instructions.push(Instruction::Bind {
                     i: reg,
                     node: op,
@@ -259,52 +849,1592 @@ impl<'a, L: Language> Compiler<'a, L> {
                 }
             }
         }
+    }
+
+    /// Bind `var` to every eclass in turn, returning its register.
+    fn scan(&mut self, var: Var) -> Reg {
+        let out = self.next_out;
+        self.next_out.0 += 1;
+        self.instructions.push(Instruction::Scan { out });
+        self.v2r.insert(var, out);
+        out
+    }
+
+    fn compile(mut self, var_order: &[Var]) -> Program<L> {
+        self.compile_pattern(Reg(0));
+        // a simple program yields the children in order
+        let simple = SimpleProgram::new(self.pattern).filter(|simple| simple.vars == var_order);
+        // with one pattern, its node ids name the bound subterms
+        let mut positions = vec![None; self.instructions.len()];
+        for &(i, id) in &self.binds {
+            positions[i] = Some(id);
+        }
+        let mut program = self.finish(var_order, simple);
+        program.positions = positions;
+        program
+    }
+
+    fn finish(self, var_order: &[Var], simple: Option<SimpleProgram<L>>) -> Program<L> {
+        let max_reg = self.next_out.0 as usize - 1;
 
-        let mut subst = Subst::default();
-        for (v, r) in self.v2r {
-            subst.insert(v, Id::from(r.0 as usize));
+        // yield in the given order, not the order the variables were bound
+        debug_assert_eq!(var_order.len(), self.v2r.len());
+        debug_assert_eq!(
+            validate(&self.instructions, self.v2r.iter().map(|(&v, &r)| (v, r))).err(),
+            None
+        );
+        let mut subst = Subst::with_capacity(var_order.len());
+        for v in var_order {
+            subst.insert(*v, Id::from(self.v2r[v].0 as usize));
         }
         Program {
             instructions: self.instructions,
             subst,
+            schema: var_order.into(),
+            simple,
+            max_reg,
+            positions: vec![],
         }
     }
 }
 
 impl<L: Language> Program<L> {
-    pub(crate) fn compile_from_pat(pattern: &PatternAst<L>) -> Self {
-        let program = Compiler::new(pattern).compile();
+    /// Compile `pattern`, yielding its variables in the order they first
+    /// appear.
+    pub fn compile_from_pat(pattern: &PatternAst<L>) -> Self {
+        Self::compile_with_var_order(pattern, &default_var_order(pattern))
+    }
+
+    /// Compile the patterns of a [`MultiPattern`] into one program.
+    ///
+    /// The first pattern matches the searched eclass, which its variable
+    /// is bound to.
+    /// Each later pattern matches the eclass its variable is bound to, or
+    /// every eclass in turn if it isn't bound yet, so variables shared
+    /// between the patterns are compared as they're bound.
+    pub(crate) fn compile_from_multi_pat(patterns: &[(Var, PatternAst<L>)]) -> Self {
+        let empty = PatternAst::default();
+        let first = patterns.first().map_or(&empty, |(_, pattern)| pattern);
+        let mut compiler = Compiler::new(first);
+        let mut var_order = vec![];
+        for (i, (var, pattern)) in patterns.iter().enumerate() {
+            for v in std::iter::once(*var).chain(default_var_order(pattern)) {
+                if !var_order.contains(&v) {
+                    var_order.push(v);
+                }
+            }
+            let root = if i == 0 {
+                compiler.v2r.insert(*var, Reg(0));
+                Reg(0)
+            } else {
+                compiler.load_pattern(pattern);
+                match compiler.v2r.get(var) {
+                    Some(&reg) => reg,
+                    None => compiler.scan(*var),
+                }
+            };
+            compiler.compile_pattern(root);
+        }
+        if patterns.is_empty() {
+            compiler.compile_pattern(Reg(0));
+        }
+        let program = compiler.finish(&var_order, None);
+        log::debug!("Compiled {:?} to {:?}", patterns, program);
+        program
+    }
+
+    /// Compile `patterns` into one program that matches all of them in
+    /// the searched eclass, whose substs have the variables in
+    /// `var_order`, which must be exactly the patterns' variables.
+    ///
+    /// Each pattern is compiled in turn against the root register, so
+    /// variables shared between them are compared as they're bound.
+    pub(crate) fn compile_from_conjunct(patterns: &[&PatternAst<L>], var_order: &[Var]) -> Self {
+        let empty = PatternAst::default();
+        let first = patterns.first().copied().unwrap_or(&empty);
+        let mut compiler = Compiler::new(first);
+        for (i, pattern) in patterns.iter().enumerate() {
+            if i > 0 {
+                compiler.load_pattern(pattern);
+            }
+            compiler.compile_pattern(Reg(0));
+        }
+        let program = compiler.finish(var_order, None);
+        log::debug!("Compiled conjunct {:?} to {:?}", patterns, program);
+        program
+    }
+
+    /// Compile `pattern` into a program whose substs have the variables
+    /// in `var_order`, which must be exactly the pattern's variables.
+    pub(crate) fn compile_with_var_order(pattern: &PatternAst<L>, var_order: &[Var]) -> Self {
+        let program = Compiler::new(pattern).compile(var_order);
         log::debug!("Compiled {:?} to {:?}", pattern.as_ref(), program);
         program
     }
 
+    /// Make a program from hand-written `instructions`, yielding each
+    /// variable of `v2r` from its register, in order.
+    ///
+    /// See [`Instruction`] for what the instructions do.
+    /// This fails if an instruction reads a register that no earlier one
+    /// wrote, if a bind or scan writes from a register at or below one
+    /// that's already written, if a lookup term isn't in order, or if a
+    /// variable's register is never written or it has more than one.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{SymbolLang as S, *};
+    /// // (f ?x ?x): bind an f, and check its children are equal
+    /// let x: Var = "?x".parse().unwrap();
+    /// let program = Program::from_parts(
+    ///     vec![
+    ///         Instruction::Bind {
+    ///             node: S::new("f", vec![Id::from(0); 2]),
+    ///             i: Reg(0),
+    ///             out: Reg(1),
+    ///         },
+    ///         Instruction::Compare { i: Reg(1), j: Reg(2) },
+    ///     ],
+    ///     vec![(x, Reg(1))],
+    /// )
+    /// .unwrap();
+    ///
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let ff = egraph.add_expr(&"(f a a)".parse().unwrap());
+    /// let fg = egraph.add_expr(&"(f a b)".parse().unwrap());
+    /// egraph.rebuild();
+    /// assert_eq!(program.run(&egraph, ff).len(), 1);
+    /// assert!(program.run(&egraph, fg).is_empty());
+    ///
+    /// // r3 is never written
+    /// let bad = Program::<S>::from_parts(vec![], vec![(x, Reg(3))]);
+    /// assert!(matches!(bad, Err(ProgramValidationError::UnwrittenVar { .. })));
+    /// ```
+    pub fn from_parts(
+        instructions: Vec<Instruction<L>>,
+        v2r: impl IntoIterator<Item = (Var, Reg)>,
+    ) -> Result<Self, ProgramValidationError> {
+        let v2r: Vec<(Var, Reg)> = v2r.into_iter().collect();
+        let max_reg = validate(&instructions, v2r.iter().copied())?;
+        let instructions = instructions
+            .into_iter()
+            .map(|instruction| match instruction {
+                Instruction::Bind { node, i, out } => Instruction::Bind {
+                    node: node.map_children(|_| Id::from(0)),
+                    i,
+                    out,
+                },
+                instruction => instruction,
+            })
+            .collect();
+        let mut subst = Subst::with_capacity(v2r.len());
+        for &(var, reg) in &v2r {
+            subst.insert(var, Id::from(reg.0 as usize));
+        }
+        let schema = v2r.iter().map(|&(var, _)| var).collect();
+        Ok(Program {
+            instructions,
+            subst,
+            schema,
+            simple: None,
+            max_reg,
+            positions: vec![],
+        })
+    }
+
+    /// The variables of this program's substs, in order.
+    pub(crate) fn vars(&self) -> &[Var] {
+        &self.schema
+    }
+
+    /// Describe this program's instructions, one per line, with the
+    /// number of candidates each one is estimated to produce when
+    /// searching every eclass of `egraph`.
+    ///
+    /// The estimates come from the operator statistics of `egraph`: a
+    /// `bind` finds as many enodes per row as there are enodes of its
+    /// operator per eclass, and a `compare` or `lookup` passes with the
+    /// probability given as its selectivity.
+    /// The last line is the estimated number of matches.
+    pub fn explain<A: Analysis<L>>(&self, egraph: &EGraph<L, A>) -> String {
+        let ops = self.instructions.iter().flat_map(|instruction| {
+            let ops: Vec<&L> = match instruction {
+                Instruction::Bind { node, .. } => vec![node],
+                Instruction::Lookup { term, .. } => term
+                    .iter()
+                    .filter_map(|n| match n {
+                        ENodeOrReg::ENode(node) => Some(node),
+                        ENodeOrReg::Reg(_) => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            ops
+        });
+        let model = Selectivity::new(egraph, ops);
+        let mut out = format!(
+            "{} eclasses, {} enodes\n",
+            model.n_classes(),
+            model.n_nodes()
+        );
+        let mut rows = model.n_classes() as f64;
+        for instruction in &self.instructions {
+            match instruction {
+                Instruction::Bind { node, i, out: o } => {
+                    let fanout = model.fanout(node);
+                    rows *= fanout;
+                    write!(out, "bind {} in r{}", node.op_display(), i.0).unwrap();
+                    match node.len() {
+                        0 => {}
+                        1 => write!(out, " to r{}", o.0).unwrap(),
+                        n => write!(out, " to r{}..r{}", o.0, o.0 + n as u32 - 1).unwrap(),
+                    }
+                    writeln!(out, ": {:.3} per row, {:.1} candidates", fanout, rows).unwrap();
+                }
+                Instruction::Compare { i, j } => {
+                    let selectivity = model.compare();
+                    rows *= selectivity;
+                    writeln!(
+                        out,
+                        "compare r{} = r{}: selectivity {:.3}, {:.1} candidates",
+                        i.0, j.0, selectivity, rows
+                    )
+                    .unwrap();
+                }
+                Instruction::Lookup { term, i } => {
+                    let selectivity = model.lookup(term.iter().filter_map(|n| match n {
+                        ENodeOrReg::ENode(node) => Some(node),
+                        ENodeOrReg::Reg(_) => None,
+                    }));
+                    rows *= selectivity;
+                    writeln!(
+                        out,
+                        "lookup {} in r{}: selectivity {:.3}, {:.1} candidates",
+                        display_term(term, term.len() - 1),
+                        i.0,
+                        selectivity,
+                        rows
+                    )
+                    .unwrap();
+                }
+                Instruction::Scan { out: o } => {
+                    rows *= model.n_classes() as f64;
+                    writeln!(out, "scan eclasses to r{}: {:.1} candidates", o.0, rows).unwrap();
+                }
+                Instruction::Fail => {
+                    rows = 0.0;
+                    writeln!(out, "fail: the pattern is empty").unwrap();
+                }
+            }
+        }
+        let vars: Vec<String> = self
+            .subst
+            .vec
+            .iter()
+            .map(|(v, reg)| format!("{} = r{}", v, usize::from(*reg)))
+            .collect();
+        write!(out, "yield {}: {:.1} matches", vars.join(", "), rows).unwrap();
+        out
+    }
+
+    /// This program without its [`SimpleProgram`], so it always runs on
+    /// the machine, to compare against.
+    pub(crate) fn general(&self) -> Self {
+        Self {
+            simple: None,
+            ..self.clone()
+        }
+    }
+
+    /// Search `eclass` of `egraph`, which must be clean, returning the
+    /// matches.
     pub fn run<A>(&self, egraph: &EGraph<L, A>, eclass: Id) -> Vec<Subst>
     where
         A: Analysis<L>,
     {
-        let mut machine = Machine::default();
+        self.run_with_limit(egraph, eclass, usize::MAX)
+    }
 
-        assert!(egraph.clean, "Tried to search a dirty e-graph!");
-        assert_eq!(machine.reg.len(), 0);
-        machine.reg.push(eclass);
+    /// Like [`run`](Program::run), but stops early with
+    /// [`Error::Cancelled`] once `token` is
+    /// [cancelled](CancellationToken::cancel).
+    ///
+    /// The token is checked every so many steps of the search, where
+    /// binding an enode and scanning an eclass are a step each, so even
+    /// a search with exponentially many partial matches stops soon after
+    /// the token is cancelled.
+    /// To search every eclass, run this on each, like
+    /// [`Searcher::search`] does with [`run`](Program::run).
+    pub fn run_cancellable<A>(
+        &self,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        token: &CancellationToken,
+    ) -> Result<Vec<Subst>, Error>
+    where
+        A: Analysis<L>,
+    {
+        if token.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        let mut machine = Machine {
+            step_budget: Some(CANCEL_CHECK_INTERVAL),
+            cancel: Some(token.clone()),
+            ..Default::default()
+        };
+        let matches = self.run_in(&mut machine, egraph, eclass, usize::MAX);
+        if machine.out_of_steps {
+            Err(Error::Cancelled)
+        } else {
+            Ok(matches)
+        }
+    }
 
+    /// Like [`run`](Program::run), but stop searching after `limit`
+    /// matches.
+    pub fn run_with_limit<A>(&self, egraph: &EGraph<L, A>, eclass: Id, limit: usize) -> Vec<Subst>
+    where
+        A: Analysis<L>,
+    {
+        self.run_in(&mut Machine::default(), egraph, eclass, limit)
+    }
+
+    /// Like [`run`](Program::run), but reuse the allocations in
+    /// `buffers` from earlier runs, and append the matches to `substs`
+    /// instead of returning them.
+    ///
+    /// Searching many eclasses this way, with the same buffers and
+    /// clearing `substs` between them, allocates only as the buffers
+    /// grow.
+    ///
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// egraph.add_expr(&"(+ (* a b) (* b a))".parse().unwrap());
+    /// egraph.rebuild();
+    ///
+    /// let pattern: PatternAst<S> = "(* ?x ?y)".parse().unwrap();
+    /// let program = Program::compile_from_pat(&pattern);
+    /// let mut buffers = MachineBuffers::default();
+    /// let mut substs = vec![];
+    /// for class in egraph.classes() {
+    ///     substs.clear();
+    ///     program.run_with_buffers(&egraph, class.id, &mut buffers, &mut substs);
+    ///     assert_eq!(substs, program.run(&egraph, class.id));
+    /// }
+    /// ```
+    pub fn run_with_buffers<A>(
+        &self,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        buffers: &mut MachineBuffers,
+        substs: &mut Vec<Subst>,
+    ) where
+        A: Analysis<L>,
+    {
+        self.extend_in(&mut buffers.machine, egraph, eclass, usize::MAX, substs)
+    }
+
+    pub(crate) fn run_in<A>(
+        &self,
+        machine: &mut Machine,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        limit: usize,
+    ) -> Vec<Subst>
+    where
+        A: Analysis<L>,
+    {
         let mut matches = Vec::new();
+        self.extend_in(machine, egraph, eclass, limit, &mut matches);
+        matches
+    }
+
+    /// Run on `machine`, appending the matches to `matches`.
+    fn extend_in<A>(
+        &self,
+        machine: &mut Machine,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        limit: usize,
+        matches: &mut Vec<Subst>,
+    ) where
+        A: Analysis<L>,
+    {
+        assert!(
+            egraph.clean,
+            "Tried to search a dirty e-graph! Use EGraph::with_clean_view to search while applying."
+        );
+        egraph.unionfind.check_tag(eclass);
+        machine.remaining = limit;
+        let start = matches.len();
+        if let Some(simple) = &self.simple {
+            simple.run(machine, egraph, eclass, matches);
+            log::trace!("Ran simple program, found {:?}", &matches[start..]);
+            return;
+        }
+
+        machine.start(eclass, self.max_reg);
+
         machine.run(
             egraph,
             &self.instructions,
             &self.subst,
             &mut |machine, subst| {
-                let subst_vec = subst
-                    .vec
+                matches.push(make_subst(egraph, machine, subst));
+                SearchAction::Continue
+            },
+        );
+
+        log::trace!("Ran program, found {:?}", &matches[start..]);
+    }
+
+    /// Like [`run`](Program::run), but call `f` with each match as it is
+    /// found instead of collecting them, stopping as soon as it returns
+    /// [`SearchAction::Stop`].
+    pub fn run_with<A>(
+        &self,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        f: impl FnMut(Subst) -> SearchAction,
+    ) where
+        A: Analysis<L>,
+    {
+        self.run_with_in(&mut Machine::default(), egraph, eclass, f)
+    }
+
+    fn run_with_in<A>(
+        &self,
+        machine: &mut Machine,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        mut f: impl FnMut(Subst) -> SearchAction,
+    ) where
+        A: Analysis<L>,
+    {
+        assert!(
+            egraph.clean,
+            "Tried to search a dirty e-graph! Use EGraph::with_clean_view to search while applying."
+        );
+        egraph.unionfind.check_tag(eclass);
+        machine.remaining = usize::MAX;
+        if let Some(simple) = &self.simple {
+            for_each_matching_node(&egraph[eclass], &simple.node, |matched| {
+                if machine.remaining == 0 {
+                    return;
+                }
+                machine.n_binds += 1;
+                let vec = simple
+                    .vars
                     .iter()
-                    // HACK we are reusing Ids here, this is bad
-                    .map(|(v, reg_id)| (*v, machine.reg(Reg(usize::from(*reg_id) as u32))))
+                    .copied()
+                    .zip(matched.children().iter().map(|&id| egraph.find(id)));
+                if f(Subst { vec: vec.collect() }) == SearchAction::Stop {
+                    machine.remaining = 0;
+                }
+            });
+        } else {
+            machine.start(eclass, self.max_reg);
+            machine.run(
+                egraph,
+                &self.instructions,
+                &self.subst,
+                &mut |machine, subst| f(make_subst(egraph, machine, subst)),
+            );
+        }
+    }
+
+    /// Like [`run`](Program::run), but each match is a [`PackedSubst`]
+    /// over this program's schema.
+    pub fn run_packed<A>(&self, egraph: &EGraph<L, A>, eclass: Id) -> Vec<PackedSubst>
+    where
+        A: Analysis<L>,
+    {
+        assert!(
+            egraph.clean,
+            "Tried to search a dirty e-graph! Use EGraph::with_clean_view to search while applying."
+        );
+        egraph.unionfind.check_tag(eclass);
+        let mut matches = Vec::new();
+        if let Some(simple) = &self.simple {
+            // the schema is the children's variables in order
+            for_each_matching_node(&egraph[eclass], &simple.node, |matched| {
+                let ids = matched
+                    .children()
+                    .iter()
+                    .map(|&id| egraph.find(id))
                     .collect();
-                matches.push(Subst { vec: subst_vec });
-            },
+                matches.push(PackedSubst::new(self.schema.clone(), ids));
+            });
+        } else {
+            let mut machine = Machine {
+                remaining: usize::MAX,
+                ..Default::default()
+            };
+            machine.start(eclass, self.max_reg);
+            machine.run(
+                egraph,
+                &self.instructions,
+                &self.subst,
+                &mut |machine, subst| {
+                    let ids = subst
+                        .vec
+                        .iter()
+                        .map(|(_, reg_id)| canonical_reg(egraph, machine, *reg_id))
+                        .collect();
+                    matches.push(PackedSubst::new(self.schema.clone(), ids));
+                    SearchAction::Continue
+                },
+            );
+        }
+
+        log::trace!("Ran packed program, found {:?}", matches);
+        matches
+    }
+
+    /// Like [`run`](Program::run), but append the matches to `arena`
+    /// over this program's schema, returning their range.
+    pub fn run_into<A>(
+        &self,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        arena: &mut MatchArena,
+    ) -> SubstRange
+    where
+        A: Analysis<L>,
+    {
+        self.run_into_with_limit(egraph, eclass, arena, usize::MAX)
+    }
+
+    /// Like [`run_into`](Program::run_into), but stop searching after
+    /// `limit` matches, or when the arena's
+    /// [step budget](MatchArena::set_step_budget) runs out.
+    pub fn run_into_with_limit<A>(
+        &self,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        arena: &mut MatchArena,
+        limit: usize,
+    ) -> SubstRange
+    where
+        A: Analysis<L>,
+    {
+        assert!(
+            egraph.clean,
+            "Tried to search a dirty e-graph! Use EGraph::with_clean_view to search while applying."
         );
+        egraph.unionfind.check_tag(eclass);
+        let mut range = arena.start_range(self.schema.clone());
+        let mut machine = std::mem::take(&mut arena.machine);
+        machine.remaining = limit;
+        if let Some(simple) = &self.simple {
+            // the schema is the children's variables in order
+            for_each_matching_node(&egraph[eclass], &simple.node, |matched| {
+                if range.len() < limit && machine.step() {
+                    let ids = matched.children().iter().map(|&id| egraph.find(id));
+                    arena.push(&mut range, ids);
+                }
+            });
+        } else {
+            machine.start(eclass, self.max_reg);
+            machine.run(
+                egraph,
+                &self.instructions,
+                &self.subst,
+                &mut |machine, subst| {
+                    let ids = subst
+                        .vec
+                        .iter()
+                        .map(|(_, reg_id)| canonical_reg(egraph, machine, *reg_id));
+                    arena.push(&mut range, ids);
+                    SearchAction::Continue
+                },
+            );
+            self.roll_up_profile(&mut machine);
+        }
+        arena.machine = machine;
+
+        log::trace!("Ran program into arena, found {} matches", range.len());
+        range
+    }
+}
+
+impl<L: Language> Program<L> {
+    /// Move the bind counts of a profiling `machine` that just ran this
+    /// program into its profile by pattern node, dropping those of
+    /// instructions that don't bind a known node.
+    fn roll_up_profile(&self, machine: &mut Machine) {
+        let Machine {
+            bind_counts,
+            bind_profile,
+            ..
+        } = machine;
+        let counts = match bind_counts {
+            Some(counts) => counts,
+            None => return,
+        };
+        let n = self.instructions.len();
+        for (rest, count) in counts.iter_mut().enumerate() {
+            let count = std::mem::take(count);
+            if count.candidates == 0 {
+                continue;
+            }
+            let position = n.checked_sub(rest + 1).and_then(|i| self.positions.get(i));
+            if let Some(&Some(id)) = position {
+                let profile = bind_profile.entry(id).or_default();
+                profile.candidates += count.candidates;
+                profile.backtracks += count.backtracks;
+            }
+        }
+    }
+}
+
+impl<L: Language, A: Analysis<L>> Searcher<L, A> for Program<L> {
+    fn search_eclass(&self, egraph: &EGraph<L, A>, eclass: Id) -> Option<SearchMatches<L>> {
+        self.search_eclass_with_limit(egraph, eclass, usize::MAX)
+    }
+
+    fn search_eclass_with_limit(
+        &self,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        limit: usize,
+    ) -> Option<SearchMatches<L>> {
+        let substs = self.run_with_limit(egraph, eclass, limit);
+        if substs.is_empty() {
+            None
+        } else {
+            Some(SearchMatches {
+                eclass: egraph.find(eclass),
+                substs,
+                ast: None,
+            })
+        }
+    }
 
-        log::trace!("Ran program, found {:?}", matches);
+    fn search_into_with_limit(
+        &self,
+        egraph: &EGraph<L, A>,
+        arena: &mut MatchArena,
+        limit_per_class: usize,
+    ) -> Vec<ArenaMatches<L>> {
+        let mut matches = vec![];
+        for class in egraph.classes() {
+            if arena.step_budget_exhausted() {
+                break;
+            }
+            let substs = self.run_into_with_limit(egraph, class.id, arena, limit_per_class);
+            if !substs.is_empty() {
+                matches.push(ArenaMatches {
+                    eclass: class.id,
+                    substs,
+                    ast: None,
+                });
+            }
+        }
         matches
     }
+
+    fn search_into(&self, egraph: &EGraph<L, A>, arena: &mut MatchArena) -> Vec<ArenaMatches<L>> {
+        self.search_into_with_limit(egraph, arena, usize::MAX)
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.schema.to_vec()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TrieNode<L> {
+    /// Indices of the patterns whose programs end at this node.
+    yields: Vec<usize>,
+    /// The next instructions, each leading to another node.
+    children: Vec<(Instruction<L>, usize)>,
+}
+
+impl<L> Default for TrieNode<L> {
+    fn default() -> Self {
+        Self {
+            yields: vec![],
+            children: vec![],
+        }
+    }
+}
+
+/// The compiled programs of several patterns, sharing common prefixes.
+///
+/// Searching a [`Pattern`] runs a compiled program that binds enodes
+/// one at a time.
+/// When many patterns start the same way, say `(* ?a ...)`, searching
+/// each of them separately repeats that work for every pattern.
+/// A [`ProgramSet`] merges the programs into a trie, so shared
+/// instructions are run once per eclass for the whole set.
+///
+/// # Example
+/// ```
+/// use egg::{*, SymbolLang as S};
+/// let patterns: Vec<PatternAst<S>> = vec![
+///     "(* ?a (+ ?b ?c))".parse().unwrap(),
+///     "(* ?a 1)".parse().unwrap(),
+///     "(* ?a ?a)".parse().unwrap(),
+/// ];
+/// let set = ProgramSet::compile(&patterns);
+///
+/// let mut egraph = EGraph::<S, ()>::default();
+/// let id = egraph.add_expr(&"(* x 1)".parse().unwrap());
+/// egraph.rebuild();
+///
+/// let matches = set.run(&egraph, id);
+/// assert_eq!(matches.len(), 1);
+/// assert_eq!(matches[0].0, 1);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramSet<L> {
+    trie: Vec<TrieNode<L>>,
+    substs: Vec<Subst>,
+    /// The highest register any of the programs writes.
+    max_reg: usize,
+}
+
+impl<L: Language> ProgramSet<L> {
+    /// Compile the given patterns into one [`ProgramSet`].
+    ///
+    /// Matches found by [`run`](ProgramSet::run) are tagged with the
+    /// index of their pattern in `patterns`.
+    pub fn compile(patterns: &[PatternAst<L>]) -> Self {
+        let mut trie = vec![TrieNode::default()];
+        let mut substs = Vec::with_capacity(patterns.len());
+        let mut max_reg = 0;
+        for (index, pattern) in patterns.iter().enumerate() {
+            let program = Program::compile_from_pat(pattern);
+            max_reg = max_reg.max(program.max_reg);
+            let mut node = 0;
+            for instruction in program.instructions {
+                let existing = trie[node]
+                    .children
+                    .iter()
+                    .find(|(i, _)| *i == instruction)
+                    .map(|(_, child)| *child);
+                node = match existing {
+                    Some(child) => child,
+                    None => {
+                        let child = trie.len();
+                        trie.push(TrieNode::default());
+                        trie[node].children.push((instruction, child));
+                        child
+                    }
+                };
+            }
+            trie[node].yields.push(index);
+            substs.push(program.subst);
+        }
+
+        let set = Self {
+            trie,
+            substs,
+            max_reg,
+        };
+        log::debug!("Compiled {} patterns to {:?}", patterns.len(), set);
+        set
+    }
+
+    /// Search one eclass for all of the patterns at once.
+    ///
+    /// Returns each match along with the index of the pattern it
+    /// matches. The matches of each pattern are the same, and in the same
+    /// order, as searching for that pattern alone.
+    pub fn run<A>(&self, egraph: &EGraph<L, A>, eclass: Id) -> Vec<(usize, Subst)>
+    where
+        A: Analysis<L>,
+    {
+        self.run_in(&mut Machine::default(), egraph, eclass)
+    }
+
+    /// Run on the machine of `arena`, reusing its allocations.
+    pub(crate) fn run_in_arena<A>(
+        &self,
+        arena: &mut MatchArena,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+    ) -> Vec<(usize, Subst)>
+    where
+        A: Analysis<L>,
+    {
+        self.run_in(&mut arena.machine, egraph, eclass)
+    }
+
+    fn run_in<A>(
+        &self,
+        machine: &mut Machine,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+    ) -> Vec<(usize, Subst)>
+    where
+        A: Analysis<L>,
+    {
+        assert!(
+            egraph.clean,
+            "Tried to search a dirty e-graph! Use EGraph::with_clean_view to search while applying."
+        );
+        egraph.unionfind.check_tag(eclass);
+        machine.start(eclass, self.max_reg);
+
+        let mut matches = Vec::new();
+        machine.run_trie(egraph, &self.trie, 0, &mut |machine, pattern| {
+            matches.push((pattern, make_subst(egraph, machine, &self.substs[pattern])))
+        });
+
+        log::trace!("Ran program set, found {:?}", matches);
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SymbolLang as S;
+
+    fn prefix_sharing_patterns() -> Vec<PatternAst<S>> {
+        let ops = ["+", "-", "*", "/", "f"];
+        let mut patterns = vec![];
+        for op in &ops {
+            patterns.push(format!("(* ?a ({} ?b ?c))", op));
+            patterns.push(format!("(* ?a ({} ?b ?b))", op));
+            patterns.push(format!("(* ({} ?b ?c) ?a)", op));
+            patterns.push(format!("(* ?a ({} ?b 1))", op));
+        }
+        patterns.iter().map(|p| p.parse().unwrap()).collect()
+    }
+
+    fn test_egraph() -> EGraph<S, ()> {
+        let mut egraph = EGraph::default();
+        for expr in &[
+            "(* x (+ y z))",
+            "(* (- y y) (f x x))",
+            "(* (/ q 1) (* x (+ y 1)))",
+            "(* (f a b) a)",
+        ] {
+            egraph.add_expr(&expr.parse().unwrap());
+        }
+        let x = egraph.add(S::leaf("x"));
+        let y = egraph.add(S::leaf("y"));
+        egraph.union(x, y);
+        egraph.rebuild();
+        egraph
+    }
+
+    /// A tiny xorshift generator, so the fuzzing is deterministic.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn random_egraph(seed: u64) -> EGraph<S, ()> {
+        let mut rng = Rng(seed);
+        let mut egraph = EGraph::default();
+        let mut ids = vec![egraph.add(S::leaf("x")), egraph.add(S::leaf("y"))];
+        for _ in 0..200 {
+            let (op, arity) = [("+", 2), ("*", 2), ("f", 1), ("g", 3)][rng.below(4)];
+            let children = (0..arity).map(|_| ids[rng.below(ids.len())]).collect();
+            ids.push(egraph.add(S::new(op, children)));
+        }
+        // make one big class, so matching uses the sorted nodes
+        let leaves: Vec<Id> = (0..10)
+            .map(|i| egraph.add(S::leaf(format!("a{}", i))))
+            .collect();
+        let big = ids[0];
+        for &a in &leaves {
+            for &b in &leaves[..6] {
+                let sum = egraph.add(S::new("+", vec![a, b]));
+                egraph.union(big, sum);
+            }
+        }
+        ids.extend(leaves);
+        for _ in 0..10 {
+            egraph.union(ids[rng.below(ids.len())], ids[rng.below(ids.len())]);
+        }
+        egraph.rebuild();
+        egraph
+    }
+
+    #[test]
+    fn simple_programs_match_machine() {
+        let patterns = [
+            ("(+ ?a ?b)", true),
+            ("(* ?b ?a)", true),
+            ("(f ?a)", true),
+            ("(g ?c ?a ?b)", true),
+            ("x", true),
+            ("(+ ?a ?a)", false),
+            ("(+ ?a (f ?b))", false),
+            ("(* ?a x)", false),
+            ("?a", false),
+        ];
+        for seed in 1..10 {
+            let egraph = random_egraph(seed);
+            assert!(egraph.classes().any(|c| c.len() >= 50));
+            for &(pattern, is_simple) in &patterns {
+                let ast: PatternAst<S> = pattern.parse().unwrap();
+                let program = Program::compile_from_pat(&ast);
+                assert_eq!(program.simple.is_some(), is_simple, "{}", pattern);
+                let general = program.general();
+                for class in egraph.classes() {
+                    let expected = general.run(&egraph, class.id);
+                    assert_eq!(program.run(&egraph, class.id), expected, "{}", pattern);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn simple_programs_take_steps() {
+        let egraph = random_egraph(1);
+        let program = Program::compile_from_pat(&"(+ ?a ?b)".parse().unwrap());
+        assert!(program.simple.is_some());
+        let class = egraph
+            .classes()
+            .max_by_key(|class| program.run(&egraph, class.id).len())
+            .unwrap();
+        let all = program.run(&egraph, class.id);
+        assert!(all.len() > 3);
+
+        let mut machine = Machine {
+            step_budget: Some(3),
+            ..Default::default()
+        };
+        let matches = program.run_in(&mut machine, &egraph, class.id, usize::MAX);
+        assert!(machine.out_of_steps);
+        assert_eq!(matches, all[..3]);
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut machine = Machine {
+            step_budget: Some(0),
+            cancel: Some(token),
+            ..Default::default()
+        };
+        assert!(program
+            .run_in(&mut machine, &egraph, class.id, usize::MAX)
+            .is_empty());
+        assert!(machine.out_of_steps);
+    }
+
+    #[test]
+    fn reused_buffers_match_fresh_machines() {
+        // programs of different sizes, so the buffers grow and shrink
+        let patterns = [
+            "(g (+ ?a ?b) (f ?c) (* ?d ?e))",
+            "(+ ?a ?b)",
+            "(+ ?a (f ?b))",
+            "(* ?a ?a)",
+            "?a",
+            "(g ?a (g ?b ?c ?d) ?e)",
+        ];
+        let programs: Vec<Program<S>> = patterns
+            .iter()
+            .map(|p| Program::compile_from_pat(&p.parse().unwrap()))
+            .collect();
+        let mut buffers = MachineBuffers::default();
+        let mut substs = vec![];
+        for seed in 1..5 {
+            let egraph = random_egraph(seed);
+            for class in egraph.classes() {
+                for (program, pattern) in programs.iter().zip(&patterns) {
+                    let expected = program.run(&egraph, class.id);
+                    // appends after what's already there
+                    substs.push(Subst::default());
+                    program.run_with_buffers(&egraph, class.id, &mut buffers, &mut substs);
+                    assert_eq!(substs[0], Subst::default());
+                    assert_eq!(substs[1..], expected[..], "{}", pattern);
+                    substs.clear();
+                }
+            }
+        }
+
+        // a pattern's search reuses one machine too
+        let egraph = random_egraph(5);
+        for pattern in &patterns {
+            let pattern: Pattern<S> = pattern.parse().unwrap();
+            let program = Program::compile_from_pat(&pattern.ast);
+            for m in pattern.search(&egraph) {
+                assert_eq!(m.substs, program.run(&egraph, m.eclass));
+            }
+            // and its search_eclass one per thread
+            for class in egraph.classes() {
+                let substs = pattern.search_eclass(&egraph, class.id).map(|m| m.substs);
+                assert_eq!(substs.unwrap_or_default(), program.run(&egraph, class.id));
+            }
+        }
+    }
+
+    #[test]
+    fn packed_substs_match_and_save_memory() {
+        // (g (f ?v0 .. ?v12) (f ?v13 .. ?v24)), 25 variables
+        let vars: Vec<String> = (0..25).map(|i| format!("?v{}", i)).collect();
+        let pattern = format!(
+            "(g (f {}) (f {}))",
+            vars[..13].join(" "),
+            vars[13..].join(" ")
+        );
+        let ast: PatternAst<S> = pattern.parse().unwrap();
+        let program = Program::compile_from_pat(&ast);
+        assert!(program.simple.is_none());
+
+        let mut egraph = EGraph::<S, ()>::default();
+        let leaves = [egraph.add(S::leaf("x")), egraph.add(S::leaf("y"))];
+        let mut fs = vec![];
+        for i in 0..20 {
+            // the low bits of i, so every f is different
+            let children = (0..13).map(|j| leaves[(i >> (j % 5)) & 1]).collect();
+            fs.push(egraph.add(S::new("f", children)));
+        }
+        let root = egraph.add(S::new("g", vec![fs[0], fs[1]]));
+        // put all the fs in two classes, so each g node matches many ways
+        for i in 2..20 {
+            egraph.union(fs[i % 2], fs[i]);
+        }
+        egraph.rebuild();
+
+        let substs = program.run(&egraph, root);
+        let packed = program.run_packed(&egraph, root);
+        assert_eq!(packed.len(), substs.len());
+        assert!(packed.len() >= 100);
+        for (p, s) in packed.iter().zip(&substs) {
+            assert!(Arc::ptr_eq(p.schema(), &program.schema));
+            assert_eq!(&p.to_subst(), s);
+            for v in &vars {
+                let v: Var = v.parse().unwrap();
+                assert_eq!(p[v], s[v]);
+            }
+        }
+
+        use std::mem::size_of;
+        let subst_bytes: usize = substs
+            .iter()
+            .map(|s| size_of::<Subst>() + s.vec.capacity() * size_of::<(Var, Id)>())
+            .sum();
+        let packed_bytes: usize = packed
+            .iter()
+            .map(|p| size_of::<PackedSubst>() + p.iter().count() * size_of::<Id>())
+            .sum();
+        let n = packed.len();
+        assert_eq!(
+            packed_bytes,
+            n * (size_of::<PackedSubst>() + 25 * size_of::<Id>())
+        );
+        assert!(subst_bytes >= n * (size_of::<Subst>() + 25 * size_of::<(Var, Id)>()));
+        // at most two thirds the size
+        assert!(packed_bytes * 3 < subst_bytes * 2);
+    }
+
+    /// Ten leaves, ten `+`s and two `f`s, all in their own eclasses.
+    fn stats_egraph() -> EGraph<S, ()> {
+        let mut egraph = EGraph::default();
+        let leaves: Vec<Id> = (0..10)
+            .map(|i| egraph.add(S::leaf(format!("a{}", i))))
+            .collect();
+        for i in 0..10 {
+            egraph.add(S::new("+", vec![leaves[i], leaves[(i + 1) % 10]]));
+        }
+        egraph.add(S::new("f", vec![leaves[0]]));
+        egraph.add(S::new("f", vec![leaves[1]]));
+        egraph.rebuild();
+        egraph
+    }
+
+    fn explain(pattern: &str, egraph: &EGraph<S, ()>) -> String {
+        let ast: PatternAst<S> = pattern.parse().unwrap();
+        Program::compile_from_pat(&ast).explain(egraph)
+    }
+
+    #[test]
+    fn explain_estimates_from_op_stats() {
+        let egraph = stats_egraph();
+        assert_eq!(
+            explain("(+ (f ?x) ?y)", &egraph),
+            "22 eclasses, 22 enodes
+bind + in r0 to r1..r2: 0.455 per row, 10.0 candidates
+bind f in r1 to r3: 0.091 per row, 0.9 candidates
+yield ?x = r3, ?y = r2: 0.9 matches"
+        );
+        assert_eq!(
+            explain("(+ ?x ?x)", &egraph),
+            "22 eclasses, 22 enodes
+bind + in r0 to r1..r2: 0.455 per row, 10.0 candidates
+compare r2 = r1: selectivity 0.045, 0.5 candidates
+yield ?x = r1: 0.5 matches"
+        );
+        assert_eq!(
+            explain("(+ ?x (f ?x))", &egraph),
+            "22 eclasses, 22 enodes
+bind + in r0 to r1..r2: 0.455 per row, 10.0 candidates
+lookup (f r1) in r2: selectivity 0.091, 0.9 candidates
+yield ?x = r1: 0.9 matches"
+        );
+    }
+
+    #[test]
+    fn explain_ranks_binds_by_op_frequency() {
+        let egraph = stats_egraph();
+        let candidates = |plan: &str, op: &str| -> f64 {
+            let line = plan
+                .lines()
+                .find(|l| l.starts_with(&format!("bind {} ", op)))
+                .unwrap();
+            let n = line.rsplit(", ").next().unwrap();
+            n.trim_end_matches(" candidates").parse().unwrap()
+        };
+
+        // the rare f as the root bind leaves fewer candidates to extend
+        let plus_first = explain("(+ (f ?x) ?y)", &egraph);
+        let f_first = explain("(f (+ ?x ?y))", &egraph);
+        assert!(f_first.lines().nth(1).unwrap().starts_with("bind f in r0"));
+        assert!(candidates(&f_first, "f") < candidates(&plus_first, "+"));
+        assert_eq!(candidates(&f_first, "f"), 2.0);
+        assert_eq!(candidates(&plus_first, "+"), 10.0);
+
+        // an operator that isn't in the egraph can never match
+        let plan = explain("(+ ?x (g ?x))", &egraph);
+        assert!(plan.ends_with("0.0 matches"), "{}", plan);
+    }
+
+    #[test]
+    fn deep_programs_grow_the_machine() {
+        // each level's eclass has two enodes, (f a<i> next) and (f b<i> next)
+        let depth = 10;
+        let mut egraph = EGraph::<S, ()>::default();
+        let mut next = egraph.add(S::leaf("z"));
+        for i in (0..depth).rev() {
+            let a = egraph.add(S::leaf(format!("a{}", i)));
+            let b = egraph.add(S::leaf(format!("b{}", i)));
+            let fa = egraph.add(S::new("f", vec![a, next]));
+            let fb = egraph.add(S::new("f", vec![b, next]));
+            egraph.union(fa, fb);
+            next = egraph.find(fa);
+        }
+        egraph.rebuild();
+        let root = next;
+
+        let mut pattern = "?z".to_string();
+        for i in (0..depth).rev() {
+            pattern = format!("(f ?v{} {})", i, pattern);
+        }
+        let program = Program::compile_from_pat(&pattern.parse().unwrap());
+        assert_eq!(program.max_reg, 2 * depth);
+        let shallow = Program::compile_from_pat(&"(f ?a (f ?b ?c))".parse().unwrap());
+        assert_eq!(shallow.max_reg, 4);
+
+        // a machine sized for a shallow program grows for the deep one
+        let mut arena = MatchArena::new();
+        let before = shallow.run_into(&egraph, root, &mut arena);
+        let deep = program.run_into(&egraph, root, &mut arena);
+        let after = shallow.run_into(&egraph, root, &mut arena);
+        assert_eq!((before.len(), deep.len(), after.len()), (4, 1 << depth, 4));
+    }
+
+    define_language! {
+        enum Payload {
+            "list" = List(Box<[Id]>),
+            Num(i32),
+            Call(Symbol, Vec<Id>),
+        }
+    }
+
+    define_language! {
+        enum ShapePayload {
+            "list" = List(Box<[Id]>),
+            Num(i32),
+            Call(Symbol, Vec<Id>),
+        }
+        search_by_shape
+    }
+
+    /// Search one big eclass of lists and calls of many arities,
+    /// returning the matches.
+    fn search_payload<L: Language + FromOp>() -> Vec<String> {
+        let mut egraph = EGraph::<L, ()>::default();
+        let root = egraph.add_expr(&"0".parse().unwrap());
+        for i in 0..120 {
+            let children: Vec<String> = (0..i % 7).map(|j| ((i + j) % 5).to_string()).collect();
+            let op = if i % 2 == 0 {
+                "list".to_string()
+            } else {
+                format!("f{}", i % 4)
+            };
+            let expr = if children.is_empty() {
+                op
+            } else {
+                format!("({} {})", op, children.join(" "))
+            };
+            let id = egraph.add_expr(&expr.parse().unwrap());
+            egraph.union(root, id);
+        }
+        egraph.rebuild();
+        let root = egraph.find(root);
+        assert!(egraph[root].len() >= 50);
+
+        let patterns: Vec<Pattern<L>> = [
+            "(list ?a ?b)",
+            "(list ?a ?b ?c)",
+            "list",
+            "(f1 ?a ?b ?c)",
+            "(f3 ?a)",
+        ]
+        .iter()
+        .map(|p| p.parse().unwrap())
+        .collect();
+        let mut matches = vec![];
+        for (i, pattern) in patterns.iter().enumerate() {
+            let substs = pattern.search_eclass(&egraph, root).unwrap().substs;
+            matches.extend(substs.iter().map(|s| format!("{}: {:?}", i, s)));
+        }
+        matches.sort();
+        matches
+    }
+
+    #[test]
+    fn search_by_shape_finds_the_same_matches() {
+        let by_ord = search_payload::<Payload>();
+        let by_shape = search_payload::<ShapePayload>();
+        assert!(ShapePayload::SEARCH_BY_SHAPE && !Payload::SEARCH_BY_SHAPE);
+        // every pattern matches, and both orders find the same matches
+        for i in 0..5 {
+            let prefix = format!("{}: ", i);
+            assert!(by_ord.iter().any(|m| m.starts_with(&prefix)));
+        }
+        assert_eq!(by_ord, by_shape);
+    }
+
+    #[test]
+    fn run_with_stops_binding_early() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let big = egraph.add(S::leaf("big"));
+        for i in 0..100 {
+            let a = egraph.add(S::leaf(format!("a{}", i)));
+            let g = egraph.add(S::new("g", vec![a]));
+            let f = egraph.add(S::new("f", vec![g]));
+            let h = egraph.add(S::new("h", vec![a]));
+            egraph.union(big, f);
+            egraph.union(big, h);
+        }
+        egraph.rebuild();
+
+        for &(pattern, is_simple) in &[("(f (g ?a))", false), ("(h ?a)", true)] {
+            let program = Program::compile_from_pat(&pattern.parse().unwrap());
+            assert_eq!(program.simple.is_some(), is_simple);
+
+            let mut full = Machine::default();
+            let mut all = vec![];
+            program.run_with_in(&mut full, &egraph, big, |subst| {
+                all.push(subst);
+                SearchAction::Continue
+            });
+            assert_eq!(all.len(), 100);
+            assert_eq!(all, program.run(&egraph, big));
+
+            let mut lazy = Machine::default();
+            let mut first = vec![];
+            program.run_with_in(&mut lazy, &egraph, big, |subst| {
+                first.push(subst);
+                if first.len() == 3 {
+                    SearchAction::Stop
+                } else {
+                    SearchAction::Continue
+                }
+            });
+            assert_eq!(first, &all[..3]);
+            assert!(
+                lazy.n_binds * 20 < full.n_binds,
+                "{}: {} binds stopping early vs {} in full",
+                pattern,
+                lazy.n_binds,
+                full.n_binds
+            );
+        }
+    }
+
+    #[test]
+    fn ground_subpatterns_are_checked_first() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let big = egraph.add_expr(&"(+ (* p q) 0)".parse().unwrap());
+        for i in 0..50 {
+            let expr = format!("(+ (* x{} y{}) 1)", i, i);
+            let sum = egraph.add_expr(&expr.parse().unwrap());
+            egraph.union(big, sum);
+        }
+        egraph.rebuild();
+
+        let ordered = Program::compile_from_pat(&"(+ (* ?a ?b) 0)".parse().unwrap());
+        let zero = vec![ENodeOrReg::ENode(S::leaf("0"))];
+        assert!(
+            matches!(&ordered.instructions[1], Instruction::Lookup { term, .. } if *term == zero),
+            "{:?}",
+            ordered.instructions
+        );
+        // the order the subpatterns were found in binds `*` before
+        // checking the `0`
+        let mut naive = ordered.clone();
+        naive.instructions.swap(1, 2);
+
+        let mut ordered_machine = Machine::default();
+        let mut naive_machine = Machine::default();
+        let matches = ordered.run_in(&mut ordered_machine, &egraph, big, usize::MAX);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(
+            naive.run_in(&mut naive_machine, &egraph, big, usize::MAX),
+            matches
+        );
+        assert!(
+            ordered_machine.n_binds * 3 < naive_machine.n_binds * 2,
+            "{} binds ordered vs {} naively",
+            ordered_machine.n_binds,
+            naive_machine.n_binds
+        );
+    }
+
+    #[test]
+    fn ground_leaves_are_looked_up() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let f_x_0 = egraph.add_expr(&"(f x 0)".parse().unwrap());
+        let f_y_1 = egraph.add_expr(&"(f y 1)".parse().unwrap());
+        let one = egraph.lookup(S::leaf("1")).unwrap();
+        egraph.rebuild();
+
+        let program = Program::compile_from_pat(&"(f ?a 1)".parse().unwrap());
+        assert!(program
+            .instructions
+            .iter()
+            .any(|inst| matches!(inst, Instruction::Lookup { .. })));
+        let search = |egraph: &EGraph<S, ()>, eclass| -> Vec<String> {
+            let a = "?a".parse().unwrap();
+            let matches = program.run(egraph, eclass);
+            matches
+                .iter()
+                .map(|s| egraph[s[a]].nodes[0].to_string())
+                .collect()
+        };
+        assert_eq!(search(&egraph, f_y_1), vec!["y"]);
+        // 1 is in the egraph, but not in the eclass of the 0
+        assert!(search(&egraph, f_x_0).is_empty());
+
+        // a leaf that isn't in the egraph at all never matches
+        let missing = Program::compile_from_pat(&"(f ?a 2)".parse().unwrap());
+        assert!(egraph.lookup(S::leaf("2")).is_none());
+        for class in egraph.classes() {
+            assert!(missing.run(&egraph, class.id).is_empty());
+        }
+
+        // the leaf is found through its new eclass once it's merged
+        let zero = egraph.lookup(S::leaf("0")).unwrap();
+        egraph.union(zero, one);
+        egraph.rebuild();
+        assert_eq!(egraph.lookup(S::leaf("1")), Some(egraph.find(zero)));
+        assert_eq!(search(&egraph, f_x_0), vec!["x"]);
+        let ground = Program::compile_from_pat(&"1".parse().unwrap());
+        assert_eq!(ground.run(&egraph, zero).len(), 1);
+    }
+
+    #[test]
+    fn subpatterns_with_fewer_unbound_variables_bind_first() {
+        let program = Program::compile_from_pat(&"(f (g ?a ?b ?c) (h ?a))".parse().unwrap());
+        let binds: Vec<&str> = program
+            .instructions
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Bind { node, .. } => Some(node.op.as_str()),
+                _ => None,
+            })
+            .collect();
+        // once `f` is bound, `h` leaves one variable unbound and `g` three
+        assert_eq!(binds, ["f", "h", "g"]);
+    }
+
+    #[test]
+    fn program_set_matches_independent_search() {
+        let egraph = test_egraph();
+        let patterns = prefix_sharing_patterns();
+        assert_eq!(patterns.len(), 20);
+
+        let programs: Vec<Program<S>> = patterns.iter().map(Program::compile_from_pat).collect();
+        let set = ProgramSet::compile(&patterns);
+
+        let mut set_machine = Machine::default();
+        let mut separate_machine = Machine::default();
+        let mut n_matches = 0;
+        for class in egraph.classes() {
+            let mut expected = vec![];
+            for (i, program) in programs.iter().enumerate() {
+                for subst in program.run_in(&mut separate_machine, &egraph, class.id, usize::MAX) {
+                    expected.push((i, subst));
+                }
+            }
+            let mut actual = set.run_in(&mut set_machine, &egraph, class.id);
+            n_matches += actual.len();
+            actual.sort();
+            expected.sort();
+            assert_eq!(actual, expected);
+        }
+
+        assert!(n_matches > 0);
+        assert!(
+            set_machine.n_binds < separate_machine.n_binds,
+            "{} binds with a program set vs {} separately",
+            set_machine.n_binds,
+            separate_machine.n_binds
+        );
+    }
+
+    #[test]
+    fn step_budget_bounds_explosive_searches() {
+        let (egraph, root) = crate::test::explosive_egraph(4);
+        let program = Program::compile_from_pat(&crate::test::explosive_pattern(4).ast);
+        let simple = Program::compile_from_pat(&"(g ?a)".parse().unwrap());
+        let g = egraph[root].nodes[0].children()[0];
+
+        let mut arena = MatchArena::new();
+        assert_eq!(program.run_into(&egraph, root, &mut arena).len(), 10_000);
+        assert!(!arena.step_budget_exhausted());
+
+        arena.set_step_budget(Some(1000));
+        let n_binds = arena.machine.n_binds;
+        let truncated = program.run_into(&egraph, root, &mut arena);
+        assert!(arena.step_budget_exhausted());
+        assert!(!truncated.is_empty() && truncated.len() < 1000);
+        assert_eq!(arena.machine.n_binds - n_binds, 1000);
+        // later searches share the spent budget
+        assert!(program.run_into(&egraph, root, &mut arena).is_empty());
+        assert!(simple.run_into(&egraph, g, &mut arena).is_empty());
+
+        arena.set_step_budget(Some(5));
+        assert_eq!(simple.run_into(&egraph, g, &mut arena).len(), 5);
+        arena.set_step_budget(None);
+        assert_eq!(simple.run_into(&egraph, g, &mut arena).len(), 10);
+        assert!(!arena.step_budget_exhausted());
+    }
+
+    #[test]
+    fn hand_written_programs_agree_with_compiled() {
+        let x: Var = "?x".parse().unwrap();
+        let f = S::new("f", vec![Id::from(7); 2]);
+        let program = Program::from_parts(
+            vec![
+                Instruction::Bind {
+                    node: f,
+                    i: Reg(0),
+                    out: Reg(1),
+                },
+                Instruction::Compare {
+                    i: Reg(2),
+                    j: Reg(1),
+                },
+            ],
+            vec![(x, Reg(2))],
+        )
+        .unwrap();
+        let compiled = Program::compile_from_pat(&"(f ?x ?x)".parse().unwrap());
+        assert_eq!(Searcher::<S, ()>::vars(&program), vec![x]);
+
+        for seed in 1..5 {
+            let egraph = random_egraph(seed);
+            for class in egraph.classes() {
+                assert_eq!(
+                    program.run(&egraph, class.id),
+                    compiled.run(&egraph, class.id)
+                );
+            }
+            let count = |searcher: &Program<S>| searcher.n_matches(&egraph);
+            assert_eq!(count(&program), count(&compiled));
+        }
+    }
+
+    #[test]
+    fn hand_written_programs_are_validated() {
+        use ProgramValidationError::*;
+        let x: Var = "?x".parse().unwrap();
+        let bind = |i, out| Instruction::Bind {
+            node: S::new("f", vec![Id::from(0); 2]),
+            i: Reg(i),
+            out: Reg(out),
+        };
+        let check = |instructions: Vec<Instruction<S>>, v2r: Vec<(Var, Reg)>| {
+            Program::from_parts(instructions, v2r).map(|_| ())
+        };
+
+        assert_eq!(
+            check(vec![bind(0, 1), bind(2, 3)], vec![(x, Reg(4))]),
+            Ok(())
+        );
+        // registers may be skipped, but not read
+        assert_eq!(check(vec![bind(0, 2)], vec![(x, Reg(3))]), Ok(()));
+        assert_eq!(
+            check(vec![bind(0, 2)], vec![(x, Reg(1))]),
+            Err(UnwrittenVar {
+                var: x,
+                reg: Reg(1)
+            })
+        );
+        assert_eq!(
+            check(vec![bind(1, 2)], vec![]),
+            Err(UnwrittenRegister {
+                instruction: 0,
+                reg: Reg(1)
+            })
+        );
+        assert_eq!(
+            check(
+                vec![
+                    bind(0, 1),
+                    Instruction::Compare {
+                        i: Reg(1),
+                        j: Reg(3)
+                    }
+                ],
+                vec![]
+            ),
+            Err(UnwrittenRegister {
+                instruction: 1,
+                reg: Reg(3)
+            })
+        );
+        assert_eq!(
+            check(vec![bind(0, 1), bind(1, 2)], vec![]),
+            Err(OverlappingWrite {
+                instruction: 1,
+                reg: Reg(2)
+            })
+        );
+        assert_eq!(
+            check(vec![Instruction::Scan { out: Reg(0) }], vec![]),
+            Err(OverlappingWrite {
+                instruction: 0,
+                reg: Reg(0)
+            })
+        );
+        let term = vec![
+            ENodeOrReg::ENode(S::new("g", vec![Id::from(1)])),
+            ENodeOrReg::Reg(Reg(0)),
+        ];
+        assert_eq!(
+            check(vec![Instruction::Lookup { term, i: Reg(0) }], vec![]),
+            Err(ForwardChild {
+                instruction: 0,
+                entry: 0
+            })
+        );
+        assert_eq!(
+            check(vec![bind(0, 1)], vec![(x, Reg(1)), (x, Reg(2))]),
+            Err(DuplicateVar(x))
+        );
+        let err = check(vec![], vec![(x, Reg(1))]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "?x is yielded from r1, which is never written"
+        );
+    }
 }
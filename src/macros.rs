@@ -58,21 +58,148 @@ define_language! {
 }
 ```
 
+# Variadic operators
+
+A string variant whose children are a `Vec<Id>` or a `Box<[Id]>` takes
+any number of children, like an n-ary `+`.
+Enodes only [match](Language::matches) if they have the same number of
+children, so the pattern `(+ ?a ?b ?c)` only matches sums of three
+terms, and a [`Pattern`] of the operator can have any number of
+children.
+An eclass can hold the same operator with different arities, and a
+search binds only those of the pattern's arity; since the enodes are
+sorted with their children, searching a big eclass scans every enode
+of the variant, unless it's [searched by
+shape](#searching-by-shape).
+```
+# use egg::*;
+define_language! {
+    enum Strings {
+        "concat" = Concat(Vec<Id>),
+        Symbol(Symbol),
+    }
+}
+
+let rules: Vec<Rewrite<Strings, ()>> = vec![
+    rewrite!("flatten-left"; "(concat (concat ?a ?b) ?c)" => "(concat ?a ?b ?c)"),
+];
+let expr = "(concat (concat x y) z)".parse().unwrap();
+let runner = Runner::default().with_expr(&expr).run(&rules);
+let flat = runner.egraph.lookup_expr(&"(concat x y z)".parse().unwrap());
+assert_eq!(flat, Some(runner.egraph.find(runner.roots[0])));
+```
+
+# Namespaces
+
+Operators from different dialects can share a name if they're
+qualified by a namespace, everything before the last `.`, like
+`"mem.load"`.
+[`Display`] always writes the qualified operator, but [`FromOp`] (and so
+parsing [`RecExpr`]s, [`Pattern`]s and [`parse_rules`]) also accepts the
+bare name when only one operator has it.
+An ambiguous bare name isn't resolved, and is parsed as written.
+See [`Symbol::namespace`] for namespaced [`Symbol`]s.
+```
+# use egg::*;
+define_language! {
+    enum Ir {
+        "mem.load" = MemLoad([Id; 2]),
+        "reg.load" = RegLoad(Id),
+        "mem.store" = Store([Id; 3]),
+        Num(i32),
+    }
+}
+
+let expr: RecExpr<Ir> = "(mem.load (store 0 1 2) 1)".parse().unwrap();
+assert_eq!(expr.to_string(), "(mem.load (mem.store 0 1 2) 1)");
+assert!("(load 0 1)".parse::<RecExpr<Ir>>().is_err());
+```
+
+# Sorts
+
+A `sorts` block after the `enum` also implements [`SortedLanguage`],
+giving each variant's sort and the sorts of its children.
+Sorts must be constants in scope:
+```
+# use egg::*;
+const VALUE: SortId = SortId(0);
+const MEM: SortId = SortId(1);
+
+define_language! {
+    enum Ir {
+        "load" = Load([Id; 2]),
+        "store" = Store([Id; 3]),
+        "+" = Add(Box<[Id]>),
+        Num(i32),
+    }
+    sorts {
+        Load => VALUE(MEM, VALUE),
+        Store => MEM(MEM, VALUE, VALUE),
+        // one child sort applies to all of a variable number of children
+        Add => VALUE(VALUE),
+        Num => VALUE(),
+    }
+}
+```
+
+# Searching by shape
+
+Enodes that carry data, like the `Other(Symbol, Vec<Id>)` above, are
+ordered by it, so searching an eclass for one of them scans every
+enode of its variant.
+Writing `search_by_shape` after the `enum` orders them by variant and
+number of children instead (see [`Language::search_cmp`]), so
+searching only scans the enodes with the same shape:
+```
+# use egg::*;
+define_language! {
+    enum Calls {
+        "list" = List(Box<[Id]>),
+        Num(i32),
+        Call(Symbol, Vec<Id>),
+    }
+    search_by_shape
+}
+assert!(Calls::SEARCH_BY_SHAPE);
+```
+A `sorts` block goes after `search_by_shape`.
+
 [`Display`]: std::fmt::Display
 **/
 #[macro_export]
 macro_rules! define_language {
-    ($(#[$meta:meta])* $vis:vis enum $name:ident $variants:tt) => {
-        $crate::__define_language!($(#[$meta])* $vis enum $name $variants -> {} {} {} {} {} {});
+    (@shape $shape:tt $(#[$meta:meta])* $vis:vis enum $name:ident $variants:tt
+     sorts { $($variant:ident => $sort:ident ( $($child:ident),* $(,)? )),* $(,)? }
+    ) => {
+        $crate::define_language!(@shape $shape $(#[$meta])* $vis enum $name $variants);
+
+        impl $crate::SortedLanguage for $name {
+            fn sort(&self) -> $crate::SortId {
+                match self { $($name::$variant { .. } => $sort,)* }
+            }
+
+            fn child_sorts(&self) -> &[$crate::SortId] {
+                match self { $($name::$variant { .. } => &[$($child),*],)* }
+            }
+        }
+    };
+    (@shape $shape:tt $(#[$meta:meta])* $vis:vis enum $name:ident $variants:tt) => {
+        $crate::__define_language!($shape; $(#[$meta])* $vis enum $name $variants -> {} {} {} {} {} {} {} {} {});
+    };
+    ($(#[$meta:meta])* $vis:vis enum $name:ident $variants:tt search_by_shape $($rest:tt)*) => {
+        $crate::define_language!(@shape true $(#[$meta])* $vis enum $name $variants $($rest)*);
+    };
+    ($(#[$meta:meta])* $vis:vis enum $name:ident $variants:tt $($rest:tt)*) => {
+        $crate::define_language!(@shape false $(#[$meta])* $vis enum $name $variants $($rest)*);
     };
 }
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! __define_language {
-    ($(#[$meta:meta])* $vis:vis enum $name:ident {} ->
+    ($shape:tt; $(#[$meta:meta])* $vis:vis enum $name:ident {} ->
      $decl:tt {$($matches:tt)*} $children:tt $children_mut:tt
-     $display:tt {$($from_op:tt)*}
+     $display:tt {$($from_op:tt)*} $op_display:tt {$($ops:tt)*} {$($shapes:tt)*}
     ) => {
         $(#[$meta])*
         #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
@@ -87,6 +214,16 @@ macro_rules! __define_language {
 
             fn children(&self) -> &[Id] { match self $children }
             fn children_mut(&mut self) -> &mut [Id] { match self $children_mut }
+            fn op_display(&self) -> &str { match self $op_display }
+            fn op_names() -> ::std::vec::Vec<&'static str> { $crate::__language_op_names!($op_display) }
+            fn with_children(&self, children: ::std::vec::Vec<$crate::Id>) -> ::std::option::Option<Self> {
+                <Self as $crate::FromOp>::from_op($crate::Language::op_display(self), children).ok()
+            }
+
+            fn search_cmp(&self, other: &Self) -> ::std::cmp::Ordering {
+                $crate::__language_search_cmp!($shape; $name; self, other; $($shapes)*)
+            }
+            const SEARCH_BY_SHAPE: bool = $shape;
         }
 
         impl ::std::fmt::Display for $name {
@@ -101,104 +238,153 @@ macro_rules! __define_language {
             type Error = $crate::FromOpError;
 
             fn from_op(op: &str, children: ::std::vec::Vec<$crate::Id>) -> ::std::result::Result<Self, Self::Error> {
-                match (op, children) {
+                // string variants match the qualified op, data variants
+                // parse the op as written
+                let qualified = $crate::resolve_op(op, &[$($ops)*]);
+                match (op, qualified, children) {
                     $($from_op)*
-                    (op, children) => Err($crate::FromOpError::new(op, children)),
+                    (op, _, children) => Err($crate::FromOpError::new(op, children)),
                 }
             }
         }
     };
 
-    ($(#[$meta:meta])* $vis:vis enum $name:ident
+    ($shape:tt; $(#[$meta:meta])* $vis:vis enum $name:ident
      {
          $string:literal = $variant:ident,
          $($variants:tt)*
      } ->
      { $($decl:tt)* } { $($matches:tt)* } { $($children:tt)* } { $($children_mut:tt)* }
-     { $($display:tt)* } { $($from_op:tt)* }
+     { $($display:tt)* } { $($from_op:tt)* } { $($op_display:tt)* } { $($ops:tt)* }
+     { $($shapes:tt)* }
     ) => {
         $crate::__define_language!(
-            $(#[$meta])* $vis enum $name
+            $shape; $(#[$meta])* $vis enum $name
             { $($variants)* } ->
             { $($decl)*          $variant, }
             { $($matches)*       ($name::$variant, $name::$variant) => true, }
             { $($children)*      $name::$variant => &[], }
             { $($children_mut)*  $name::$variant => &mut [], }
             { $($display)*       ($name::$variant, f) => f.write_str($string), }
-            { $($from_op)*       ($string, children) if children.is_empty() => Ok($name::$variant), }
+            { $($from_op)*       (_, $string, children) if children.is_empty() => Ok($name::$variant), }
+            { $($op_display)*    $name::$variant => $string, }
+            { $($ops)*           $string, }
+            { $($shapes)*        $name::$variant { .. }, }
         );
     };
 
-    ($(#[$meta:meta])* $vis:vis enum $name:ident
+    ($shape:tt; $(#[$meta:meta])* $vis:vis enum $name:ident
      {
          $string:literal = $variant:ident ($ids:ty),
          $($variants:tt)*
      } ->
      { $($decl:tt)* } { $($matches:tt)* } { $($children:tt)* } { $($children_mut:tt)* }
-     { $($display:tt)* } { $($from_op:tt)* }
+     { $($display:tt)* } { $($from_op:tt)* } { $($op_display:tt)* } { $($ops:tt)* }
+     { $($shapes:tt)* }
     ) => {
         $crate::__define_language!(
-            $(#[$meta])* $vis enum $name
+            $shape; $(#[$meta])* $vis enum $name
             { $($variants)* } ->
             { $($decl)*          $variant($ids), }
             { $($matches)*       ($name::$variant(l), $name::$variant(r)) => $crate::LanguageChildren::len(l) == $crate::LanguageChildren::len(r), }
             { $($children)*      $name::$variant(ids) => $crate::LanguageChildren::as_slice(ids), }
             { $($children_mut)*  $name::$variant(ids) => $crate::LanguageChildren::as_mut_slice(ids), }
             { $($display)*       ($name::$variant(..), f) => f.write_str($string), }
-            { $($from_op)*       (op, children) if op == $string && <$ids as $crate::LanguageChildren>::can_be_length(children.len()) => {
+            { $($from_op)*       (_, qualified, children) if qualified == $string && <$ids as $crate::LanguageChildren>::can_be_length(children.len()) => {
                   let children = <$ids as $crate::LanguageChildren>::from_vec(children);
                   Ok($name::$variant(children))
               },
             }
+            { $($op_display)*    $name::$variant(..) => $string, }
+            { $($ops)*           $string, }
+            { $($shapes)*        $name::$variant { .. }, }
         );
     };
 
-    ($(#[$meta:meta])* $vis:vis enum $name:ident
+    ($shape:tt; $(#[$meta:meta])* $vis:vis enum $name:ident
      {
          $variant:ident ($data:ty),
          $($variants:tt)*
      } ->
      { $($decl:tt)* } { $($matches:tt)* } { $($children:tt)* } { $($children_mut:tt)* }
-     { $($display:tt)* } { $($from_op:tt)* }
+     { $($display:tt)* } { $($from_op:tt)* } { $($op_display:tt)* } { $($ops:tt)* }
+     { $($shapes:tt)* }
     ) => {
         $crate::__define_language!(
-            $(#[$meta])* $vis enum $name
+            $shape; $(#[$meta])* $vis enum $name
             { $($variants)* } ->
             { $($decl)*          $variant($data), }
             { $($matches)*       ($name::$variant(data1), $name::$variant(data2)) => data1 == data2, }
             { $($children)*      $name::$variant(_data) => &[], }
             { $($children_mut)*  $name::$variant(_data) => &mut [], }
             { $($display)*       ($name::$variant(data), f) => ::std::fmt::Display::fmt(data, f), }
-            { $($from_op)*       (op, children) if op.parse::<$data>().is_ok() && children.is_empty() => Ok($name::$variant(op.parse().unwrap())), }
+            { $($from_op)*       (op, _, children) if op.parse::<$data>().is_ok() && children.is_empty() => Ok($name::$variant(op.parse().unwrap())), }
+            { $($op_display)*    $name::$variant(..) => stringify!($variant), }
+            { $($ops)* }
+            { $($shapes)*        $name::$variant { .. }, }
         );
     };
 
-    ($(#[$meta:meta])* $vis:vis enum $name:ident
+    ($shape:tt; $(#[$meta:meta])* $vis:vis enum $name:ident
      {
          $variant:ident ($data:ty, $ids:ty),
          $($variants:tt)*
      } ->
      { $($decl:tt)* } { $($matches:tt)* } { $($children:tt)* } { $($children_mut:tt)* }
-     { $($display:tt)* } { $($from_op:tt)* }
+     { $($display:tt)* } { $($from_op:tt)* } { $($op_display:tt)* } { $($ops:tt)* }
+     { $($shapes:tt)* }
     ) => {
         $crate::__define_language!(
-            $(#[$meta])* $vis enum $name
+            $shape; $(#[$meta])* $vis enum $name
             { $($variants)* } ->
             { $($decl)*          $variant($data, $ids), }
             { $($matches)*       ($name::$variant(d1, l), $name::$variant(d2, r)) => d1 == d2 && $crate::LanguageChildren::len(l) == $crate::LanguageChildren::len(r), }
             { $($children)*      $name::$variant(_, ids) => $crate::LanguageChildren::as_slice(ids), }
             { $($children_mut)*  $name::$variant(_, ids) => $crate::LanguageChildren::as_mut_slice(ids), }
             { $($display)*       ($name::$variant(data, _), f) => ::std::fmt::Display::fmt(data, f), }
-            { $($from_op)*       (op, children) if op.parse::<$data>().is_ok() && <$ids as $crate::LanguageChildren>::can_be_length(children.len()) => {
+            { $($from_op)*       (op, _, children) if op.parse::<$data>().is_ok() && <$ids as $crate::LanguageChildren>::can_be_length(children.len()) => {
                   let data = op.parse::<$data>().unwrap();
                   let children = <$ids as $crate::LanguageChildren>::from_vec(children);
                   Ok($name::$variant(data, children))
               },
             }
+            { $($op_display)*    $name::$variant(..) => stringify!($variant), }
+            { $($ops)* }
+            { $($shapes)*        $name::$variant { .. }, }
         );
     };
 }
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __language_search_cmp {
+    (false; $name:ident; $a:expr, $b:expr; $($variant:pat,)*) => {
+        ::std::cmp::Ord::cmp($a, $b)
+    };
+    (true; $name:ident; $a:expr, $b:expr; $($variant:pat,)*) => {{
+        #[allow(unused_assignments)]
+        fn variant_index(node: &$name) -> usize {
+            let mut index = 0;
+            $(
+                if let $variant = node {
+                    return index;
+                }
+                index += 1;
+            )*
+            index
+        }
+        let (a, b) = ($a, $b);
+        let a_shape = (variant_index(a), $crate::Language::len(a));
+        a_shape.cmp(&(variant_index(b), $crate::Language::len(b)))
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __language_op_names {
+    ({ $($variant:pat => $op:expr,)* }) => { ::std::vec![$($op),*] };
+}
+
 /** A macro to easily make [`Rewrite`]s.
 
 The `rewrite!` macro greatly simplifies creating simple, purely
@@ -269,10 +455,10 @@ impl Applier<SimpleLanguage, ()> for MySillyApplier {
 }
 
 // This returns a function that implements Condition
-fn is_not_zero(var: &'static str) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+fn is_not_zero(var: &'static str) -> impl Fn(&dyn EGraphView<SimpleLanguage, ()>, Id, &Subst) -> bool {
     let var = var.parse().unwrap();
     let zero = SimpleLanguage::Num(0);
-    move |egraph, _, subst| !egraph[subst[var]].nodes.contains(&zero)
+    move |egraph, _, subst| !egraph.class(subst[var]).nodes.contains(&zero)
 }
 ```
 
@@ -320,6 +506,163 @@ macro_rules! __rewrite {
     };
 }
 
+/** A macro to easily make an [`Analysis`] whose data is a struct of
+[`Lattice`]s, merged field by field.
+
+Most analyses track a few independent facts about each eclass, like
+a constant it folds to or whether it mentions a variable, each with an
+obvious join.
+Writing their [`merge`](Analysis::merge) by hand means reporting
+every field's changes in one [`DidMerge`], which is easy to get
+subtly wrong; this macro derives it from each field's
+[`Lattice::join`] instead.
+
+It takes a struct definition whose fields may each be followed by
+`= make` or `= make, modify`, then an `impl Analysis<L> for A {}`
+block, where `A` is a type you define.
+It defines the struct as the analysis's `Data`, which must implement
+[`Debug`](std::fmt::Debug), and implements
+[`Analysis<L>`](Analysis) for `A`:
+- [`make`](Analysis::make) builds each field with its `make`
+  function, which has the signature of [`Analysis::make`] but only
+  returns the field, or, for a field without one, with
+  [`Lattice::make_leaf`] or [`Lattice::make_node`] of its children's
+  fields.
+- [`merge`](Analysis::merge) joins each field with
+  [`Lattice::join`].
+- [`modify`](Analysis::modify) calls each field's `modify` function,
+  if it has one, in order, with the signature of
+  [`Analysis::modify`].
+
+Any other [`Analysis`] methods, like [`distinct`](Analysis::distinct),
+go in the `impl` block.
+The fields can be of any type implementing [`Lattice`]:
+`egg` implements it for [`Option`], [`BTreeSet`](std::collections::BTreeSet),
+[`AnyTrue`] and [`AllTrue`], whose own `make_leaf` and `make_node` only
+propagate what the children have, so their fields usually have a
+`make` function.
+
+# Example
+```
+use egg::{*, SymbolLang as S};
+
+#[derive(Default)]
+struct Facts;
+
+define_analysis! {
+    #[derive(Debug)]
+    pub struct FactsData {
+        pub number: Option<i64> = make_number;
+        pub has_var: AnyTrue = make_has_var;
+        pub size: Size;
+    }
+    impl Analysis<S> for Facts {
+        fn distinct(a: &FactsData, b: &FactsData) -> bool {
+            matches!((a.number, b.number), (Some(x), Some(y)) if x != y)
+        }
+    }
+}
+
+/// The size of the smallest term in an eclass.
+#[derive(Debug, PartialEq)]
+pub struct Size(usize);
+
+impl Lattice for Size {
+    fn make_leaf<L: Language>(_: &L) -> Self {
+        Size(1)
+    }
+    fn make_node<L: Language>(_: &L, children: &[&Self]) -> Self {
+        Size(1 + children.iter().map(|c| c.0).sum::<usize>())
+    }
+    fn join(&mut self, other: Self) -> DidMerge {
+        let did_merge = DidMerge(other.0 < self.0, self.0 < other.0);
+        self.0 = self.0.min(other.0);
+        did_merge
+    }
+}
+
+fn make_number(_: &EGraph<S, Facts>, enode: &S) -> Option<i64> {
+    enode.op.as_str().parse().ok()
+}
+
+fn make_has_var(egraph: &EGraph<S, Facts>, enode: &S) -> AnyTrue {
+    let child = |&id: &Id| egraph[id].data.has_var.0;
+    AnyTrue(enode.op.as_str().starts_with('?') || enode.children.iter().any(child))
+}
+
+let mut egraph = EGraph::<S, Facts>::default();
+let f = egraph.add_expr(&"(f ?x)".parse().unwrap());
+let one = egraph.add(S::leaf("1"));
+assert!(egraph[f].data.has_var.0);
+assert_eq!(egraph[f].data.size, Size(2));
+egraph.union(f, one);
+egraph.rebuild();
+assert_eq!(egraph[f].data.number, Some(1));
+assert!(egraph[f].data.has_var.0);
+assert_eq!(egraph[f].data.size, Size(1));
+```
+**/
+#[macro_export]
+macro_rules! define_analysis {
+    (@make $egraph:ident, $enode:ident, $field:ident, $ty:ty, $make:path) => {
+        $make($egraph, $enode)
+    };
+    (@make $egraph:ident, $enode:ident, $field:ident, $ty:ty) => {
+        if $crate::Language::is_leaf($enode) {
+            <$ty as $crate::Lattice>::make_leaf($enode)
+        } else {
+            let children: ::std::vec::Vec<&$ty> = $crate::Language::children($enode)
+                .iter()
+                .map(|&id| &$egraph[id].data.$field)
+                .collect();
+            <$ty as $crate::Lattice>::make_node($enode, &children)
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $data:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident : $ty:ty $(= $make:path $(, $modify:path)?)?
+            );* $(;)?
+        }
+        impl Analysis<$lang:ty> for $analysis:ty {
+            $($rest:tt)*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $data {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::Analysis<$lang> for $analysis {
+            type Data = $data;
+
+            fn make(egraph: &$crate::EGraph<$lang, Self>, enode: &$lang) -> Self::Data {
+                $data {
+                    $($field: $crate::define_analysis!(@make egraph, enode, $field, $ty $(, $make)?),)*
+                }
+            }
+
+            fn merge(&mut self, a: &mut Self::Data, b: Self::Data) -> $crate::DidMerge {
+                let mut did_merge = $crate::DidMerge(false, false);
+                $(did_merge = did_merge | $crate::Lattice::join(&mut a.$field, b.$field);)*
+                did_merge
+            }
+
+            #[allow(unused_variables)]
+            fn modify(egraph: &mut $crate::EGraph<$lang, Self>, id: $crate::Id) {
+                $($($($modify(egraph, id);)?)?)*
+            }
+
+            $($rest)*
+        }
+    };
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -370,4 +713,108 @@ mod tests {
             "bad"; "?a" => "?a" if ConditionEqual::new(x.clone(), x)
         );
     }
+
+    define_language! {
+        enum Nary {
+            "+" = Add(Vec<Id>),
+            "concat" = Concat(Vec<Id>),
+            Int(i32),
+            Var(Symbol),
+        }
+    }
+
+    #[test]
+    fn variadic_operators() {
+        let pattern: Pattern<Nary> = "(+ ?a ?b ?c)".parse().unwrap();
+        assert_eq!(pattern.to_string(), "(+ ?a ?b ?c)");
+        let sum = pattern.ast.as_ref().last().unwrap();
+        assert_eq!(sum.len(), 3);
+
+        // only the sums of three match
+        let mut egraph = EGraph::<Nary, ()>::default();
+        let three = egraph.add_expr(&"(+ 1 2 3)".parse().unwrap());
+        let two = egraph.add_expr(&"(+ 1 2)".parse().unwrap());
+        let four = egraph.add_expr(&"(+ 1 2 3 4)".parse().unwrap());
+        egraph.union(two, four);
+        egraph.rebuild();
+        let matches = pattern.search(&egraph);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].eclass, egraph.find(three));
+        assert!(!Nary::Add(vec![0.into(); 2]).matches(&Nary::Add(vec![0.into(); 3])));
+        assert!(!Nary::Add(vec![]).matches(&Nary::Concat(vec![])));
+
+        // flatten nested binary sums into one variadic sum
+        let rules: Vec<Rewrite<Nary, ()>> = vec![
+            rewrite!("flatten-left"; "(+ (+ ?a ?b) ?c)" => "(+ ?a ?b ?c)"),
+            rewrite!("flatten-right"; "(+ ?a (+ ?b ?c))" => "(+ ?a ?b ?c)"),
+            rewrite!("flatten-3"; "(+ (+ ?a ?b ?c) ?d)" => "(+ ?a ?b ?c ?d)"),
+        ];
+        let expr = "(+ (+ (+ a b) c) d)".parse().unwrap();
+        let runner = Runner::default().with_expr(&expr).run(&rules);
+        let egraph = &runner.egraph;
+        let root = egraph.find(runner.roots[0]);
+        let flat = egraph.lookup_expr(&"(+ a b c d)".parse().unwrap());
+        assert_eq!(flat, Some(root));
+        let best = Extractor::new(egraph, AstSize).find_best(root).1;
+        assert_eq!(best.to_string(), "(+ a b c d)");
+    }
+
+    define_language! {
+        enum Dialects {
+            "mem.load" = MemLoad([Id; 2]),
+            "reg.load" = RegLoad(Id),
+            "mem.store" = Store([Id; 3]),
+            "+" = Add([Id; 2]),
+            Int(i32),
+            Var(Symbol),
+        }
+    }
+
+    fn parse(s: &str) -> RecExpr<Dialects> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn namespaced_ops_parse_and_display() {
+        // unambiguous bare names resolve, and display qualified
+        let expr = parse("(+ (store m a 1) (reg.load r))");
+        assert_eq!(expr.to_string(), "(+ (mem.store m a 1) (reg.load r))");
+        assert_eq!(parse(&expr.to_string()), expr);
+
+        // load is ambiguous, so it's only a symbol
+        assert!("(load m a)".parse::<RecExpr<Dialects>>().is_err());
+        assert_eq!(parse("load").as_ref(), &[Dialects::Var("load".into())]);
+        // and so is a bare name without the op's children
+        assert_eq!(parse("store").as_ref(), &[Dialects::Var("store".into())]);
+
+        let op = Symbol::from(Dialects::Store([0.into(); 3]).op_display());
+        assert_eq!((op.namespace(), op.name()), (Some("mem"), "store"));
+    }
+
+    #[test]
+    fn qualified_patterns_disambiguate() {
+        let mut egraph = EGraph::<Dialects, ()>::default();
+        let mem = egraph.add_expr(&parse("(mem.load m a)"));
+        let reg = egraph.add_expr(&parse("(reg.load a)"));
+        egraph.rebuild();
+        let search = |s: &str| -> Vec<Id> {
+            let pattern: Pattern<Dialects> = s.parse().unwrap();
+            pattern.search(&egraph).iter().map(|m| m.eclass).collect()
+        };
+        assert_eq!(search("(mem.load ?m ?a)"), vec![mem]);
+        assert_eq!(search("(reg.load ?a)"), vec![reg]);
+        assert!("(load ?m ?a)".parse::<Pattern<Dialects>>().is_err());
+
+        let rules: Vec<Rewrite<Dialects, ()>> =
+            parse_rules("store-load: (mem.load (store ?m ?a ?v) ?a) => ?v").unwrap();
+        assert_eq!(
+            format_rules(&rules).unwrap(),
+            "store-load: (mem.load (mem.store ?m ?a ?v) ?a) => ?v\n"
+        );
+        let expr = parse("(+ (mem.load (store m a 1) a) (reg.load a))");
+        let runner = Runner::default().with_expr(&expr).run(&rules);
+        let (egraph, root) = (&runner.egraph, runner.roots[0]);
+        let one_plus_load = parse("(+ 1 (reg.load a))");
+        assert_eq!(egraph.lookup_expr(&one_plus_load), Some(egraph.find(root)));
+    }
 }
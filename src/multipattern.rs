@@ -0,0 +1,267 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::*;
+
+/// A set of patterns that match together, sharing their variables.
+///
+/// Each pattern is bound to a variable: `?x = (f ?a)` matches `(f ?a)` in
+/// the eclass of `?x`.
+/// The first pattern matches the eclass being searched, and each later
+/// one the eclass its variable is bound to, or any eclass if its
+/// variable isn't bound by an earlier pattern.
+/// A variable shared between the patterns must be bound to the same
+/// eclass in all of them, which is checked as the patterns are matched,
+/// in one compiled program, rather than by joining their matches
+/// afterwards.
+///
+/// A [`MultiPattern`] is a [`Searcher`], so it can be the lefthand side
+/// of a [`Rewrite`]; each match is reported in the eclass of the first
+/// pattern.
+/// Patterns whose variable isn't bound yet try every eclass, so put the
+/// most selective pattern first, and bind later ones through shared
+/// variables where possible.
+///
+/// A [`MultiPattern`] parses from its patterns separated by commas, like
+/// `?x = (f ?a), ?y = (g ?a)`.
+///
+/// # Example
+/// ```
+/// use egg::{rewrite as rw, *};
+/// let searcher: MultiPattern<SymbolLang> = "?x = (f ?a), ?y = (g ?a)".parse().unwrap();
+/// // an f and a g of the same argument are equal
+/// let rules: &[Rewrite<SymbolLang, ()>] = &[rw!("f-g"; searcher => "?y")];
+///
+/// let runner = Runner::default()
+///     .with_expr(&"(+ (f a) (+ (g a) (g b)))".parse().unwrap())
+///     .run(rules);
+/// let egraph = &runner.egraph;
+/// let id = |s: &str| egraph.lookup_expr(&s.parse().unwrap()).unwrap();
+/// assert_eq!(id("(f a)"), id("(g a)"));
+/// assert_ne!(id("(f a)"), id("(g b)"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiPattern<L> {
+    asts: Vec<(Var, PatternAst<L>)>,
+    program: machine::Program<L>,
+}
+
+impl<L: Language> MultiPattern<L> {
+    /// Creates a new multi-pattern, binding each pattern to its variable.
+    pub fn new(asts: Vec<(Var, PatternAst<L>)>) -> Self {
+        let program = machine::Program::compile_from_multi_pat(&asts);
+        Self { asts, program }
+    }
+
+    /// Returns the patterns and the variables they are bound to.
+    pub fn patterns(&self) -> &[(Var, PatternAst<L>)] {
+        &self.asts
+    }
+}
+
+/// An error parsing a [`MultiPattern`].
+#[derive(Debug, Error)]
+pub enum MultiPatternParseError<E> {
+    /// One of the patterns didn't parse.
+    #[error(transparent)]
+    PatternParseError(E),
+    /// A pattern isn't bound to a variable with `=`.
+    #[error("pattern {0:?} should be bound to a variable, like ?x = {0}")]
+    PatternAssignmentError(String),
+    /// The variable a pattern is bound to didn't parse.
+    #[error(transparent)]
+    VariableError(<Var as FromStr>::Err),
+}
+
+impl<L: FromOp> FromStr for MultiPattern<L> {
+    type Err = MultiPatternParseError<<PatternAst<L> as FromStr>::Err>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use MultiPatternParseError::*;
+        let mut asts = vec![];
+        for split in s.trim().split(',') {
+            let split = split.trim();
+            if split.is_empty() {
+                continue;
+            }
+            let mut parts = split.splitn(2, '=');
+            let var = parts.next().unwrap().trim();
+            let pattern = parts
+                .next()
+                .ok_or_else(|| PatternAssignmentError(split.to_owned()))?;
+            let var: Var = var.parse().map_err(VariableError)?;
+            let pattern = pattern.trim().parse().map_err(PatternParseError)?;
+            asts.push((var, pattern));
+        }
+        Ok(MultiPattern::new(asts))
+    }
+}
+
+impl<L: Language + Display> Display for MultiPattern<L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, (var, ast)) in self.asts.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} = {}", var, ast)?;
+        }
+        Ok(())
+    }
+}
+
+impl<L: Language, A: Analysis<L>> Searcher<L, A> for MultiPattern<L> {
+    fn search_eclass(&self, egraph: &EGraph<L, A>, eclass: Id) -> Option<SearchMatches<L>> {
+        self.search_eclass_with_limit(egraph, eclass, usize::MAX)
+    }
+
+    fn search_eclass_with_limit(
+        &self,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        limit: usize,
+    ) -> Option<SearchMatches<L>> {
+        let substs = self.program.run_with_limit(egraph, eclass, limit);
+        if substs.is_empty() {
+            None
+        } else {
+            Some(SearchMatches {
+                eclass: egraph.find(eclass),
+                substs,
+                ast: None,
+            })
+        }
+    }
+
+    fn search_into(&self, egraph: &EGraph<L, A>, arena: &mut MatchArena) -> Vec<ArenaMatches<L>> {
+        self.search_into_with_limit(egraph, arena, usize::MAX)
+    }
+
+    /// Searches on the arena's machine, so the searches take steps from
+    /// its [step budget](MatchArena::set_step_budget).
+    fn search_into_with_limit(
+        &self,
+        egraph: &EGraph<L, A>,
+        arena: &mut MatchArena,
+        limit_per_class: usize,
+    ) -> Vec<ArenaMatches<L>> {
+        let mut matches = vec![];
+        for class in egraph.classes() {
+            if arena.step_budget_exhausted() {
+                break;
+            }
+            let substs = self
+                .program
+                .run_into_with_limit(egraph, class.id, arena, limit_per_class);
+            if !substs.is_empty() {
+                matches.push(ArenaMatches {
+                    eclass: class.id,
+                    substs,
+                    ast: None,
+                });
+            }
+        }
+        matches
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.program.vars().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    fn egraph() -> EGraph<S, ()> {
+        let mut egraph = EGraph::default();
+        for expr in &[
+            "(f a)", "(f b)", "(f c)", "(g a)", "(g c)", "(g d)", "(h a b)",
+        ] {
+            egraph.add_expr(&expr.parse().unwrap());
+        }
+        egraph.rebuild();
+        egraph
+    }
+
+    #[test]
+    fn shared_variables_join_the_patterns() {
+        let egraph = egraph();
+        let multi: MultiPattern<S> = "?x = (f ?a), ?y = (g ?a)".parse().unwrap();
+        assert_eq!(multi.to_string(), "?x = (f ?a), ?y = (g ?a)");
+        assert_eq!(
+            Searcher::<S, ()>::vars(&multi),
+            vec![
+                "?x".parse().unwrap(),
+                "?a".parse().unwrap(),
+                "?y".parse().unwrap()
+            ]
+        );
+
+        let f: Pattern<S> = "(f ?a)".parse().unwrap();
+        let g: Pattern<S> = "(g ?b)".parse().unwrap();
+        let product = f.n_matches(&egraph) * g.n_matches(&egraph);
+        assert_eq!(product, 9);
+        let matches = multi.search(&egraph);
+        assert_eq!(matches.iter().map(|m| m.substs.len()).sum::<usize>(), 2);
+        let a: Var = "?a".parse().unwrap();
+        let mut args: Vec<String> = matches
+            .iter()
+            .flat_map(|m| &m.substs)
+            .map(|s| egraph[s[a]].nodes[0].to_string())
+            .collect();
+        args.sort();
+        assert_eq!(args, vec!["a", "c"]);
+        for m in &matches {
+            let x: Var = "?x".parse().unwrap();
+            assert!(m.substs.iter().all(|s| s[x] == m.eclass));
+        }
+
+        // the later pattern isn't ground, so the shared variable is compared
+        let multi: MultiPattern<S> = "?x = (f ?a), ?y = (h ?a ?b)".parse().unwrap();
+        let program = &multi.program;
+        assert!(program.explain(&egraph).contains("compare"));
+        assert_eq!(multi.n_matches(&egraph), 1);
+    }
+
+    #[test]
+    fn bound_variables_start_later_patterns() {
+        let egraph = egraph();
+        // ?a is bound by the first pattern, so no eclass is scanned
+        let multi: MultiPattern<S> = "?x = (h ?a ?b), ?a = (f ?c)".parse().unwrap();
+        assert!(!multi.program.explain(&egraph).contains("scan"));
+        assert_eq!(multi.n_matches(&egraph), 0);
+        let multi: MultiPattern<S> = "?x = (h ?a ?b), ?a = a, ?b = b".parse().unwrap();
+        assert_eq!(multi.n_matches(&egraph), 1);
+    }
+
+    #[test]
+    fn multi_patterns_parse_errors() {
+        let err = "?x = (f ?a), (g ?a)"
+            .parse::<MultiPattern<S>>()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            MultiPatternParseError::PatternAssignmentError(_)
+        ));
+        let err = "x = (f ?a)".parse::<MultiPattern<S>>().unwrap_err();
+        assert!(matches!(err, MultiPatternParseError::VariableError(_)));
+        let err = "?x = (f ?a".parse::<MultiPattern<S>>().unwrap_err();
+        assert!(matches!(err, MultiPatternParseError::PatternParseError(_)));
+        assert!(matches!(Error::from(err), Error::Parse(_)));
+    }
+
+    #[test]
+    fn multi_patterns_rewrite() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rw!("f-g"; { "?x = (f ?a), ?y = (g ?a)".parse::<MultiPattern<S>>().unwrap() } => "(fg ?a)"),
+        ];
+        let runner = Runner::default().with_egraph(egraph()).run(&rules);
+        let egraph = &runner.egraph;
+        let id = |s: &str| egraph.lookup_expr(&s.parse().unwrap());
+        assert_eq!(id("(fg a)"), id("(f a)"));
+        assert_eq!(id("(fg c)"), id("(f c)"));
+        assert_eq!(id("(fg b)"), None);
+    }
+}
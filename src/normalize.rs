@@ -0,0 +1,319 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::*;
+
+/// The limits [`normalize`] saturates with.
+///
+/// The defaults are the same as [`Runner`]'s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NormalizeLimits {
+    /// The iteration limit.
+    pub iter_limit: usize,
+    /// The enode limit.
+    pub node_limit: usize,
+    /// The time limit.
+    pub time_limit: Duration,
+}
+
+impl Default for NormalizeLimits {
+    fn default() -> Self {
+        Self {
+            iter_limit: 30,
+            node_limit: 10_000,
+            time_limit: Duration::from_secs(5),
+        }
+    }
+}
+
+/// What [`normalize`] did to find a normal form.
+#[derive(Debug, Clone)]
+pub struct NormalizeReport {
+    /// The number of iterations the rules ran for.
+    pub iterations: usize,
+    /// Why the rules stopped running.
+    pub stop_reason: StopReason,
+    /// The number of enodes in the egraph at the end.
+    pub egraph_nodes: usize,
+    /// The number of eclasses in the egraph at the end.
+    pub egraph_classes: usize,
+    /// Whether the normal form differs from the input.
+    pub changed: bool,
+    /// Seconds spent saturating and extracting.
+    pub total_time: f64,
+}
+
+impl Display for NormalizeReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} after {} iterations ({:?}), {} nodes, {} classes, {:.6}s",
+            if self.changed {
+                "rewritten"
+            } else {
+                "unchanged"
+            },
+            self.iterations,
+            self.stop_reason,
+            self.egraph_nodes,
+            self.egraph_classes,
+            self.total_time
+        )
+    }
+}
+
+/// Rewrite `expr` to a normal form with `rules`, using the egraph only
+/// as a means to that end.
+///
+/// This adds `expr` to a new egraph, runs the rules until saturation or
+/// a limit, and extracts the smallest term with [`AstSize`], dropping
+/// the egraph.
+/// See [`normalize_with`] to extract with another [`CostFunction`], and
+/// [`Normalizer`] to normalize many expressions without making a new
+/// egraph for each.
+///
+/// # Example
+/// ```
+/// use egg::{rewrite as rw, *};
+/// let rules: &[Rewrite<SymbolLang, ()>] = &[
+///     rw!("add-0"; "(+ ?a 0)" => "?a"),
+///     rw!("mul-1"; "(* ?a 1)" => "?a"),
+/// ];
+/// let expr = "(+ (* x 1) 0)".parse().unwrap();
+/// let (normal, report) = normalize(&expr, rules, NormalizeLimits::default());
+/// assert_eq!(normal.to_string(), "x");
+/// assert!(report.changed);
+/// assert!(matches!(report.stop_reason, StopReason::Saturated));
+/// ```
+pub fn normalize<L, N>(
+    expr: &RecExpr<L>,
+    rules: &[Rewrite<L, N>],
+    limits: NormalizeLimits,
+) -> (RecExpr<L>, NormalizeReport)
+where
+    L: Language + 'static,
+    N: Analysis<L> + Default + 'static,
+{
+    normalize_with(expr, rules, limits, AstSize)
+}
+
+/// Like [`normalize`], but extracts the normal form with
+/// `cost_function`.
+pub fn normalize_with<L, N, CF>(
+    expr: &RecExpr<L>,
+    rules: &[Rewrite<L, N>],
+    limits: NormalizeLimits,
+    cost_function: CF,
+) -> (RecExpr<L>, NormalizeReport)
+where
+    L: Language + 'static,
+    N: Analysis<L> + Default + 'static,
+    CF: CostFunction<L> + Clone,
+{
+    Normalizer::with_cost_function(rules, limits, cost_function).normalize(expr)
+}
+
+/// Normalizes many expressions, one at a time, like [`normalize`].
+///
+/// A `Normalizer` keeps its egraph between expressions, clearing it
+/// with [`EGraph::clear_keep_capacity`] instead of making a new one,
+/// and reuses the rules' compiled patterns, so each expression only
+/// pays for its own saturation.
+/// Each expression still starts with a fresh `N::default()` analysis,
+/// so nothing an [`Analysis`] stored carries over.
+///
+/// # Example
+/// ```
+/// use egg::{rewrite as rw, *};
+/// let rules: &[Rewrite<SymbolLang, ()>] = &[rw!("add-0"; "(+ ?a 0)" => "?a")];
+/// let mut normalizer = Normalizer::new(rules, NormalizeLimits::default());
+/// for (input, output) in &[("(+ x 0)", "x"), ("(+ (+ y 0) 0)", "y"), ("z", "z")] {
+///     let (normal, _) = normalizer.normalize(&input.parse().unwrap());
+///     assert_eq!(&normal.to_string(), output);
+/// }
+/// ```
+pub struct Normalizer<'a, L: Language, N: Analysis<L>, CF = AstSize> {
+    rules: &'a [Rewrite<L, N>],
+    limits: NormalizeLimits,
+    cost_function: CF,
+    egraph: Option<EGraph<L, N>>,
+}
+
+impl<'a, L, N> Normalizer<'a, L, N>
+where
+    L: Language + 'static,
+    N: Analysis<L> + Default + 'static,
+{
+    /// Makes a normalizer that runs `rules` within `limits`, and
+    /// extracts with [`AstSize`].
+    pub fn new(rules: &'a [Rewrite<L, N>], limits: NormalizeLimits) -> Self {
+        Self::with_cost_function(rules, limits, AstSize)
+    }
+}
+
+impl<'a, L, N, CF> Normalizer<'a, L, N, CF>
+where
+    L: Language + 'static,
+    N: Analysis<L> + Default + 'static,
+    CF: CostFunction<L> + Clone,
+{
+    /// Makes a normalizer that runs `rules` within `limits`, and
+    /// extracts with `cost_function`.
+    pub fn with_cost_function(
+        rules: &'a [Rewrite<L, N>],
+        limits: NormalizeLimits,
+        cost_function: CF,
+    ) -> Self {
+        Self {
+            rules,
+            limits,
+            cost_function,
+            egraph: None,
+        }
+    }
+
+    /// Normalizes `expr`, see [`normalize`].
+    pub fn normalize(&mut self, expr: &RecExpr<L>) -> (RecExpr<L>, NormalizeReport) {
+        let start = Instant::now();
+        let mut egraph = self.egraph.take().unwrap_or_default();
+        egraph.clear_keep_capacity();
+        egraph.analysis = N::default();
+        let runner = Runner::default()
+            .with_egraph(egraph)
+            .with_iter_limit(self.limits.iter_limit)
+            .with_node_limit(self.limits.node_limit)
+            .with_time_limit(self.limits.time_limit)
+            .with_expr(expr)
+            .run(self.rules);
+
+        let extractor = Extractor::new(&runner.egraph, self.cost_function.clone());
+        let (_, normal) = extractor.find_best(runner.roots[0]);
+        let report = NormalizeReport {
+            iterations: runner.iterations.len(),
+            stop_reason: runner.stop_reason.clone().unwrap(),
+            egraph_nodes: runner.egraph.total_size(),
+            egraph_classes: runner.egraph.number_of_classes(),
+            changed: &normal != expr,
+            total_time: start.elapsed().as_secs_f64(),
+        };
+        self.egraph = Some(runner.egraph);
+        (normal, report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    fn rules() -> Vec<Rewrite<S, ()>> {
+        vec![
+            rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rw!("add-0"; "(+ ?a 0)" => "?a"),
+            rw!("mul-0"; "(* ?a 0)" => "0"),
+            rw!("double"; "(+ ?a ?a)" => "(dbl ?a)"),
+        ]
+    }
+
+    #[test]
+    fn normalizer_reuses_its_egraph() {
+        let rules = rules();
+        let cases = [
+            ("(+ 0 (+ x x))", "(dbl x)"),
+            ("(* (+ y 0) 0)", "0"),
+            ("(f a b)", "(f a b)"),
+            ("(+ 0 (+ x x))", "(dbl x)"),
+        ];
+        let mut normalizer = Normalizer::new(&rules, NormalizeLimits::default());
+        let mut reports = vec![];
+        for (input, output) in &cases {
+            let expr: RecExpr<S> = input.parse().unwrap();
+            let (normal, report) = normalizer.normalize(&expr);
+            assert_eq!(&normal.to_string(), output);
+            assert_eq!(report.changed, input != output);
+            // nothing is left over from the previous expression
+            let one_shot = normalize(&expr, &rules, NormalizeLimits::default()).1;
+            assert_eq!(report.egraph_nodes, one_shot.egraph_nodes);
+            assert_eq!(report.egraph_classes, one_shot.egraph_classes);
+            reports.push(report);
+        }
+        assert_eq!(reports[0].egraph_nodes, reports[3].egraph_nodes);
+        assert!(reports[0].to_string().starts_with("rewritten after"));
+        assert!(reports[2]
+            .to_string()
+            .starts_with("unchanged after 1 iterations"));
+    }
+
+    /// Remembers every leaf it has seen.
+    #[derive(Default)]
+    struct Seen(HashSet<Symbol>);
+
+    impl Analysis<S> for Seen {
+        type Data = ();
+        fn make(_: &EGraph<S, Self>, _: &S) {}
+        fn merge(&mut self, _: &mut (), _: ()) -> DidMerge {
+            DidMerge(false, false)
+        }
+        fn modify(egraph: &mut EGraph<S, Self>, id: Id) {
+            let leaves: Vec<Symbol> = egraph[id]
+                .iter()
+                .filter(|n| n.is_leaf())
+                .map(|n| n.op)
+                .collect();
+            egraph.analysis.0.extend(leaves);
+        }
+    }
+
+    #[test]
+    fn normalizer_resets_the_analysis() {
+        // unless the analysis remembers an old leaf, f is marked
+        let not_old = |egraph: &dyn EGraphView<S, Seen>, _: Id, _: &Subst| {
+            !egraph.analysis().0.contains(&Symbol::from("old"))
+        };
+        let rules: Vec<Rewrite<S, Seen>> = vec![rw!("mark"; "(f ?a)" => "marked" if not_old)];
+        let mut normalizer = Normalizer::new(&rules, NormalizeLimits::default());
+        let (normal, _) = normalizer.normalize(&"(g old)".parse().unwrap());
+        assert_eq!(normal.to_string(), "(g old)");
+        let (normal, _) = normalizer.normalize(&"(f x)".parse().unwrap());
+        assert_eq!(normal.to_string(), "marked");
+    }
+
+    /// Like [`AstSize`], but `sq` is expensive.
+    #[derive(Clone)]
+    struct NoSquares;
+
+    impl CostFunction<S> for NoSquares {
+        type Cost = usize;
+        fn cost<C>(&mut self, enode: &S, mut costs: C) -> usize
+        where
+            C: FnMut(Id) -> usize,
+        {
+            let op = if enode.op.as_str() == "sq" { 100 } else { 1 };
+            enode.fold(op, |sum, id| sum + costs(id))
+        }
+    }
+
+    #[test]
+    fn normalize_with_a_cost_function() {
+        let rules: Vec<Rewrite<S, ()>> = vec![rw!("expand"; "(sq ?a)" => "(* ?a ?a)")];
+        let expr = "(sq (sq x))".parse().unwrap();
+        let limits = NormalizeLimits::default();
+        let (normal, report) = normalize(&expr, &rules, limits);
+        assert_eq!(normal, expr);
+        assert!(!report.changed);
+        let (normal, report) = normalize_with(&expr, &rules, limits, NoSquares);
+        assert_eq!(normal.to_string(), "(* (* x x) (* x x))");
+        assert!(report.changed);
+    }
+
+    #[test]
+    fn limits_stop_normalizing() {
+        let rules: Vec<Rewrite<S, ()>> = vec![rw!("grow"; "?a" => "(f ?a)")];
+        let limits = NormalizeLimits {
+            iter_limit: 3,
+            ..Default::default()
+        };
+        let (normal, report) = normalize(&"x".parse().unwrap(), &rules, limits);
+        assert_eq!(normal.to_string(), "x");
+        assert!(!report.changed);
+        assert!(matches!(report.stop_reason, StopReason::IterationLimit(3)));
+    }
+}
@@ -0,0 +1,323 @@
+use std::fmt::Debug;
+
+/// What constant folding should do when integer arithmetic overflows.
+///
+/// Folding `(* a b)` into a literal unions the product with that
+/// literal, so a product that silently wraps makes the egraph equate
+/// terms that aren't equal.
+/// Use a policy's [`add`](OverflowPolicy::add),
+/// [`sub`](OverflowPolicy::sub) and [`mul`](OverflowPolicy::mul) in an
+/// [`Analysis`](crate::Analysis)'s `make` instead of the operators, so
+/// the choice is made in one place, and return it from
+/// [`Analysis::overflow_policy`](crate::Analysis::overflow_policy) so
+/// it's shown when folded eclasses conflict.
+///
+/// # Example
+/// ```
+/// use egg::OverflowPolicy;
+/// let big = i64::MAX / 2;
+/// assert_eq!(OverflowPolicy::Saturate.mul(big, 3), Some(i64::MAX));
+/// assert_eq!(OverflowPolicy::Wrap.mul(big, 3), Some(big.wrapping_mul(3)));
+/// assert_eq!(OverflowPolicy::RefuseToFold.mul(big, 3), None);
+/// assert_eq!(OverflowPolicy::RefuseToFold.mul(2i64, 3), Some(6));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
+pub enum OverflowPolicy {
+    /// Clamp to the type's minimum or maximum.
+    ///
+    /// This is still unsound for rules that rely on exact arithmetic,
+    /// since different products fold to the same bound.
+    Saturate,
+    /// Wrap around in two's complement, like the `wrapping_*` methods.
+    ///
+    /// This is only sound if the language's arithmetic wraps too.
+    Wrap,
+    /// Don't fold: the result is `None`, so the eclass keeps its
+    /// symbolic form.
+    RefuseToFold,
+    /// Fold exactly, into a [`BigInt`](num_bigint::BigInt).
+    ///
+    /// [`OverflowInt`] is implemented for `BigInt`, whose arithmetic
+    /// never overflows, so an analysis whose data is a `BigInt` always
+    /// folds.
+    /// A fixed-width type can't hold the result, so on one this
+    /// refuses to fold, like [`RefuseToFold`](OverflowPolicy::RefuseToFold).
+    ///
+    /// This is only available with the `bigint` feature.
+    #[cfg(feature = "bigint")]
+    PromoteToBigInt,
+}
+
+impl Default for OverflowPolicy {
+    fn default() -> Self {
+        OverflowPolicy::RefuseToFold
+    }
+}
+
+/// An integer type an [`OverflowPolicy`] can do arithmetic on.
+///
+/// This is implemented for all the primitive integer types, and for
+/// [`BigInt`](num_bigint::BigInt) with the `bigint` feature.
+/// It's sealed, so it can't be implemented for other types.
+pub trait OverflowInt: sealed::IntOps + Clone + Debug {}
+
+mod sealed {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ArithOp {
+        Add,
+        Sub,
+        Mul,
+    }
+
+    pub trait IntOps: Sized {
+        fn checked(&self, op: ArithOp, other: &Self) -> Option<Self>;
+        fn saturating(&self, op: ArithOp, other: &Self) -> Self;
+        fn wrapping(&self, op: ArithOp, other: &Self) -> Self;
+    }
+}
+
+use sealed::{ArithOp, IntOps};
+
+macro_rules! impl_overflow_int {
+    ($($t:ty),*) => {$(
+        impl IntOps for $t {
+            fn checked(&self, op: ArithOp, other: &Self) -> Option<Self> {
+                match op {
+                    ArithOp::Add => self.checked_add(*other),
+                    ArithOp::Sub => self.checked_sub(*other),
+                    ArithOp::Mul => self.checked_mul(*other),
+                }
+            }
+
+            fn saturating(&self, op: ArithOp, other: &Self) -> Self {
+                match op {
+                    ArithOp::Add => self.saturating_add(*other),
+                    ArithOp::Sub => self.saturating_sub(*other),
+                    ArithOp::Mul => self.saturating_mul(*other),
+                }
+            }
+
+            fn wrapping(&self, op: ArithOp, other: &Self) -> Self {
+                match op {
+                    ArithOp::Add => self.wrapping_add(*other),
+                    ArithOp::Sub => self.wrapping_sub(*other),
+                    ArithOp::Mul => self.wrapping_mul(*other),
+                }
+            }
+        }
+
+        impl OverflowInt for $t {}
+    )*};
+}
+
+impl_overflow_int!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+#[cfg(feature = "bigint")]
+impl IntOps for num_bigint::BigInt {
+    fn checked(&self, op: ArithOp, other: &Self) -> Option<Self> {
+        Some(match op {
+            ArithOp::Add => self + other,
+            ArithOp::Sub => self - other,
+            ArithOp::Mul => self * other,
+        })
+    }
+
+    fn saturating(&self, op: ArithOp, other: &Self) -> Self {
+        self.checked(op, other).unwrap()
+    }
+
+    fn wrapping(&self, op: ArithOp, other: &Self) -> Self {
+        self.checked(op, other).unwrap()
+    }
+}
+
+#[cfg(feature = "bigint")]
+impl OverflowInt for num_bigint::BigInt {}
+
+impl OverflowPolicy {
+    fn apply<T: OverflowInt>(self, op: ArithOp, a: T, b: T) -> Option<T> {
+        a.checked(op, &b).or_else(|| match self {
+            OverflowPolicy::Saturate => Some(a.saturating(op, &b)),
+            OverflowPolicy::Wrap => Some(a.wrapping(op, &b)),
+            OverflowPolicy::RefuseToFold => None,
+            #[cfg(feature = "bigint")]
+            OverflowPolicy::PromoteToBigInt => None,
+        })
+    }
+
+    /// `a + b`, or what this policy says to do if it overflows.
+    pub fn add<T: OverflowInt>(self, a: T, b: T) -> Option<T> {
+        self.apply(ArithOp::Add, a, b)
+    }
+
+    /// `a - b`, or what this policy says to do if it overflows.
+    pub fn sub<T: OverflowInt>(self, a: T, b: T) -> Option<T> {
+        self.apply(ArithOp::Sub, a, b)
+    }
+
+    /// `a * b`, or what this policy says to do if it overflows.
+    pub fn mul<T: OverflowInt>(self, a: T, b: T) -> Option<T> {
+        self.apply(ArithOp::Mul, a, b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    define_language! {
+        enum Arith {
+            "*" = Mul([Id; 2]),
+            Num(i64),
+        }
+    }
+
+    struct Folding {
+        policy: OverflowPolicy,
+    }
+
+    impl Analysis<Arith> for Folding {
+        type Data = Option<i64>;
+
+        fn make(egraph: &EGraph<Arith, Self>, enode: &Arith) -> Self::Data {
+            let x = |i: &Id| egraph[*i].data;
+            match enode {
+                Arith::Num(n) => Some(*n),
+                Arith::Mul([a, b]) => egraph.analysis.policy.mul(x(a)?, x(b)?),
+            }
+        }
+
+        fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge {
+            merge_max(to, from)
+        }
+
+        fn modify(egraph: &mut EGraph<Arith, Self>, id: Id) {
+            if let Some(n) = egraph[id].data {
+                let added = egraph.add(Arith::Num(n));
+                egraph.union(id, added);
+            }
+        }
+
+        fn distinct(a: &Self::Data, b: &Self::Data) -> bool {
+            matches!((a, b), (Some(a), Some(b)) if a != b)
+        }
+
+        fn overflow_policy(&self) -> Option<OverflowPolicy> {
+            Some(self.policy)
+        }
+    }
+
+    #[test]
+    fn folding_large_products() {
+        let big = i64::MAX / 2;
+        let fold = |policy| {
+            let mut egraph = EGraph::new(Folding { policy });
+            let product = egraph.add_expr(&format!("(* {} 3)", big).parse().unwrap());
+            egraph.rebuild();
+            let literal = |n: i64| {
+                egraph
+                    .lookup(Arith::Num(n))
+                    .map(|id| egraph.find(id) == egraph.find(product))
+            };
+            (
+                egraph[product].data,
+                literal(i64::MAX),
+                literal(big.wrapping_mul(3)),
+                egraph[product].nodes.len(),
+            )
+        };
+
+        assert_eq!(
+            fold(OverflowPolicy::Saturate),
+            (Some(i64::MAX), Some(true), None, 2)
+        );
+        let wrapped = big.wrapping_mul(3);
+        assert!(wrapped < 0);
+        assert_eq!(
+            fold(OverflowPolicy::Wrap),
+            (Some(wrapped), None, Some(true), 2)
+        );
+        // the product stays symbolic
+        assert_eq!(fold(OverflowPolicy::RefuseToFold), (None, None, None, 1));
+
+        // products that fit fold the same under every policy
+        for &policy in &[
+            OverflowPolicy::Saturate,
+            OverflowPolicy::Wrap,
+            OverflowPolicy::RefuseToFold,
+        ] {
+            let mut egraph = EGraph::new(Folding { policy });
+            let product = egraph.add_expr(&"(* 6 7)".parse().unwrap());
+            egraph.rebuild();
+            assert_eq!(egraph[product].data, Some(42));
+            let literal = egraph.lookup(Arith::Num(42)).unwrap();
+            assert_eq!(egraph.find(literal), egraph.find(product));
+        }
+    }
+
+    #[test]
+    fn conflicts_show_the_policy() {
+        let mut egraph = EGraph::new(Folding {
+            policy: OverflowPolicy::Wrap,
+        });
+        let big = i64::MAX / 2;
+        let product = egraph.add_expr(&format!("(* {} 3)", big).parse().unwrap());
+        let zero = egraph.add(Arith::Num(0));
+        egraph.rebuild();
+        let err = egraph.try_union(product, zero).unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("by a direct union, folding with Wrap"),
+            "{}",
+            err
+        );
+        assert!(matches!(
+            err,
+            Error::AnalysisConflict {
+                policy: Some(OverflowPolicy::Wrap),
+                ..
+            }
+        ));
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn promoting_large_products() {
+        use num_bigint::BigInt;
+
+        let big = i64::MAX / 2;
+        assert_eq!(OverflowPolicy::PromoteToBigInt.mul(big, 3), None);
+        assert_eq!(OverflowPolicy::PromoteToBigInt.mul(big, 2), Some(big * 2));
+
+        #[derive(Default)]
+        struct Exact;
+        impl Analysis<Arith> for Exact {
+            type Data = Option<BigInt>;
+            fn make(egraph: &EGraph<Arith, Self>, enode: &Arith) -> Self::Data {
+                let x = |i: &Id| egraph[*i].data.clone();
+                let policy = OverflowPolicy::PromoteToBigInt;
+                match enode {
+                    Arith::Num(n) => Some(BigInt::from(*n)),
+                    Arith::Mul([a, b]) => policy.mul(x(a)?, x(b)?),
+                }
+            }
+            fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge {
+                merge_max(to, from)
+            }
+        }
+
+        let mut egraph = EGraph::<Arith, Exact>::default();
+        let product = egraph.add_expr(&format!("(* (* {} 3) 2)", big).parse().unwrap());
+        egraph.rebuild();
+        assert_eq!(egraph[product].data, Some(BigInt::from(big) * 6));
+    }
+
+    #[test]
+    fn policies_on_other_types() {
+        assert_eq!(OverflowPolicy::Saturate.sub(0u8, 1), Some(0));
+        assert_eq!(OverflowPolicy::Wrap.sub(0u8, 1), Some(255));
+        assert_eq!(OverflowPolicy::RefuseToFold.add(i32::MAX, 1), None);
+        assert_eq!(OverflowPolicy::Saturate.add(i32::MIN, -1), Some(i32::MIN));
+    }
+}
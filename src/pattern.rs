@@ -1,7 +1,9 @@
 use fmt::Formatter;
 use log::*;
 use std::borrow::Cow;
+use std::cell::RefCell;
 use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
 use std::{convert::TryFrom, str::FromStr};
 
 use thiserror::Error;
@@ -33,6 +35,8 @@ use crate::*;
 /// Importantly, [`Pattern`] implements [`FromStr`] if the
 /// [`Language`] does.
 /// This is probably how you'll create most [`Pattern`]s.
+/// With the `serde-1` feature, a [`Pattern`] also de/serializes as that
+/// string.
 ///
 /// ```
 /// use egg::*;
@@ -65,7 +69,7 @@ use crate::*;
 pub struct Pattern<L> {
     /// The actual pattern as a [`RecExpr`]
     pub ast: PatternAst<L>,
-    program: machine::Program<L>,
+    pub(crate) program: machine::Program<L>,
 }
 
 /// A [`RecExpr`] that represents a
@@ -108,18 +112,271 @@ impl<L: Language> Pattern<L> {
         Pattern { ast, program }
     }
 
+    /// Search one eclass like [`Searcher::search_eclass`], but return
+    /// each match as a [`PackedSubst`].
+    ///
+    /// All of the matches share one schema, so this uses much less
+    /// memory than [`Subst`]s for patterns with many variables.
+    /// They can be instantiated directly with
+    /// [`EGraph::add_instantiation`].
+    pub fn search_eclass_packed<A: Analysis<L>>(
+        &self,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+    ) -> Vec<PackedSubst> {
+        self.program.run_packed(egraph, eclass)
+    }
+
+    /// Search one eclass like [`Searcher::search_eclass`], but call `f`
+    /// with each match as it's found instead of collecting them.
+    ///
+    /// Searching stops as soon as `f` returns [`SearchAction::Stop`],
+    /// without binding any more enodes, so finding the first few of
+    /// many matches only costs as much as finding those.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let a = egraph.add_expr(&"(+ x y)".parse().unwrap());
+    /// for e in &["(+ y x)", "(+ x x)", "(+ y y)"] {
+    ///     let b = egraph.add_expr(&e.parse().unwrap());
+    ///     egraph.union(a, b);
+    /// }
+    /// egraph.rebuild();
+    ///
+    /// let pattern: Pattern<S> = "(+ ?a ?b)".parse().unwrap();
+    /// let mut first = vec![];
+    /// pattern.search_eclass_with(&egraph, a, |subst| {
+    ///     first.push(subst);
+    ///     if first.len() == 2 {
+    ///         SearchAction::Stop
+    ///     } else {
+    ///         SearchAction::Continue
+    ///     }
+    /// });
+    /// assert_eq!(first.len(), 2);
+    /// ```
+    pub fn search_eclass_with<A: Analysis<L>>(
+        &self,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        f: impl FnMut(Subst) -> SearchAction,
+    ) {
+        self.program.run_with(egraph, eclass, f)
+    }
+
+    /// Describe how searching for this pattern in `egraph` works, like a
+    /// database's query plan: the instructions of its compiled program,
+    /// each with the number of candidates it is estimated to produce
+    /// from `egraph`'s operator statistics.
+    ///
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// egraph.add_expr(&"(+ (f x) y)".parse().unwrap());
+    /// egraph.rebuild();
+    /// let pattern: Pattern<S> = "(+ (f ?x) ?y)".parse().unwrap();
+    /// let plan = pattern.explain(&egraph);
+    /// assert!(plan.lines().nth(1).unwrap().starts_with("bind + in r0"));
+    /// ```
+    pub fn explain<A: Analysis<L>>(&self, egraph: &EGraph<L, A>) -> String {
+        self.program.explain(egraph)
+    }
+
+    /// Search every eclass of `egraph`, grouping the matches by the
+    /// canonical eclass bound to `var`, for example to find every
+    /// eclass used as a denominator with `(/ ?n ?d)`.
+    ///
+    /// Each group holds the matched eclass and subst of each match,
+    /// ordered by eclass and then as the search finds them.
+    /// Matches are grouped as the search finds them, without collecting
+    /// them per eclass first as [`search`](Searcher::search) does.
+    ///
+    /// Panics if `var` isn't in this pattern.
+    ///
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let a_b = egraph.add_expr(&"(/ a b)".parse().unwrap());
+    /// let c_b = egraph.add_expr(&"(/ c b)".parse().unwrap());
+    /// egraph.add_expr(&"(/ b c)".parse().unwrap());
+    /// egraph.rebuild();
+    ///
+    /// let pattern: Pattern<S> = "(/ ?n ?d)".parse().unwrap();
+    /// let by_denominator = pattern.search_grouped_by(&egraph, "?d".parse().unwrap());
+    /// assert_eq!(by_denominator.len(), 2);
+    /// let b = egraph.lookup_expr(&"b".parse().unwrap()).unwrap();
+    /// let classes: Vec<Id> = by_denominator[&b].iter().map(|(id, _)| *id).collect();
+    /// assert_eq!(classes, vec![a_b, c_b]);
+    /// ```
+    pub fn search_grouped_by<A: Analysis<L>>(
+        &self,
+        egraph: &EGraph<L, A>,
+        var: Var,
+    ) -> HashMap<Id, Vec<(Id, Subst)>> {
+        if !self.program.vars().contains(&var) {
+            panic!("{}", Error::UnknownVar(var));
+        }
+        let mut classes: Vec<Id> = match self.ast.as_ref().last() {
+            Some(ENodeOrVar::ENode(e)) => {
+                #[allow(clippy::mem_discriminant_non_enum)]
+                let key = std::mem::discriminant(e);
+                let ids = egraph.classes_by_op.get(&key);
+                ids.map_or(vec![], |ids| ids.iter().copied().collect())
+            }
+            Some(ENodeOrVar::Var(_)) => egraph.classes().map(|c| c.id).collect(),
+            None => vec![],
+        };
+        classes.sort_unstable();
+
+        let mut groups: HashMap<Id, Vec<(Id, Subst)>> = HashMap::default();
+        for eclass in classes {
+            self.program.run_with(egraph, eclass, |subst| {
+                let key = egraph.find(subst[var]);
+                groups.entry(key).or_default().push((eclass, subst));
+                SearchAction::Continue
+            });
+        }
+        groups
+    }
+
+    /// Search the single eclass `eclass` for matches of all of
+    /// `patterns` at once, each against an enode of that eclass, with the
+    /// variables they share bound to the same eclasses.
+    ///
+    /// This answers whether an eclass has all of the forms at once, like
+    /// both `(neg ?x)` and `(abs ?x)` for the same `?x`.
+    /// Unlike a [`MultiPattern`], which matches its patterns in
+    /// different eclasses, every pattern here matches `eclass` itself.
+    /// The substs have the variables of each pattern in turn, in the
+    /// order of its [`vars`](Pattern::vars).
+    /// With no patterns, `eclass` matches once, with an empty subst.
+    ///
+    /// The patterns are compiled into one program on each call.
+    ///
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let neg = egraph.add_expr(&"(neg a)".parse().unwrap());
+    /// let abs = egraph.add_expr(&"(abs a)".parse().unwrap());
+    /// let abs_b = egraph.add_expr(&"(abs b)".parse().unwrap());
+    /// egraph.union(neg, abs);
+    /// egraph.union(neg, abs_b);
+    /// egraph.rebuild();
+    ///
+    /// let patterns: Vec<Pattern<S>> = ["(neg ?x)", "(abs ?x)"]
+    ///     .iter()
+    ///     .map(|s| s.parse().unwrap())
+    ///     .collect();
+    /// let substs = Pattern::search_conjunct_in_class(&patterns, &egraph, neg);
+    /// assert_eq!(substs.len(), 1);
+    /// let a = egraph.lookup_expr(&"a".parse().unwrap()).unwrap();
+    /// assert_eq!(substs[0]["?x".parse().unwrap()], a);
+    /// ```
+    pub fn search_conjunct_in_class<A: Analysis<L>>(
+        patterns: &[Pattern<L>],
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+    ) -> Vec<Subst> {
+        let mut vars = vec![];
+        for pattern in patterns {
+            for v in pattern.vars() {
+                if !vars.contains(&v) {
+                    vars.push(v);
+                }
+            }
+        }
+        let asts: Vec<&PatternAst<L>> = patterns.iter().map(|p| &p.ast).collect();
+        let program = machine::Program::compile_from_conjunct(&asts, &vars);
+        program.run(egraph, egraph.find(eclass))
+    }
+
     /// Returns a list of the [`Var`]s in this pattern.
+    ///
+    /// This is also the order of the variables in the [`Subst`]s and
+    /// [`PackedSubst`]s this pattern's searches yield.
+    /// By default, that's the order the variables first appear in the
+    /// pattern's text, so `(+ (f ?b) ?a)` has `[?b, ?a]`;
+    /// set it with [`with_var_order`](Pattern::with_var_order).
     pub fn vars(&self) -> Vec<Var> {
-        let mut vars = vec![];
-        for n in self.ast.as_ref() {
-            if let ENodeOrVar::Var(v) = n {
+        self.program.vars().to_vec()
+    }
+
+    /// Make this pattern's searches yield the variables in `order`.
+    ///
+    /// Variables of the pattern that `order` leaves out come after
+    /// those in it, in the default order (see [`vars`](Pattern::vars)).
+    /// Fails if `order` has a variable that isn't in the pattern.
+    ///
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let (a, b): (Var, Var) = ("?a".parse().unwrap(), "?b".parse().unwrap());
+    /// let pattern: Pattern<S> = "(+ (f ?b) ?a)".parse().unwrap();
+    /// assert_eq!(pattern.vars(), vec![b, a]);
+    /// let pattern = pattern.with_var_order(&[a]).unwrap();
+    /// assert_eq!(pattern.vars(), vec![a, b]);
+    ///
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// egraph.add_expr(&"(+ (f x) y)".parse().unwrap());
+    /// egraph.rebuild();
+    /// let subst = &pattern.search(&egraph)[0].substs[0];
+    /// assert_eq!(subst.iter().map(|(v, _)| v).collect::<Vec<_>>(), vec![a, b]);
+    /// ```
+    pub fn with_var_order(mut self, order: &[Var]) -> Result<Self, UnknownVar> {
+        let default = default_var_order(&self.ast);
+        let mut vars = Vec::with_capacity(default.len());
+        for v in order {
+            if !default.contains(v) {
+                return Err(UnknownVar(*v));
+            }
+            if !vars.contains(v) {
+                vars.push(*v);
+            }
+        }
+        for v in default {
+            if !vars.contains(&v) {
+                vars.push(v);
+            }
+        }
+        self.program = machine::Program::compile_with_var_order(&self.ast, &vars);
+        Ok(self)
+    }
+}
+
+/// A variable that isn't in the [`Pattern`] given to
+/// [`Pattern::with_var_order`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("Variable {0} is not in the pattern")]
+pub struct UnknownVar(pub Var);
+
+/// The variables of `ast` in the order they first appear in its text,
+/// that is, in a left-to-right traversal from the root.
+pub(crate) fn default_var_order<L: Language>(ast: &PatternAst<L>) -> Vec<Var> {
+    let nodes = ast.as_ref();
+    let mut vars = vec![];
+    let mut seen = HashSet::default();
+    let mut todo: Vec<Id> = nodes
+        .len()
+        .checked_sub(1)
+        .map(Id::from)
+        .into_iter()
+        .collect();
+    while let Some(id) = todo.pop() {
+        // a shared subterm can't have new variables the second time
+        if !seen.insert(id) {
+            continue;
+        }
+        match &nodes[usize::from(id)] {
+            ENodeOrVar::Var(v) => {
                 if !vars.contains(v) {
                     vars.push(*v)
                 }
             }
+            ENodeOrVar::ENode(n) => todo.extend(n.children().iter().rev()),
         }
-        vars
     }
+    vars
 }
 
 impl<L: Language + Display> Pattern<L> {
@@ -206,6 +463,27 @@ impl<L: FromOp> std::str::FromStr for Pattern<L> {
     }
 }
 
+#[cfg(feature = "serde-1")]
+impl<L: Language + Display> serde::Serialize for Pattern<L> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serde::Serialize::serialize(&self.ast, serializer)
+    }
+}
+
+#[cfg(feature = "serde-1")]
+impl<'de, L: FromOp> serde::Deserialize<'de> for Pattern<L> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let ast = <PatternAst<L> as serde::Deserialize>::deserialize(deserializer)?;
+        Ok(Self::new(ast))
+    }
+}
+
 impl<'a, L: Language> From<&'a [L]> for Pattern<L> {
     fn from(expr: &'a [L]) -> Self {
         let nodes: Vec<_> = expr.iter().cloned().map(ENodeOrVar::ENode).collect();
@@ -247,46 +525,154 @@ impl<L: Language + Display> Display for Pattern<L> {
 /// tells you how many eclasses something was matched in, _not_ how
 /// many matches were found total.
 ///
+/// The substitutions are a `Vec<Subst>` by default, or a
+/// [`SubstRange`] of a [`MatchArena`] for an [`ArenaMatches`].
 #[derive(Debug)]
-pub struct SearchMatches<'a, L: Language> {
+pub struct SearchMatches<'a, L: Language, S = Vec<Subst>> {
     /// The eclass id that these matches were found in.
     pub eclass: Id,
     /// The substitutions for each match.
-    pub substs: Vec<Subst>,
+    pub substs: S,
     /// Optionally, an ast for the matches used in proof production.
     pub ast: Option<Cow<'a, PatternAst<L>>>,
 }
 
-impl<L: Language, A: Analysis<L>> Searcher<L, A> for Pattern<L> {
-    fn get_pattern_ast(&self) -> Option<&PatternAst<L>> {
-        Some(&self.ast)
+/// The result of searching a [`Searcher`] over one eclass with
+/// [`search_into`](Searcher::search_into): a [`SearchMatches`] whose
+/// substitutions are stored in a [`MatchArena`].
+pub type ArenaMatches<'a, L> = SearchMatches<'a, L, SubstRange>;
+
+impl<'a, L: Language, S> SearchMatches<'a, L, S> {
+    /// Returns the enode in `eclass` that the match `subst` is rooted
+    /// at, found by looking up the pattern in `ast` under `subst`.
+    ///
+    /// This is `None` if there is no `ast`, if the pattern is just a
+    /// variable, or if a subterm of the match can no longer be looked
+    /// up (for example, after unions that haven't been rebuilt yet).
+    pub fn matched_node<N, B>(&self, egraph: &EGraph<L, N>, subst: &B) -> Option<L>
+    where
+        N: Analysis<L>,
+        B: Bindings + ?Sized,
+    {
+        let ast = self.ast.as_ref()?.as_ref().as_ref();
+        let (root, rest) = ast.split_last()?;
+        let mut ids = Vec::with_capacity(rest.len());
+        for pat_node in rest {
+            let id = match pat_node {
+                ENodeOrVar::Var(v) => subst.id_of(*v),
+                ENodeOrVar::ENode(e) => {
+                    let node = e.clone().map_children(|child| ids[usize::from(child)]);
+                    egraph.lookup(node)?
+                }
+            };
+            ids.push(id);
+        }
+        let root = match root {
+            ENodeOrVar::Var(_) => return None,
+            ENodeOrVar::ENode(e) => e
+                .clone()
+                .map_children(|child| egraph.find(ids[usize::from(child)])),
+        };
+        // the pattern only has the operator, so look for the actual
+        // enode to get any data it carries
+        egraph[self.eclass]
+            .iter()
+            .find(|n| {
+                root.matches(n)
+                    && n.children()
+                        .iter()
+                        .map(|&c| egraph.find(c))
+                        .eq(root.children().iter().copied())
+            })
+            .cloned()
     }
+}
 
-    fn search(&self, egraph: &EGraph<L, A>) -> Vec<SearchMatches<L>> {
-        match self.ast.as_ref().last().unwrap() {
-            ENodeOrVar::ENode(e) => {
+impl<'a, L: Language> SearchMatches<'a, L> {
+    /// Move these matches into `arena`.
+    ///
+    /// Runs of substitutions that bind the same [`Var`]s in the same
+    /// order share an [`ArenaMatches`], so this usually returns one.
+    pub fn into_arena(self, arena: &mut MatchArena) -> Vec<ArenaMatches<'a, L>> {
+        let mut ranges: Vec<SubstRange> = vec![];
+        for subst in &self.substs {
+            let same_schema = ranges.last().map_or(false, |range| {
+                range
+                    .schema()
+                    .iter()
+                    .copied()
+                    .eq(subst.iter().map(|(v, _)| v))
+            });
+            if !same_schema {
+                let schema: Vec<Var> = subst.iter().map(|(v, _)| v).collect();
+                ranges.push(arena.start_range(schema.into()));
+            }
+            let range = ranges.last_mut().unwrap();
+            arena.push(range, subst.iter().map(|(_, id)| id));
+        }
+        let (eclass, ast) = (self.eclass, self.ast);
+        ranges
+            .into_iter()
+            .map(|substs| ArenaMatches {
+                eclass,
+                substs,
+                ast: ast.clone(),
+            })
+            .collect()
+    }
+}
+
+impl<'a, L: Language> SearchMatches<'a, L, SubstRange> {
+    /// Copy these matches out of `arena` into a [`SearchMatches`].
+    pub fn to_search_matches(&self, arena: &MatchArena) -> SearchMatches<'a, L> {
+        SearchMatches {
+            eclass: self.eclass,
+            substs: arena.to_substs(&self.substs),
+            ast: self.ast.clone(),
+        }
+    }
+}
+
+thread_local! {
+    static SCRATCH_MACHINE: RefCell<machine::Machine> = RefCell::new(Default::default());
+}
+
+impl<L: Language> Pattern<L> {
+    /// The eclasses this pattern can match: those with an enode of its
+    /// root's operator, or every eclass if its root is a variable.
+    pub(crate) fn candidate_classes<'e, A: Analysis<L>>(
+        &self,
+        egraph: &'e EGraph<L, A>,
+    ) -> Box<dyn Iterator<Item = Id> + 'e> {
+        match self.ast.as_ref().last() {
+            Some(ENodeOrVar::ENode(e)) => {
                 #[allow(clippy::mem_discriminant_non_enum)]
                 let key = std::mem::discriminant(e);
                 match egraph.classes_by_op.get(&key) {
-                    None => vec![],
-                    Some(ids) => ids
-                        .iter()
-                        .filter_map(|&id| self.search_eclass(egraph, id))
-                        .collect(),
+                    None => Box::new(std::iter::empty()),
+                    Some(ids) => Box::new(ids.iter().copied()),
                 }
             }
-            ENodeOrVar::Var(_) => egraph
-                .classes()
-                .filter_map(|e| self.search_eclass(egraph, e.id))
-                .collect(),
+            Some(ENodeOrVar::Var(_)) => Box::new(egraph.classes().map(|e| e.id)),
+            // an empty pattern matches nothing
+            None => Box::new(std::iter::empty()),
         }
     }
 
-    fn search_eclass(&self, egraph: &EGraph<L, A>, eclass: Id) -> Option<SearchMatches<L>> {
-        let substs = self.program.run(egraph, eclass);
+    /// Search `eclass` on `machine`, which keeps its allocations for
+    /// the next search.
+    fn search_eclass_in<A: Analysis<L>>(
+        &self,
+        machine: &mut machine::Machine,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        limit: usize,
+    ) -> Option<SearchMatches<L>> {
+        let substs = self.program.run_in(machine, egraph, eclass, limit);
         if substs.is_empty() {
             None
         } else {
+            let eclass = egraph.find(eclass);
             let ast = Some(Cow::Borrowed(&self.ast));
             Some(SearchMatches {
                 eclass,
@@ -296,6 +682,129 @@ impl<L: Language, A: Analysis<L>> Searcher<L, A> for Pattern<L> {
         }
     }
 
+    /// Apply this pattern to one match for [`Applier::apply_matches`],
+    /// returning the instantiated eclass if that changed the egraph.
+    fn apply_subst<A: Analysis<L>, B: Bindings + ?Sized>(
+        &self,
+        egraph: &mut EGraph<L, A>,
+        id_buf: &mut [Id],
+        eclass: Id,
+        subst: &B,
+        searcher_ast: Option<&PatternAst<L>>,
+        rule_name: Symbol,
+    ) -> Option<Id> {
+        let ast = self.ast.as_ref();
+        egraph.set_provenance_match(rule_name, subst);
+        let did_something;
+        let id;
+        if egraph.are_explanations_enabled() {
+            let subst = subst.to_subst();
+            let (id_temp, did_something_temp) =
+                egraph.union_instantiations(searcher_ast.unwrap(), &self.ast, &subst, rule_name);
+            did_something = did_something_temp;
+            id = id_temp;
+        } else {
+            if egraph.is_redundant_application(ast, eclass, subst)
+                || egraph.is_duplicate_application(ast, eclass, subst)
+            {
+                return None;
+            }
+            id = apply_pat(id_buf, ast, egraph, subst);
+            did_something = egraph.union(id, eclass);
+            egraph.record_application(ast, eclass, subst);
+        }
+        Some(id).filter(|_| did_something)
+    }
+}
+
+impl<L: Language, A: Analysis<L>> Searcher<L, A> for Pattern<L> {
+    fn get_pattern_ast(&self) -> Option<&PatternAst<L>> {
+        Some(&self.ast)
+    }
+
+    fn search_into(&self, egraph: &EGraph<L, A>, arena: &mut MatchArena) -> Vec<ArenaMatches<L>> {
+        self.search_into_with_limit(egraph, arena, usize::MAX)
+    }
+
+    fn search_into_with_limit(
+        &self,
+        egraph: &EGraph<L, A>,
+        arena: &mut MatchArena,
+        limit_per_class: usize,
+    ) -> Vec<ArenaMatches<L>> {
+        let mut matches = vec![];
+        for eclass in self.candidate_classes(egraph) {
+            if arena.step_budget_exhausted() {
+                break;
+            }
+            let eclass = egraph.find(eclass);
+            let substs = self
+                .program
+                .run_into_with_limit(egraph, eclass, arena, limit_per_class);
+            if !substs.is_empty() {
+                let ast = Some(Cow::Borrowed(&self.ast));
+                matches.push(ArenaMatches {
+                    eclass,
+                    substs,
+                    ast,
+                });
+            }
+        }
+        matches
+    }
+
+    fn search(&self, egraph: &EGraph<L, A>) -> Vec<SearchMatches<L>> {
+        // one machine for every eclass, so its registers are allocated once
+        let mut machine = machine::Machine::default();
+        self.candidate_classes(egraph)
+            .filter_map(|eclass| self.search_eclass_in(&mut machine, egraph, eclass, usize::MAX))
+            .collect()
+    }
+
+    /// Each rayon job reuses one machine across the eclasses it
+    /// searches.
+    /// Rayon may split the eclasses into more jobs than there are
+    /// threads, so there can be more machines than threads.
+    #[cfg(feature = "parallel")]
+    fn parallel_search(&self, egraph: &EGraph<L, A>) -> Vec<SearchMatches<L>>
+    where
+        L: Send + Sync,
+        EGraph<L, A>: Sync,
+    {
+        use rayon::prelude::*;
+
+        let mut ids: Vec<Id> = self.candidate_classes(egraph).collect();
+        ids.sort_unstable();
+        ids.par_iter()
+            .map_init(machine::Machine::default, |machine, &eclass| {
+                self.search_eclass_in(machine, egraph, eclass, usize::MAX)
+            })
+            .flatten()
+            .collect()
+    }
+
+    fn search_eclass(&self, egraph: &EGraph<L, A>, eclass: Id) -> Option<SearchMatches<L>> {
+        self.search_eclass_with_limit(egraph, eclass, usize::MAX)
+    }
+
+    /// Searches on a machine kept for each thread, so searching many
+    /// eclasses one at a time only allocates as the machine grows.
+    fn search_eclass_with_limit(
+        &self,
+        egraph: &EGraph<L, A>,
+        eclass: Id,
+        limit: usize,
+    ) -> Option<SearchMatches<L>> {
+        SCRATCH_MACHINE.with(|machine| match machine.try_borrow_mut() {
+            Ok(mut machine) => self.search_eclass_in(&mut machine, egraph, eclass, limit),
+            // a search from within a search, say by a condition
+            Err(_) => {
+                let mut machine = machine::Machine::default();
+                self.search_eclass_in(&mut machine, egraph, eclass, limit)
+            }
+        })
+    }
+
     fn vars(&self) -> Vec<Var> {
         Pattern::vars(self)
     }
@@ -317,26 +826,33 @@ where
         rule_name: Symbol,
     ) -> Vec<Id> {
         let mut added = vec![];
-        let ast = self.ast.as_ref();
-        let mut id_buf = vec![0.into(); ast.len()];
+        let mut id_buf = vec![0.into(); self.ast.as_ref().len()];
         for mat in matches {
             let sast = mat.ast.as_ref().map(|cow| cow.as_ref());
             for subst in &mat.substs {
-                let did_something;
-                let id;
-                if egraph.are_explanations_enabled() {
-                    let (id_temp, did_something_temp) =
-                        egraph.union_instantiations(sast.unwrap(), &self.ast, subst, rule_name);
-                    did_something = did_something_temp;
-                    id = id_temp;
-                } else {
-                    id = apply_pat(&mut id_buf, ast, egraph, subst);
-                    did_something = egraph.union(id, mat.eclass);
-                }
+                let applied =
+                    self.apply_subst(egraph, &mut id_buf, mat.eclass, subst, sast, rule_name);
+                added.extend(applied);
+            }
+        }
+        added
+    }
 
-                if did_something {
-                    added.push(id)
-                }
+    fn apply_arena_matches(
+        &self,
+        egraph: &mut EGraph<L, A>,
+        arena: &MatchArena,
+        matches: &[ArenaMatches<L>],
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let mut added = vec![];
+        let mut id_buf = vec![0.into(); self.ast.as_ref().len()];
+        for mat in matches {
+            let sast = mat.ast.as_ref().map(|cow| cow.as_ref());
+            for subst in arena.iter(&mat.substs) {
+                let applied =
+                    self.apply_subst(egraph, &mut id_buf, mat.eclass, &subst, sast, rule_name);
+                added.extend(applied);
             }
         }
         added
@@ -374,22 +890,30 @@ where
     }
 }
 
-pub(crate) fn apply_pat<L: Language, A: Analysis<L>>(
+pub(crate) fn apply_pat<L: Language, A: Analysis<L>, B: Bindings + ?Sized>(
     ids: &mut [Id],
     pat: &[ENodeOrVar<L>],
     egraph: &mut EGraph<L, A>,
-    subst: &Subst,
+    subst: &B,
 ) -> Id {
     debug_assert_eq!(pat.len(), ids.len());
+    assert!(!pat.is_empty(), "Can't instantiate an empty pattern");
+    let pat = egraph.flattened_pattern(pat);
     trace!("apply_rec {:2?} {:?}", pat, subst);
 
     for (i, pat_node) in pat.iter().enumerate() {
         let id = match pat_node {
-            ENodeOrVar::Var(w) => subst[*w],
+            ENodeOrVar::Var(w) => subst.id_of(*w),
             ENodeOrVar::ENode(e) => {
                 let n = e.clone().map_children(|child| ids[usize::from(child)]);
                 trace!("adding: {:?}", n);
-                egraph.add(n)
+                // the root is unioned with the match anyway, and adding
+                // it as is keeps rules like commutativity working
+                if i + 1 < pat.len() {
+                    egraph.add_instantiated_node(n)
+                } else {
+                    egraph.add(n)
+                }
             }
         };
         ids[i] = id;
@@ -398,6 +922,102 @@ pub(crate) fn apply_pat<L: Language, A: Analysis<L>>(
     *ids.last().unwrap()
 }
 
+/// Count how many enodes [`apply_pat`] would add for this pattern and
+/// substitution, looking up subterms exactly as [`apply_pat`] adds them.
+/// A subterm with a child that isn't in the egraph is counted as new
+/// without being looked up.
+pub(crate) fn count_new_nodes<L: Language, A: Analysis<L>, B: Bindings + ?Sized>(
+    pat: &[ENodeOrVar<L>],
+    egraph: &EGraph<L, A>,
+    subst: &B,
+) -> usize {
+    lookup_pat(&egraph.flattened_pattern(pat), egraph, subst).1
+}
+
+/// Find the eclass [`apply_pat`] would return for this pattern and
+/// substitution without changing the egraph, if all of the instantiated
+/// term is already there.
+pub(crate) fn lookup_instantiation<L: Language, A: Analysis<L>, B: Bindings + ?Sized>(
+    pat: &[ENodeOrVar<L>],
+    egraph: &EGraph<L, A>,
+    subst: &B,
+) -> Option<Id> {
+    let (ids, _) = lookup_pat(&egraph.flattened_pattern(pat), egraph, subst);
+    ids.last().copied().flatten()
+}
+
+/// Hash the term [`apply_pat`] would instantiate for this pattern and
+/// substitution without changing the egraph: the root enode with its
+/// children's canonical eclasses, or the eclass of a variable.
+///
+/// Returns `None` if one of the root's children isn't in the egraph,
+/// since then the term is certainly new.
+pub(crate) fn instantiation_hash<L: Language, A: Analysis<L>, B: Bindings + ?Sized>(
+    pat: &[ENodeOrVar<L>],
+    egraph: &EGraph<L, A>,
+    subst: &B,
+) -> Option<u64> {
+    let pat = egraph.flattened_pattern(pat);
+    let mut hasher = fxhash::FxHasher::default();
+    match pat.last()? {
+        ENodeOrVar::Var(v) => egraph.find(subst.id_of(*v)).hash(&mut hasher),
+        ENodeOrVar::ENode(e) => {
+            let (ids, _) = lookup_pat(&pat, egraph, subst);
+            if !e.all(|child| ids[usize::from(child)].is_some()) {
+                return None;
+            }
+            let mut node = e
+                .clone()
+                .map_children(|child| egraph.find(ids[usize::from(child)].unwrap()));
+            if let Some(flattening) = &egraph.flattening {
+                flattening.sort(&mut node);
+            }
+            node.hash(&mut hasher)
+        }
+    }
+    Some(hasher.finish())
+}
+
+/// Look up each subterm of this pattern and substitution the way
+/// [`apply_pat`] adds them, returning their eclasses (if they are in
+/// the egraph) and the number of enodes [`apply_pat`] would add.
+/// The pattern must already be [flattened](EGraph::flattened_pattern).
+fn lookup_pat<L: Language, A: Analysis<L>, B: Bindings + ?Sized>(
+    pat: &[ENodeOrVar<L>],
+    egraph: &EGraph<L, A>,
+    subst: &B,
+) -> (Vec<Option<Id>>, usize) {
+    let mut ids: Vec<Option<Id>> = Vec::with_capacity(pat.len());
+    let mut n_new = 0;
+    for (i, pat_node) in pat.iter().enumerate() {
+        let id = match pat_node {
+            ENodeOrVar::Var(w) => Some(subst.id_of(*w)),
+            ENodeOrVar::ENode(e) => {
+                let found = if e.all(|child| ids[usize::from(child)].is_some()) {
+                    let mut node = e
+                        .clone()
+                        .map_children(|child| ids[usize::from(child)].unwrap());
+                    egraph.lookup(&mut node).or_else(|| {
+                        if i + 1 < pat.len() {
+                            egraph.lookup_canonical(&node)
+                        } else {
+                            None
+                        }
+                    })
+                } else {
+                    None
+                };
+                if found.is_none() {
+                    n_new += 1;
+                }
+                found
+            }
+        };
+        ids.push(id);
+    }
+    (ids, n_new)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -467,4 +1087,177 @@ mod tests {
         assert_eq!(n_matches("(f ?x (g ?x))))"), 1);
         assert_eq!(n_matches("(h ?x 0 0)"), 1);
     }
+
+    #[test]
+    fn substs_follow_the_var_order() {
+        let mut egraph = EGraph::default();
+        egraph.add_expr(&"(f (g a b) (h c (g a b)))".parse().unwrap());
+        egraph.add_expr(&"(k a (k b (k c d)))".parse().unwrap());
+        egraph.rebuild();
+
+        let var = |s: &str| -> Var { s.parse().unwrap() };
+        let order = [var("?c"), var("?a"), var("?b")];
+        let patterns = [
+            ("(f (g ?a ?b) (h ?c (g ?a ?b)))", "?a ?b ?c"),
+            ("(f ?x (h ?c ?x))", "?x ?c"),
+            ("(k ?a (k ?b (k ?c ?d)))", "?a ?b ?c ?d"),
+            ("(k ?c (k ?a (k ?b ?d)))", "?c ?a ?b ?d"),
+            ("(k ?b ?c)", "?b ?c"),
+        ];
+        let check = |pattern: &Pattern<S>, expected: &[Var]| {
+            assert_eq!(pattern.vars(), expected);
+            let matches = pattern.search(&egraph);
+            assert!(!matches.is_empty());
+            for m in &matches {
+                for subst in &m.substs {
+                    let vars: Vec<Var> = subst.iter().map(|(v, _)| v).collect();
+                    assert_eq!(vars, expected);
+                }
+                for packed in pattern.search_eclass_packed(&egraph, m.eclass) {
+                    assert_eq!(&packed.schema()[..], expected);
+                }
+            }
+        };
+        for &(pattern, default) in &patterns {
+            let pattern: Pattern<S> = pattern.parse().unwrap();
+            let default: Vec<Var> = default.split(' ').map(var).collect();
+            check(&pattern, &default);
+
+            let declared: Vec<Var> = order
+                .iter()
+                .filter(|v| default.contains(v))
+                .copied()
+                .collect();
+            let mut expected: Vec<Var> = declared.clone();
+            expected.extend(default.iter().filter(|v| !declared.contains(v)));
+            check(&pattern.with_var_order(&declared).unwrap(), &expected);
+        }
+
+        let pattern: Pattern<S> = "(k ?b ?c)".parse().unwrap();
+        let err = pattern.with_var_order(&[var("?b"), var("?z")]).unwrap_err();
+        assert_eq!(err, UnknownVar(var("?z")));
+    }
+
+    #[test]
+    fn grouped_search_pivots_search() {
+        let mut egraph = EGraph::default();
+        for expr in &["(/ a b)", "(/ c d)", "(/ (/ a b) d)", "(/ e e)", "(* a b)"] {
+            egraph.add_expr(&expr.parse().unwrap());
+        }
+        let b = egraph.add_expr(&"b".parse().unwrap());
+        let d = egraph.add_expr(&"d".parse().unwrap());
+        egraph.union(b, d);
+        egraph.rebuild();
+
+        let d_var: Var = "?d".parse().unwrap();
+        for pattern in &["(/ ?n ?d)", "(/ ?d ?d)", "(/ (/ ?n ?d) ?e)"] {
+            let pattern: Pattern<S> = pattern.parse().unwrap();
+            let grouped = pattern.search_grouped_by(&egraph, d_var);
+
+            // the same as grouping the matches of a search
+            let mut expected: HashMap<Id, Vec<(Id, Subst)>> = HashMap::default();
+            let mut matches = pattern.search(&egraph);
+            matches.sort_by_key(|m| m.eclass);
+            for m in matches {
+                for subst in m.substs {
+                    let key = egraph.find(subst[d_var]);
+                    expected.entry(key).or_default().push((m.eclass, subst));
+                }
+            }
+            assert_eq!(grouped, expected);
+            assert!(grouped.keys().all(|&id| egraph.find(id) == id));
+        }
+
+        let pattern: Pattern<S> = "(/ ?n ?d)".parse().unwrap();
+        let grouped = pattern.search_grouped_by(&egraph, d_var);
+        let b = egraph.find(b);
+        assert_eq!(grouped[&b].len(), 3);
+        assert!(grouped[&b].windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Variable ?z is not in the pattern")]
+    fn grouped_search_by_unknown_var_panics() {
+        let egraph = EGraph::default();
+        let pattern: Pattern<S> = "(/ ?n ?d)".parse().unwrap();
+        pattern.search_grouped_by(&egraph, "?z".parse().unwrap());
+    }
+
+    #[test]
+    fn conjunct_binds_shared_vars_once() {
+        let mut egraph = EGraph::default();
+        let root = egraph.add_expr(&"(neg a)".parse().unwrap());
+        for expr in &["(abs b)", "(sq a b)", "(sq b b)"] {
+            let id = egraph.add_expr(&expr.parse().unwrap());
+            egraph.union(root, id);
+        }
+        egraph.rebuild();
+        let lookup = |s: &str| egraph.lookup_expr(&s.parse().unwrap()).unwrap();
+        let (a, b) = (lookup("a"), lookup("b"));
+        let (x, y): (Var, Var) = ("?x".parse().unwrap(), "?y".parse().unwrap());
+
+        let search = |patterns: &[&str]| {
+            let patterns: Vec<Pattern<S>> = patterns.iter().map(|p| p.parse().unwrap()).collect();
+            Pattern::search_conjunct_in_class(&patterns, &egraph, root)
+        };
+        let pairs = |substs: Vec<Subst>| -> Vec<(Id, Id)> {
+            let mut pairs: Vec<_> = substs.iter().map(|s| (s[x], s[y])).collect();
+            pairs.sort_unstable();
+            pairs
+        };
+
+        // ?x is bound by (neg ?x), and (abs ?x) disagrees
+        assert!(search(&["(neg ?x)", "(abs ?x)"]).is_empty());
+        assert_eq!(search(&["(neg ?x)", "(abs ?y)"]).len(), 1);
+        // (sq ?x ?y) matches two enodes, (neg ?x) keeps the one with a
+        assert_eq!(pairs(search(&["(neg ?x)", "(sq ?x ?y)"])), vec![(a, b)]);
+        assert_eq!(pairs(search(&["(sq ?x ?y)", "(abs ?y)"])), {
+            let mut expected = vec![(a, b), (b, b)];
+            expected.sort_unstable();
+            expected
+        });
+        // both patterns may match the same enode
+        assert_eq!(search(&["(sq ?x ?x)", "(sq ?y ?y)"]).len(), 1);
+        assert!(search(&["(neg ?x)", "(neg b)"]).is_empty());
+        assert_eq!(search(&["(neg ?x)", "(neg a)"]).len(), 1);
+        assert_eq!(search(&[]), vec![Subst::default()]);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_search_matches_serial() {
+        let mut egraph = EGraph::default();
+        for i in 0..2000 {
+            let x = egraph.add(S::leaf(format!("x{}", i)));
+            let y = egraph.add(S::leaf(format!("y{}", i % 7)));
+            let xy = egraph.add(S::new("*", vec![x, y]));
+            let fx = egraph.add(S::new("f", vec![x]));
+            egraph.add(S::new("+", vec![xy, fx]));
+            if i % 3 == 0 {
+                egraph.union(x, y);
+            }
+        }
+        egraph.rebuild();
+
+        let in_order = |mut matches: Vec<SearchMatches<S>>| {
+            matches.sort_by_key(|m| m.eclass);
+            matches
+                .into_iter()
+                .map(|m| (m.eclass, m.substs))
+                .collect::<Vec<_>>()
+        };
+        for pattern in &[
+            "(+ (* ?a ?b) (f ?a))",
+            "(* ?a ?a)",
+            "(f ?a)",
+            "?a",
+            "(g ?a)",
+        ] {
+            let pattern: Pattern<S> = pattern.parse().unwrap();
+            let serial = in_order(pattern.search(&egraph));
+            let parallel = pattern.parallel_search(&egraph);
+            assert!(parallel.windows(2).all(|w| w[0].eclass < w[1].eclass));
+            assert_eq!(in_order(parallel), serial, "{}", pattern);
+        }
+    }
 }
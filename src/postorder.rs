@@ -0,0 +1,245 @@
+use thiserror::Error;
+
+use crate::*;
+
+/// An error from [`EGraph::add_from_postorder`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum StreamError {
+    /// The stream had no nodes.
+    #[error("the stream of nodes is empty")]
+    Empty,
+    /// A node's child isn't the position of a node before it.
+    #[error("node {position} has child {child}, which doesn't come before it")]
+    InvalidChild {
+        /// The position of the node in the stream.
+        position: usize,
+        /// The child, as a position in the stream.
+        child: Id,
+    },
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+    /// Add the nodes of a term in post-order, like the nodes of a
+    /// [`RecExpr`], without collecting them into one first, returning
+    /// the eclass of the last one.
+    ///
+    /// Each node's children are the positions in the stream of nodes
+    /// before it, starting from 0.
+    /// Each node is added as soon as it comes, so a subterm the egraph
+    /// already has isn't added again, and only the ids of the nodes so
+    /// far are kept.
+    /// This adds the same enodes as [`add_expr`](EGraph::add_expr) of
+    /// the [`RecExpr`] of the nodes would.
+    ///
+    /// Fails if the stream is empty or a node's child isn't before it;
+    /// the nodes before that one stay added.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [flattening](EGraph::with_flattening) is enabled, since
+    /// flattening a node needs its children's nodes.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let nodes = vec![
+    ///     S::leaf("x"),
+    ///     S::new("f", vec![Id::from(0)]),
+    ///     S::new("+", vec![Id::from(1), Id::from(0)]),
+    /// ];
+    /// let sum = egraph.add_from_postorder(nodes).unwrap();
+    /// assert_eq!(egraph.lookup_expr(&"(+ (f x) x)".parse().unwrap()), Some(sum));
+    ///
+    /// let forward = vec![S::new("f", vec![Id::from(1)]), S::leaf("x")];
+    /// assert_eq!(
+    ///     egraph.add_from_postorder(forward),
+    ///     Err(StreamError::InvalidChild { position: 0, child: Id::from(1) })
+    /// );
+    /// ```
+    pub fn add_from_postorder<I>(&mut self, nodes: I) -> Result<Id, StreamError>
+    where
+        I: IntoIterator<Item = L>,
+    {
+        assert!(
+            !self.is_flattening_enabled(),
+            "Can't add a stream of nodes with flattening enabled"
+        );
+        let mut new_ids = vec![];
+        let mut new_node_q = vec![];
+        for node in nodes {
+            let position = new_ids.len();
+            let invalid = node
+                .children()
+                .iter()
+                .find(|&&c| usize::from(c) >= position);
+            if let Some(&child) = invalid {
+                return Err(StreamError::InvalidChild { position, child });
+            }
+            self.add_expr_node(&node, &mut new_ids, &mut new_node_q);
+        }
+        match new_ids.last() {
+            Some(&id) => Ok(self.find(id)),
+            None => Err(StreamError::Empty),
+        }
+    }
+
+    /// Stream the nodes of the cheapest term of eclass `id` by
+    /// `cost_function` in post-order, like the nodes of the
+    /// [`RecExpr`] [`Extractor::find_best`] would make, without
+    /// collecting them into one.
+    ///
+    /// Each node's children are the positions in the stream of nodes
+    /// before it, and each eclass in the term is streamed once, so the
+    /// stream is as long as the [`RecExpr`], and
+    /// [`add_from_postorder`](EGraph::add_from_postorder) can read it.
+    /// The costs are found when this is called, as by
+    /// [`Extractor::new`]; then only the path to the current node and
+    /// the positions of the eclasses so far are kept.
+    ///
+    /// # Panics
+    ///
+    /// The iterator panics if the term goes through an eclass with no
+    /// finite term, like [`Extractor::find_best`].
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let sum = egraph.add_expr(&"(+ (f x) (f x))".parse().unwrap());
+    /// let other = egraph.add_expr(&"(* (f x) (g (h 2)))".parse().unwrap());
+    /// egraph.union(sum, other);
+    /// egraph.rebuild();
+    ///
+    /// let nodes: Vec<S> = egraph.stream_class_postorder(sum, AstSize).collect();
+    /// // (f x) is only streamed once
+    /// assert_eq!(nodes.len(), 3);
+    /// assert_eq!(RecExpr::from(nodes).to_string(), "(+ (f x) (f x))");
+    /// ```
+    pub fn stream_class_postorder<'a, CF>(
+        &'a self,
+        id: Id,
+        cost_function: CF,
+    ) -> impl Iterator<Item = L> + 'a
+    where
+        CF: CostFunction<L> + 'a,
+    {
+        let extractor = Extractor::new(self, cost_function);
+        let mut positions: HashMap<Id, Id> = HashMap::default();
+        // each eclass, and whether its children are streamed already
+        let mut todo = vec![(self.find(id), false)];
+        std::iter::from_fn(move || {
+            while let Some((class, expanded)) = todo.pop() {
+                if positions.contains_key(&class) {
+                    continue;
+                }
+                let node = extractor.find_best_node(class);
+                if !expanded {
+                    todo.push((class, true));
+                    for &child in node.children().iter().rev() {
+                        let child = self.find(child);
+                        if !positions.contains_key(&child) {
+                            todo.push((child, false));
+                        }
+                    }
+                    continue;
+                }
+                let node = node
+                    .clone()
+                    .map_children(|child| positions[&self.find(child)]);
+                positions.insert(class, Id::from(positions.len()));
+                return Some(node);
+            }
+            None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolLang as S, *};
+
+    /// A term of about `n` nodes, made like a post-order stream of a
+    /// tree, with its leaves and some small subterms shared.
+    fn program(n: usize) -> RecExpr<S> {
+        let mut seed = 7u64;
+        let mut rand = move |bound: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound
+        };
+        let mut expr = RecExpr::default();
+        let leaves: Vec<Id> = (0..20)
+            .map(|i| expr.add(S::leaf(format!("x{}", i))))
+            .collect();
+        let mut stack: Vec<Id> = vec![];
+        while expr.as_ref().len() < n {
+            let arity = 1 + rand(2);
+            let children: Vec<Id> = (0..arity)
+                .map(|_| match stack.len() {
+                    0 => leaves[rand(leaves.len())],
+                    _ if rand(3) == 0 => leaves[rand(leaves.len())],
+                    _ => stack.pop().unwrap(),
+                })
+                .collect();
+            let op = if arity == 1 { "f" } else { ["+", "*"][rand(2)] };
+            stack.push(expr.add(S::new(op, children)));
+        }
+        while stack.len() > 1 {
+            let children = vec![stack.pop().unwrap(), stack.pop().unwrap()];
+            stack.push(expr.add(S::new("+", children)));
+        }
+        expr
+    }
+
+    #[test]
+    fn streams_match_recexprs() {
+        let expr = program(100_000);
+        let mut by_expr = EGraph::<S, ()>::default();
+        let mut by_stream = EGraph::<S, ()>::default();
+        let nodes = expr.as_ref().iter().cloned();
+        let root = by_stream.add_from_postorder(nodes).unwrap();
+        assert_eq!(usize::from(by_expr.add_expr(&expr)), usize::from(root));
+        assert_eq!(by_stream.total_size(), by_expr.total_size());
+        assert_eq!(by_stream.number_of_classes(), by_expr.number_of_classes());
+        // the shared subterms were only added once
+        assert!(by_stream.total_size() < expr.as_ref().len());
+        assert_eq!(by_stream.lookup_expr(&expr), Some(root));
+
+        // and back out, after some unions
+        let mut egraph = by_stream;
+        let n = expr.as_ref().len();
+        for i in 1..50 {
+            egraph.union(Id::from(i * 1999 % n), Id::from(i * 7919 % n));
+        }
+        egraph.rebuild();
+        let extractor = Extractor::new(&egraph, AstSize);
+        for &id in &[root, Id::from(5_000), Id::from(60_000)] {
+            let best = extractor.find_best(id).1;
+            let streamed: Vec<S> = egraph.stream_class_postorder(id, AstSize).collect();
+            let streamed = RecExpr::from(streamed);
+            assert_eq!(streamed.as_ref().len(), best.as_ref().len());
+            assert_eq!(egraph.lookup_expr(&streamed), Some(egraph.find(id)));
+            let mut fresh = EGraph::<S, ()>::default();
+            assert_eq!(fresh.add_expr(&streamed), fresh.add_expr(&best));
+        }
+    }
+
+    #[test]
+    fn invalid_streams() {
+        let mut egraph = EGraph::<S, ()>::default();
+        assert_eq!(egraph.add_from_postorder(vec![]), Err(StreamError::Empty));
+        let own_child = vec![S::leaf("x"), S::new("f", vec![Id::from(1)])];
+        assert_eq!(
+            egraph.add_from_postorder(own_child),
+            Err(StreamError::InvalidChild {
+                position: 1,
+                child: Id::from(1)
+            })
+        );
+        // the nodes before the invalid one were added
+        assert!(egraph.lookup(S::leaf("x")).is_some());
+        assert_eq!(egraph.number_of_classes(), 1);
+    }
+}
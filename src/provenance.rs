@@ -0,0 +1,511 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use crate::*;
+
+/// Where an enode came from, as recorded by an [`EGraph`] with
+/// [provenance](EGraph::with_provenance) enabled.
+///
+/// See [`EGraph::provenance`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin<L> {
+    /// The enode was added directly, not by applying a rewrite.
+    Input,
+    /// The enode was added while applying a rewrite.
+    Rule {
+        /// The name of the rewrite.
+        rule: Symbol,
+        /// The match the rewrite was applied to, with each variable
+        /// mapped to a term from its eclass at the time.
+        ///
+        /// This is empty if the rewrite's [`Applier`] overrides
+        /// [`apply_matches`](Applier::apply_matches) without reporting
+        /// its matches.
+        subst: Arc<[(Var, RecExpr<L>)]>,
+        /// The [`Runner`] iteration in which the rewrite was applied,
+        /// or 0 outside of a [`Runner`].
+        iteration: usize,
+    },
+}
+
+/// The enodes [`EGraph::growth_attribution`] attributes to one rule.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeCount {
+    /// The number of enodes the rule added that are still in the egraph.
+    pub total: usize,
+    /// The same enodes, counted by the [`Runner`] iteration that added
+    /// them.
+    ///
+    /// This is empty for the `"<input>"` pseudo-rule, since input enodes
+    /// don't record an iteration.
+    pub by_iteration: BTreeMap<usize, usize>,
+}
+
+/// The per-enode provenance an [`EGraph`] keeps when enabled.
+#[derive(Debug, Clone)]
+pub(crate) struct Provenance<L> {
+    /// The origin of each enode, indexed by [`ENodeId`].
+    pub(crate) origins: Vec<Origin<L>>,
+    /// The rule and match currently being applied, if any.
+    pub(crate) current: Option<(Symbol, Subst)>,
+    /// The origin for `current`, computed when it is first needed.
+    pub(crate) current_origin: Option<Origin<L>>,
+    pub(crate) iteration: usize,
+}
+
+impl<L: Clone> Provenance<L> {
+    pub(crate) fn new(n_enodes: usize) -> Self {
+        Self {
+            origins: vec![Origin::Input; n_enodes],
+            current: None,
+            current_origin: None,
+            iteration: 0,
+        }
+    }
+
+    /// Forget every origin.
+    pub(crate) fn clear(&mut self) {
+        self.origins.clear();
+        self.current = None;
+        self.current_origin = None;
+        self.iteration = 0;
+    }
+
+    pub(crate) fn set_current(&mut self, current: Option<(Symbol, Subst)>) {
+        self.current = current;
+        self.current_origin = None;
+    }
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+    /// Enable or disable recording the [`Origin`] of each new enode.
+    ///
+    /// Enodes that are already in the egraph when provenance is enabled
+    /// are recorded as [`Origin::Input`].
+    /// Recording an [`Origin::Rule`] builds a term for each variable in
+    /// the match, so this costs a good deal of time and memory; it is off
+    /// by default.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, rewrite as rw};
+    /// let rules: &[Rewrite<SymbolLang, ()>] = &[rw!("double"; "(* ?x 2)" => "(+ ?x ?x)")];
+    /// let egraph = EGraph::default().with_provenance(true);
+    /// let runner = Runner::default()
+    ///     .with_egraph(egraph)
+    ///     .with_expr(&"(* a 2)".parse().unwrap())
+    ///     .run(rules);
+    ///
+    /// let egraph = &runner.egraph;
+    /// let plus = egraph.lookup_expr(&"(+ a a)".parse().unwrap()).unwrap();
+    /// let (enode, _) = egraph[plus]
+    ///     .nodes_with_ids()
+    ///     .find(|(_, n)| n.op == "+".into())
+    ///     .unwrap();
+    /// match egraph.provenance(enode) {
+    ///     Origin::Rule { rule, subst, iteration } => {
+    ///         assert_eq!(*rule, "double".into());
+    ///         assert_eq!(subst[0].1.to_string(), "a");
+    ///         assert_eq!(*iteration, 0);
+    ///     }
+    ///     Origin::Input => panic!("(+ a a) came from a rule"),
+    /// }
+    /// ```
+    pub fn with_provenance(mut self, enabled: bool) -> Self {
+        if !enabled {
+            self.provenance = None;
+        } else if self.provenance.is_none() {
+            self.provenance = Some(Provenance::new(self.enode_classes.len()));
+        }
+        self
+    }
+
+    /// Check if provenance is enabled.
+    pub fn is_provenance_enabled(&self) -> bool {
+        self.provenance.is_some()
+    }
+
+    /// Returns the recorded [`Origin`] of the enode with the given
+    /// [`ENodeId`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if provenance is not [enabled](EGraph::with_provenance).
+    /// See [`try_provenance`](EGraph::try_provenance) for a version that
+    /// fails instead.
+    pub fn provenance(&self, id: ENodeId) -> &Origin<L> {
+        self.try_provenance(id).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Returns the recorded [`Origin`] of the enode with the given
+    /// [`ENodeId`], or fails with [`Error::ProvenanceDisabled`] if
+    /// provenance is not [enabled](EGraph::with_provenance).
+    pub fn try_provenance(&self, id: ENodeId) -> Result<&Origin<L>, Error> {
+        let provenance = self.provenance.as_ref().ok_or(Error::ProvenanceDisabled)?;
+        Ok(&provenance.origins[usize::from(id)])
+    }
+
+    /// Counts the enodes now in the egraph by the rule that added them,
+    /// according to their [provenance](EGraph::provenance).
+    ///
+    /// Enodes with an [`Origin::Input`], like those added with
+    /// [`add_expr`](EGraph::add_expr), are attributed to the `"<input>"`
+    /// pseudo-rule.
+    /// Enodes that [`rebuild`](EGraph::rebuild) found to be duplicates
+    /// are no longer in the egraph, so the totals sum to
+    /// [`total_number_of_nodes`](EGraph::total_number_of_nodes) of a
+    /// rebuilt egraph.
+    ///
+    /// Returns [`Error::ProvenanceDisabled`] if provenance isn't
+    /// [enabled](EGraph::with_provenance).
+    pub fn growth_attribution(&self) -> Result<BTreeMap<Symbol, NodeCount>, Error> {
+        let provenance = self.provenance.as_ref().ok_or(Error::ProvenanceDisabled)?;
+        let input = Symbol::from("<input>");
+        let mut counts: BTreeMap<Symbol, NodeCount> = BTreeMap::new();
+        for class in self.classes() {
+            for (enode, _) in class.nodes_with_ids() {
+                match &provenance.origins[usize::from(enode)] {
+                    Origin::Input => counts.entry(input).or_default().total += 1,
+                    Origin::Rule {
+                        rule, iteration, ..
+                    } => {
+                        let count = counts.entry(*rule).or_default();
+                        count.total += 1;
+                        *count.by_iteration.entry(*iteration).or_default() += 1;
+                    }
+                }
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Writes the [`growth_attribution`](EGraph::growth_attribution) in
+    /// the collapsed stack format that flame graph tools, like
+    /// `flamegraph.pl` and `inferno-flamegraph`, read.
+    ///
+    /// Each line is a rule name and its number of enodes, sorted by rule
+    /// name.
+    /// With `by_iteration`, each rule's enodes are nested under it by
+    /// iteration instead, as `rule;iteration 3 12`.
+    /// Semicolons in rule names, which would separate frames, are
+    /// replaced by colons.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, rewrite as rw};
+    /// let rules: &[Rewrite<SymbolLang, ()>] = &[rw!("double"; "(* ?x 2)" => "(+ ?x ?x)")];
+    /// let runner = Runner::default()
+    ///     .with_egraph(EGraph::default().with_provenance(true))
+    ///     .with_expr(&"(* a 2)".parse().unwrap())
+    ///     .run(rules);
+    /// let stacks = runner.egraph.growth_collapsed_stacks(true).unwrap();
+    /// assert_eq!(stacks, "<input> 3\ndouble;iteration 0 1\n");
+    /// ```
+    pub fn growth_collapsed_stacks(&self, by_iteration: bool) -> Result<String, Error> {
+        let mut counts: Vec<(String, NodeCount)> = self
+            .growth_attribution()?
+            .into_iter()
+            .map(|(rule, count)| (rule.as_str().replace(';', ":"), count))
+            .collect();
+        counts.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut out = String::new();
+        for (rule, count) in &counts {
+            if by_iteration && !count.by_iteration.is_empty() {
+                for (iteration, n) in &count.by_iteration {
+                    writeln!(out, "{};iteration {} {}", rule, iteration, n).unwrap();
+                }
+            } else {
+                writeln!(out, "{} {}", rule, count.total).unwrap();
+            }
+        }
+        Ok(out)
+    }
+
+    /// Record that the following additions are from applying `rule` to
+    /// the match `subst`.
+    pub(crate) fn set_provenance_match<B>(&mut self, rule: Symbol, subst: &B)
+    where
+        B: Bindings + ?Sized,
+    {
+        self.current_rule = Some(rule);
+        if self.union_mode == UnionMode::Record {
+            self.current_subst = Some(subst.to_subst());
+        }
+        if let Some(provenance) = &mut self.provenance {
+            provenance.set_current(Some((rule, subst.to_subst())));
+        }
+    }
+
+    /// Record that the following additions are from applying the
+    /// current rule, if any, to the match `subst`.
+    pub(crate) fn set_provenance_subst(&mut self, subst: &Subst) {
+        if let Some(rule) = self.current_rule {
+            self.set_provenance_match(rule, subst);
+        }
+    }
+
+    /// Record that the following additions are not from a rule.
+    pub(crate) fn clear_provenance_match(&mut self) {
+        self.current_rule = None;
+        self.current_subst = None;
+        if let Some(provenance) = &mut self.provenance {
+            provenance.set_current(None);
+        }
+    }
+
+    pub(crate) fn set_provenance_iteration(&mut self, iteration: usize) {
+        if let Some(provenance) = &mut self.provenance {
+            provenance.iteration = iteration;
+        }
+    }
+
+    /// Record the origin of an enode that was just given an [`ENodeId`].
+    pub(crate) fn record_provenance(&mut self) {
+        let provenance = match &self.provenance {
+            Some(provenance) => provenance,
+            None => return,
+        };
+        let origin = match (&provenance.current, &provenance.current_origin) {
+            (None, _) => Origin::Input,
+            (Some(_), Some(origin)) => origin.clone(),
+            (Some((rule, subst)), None) => {
+                let subst: Vec<(Var, RecExpr<L>)> = subst
+                    .vec
+                    .iter()
+                    .map(|&(v, id)| (v, self.oldest_term(id)))
+                    .collect();
+                Origin::Rule {
+                    rule: *rule,
+                    subst: subst.into(),
+                    iteration: provenance.iteration,
+                }
+            }
+        };
+
+        let provenance = self.provenance.as_mut().unwrap();
+        if provenance.current.is_some() {
+            provenance.current_origin = Some(origin.clone());
+        }
+        provenance.origins.push(origin);
+        debug_assert_eq!(provenance.origins.len(), self.enode_classes.len());
+    }
+
+    /// Build a term for an eclass, picking in each eclass the enode with
+    /// the smallest [`ENodeId`] that leads to a finite term.
+    ///
+    /// When enodes are only ever added, the oldest enode always works,
+    /// since its children were added before it.
+    /// But [`rebuild`](EGraph::rebuild) drops duplicate enodes and
+    /// [`Analysis::modify`] may remove enodes, so an eclass's oldest
+    /// enode can lead back to it; then the next oldest is tried.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the eclass has no finite term left, which only happens
+    /// if enodes were removed from every one of its terms.
+    fn oldest_term(&self, id: Id) -> RecExpr<L> {
+        fn build<L: Language, N: Analysis<L>>(
+            egraph: &EGraph<L, N>,
+            id: Id,
+            memo: &mut HashMap<Id, Id>,
+            visiting: &mut HashSet<Id>,
+            expr: &mut RecExpr<L>,
+        ) -> Option<Id> {
+            let id = egraph.find(id);
+            if let Some(&i) = memo.get(&id) {
+                return Some(i);
+            }
+            if !visiting.insert(id) {
+                return None;
+            }
+            let mut nodes: Vec<(ENodeId, &L)> = egraph[id].nodes_with_ids().collect();
+            nodes.sort_unstable_by_key(|(enode_id, _)| *enode_id);
+            let found = nodes.into_iter().find_map(|(_, node)| {
+                let children = node
+                    .children()
+                    .iter()
+                    .map(|&child| build(egraph, child, memo, visiting, expr))
+                    .collect::<Option<Vec<Id>>>()?;
+                let mut children = children.into_iter();
+                Some(expr.add(node.clone().map_children(|_| children.next().unwrap())))
+            });
+            visiting.remove(&id);
+            if let Some(i) = found {
+                memo.insert(id, i);
+            }
+            found
+        }
+
+        let mut expr = RecExpr::default();
+        let mut visiting = HashSet::default();
+        build(self, id, &mut HashMap::default(), &mut visiting, &mut expr)
+            .unwrap_or_else(|| panic!("{}", Error::Unextractable(id)));
+        expr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    fn origin_rule_of(egraph: &EGraph<S, ()>, expr: &str) -> Option<(Symbol, usize)> {
+        let id = egraph.lookup_expr(&expr.parse().unwrap()).unwrap();
+        let op = expr.trim_start_matches('(').split(' ').next().unwrap();
+        let (enode, _) = egraph[id]
+            .nodes_with_ids()
+            .find(|(_, n)| n.op.as_str() == op)
+            .unwrap();
+        match egraph.provenance(enode) {
+            Origin::Input => None,
+            Origin::Rule {
+                rule, iteration, ..
+            } => Some((*rule, *iteration)),
+        }
+    }
+
+    #[test]
+    fn provenance_records_rules() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rw!("mul-two"; "(* ?x 2)" => "(<< ?x 1)"),
+            rw!("shift-to-f"; "(<< ?x 1)" => "(f ?x)"),
+        ];
+        let runner = Runner::default()
+            .with_egraph(EGraph::default().with_provenance(true))
+            .with_expr(&"(* (g a) 2)".parse().unwrap())
+            .run(&rules);
+        let egraph = &runner.egraph;
+
+        assert_eq!(origin_rule_of(egraph, "(* (g a) 2)"), None);
+        assert_eq!(origin_rule_of(egraph, "(g a)"), None);
+        assert_eq!(
+            origin_rule_of(egraph, "(<< (g a) 1)"),
+            Some(("mul-two".into(), 0))
+        );
+        assert_eq!(origin_rule_of(egraph, "1"), Some(("mul-two".into(), 0)));
+        assert_eq!(
+            origin_rule_of(egraph, "(f (g a))"),
+            Some(("shift-to-f".into(), 1))
+        );
+
+        let dot = egraph.dot().with_rule_colors(true).to_string();
+        assert!(dot.contains("tooltip = \"shift-to-f\""));
+        assert!(!egraph.dot().to_string().contains("fillcolor"));
+    }
+
+    #[test]
+    fn growth_attribution_counts_every_enode() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rw!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rw!("double"; "(+ ?a ?a)" => "(* ?a 2)"),
+            rw!("shift;left"; "(* ?a 2)" => "(<< ?a 1)"),
+        ];
+        assert!(matches!(
+            EGraph::<S, ()>::default().growth_attribution(),
+            Err(Error::ProvenanceDisabled)
+        ));
+
+        let runner = Runner::default()
+            .with_egraph(EGraph::default().with_provenance(true))
+            .with_expr(&"(+ (+ x y) (+ x y))".parse().unwrap())
+            .run(&rules);
+        let egraph = &runner.egraph;
+        let counts = egraph.growth_attribution().unwrap();
+        let total: usize = counts.values().map(|c| c.total).sum();
+        assert_eq!(total, egraph.total_number_of_nodes());
+
+        let count = |rule: &str| counts[&Symbol::from(rule)].clone();
+        assert_eq!(count("<input>").total, 4);
+        assert!(count("<input>").by_iteration.is_empty());
+        // (+ y x), then (* (+ x y) 2) and 2, then (<< (+ x y) 1) and 1
+        assert_eq!(count("comm").total, 1);
+        assert_eq!(
+            count("double").by_iteration.into_iter().collect::<Vec<_>>(),
+            vec![(0, 2)]
+        );
+        assert_eq!(
+            count("shift;left")
+                .by_iteration
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![(1, 2)]
+        );
+
+        let stacks = egraph.growth_collapsed_stacks(false).unwrap();
+        assert_eq!(stacks, "<input> 4\ncomm 1\ndouble 2\nshift:left 2\n");
+        let stacks = egraph.growth_collapsed_stacks(true).unwrap();
+        assert!(stacks.contains("\ndouble;iteration 0 2\nshift:left;iteration 1 2\n"));
+        let total: usize = stacks
+            .lines()
+            .map(|l| l.rsplit(' ').next().unwrap().parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(total, egraph.total_number_of_nodes());
+    }
+
+    #[test]
+    fn provenance_subst_terms() {
+        let rule: Rewrite<S, ()> = rw!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)");
+        let mut egraph = EGraph::default().with_provenance(true);
+        let x = egraph.add_expr(&"(h x)".parse().unwrap());
+        let y = egraph.add_expr(&"y".parse().unwrap());
+        egraph.add_expr(&"(+ (h x) y)".parse().unwrap());
+        // the term for ?a should use the older enode (h x), not z
+        let z = egraph.add_expr(&"z".parse().unwrap());
+        egraph.union(x, z);
+        egraph.rebuild();
+        rule.run(&mut egraph);
+
+        let yx = egraph.add(S::new("+", vec![y, x]));
+        let (enode, _) = egraph[yx]
+            .nodes_with_ids()
+            .find(|(_, n)| n.children == vec![y, egraph.find(x)])
+            .unwrap();
+        match egraph.provenance(enode) {
+            Origin::Rule { subst, .. } => {
+                let terms: Vec<String> =
+                    subst.iter().map(|(v, t)| format!("{}={}", v, t)).collect();
+                assert_eq!(terms, vec!["?a=(h x)", "?b=y"]);
+            }
+            Origin::Input => panic!("expected a rule origin"),
+        }
+    }
+
+    #[test]
+    fn conditions_record_each_match() {
+        let rule: Rewrite<S, ()> =
+            rw!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)" if ConditionEqual::parse("(f ?a)", "(f ?a)"));
+        let mut egraph = EGraph::default().with_provenance(true);
+        let xy = egraph.add_expr(&"(+ x y)".parse().unwrap());
+        let zw = egraph.add_expr(&"(+ z w)".parse().unwrap());
+        egraph.union(xy, zw);
+        egraph.rebuild();
+        rule.run(&mut egraph);
+
+        // each (f ?a) the condition added comes from its own match
+        for a in &["x", "z"] {
+            let f = egraph.lookup_expr(&format!("(f {})", a).parse().unwrap());
+            let (enode, _) = egraph[f.unwrap()].nodes_with_ids().next().unwrap();
+            match egraph.provenance(enode) {
+                Origin::Rule { subst, .. } => assert_eq!(subst[0].1.to_string(), *a),
+                Origin::Input => panic!("expected a rule origin"),
+            }
+        }
+    }
+
+    #[test]
+    fn oldest_term_skips_enodes_that_lead_back() {
+        let mut egraph = EGraph::<S, ()>::default().with_provenance(true);
+        let a = egraph.add(S::leaf("a"));
+        let fa = egraph.add(S::new("f", vec![a]));
+        let b = egraph.add(S::leaf("b"));
+        egraph.union(a, fa);
+        egraph.union(a, b);
+        egraph.rebuild();
+        assert_eq!(egraph.oldest_term(b).to_string(), "a");
+
+        // now the oldest enode is (f <itself>)
+        egraph.retain_nodes(a, |n| n.op.as_str() != "a");
+        assert_eq!(egraph.oldest_term(b).to_string(), "b");
+    }
+}
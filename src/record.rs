@@ -0,0 +1,247 @@
+use crate::*;
+
+use log::*;
+
+/// Whether an [`EGraph`] performs its unions, set with
+/// [`EGraph::set_union_mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnionMode {
+    /// Unions merge eclasses, as usual.
+    Perform,
+    /// Unions are only recorded, as [`RecordedUnion`]s, leaving the
+    /// union-find alone; the egraph only grows by added enodes.
+    Record,
+}
+
+impl Default for UnionMode {
+    fn default() -> Self {
+        UnionMode::Perform
+    }
+}
+
+/// A union that an [`EGraph`] in [`UnionMode::Record`] would have
+/// performed, returned by [`EGraph::recorded_unions`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedUnion {
+    /// The first id given to the union.
+    pub id1: Id,
+    /// The second id given to the union.
+    pub id2: Id,
+    /// The canonical id the unioned eclass would have had.
+    pub winner: Id,
+    /// The rule whose application made the union, if any.
+    pub rule: Option<Symbol>,
+    /// The match the rule was applied to, if any.
+    pub subst: Option<Subst>,
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+    /// Start performing or recording unions.
+    ///
+    /// In [`UnionMode::Record`], a [`union`](EGraph::union) (including
+    /// through [`union_instantiations`](EGraph::union_instantiations),
+    /// an [`Applier`] or [`Analysis::modify`]) that would merge two
+    /// eclasses is [recorded](EGraph::recorded_unions) with the rule and
+    /// match that made it, and returns `true` without merging them.
+    /// The union is checked as usual first, so unions of
+    /// [frozen](EGraph::freeze) eclasses are still skipped, and
+    /// [distinctness checks](EGraph::with_distinctness_checks) still
+    /// panic.
+    /// Since nothing is merged, [`rebuild`](EGraph::rebuild) has no
+    /// congruences to restore, so a recording egraph stays a forest of
+    /// the terms added to it; a [`Runner`] running it saturates when an
+    /// iteration adds no enodes.
+    ///
+    /// Switch back to [`UnionMode::Perform`] and
+    /// [replay](EGraph::replay_unions) the recorded unions to perform
+    /// them.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// egraph.set_union_mode(UnionMode::Record);
+    /// let x = egraph.add(S::leaf("x"));
+    /// let y = egraph.add(S::leaf("y"));
+    /// assert!(egraph.union(x, y));
+    /// egraph.rebuild();
+    /// assert_ne!(egraph.find(x), egraph.find(y));
+    ///
+    /// egraph.set_union_mode(UnionMode::Perform);
+    /// let unions = egraph.take_recorded_unions();
+    /// assert_eq!(egraph.replay_unions(&unions), 1);
+    /// egraph.rebuild();
+    /// assert_eq!(egraph.find(x), egraph.find(y));
+    /// ```
+    pub fn set_union_mode(&mut self, mode: UnionMode) {
+        self.union_mode = mode;
+    }
+
+    /// Returns whether this egraph performs or records its unions.
+    pub fn union_mode(&self) -> UnionMode {
+        self.union_mode
+    }
+
+    /// Returns the unions recorded in [`UnionMode::Record`], in order.
+    ///
+    /// A union is recorded each time it's made, so a rule matching
+    /// again in a later iteration records its unions again.
+    pub fn recorded_unions(&self) -> &[RecordedUnion] {
+        &self.recorded_unions
+    }
+
+    /// Returns the recorded unions, and forgets them.
+    pub fn take_recorded_unions(&mut self) -> Vec<RecordedUnion> {
+        std::mem::take(&mut self.recorded_unions)
+    }
+
+    /// Perform the given recorded unions, in order, returning how many
+    /// of them merged eclasses.
+    ///
+    /// Unions made by a rule are justified by it when explanations are
+    /// enabled.
+    /// Like [`union`](EGraph::union), this doesn't
+    /// [`rebuild`](EGraph::rebuild).
+    ///
+    /// # Panics
+    ///
+    /// Panics if this egraph is still in [`UnionMode::Record`].
+    pub fn replay_unions(&mut self, unions: &[RecordedUnion]) -> usize {
+        assert_eq!(
+            self.union_mode,
+            UnionMode::Perform,
+            "Can't replay unions while recording them"
+        );
+        let mut performed = 0;
+        for union in unions {
+            self.current_rule = union.rule;
+            let did_union = match union.rule {
+                Some(rule) => {
+                    let rule = Some(Justification::Rule(rule));
+                    self.perform_union(union.id1, union.id2, rule, false)
+                }
+                None => self.union(union.id1, union.id2),
+            };
+            performed += did_union as usize;
+        }
+        self.current_rule = None;
+        performed
+    }
+
+    /// Record the union of `id1` and `id2` into the canonical `winner`,
+    /// made by the rule and match currently being applied.
+    pub(crate) fn record_union(&mut self, id1: Id, id2: Id, winner: Id) {
+        let union = RecordedUnion {
+            id1,
+            id2,
+            winner,
+            rule: self.current_rule,
+            subst: self.current_subst.clone(),
+        };
+        debug!("Recording union {:?}", union);
+        self.recorded_unions.push(union);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, SymbolLang as S, *};
+
+    fn rules() -> Vec<Rewrite<S, ()>> {
+        vec![
+            rw!("comm"; "(+ ?x ?y)" => "(+ ?y ?x)"),
+            rw!("zero"; "(+ ?x 0)" => "?x"),
+            rw!("one"; "(* ?x 1)" => "?x"),
+            rw!("double"; "(+ ?x ?x)" => "(* 2 ?x)"),
+        ]
+    }
+
+    const EXPRS: &[&str] = &[
+        "(+ (* a 1) (* a 1))",
+        "(+ b 0)",
+        "(+ 0 b)",
+        "(* 2 (* a 1))",
+        "(* 2 a)",
+        "(* a 1)",
+        "a",
+        "b",
+    ];
+
+    fn egraph() -> EGraph<S, ()> {
+        let mut egraph = EGraph::default();
+        for expr in &EXPRS[..2] {
+            egraph.add_expr(&expr.parse().unwrap());
+        }
+        egraph.rebuild();
+        egraph
+    }
+
+    #[test]
+    fn recording_leaves_the_union_find_alone() {
+        let runner = Runner::default()
+            .with_egraph(egraph())
+            .with_union_mode(UnionMode::Record)
+            .run(&rules());
+        assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+        assert_eq!(runner.iterations.len(), 2);
+        let egraph = &runner.egraph;
+        assert_eq!(egraph.number_of_classes(), egraph.total_size());
+        let lookup = |s: &str| egraph.lookup_expr(&s.parse().unwrap()).unwrap();
+
+        let unions = egraph.recorded_unions();
+        let one = unions
+            .iter()
+            .find(|u| u.rule == Some("one".into()))
+            .unwrap();
+        assert_eq!(one.winner, lookup("(* a 1)"));
+        let x = "?x".parse().unwrap();
+        assert_eq!(one.subst.as_ref().unwrap()[x], lookup("a"));
+        // every iteration records its unions again
+        let ones = unions.iter().filter(|u| u.rule == Some("one".into()));
+        assert_eq!(ones.count(), 2);
+    }
+
+    #[test]
+    fn replaying_recorded_unions_matches_a_normal_run() {
+        let normal = Runner::default().with_egraph(egraph()).run(&rules());
+        let mut recorded = Runner::default()
+            .with_egraph(egraph())
+            .with_union_mode(UnionMode::Record)
+            .run(&rules())
+            .egraph;
+
+        recorded.set_union_mode(UnionMode::Perform);
+        let unions = recorded.take_recorded_unions();
+        assert!(recorded.replay_unions(&unions) > 0);
+        recorded.rebuild();
+
+        let (normal, recorded) = (&normal.egraph, &recorded);
+        assert_eq!(recorded.number_of_classes(), normal.number_of_classes());
+        assert_eq!(recorded.total_size(), normal.total_size());
+        let ids = |egraph: &EGraph<S, ()>| -> Vec<Id> {
+            let exprs = EXPRS.iter().map(|s| s.parse().unwrap());
+            exprs.map(|e| egraph.lookup_expr(&e).unwrap()).collect()
+        };
+        let (normal_ids, recorded_ids) = (ids(normal), ids(recorded));
+        for i in 0..EXPRS.len() {
+            for j in 0..EXPRS.len() {
+                assert_eq!(
+                    normal_ids[i] == normal_ids[j],
+                    recorded_ids[i] == recorded_ids[j],
+                    "{} and {}",
+                    EXPRS[i],
+                    EXPRS[j]
+                );
+            }
+        }
+        assert_eq!(normal_ids[0], normal_ids[4]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't replay unions while recording them")]
+    fn replaying_while_recording_panics() {
+        let mut egraph = egraph();
+        egraph.set_union_mode(UnionMode::Record);
+        egraph.replay_unions(&[]);
+    }
+}
@@ -1,6 +1,11 @@
-use pattern::apply_pat;
+use pattern::{apply_pat, count_new_nodes};
+use std::borrow::Cow;
 use std::fmt::{self, Debug, Display};
-use std::{any::Any, sync::Arc};
+use std::iter::FromIterator;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
 
 use crate::*;
 
@@ -22,25 +27,41 @@ pub struct Rewrite<L, N> {
     pub searcher: Arc<dyn Searcher<L, N> + Sync + Send>,
     /// The applier (right-hand side) of the rewrite.
     pub applier: Arc<dyn Applier<L, N> + Sync + Send>,
+    /// The order to apply matches in, if any.
+    /// See [`with_match_ranker`](Rewrite::with_match_ranker).
+    pub ranker: Option<Arc<dyn MatchRanker<L, N> + Sync + Send>>,
+    /// The most matches to search for in each eclass, if any.
+    /// See [`with_max_matches_per_class`](Rewrite::with_max_matches_per_class).
+    pub max_matches_per_class: Option<usize>,
+    /// The rules this rewrite is searched together with, if any.
+    /// See [`with_search_group`](Rewrite::with_search_group).
+    pub search_group: Option<Symbol>,
+    /// How to search in parallel, if enabled.
+    pub(crate) parallel_search: Option<ParallelSearch<L, N>>,
 }
 
+/// Searches a [`Rewrite`] with [`Rewrite::parallel_search`], captured
+/// where its bounds are known.
+pub(crate) type ParallelSearch<L, N> =
+    for<'a> fn(&'a Rewrite<L, N>, &EGraph<L, N>) -> Vec<SearchMatches<'a, L>>;
+
 impl<L, N> Debug for Rewrite<L, N>
 where
-    L: Language + Display + 'static,
-    N: 'static,
+    L: Language + Display,
+    N: Analysis<L>,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut d = f.debug_struct("Rewrite");
         d.field("name", &self.name);
 
-        if let Some(pat) = Any::downcast_ref::<Pattern<L>>(&self.searcher) {
-            d.field("searcher", &DisplayAsDebug(pat));
+        if let Some(ast) = self.searcher.get_pattern_ast() {
+            d.field("searcher", &DisplayAsDebug(ast));
         } else {
             d.field("searcher", &"<< searcher >>");
         }
 
-        if let Some(pat) = Any::downcast_ref::<Pattern<L>>(&self.applier) {
-            d.field("applier", &DisplayAsDebug(pat));
+        if let Some(ast) = self.applier.get_pattern_ast() {
+            d.field("applier", &DisplayAsDebug(ast));
         } else {
             d.field("applier", &"<< applier >>");
         }
@@ -58,14 +79,26 @@ impl<L: Language, N: Analysis<L>> Rewrite<L, N> {
         searcher: impl Searcher<L, N> + Send + Sync + 'static,
         applier: impl Applier<L, N> + Send + Sync + 'static,
     ) -> Result<Self, String> {
+        Self::try_new(name, searcher, applier).map_err(|e| e.to_string())
+    }
+
+    /// Create a new [`Rewrite`], or fail with [`Error::UnboundVar`] if
+    /// the applier uses a variable the searcher doesn't bind.
+    ///
+    /// This is like [`new`](Rewrite::new), but with an [`Error`].
+    pub fn try_new(
+        name: impl Into<Symbol>,
+        searcher: impl Searcher<L, N> + Send + Sync + 'static,
+        applier: impl Applier<L, N> + Send + Sync + 'static,
+    ) -> Result<Self, Error> {
         let name = name.into();
         let searcher = Arc::new(searcher);
         let applier = Arc::new(applier);
 
         let bound_vars = searcher.vars();
-        for v in applier.vars() {
-            if !bound_vars.contains(&v) {
-                return Err(format!("Rewrite {} refers to unbound var {}", name, v));
+        for var in applier.vars() {
+            if !bound_vars.contains(&var) {
+                return Err(Error::UnboundVar { rule: name, var });
             }
         }
 
@@ -73,21 +106,419 @@ impl<L: Language, N: Analysis<L>> Rewrite<L, N> {
             name,
             searcher,
             applier,
+            ranker: None,
+            max_matches_per_class: None,
+            search_group: None,
+            parallel_search: None,
         })
     }
 
-    /// Call [`search`] on the [`Searcher`].
+    /// Apply the matches of this rewrite in order of the scores that
+    /// `ranker` gives them, highest first.
+    ///
+    /// Ties keep the order they were found in.
+    /// This matters for appliers that only apply some of their matches,
+    /// like [`BudgetedApplier`], which will then apply the best ones.
+    ///
+    /// ```
+    /// use egg::*;
+    /// let rule: Rewrite<SymbolLang, ()> = rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)")
+    ///     // prefer matches in bigger eclasses
+    ///     .with_match_ranker(|egraph: &dyn EGraphView<SymbolLang, ()>, id: Id, _: &Subst| {
+    ///         egraph.class(id).nodes.len() as i64
+    ///     });
+    /// ```
+    pub fn with_match_ranker(
+        mut self,
+        ranker: impl MatchRanker<L, N> + Send + Sync + 'static,
+    ) -> Self {
+        self.ranker = Some(Arc::new(ranker));
+        self
+    }
+
+    /// Stop searching each eclass after `limit` matches.
+    ///
+    /// A pattern rooted in an eclass with many enodes can match it
+    /// in a quadratic number of ways; this caps how many of them a
+    /// single eclass contributes to an iteration, leaving the rest of
+    /// the egraph unaffected.
+    /// The [`Runner`] reports the eclasses with the most matches in
+    /// [`Iteration::top_fanout`].
+    ///
+    /// ```
+    /// use egg::*;
+    /// let rule: Rewrite<SymbolLang, ()> = rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)")
+    ///     .with_max_matches_per_class(100);
+    /// ```
+    pub fn with_max_matches_per_class(mut self, limit: usize) -> Self {
+        self.max_matches_per_class = Some(limit);
+        self
+    }
+
+    /// Have a [`Runner`] search this rewrite together with the other
+    /// rewrites of the same `group`.
+    ///
+    /// The [`Pattern`]s of a group are compiled into one
+    /// [`ProgramSet`], so when many of them start the same way, like
+    /// `(* ?a ...)`, each eclass is searched for that start once for the
+    /// whole group.
+    /// Each rewrite still gets the same matches as searching it alone,
+    /// see [`search_group_into`](Rewrite::search_group_into).
+    ///
+    /// ```
+    /// use egg::*;
+    /// let rules: Vec<Rewrite<SymbolLang, ()>> = vec![
+    ///     rewrite!("mul-1"; "(* ?a 1)" => "?a").with_search_group("mul"),
+    ///     rewrite!("mul-0"; "(* ?a 0)" => "0").with_search_group("mul"),
+    /// ];
+    /// let runner = Runner::default()
+    ///     .with_scheduler(SimpleScheduler)
+    ///     .with_expr(&"(+ (* x 1) (* y 0))".parse().unwrap())
+    ///     .run(&rules);
+    /// let id = runner.egraph.lookup_expr(&"(+ x 0)".parse().unwrap());
+    /// assert_eq!(id, Some(runner.egraph.find(runner.roots[0])));
+    /// ```
+    pub fn with_search_group(mut self, group: impl Into<Symbol>) -> Self {
+        self.search_group = Some(group.into());
+        self
+    }
+
+    /// Search each of `rewrites`, storing the matches in `arena`, and
+    /// return the matches of each in order.
+    ///
+    /// The rewrites whose searchers are [`Pattern`]s are searched at
+    /// once with a [`ProgramSet`], compiled for each call, and their
+    /// matches are dispatched back to each rewrite.
+    /// The others, and those with a
+    /// [match limit](Rewrite::with_max_matches_per_class) or a
+    /// [parallel search](Rewrite::parallel_search), are searched with
+    /// [`search_into`](Rewrite::search_into).
+    /// The searches with a [`ProgramSet`] don't take steps from the
+    /// arena's [step budget](MatchArena::set_step_budget).
+    pub fn search_group_into<'a>(
+        rewrites: &[&'a Self],
+        egraph: &EGraph<L, N>,
+        arena: &mut MatchArena,
+    ) -> Vec<Vec<ArenaMatches<'a, L>>> {
+        let shared: Vec<usize> = (0..rewrites.len())
+            .filter(|&i| {
+                let rw = rewrites[i];
+                rw.searcher.get_pattern_ast().is_some()
+                    && rw.max_matches_per_class.is_none()
+                    && rw.parallel_search.is_none()
+            })
+            .collect();
+        let mut matches: Vec<Vec<ArenaMatches<'a, L>>> = rewrites
+            .iter()
+            .enumerate()
+            .map(|(i, rw)| {
+                if shared.len() < 2 || !shared.contains(&i) {
+                    rw.search_into(egraph, arena)
+                } else {
+                    vec![]
+                }
+            })
+            .collect();
+        if shared.len() < 2 {
+            return matches;
+        }
+
+        let asts: Vec<&'a PatternAst<L>> = shared
+            .iter()
+            .map(|&i| rewrites[i].searcher.get_pattern_ast().unwrap())
+            .collect();
+        let owned: Vec<PatternAst<L>> = asts.iter().map(|&ast| ast.clone()).collect();
+        let set = ProgramSet::compile(&owned);
+        let mut substs: Vec<Vec<Subst>> = vec![vec![]; shared.len()];
+        for class in egraph.classes() {
+            for (pattern, subst) in set.run_in_arena(arena, egraph, class.id) {
+                substs[pattern].push(subst);
+            }
+            for (pattern, substs) in substs.iter_mut().enumerate() {
+                if substs.is_empty() {
+                    continue;
+                }
+                let found = SearchMatches {
+                    eclass: class.id,
+                    substs: std::mem::take(substs),
+                    ast: Some(Cow::Borrowed(asts[pattern])),
+                };
+                matches[shared[pattern]].extend(found.into_arena(arena));
+            }
+        }
+        matches
+    }
+
+    /// Call [`search`] on the [`Searcher`], with at most
+    /// [`max_matches_per_class`](Rewrite::with_max_matches_per_class)
+    /// matches in each eclass.
     ///
     /// [`search`]: Searcher::search()
     pub fn search(&self, egraph: &EGraph<L, N>) -> Vec<SearchMatches<L>> {
-        self.searcher.search(egraph)
+        if let Some(parallel_search) = self.parallel_search {
+            return parallel_search(self, egraph);
+        }
+        match self.max_matches_per_class {
+            None => self.searcher.search(egraph),
+            Some(limit) => {
+                let mut arena = MatchArena::new();
+                let matches = self
+                    .searcher
+                    .search_into_with_limit(egraph, &mut arena, limit);
+                matches
+                    .iter()
+                    .map(|m| m.to_search_matches(&arena))
+                    .collect()
+            }
+        }
+    }
+
+    /// Call [`parallel_search`](Searcher::parallel_search) on the
+    /// [`Searcher`], with at most
+    /// [`max_matches_per_class`](Rewrite::with_max_matches_per_class)
+    /// matches in each eclass.
+    ///
+    /// This is only available with the `parallel` feature.
+    #[cfg(feature = "parallel")]
+    pub fn parallel_search(&self, egraph: &EGraph<L, N>) -> Vec<SearchMatches<L>>
+    where
+        L: Send + Sync,
+        EGraph<L, N>: Sync,
+    {
+        let mut matches = self.searcher.parallel_search(egraph);
+        if let Some(limit) = self.max_matches_per_class {
+            matches.iter_mut().for_each(|m| m.substs.truncate(limit));
+        }
+        matches
+    }
+
+    /// Make [`search`](Rewrite::search) and
+    /// [`search_into`](Rewrite::search_into) search in parallel, with
+    /// [`parallel_search`](Rewrite::parallel_search).
+    ///
+    /// Parallel searches don't take steps from the
+    /// [step budget](MatchArena::set_step_budget) of the arena they're
+    /// stored in.
+    /// See also [`Runner::with_parallel_search`].
+    ///
+    /// This is only available with the `parallel` feature.
+    #[cfg(feature = "parallel")]
+    pub fn with_parallel_search(mut self) -> Self
+    where
+        L: Send + Sync,
+        EGraph<L, N>: Sync,
+    {
+        self.parallel_search = Some(parallel_search::<L, N>);
+        self
     }
 
     /// Call [`apply_matches`] on the [`Applier`].
     ///
+    /// If this rewrite has a [`MatchRanker`], the matches are sorted
+    /// by it first.
+    ///
     /// [`apply_matches`]: Applier::apply_matches()
     pub fn apply(&self, egraph: &mut EGraph<L, N>, matches: &[SearchMatches<L>]) -> Vec<Id> {
-        self.applier.apply_matches(egraph, matches, self.name)
+        if let Some(ranker) = &self.ranker {
+            // ranking regroups the substs, so it needs its own copy
+            let matches = matches
+                .iter()
+                .map(|m| SearchMatches {
+                    eclass: m.eclass,
+                    substs: m.substs.clone(),
+                    ast: m.ast.as_ref().map(|ast| Cow::Borrowed(ast.as_ref())),
+                })
+                .collect();
+            let ranked = rank_matches(&**ranker, egraph, matches);
+            return self.apply_unranked(egraph, &ranked);
+        }
+        self.apply_unranked(egraph, matches)
+    }
+
+    fn apply_unranked(&self, egraph: &mut EGraph<L, N>, matches: &[SearchMatches<L>]) -> Vec<Id> {
+        // in case the applier doesn't report its matches for provenance
+        egraph.set_provenance_match(self.name, &Subst::default());
+        let ids = self.applier.apply_matches(egraph, matches, self.name);
+        egraph.clear_provenance_match();
+        ids
+    }
+
+    /// Like [`search`](Rewrite::search), but stop once the searcher has
+    /// taken `steps` steps, as with a
+    /// [step budget](MatchArena::set_step_budget).
+    /// Also returns whether the steps ran out, so the matches may be
+    /// incomplete.
+    pub fn search_with_step_limit(
+        &self,
+        egraph: &EGraph<L, N>,
+        steps: usize,
+    ) -> (Vec<SearchMatches<L>>, bool) {
+        let mut arena = MatchArena::new();
+        arena.set_step_budget(Some(steps));
+        let matches = self.search_into(egraph, &mut arena);
+        let matches = matches
+            .iter()
+            .map(|m| m.to_search_matches(&arena))
+            .collect();
+        (matches, arena.step_budget_exhausted())
+    }
+
+    /// Call [`search_into`](Searcher::search_into) on the [`Searcher`],
+    /// with at most
+    /// [`max_matches_per_class`](Rewrite::with_max_matches_per_class)
+    /// matches in each eclass.
+    pub fn search_into(
+        &self,
+        egraph: &EGraph<L, N>,
+        arena: &mut MatchArena,
+    ) -> Vec<ArenaMatches<L>> {
+        if let Some(parallel_search) = self.parallel_search {
+            let mut matches = vec![];
+            for m in parallel_search(self, egraph) {
+                matches.extend(m.into_arena(arena));
+            }
+            return matches;
+        }
+        match self.max_matches_per_class {
+            None => self.searcher.search_into(egraph, arena),
+            Some(limit) => self.searcher.search_into_with_limit(egraph, arena, limit),
+        }
+    }
+
+    /// Like [`apply`](Rewrite::apply), for matches stored in `arena`.
+    pub fn apply_from(
+        &self,
+        egraph: &mut EGraph<L, N>,
+        arena: &MatchArena,
+        matches: &[ArenaMatches<L>],
+    ) -> Vec<Id> {
+        if let Some(ranker) = &self.ranker {
+            let matches = matches.iter().map(|m| m.to_search_matches(arena)).collect();
+            let ranked = rank_matches(&**ranker, egraph, matches);
+            return self.apply_unranked(egraph, &ranked);
+        }
+        egraph.set_provenance_match(self.name, &Subst::default());
+        let ids = self
+            .applier
+            .apply_arena_matches(egraph, arena, matches, self.name);
+        egraph.clear_provenance_match();
+        ids
+    }
+
+    /// Search for this rewrite and apply its matches in one pass,
+    /// stopping after `limit` matches.
+    ///
+    /// Unlike [`search`](Rewrite::search) followed by
+    /// [`apply`](Rewrite::apply), this never holds more than about
+    /// `batch_size` matches: eclasses are searched one at a time, and
+    /// whenever `batch_size` matches have been found they are applied.
+    /// The egraph is rebuilt after each batch, so later
+    /// eclasses are searched in an egraph that already has the enodes
+    /// and unions of earlier batches, and may match terms that two-phase
+    /// search would only find in the next iteration.
+    /// Each batch is re-canonicalized right before it's applied.
+    /// An eclass that is merged into an already searched one isn't
+    /// searched again, so some of its matches may wait for the next
+    /// iteration.
+    /// A [`MatchRanker`] only orders the matches within each batch.
+    ///
+    /// Returns the number of matches found and the number of
+    /// applications that changed the egraph.
+    pub fn search_apply(
+        &self,
+        egraph: &mut EGraph<L, N>,
+        limit: usize,
+        batch_size: usize,
+    ) -> (usize, usize) {
+        self.search_apply_probing(egraph, limit, batch_size, false)
+    }
+
+    /// Like [`search_apply`](Rewrite::search_apply), but if `probe` is
+    /// set, search for one match past `limit` without applying it, so
+    /// the number of matches found is more than `limit` if and only if
+    /// there were more.
+    pub(crate) fn search_apply_probing(
+        &self,
+        egraph: &mut EGraph<L, N>,
+        limit: usize,
+        batch_size: usize,
+        probe: bool,
+    ) -> (usize, usize) {
+        let search_limit = limit.saturating_add(probe as usize);
+        let ids: Vec<Id> = egraph.classes().map(|class| class.id).collect();
+        let mut searched = HashSet::default();
+        let mut batch = vec![];
+        let mut n_batched = 0;
+        let mut n_matches = 0;
+        let mut n_applied = 0;
+        for id in ids {
+            if n_matches >= search_limit {
+                break;
+            }
+            let found = egraph.with_clean_view(|egraph| {
+                let id = egraph.find(id);
+                if searched.insert(id) {
+                    let per_class = self.max_matches_per_class.unwrap_or(usize::MAX);
+                    let limit = per_class.min(search_limit - n_matches);
+                    self.searcher.search_eclass_with_limit(egraph, id, limit)
+                } else {
+                    None
+                }
+            });
+            if let Some(mut m) = found {
+                let n_found = m.substs.len();
+                // the probe is counted, but not applied
+                let n_kept = n_found.min(limit.saturating_sub(n_matches));
+                m.substs.truncate(n_kept);
+                n_matches += n_found;
+                n_batched += n_kept;
+                if n_kept > 0 {
+                    batch.push(m);
+                }
+            }
+            if n_batched >= batch_size {
+                n_applied += self.apply_batch(egraph, &mut batch);
+                n_batched = 0;
+            }
+        }
+        n_applied += self.apply_batch(egraph, &mut batch);
+        (n_matches, n_applied)
+    }
+
+    /// Canonicalize and apply a batch of [`search_apply`](Rewrite::search_apply),
+    /// leaving it empty.
+    fn apply_batch(&self, egraph: &mut EGraph<L, N>, batch: &mut Vec<SearchMatches<L>>) -> usize {
+        if batch.is_empty() {
+            return 0;
+        }
+        for m in batch.iter_mut() {
+            m.eclass = egraph.find(m.eclass);
+            for subst in &mut m.substs {
+                for (_, id) in subst.vec.iter_mut() {
+                    *id = egraph.find(*id);
+                }
+            }
+        }
+        let n_applied = self.apply(egraph, batch).len();
+        batch.clear();
+        n_applied
+    }
+
+    /// Returns false if this rewrite can never match in `egraph`.
+    ///
+    /// That's the case when the searcher's pattern mentions an operator
+    /// that isn't in `egraph` and that the language says can never
+    /// exist (see [`Language::op_exists`]).
+    /// Searchers that aren't patterns are always applicable.
+    pub fn is_applicable_to(&self, egraph: &EGraph<L, N>) -> bool {
+        let ast = match self.searcher.get_pattern_ast() {
+            Some(ast) => ast,
+            None => return true,
+        };
+        ast.as_ref().iter().all(|node| match node {
+            ENodeOrVar::Var(_) => true,
+            ENodeOrVar::ENode(n) => n.op_exists() || egraph.has_op(n),
+        })
     }
 
     /// This `run` is for testing use only. You should use things
@@ -114,11 +545,176 @@ impl<L: Language, N: Analysis<L>> Rewrite<L, N> {
     }
 }
 
+/// Regroup `matches` in order of descending score.
+///
+/// Consecutive matches from the same [`SearchMatches`] stay together.
+/// Only indices are sorted, and the substs are moved, not copied.
+fn rank_matches<'a, L, N>(
+    ranker: &dyn MatchRanker<L, N>,
+    egraph: &EGraph<L, N>,
+    mut matches: Vec<SearchMatches<'a, L>>,
+) -> Vec<SearchMatches<'a, L>>
+where
+    L: Language,
+    N: Analysis<L>,
+{
+    // score everything first, so only the indices get sorted
+    let n_matches = matches.iter().map(|m| m.substs.len()).sum();
+    let mut order = Vec::with_capacity(n_matches);
+    for (i, mat) in matches.iter().enumerate() {
+        for (j, subst) in mat.substs.iter().enumerate() {
+            let score = ranker.score(egraph, mat.eclass, subst);
+            order.push((std::cmp::Reverse(score), i, j));
+        }
+    }
+    // the indices break ties, so this is stable
+    order.sort_unstable();
+
+    let mut substs: Vec<Vec<Option<Subst>>> = matches
+        .iter_mut()
+        .map(|m| {
+            std::mem::take(&mut m.substs)
+                .into_iter()
+                .map(Some)
+                .collect()
+        })
+        .collect();
+    let mut ranked: Vec<SearchMatches<L>> = vec![];
+    let mut last = None;
+    for (_, i, j) in order {
+        let subst = substs[i][j].take().unwrap();
+        if last == Some(i) {
+            ranked.last_mut().unwrap().substs.push(subst);
+        } else {
+            last = Some(i);
+            ranked.push(SearchMatches {
+                eclass: matches[i].eclass,
+                substs: vec![subst],
+                ast: matches[i].ast.clone(),
+            });
+        }
+    }
+    ranked
+}
+
+#[cfg(feature = "parallel")]
+fn parallel_search<'a, L, N>(
+    rewrite: &'a Rewrite<L, N>,
+    egraph: &EGraph<L, N>,
+) -> Vec<SearchMatches<'a, L>>
+where
+    L: Language + Send + Sync,
+    N: Analysis<L>,
+    EGraph<L, N>: Sync,
+{
+    rewrite.parallel_search(egraph)
+}
+
+/// Scores the matches of a [`Rewrite`], so that the ones with the
+/// highest scores are applied first.
+/// See [`Rewrite::with_match_ranker`].
+///
+/// This is implemented for functions of type
+/// `Fn(&dyn EGraphView<L, N>, Id, &Subst) -> i64`.
+pub trait MatchRanker<L, N>
+where
+    L: Language,
+    N: Analysis<L>,
+{
+    /// Score the match `subst` found in `eclass`.
+    fn score(&self, egraph: &dyn EGraphView<L, N>, eclass: Id, subst: &Subst) -> i64;
+}
+
+impl<L, F, N> MatchRanker<L, N> for F
+where
+    L: Language,
+    N: Analysis<L>,
+    F: Fn(&dyn EGraphView<L, N>, Id, &Subst) -> i64,
+{
+    fn score(&self, egraph: &dyn EGraphView<L, N>, eclass: Id, subst: &Subst) -> i64 {
+        self(egraph, eclass, subst)
+    }
+}
+
+/// A collection of [`Rewrite`]s.
+///
+/// A [`RuleSet`] derefs to a slice of its rules, and a reference to one
+/// can be passed directly to [`Runner::run`].
+#[derive(Clone)]
+pub struct RuleSet<L, N> {
+    rules: Vec<Rewrite<L, N>>,
+}
+
+impl<L, N> Default for RuleSet<L, N> {
+    fn default() -> Self {
+        Self { rules: vec![] }
+    }
+}
+
+impl<L, N> Debug for RuleSet<L, N>
+where
+    L: Language + Display,
+    N: Analysis<L>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.rules).finish()
+    }
+}
+
+impl<L, N> From<Vec<Rewrite<L, N>>> for RuleSet<L, N> {
+    fn from(rules: Vec<Rewrite<L, N>>) -> Self {
+        Self { rules }
+    }
+}
+
+impl<L, N> FromIterator<Rewrite<L, N>> for RuleSet<L, N> {
+    fn from_iter<I: IntoIterator<Item = Rewrite<L, N>>>(iter: I) -> Self {
+        Self {
+            rules: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<L, N> Deref for RuleSet<L, N> {
+    type Target = [Rewrite<L, N>];
+    fn deref(&self) -> &Self::Target {
+        &self.rules
+    }
+}
+
+impl<'a, L, N> IntoIterator for &'a RuleSet<L, N> {
+    type Item = &'a Rewrite<L, N>;
+    type IntoIter = std::slice::Iter<'a, Rewrite<L, N>>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.rules.iter()
+    }
+}
+
+impl<L: Language, N: Analysis<L>> RuleSet<L, N> {
+    /// Split off the rules that can never match in `egraph`.
+    ///
+    /// Returns the rules that are
+    /// [applicable](Rewrite::is_applicable_to) to `egraph`, and the
+    /// names of the ones that were dropped.
+    pub fn prune_inapplicable(&self, egraph: &EGraph<L, N>) -> (Self, Vec<Symbol>) {
+        let mut dropped = vec![];
+        let mut rules = vec![];
+        for rule in &self.rules {
+            if rule.is_applicable_to(egraph) {
+                rules.push(rule.clone());
+            } else {
+                dropped.push(rule.name);
+            }
+        }
+        (Self { rules }, dropped)
+    }
+}
+
 /// The lefthand side of a [`Rewrite`].
 ///
 /// A [`Searcher`] is something that can search the egraph and find
 /// matching substititions.
-/// Right now the only significant [`Searcher`] is [`Pattern`].
+/// The significant [`Searcher`]s are [`Pattern`] and [`MultiPattern`].
 ///
 pub trait Searcher<L, N>
 where
@@ -129,6 +725,23 @@ where
     /// This should not return a SearchMatches with no substs.
     fn search_eclass(&self, egraph: &EGraph<L, N>, eclass: Id) -> Option<SearchMatches<L>>;
 
+    /// Search one eclass like [`search_eclass`](Searcher::search_eclass),
+    /// but return at most `limit` matches.
+    ///
+    /// The default implementation truncates the result of
+    /// [`search_eclass`](Searcher::search_eclass); [`Pattern`]s stop
+    /// searching at the limit.
+    fn search_eclass_with_limit(
+        &self,
+        egraph: &EGraph<L, N>,
+        eclass: Id,
+        limit: usize,
+    ) -> Option<SearchMatches<L>> {
+        let mut matches = self.search_eclass(egraph, eclass)?;
+        matches.substs.truncate(limit);
+        Some(matches).filter(|m| !m.substs.is_empty())
+    }
+
     /// Search the whole [`EGraph`], returning a list of all the
     /// [`SearchMatches`] where something was found.
     /// This just calls [`search_eclass`] on each eclass.
@@ -141,6 +754,65 @@ where
             .collect()
     }
 
+    /// Search the whole [`EGraph`] like [`search`](Searcher::search),
+    /// but search the eclasses in parallel, with
+    /// [`rayon`](https://docs.rs/rayon).
+    ///
+    /// The matches come in the order of their eclass ids, so two
+    /// searches of the same egraph give the same result, serial or
+    /// parallel.
+    /// The default implementation sorts the results of
+    /// [`search`](Searcher::search) without going parallel;
+    /// [`Pattern`]s search in parallel.
+    ///
+    /// This is only available with the `parallel` feature.
+    #[cfg(feature = "parallel")]
+    fn parallel_search(&self, egraph: &EGraph<L, N>) -> Vec<SearchMatches<L>>
+    where
+        L: Send + Sync,
+        EGraph<L, N>: Sync,
+    {
+        let mut matches = self.search(egraph);
+        matches.sort_by_key(|m| m.eclass);
+        matches
+    }
+
+    /// Search the whole [`EGraph`] like [`search`](Searcher::search),
+    /// but store the substitutions in `arena`.
+    ///
+    /// The default implementation moves the results of
+    /// [`search`](Searcher::search) into the arena; [`Pattern`]s search
+    /// into it directly.
+    fn search_into(&self, egraph: &EGraph<L, N>, arena: &mut MatchArena) -> Vec<ArenaMatches<L>> {
+        let mut matches = vec![];
+        for m in self.search(egraph) {
+            matches.extend(m.into_arena(arena));
+        }
+        matches
+    }
+
+    /// Search the whole [`EGraph`] like
+    /// [`search_into`](Searcher::search_into), but with at most
+    /// `limit_per_class` matches in each eclass.
+    ///
+    /// The default implementation calls
+    /// [`search_eclass_with_limit`](Searcher::search_eclass_with_limit)
+    /// on each eclass.
+    fn search_into_with_limit(
+        &self,
+        egraph: &EGraph<L, N>,
+        arena: &mut MatchArena,
+        limit_per_class: usize,
+    ) -> Vec<ArenaMatches<L>> {
+        let mut matches = vec![];
+        for class in egraph.classes() {
+            if let Some(m) = self.search_eclass_with_limit(egraph, class.id, limit_per_class) {
+                matches.extend(m.into_arena(arena));
+            }
+        }
+        matches
+    }
+
     /// Returns the number of matches in the e-graph
     fn n_matches(&self, egraph: &EGraph<L, N>) -> usize {
         self.search(egraph).iter().map(|m| m.substs.len()).sum()
@@ -155,81 +827,361 @@ where
     fn vars(&self) -> Vec<Var>;
 }
 
-/// The righthand side of a [`Rewrite`].
+/// A [`Searcher`] that matches on [`Analysis`] data instead of syntax.
 ///
-/// An [`Applier`] is anything that can do something with a
-/// substitition ([`Subst`]). This allows you to implement rewrites
-/// that determine when and how to respond to a match using custom
-/// logic, including access to the [`Analysis`] data of an [`EClass`].
+/// A [`FactSearcher`] calls its function on every eclass (together with
+/// that eclass's data) and produces one match wherever the function
+/// returns `Some`.
+/// The returned [`Subst`] may bind any of the variables the
+/// [`FactSearcher`] was created with to [`Id`]s of the function's choosing,
+/// so an [`Applier`] can refer to them.
 ///
-/// Notably, [`Pattern`] implements [`Applier`], which suffices in
-/// most cases.
-/// Additionally, `egg` provides [`ConditionalApplier`] to stack
-/// [`Condition`]s onto an [`Applier`], which in many cases can save
-/// you from having to implement your own applier.
+/// Paired with a custom [`Applier`], this lets analysis-driven rewrites
+/// run inside a [`Runner`] alongside ordinary syntactic ones.
 ///
 /// # Example
+///
+/// Materialize eclasses whose interval is a single value as constants:
 /// ```
-/// use egg::{rewrite as rw, *};
-/// use std::sync::Arc;
+/// use egg::*;
 ///
 /// define_language! {
 ///     enum Math {
-///         Num(i32),
+///         Num(i64),
 ///         "+" = Add([Id; 2]),
 ///         "*" = Mul([Id; 2]),
 ///         Symbol(Symbol),
 ///     }
 /// }
 ///
-/// type EGraph = egg::EGraph<Math, MinSize>;
-///
-/// // Our metadata in this case will be size of the smallest
-/// // represented expression in the eclass.
+/// // the interval of values an eclass may take, or None if unbounded
 /// #[derive(Default)]
-/// struct MinSize;
-/// impl Analysis<Math> for MinSize {
-///     type Data = usize;
+/// struct Interval;
+/// impl Analysis<Math> for Interval {
+///     type Data = Option<(i64, i64)>;
+///     fn make(egraph: &EGraph<Math, Self>, enode: &Math) -> Self::Data {
+///         let x = |i: &Id| egraph[*i].data;
+///         let policy = OverflowPolicy::RefuseToFold;
+///         match enode {
+///             Math::Num(n) => Some((*n, *n)),
+///             // a bound that overflows is unknown
+///             Math::Add([a, b]) => {
+///                 let ((a_lo, a_hi), (b_lo, b_hi)) = (x(a)?, x(b)?);
+///                 Some((policy.add(a_lo, b_lo)?, policy.add(a_hi, b_hi)?))
+///             }
+///             Math::Mul([a, b]) => match (x(a), x(b)) {
+///                 (Some((0, 0)), _) | (_, Some((0, 0))) => Some((0, 0)),
+///                 (Some((a_lo, a_hi)), Some((b_lo, b_hi))) => {
+///                     let ps = [
+///                         policy.mul(a_lo, b_lo)?,
+///                         policy.mul(a_lo, b_hi)?,
+///                         policy.mul(a_hi, b_lo)?,
+///                         policy.mul(a_hi, b_hi)?,
+///                     ];
+///                     Some((*ps.iter().min()?, *ps.iter().max()?))
+///                 }
+///                 _ => None,
+///             },
+///             Math::Symbol(_) => None,
+///         }
+///     }
 ///     fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge {
-///         merge_min(to, from)
+///         // intersect the two intervals
+///         let new = match (*to, from) {
+///             (Some(a), Some(b)) => Some((a.0.max(b.0), a.1.min(b.1))),
+///             (a, b) => a.or(b),
+///         };
+///         let did_merge = DidMerge(*to != new, from != new);
+///         *to = new;
+///         did_merge
 ///     }
-///     fn make(egraph: &EGraph, enode: &Math) -> Self::Data {
-///         let get_size = |i: Id| egraph[i].data;
-///         AstSize.cost(enode, get_size)
+/// }
+///
+/// // add the constant and union it with the matched eclass
+/// struct Materialize;
+/// impl Applier<Math, Interval> for Materialize {
+///     fn apply_one(
+///         &self,
+///         egraph: &mut EGraph<Math, Interval>,
+///         eclass: Id,
+///         _subst: &Subst,
+///         _searcher_ast: Option<&PatternAst<Math>>,
+///         _rule_name: Symbol,
+///     ) -> Vec<Id> {
+///         let (n, _) = egraph[eclass].data.unwrap();
+///         let num = egraph.add(Math::Num(n));
+///         if egraph.union(eclass, num) {
+///             vec![eclass]
+///         } else {
+///             vec![]
+///         }
 ///     }
 /// }
 ///
-/// let rules = &[
-///     rw!("commute-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
-///     rw!("commute-mul"; "(* ?a ?b)" => "(* ?b ?a)"),
-///     rw!("add-0"; "(+ ?a 0)" => "?a"),
-///     rw!("mul-0"; "(* ?a 0)" => "0"),
-///     rw!("mul-1"; "(* ?a 1)" => "?a"),
-///     // the rewrite macro parses the rhs as a single token tree, so
-///     // we wrap it in braces (parens work too).
-///     rw!("funky"; "(+ ?a (* ?b ?c))" => { Funky {
-///         a: "?a".parse().unwrap(),
-///         b: "?b".parse().unwrap(),
-///         c: "?c".parse().unwrap(),
-///         ast: "(+ (+ ?a 0) (* (+ ?b 0) (+ ?c 0)))".parse().unwrap(),
-///     }}),
-/// ];
+/// let singleton = FactSearcher::new(vec![], |egraph, id, data: &Option<(i64, i64)>| {
+///     let (lo, hi) = (*data)?;
+///     let already_const = egraph[id].iter().any(|n| matches!(n, Math::Num(_)));
+///     if lo == hi && !already_const {
+///         Some(Subst::default())
+///     } else {
+///         None
+///     }
+/// });
+/// let rules = vec![Rewrite::new("materialize-singleton", singleton, Materialize).unwrap()];
 ///
-/// #[derive(Debug, Clone, PartialEq, Eq)]
-/// struct Funky {
-///     a: Var,
-///     b: Var,
-///     c: Var,
-///     ast: PatternAst<Math>,
-/// }
+/// let expr: RecExpr<Math> = "(+ 3 (* x 0))".parse().unwrap();
+/// let runner = Runner::default().with_expr(&expr).run(&rules);
+/// let three = runner.egraph.lookup_expr(&"3".parse().unwrap()).unwrap();
+/// assert_eq!(runner.egraph.find(runner.roots[0]), three);
+/// ```
+pub struct FactSearcher<L: Language, N: Analysis<L>> {
+    vars: Vec<Var>,
+    #[allow(clippy::type_complexity)]
+    f: Box<dyn Fn(&EGraph<L, N>, Id, &N::Data) -> Option<Subst> + Send + Sync>,
+}
+
+impl<L: Language, N: Analysis<L>> FactSearcher<L, N> {
+    /// Create a new [`FactSearcher`].
+    ///
+    /// `vars` are the variables that `f` may bind in the [`Subst`]s it
+    /// returns; they are reported by [`Searcher::vars`] so that
+    /// [`Rewrite::new`] can check the applier against them.
+    pub fn new<F>(vars: Vec<Var>, f: F) -> Self
+    where
+        F: Fn(&EGraph<L, N>, Id, &N::Data) -> Option<Subst> + Send + Sync + 'static,
+    {
+        Self {
+            vars,
+            f: Box::new(f),
+        }
+    }
+}
+
+impl<L: Language, N: Analysis<L>> Searcher<L, N> for FactSearcher<L, N> {
+    fn search_eclass(&self, egraph: &EGraph<L, N>, eclass: Id) -> Option<SearchMatches<L>> {
+        let subst = (self.f)(egraph, eclass, &egraph[eclass].data)?;
+        Some(SearchMatches {
+            eclass,
+            substs: vec![subst],
+            ast: None,
+        })
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.vars.clone()
+    }
+}
+
+/// A [`Searcher`] that drops the matches of another wherever a forbidden
+/// [`Pattern`] also matches.
 ///
-/// impl Applier<Math, MinSize> for Funky {
+/// For each match of the inner searcher, the forbidden pattern is
+/// searched for in the matched eclass, or in the eclass of the
+/// [root](ExceptSearcher::with_root) variable, and the match is dropped
+/// if any match of the forbidden pattern binds the variables the two
+/// share to the same eclasses.
+/// Variables only in the forbidden pattern may be bound to anything, so
+/// `(not ?z)` forbids any `not` at all.
 ///
-///     fn apply_one(&self, egraph: &mut EGraph, matched_id: Id, subst: &Subst, searcher_pattern: Option<&PatternAst<Math>>, rule_name: Symbol) -> Vec<Id> {
-///         let a: Id = subst[self.a];
-///         // In a custom Applier, you can inspect the analysis data,
-///         // which is powerful combination!
-///         let size_of_a = egraph[a].data;
+/// Since the forbidden pattern is searched for with the inner
+/// searcher, a union that makes it match stops the rewrite from
+/// matching from the next search on.
+///
+/// An [`ExceptSearcher`] has no [pattern](Searcher::get_pattern_ast),
+/// even if its inner searcher does, since that pattern alone would
+/// match more: [`format_rules`] rejects its rules, and
+/// [`validate_rules`] and explanation checking skip them.
+///
+/// # Example
+/// ```
+/// use egg::{*, SymbolLang as S};
+/// // (select ?c ?t ?f) => ?t, unless ?c could be false
+/// let select = ExceptSearcher::new(
+///     "(select ?c ?t ?f)".parse::<Pattern<S>>().unwrap(),
+///     "false".parse().unwrap(),
+/// )
+/// .with_root("?c".parse().unwrap());
+/// let rules: Vec<Rewrite<S, ()>> = vec![rewrite!("select-true"; { select } => "?t")];
+///
+/// let runner = Runner::default()
+///     .with_expr(&"(select c a b)".parse().unwrap())
+///     .with_expr(&"(select false a b)".parse().unwrap())
+///     .run(&rules);
+/// let a = runner.egraph.lookup(S::leaf("a")).unwrap();
+/// assert_eq!(runner.egraph.find(runner.roots[0]), a);
+/// assert_ne!(runner.egraph.find(runner.roots[1]), a);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExceptSearcher<S, L> {
+    searcher: S,
+    forbidden: Pattern<L>,
+    root: Option<Var>,
+}
+
+impl<S, L: Language> ExceptSearcher<S, L> {
+    /// Match what `searcher` does, except where `forbidden` also matches
+    /// in the matched eclass.
+    pub fn new(searcher: S, forbidden: Pattern<L>) -> Self {
+        Self {
+            searcher,
+            forbidden,
+            root: None,
+        }
+    }
+
+    /// Search for the forbidden pattern in the eclass bound to `var`
+    /// instead of the matched eclass.
+    ///
+    /// Searching panics if the inner searcher doesn't bind `var`.
+    pub fn with_root(self, var: Var) -> Self {
+        Self {
+            root: Some(var),
+            ..self
+        }
+    }
+
+    /// Drop the substs of `matches` that the forbidden pattern matches.
+    fn filter<'a, N>(
+        &self,
+        egraph: &EGraph<L, N>,
+        shared: &[Var],
+        mut matches: SearchMatches<'a, L>,
+    ) -> Option<SearchMatches<'a, L>>
+    where
+        N: Analysis<L>,
+    {
+        let mut forbidden: HashMap<Id, Vec<Subst>> = HashMap::default();
+        let eclass = matches.eclass;
+        matches.substs.retain(|subst| {
+            let root = egraph.find(self.root.map_or(eclass, |v| subst[v]));
+            let found = forbidden.entry(root).or_insert_with(|| {
+                Searcher::search_eclass(&self.forbidden, egraph, root).map_or(vec![], |m| m.substs)
+            });
+            !found.iter().any(|f| {
+                shared
+                    .iter()
+                    .all(|&v| egraph.find(f[v]) == egraph.find(subst[v]))
+            })
+        });
+        Some(matches).filter(|m| !m.substs.is_empty())
+    }
+
+    fn shared_vars<N>(&self) -> Vec<Var>
+    where
+        N: Analysis<L>,
+        S: Searcher<L, N>,
+    {
+        let vars = self.searcher.vars();
+        let mut shared = self.forbidden.vars();
+        shared.retain(|v| vars.contains(v));
+        shared
+    }
+}
+
+impl<S, L, N> Searcher<L, N> for ExceptSearcher<S, L>
+where
+    S: Searcher<L, N>,
+    L: Language,
+    N: Analysis<L>,
+{
+    fn search_eclass(&self, egraph: &EGraph<L, N>, eclass: Id) -> Option<SearchMatches<L>> {
+        let matches = self.searcher.search_eclass(egraph, eclass)?;
+        self.filter(egraph, &self.shared_vars::<N>(), matches)
+    }
+
+    fn search(&self, egraph: &EGraph<L, N>) -> Vec<SearchMatches<L>> {
+        let shared = self.shared_vars::<N>();
+        self.searcher
+            .search(egraph)
+            .into_iter()
+            .filter_map(|matches| self.filter(egraph, &shared, matches))
+            .collect()
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.searcher.vars()
+    }
+}
+
+/// The righthand side of a [`Rewrite`].
+///
+/// An [`Applier`] is anything that can do something with a
+/// substitition ([`Subst`]). This allows you to implement rewrites
+/// that determine when and how to respond to a match using custom
+/// logic, including access to the [`Analysis`] data of an [`EClass`].
+///
+/// Notably, [`Pattern`] implements [`Applier`], which suffices in
+/// most cases.
+/// Additionally, `egg` provides [`ConditionalApplier`] to stack
+/// [`Condition`]s onto an [`Applier`], which in many cases can save
+/// you from having to implement your own applier.
+///
+/// Appliers run while the egraph may be dirty, so they must
+/// canonicalize ids with [`EGraph::find`] before comparing them, and
+/// any searches they run must go through [`EGraph::with_clean_view`].
+///
+/// # Example
+/// ```
+/// use egg::{rewrite as rw, *};
+/// use std::sync::Arc;
+///
+/// define_language! {
+///     enum Math {
+///         Num(i32),
+///         "+" = Add([Id; 2]),
+///         "*" = Mul([Id; 2]),
+///         Symbol(Symbol),
+///     }
+/// }
+///
+/// type EGraph = egg::EGraph<Math, MinSize>;
+///
+/// // Our metadata in this case will be size of the smallest
+/// // represented expression in the eclass.
+/// #[derive(Default)]
+/// struct MinSize;
+/// impl Analysis<Math> for MinSize {
+///     type Data = usize;
+///     fn merge(&mut self, to: &mut Self::Data, from: Self::Data) -> DidMerge {
+///         merge_min(to, from)
+///     }
+///     fn make(egraph: &EGraph, enode: &Math) -> Self::Data {
+///         let get_size = |i: Id| egraph[i].data;
+///         AstSize.cost(enode, get_size)
+///     }
+/// }
+///
+/// let rules = &[
+///     rw!("commute-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+///     rw!("commute-mul"; "(* ?a ?b)" => "(* ?b ?a)"),
+///     rw!("add-0"; "(+ ?a 0)" => "?a"),
+///     rw!("mul-0"; "(* ?a 0)" => "0"),
+///     rw!("mul-1"; "(* ?a 1)" => "?a"),
+///     // the rewrite macro parses the rhs as a single token tree, so
+///     // we wrap it in braces (parens work too).
+///     rw!("funky"; "(+ ?a (* ?b ?c))" => { Funky {
+///         a: "?a".parse().unwrap(),
+///         b: "?b".parse().unwrap(),
+///         c: "?c".parse().unwrap(),
+///         ast: "(+ (+ ?a 0) (* (+ ?b 0) (+ ?c 0)))".parse().unwrap(),
+///     }}),
+/// ];
+///
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// struct Funky {
+///     a: Var,
+///     b: Var,
+///     c: Var,
+///     ast: PatternAst<Math>,
+/// }
+///
+/// impl Applier<Math, MinSize> for Funky {
+///
+///     fn apply_one(&self, egraph: &mut EGraph, matched_id: Id, subst: &Subst, searcher_pattern: Option<&PatternAst<Math>>, rule_name: Symbol) -> Vec<Id> {
+///         let a: Id = subst[self.a];
+///         // In a custom Applier, you can inspect the analysis data,
+///         // which is powerful combination!
+///         let size_of_a = egraph[a].data;
 ///         if size_of_a > 50 {
 ///             println!("Too big! Not doing anything");
 ///             vec![]
@@ -288,6 +1240,7 @@ where
                 ast = None;
             }
             for subst in &mat.substs {
+                egraph.set_provenance_match(rule_name, subst);
                 let ids = self.apply_one(egraph, mat.eclass, subst, ast, rule_name);
                 added.extend(ids)
             }
@@ -295,6 +1248,24 @@ where
         added
     }
 
+    /// Apply many substitutions stored in `arena`, like
+    /// [`apply_matches`](Applier::apply_matches).
+    ///
+    /// The default implementation copies the matches out of the arena
+    /// and calls [`apply_matches`](Applier::apply_matches), so appliers
+    /// only need to implement this to avoid the copy.
+    fn apply_arena_matches(
+        &self,
+        egraph: &mut EGraph<L, N>,
+        arena: &MatchArena,
+        matches: &[ArenaMatches<L>],
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let matches: Vec<SearchMatches<L>> =
+            matches.iter().map(|m| m.to_search_matches(arena)).collect();
+        self.apply_matches(egraph, &matches, rule_name)
+    }
+
     /// For patterns, get the ast directly as a reference.
     fn get_pattern_ast(&self) -> Option<&PatternAst<L>> {
         None
@@ -311,6 +1282,12 @@ where
     /// When explanations mode is enabled, a [`PatternAst`] for
     /// the searcher is provided.
     ///
+    /// Adds and unions take effect at once, so a later match of the
+    /// same iteration sees them, but the egraph isn't rebuilt until
+    /// every match has been applied: `eclass` and the ids in `subst` may
+    /// no longer be canonical, and analysis data has been merged but not
+    /// yet [modified](Analysis::modify) for unions made since the search.
+    ///
     /// [`apply_matches`]: Applier::apply_matches()
     fn apply_one(
         &self,
@@ -330,6 +1307,166 @@ where
     fn vars(&self) -> Vec<Var> {
         vec![]
     }
+
+    /// Returns the conditions checked before applying, as pairs of
+    /// patterns that must be equivalent, or `None` if some condition
+    /// can't be described that way.
+    ///
+    /// [`ConditionalApplier`] returns its [`ConditionEqual`]s here.
+    /// By default this returns an empty list.
+    fn get_condition_asts(&self) -> Option<Vec<(&PatternAst<L>, &PatternAst<L>)>> {
+        Some(vec![])
+    }
+
+    /// Returns the number of matches this applier chose not to apply
+    /// during the last call to [`apply_matches`], and resets that count.
+    ///
+    /// The [`Runner`] records this in [`Iteration::skipped`].
+    /// By default this returns 0; see [`BudgetedApplier`] for an
+    /// applier that skips matches.
+    ///
+    /// [`apply_matches`]: Applier::apply_matches()
+    fn take_skipped(&self) -> usize {
+        0
+    }
+}
+
+/// An [`Applier`] that limits how many new enodes it adds per
+/// call to [`apply_matches`], which the [`Runner`] makes once per
+/// rule per iteration.
+///
+/// Before applying each match, a [`BudgetedApplier`] estimates how many
+/// enodes the inner applier's pattern would add by looking up its
+/// subterms in the egraph.
+/// Once a match would exceed the remaining budget, that match and all
+/// later matches in the same call are skipped, and the number of
+/// skipped matches is reported through [`Applier::take_skipped`].
+/// The budget is charged with the number of enodes each application
+/// actually added.
+///
+/// If the inner applier has no pattern ([`Applier::get_pattern_ast`]
+/// returns `None`), the estimate is 0, so matches are only skipped after
+/// the budget has already been spent.
+///
+/// ```
+/// use egg::*;
+/// let mut egraph = EGraph::<SymbolLang, ()>::default();
+/// egraph.add_expr(&"(+ (f a) (f b))".parse().unwrap());
+/// egraph.rebuild();
+///
+/// let searcher: Pattern<SymbolLang> = "(f ?x)".parse().unwrap();
+/// let applier: Pattern<SymbolLang> = "(g (h ?x))".parse().unwrap();
+/// // each match adds 2 nodes, so only one of them fits
+/// let budgeted = BudgetedApplier::new(applier, 3);
+/// let rule = Rewrite::new("f-unroll", searcher, budgeted).unwrap();
+///
+/// let matches = rule.search(&egraph);
+/// rule.apply(&mut egraph, &matches);
+/// assert_eq!(rule.applier.take_skipped(), 1);
+/// ```
+///
+/// [`apply_matches`]: Applier::apply_matches()
+#[derive(Debug)]
+pub struct BudgetedApplier<A> {
+    applier: A,
+    max_new_nodes: usize,
+    skipped: AtomicUsize,
+}
+
+impl<A> BudgetedApplier<A> {
+    /// Wrap `applier` so that it adds at most roughly
+    /// `max_new_nodes_per_iteration` enodes per iteration.
+    pub fn new(applier: A, max_new_nodes_per_iteration: usize) -> Self {
+        Self {
+            applier,
+            max_new_nodes: max_new_nodes_per_iteration,
+            skipped: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<L, N, A> Applier<L, N> for BudgetedApplier<A>
+where
+    L: Language,
+    N: Analysis<L>,
+    A: Applier<L, N>,
+{
+    fn apply_matches(
+        &self,
+        egraph: &mut EGraph<L, N>,
+        matches: &[SearchMatches<L>],
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let mut remaining = self.max_new_nodes;
+        let mut skipped = 0;
+        let mut added = vec![];
+        for mat in matches {
+            let ast;
+            if egraph.are_explanations_enabled() {
+                ast = mat.ast.as_ref().map(|cow| cow.as_ref());
+            } else {
+                ast = None;
+            }
+            for subst in &mat.substs {
+                if skipped > 0 {
+                    skipped += 1;
+                    continue;
+                }
+
+                let estimate = match self.applier.get_pattern_ast() {
+                    Some(pat) => count_new_nodes(pat.as_ref(), egraph, subst),
+                    None => 0,
+                };
+                if estimate > remaining {
+                    skipped += 1;
+                    continue;
+                }
+
+                egraph.set_provenance_match(rule_name, subst);
+                let size_before = egraph.total_size();
+                let ids = self
+                    .applier
+                    .apply_one(egraph, mat.eclass, subst, ast, rule_name);
+                added.extend(ids);
+                let n_added = egraph.total_size().saturating_sub(size_before);
+                remaining = remaining.saturating_sub(n_added);
+            }
+        }
+
+        if skipped > 0 {
+            log::debug!("Rule {} skipped {} matches over budget", rule_name, skipped);
+        }
+        self.skipped.fetch_add(skipped, Ordering::Relaxed);
+        added
+    }
+
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<L, N>,
+        eclass: Id,
+        subst: &Subst,
+        searcher_ast: Option<&PatternAst<L>>,
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        self.applier
+            .apply_one(egraph, eclass, subst, searcher_ast, rule_name)
+    }
+
+    fn get_pattern_ast(&self) -> Option<&PatternAst<L>> {
+        self.applier.get_pattern_ast()
+    }
+
+    fn get_condition_asts(&self) -> Option<Vec<(&PatternAst<L>, &PatternAst<L>)>> {
+        self.applier.get_condition_asts()
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.applier.vars()
+    }
+
+    fn take_skipped(&self) -> usize {
+        self.skipped.swap(0, Ordering::Relaxed)
+    }
 }
 
 /// An [`Applier`] that checks a [`Condition`] before applying.
@@ -337,11 +1474,19 @@ where
 /// A [`ConditionalApplier`] simply calls [`check`] on the
 /// [`Condition`] before calling [`apply_one`] on the inner
 /// [`Applier`].
+/// When the matched enode is known (see [`SearchMatches::matched_node`]),
+/// it calls [`check_with_node`] instead; to find it, the egraph is
+/// rebuilt before checking each eclass's matches if applying the
+/// previous ones left it dirty.
+/// When applying many matches, it checks all the matches of each eclass
+/// at once with [`check_bulk`], before applying any of them.
 ///
 /// See the [`rewrite!`] macro documentation for an example.
 ///
 /// [`apply_one`]: Applier::apply_one()
 /// [`check`]: Condition::check()
+/// [`check_with_node`]: Condition::check_with_node()
+/// [`check_bulk`]: Condition::check_bulk()
 #[derive(Clone, Debug)]
 pub struct ConditionalApplier<C, A> {
     /// The [`Condition`] to [`check`] before calling [`apply_one`] on
@@ -362,6 +1507,43 @@ where
     A: Applier<L, N>,
     N: Analysis<L>,
 {
+    fn apply_matches(
+        &self,
+        egraph: &mut EGraph<L, N>,
+        matches: &[SearchMatches<L>],
+        rule_name: Symbol,
+    ) -> Vec<Id> {
+        let mut added = vec![];
+        for mat in matches {
+            let ast;
+            if egraph.are_explanations_enabled() {
+                ast = mat.ast.as_ref().map(|cow| cow.as_ref());
+            } else {
+                ast = None;
+            }
+            // applying earlier matches may have left the egraph dirty,
+            // and the matched enodes can only be looked up in a clean one
+            if mat.ast.is_some() && !egraph.clean {
+                egraph.rebuild();
+            }
+            // in case check_bulk doesn't report its matches for provenance,
+            // what it adds is attributed to the first one
+            if let Some(first) = mat.substs.first() {
+                egraph.set_provenance_match(rule_name, first);
+            }
+            let passed = self.condition.check_bulk(egraph, mat);
+            debug_assert_eq!(passed.len(), mat.substs.len());
+            for (subst, _) in mat.substs.iter().zip(passed).filter(|(_, p)| *p) {
+                egraph.set_provenance_match(rule_name, subst);
+                let ids = self
+                    .applier
+                    .apply_one(egraph, mat.eclass, subst, ast, rule_name);
+                added.extend(ids)
+            }
+        }
+        added
+    }
+
     fn apply_one(
         &self,
         egraph: &mut EGraph<L, N>,
@@ -370,7 +1552,7 @@ where
         searcher_ast: Option<&PatternAst<L>>,
         rule_name: Symbol,
     ) -> Vec<Id> {
-        if self.condition.check(egraph, eclass, subst) {
+        if self.condition.check_mut(egraph, eclass, subst, None) {
             self.applier
                 .apply_one(egraph, eclass, subst, searcher_ast, rule_name)
         } else {
@@ -378,6 +1560,16 @@ where
         }
     }
 
+    fn get_pattern_ast(&self) -> Option<&PatternAst<L>> {
+        self.applier.get_pattern_ast()
+    }
+
+    fn get_condition_asts(&self) -> Option<Vec<(&PatternAst<L>, &PatternAst<L>)>> {
+        let mut asts = self.condition.get_equality_asts()?;
+        asts.extend(self.applier.get_condition_asts()?);
+        Some(asts)
+    }
+
     fn vars(&self) -> Vec<Var> {
         let mut vars = self.applier.vars();
         vars.extend(self.condition.vars());
@@ -389,10 +1581,20 @@ where
 ///
 /// See the [`ConditionalApplier`] docs.
 ///
+/// A condition reads the egraph through an [`EGraphView`], so it can be
+/// checked against an [`EGraph`] or another view, like a
+/// [`RecordingView`] to find out which eclasses its result depends on.
 /// Notably, any function ([`Fn`]) that doesn't mutate other state
 /// and matches the signature of [`check`] implements [`Condition`].
+/// A condition that needs to change the egraph, like
+/// [`ConditionEqual`] adding its terms, also implements [`check_mut`].
+///
+/// Like [`Applier`]s, conditions are checked while the egraph may be
+/// dirty; a condition that runs nested searches should implement
+/// [`check_mut`] and search inside [`EGraph::with_clean_view`].
 ///
 /// [`check`]: Condition::check()
+/// [`check_mut`]: Condition::check_mut()
 /// [`Fn`]: std::ops::Fn
 pub trait Condition<L, N>
 where
@@ -404,7 +1606,82 @@ where
     /// `eclass` is the eclass [`Id`] where the match (`subst`) occured.
     /// If this is true, then the [`ConditionalApplier`] will fire.
     ///
-    fn check(&self, egraph: &mut EGraph<L, N>, eclass: Id, subst: &Subst) -> bool;
+    fn check(&self, egraph: &dyn EGraphView<L, N>, eclass: Id, subst: &Subst) -> bool;
+
+    /// Check a condition, also given the enode the match is rooted at.
+    ///
+    /// A [`ConditionalApplier`] calls this instead of [`check`] when
+    /// the search results say which enode was matched, as [`Pattern`]s'
+    /// do, so a condition can look at the enode's operator or payload
+    /// rather than just its eclass.
+    /// By default this ignores `matched` and calls [`check`].
+    ///
+    /// [`check`]: Condition::check()
+    fn check_with_node(
+        &self,
+        egraph: &dyn EGraphView<L, N>,
+        eclass: Id,
+        subst: &Subst,
+        matched: &L,
+    ) -> bool {
+        let _ = matched;
+        self.check(egraph, eclass, subst)
+    }
+
+    /// Check a condition with the egraph itself, which it may change,
+    /// given the matched enode if it's known.
+    ///
+    /// A [`ConditionalApplier`] calls this, through
+    /// [`check_bulk`](Condition::check_bulk), for each match.
+    /// By default this calls [`check_with_node`] if `matched` is known,
+    /// and [`check`] otherwise, with the egraph as a view.
+    ///
+    /// [`check`]: Condition::check()
+    /// [`check_with_node`]: Condition::check_with_node()
+    fn check_mut(
+        &self,
+        egraph: &mut EGraph<L, N>,
+        eclass: Id,
+        subst: &Subst,
+        matched: Option<&L>,
+    ) -> bool {
+        match matched {
+            Some(node) => self.check_with_node(&*egraph, eclass, subst, node),
+            None => self.check(&*egraph, eclass, subst),
+        }
+    }
+
+    /// Check every subst of `matches`, returning whether each one
+    /// passes, in order.
+    ///
+    /// A [`ConditionalApplier`] calls this with the matches of each
+    /// eclass before applying those that pass.
+    /// By default this calls [`check_mut`] on each subst, with its
+    /// matched enode if it's known, recording each subst as the current
+    /// match for [provenance](EGraph::with_provenance) first.
+    /// A condition may check many substs more cheaply at once, like
+    /// [`AnalysisCondition`] does.
+    ///
+    /// [`check_mut`]: Condition::check_mut()
+    fn check_bulk(&self, egraph: &mut EGraph<L, N>, matches: &SearchMatches<L>) -> Vec<bool> {
+        let eclass = matches.eclass;
+        matches
+            .substs
+            .iter()
+            .map(|subst| {
+                egraph.set_provenance_subst(subst);
+                let node = matches.matched_node(egraph, subst);
+                self.check_mut(egraph, eclass, subst, node.as_ref())
+            })
+            .collect()
+    }
+
+    /// If this condition checks that pairs of patterns are equivalent,
+    /// like [`ConditionEqual`], returns those pairs.
+    /// By default this returns `None`.
+    fn get_equality_asts(&self) -> Option<Vec<(&PatternAst<L>, &PatternAst<L>)>> {
+        None
+    }
 
     /// Returns a list of variables that this Condition assumes are bound.
     ///
@@ -421,19 +1698,30 @@ impl<L, F, N> Condition<L, N> for F
 where
     L: Language,
     N: Analysis<L>,
-    F: Fn(&mut EGraph<L, N>, Id, &Subst) -> bool,
+    F: Fn(&dyn EGraphView<L, N>, Id, &Subst) -> bool,
 {
-    fn check(&self, egraph: &mut EGraph<L, N>, eclass: Id, subst: &Subst) -> bool {
+    fn check(&self, egraph: &dyn EGraphView<L, N>, eclass: Id, subst: &Subst) -> bool {
         self(egraph, eclass, subst)
     }
 }
 
 /// A [`Condition`] that checks if two terms are equivalent.
 ///
-/// This condition adds its two [`Pattern`] to the egraph and passes
-/// if and only if they are equivalent (in the same eclass).
+/// In a [`ConditionalApplier`], through
+/// [`check_mut`](Condition::check_mut), this condition adds its two
+/// [`Pattern`] to the egraph and passes if and only if they are
+/// equivalent (in the same eclass).
+/// The instantiated terms stay in the egraph whether or not the check
+/// passes.
+/// Their eclasses are compared with [`EGraph::find`], so unions made
+/// earlier in the same apply phase count, though anything that only a
+/// rebuild would find (like congruence) doesn't until the next one.
 ///
-#[derive(Debug)]
+/// Checked against a view with [`check`](Condition::check), which
+/// can't add anything, it only passes if both terms are already in
+/// the same eclass.
+///
+#[derive(Debug, Clone)]
 pub struct ConditionEqual<L> {
     p1: Pattern<L>,
     p2: Pattern<L>,
@@ -463,12 +1751,21 @@ where
     L: Language,
     N: Analysis<L>,
 {
-    fn check(&self, egraph: &mut EGraph<L, N>, _eclass: Id, subst: &Subst) -> bool {
-        let mut id_buf_1 = vec![0.into(); self.p1.ast.as_ref().len()];
+    fn check(&self, egraph: &dyn EGraphView<L, N>, _eclass: Id, subst: &Subst) -> bool {
+        let a1 = lookup_in_view(&self.p1.ast, egraph, subst);
+        a1.is_some() && a1 == lookup_in_view(&self.p2.ast, egraph, subst)
+    }
+
+    fn check_mut(&self, egraph: &mut EGraph<L, N>, _: Id, subst: &Subst, _: Option<&L>) -> bool {
+        let mut id_buf_1 = vec![0.into(); self.p1.ast.as_ref().len()];
         let mut id_buf_2 = vec![0.into(); self.p2.ast.as_ref().len()];
         let a1 = apply_pat(&mut id_buf_1, self.p1.ast.as_ref(), egraph, subst);
         let a2 = apply_pat(&mut id_buf_2, self.p2.ast.as_ref(), egraph, subst);
-        a1 == a2
+        egraph.find(a1) == egraph.find(a2)
+    }
+
+    fn get_equality_asts(&self) -> Option<Vec<(&PatternAst<L>, &PatternAst<L>)>> {
+        Some(vec![(&self.p1.ast, &self.p2.ast)])
     }
 
     fn vars(&self) -> Vec<Var> {
@@ -478,6 +1775,193 @@ where
     }
 }
 
+/// The eclass of the instantiation of `pat`, if all of it is already in
+/// the egraph `view` shows.
+fn lookup_in_view<L, N>(
+    pat: &PatternAst<L>,
+    view: &dyn EGraphView<L, N>,
+    subst: &Subst,
+) -> Option<Id>
+where
+    L: Language,
+    N: Analysis<L>,
+{
+    let mut ids: Vec<Id> = Vec::with_capacity(pat.as_ref().len());
+    for node in pat.as_ref() {
+        let id = match node {
+            ENodeOrVar::Var(v) => view.find(subst[*v]),
+            ENodeOrVar::ENode(e) => view.lookup(e.clone().map_children(|c| ids[usize::from(c)]))?,
+        };
+        ids.push(id);
+    }
+    ids.last().copied()
+}
+
+/// A [`Condition`] that checks the analysis data of the eclass a
+/// variable is bound to.
+///
+/// Checking many matches at once with
+/// [`check_bulk`](Condition::check_bulk), it calls the predicate once
+/// for each distinct eclass rather than once per match.
+///
+/// # Example
+/// ```
+/// use egg::{*, SymbolLang as S};
+/// // ?b is bound to a leaf
+/// let leaf = AnalysisCondition::new("?b".parse().unwrap(), |size: &usize| *size == 1);
+///
+/// #[derive(Default)]
+/// struct Size;
+/// impl Analysis<S> for Size {
+///     type Data = usize;
+///     fn make(egraph: &EGraph<S, Self>, enode: &S) -> usize {
+///         enode.fold(1, |n, child| n + egraph[child].data)
+///     }
+///     fn merge(&mut self, a: &mut usize, b: usize) -> DidMerge {
+///         merge_min(a, b)
+///     }
+/// }
+///
+/// let rules: Vec<Rewrite<S, Size>> = vec![
+///     rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)" if leaf),
+/// ];
+/// let runner = Runner::default()
+///     .with_expr(&"(+ (f x) y)".parse().unwrap())
+///     .with_expr(&"(+ y (f x))".parse().unwrap())
+///     .run(&rules);
+/// assert_eq!(runner.egraph.find(runner.roots[0]), runner.egraph.find(runner.roots[1]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AnalysisCondition<F> {
+    var: Var,
+    predicate: F,
+}
+
+impl<F> AnalysisCondition<F> {
+    /// Create a condition that passes if `predicate` holds for the data
+    /// of the eclass bound to `var`.
+    pub fn new(var: Var, predicate: F) -> Self {
+        Self { var, predicate }
+    }
+}
+
+impl<L, N, F> Condition<L, N> for AnalysisCondition<F>
+where
+    L: Language,
+    N: Analysis<L>,
+    F: Fn(&N::Data) -> bool,
+{
+    fn check(&self, egraph: &dyn EGraphView<L, N>, _eclass: Id, subst: &Subst) -> bool {
+        (self.predicate)(egraph.data(subst[self.var]))
+    }
+
+    fn check_bulk(&self, egraph: &mut EGraph<L, N>, matches: &SearchMatches<L>) -> Vec<bool> {
+        let mut passed: HashMap<Id, bool> = HashMap::default();
+        matches
+            .substs
+            .iter()
+            .map(|subst| {
+                let id = egraph.find(subst[self.var]);
+                *passed
+                    .entry(id)
+                    .or_insert_with(|| (self.predicate)(&egraph[id].data))
+            })
+            .collect()
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        vec![self.var]
+    }
+}
+
+/// An error found by [`validate_rules`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RuleValidationError {
+    /// The rule at this index has an empty name.
+    #[error("rule at index {index} has an empty name")]
+    EmptyName {
+        /// The index of the offending rule.
+        index: usize,
+    },
+    /// Several rules share the same name.
+    #[error("rule name '{name}' is used by the rules at indices {indices:?}")]
+    DuplicateName {
+        /// The shared name.
+        name: Symbol,
+        /// The indices of all rules with this name.
+        indices: Vec<usize>,
+    },
+    /// The rule's lefthand side and righthand side are the same
+    /// pattern, so applying it can never do anything.
+    #[error("rule '{name}' at index {index} has identical lefthand and righthand sides")]
+    NoOp {
+        /// The name of the offending rule.
+        name: Symbol,
+        /// The index of the offending rule.
+        index: usize,
+    },
+}
+
+/// Check a set of rules for common mistakes.
+///
+/// This looks for the following problems:
+/// - a rule with an empty name,
+/// - a rule whose searcher and applier are the same [`Pattern`],
+/// - several rules with the same name, which would make their
+///   [`Report`] entries and scheduler state collide.
+///
+/// The rules are checked in order, each for an empty name and then for
+/// being a no-op, and the first of those found is reported.
+/// Only if there is none is a duplicate name reported, the first one
+/// in the order the names are first used.
+///
+/// [`Runner::run`] calls this, in debug and release builds alike, and
+/// stops with [`StopReason::Other`] if it fails.
+///
+/// ```
+/// use egg::{rewrite as rw, *};
+/// let rules: Vec<Rewrite<SymbolLang, ()>> = vec![
+///     rw!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+///     rw!("comm"; "(* ?a ?b)" => "(* ?b ?a)"),
+/// ];
+/// assert_eq!(
+///     validate_rules(&rules),
+///     Err(RuleValidationError::DuplicateName {
+///         name: "comm".into(),
+///         indices: vec![0, 1],
+///     })
+/// );
+/// ```
+pub fn validate_rules<'a, L, N, R>(rules: R) -> Result<(), RuleValidationError>
+where
+    L: Language + 'a,
+    N: Analysis<L> + 'a,
+    R: IntoIterator<Item = &'a Rewrite<L, N>>,
+{
+    let mut indices_by_name: IndexMap<Symbol, Vec<usize>> = IndexMap::default();
+    for (index, rw) in rules.into_iter().enumerate() {
+        if rw.name.as_str().is_empty() {
+            return Err(RuleValidationError::EmptyName { index });
+        }
+        if let (Some(lhs), Some(rhs)) =
+            (rw.searcher.get_pattern_ast(), rw.applier.get_pattern_ast())
+        {
+            if lhs == rhs {
+                let name = rw.name;
+                return Err(RuleValidationError::NoOp { name, index });
+            }
+        }
+        indices_by_name.entry(rw.name).or_default().push(index);
+    }
+
+    for (name, indices) in indices_by_name {
+        if indices.len() > 1 {
+            return Err(RuleValidationError::DuplicateName { name, indices });
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -486,6 +1970,479 @@ mod tests {
 
     type EGraph = crate::EGraph<S, ()>;
 
+    /// The value of each eclass that is a known constant.
+    #[derive(Default)]
+    struct Constants;
+
+    impl Analysis<S> for Constants {
+        type Data = Option<i64>;
+        fn make(egraph: &crate::EGraph<S, Self>, enode: &S) -> Self::Data {
+            let c = |i: usize| egraph[enode.children[i]].data;
+            let policy = OverflowPolicy::RefuseToFold;
+            match (enode.op.as_str(), enode.len()) {
+                ("+", 2) => policy.add(c(0)?, c(1)?),
+                ("*", 2) => policy.mul(c(0)?, c(1)?),
+                (op, 0) => op.parse().ok(),
+                _ => None,
+            }
+        }
+        fn merge(&mut self, a: &mut Self::Data, b: Self::Data) -> DidMerge {
+            merge_max(a, b)
+        }
+    }
+
+    #[test]
+    fn validate_rules_errors() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rewrite!("a"; "(f ?x)" => "(g ?x)"),
+            rewrite!("b"; "(g ?x)" => "(f ?x)"),
+            rewrite!("a"; "(h ?x)" => "?x"),
+        ];
+        assert_eq!(
+            validate_rules(&rules),
+            Err(RuleValidationError::DuplicateName {
+                name: "a".into(),
+                indices: vec![0, 2],
+            })
+        );
+        assert_eq!(validate_rules(&rules[..2]), Ok(()));
+
+        let no_op: Rewrite<S, ()> = rewrite!("no-op"; "(f ?x)" => "(f ?x)");
+        assert_eq!(
+            validate_rules(&[no_op]),
+            Err(RuleValidationError::NoOp {
+                name: "no-op".into(),
+                index: 0,
+            })
+        );
+
+        let empty: Rewrite<S, ()> = rewrite!(""; "(f ?x)" => "(g ?x)");
+        assert_eq!(
+            validate_rules(&[empty]),
+            Err(RuleValidationError::EmptyName { index: 0 })
+        );
+
+        let runner = Runner::<S, ()>::default().run(&rules);
+        assert!(runner.iterations.is_empty());
+        assert!(matches!(runner.stop_reason, Some(StopReason::Other(_))));
+
+        assert_eq!(
+            format!("{:?}", rules[0]),
+            r#"Rewrite { name: "a", searcher: (f ?x), applier: (g ?x) }"#
+        );
+    }
+
+    #[test]
+    fn condition_sees_matched_node() {
+        /// A language where an operator can be flagged as approximate,
+        /// written with a trailing `~`. The flag doesn't affect matching.
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        struct Flagged {
+            op: Symbol,
+            approx: bool,
+            children: Vec<Id>,
+        }
+
+        impl Language for Flagged {
+            fn matches(&self, other: &Self) -> bool {
+                self.op == other.op && self.children.len() == other.children.len()
+            }
+            fn children(&self) -> &[Id] {
+                &self.children
+            }
+            fn children_mut(&mut self) -> &mut [Id] {
+                &mut self.children
+            }
+        }
+
+        impl FromOp for Flagged {
+            type Error = std::convert::Infallible;
+            fn from_op(op: &str, children: Vec<Id>) -> Result<Self, Self::Error> {
+                let approx = op.ends_with('~');
+                let op = op.trim_end_matches('~').into();
+                Ok(Flagged {
+                    op,
+                    approx,
+                    children,
+                })
+            }
+        }
+
+        struct NotApprox;
+        impl Condition<Flagged, ()> for NotApprox {
+            fn check(&self, _: &dyn EGraphView<Flagged, ()>, _: Id, _: &Subst) -> bool {
+                true
+            }
+            fn check_with_node(
+                &self,
+                _: &dyn EGraphView<Flagged, ()>,
+                _: Id,
+                _: &Subst,
+                matched: &Flagged,
+            ) -> bool {
+                !matched.approx
+            }
+        }
+
+        let rules: Vec<Rewrite<Flagged, ()>> = vec![
+            rewrite!("assoc"; "(+ (+ ?a ?b) ?c)" => "(+ ?a (+ ?b ?c))" if NotApprox),
+            rewrite!("to-times"; "(+ ?a ?a)" => "(* 2 ?a)" if NotApprox),
+        ];
+        let exact = "(+ (+ x y) z)".parse().unwrap();
+        let approx = "(+~ (+ u v) w)".parse().unwrap();
+        let double_exact = "(+ q q)".parse().unwrap();
+        let double_approx = "(+~ r r)".parse().unwrap();
+        let runner = Runner::default()
+            .with_expr(&exact)
+            .with_expr(&approx)
+            .with_expr(&double_exact)
+            .with_expr(&double_approx)
+            .run(&rules);
+        let egraph = &runner.egraph;
+
+        let pairs = [
+            ("(+ (+ x y) z)", "(+ x (+ y z))", true),
+            ("(+~ (+ u v) w)", "(+ u (+ v w))", false),
+            ("(+ q q)", "(* 2 q)", true),
+            ("(+~ r r)", "(* 2 r)", false),
+        ];
+        for (a, b, equiv) in &pairs {
+            let a = egraph.lookup_expr(&a.parse().unwrap()).unwrap();
+            let found = egraph.lookup_expr(&b.parse().unwrap());
+            assert_eq!(found == Some(a), *equiv, "{:?}", b);
+        }
+    }
+
+    #[test]
+    fn conditions_see_the_matched_node_in_a_dirty_egraph() {
+        struct OnlyWithNode;
+        impl Condition<S, ()> for OnlyWithNode {
+            fn check(&self, _: &dyn EGraphView<S, ()>, _: Id, _: &Subst) -> bool {
+                false
+            }
+            fn check_with_node(&self, _: &dyn EGraphView<S, ()>, _: Id, _: &Subst, _: &S) -> bool {
+                true
+            }
+        }
+
+        let mut egraph = EGraph::default();
+        let fga = egraph.add_expr(&"(f (g a))".parse().unwrap());
+        egraph.add_expr(&"(k c)".parse().unwrap());
+        egraph.add_expr(&"(m c)".parse().unwrap());
+        egraph.rebuild();
+        let rule: Rewrite<S, ()> = Rewrite::new(
+            "f-to-h",
+            "(f (g ?x))".parse::<Pattern<S>>().unwrap(),
+            ConditionalApplier {
+                condition: OnlyWithNode,
+                applier: "(h ?x)".parse::<Pattern<S>>().unwrap(),
+            },
+        )
+        .unwrap();
+        let matches = rule.search(&egraph);
+
+        // c has more parents, so a is merged into it, and (g a) can't be
+        // looked up by its canonical children until a rebuild
+        let a = egraph.lookup(S::leaf("a")).unwrap();
+        let c = egraph.lookup(S::leaf("c")).unwrap();
+        egraph.union(a, c);
+        rule.apply(&mut egraph, &matches);
+        egraph.rebuild();
+        let h = egraph.lookup_expr(&"(h a)".parse().unwrap());
+        assert_eq!(h, Some(egraph.find(fga)));
+    }
+
+    #[test]
+    fn search_groups_find_the_same_matches() {
+        let mut rules: Vec<Rewrite<S, ()>> = vec![
+            rewrite!("mul-1"; "(* ?a 1)" => "?a"),
+            rewrite!("mul-add"; "(* ?a (+ ?b ?c))" => "(+ (* ?a ?b) (* ?a ?c))"),
+            rewrite!("mul-same"; "(* ?a ?a)" => "(sq ?a)"),
+            rewrite!("comm-mul"; "(* ?a ?b)" => "(* ?b ?a)"),
+            // alone in its group, since it has a match limit
+            rewrite!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)").with_max_matches_per_class(1),
+        ];
+        let start = "(+ (* x (+ y 1)) (* (* z z) 1))".parse().unwrap();
+        let run = |rules: &[Rewrite<S, ()>]| {
+            Runner::default()
+                .with_scheduler(SimpleScheduler)
+                .with_iter_limit(3)
+                .with_expr(&start)
+                .run(rules)
+        };
+        let alone = run(&rules);
+        rules = rules
+            .into_iter()
+            .map(|rw| rw.with_search_group("mul"))
+            .collect();
+        let grouped = run(&rules);
+        assert_eq!(alone.iterations.len(), grouped.iterations.len());
+        for (a, g) in alone.iterations.iter().zip(&grouped.iterations) {
+            assert_eq!(a.matched, g.matched);
+        }
+        assert_eq!(alone.egraph.total_size(), grouped.egraph.total_size());
+
+        // each rule's matches, as sorted (eclass, subst) pairs
+        let egraph = &alone.egraph;
+        let sorted = |arena: &MatchArena, ms: &[ArenaMatches<S>]| {
+            let mut found: Vec<(Id, Subst)> = ms
+                .iter()
+                .flat_map(|m| {
+                    arena
+                        .to_substs(&m.substs)
+                        .into_iter()
+                        .map(move |s| (m.eclass, s))
+                })
+                .collect();
+            found.sort();
+            found
+        };
+        let group: Vec<&Rewrite<S, ()>> = rules.iter().collect();
+        let mut arena = MatchArena::new();
+        let together = Rewrite::search_group_into(&group, egraph, &mut arena);
+        let mut alone_arena = MatchArena::new();
+        for (rw, ms) in rules.iter().zip(&together) {
+            let expected = rw.search_into(egraph, &mut alone_arena);
+            assert!(!ms.is_empty());
+            assert_eq!(
+                sorted(&arena, ms),
+                sorted(&alone_arena, &expected),
+                "{}",
+                rw.name
+            );
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn runner_parallel_search_matches_serial() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rewrite!("assoc"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+        ];
+        let run = |parallel| {
+            Runner::default()
+                .with_scheduler(SimpleScheduler)
+                .with_parallel_search(parallel)
+                .with_expr(&"(+ a (+ b (+ c d)))".parse().unwrap())
+                .run(&rules)
+        };
+        let (serial, parallel) = (run(false), run(true));
+        assert!(matches!(parallel.stop_reason, Some(StopReason::Saturated)));
+        assert_eq!(serial.iterations.len(), parallel.iterations.len());
+        for (s, p) in serial.iterations.iter().zip(&parallel.iterations) {
+            assert_eq!(s.matched, p.matched);
+            assert_eq!(s.egraph_nodes, p.egraph_nodes);
+        }
+    }
+
+    #[test]
+    fn prune_inapplicable_rules() {
+        /// A language that parses `sqrt` but never constructs it.
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        struct NoSqrt(S);
+
+        impl Language for NoSqrt {
+            fn matches(&self, other: &Self) -> bool {
+                self.0.matches(&other.0)
+            }
+            fn children(&self) -> &[Id] {
+                self.0.children()
+            }
+            fn children_mut(&mut self) -> &mut [Id] {
+                self.0.children_mut()
+            }
+            fn op_exists(&self) -> bool {
+                self.0.op.as_str() != "sqrt"
+            }
+        }
+
+        impl FromOp for NoSqrt {
+            type Error = std::convert::Infallible;
+            fn from_op(op: &str, children: Vec<Id>) -> Result<Self, Self::Error> {
+                Ok(NoSqrt(S::new(op, children)))
+            }
+        }
+
+        let rules: RuleSet<NoSqrt, ()> = vec![
+            rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rewrite!("sqrt-square"; "(sqrt (* ?a ?a))" => "?a"),
+            rewrite!("square"; "(* ?a ?a)" => "(pow ?a 2)"),
+        ]
+        .into();
+
+        let mut egraph = crate::EGraph::<NoSqrt, ()>::default();
+        egraph.add_expr(&"(+ x (* y y))".parse().unwrap());
+        egraph.rebuild();
+        assert!(rules[0].is_applicable_to(&egraph));
+        assert!(!rules[1].is_applicable_to(&egraph));
+
+        let (pruned, dropped) = rules.prune_inapplicable(&egraph);
+        assert_eq!(dropped, vec![Symbol::from("sqrt-square")]);
+        let names: Vec<Symbol> = pruned.iter().map(|rw| rw.name).collect();
+        assert_eq!(names, vec![Symbol::from("comm"), Symbol::from("square")]);
+
+        // a node that's actually in the egraph always counts
+        egraph.add_expr(&"(sqrt z)".parse().unwrap());
+        egraph.rebuild();
+        assert!(rules[1].is_applicable_to(&egraph));
+
+        let runner = Runner::default()
+            .with_expr(&"(+ x (* y y))".parse().unwrap())
+            .run(&rules);
+        assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+        assert_eq!(runner.skipped_rules, vec![Symbol::from("sqrt-square")]);
+        let applied: Vec<Symbol> = runner.iterations[0].applied.keys().copied().collect();
+        assert_eq!(applied, vec![Symbol::from("comm"), Symbol::from("square")]);
+    }
+
+    #[test]
+    fn fact_searcher_binds_vars() {
+        let x: Var = "?x".parse().unwrap();
+        // match every (f _) class, binding ?x to the child of its f node
+        let searcher = FactSearcher::new(vec![x], move |egraph: &EGraph, id, _: &()| {
+            let f = egraph[id].iter().find(|n| n.op.as_str() == "f")?;
+            let mut subst = Subst::default();
+            subst.insert(x, f.children[0]);
+            Some(subst)
+        });
+        let applier: Pattern<S> = "(g ?x)".parse().unwrap();
+        let rule = Rewrite::new("f-to-g", searcher, applier).unwrap();
+
+        let mut egraph = EGraph::default();
+        let fa = egraph.add_expr(&"(f a)".parse().unwrap());
+        egraph.add_expr(&"(h b)".parse().unwrap());
+        egraph.rebuild();
+        assert_eq!(rule.search(&egraph).len(), 1);
+        rule.run(&mut egraph);
+        let ga = egraph.lookup_expr(&"(g a)".parse().unwrap()).unwrap();
+        assert_eq!(egraph.find(fa), egraph.find(ga));
+    }
+
+    #[test]
+    fn budgeted_applier_skips() {
+        let applier: Pattern<S> = "(g (h ?x))".parse().unwrap();
+        let rules: Vec<Rewrite<S, ()>> = vec![Rewrite::new(
+            "f-unroll",
+            "(f ?x)".parse::<Pattern<S>>().unwrap(),
+            BudgetedApplier::new(applier, 2),
+        )
+        .unwrap()];
+
+        let runner = Runner::default()
+            .with_expr(&"(+ (f a) (+ (f b) (f c)))".parse().unwrap())
+            .run(&rules);
+        // one match fits each iteration, so this takes three iterations
+        let skipped: Vec<usize> = runner
+            .iterations
+            .iter()
+            .map(|it| {
+                it.skipped
+                    .get(&Symbol::from("f-unroll"))
+                    .copied()
+                    .unwrap_or(0)
+            })
+            .collect();
+        assert_eq!(skipped, vec![2, 1, 0, 0]);
+        assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+    }
+
+    #[test]
+    fn ranked_budgeted_applier() {
+        let x: Var = "?x".parse().unwrap();
+        let applier: Pattern<S> = "(g (h ?x))".parse().unwrap();
+        let rule = Rewrite::new(
+            "f-unroll",
+            "(f ?x)".parse::<Pattern<S>>().unwrap(),
+            BudgetedApplier::new(applier, 2),
+        )
+        .unwrap()
+        // prefer leaves later in the alphabet
+        .with_match_ranker(
+            move |egraph: &dyn EGraphView<S, ()>, _: Id, subst: &Subst| {
+                egraph.class(subst[x]).nodes[0].op.as_str().as_bytes()[0] as i64
+            },
+        );
+
+        let mut egraph = EGraph::default();
+        egraph.add_expr(&"(+ (f a) (+ (f c) (f b)))".parse().unwrap());
+        egraph.rebuild();
+
+        let unrolled = |egraph: &EGraph, leaf: &str| {
+            let expr = format!("(g (h {}))", leaf).parse().unwrap();
+            egraph.lookup_expr(&expr).is_some()
+        };
+        // one new match fits the budget each time
+        for expected in &[
+            [false, false, true],
+            [false, true, true],
+            [true, true, true],
+        ] {
+            let matches = rule.search(&egraph);
+            rule.apply(&mut egraph, &matches);
+            egraph.rebuild();
+            let actual = [
+                unrolled(&egraph, "a"),
+                unrolled(&egraph, "b"),
+                unrolled(&egraph, "c"),
+            ];
+            assert_eq!(&actual, expected);
+        }
+    }
+
+    #[test]
+    fn synthesized_commutativity_fires() {
+        // find a class with (op x y) and (op y x), and guess op commutes
+        let synthesize = |egraph: &dyn EGraphView<S, ()>, rules: &[Rewrite<S, ()>]| {
+            let mut new_rules = vec![];
+            for class in egraph.classes() {
+                for n in class.nodes.iter().filter(|n| n.len() == 2) {
+                    let name = format!("comm-{}", n.op);
+                    let swapped = class
+                        .nodes
+                        .iter()
+                        .any(|m| m.op == n.op && m.children[..] == [n.children[1], n.children[0]]);
+                    let known = rules
+                        .iter()
+                        .chain(&new_rules)
+                        .any(|rw| rw.name.as_str() == name);
+                    if swapped && n.children[0] != n.children[1] && !known {
+                        let lhs: Pattern<S> = format!("({} ?a ?b)", n.op).parse().unwrap();
+                        let rhs: Pattern<S> = format!("({} ?b ?a)", n.op).parse().unwrap();
+                        new_rules.push(Rewrite::new(name, lhs, rhs).unwrap());
+                    }
+                }
+            }
+            new_rules
+        };
+
+        let rules: Vec<Rewrite<S, ()>> = vec![rewrite!("swap-ab"; "(+ a b)" => "(+ b a)")];
+        let runner = Runner::default()
+            .with_expr(&"(+ a b)".parse().unwrap())
+            .with_expr(&"(* (+ c d) e)".parse().unwrap())
+            .with_rule_synthesizer(synthesize)
+            .run(&rules);
+
+        let comm = Symbol::from("comm-+");
+        let synthesized: Vec<&Vec<Symbol>> = runner
+            .iterations
+            .iter()
+            .map(|it| &it.synthesized)
+            .filter(|names| !names.is_empty())
+            .collect();
+        assert_eq!(synthesized, vec![&vec![comm]]);
+        assert!(runner
+            .iterations
+            .iter()
+            .any(|it| it.applied.contains_key(&comm)));
+        assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+
+        let egraph = &runner.egraph;
+        let cd = egraph.lookup_expr(&"(+ c d)".parse().unwrap()).unwrap();
+        let dc = egraph.lookup_expr(&"(+ d c)".parse().unwrap());
+        assert_eq!(dc, Some(cd));
+    }
+
     #[test]
     fn conditional_rewrite() {
         crate::init_logger();
@@ -524,6 +2481,846 @@ mod tests {
         assert_eq!(apps, vec![egraph.find(mul)]);
     }
 
+    #[test]
+    fn condition_equal_sees_dirty_unions() {
+        let mut egraph = EGraph::default();
+        let a = egraph.add(S::leaf("a"));
+        let b = egraph.add(S::leaf("b"));
+        egraph.rebuild();
+        let mut subst = Subst::default();
+        subst.insert("?a".parse().unwrap(), a);
+        subst.insert("?b".parse().unwrap(), b);
+
+        let same = ConditionEqual::parse("?a", "?b");
+        assert!(!same.check_mut(&mut egraph, a, &subst, None));
+        egraph.union(a, b);
+        assert!(same.check_mut(&mut egraph, a, &subst, None));
+        assert!(Condition::<S, ()>::check(&same, &egraph, a, &subst));
+        // the terms are added even if they don't end up equal, but not
+        // through a view
+        let f = ConditionEqual::parse("(f ?a)", "(g ?b)");
+        let gb = "(g b)".parse().unwrap();
+        assert!(!Condition::<S, ()>::check(&f, &egraph, a, &subst));
+        assert!(egraph.lookup_expr(&gb).is_none());
+        assert!(!f.check_mut(&mut egraph, a, &subst, None));
+        assert!(egraph.lookup_expr(&gb).is_some());
+        // the view sees them once they're equal
+        let fa = egraph.lookup_expr(&"(f a)".parse().unwrap()).unwrap();
+        let gb = egraph.lookup_expr(&gb).unwrap();
+        egraph.union(fa, gb);
+        assert!(Condition::<S, ()>::check(&f, &egraph, a, &subst));
+    }
+
+    #[test]
+    fn division_cancels_only_nonzero() {
+        type EGraph = crate::EGraph<S, Constants>;
+        fn is_nonzero(var: &str) -> impl Fn(&dyn EGraphView<S, Constants>, Id, &Subst) -> bool {
+            let var: Var = var.parse().unwrap();
+            move |egraph: &dyn EGraphView<S, Constants>, _: Id, subst: &Subst| {
+                egraph.data(subst[var]).map_or(false, |c| c != 0)
+            }
+        }
+
+        let cancel: Rewrite<S, Constants> =
+            rewrite!("cancel-div"; "(/ (* ?x ?y) ?y)" => "?x" if is_nonzero("?y"));
+        let exprs = [
+            "(/ (* a 2) 2)",
+            "(/ (* a (* 3 5)) (* 3 5))",
+            "(/ (* a 0) 0)",
+            "(/ (* a z) z)",
+        ];
+        let mut runner = Runner::default();
+        for expr in &exprs {
+            runner = runner.with_expr(&expr.parse().unwrap());
+        }
+        let runner = runner.run(&[cancel]);
+        let egraph = &runner.egraph;
+        let a = egraph.lookup(S::leaf("a")).unwrap();
+        let cancelled: Vec<bool> = runner.roots.iter().map(|&r| egraph.find(r) == a).collect();
+        assert_eq!(cancelled, vec![true, true, false, false]);
+    }
+
+    #[test]
+    fn analysis_condition_checks_each_class_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        #[derive(Default)]
+        struct Leaf;
+        impl Analysis<S> for Leaf {
+            type Data = bool;
+            fn make(_egraph: &crate::EGraph<S, Self>, enode: &S) -> bool {
+                enode.is_leaf()
+            }
+            fn merge(&mut self, a: &mut bool, b: bool) -> DidMerge {
+                merge_max(a, b)
+            }
+        }
+
+        // one eclass with (f x<i> y) for many i, all binding ?b to y
+        let mut egraph = crate::EGraph::<S, Leaf>::default();
+        let y = egraph.add(S::leaf("y"));
+        let root = egraph.add_expr(&"(f x0 y)".parse().unwrap());
+        for i in 1..20 {
+            let x = egraph.add(S::leaf(format!("x{}", i)));
+            let f = egraph.add(S::new("f", vec![x, y]));
+            egraph.union(root, f);
+        }
+        egraph.rebuild();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let leaf = AnalysisCondition::new("?b".parse().unwrap(), move |is_leaf: &bool| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            *is_leaf
+        });
+        let rule: Rewrite<S, Leaf> = rewrite!("swap"; "(f ?a ?b)" => "(g ?b ?a)" if leaf);
+        let matches = rule.search(&egraph);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].substs.len(), 20);
+        assert_eq!(rule.apply(&mut egraph, &matches).len(), 20);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        // one at a time, it's called once per match
+        for subst in &matches[0].substs {
+            assert!(rule
+                .applier
+                .apply_one(&mut egraph, root, subst, None, rule.name)
+                .is_empty());
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 21);
+    }
+
+    #[test]
+    fn except_searcher_drops_forbidden_matches() {
+        let select: Pattern<S> = "(select ?c ?t ?f)".parse().unwrap();
+        let except = |forbidden: &str| {
+            ExceptSearcher::new(select.clone(), forbidden.parse().unwrap())
+                .with_root("?c".parse().unwrap())
+        };
+        let matched = |forbidden: &str, egraph: &EGraph| {
+            let matches = except(forbidden).search(egraph);
+            let mut ids: Vec<Id> = matches.iter().map(|m| m.eclass).collect();
+            ids.sort_unstable();
+            ids
+        };
+
+        let mut egraph = EGraph::default();
+        let fires = egraph.add_expr(&"(select c a b)".parse().unwrap());
+        let never = egraph.add_expr(&"(select false a b)".parse().unwrap());
+        let later = egraph.add_expr(&"(select d a b)".parse().unwrap());
+        egraph.rebuild();
+        let sorted = |mut ids: Vec<Id>| {
+            ids.sort_unstable();
+            ids
+        };
+        assert_eq!(matched("false", &egraph), sorted(vec![fires, later]));
+        assert!(except("false").search_eclass(&egraph, never).is_none());
+        assert!(except("false").search_eclass(&egraph, fires).is_some());
+
+        // variables shared with the forbidden pattern must agree, and
+        // the others may be anything
+        let mut add = |expr: &str, leaf: &str| {
+            let id = egraph.add_expr(&expr.parse().unwrap());
+            let leaf = egraph.add(S::leaf(leaf));
+            egraph.union(id, leaf);
+        };
+        add("(eq a b)", "c");
+        add("(eq b a)", "d");
+        egraph.rebuild();
+        let all = sorted(vec![fires, never, later]);
+        assert_eq!(matched("(eq ?t ?f)", &egraph), sorted(vec![never, later]));
+        assert_eq!(matched("(eq ?f ?t)", &egraph), sorted(vec![fires, never]));
+        assert_eq!(matched("(eq ?x ?y)", &egraph), vec![never]);
+        assert_eq!(matched("(not ?z)", &egraph), all);
+
+        // a later union makes the forbidden pattern match, so the next
+        // search drops the match
+        let (d, f) = (egraph.add(S::leaf("d")), egraph.add(S::leaf("false")));
+        egraph.union(d, f);
+        egraph.rebuild();
+        assert_eq!(matched("false", &egraph), vec![fires]);
+
+        // the inner pattern would match more, so it isn't the rule's lhs
+        let except_false = except("false");
+        let rule: Rewrite<S, ()> = rewrite!("select-true"; { except_false } => "?t");
+        assert!(rule.searcher.get_pattern_ast().is_none());
+        assert!(matches!(
+            format_rules(&[rule]),
+            Err(Error::UnwritableRule { .. })
+        ));
+    }
+
+    #[test]
+    fn condition_searches_clean_view() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        crate::init_logger();
+        let mut egraph = EGraph::default();
+        egraph.add_expr(&"(g b)".parse().unwrap());
+        let root = egraph.add_expr(&"(f (g a))".parse().unwrap());
+
+        // only true once (g a) and (g b) are merged by congruence
+        let gb: Pattern<S> = "(g b)".parse().unwrap();
+        let x: Var = "?x".parse().unwrap();
+        let saw_dirty = Arc::new(AtomicBool::new(false));
+        struct InCleanView {
+            gb: Pattern<S>,
+            x: Var,
+            seen: Arc<AtomicBool>,
+        }
+        impl Condition<S, ()> for InCleanView {
+            fn check(&self, _: &dyn EGraphView<S, ()>, _: Id, _: &Subst) -> bool {
+                unreachable!("a ConditionalApplier calls check_mut")
+            }
+            fn check_mut(&self, egraph: &mut EGraph, _: Id, subst: &Subst, _: Option<&S>) -> bool {
+                self.seen.fetch_or(!egraph.clean, Ordering::Relaxed);
+                egraph.with_clean_view(|clean| {
+                    let x = clean.find(subst[self.x]);
+                    self.gb.search(clean).iter().any(|m| m.eclass == x)
+                })
+            }
+        }
+        let cond = InCleanView {
+            gb,
+            x,
+            seen: saw_dirty.clone(),
+        };
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rewrite!("a-to-b"; "a" => "b"),
+            rewrite!("f-to-k"; "(f ?x)" => "(k ?x)" if cond),
+        ];
+
+        let runner: Runner<S, ()> = Runner::default()
+            .with_egraph(egraph)
+            .with_iter_limit(1)
+            .run(&rules);
+        let egraph = &runner.egraph;
+        assert!(saw_dirty.load(Ordering::Relaxed));
+        let k = egraph.lookup_expr(&"(k (g b))".parse().unwrap());
+        assert_eq!(k, Some(egraph.find(root)));
+        assert_eq!(runner.iterations[0].applied.len(), 2);
+    }
+
+    #[test]
+    fn frozen_class_counts_skipped_unions() {
+        crate::init_logger();
+        let mut egraph = EGraph::default();
+        let root = egraph.add_expr(&"(+ a 0)".parse().unwrap());
+        let a = egraph.lookup(S::leaf("a")).unwrap();
+        egraph.freeze(root);
+        let rules: Vec<Rewrite<S, ()>> = vec![rewrite!("add-0"; "(+ ?a 0)" => "?a")];
+
+        let runner: Runner<S, ()> = Runner::default().with_egraph(egraph).run(&rules);
+        assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+        assert_ne!(runner.egraph.find(root), runner.egraph.find(a));
+        let iteration = &runner.iterations[0];
+        assert!(iteration.applied.is_empty());
+        assert_eq!(
+            iteration.frozen_skipped.get(&Symbol::from("add-0")),
+            Some(&1)
+        );
+        assert_eq!(runner.report().frozen_skips, runner.egraph.frozen_skips());
+    }
+
+    #[test]
+    fn panicking_condition_rolls_back() {
+        crate::init_logger();
+        // only blows up once the iteration got somewhere
+        let cond = |egraph: &dyn EGraphView<S, ()>, _: Id, _: &Subst| {
+            assert!(egraph.total_size() < 6, "boom");
+            true
+        };
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rewrite!("boom"; "(* ?a 2)" => "(+ ?a ?a)" if cond),
+        ];
+        let start = "(* (+ a b) 2)".parse().unwrap();
+        let runner = Runner::<S, ()>::default()
+            .with_panic_isolation(true)
+            .with_expr(&start)
+            .run(&rules);
+
+        match runner.stop_reason.as_ref().unwrap() {
+            StopReason::Panicked {
+                rule,
+                phase,
+                message,
+            } => {
+                assert_eq!(*rule, Some(Symbol::from("boom")));
+                assert_eq!(*phase, RunPhase::Apply);
+                assert!(message.contains("boom"), "{}", message);
+            }
+            reason => panic!("unexpected stop reason {:?}", reason),
+        }
+        assert_eq!(runner.iterations.len(), 1);
+        // the commuted term from the same iteration is gone too
+        let egraph = &runner.egraph;
+        assert!(egraph.clean);
+        egraph.check_invariants();
+        assert_eq!(egraph.total_size(), 5);
+        assert!(egraph.lookup_expr(&start).is_some());
+        assert!(egraph.lookup_expr(&"(+ b a)".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn panicking_hook_is_reported() {
+        crate::init_logger();
+        let runner = Runner::<S, ()>::default()
+            .with_panic_isolation(true)
+            .with_expr(&"(+ a b)".parse().unwrap())
+            .with_hook(|_| panic!("hook failed"))
+            .run(&[rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)")]);
+        assert!(matches!(
+            runner.stop_reason,
+            Some(StopReason::Panicked {
+                rule: None,
+                phase: RunPhase::Hooks,
+                ..
+            })
+        ));
+        assert_eq!(runner.egraph.total_size(), 3);
+    }
+
+    #[test]
+    fn overlapping_rules_are_deduped() {
+        crate::init_logger();
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rewrite!("dist"; "(* ?a (+ ?b ?c))" => "(+ (* ?a ?b) (* ?a ?c))"),
+            rewrite!("dist-2"; "(* 2 (+ ?b ?c))" => "(+ (* 2 ?b) (* 2 ?c))"),
+        ];
+        let start = "(* 2 (+ x y))".parse().unwrap();
+        let run = |dedup| {
+            Runner::<S, ()>::default()
+                .with_apply_dedup(dedup)
+                .with_expr(&start)
+                .run(&rules)
+        };
+        let plain = run(false);
+        let deduped = run(true);
+
+        let dist_2 = Symbol::from("dist-2");
+        assert!(plain.iterations.iter().all(|it| it.deduped.is_empty()));
+        assert_eq!(deduped.iterations[0].deduped.get(&dist_2), Some(&1));
+        assert!(deduped.iterations[0].applied.get(&dist_2).is_none());
+
+        assert!(matches!(deduped.stop_reason, Some(StopReason::Saturated)));
+        assert_eq!(plain.iterations.len(), deduped.iterations.len());
+        let (plain, deduped) = (&plain.egraph, &deduped.egraph);
+        assert_eq!(plain.total_size(), deduped.total_size());
+        assert_eq!(plain.number_of_classes(), deduped.number_of_classes());
+        let expanded = "(+ (* 2 x) (* 2 y))".parse().unwrap();
+        assert_eq!(deduped.lookup_expr(&start), deduped.lookup_expr(&expanded));
+    }
+
+    #[test]
+    fn fanout_is_capped_and_reported() {
+        crate::init_logger();
+        // one eclass with 30 * 30 additions and 30 * 30 (+ ?a (g ?b))s
+        let mut egraph = EGraph::default();
+        let leaves: Vec<Id> = (0..30)
+            .map(|i| egraph.add(S::leaf(format!("a{}", i))))
+            .collect();
+        let gs: Vec<Id> = leaves
+            .iter()
+            .map(|&a| egraph.add(S::new("g", vec![a])))
+            .collect();
+        let fat = egraph.add(S::leaf("fat"));
+        for &a in &leaves {
+            for (&b, &g) in leaves.iter().zip(&gs) {
+                let sum = egraph.add(S::new("+", vec![a, b]));
+                let sum_g = egraph.add(S::new("+", vec![a, g]));
+                egraph.union(fat, sum);
+                egraph.union(fat, sum_g);
+            }
+        }
+        let thin = egraph.add_expr(&"(+ x y)".parse().unwrap());
+        egraph.rebuild();
+        let fat = egraph.find(fat);
+
+        let nested: Pattern<S> = "(+ ?a (g ?b))".parse().unwrap();
+        let capped = nested.search_eclass_with_limit(&egraph, fat, 7).unwrap();
+        assert_eq!(capped.substs.len(), 7);
+        assert_eq!(
+            nested.search_eclass(&egraph, fat).unwrap().substs.len(),
+            900
+        );
+
+        let comm = rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)");
+        let capped_comm = comm.clone().with_max_matches_per_class(50);
+        let matches = capped_comm.search(&egraph);
+        assert!(matches.iter().all(|m| m.substs.len() <= 50));
+        assert_eq!(
+            matches
+                .iter()
+                .find(|m| m.eclass == fat)
+                .unwrap()
+                .substs
+                .len(),
+            50
+        );
+
+        let run = |rule: &Rewrite<S, ()>| {
+            Runner::default()
+                .with_egraph(egraph.clone())
+                .with_scheduler(SimpleScheduler)
+                .with_iter_limit(1)
+                .run(std::slice::from_ref(rule))
+        };
+        let comm_name = Symbol::from("comm");
+        let runner = run(&comm);
+        let top = &runner.iterations[0].top_fanout;
+        // the other eclasses are the (g a)s, which don't match
+        assert_eq!(top[0], (comm_name, fat, 1800));
+        assert_eq!(top[1], (comm_name, thin, 1));
+        let runner = run(&capped_comm);
+        let top = &runner.iterations[0].top_fanout;
+        assert_eq!(top[0], (comm_name, fat, 50));
+        let stats = runner.export_rule_stats();
+        assert_eq!(stats.get("comm").unwrap().matches, 51);
+    }
+
+    #[test]
+    fn iterations_record_matches_and_sizes() {
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rewrite!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rewrite!("comm-mul"; "(* ?a ?b)" => "(* ?b ?a)"),
+            rewrite!("never"; "(+ ?a 0)" => "?a"),
+        ];
+        let runner = Runner::default()
+            .with_expr(&"(+ a (* b (+ c d)))".parse().unwrap())
+            .run(&rules);
+        assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+        let iterations = &runner.iterations;
+        assert_eq!(iterations.len(), 2);
+
+        let name = |s: &str| Symbol::from(s);
+        for (i, it) in iterations.iter().enumerate() {
+            assert!(it.rebuilt_nodes >= it.egraph_nodes);
+            assert!(it.rebuilt_classes >= it.egraph_classes);
+            if let Some(next) = iterations.get(i + 1) {
+                assert_eq!(next.egraph_nodes, it.rebuilt_nodes);
+            }
+            assert_eq!(it.matched.get(&name("never")), None);
+            for (rule, n) in &it.applied {
+                assert!(it.matched[rule] >= *n);
+            }
+        }
+        let last = iterations.last().unwrap();
+        assert_eq!(last.rebuilt_nodes, runner.egraph.total_size());
+        assert_eq!(last.rebuilt_classes, runner.egraph.number_of_classes());
+
+        // each comm flips every sum and product once
+        let first = &iterations[0];
+        assert_eq!((first.egraph_nodes, first.rebuilt_nodes), (7, 10));
+        assert_eq!(first.matched[&name("comm-add")], 2);
+        assert_eq!(first.matched[&name("comm-mul")], 1);
+        assert_eq!(first.applied[&name("comm-add")], 2);
+        // and then finds both orders, but has nothing left to do
+        assert_eq!(last.matched[&name("comm-add")], 4);
+        assert_eq!(last.matched[&name("comm-mul")], 2);
+        assert!(last.applied.is_empty());
+    }
+
+    #[test]
+    fn runner_stops_for_each_reason() {
+        let comm = rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)");
+        let assoc = rewrite!("assoc"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)");
+        // 5 variables, so 31 eclasses and 185 enodes once saturated
+        let run = |runner: Runner<S, ()>, rules: &[Rewrite<S, ()>]| {
+            let expr = "(+ a (+ b (+ c (+ d e))))".parse().unwrap();
+            let runner = runner.with_expr(&expr).run(rules);
+            let stop_reason = runner.stop_reason.clone().unwrap();
+            (runner, stop_reason)
+        };
+        let both = [comm.clone(), assoc];
+
+        let (_, stop) = run(Runner::default(), &[comm.clone()]);
+        assert!(matches!(stop, StopReason::Saturated));
+        let (_, stop) = run(Runner::default().with_iter_limit(2), &both);
+        assert!(matches!(stop, StopReason::IterationLimit(2)));
+        let (runner, stop) = run(Runner::default().with_node_limit(50), &both);
+        assert!(matches!(stop, StopReason::NodeLimit(n) if n > 50));
+        assert!(runner.ensure_within_limits().is_err());
+        let (_, stop) = run(Runner::default().with_class_limit(20), &both);
+        assert!(matches!(stop, StopReason::ClassLimit(n) if n > 20));
+        let hook = Runner::default().with_hook(|_| Err("stop".into()));
+        let (_, stop) = run(hook, &both);
+        assert!(matches!(stop, StopReason::Other(s) if s == "stop"));
+
+        // the time runs out while applying the first of the first
+        // iteration's rules, so the second one is never applied; each
+        // application of it takes 10ms on the test's clock
+        use std::cell::Cell;
+        thread_local!(static TICKS: Cell<u64> = Cell::new(0));
+        let slow = rewrite!("slow"; "(+ ?a ?b)" => "(+ ?b ?a)" if |_: &dyn EGraphView<S, ()>, _: Id, _: &Subst| {
+            TICKS.with(|ticks| ticks.set(ticks.get() + 1));
+            true
+        });
+        let assoc = both[1].clone();
+        let runner = Runner::default()
+            .with_time_limit(Duration::from_millis(20))
+            .with_clock(|_| Duration::from_millis(TICKS.with(Cell::get) * 10));
+        let (runner, stop) = run(runner, &[slow, assoc]);
+        assert!(matches!(stop, StopReason::TimeLimit(_)));
+        assert_eq!(runner.iterations.len(), 1);
+        let applied = &runner.iterations[0].applied;
+        assert_eq!(applied.get(&Symbol::from("slow")), Some(&4));
+        assert!(!applied.contains_key(&Symbol::from("assoc")));
+        // the egraph is still rebuilt, so it can be extracted from
+        let extractor = Extractor::new(&runner.egraph, AstSize);
+        assert_eq!(extractor.find_best(runner.roots[0]).0, 9);
+        assert_eq!(runner.egraph.number_of_classes(), 9);
+    }
+
+    #[test]
+    fn goals_stop_the_runner_early() {
+        let rules: &[Rewrite<S, ()>] = &[
+            rewrite!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rewrite!("assoc-add"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+            rewrite!("add-0"; "(+ ?a 0)" => "?a"),
+        ];
+        let start: RecExpr<S> = "(+ (+ a 0) (+ b 0))".parse().unwrap();
+        let goal: RecExpr<S> = "(+ b a)".parse().unwrap();
+        let pattern: Pattern<S> = "(+ ?x a)".parse().unwrap();
+        let runner = Runner::default()
+            .with_iter_limit(10)
+            .with_expr(&start)
+            .with_goals(&[goal.clone()])
+            .with_goals(&[pattern])
+            .run(rules);
+        assert!(matches!(runner.stop_reason, Some(StopReason::GoalsReached)));
+        // the first iteration proves them
+        assert_eq!(runner.iterations.len(), 1);
+        assert!(matches!(
+            runner.iterations[0].stop_reason,
+            Some(StopReason::GoalsReached)
+        ));
+        let root = runner.egraph.find(runner.roots[0]);
+        assert_eq!(runner.egraph.equivs(&start, &goal), vec![root]);
+
+        // the ith goal is for the ith root
+        let other: RecExpr<S> = "(+ c 0)".parse().unwrap();
+        let c: RecExpr<S> = "c".parse().unwrap();
+        let goals: Vec<Goal<S>> = vec![goal.clone().into(), c.into()];
+        let runner = Runner::default()
+            .with_expr(&start)
+            .with_expr(&other)
+            .with_goals(&goals)
+            .run(rules);
+        assert!(matches!(runner.stop_reason, Some(StopReason::GoalsReached)));
+        let runner = Runner::default()
+            .with_expr(&other)
+            .with_expr(&start)
+            .with_goals(&goals)
+            .run(rules);
+        assert!(!matches!(
+            runner.stop_reason,
+            Some(StopReason::GoalsReached)
+        ));
+
+        // goals that already hold stop it before the first iteration
+        let runner = Runner::default()
+            .with_expr(&start)
+            .with_goals(&[start.clone()])
+            .run(rules);
+        assert!(matches!(runner.stop_reason, Some(StopReason::GoalsReached)));
+        assert!(runner.iterations.is_empty());
+
+        // an unreachable goal doesn't stop it
+        let unreachable: RecExpr<S> = "(* a b)".parse().unwrap();
+        let runner = Runner::default()
+            .with_iter_limit(10)
+            .with_expr(&start)
+            .with_goals(&[unreachable.clone()])
+            .run(rules);
+        assert!(!matches!(
+            runner.stop_reason,
+            Some(StopReason::GoalsReached)
+        ));
+        assert!(runner.iterations.len() > 1);
+        assert!(runner.egraph.equivs(&start, &unreachable).is_empty());
+    }
+
+    #[test]
+    fn runner_stops_when_converged() {
+        let rules = [
+            rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rewrite!("assoc"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+            // never saturates, but only adds (g (g .. a)) and its f each time
+            rewrite!("grow"; "(f ?x)" => "(f (g ?x))"),
+        ];
+        let run = |runner: Runner<S, ()>| {
+            runner
+                .with_scheduler(SimpleScheduler)
+                .with_expr(&"(+ a (+ b (+ c d)))".parse().unwrap())
+                .with_expr(&"(f a)".parse().unwrap())
+                .run(&rules)
+        };
+
+        let runner = run(Runner::default().with_iter_limit(20));
+        assert!(matches!(
+            runner.stop_reason,
+            Some(StopReason::IterationLimit(20))
+        ));
+        let plateau = &runner.iterations[10..];
+        assert!(plateau.iter().all(|i| (i.unions, i.new_nodes) == (1, 2)));
+        // the new g and the f eclass it joined
+        assert!(plateau.iter().all(|i| i.changed_classes == 2));
+        assert!(runner.iterations[0].change() > 3);
+
+        let runner = run(Runner::default()
+            .with_iter_limit(20)
+            .with_convergence(3, 10));
+        let n = runner.iterations.len();
+        assert!(n < 20);
+        assert!(matches!(
+            runner.stop_reason,
+            Some(StopReason::Converged {
+                window: 3,
+                change: 9
+            })
+        ));
+        assert!(runner.iterations[n - 3..].iter().all(|i| i.change() == 3));
+        assert!(runner.iterations[n - 4].change() > 3);
+        assert!(runner.ensure_within_limits().is_ok());
+    }
+
+    #[test]
+    fn search_step_limit_truncates_searches() {
+        let (egraph, _) = crate::test::explosive_egraph(4);
+        // 10,000 matches that change nothing: every g is in one eclass,
+        // so the appliers only rebuild terms the egraph already has
+        let explode = crate::test::explosive_pattern(4);
+        let collapse: Pattern<S> = "(f (g ?x0) (g ?x0) (g ?x0) (g ?x0))".parse().unwrap();
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            Rewrite::new("explode", explode, collapse).unwrap(),
+            rewrite!("g"; "(g ?a)" => "(g a0)"),
+        ];
+        let run = |limit: Option<usize>| {
+            let runner = Runner::default()
+                .with_egraph(egraph.clone())
+                .with_scheduler(SimpleScheduler)
+                .with_iter_limit(3);
+            match limit {
+                Some(limit) => runner.with_search_step_limit(limit),
+                None => runner,
+            }
+            .run(&rules)
+        };
+
+        let runner = run(None);
+        assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+        assert!(runner.iterations[0].search_truncated.is_empty());
+        assert_eq!(
+            runner.export_rule_stats().get("explode").unwrap().matches,
+            10_000
+        );
+
+        let runner = run(Some(1000));
+        // the missed matches might have done something
+        assert!(matches!(
+            runner.stop_reason,
+            Some(StopReason::IterationLimit(3))
+        ));
+        for iteration in &runner.iterations {
+            let truncated: Vec<&str> = iteration
+                .search_truncated
+                .iter()
+                .map(|name| name.as_str())
+                .collect();
+            assert_eq!(truncated, vec!["explode", "g"]);
+        }
+        let stats = runner.export_rule_stats();
+        assert!(stats.get("explode").unwrap().matches < 3 * 1000);
+        assert_eq!(stats.get("g").unwrap().matches, 0);
+
+        // without a runner
+        let (matches, truncated) = rules[0].search_with_step_limit(&egraph, 1000);
+        let n_matches: usize = matches.iter().map(|m| m.substs.len()).sum();
+        assert!(truncated && 0 < n_matches && n_matches < 1000);
+        let (matches, truncated) = rules[1].search_with_step_limit(&egraph, 1000);
+        assert!(!truncated);
+        let substs = |ms: Vec<SearchMatches<S>>| -> Vec<Vec<Subst>> {
+            ms.into_iter().map(|m| m.substs).collect()
+        };
+        assert_eq!(substs(matches), substs(rules[1].search(&egraph)));
+
+        // multi-patterns take steps too
+        let multi: MultiPattern<S> =
+            format!("?root = {}", rules[0].searcher.get_pattern_ast().unwrap())
+                .parse()
+                .unwrap();
+        let multi = Rewrite::new("multi", multi, "(g ?x0)".parse::<Pattern<S>>().unwrap()).unwrap();
+        let (matches, truncated) = multi.search_with_step_limit(&egraph, 1000);
+        let n_matches: usize = matches.iter().map(|m| m.substs.len()).sum();
+        assert!(truncated && n_matches < 1000);
+    }
+
+    #[test]
+    fn backtrack_profiling_finds_wasteful_binders() {
+        // one f whose second child is a g, and five whose aren't
+        let mut egraph = EGraph::default();
+        egraph.add_expr(&"(f x (g y))".parse().unwrap());
+        for i in 0..5 {
+            egraph.add_expr(&format!("(f x{} (k y))", i).parse().unwrap());
+        }
+        egraph.rebuild();
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rewrite!("fg"; "(f ?a (g ?b))" => "(p ?a ?b)"),
+            rewrite!("k"; "(k ?a)" => "(q ?a)"),
+        ];
+        let run = |profiling| {
+            Runner::default()
+                .with_egraph(egraph.clone())
+                .with_iter_limit(1)
+                .with_backtrack_profiling(profiling)
+                .run(&rules)
+        };
+
+        let runner = run(false);
+        assert!(runner.iterations[0].wasteful_binders.is_empty());
+        assert!(runner.report().top_wasteful_binders.is_empty());
+
+        // each f without a g is bound and backtracked from, and the
+        // simple k pattern never backtracks
+        let runner = run(true);
+        let binders = &runner.iterations[0].wasteful_binders;
+        assert_eq!(binders.len(), 1);
+        let b = &binders[0];
+        assert_eq!(
+            (
+                b.rule.as_str(),
+                b.subterm.as_str(),
+                b.candidates,
+                b.backtracks
+            ),
+            ("fg", "(f ?a (g ?b))", 6, 5)
+        );
+        assert_eq!(runner.report().top_wasteful_binders, binders.clone());
+        let report = runner.report().to_string();
+        assert!(
+            report.contains("(f ?a (g ?b)) (node 3) in fg: 5 of 6 candidates backtracked"),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn fused_apply_saturates_the_same() {
+        crate::init_logger();
+        let rules: Vec<Rewrite<S, ()>> = vec![
+            rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rewrite!("assoc"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+        ];
+        let start = "(+ a (+ b (+ c d)))".parse().unwrap();
+        let run = |fused: Option<usize>| {
+            let runner = Runner::<S, ()>::default()
+                .with_explanations_disabled()
+                .with_scheduler(SimpleScheduler);
+            let runner = match fused {
+                Some(batch_size) => runner.with_fused_batch_size(batch_size),
+                None => runner,
+            };
+            runner.with_expr(&start).run(&rules)
+        };
+        let two_phase = run(None);
+        for &batch_size in &[1, 1000] {
+            let fused = run(Some(batch_size));
+            assert!(matches!(fused.stop_reason, Some(StopReason::Saturated)));
+            assert_eq!(fused.egraph.total_size(), two_phase.egraph.total_size());
+            let stats = fused.export_rule_stats();
+            let unions: usize = fused
+                .iterations
+                .iter()
+                .flat_map(|i| i.applied.values())
+                .sum();
+            assert_eq!(
+                stats.rules.values().map(|e| e.unions).sum::<usize>(),
+                unions
+            );
+            assert!(stats.get("assoc").unwrap().matches > 0);
+        }
+
+        let mut egraph = EGraph::default();
+        for x in &["(f a)", "(f b)", "(f c)", "(f d)"] {
+            egraph.add_expr(&x.parse().unwrap());
+        }
+        egraph.rebuild();
+        let rule: Rewrite<S, ()> = rewrite!("f-g"; "(f ?x)" => "(g ?x)");
+        assert_eq!(rule.search_apply(&mut egraph, 3, 1), (3, 3));
+        assert_eq!(rule.search_apply(&mut egraph, usize::MAX, 2), (4, 1));
+        egraph.rebuild();
+        assert_eq!(rule.search(&egraph).len(), 4);
+    }
+
+    #[test]
+    fn lemmas_seed_to_fixpoint() {
+        let pat = |s: &str| -> Pattern<S> { s.parse().unwrap() };
+        let rules: Vec<Rewrite<S, ()>> = vec![rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)")];
+        let runner = Runner::<S, ()>::default()
+            .with_expr(
+                &"(+ (* a (pow a -1)) (* (f b) (pow (f b) -1)))"
+                    .parse()
+                    .unwrap(),
+            )
+            .with_lemma(pat("(* ?x (pow ?x -1))"), pat("1"))
+            .with_lemma(pat("(+ 1 1)"), pat("2"))
+            .run(&rules);
+
+        // the second lemma only matches after the first one's unions
+        let seeding = runner.iterations[0].lemma_seeding.as_ref().unwrap();
+        assert_eq!(seeding.passes, 3);
+        assert!(seeding.saturated);
+        let inverse = Symbol::from("(* ?x (pow ?x -1)) => 1");
+        assert_eq!(seeding.applied.get(&inverse), Some(&2));
+        assert_eq!(seeding.applied.get(&Symbol::from("(+ 1 1) => 2")), Some(&1));
+        let two = runner.egraph.lookup_expr(&"2".parse().unwrap());
+        assert_eq!(two, Some(runner.egraph.find(runner.roots[0])));
+        assert!(runner.iterations[1..]
+            .iter()
+            .all(|i| i.lemma_seeding.is_none()));
+
+        // lemmas aren't rules
+        let stats = runner.export_rule_stats();
+        assert!(stats.get(inverse.as_str()).is_none());
+        assert!(runner
+            .iterations
+            .iter()
+            .all(|i| !i.applied.contains_key(&inverse)));
+        let report = runner.report();
+        assert_eq!((report.lemma_seedings, report.lemma_unions), (1, 3));
+        assert!(report
+            .to_string()
+            .contains("Lemma seeding: 1 times, 3 unions"));
+    }
+
+    #[test]
+    fn growing_lemmas_stop_at_the_pass_limit() {
+        let pat = |s: &str| -> Pattern<S> { s.parse().unwrap() };
+        let rules: Vec<Rewrite<S, ()>> = vec![rewrite!("grow"; "(k ?x)" => "(k (j ?x))")];
+        let runner = Runner::<S, ()>::default()
+            .with_expr(&"(+ (g a) (k b))".parse().unwrap())
+            .with_lemma(pat("(g ?x)"), pat("(g (h ?x))"))
+            .with_lemma_pass_limit(3)
+            .with_lemma_interval(2)
+            .with_iter_limit(5)
+            .run(&rules);
+
+        assert_eq!(runner.iterations.len(), 5);
+        for (i, iteration) in runner.iterations.iter().enumerate() {
+            match &iteration.lemma_seeding {
+                Some(seeding) => {
+                    assert_eq!(i % 2, 0);
+                    assert_eq!(seeding.passes, 3);
+                    assert!(!seeding.saturated);
+                }
+                None => assert_eq!(i % 2, 1),
+            }
+        }
+        assert_eq!(runner.report().lemma_seedings, 3);
+    }
+
     #[test]
     fn fn_rewrite() {
         crate::init_logger();
@@ -588,4 +3385,87 @@ mod tests {
         fold_add.run(&mut egraph);
         assert_eq!(egraph.equivs(&start, &goal), vec![egraph.find(root)]);
     }
+
+    #[test]
+    fn applier_folds_analysis_constants() {
+        /// Adds the literal value of the matched eclass, if it has one.
+        struct Fold;
+        impl Applier<S, Constants> for Fold {
+            fn apply_one(
+                &self,
+                egraph: &mut crate::EGraph<S, Constants>,
+                matched: Id,
+                _: &Subst,
+                _: Option<&PatternAst<S>>,
+                _: Symbol,
+            ) -> Vec<Id> {
+                match egraph[matched].data {
+                    Some(c) => {
+                        let literal = egraph.add(S::leaf(c.to_string()));
+                        if egraph.union(matched, literal) {
+                            vec![literal]
+                        } else {
+                            vec![]
+                        }
+                    }
+                    None => vec![],
+                }
+            }
+        }
+
+        let rules: Vec<Rewrite<S, Constants>> = vec![
+            rewrite!("fold-add"; "(+ ?a ?b)" => { Fold }),
+            rewrite!("fold-mul"; "(* ?a ?b)" => { Fold }),
+            rewrite!("mul-0"; "(* ?a 0)" => "0"),
+        ];
+        let folded = "(+ x (* 2 (+ 1 2)))".parse().unwrap();
+        let zeroed = "(* (+ y 1) (+ 2 -2))".parse().unwrap();
+        let overflowing = format!("(* {} 3)", i64::MAX / 2).parse().unwrap();
+        let runner = Runner::default()
+            .with_expr(&folded)
+            .with_expr(&zeroed)
+            .with_expr(&overflowing)
+            .run(&rules);
+        let egraph = &runner.egraph;
+        let lookup = |s: &str| egraph.lookup_expr(&s.parse().unwrap());
+
+        assert_eq!(lookup("(+ 1 2)"), lookup("3"));
+        assert_eq!(lookup("(* 2 (+ 1 2))"), lookup("6"));
+        assert_eq!(lookup("(+ x 6)"), Some(egraph.find(runner.roots[0])));
+        assert_eq!(egraph[runner.roots[0]].data, None);
+        // folding to 0 lets another rule fire on the literal
+        assert_eq!(lookup("(* (+ y 1) 0)"), Some(egraph.find(runner.roots[1])));
+        assert_eq!(egraph[runner.roots[1]].data, Some(0));
+        // a product that overflows isn't folded
+        assert_eq!(egraph[runner.roots[2]].data, None);
+        assert_eq!(egraph[runner.roots[2]].len(), 1);
+    }
+
+    #[test]
+    fn best_terms_take_flat_memory() {
+        // the egraph grows every iteration, but the best term never
+        // changes, so it's only stored once
+        let rules: Vec<Rewrite<S, ()>> = vec![rewrite!("wrap"; "(f ?a)" => "(f (g ?a))")];
+        let runner = Runner::default()
+            .with_scheduler(SimpleScheduler)
+            .with_iter_limit(300)
+            .with_node_limit(100_000)
+            .with_time_limit(std::time::Duration::from_secs(60))
+            .with_best_term_tracking(AstSize)
+            .with_expr(&"(f x)".parse().unwrap())
+            .run(&rules);
+        assert!(matches!(
+            runner.stop_reason,
+            Some(StopReason::IterationLimit(300))
+        ));
+        assert!(runner.egraph.total_size() > 300);
+
+        let first = runner.iterations[0].best_terms[0];
+        assert!(runner.iterations.iter().all(|i| i.best_terms == [first]));
+        assert_eq!(runner.best_terms.to_recexpr(first).to_string(), "(f x)");
+        assert_eq!(runner.best_terms.len(), 2);
+        assert_eq!(runner.report().best_term_nodes, 2);
+        // the report's term of the root stays small too
+        assert_eq!(runner.report().root_terms, vec!["(f x)".to_string()]);
+    }
 }
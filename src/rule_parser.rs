@@ -0,0 +1,305 @@
+use std::fmt::Display;
+
+use thiserror::Error;
+
+use crate::*;
+
+/// An error from [`parse_rules`], with the position it occurred at.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("line {line}, column {column}: {message}")]
+pub struct RuleParseError {
+    /// The line of the error, starting from 1.
+    pub line: usize,
+    /// The column of the error, starting from 1.
+    pub column: usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+/// Parse a set of [`Rewrite`]s from a string, one rule per line.
+///
+/// Each line has one of these forms:
+/// ```text
+/// name: lhs => rhs
+/// name: lhs => rhs if cond-equal lhs2 rhs2 if cond-equal lhs3 rhs3
+/// name: lhs <=> rhs
+/// ```
+/// where the sides are [`Pattern`]s.
+/// Each `if cond-equal a b` adds a [`ConditionEqual`] on `a` and `b`.
+/// A `<=>` rule produces two rewrites, just like the [`rewrite!`] macro,
+/// with the reverse one named `name-rev`.
+/// Blank lines and lines starting with `#` are ignored.
+///
+/// Every pattern is parsed (and compiled) while loading, so unknown
+/// operators and unbound variables are reported here rather than when
+/// the rules run.
+/// [`format_rules`] does the reverse.
+///
+/// # Example
+/// ```
+/// use egg::*;
+/// let rules: Vec<Rewrite<SymbolLang, ()>> = parse_rules("
+///     ## arithmetic
+///     comm-add: (+ ?a ?b) => (+ ?b ?a)
+///     add-0: (+ ?a 0) <=> ?a
+///     div-self: (/ ?a ?a) => 1 if cond-equal (is-zero ?a) false
+/// ").unwrap();
+/// assert_eq!(rules.len(), 4);
+///
+/// let err = parse_rules::<SymbolLang, ()>("bad: (+ ?a ?b) => ?c").unwrap_err();
+/// assert_eq!((err.line, err.column), (1, 1));
+/// ```
+pub fn parse_rules<L, N>(src: &str) -> Result<Vec<Rewrite<L, N>>, RuleParseError>
+where
+    L: FromOp + Send + Sync + 'static,
+    N: Analysis<L>,
+{
+    let mut rules = vec![];
+    for (i, line) in src.lines().enumerate() {
+        let mut cursor = Cursor {
+            line,
+            line_no: i + 1,
+            pos: 0,
+        };
+        cursor.skip_whitespace();
+        if cursor.at_end() || cursor.rest().starts_with('#') {
+            continue;
+        }
+        cursor.parse_rule(&mut rules)?;
+    }
+    Ok(rules)
+}
+
+/// Write a set of [`Rewrite`]s in the format read by [`parse_rules`].
+///
+/// This fails with [`Error::UnwritableRule`] if a rule isn't made of
+/// [`Pattern`]s and [`ConditionEqual`]s, or if its name can't be written
+/// on one line before a `:`.
+///
+/// ```
+/// use egg::{rewrite as rw, *};
+/// let rules: Vec<Rewrite<SymbolLang, ()>> = vec![
+///     rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+///     rw!("div-self"; "(/ ?a ?a)" => "1" if ConditionEqual::parse("(is-zero ?a)", "false")),
+/// ];
+/// let text = format_rules(&rules).unwrap();
+/// assert_eq!(
+///     text,
+///     "comm-add: (+ ?a ?b) => (+ ?b ?a)\n\
+///      div-self: (/ ?a ?a) => 1 if cond-equal (is-zero ?a) false\n"
+/// );
+/// let reparsed: Vec<Rewrite<SymbolLang, ()>> = parse_rules(&text).unwrap();
+/// assert_eq!(format_rules(&reparsed).unwrap(), text);
+/// ```
+pub fn format_rules<L, N>(rules: &[Rewrite<L, N>]) -> Result<String, Error>
+where
+    L: Language + Display,
+    N: Analysis<L>,
+{
+    let mut out = String::new();
+    for rule in rules {
+        let unwritable = |reason: &'static str| Error::UnwritableRule {
+            rule: rule.name,
+            reason,
+        };
+        let name = rule.name.as_str();
+        if name.trim().is_empty() || name.trim() != name || name.contains(&[':', '\n'][..]) {
+            return Err(unwritable("its name isn't a valid rule name"));
+        }
+        let lhs = rule
+            .searcher
+            .get_pattern_ast()
+            .ok_or_else(|| unwritable("its searcher isn't a pattern"))?;
+        let rhs = rule
+            .applier
+            .get_pattern_ast()
+            .ok_or_else(|| unwritable("its applier isn't a pattern"))?;
+        let conditions = rule
+            .applier
+            .get_condition_asts()
+            .ok_or_else(|| unwritable("it has a condition that isn't a ConditionEqual"))?;
+
+        out += &format!("{}: {} => {}", name, lhs, rhs);
+        for (a, b) in conditions {
+            out += &format!(" if cond-equal {} {}", a, b);
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// All of several [`ConditionEqual`]s, for rules with more than one.
+struct ConditionsEqual<L>(Vec<ConditionEqual<L>>);
+
+impl<L: Language, N: Analysis<L>> Condition<L, N> for ConditionsEqual<L> {
+    fn check(&self, egraph: &dyn EGraphView<L, N>, eclass: Id, subst: &Subst) -> bool {
+        self.0.iter().all(|c| c.check(egraph, eclass, subst))
+    }
+
+    fn check_mut(
+        &self,
+        egraph: &mut EGraph<L, N>,
+        eclass: Id,
+        subst: &Subst,
+        _: Option<&L>,
+    ) -> bool {
+        self.0
+            .iter()
+            .all(|c| c.check_mut(egraph, eclass, subst, None))
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.0
+            .iter()
+            .flat_map(|c| Condition::<L, N>::vars(c))
+            .collect()
+    }
+
+    fn get_equality_asts(&self) -> Option<Vec<(&PatternAst<L>, &PatternAst<L>)>> {
+        let mut asts = vec![];
+        for c in &self.0 {
+            asts.extend(Condition::<L, N>::get_equality_asts(c)?);
+        }
+        Some(asts)
+    }
+}
+
+struct Cursor<'a> {
+    line: &'a str,
+    line_no: usize,
+    /// byte offset into `line`
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn rest(&self) -> &'a str {
+        &self.line[self.pos..]
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.line.len()
+    }
+
+    fn column(&self, pos: usize) -> usize {
+        self.line[..pos].chars().count() + 1
+    }
+
+    fn error(&self, pos: usize, message: impl Into<String>) -> RuleParseError {
+        RuleParseError {
+            line: self.line_no,
+            column: self.column(pos),
+            message: message.into(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Read the next s-expression, returning it with its byte offset.
+    fn sexp(&mut self, what: &str) -> Result<(usize, &'a str), RuleParseError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let rest = self.rest();
+        let len = if rest.starts_with('(') {
+            let mut depth = 0;
+            let mut end = None;
+            for (i, c) in rest.char_indices() {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => continue,
+                }
+                if depth == 0 {
+                    end = Some(i + 1);
+                    break;
+                }
+            }
+            end.ok_or_else(|| self.error(start, "unbalanced parentheses"))?
+        } else if rest.starts_with(')') {
+            return Err(self.error(start, "unexpected ')'"));
+        } else {
+            rest.find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                .unwrap_or_else(|| rest.len())
+        };
+
+        if len == 0 {
+            return Err(self.error(start, format!("expected {}", what)));
+        }
+        self.pos += len;
+        Ok((start, &rest[..len]))
+    }
+
+    fn keyword(&mut self, keywords: &[&str]) -> Result<(usize, &'a str), RuleParseError> {
+        let expected = keywords.join("' or '");
+        let (pos, word) = self.sexp(&format!("'{}'", expected))?;
+        if keywords.contains(&word) {
+            Ok((pos, word))
+        } else {
+            Err(self.error(pos, format!("expected '{}', found '{}'", expected, word)))
+        }
+    }
+
+    fn pattern<L: FromOp>(&mut self, what: &str) -> Result<Pattern<L>, RuleParseError> {
+        let (pos, s) = self.sexp(what)?;
+        s.parse()
+            .map_err(|e| self.error(pos, format!("bad {} {:?}: {:?}", what, s, e)))
+    }
+
+    fn parse_rule<L, N>(&mut self, rules: &mut Vec<Rewrite<L, N>>) -> Result<(), RuleParseError>
+    where
+        L: FromOp + Send + Sync + 'static,
+        N: Analysis<L>,
+    {
+        let start = self.pos;
+        let colon = self
+            .rest()
+            .find(':')
+            .ok_or_else(|| self.error(start, "expected 'name:' at the start of a rule"))?;
+        let name = self.rest()[..colon].trim();
+        if name.is_empty() {
+            return Err(self.error(start, "empty rule name"));
+        }
+        self.pos += colon + 1;
+
+        let lhs: Pattern<L> = self.pattern("lefthand side")?;
+        let (_, arrow) = self.keyword(&["=>", "<=>"])?;
+        let rhs: Pattern<L> = self.pattern("righthand side")?;
+
+        let mut conditions = vec![];
+        loop {
+            self.skip_whitespace();
+            if self.at_end() {
+                break;
+            }
+            self.keyword(&["if"])?;
+            self.keyword(&["cond-equal"])?;
+            let a = self.pattern("condition pattern")?;
+            let b = self.pattern("condition pattern")?;
+            conditions.push(ConditionEqual::new(a, b));
+        }
+
+        let make = |name: String, lhs: Pattern<L>, rhs: Pattern<L>| {
+            let rw = if conditions.is_empty() {
+                Rewrite::new(name, lhs, rhs)
+            } else {
+                let applier = ConditionalApplier {
+                    condition: ConditionsEqual(conditions.clone()),
+                    applier: rhs,
+                };
+                Rewrite::new(name, lhs, applier)
+            };
+            rw.map_err(|e| self.error(start, e))
+        };
+
+        if arrow == "<=>" {
+            let rev = format!("{}-rev", name);
+            rules.push(make(name.to_string(), lhs.clone(), rhs.clone())?);
+            rules.push(make(rev, rhs, lhs)?);
+        } else {
+            rules.push(make(name.to_string(), lhs, rhs)?);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde-1")]
+use ::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// The format version written by [`RuleStatsSnapshot::to_json`].
+pub const RULE_STATS_VERSION: u32 = 1;
+
+/// What a [`Runner`] learned about one rule, as part of a
+/// [`RuleStatsSnapshot`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde-1", derive(Serialize, Deserialize))]
+pub struct RuleStatsEntry {
+    /// The number of matches the rule's searcher found.
+    pub matches: usize,
+    /// The number of matches that changed the egraph when applied.
+    pub unions: usize,
+    /// Seconds spent searching for the rule.
+    pub search_time: f64,
+    /// Seconds spent applying the rule.
+    pub apply_time: f64,
+    /// The number of iterations in which the rule found a match.
+    pub iterations: usize,
+}
+
+impl RuleStatsEntry {
+    /// The number of effective unions per second spent on this rule.
+    ///
+    /// Rules that never took measurable time are ranked by their
+    /// number of unions alone.
+    pub fn productivity(&self) -> f64 {
+        let time = self.search_time + self.apply_time;
+        if time > 0.0 {
+            self.unions as f64 / time
+        } else {
+            self.unions as f64
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.matches += other.matches;
+        self.unions += other.unions;
+        self.search_time += other.search_time;
+        self.apply_time += other.apply_time;
+        self.iterations += other.iterations;
+    }
+}
+
+/// Per-rule statistics accumulated over one or more [`Runner`] runs.
+///
+/// Get one from [`Runner::export_rule_stats`], combine the snapshots of
+/// many runs with [`merge`](RuleStatsSnapshot::merge), and use the
+/// result to set up a [`PrioritizedScheduler`].
+///
+/// With the `reports` feature, snapshots can be saved and loaded as
+/// versioned JSON with [`to_json`](RuleStatsSnapshot::to_json) and
+/// [`from_json`](RuleStatsSnapshot::from_json).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde-1", derive(Serialize, Deserialize))]
+pub struct RuleStatsSnapshot {
+    /// The format version, [`RULE_STATS_VERSION`] for new snapshots.
+    pub version: u32,
+    /// The number of runs merged into this snapshot.
+    pub runs: usize,
+    /// The statistics of each rule, by rule name.
+    pub rules: BTreeMap<String, RuleStatsEntry>,
+}
+
+impl Default for RuleStatsSnapshot {
+    fn default() -> Self {
+        Self {
+            version: RULE_STATS_VERSION,
+            runs: 0,
+            rules: Default::default(),
+        }
+    }
+}
+
+impl RuleStatsSnapshot {
+    /// Adds the statistics of `other` to this snapshot.
+    ///
+    /// Rules only in `other` are added, and the statistics of rules in
+    /// both are summed.
+    pub fn merge(&mut self, other: &Self) {
+        self.runs += other.runs;
+        for (name, entry) in &other.rules {
+            self.rules.entry(name.clone()).or_default().merge(entry);
+        }
+    }
+
+    /// Returns the statistics of the rule `name`, if it was ever run.
+    pub fn get(&self, name: &str) -> Option<&RuleStatsEntry> {
+        self.rules.get(name)
+    }
+
+    pub(crate) fn entry(&mut self, name: Symbol) -> &mut RuleStatsEntry {
+        self.rules.entry(name.to_string()).or_default()
+    }
+}
+
+/// An error from [`RuleStatsSnapshot::from_json`].
+#[cfg(feature = "reports")]
+#[derive(Debug, thiserror::Error)]
+pub enum RuleStatsError {
+    /// The input isn't a valid snapshot.
+    #[error("invalid rule stats: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The snapshot was written in a format this version can't read.
+    #[error("unsupported rule stats version {0}, expected {}", RULE_STATS_VERSION)]
+    Version(u32),
+}
+
+#[cfg(feature = "reports")]
+impl RuleStatsSnapshot {
+    /// Serializes this snapshot to JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Deserializes a snapshot written by
+    /// [`to_json`](RuleStatsSnapshot::to_json), checking its version.
+    pub fn from_json(json: &str) -> Result<Self, RuleStatsError> {
+        let snapshot: Self = serde_json::from_str(json)?;
+        if snapshot.version != RULE_STATS_VERSION {
+            return Err(RuleStatsError::Version(snapshot.version));
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn snapshot(rules: &[(&str, usize, usize, f64)]) -> RuleStatsSnapshot {
+        let mut snapshot = RuleStatsSnapshot {
+            runs: 1,
+            ..Default::default()
+        };
+        for &(name, matches, unions, time) in rules {
+            let entry = RuleStatsEntry {
+                matches,
+                unions,
+                search_time: time,
+                apply_time: time,
+                iterations: 1,
+            };
+            snapshot.rules.insert(name.to_string(), entry);
+        }
+        snapshot
+    }
+
+    #[test]
+    fn merged_stats_order_scheduler() {
+        let mut stats = snapshot(&[("comm", 100, 10, 1.0), ("assoc", 500, 0, 2.0)]);
+        stats.merge(&snapshot(&[
+            ("comm", 100, 10, 1.0),
+            ("zero", 10, 40, 0.5),
+            ("assoc", 500, 0, 2.0),
+        ]));
+        assert_eq!(stats.runs, 2);
+        let comm = stats.get("comm").unwrap();
+        assert_eq!((comm.matches, comm.unions, comm.iterations), (200, 20, 2));
+        assert_eq!(comm.search_time + comm.apply_time, 4.0);
+
+        let rules: Vec<Rewrite<SymbolLang, ()>> = vec![
+            rewrite!("assoc"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+            rewrite!("new"; "(* ?a 1)" => "?a"),
+            rewrite!("comm"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+            rewrite!("zero"; "(+ ?a 0)" => "?a"),
+        ];
+        let mut rules: Vec<&Rewrite<SymbolLang, ()>> = rules.iter().collect();
+        let mut scheduler = PrioritizedScheduler::from_stats(&stats);
+        RewriteScheduler::<SymbolLang, ()>::prioritize(&mut scheduler, &mut rules);
+        let names: Vec<&str> = rules.iter().map(|rw| rw.name.as_str()).collect();
+        assert_eq!(names, vec!["new", "zero", "comm", "assoc"]);
+
+        assert_eq!(scheduler.match_limit("assoc".into()), 100);
+        assert_eq!(scheduler.match_limit("comm".into()), 1_000);
+        assert_eq!(scheduler.match_limit("new".into()), 1_000);
+    }
+
+    #[cfg(feature = "reports")]
+    #[test]
+    fn json_round_trip() {
+        let stats = snapshot(&[("comm", 100, 10, 1.0)]);
+        let json = stats.to_json();
+        assert_eq!(RuleStatsSnapshot::from_json(&json).unwrap(), stats);
+
+        let future = json.replace("\"version\": 1", "\"version\": 2");
+        assert!(matches!(
+            RuleStatsSnapshot::from_json(&future),
+            Err(RuleStatsError::Version(2))
+        ));
+    }
+}
@@ -1,4 +1,5 @@
-use std::fmt::{self, Debug, Formatter};
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Display, Formatter};
 
 use log::*;
 
@@ -45,9 +46,12 @@ Here are some of the things [`Runner`] does for you:
 
 - [`EGraph`] size limit
 
-  You can set a upper limit on the number of enodes in the egraph.
-  If this limit is hit, it stops with
-  [`StopReason::NodeLimit`].
+  You can set a upper limit on the number of enodes in the egraph,
+  and on the number of eclasses.
+  The limits are checked after each rule is applied, so one explosive
+  rule can't overshoot them by much.
+  If a limit is hit, it stops with
+  [`StopReason::NodeLimit`] or [`StopReason::ClassLimit`].
 
 - Time limit
 
@@ -55,6 +59,20 @@ Here are some of the things [`Runner`] does for you:
   If this limit is hit, it stops with
   [`StopReason::TimeLimit`].
 
+- Goals
+
+  You can give the runner goals, like the other side of an equation
+  you're trying to prove.
+  Once they're all proven, it stops with [`StopReason::GoalsReached`]
+  (see [`Runner::with_goals`]).
+
+- Convergence
+
+  You can have the runner stop once the egraph has stopped changing
+  much, over a window of iterations, before it saturates.
+  It then stops with [`StopReason::Converged`]
+  (see [`Runner::with_convergence`]).
+
 - Rule scheduling
 
   Some rules enable themselves, blowing up the [`EGraph`] and
@@ -104,9 +122,9 @@ type MyRunner = Runner<SimpleLanguage, (), MyIterData>;
 impl IterationData<SimpleLanguage, ()> for MyIterData {
     fn make(runner: &MyRunner) -> Self {
         let root = runner.roots[0];
-        let mut extractor = Extractor::new(&runner.egraph, AstSize);
+        let extractor = Extractor::new(&runner.egraph, AstSize);
         MyIterData {
-            smallest_so_far: extractor.find_best(root).0,
+            smallest_so_far: extractor.find_best_cost(root),
         }
     }
 }
@@ -142,6 +160,9 @@ pub struct Runner<L: Language, N: Analysis<L>, IterData = ()> {
     /// The roots of expressions added by the
     /// [`with_expr`](Runner::with_expr()) method, in insertion order.
     pub roots: Vec<Id>,
+    /// The goals added by the [`with_goals`](Runner::with_goals())
+    /// method.
+    pub goals: Vec<Goal<L>>,
     /// Why the `Runner` stopped. This will be `None` if it hasn't
     /// stopped yet.
     pub stop_reason: Option<StopReason>,
@@ -151,13 +172,82 @@ pub struct Runner<L: Language, N: Analysis<L>, IterData = ()> {
     #[allow(clippy::type_complexity)]
     pub hooks: Vec<Box<dyn FnMut(&mut Self) -> Result<(), String>>>,
 
+    /// The snapshots recorded after each iteration, if enabled with
+    /// [`with_history`](Runner::with_history()).
+    pub history: Option<HistoryRecorder<L, N>>,
+
+    /// The rules that the last [`run`](Runner::run) skipped because they
+    /// can never match (see [`Rewrite::is_applicable_to`]), in the order
+    /// they were given.
+    pub skipped_rules: Vec<Symbol>,
+
+    /// The cheapest terms of the roots after each iteration, if tracked
+    /// with [`with_best_term_tracking`](Runner::with_best_term_tracking);
+    /// see [`Iteration::best_terms`].
+    pub best_terms: ExprArena<L>,
+
     // limits
     iter_limit: usize,
     node_limit: usize,
+    class_limit: usize,
     time_limit: Duration,
+    /// The window and threshold to stop at when converged, if set.
+    convergence: Option<(usize, usize)>,
 
     start_time: Option<Instant>,
+    /// Measures the time since `start_time`, replaced in tests so the
+    /// time limit doesn't depend on the wall clock.
+    clock: fn(&Instant) -> Duration,
     scheduler: Box<dyn RewriteScheduler<L, N>>,
+    rule_stats: RuleStatsSnapshot,
+    apply_dedup: bool,
+    skip_redundant: bool,
+    /// The batch size to search and apply each rule in one pass with,
+    /// if enabled.
+    fused_apply: Option<usize>,
+    /// The most steps each iteration's searches may take, if limited.
+    search_step_limit: Option<usize>,
+    /// Whether to profile where the searches backtrack.
+    backtrack_profiling: bool,
+    /// How to search each rule in parallel, if enabled.
+    parallel_search: Option<crate::rewrite::ParallelSearch<L, N>>,
+    /// The lemmas to seed the egraph with, as rewrites.
+    lemmas: Vec<Rewrite<L, N>>,
+    /// Seed lemmas before every iteration that's a multiple of this,
+    /// not just the first.
+    lemma_interval: Option<usize>,
+    /// The most passes over the lemmas per seeding.
+    lemma_pass_limit: usize,
+    /// Where the matches of each iteration are stored.
+    arena: MatchArena,
+    /// Starts a checkpoint of the egraph to roll back to, if panics are
+    /// isolated; a function pointer so that running doesn't need the
+    /// bounds that checkpoints do.
+    checkpoint: Option<fn(&mut EGraph<L, N>)>,
+    #[allow(clippy::type_complexity)]
+    synthesizer:
+        Option<Box<dyn FnMut(&dyn EGraphView<L, N>, &[Rewrite<L, N>]) -> Vec<Rewrite<L, N>>>>,
+    /// Extracts the cheapest term of a root into an arena, if tracked.
+    best_term_extractor: Option<Box<dyn BestTermExtractor<L, N>>>,
+}
+
+/// A cost function to track the cheapest terms of the roots with, see
+/// [`Runner::with_best_term_tracking`].
+trait BestTermExtractor<L: Language, N: Analysis<L>> {
+    /// Add the cheapest term of `root` to `arena`, returning its id there.
+    fn extract(&self, egraph: &EGraph<L, N>, arena: &mut ExprArena<L>, root: Id) -> Id;
+}
+
+impl<L, N, CF> BestTermExtractor<L, N> for CF
+where
+    L: Language,
+    N: Analysis<L>,
+    CF: CostFunction<L> + Clone,
+{
+    fn extract(&self, egraph: &EGraph<L, N>, arena: &mut ExprArena<L>, root: Id) -> Id {
+        let extractor = Extractor::new(egraph, self.clone());
+        extractor.find_best_into(arena, root).1
+    }
 }
 
 impl<L, N> Default for Runner<L, N, ()>
@@ -182,34 +272,80 @@ where
             egraph,
             iterations,
             roots,
+            goals,
             stop_reason,
             hooks,
+            history,
+            skipped_rules,
+            best_terms,
             iter_limit,
             node_limit,
+            class_limit,
             time_limit,
+            convergence,
             start_time,
+            clock: _,
             scheduler: _,
+            rule_stats,
+            apply_dedup,
+            skip_redundant,
+            fused_apply,
+            search_step_limit,
+            backtrack_profiling,
+            parallel_search,
+            lemmas,
+            lemma_interval,
+            lemma_pass_limit,
+            arena: _,
+            checkpoint,
+            synthesizer,
+            best_term_extractor,
         } = self;
 
         f.debug_struct("Runner")
             .field("egraph", egraph)
             .field("iterations", iterations)
             .field("roots", roots)
+            .field("goals", goals)
             .field("stop_reason", stop_reason)
             .field("hooks", &vec![format_args!("<dyn FnMut ..>"); hooks.len()])
+            .field("history", history)
+            .field("skipped_rules", skipped_rules)
+            .field("best_terms", best_terms)
             .field("iter_limit", iter_limit)
             .field("node_limit", node_limit)
+            .field("class_limit", class_limit)
             .field("time_limit", time_limit)
+            .field("convergence", convergence)
             .field("start_time", start_time)
             .field("scheduler", &format_args!("<dyn RewriteScheduler ..>"))
+            .field("rule_stats", rule_stats)
+            .field("apply_dedup", apply_dedup)
+            .field("skip_redundant", skip_redundant)
+            .field("fused_apply", fused_apply)
+            .field("search_step_limit", search_step_limit)
+            .field("backtrack_profiling", backtrack_profiling)
+            .field("parallel_search", &parallel_search.is_some())
+            .field("lemmas", lemmas)
+            .field("lemma_interval", lemma_interval)
+            .field("lemma_pass_limit", lemma_pass_limit)
+            .field("panic_isolation", &checkpoint.is_some())
+            .field(
+                "synthesizer",
+                &synthesizer.as_ref().map(|_| format_args!("<dyn FnMut ..>")),
+            )
+            .field("best_term_tracking", &best_term_extractor.is_some())
             .finish()
     }
 }
 
 /// Error returned by [`Runner`] when it stops.
 ///
+/// More reasons may be added in minor releases, so matches on it need a
+/// wildcard arm.
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde-1", derive(serde::Serialize))]
+#[non_exhaustive]
 pub enum StopReason {
     /// The egraph saturated, i.e., there was an iteration where we
     /// didn't learn anything new from applying the rules.
@@ -218,10 +354,195 @@ pub enum StopReason {
     IterationLimit(usize),
     /// The enode limit was hit. The data is the enode limit.
     NodeLimit(usize),
+    /// The eclass limit was hit. The data is the number of eclasses.
+    ClassLimit(usize),
     /// The time limit was hit. The data is the time limit in seconds.
     TimeLimit(f64),
+    /// The egraph changed by less than the threshold over the last
+    /// iterations (see [`Runner::with_convergence`]).
+    Converged {
+        /// The number of iterations looked at.
+        window: usize,
+        /// Their total [change](Iteration::change).
+        change: usize,
+    },
+    /// All the [goals](Runner::with_goals) were reached.
+    GoalsReached,
     /// Some other reason to stop.
     Other(String),
+    /// A rule, hook or analysis panicked while the runner had
+    /// [panic isolation](Runner::with_panic_isolation) enabled.
+    /// The egraph was rolled back to the start of the iteration.
+    Panicked {
+        /// The rule being searched or applied, if any.
+        rule: Option<Symbol>,
+        /// What the runner was doing.
+        phase: RunPhase,
+        /// The panic message, if it was a string.
+        message: String,
+    },
+}
+
+/// A part of a [`Runner`] iteration, as reported by
+/// [`StopReason::Panicked`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize))]
+pub enum RunPhase {
+    /// Running the [hooks](Runner::with_hook).
+    Hooks,
+    /// Seeding the [lemmas](Runner::with_lemma).
+    Lemmas,
+    /// Searching for a rule's matches.
+    Search,
+    /// Applying a rule's matches.
+    Apply,
+    /// [Rebuilding](EGraph::rebuild) the egraph.
+    Rebuild,
+}
+
+/// Something a [`Runner`] tries to reach from one of its
+/// [roots](Runner::roots) (see [`Runner::with_goals`]).
+#[derive(Debug, Clone)]
+pub enum Goal<L> {
+    /// The expression is in the root's eclass.
+    Expr(RecExpr<L>),
+    /// The pattern matches in the root's eclass.
+    Pattern(Pattern<L>),
+}
+
+impl<L: Language> Goal<L> {
+    /// Whether this goal is reached in eclass `root` of `egraph`.
+    pub fn is_reached<N: Analysis<L>>(&self, egraph: &EGraph<L, N>, root: Id) -> bool {
+        match self {
+            Goal::Expr(expr) => egraph.lookup_expr(expr) == Some(egraph.find(root)),
+            Goal::Pattern(pattern) => pattern.search_eclass_with_limit(egraph, root, 1).is_some(),
+        }
+    }
+}
+
+impl<L> From<RecExpr<L>> for Goal<L> {
+    fn from(expr: RecExpr<L>) -> Self {
+        Goal::Expr(expr)
+    }
+}
+
+impl<L> From<Pattern<L>> for Goal<L> {
+    fn from(pattern: Pattern<L>) -> Self {
+        Goal::Pattern(pattern)
+    }
+}
+
+/// Run `f`, turning a panic into [`StopReason::Panicked`] if `isolate`.
+fn isolate_panic<T>(
+    isolate: bool,
+    rule: Option<Symbol>,
+    phase: RunPhase,
+    f: impl FnOnce() -> T,
+) -> RunnerResult<T> {
+    if !isolate {
+        return Ok(f());
+    }
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|payload| {
+        let message = if let Some(s) = payload.downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = payload.downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "<non-string panic payload>".to_string()
+        };
+        warn!("Caught panic in {:?} of {:?}: {}", phase, rule, message);
+        StopReason::Panicked {
+            rule,
+            phase,
+            message,
+        }
+    })
+}
+
+/// The number of rule and eclass pairs kept in [`Iteration::top_fanout`].
+const TOP_FANOUT: usize = 10;
+
+/// Add the matches of `rule` in each eclass to `top_fanout`, keeping
+/// the [`TOP_FANOUT`] largest.
+fn record_fanout<L: Language>(
+    top_fanout: &mut Vec<(Symbol, Id, usize)>,
+    rule: Symbol,
+    matches: &[ArenaMatches<L>],
+) {
+    let mut counts: Vec<(Id, usize)> = vec![];
+    for m in matches {
+        match counts.last_mut() {
+            // matches with different schemas can split an eclass in two
+            Some((id, count)) if *id == m.eclass => *count += m.substs.len(),
+            _ => counts.push((m.eclass, m.substs.len())),
+        }
+    }
+    for (id, count) in counts {
+        let pos = top_fanout.iter().position(|&(_, _, n)| n < count);
+        match pos {
+            Some(pos) => top_fanout.insert(pos, (rule, id, count)),
+            None => top_fanout.push((rule, id, count)),
+        }
+        top_fanout.truncate(TOP_FANOUT);
+    }
+}
+
+/// The number of binders kept in [`Report::top_wasteful_binders`].
+const TOP_BINDERS: usize = 10;
+
+/// A subterm of a rule's pattern that the search bound enodes for, and
+/// how often it backtracked from them, from
+/// [backtrack profiling](Runner::with_backtrack_profiling).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize))]
+pub struct WastefulBinder {
+    /// The rule whose pattern it's in.
+    pub rule: Symbol,
+    /// The subterm, like `(+ ?a ?b)`.
+    pub subterm: String,
+    /// The index of the subterm's root in the rule's
+    /// [pattern](Searcher::get_pattern_ast).
+    pub position: Id,
+    /// The number of enodes bound for the subterm.
+    pub candidates: usize,
+    /// The number of those the search backtracked from without finding
+    /// a match.
+    pub backtracks: usize,
+}
+
+/// Add the profile of `rule`'s search to `binders`, naming each subterm
+/// of its pattern `ast`.
+fn record_binders<L: Language>(
+    binders: &mut Vec<WastefulBinder>,
+    rule: Symbol,
+    ast: Option<&PatternAst<L>>,
+    profile: HashMap<Id, crate::machine::BindProfile>,
+) {
+    let ast = match ast {
+        Some(ast) => ast,
+        None => return,
+    };
+    for (position, p) in profile {
+        if p.backtracks > 0 {
+            binders.push(WastefulBinder {
+                rule,
+                subterm: ast.extract(position).to_string(),
+                position,
+                candidates: p.candidates,
+                backtracks: p.backtracks,
+            });
+        }
+    }
+}
+
+/// Sort `binders` by backtracks, most first.
+fn sort_binders(binders: &mut [WastefulBinder]) {
+    binders.sort_by(|a, b| {
+        let key = |b: &WastefulBinder| (b.rule.as_str(), b.position);
+        b.backtracks
+            .cmp(&a.backtracks)
+            .then_with(|| key(a).cmp(&key(b)))
+    });
 }
 
 /// A report containing data about an entire [`Runner`] run.
@@ -245,9 +566,32 @@ pub struct Report {
     pub search_time: f64,
     pub apply_time: f64,
     pub rebuild_time: f64,
+    /// The number of unions the rules made that were skipped because an
+    /// eclass was [frozen](EGraph::freeze).
+    pub frozen_skips: usize,
+    /// The number of times [lemmas](Runner::with_lemma) were seeded.
+    pub lemma_seedings: usize,
+    /// The number of unions seeding lemmas made, not counting the rules'.
+    pub lemma_unions: usize,
+    /// Seconds spent seeding lemmas.
+    pub lemma_time: f64,
+    /// The operators with the most enodes in the final egraph, most
+    /// first (see [`EGraph::op_stats`]).
+    pub top_ops: Vec<(String, OpStats)>,
+    /// The number of enodes in [`Runner::best_terms`], which the best
+    /// terms of every iteration share.
+    pub best_term_nodes: usize,
+    /// The (at most 10) subterms of the rules' patterns the searches
+    /// backtracked from most, summed over the iterations, most first.
+    /// Only recorded with [backtrack
+    /// profiling](Runner::with_backtrack_profiling).
+    pub top_wasteful_binders: Vec<WastefulBinder>,
+    /// A term of each root's eclass, cut short within the default
+    /// [`TermBudget`] (see [`EGraph::class_term`]).
+    pub root_terms: Vec<String>,
 }
 
-impl std::fmt::Display for Report {
+impl Display for Report {
     #[rustfmt::skip]
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         writeln!(f, "Runner report")?;
@@ -255,11 +599,38 @@ impl std::fmt::Display for Report {
         writeln!(f, "  Stop reason: {:?}", self.stop_reason)?;
         writeln!(f, "  Iterations: {}", self.iterations)?;
         writeln!(f, "  Egraph size: {} nodes, {} classes, {} memo", self.egraph_nodes, self.egraph_classes, self.memo_size)?;
+        if !self.root_terms.is_empty() {
+            writeln!(f, "  Roots:")?;
+            for term in &self.root_terms {
+                writeln!(f, "    {}", term)?;
+            }
+        }
         writeln!(f, "  Rebuilds: {}", self.rebuilds)?;
+        if self.frozen_skips > 0 {
+            writeln!(f, "  Unions skipped by frozen classes: {}", self.frozen_skips)?;
+        }
+        if self.lemma_seedings > 0 {
+            writeln!(f, "  Lemma seeding: {} times, {} unions, {} seconds", self.lemma_seedings, self.lemma_unions, self.lemma_time)?;
+        }
         writeln!(f, "  Total time: {}", self.total_time)?;
         writeln!(f, "    Search:  ({:.2}) {}", self.search_time / self.total_time, self.search_time)?;
         writeln!(f, "    Apply:   ({:.2}) {}", self.apply_time / self.total_time, self.apply_time)?;
         writeln!(f, "    Rebuild: ({:.2}) {}", self.rebuild_time / self.total_time, self.rebuild_time)?;
+        if self.best_term_nodes > 0 {
+            writeln!(f, "  Best terms: {} nodes", self.best_term_nodes)?;
+        }
+        if !self.top_ops.is_empty() {
+            writeln!(f, "  Top operators:")?;
+            for (op, stats) in &self.top_ops {
+                writeln!(f, "    {:<12} {} nodes, {} classes", op, stats.nodes, stats.classes)?;
+            }
+        }
+        if !self.top_wasteful_binders.is_empty() {
+            writeln!(f, "  Top wasteful binders:")?;
+            for b in &self.top_wasteful_binders {
+                writeln!(f, "    {} (node {}) in {}: {} of {} candidates backtracked", b.subterm, b.position, b.rule, b.backtracks, b.candidates)?;
+            }
+        }
         Ok(())
     }
 }
@@ -281,9 +652,79 @@ pub struct Iteration<IterData> {
     /// The number of eclasses in the egraph at the start of this
     /// iteration.
     pub egraph_classes: usize,
+    /// The number of enodes in the egraph after this iteration's
+    /// [`rebuild`](EGraph::rebuild()).
+    pub rebuilt_nodes: usize,
+    /// The number of eclasses in the egraph after this iteration's
+    /// [`rebuild`](EGraph::rebuild()).
+    pub rebuilt_classes: usize,
+    /// The number of unions that merged two different eclasses in this
+    /// iteration, by the hooks and rules and by the
+    /// [`rebuild`](EGraph::rebuild()).
+    pub unions: usize,
+    /// The number of enodes added to the egraph in this iteration,
+    /// including any the [`rebuild`](EGraph::rebuild()) then found to
+    /// be duplicates.
+    pub new_nodes: usize,
+    /// The number of eclasses whose enodes changed in this iteration,
+    /// as counted by [`RebuildReport::changed_classes`].
+    pub changed_classes: usize,
+    /// A map from rule name to number of matches it found in this
+    /// iteration, counted like [`RuleStatsEntry::matches`].
+    /// Rules that didn't match aren't in the map.
+    pub matched: IndexMap<Symbol, usize>,
     /// A map from rule name to number of times it was _newly_ applied
     /// in this iteration.
     pub applied: IndexMap<Symbol, usize>,
+    /// A map from rule name to number of matches its applier skipped
+    /// in this iteration (see [`Applier::take_skipped`]).
+    pub skipped: IndexMap<Symbol, usize>,
+    /// A map from rule name to number of unions skipped in this
+    /// iteration because an eclass was [frozen](EGraph::freeze).
+    pub frozen_skipped: IndexMap<Symbol, usize>,
+    /// A map from rule name to number of matches skipped in this
+    /// iteration because an identical application already happened
+    /// (see [`Runner::with_apply_dedup`]).
+    pub deduped: IndexMap<Symbol, usize>,
+    /// A map from rule name to number of matches skipped in this
+    /// iteration because they were already true in the egraph
+    /// (see [`Runner::with_redundant_match_skipping`]).
+    pub redundant: IndexMap<Symbol, usize>,
+    /// The names of the rules added after this iteration by the
+    /// [rule synthesizer](Runner::with_rule_synthesizer).
+    pub synthesized: Vec<Symbol>,
+    /// What seeding the [lemmas](Runner::with_lemma) at the start of
+    /// this iteration did, if they were seeded.
+    pub lemma_seeding: Option<LemmaSeeding>,
+    /// The (at most 10) rules and eclasses with the most matches in
+    /// this iteration, most first, with their number of matches.
+    /// The numbers count matches after any
+    /// [per-eclass cap](Rewrite::with_max_matches_per_class), and
+    /// matches the scheduler dropped aren't counted.
+    /// This is empty for [fused](Runner::with_fused_apply) runners.
+    pub top_fanout: Vec<(Symbol, Id, usize)>,
+    /// The rules whose search in this iteration was cut short, or
+    /// skipped, because the [search step
+    /// limit](Runner::with_search_step_limit) ran out, so they may have
+    /// missed matches.
+    pub search_truncated: Vec<Symbol>,
+    /// The subterms of the rules' patterns the searches in this
+    /// iteration backtracked from, most backtracks first.
+    /// Only recorded with [backtrack
+    /// profiling](Runner::with_backtrack_profiling), and not for
+    /// [fused](Runner::with_fused_apply) runners.
+    pub wasteful_binders: Vec<WastefulBinder>,
+    /// The change in [`EGraph::op_stats`] over this iteration, for the
+    /// operators that changed.
+    /// Only recorded when the runner keeps a
+    /// [history](Runner::with_history), otherwise this is empty.
+    pub op_deltas: BTreeMap<String, OpDelta>,
+    /// The ids in [`Runner::best_terms`] of the cheapest term of each
+    /// root after this iteration, in the order of [`Runner::roots`].
+    /// Only recorded with [best term
+    /// tracking](Runner::with_best_term_tracking), otherwise this is
+    /// empty.
+    pub best_terms: Vec<Id>,
     /// Seconds spent running hooks.
     pub hook_time: f64,
     /// Seconds spent searching in this iteration.
@@ -299,10 +740,42 @@ pub struct Iteration<IterData> {
     pub data: IterData,
     /// The number of rebuild iterations done after this iteration completed.
     pub n_rebuilds: usize,
+    /// What the [`rebuild`](EGraph::rebuild()) after this iteration did.
+    pub rebuild: RebuildReport,
     /// If the runner stopped on this iterations, this is the reason
     pub stop_reason: Option<StopReason>,
 }
 
+impl<IterData> Iteration<IterData> {
+    /// How much this iteration changed the egraph: the number of
+    /// [`unions`](Iteration::unions) plus the number of
+    /// [`new_nodes`](Iteration::new_nodes).
+    ///
+    /// This is what [`Runner::with_convergence`] measures.
+    pub fn change(&self) -> usize {
+        self.unions + self.new_nodes
+    }
+}
+
+/// What seeding the [lemmas](Runner::with_lemma) of a [`Runner`] did
+/// before an [`Iteration`].
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct LemmaSeeding {
+    /// The number of passes over the lemmas.
+    pub passes: usize,
+    /// Whether the last pass made no unions, so the lemmas reached
+    /// their fixpoint. Otherwise the
+    /// [pass limit](Runner::with_lemma_pass_limit) or the node limit
+    /// cut the seeding short.
+    pub saturated: bool,
+    /// A map from lemma name to the number of unions it made.
+    pub applied: IndexMap<Symbol, usize>,
+    /// Seconds spent seeding.
+    pub time: f64,
+}
+
 type RunnerResult<T> = std::result::Result<T, StopReason>;
 
 impl<L, N, IterData> Runner<L, N, IterData>
@@ -316,16 +789,306 @@ where
         Self {
             iter_limit: 30,
             node_limit: 10_000,
+            class_limit: usize::MAX,
             time_limit: Duration::from_secs(5),
+            convergence: None,
 
             egraph: EGraph::new(analysis),
             roots: vec![],
+            goals: vec![],
             iterations: vec![],
             stop_reason: None,
             hooks: vec![],
+            history: None,
+            skipped_rules: vec![],
+            best_terms: ExprArena::new(),
 
             start_time: None,
+            clock: Instant::elapsed,
             scheduler: Box::new(BackoffScheduler::default()),
+            rule_stats: RuleStatsSnapshot::default(),
+            apply_dedup: false,
+            skip_redundant: false,
+            fused_apply: None,
+            search_step_limit: None,
+            backtrack_profiling: false,
+            parallel_search: None,
+            lemmas: vec![],
+            lemma_interval: None,
+            lemma_pass_limit: 10,
+            arena: MatchArena::default(),
+            checkpoint: None,
+            synthesizer: None,
+            best_term_extractor: None,
+        }
+    }
+
+    /// Enable or disable deduplicating applications within each
+    /// iteration's apply phase.
+    ///
+    /// When enabled, a [`Pattern`] applier skips a match if the same
+    /// term was already instantiated and unioned into the same eclass
+    /// earlier in the apply phase, for example by an overlapping rule.
+    /// Skipped matches are counted in [`Iteration::deduped`].
+    /// Only terms that are already in the egraph are checked, by
+    /// looking them up, so applications that add something new always
+    /// go ahead.
+    /// The resulting egraph is the same either way.
+    /// Deduplication is skipped when explanations are enabled.
+    /// This is off by default.
+    pub fn with_apply_dedup(self, apply_dedup: bool) -> Self {
+        Self {
+            apply_dedup,
+            ..self
+        }
+    }
+
+    /// Enable or disable catching panics from rules, hooks and the
+    /// analysis.
+    ///
+    /// When enabled, a panic while running a [hook](Runner::with_hook),
+    /// searching or applying a rule, or rebuilding stops the runner
+    /// with [`StopReason::Panicked`], which says which rule and
+    /// [`RunPhase`] it came from.
+    /// The egraph is then rolled back to how it was at the start of the
+    /// iteration, so it is consistent and can still be inspected and
+    /// extracted from.
+    /// For that, the egraph keeps a copy of each eclass and hashcons
+    /// entry just before it first changes in an iteration, and of the
+    /// union-find entries it overwrites, so isolation costs memory in
+    /// the size of an iteration's changes.
+    /// The analysis, frozen eclasses, sort checking state and
+    /// [annotation](EGraph::annotate) layers are copied whole at the
+    /// start of every iteration, as are explanations, if enabled.
+    ///
+    /// Scheduler state and [rule statistics](Runner::export_rule_stats)
+    /// are not rolled back.
+    /// This is off by default.
+    pub fn with_panic_isolation(mut self, enabled: bool) -> Self
+    where
+        N: Clone,
+        N::Data: Clone,
+    {
+        self.checkpoint = if enabled {
+            Some(EGraph::checkpoint)
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Enable or disable skipping matches that can't teach the egraph
+    /// anything.
+    ///
+    /// When enabled, a [`Pattern`] applier first looks up its
+    /// instantiation without adding anything, and skips the match if
+    /// the whole term is already in the matched eclass.
+    /// Instantiations that are only partly in the egraph are added as
+    /// usual.
+    /// Skipped matches are counted in [`Iteration::redundant`].
+    /// This saves adding (and hashing) the same enodes over and over in
+    /// the late iterations of a saturating run, without changing the
+    /// resulting egraph.
+    /// Skipping is disabled when explanations are enabled.
+    /// This is off by default.
+    pub fn with_redundant_match_skipping(self, skip_redundant: bool) -> Self {
+        Self {
+            skip_redundant,
+            ..self
+        }
+    }
+
+    /// Enable or disable searching and applying each rule in one pass.
+    ///
+    /// By default, every rule is searched before any is applied, so all
+    /// of an iteration's matches are held at once.
+    /// When enabled, each rule is searched and applied in turn by
+    /// [`RewriteScheduler::search_apply_rewrite`], which by default uses
+    /// [`Rewrite::search_apply`] to apply the matches in batches of
+    /// 10,000 (see
+    /// [`with_fused_batch_size`](Runner::with_fused_batch_size)), so
+    /// only about one batch is held at once.
+    /// That's for runs whose matches don't fit in memory; otherwise
+    /// it's slower, since the egraph is rebuilt after every batch.
+    ///
+    /// Rules (and later batches of the same rule) then see the enodes
+    /// and unions of earlier ones in the same iteration, so the results
+    /// depend on the rule order, and each iteration may get further
+    /// than a two-phase one.
+    /// Matches are re-canonicalized before they are applied.
+    /// Iterations still record the matches and applications of each
+    /// rule, but their search time is only the time it took to
+    /// [prioritize](RewriteScheduler::prioritize) the rules; searching
+    /// counts as applying.
+    /// This is off by default.
+    pub fn with_fused_apply(mut self, enabled: bool) -> Self {
+        self.fused_apply = if enabled {
+            Some(self.fused_apply.unwrap_or(10_000))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Enable [fused search and apply](Runner::with_fused_apply),
+    /// applying the matches in batches of `batch_size`.
+    pub fn with_fused_batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "Fused batch size must be positive");
+        self.fused_apply = Some(batch_size);
+        self
+    }
+
+    /// Limit the searches of each iteration to `steps` steps between
+    /// them, so a pattern with exponentially many matches can't stall
+    /// the runner.
+    ///
+    /// The steps are counted by the runner's [`MatchArena`], see
+    /// [`MatchArena::set_step_budget`].
+    /// Once they run out, the rest of the iteration's searches stop
+    /// early, or find nothing, and the rules they were for are recorded
+    /// in [`Iteration::search_truncated`]; the rules are searched in
+    /// the order the scheduler
+    /// [prioritizes](RewriteScheduler::prioritize) them.
+    /// A runner whose searches were cut short doesn't report
+    /// saturation.
+    /// The limit doesn't apply to [fused](Runner::with_fused_apply)
+    /// runners.
+    /// Default: no limit
+    pub fn with_search_step_limit(mut self, steps: usize) -> Self {
+        self.search_step_limit = Some(steps);
+        self
+    }
+
+    /// Enable or disable profiling where the searches backtrack.
+    ///
+    /// Each enode a [`Pattern`]'s search binds for a subterm, and then
+    /// finds no match under, is a backtrack charged to that subterm.
+    /// A subterm with many backtracks for its candidates is where the
+    /// pattern wastes its time, and might be better written another
+    /// way, or guarded by a more selective subterm.
+    /// Each iteration's are recorded in [`Iteration::wasteful_binders`],
+    /// and the worst over the run in [`Report::top_wasteful_binders`].
+    /// Only the [`Pattern`] searches into the runner's [`MatchArena`]
+    /// are profiled, and patterns simple enough not to need the
+    /// matching machine never backtrack.
+    /// This is off by default.
+    pub fn with_backtrack_profiling(self, backtrack_profiling: bool) -> Self {
+        Self {
+            backtrack_profiling,
+            ..self
+        }
+    }
+
+    /// Track the cheapest term of each root, by `cost_function`, after
+    /// every iteration.
+    ///
+    /// The terms are extracted into one [`ExprArena`],
+    /// [`best_terms`](Runner::best_terms), and each iteration records
+    /// their ids there in [`Iteration::best_terms`].
+    /// Terms that are the same from one iteration to the next, or that
+    /// share subterms, are only stored once, so tracking them over many
+    /// iterations only takes memory for the enodes that change.
+    ///
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let rules: &[Rewrite<S, ()>] = &[rewrite!("mul-1"; "(* ?a 1)" => "?a")];
+    /// let runner = Runner::default()
+    ///     .with_best_term_tracking(AstSize)
+    ///     .with_expr(&"(+ (* x 1) y)".parse().unwrap())
+    ///     .run(rules);
+    /// let last = runner.iterations.last().unwrap();
+    /// let best = runner.best_terms.to_recexpr(last.best_terms[0]);
+    /// assert_eq!(best.to_string(), "(+ x y)");
+    /// ```
+    pub fn with_best_term_tracking<CF>(mut self, cost_function: CF) -> Self
+    where
+        CF: CostFunction<L> + Clone + 'static,
+    {
+        self.best_term_extractor = Some(Box::new(cost_function));
+        self
+    }
+
+    /// Enable or disable searching each rule in parallel, with
+    /// [`Rewrite::with_parallel_search`].
+    ///
+    /// The rules are still searched one after the other, and each
+    /// rule's search goes through the scheduler as usual, but the
+    /// eclasses a rule is searched in are split among threads.
+    /// Parallel searches aren't limited by the
+    /// [search step limit](Runner::with_search_step_limit), and aren't
+    /// [profiled](Runner::with_backtrack_profiling).
+    /// This is off by default.
+    ///
+    /// This is only available with the `parallel` feature.
+    #[cfg(feature = "parallel")]
+    pub fn with_parallel_search(mut self, enabled: bool) -> Self
+    where
+        L: Send + Sync,
+        EGraph<L, N>: Sync,
+    {
+        self.parallel_search = if enabled {
+            Some(|rw, egraph| rw.parallel_search(egraph))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Add a lemma, a rewrite from `lhs` to `rhs` that is run eagerly
+    /// to seed the egraph, instead of being scheduled like a rule.
+    ///
+    /// Before the first iteration (and, with
+    /// [`with_lemma_interval`](Runner::with_lemma_interval), before
+    /// later ones), all the lemmas are searched and applied in passes
+    /// until a pass makes no unions, or the
+    /// [pass limit](Runner::with_lemma_pass_limit) or the node limit is
+    /// hit.
+    /// That instantiates them for the terms already in the egraph,
+    /// which the rules may only derive after many iterations.
+    ///
+    /// The lemma is named `lhs => rhs`.
+    /// What seeding did is recorded in [`Iteration::lemma_seeding`] and
+    /// summed up in the [`Report`], apart from the rules' statistics;
+    /// the scheduler never sees lemmas.
+    ///
+    /// Panics if `rhs` has a variable `lhs` doesn't bind.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let runner = Runner::<S, ()>::default()
+    ///     .with_expr(&"(+ (* a (pow a -1)) b)".parse().unwrap())
+    ///     .with_lemma("(* ?x (pow ?x -1))".parse().unwrap(), "1".parse().unwrap())
+    ///     .run(&[]);
+    /// let one_plus_b = runner.egraph.lookup_expr(&"(+ 1 b)".parse().unwrap());
+    /// assert_eq!(one_plus_b, Some(runner.egraph.find(runner.roots[0])));
+    /// assert_eq!(runner.report().lemma_unions, 1);
+    /// ```
+    pub fn with_lemma(mut self, lhs: Pattern<L>, rhs: Pattern<L>) -> Self
+    where
+        L: Display + Send + Sync + 'static,
+    {
+        let name = format!("{} => {}", lhs, rhs);
+        let lemma = Rewrite::try_new(name, lhs, rhs).unwrap_or_else(|e| panic!("{}", e));
+        self.lemmas.push(lemma);
+        self
+    }
+
+    /// Seed the [lemmas](Runner::with_lemma) again before every
+    /// `interval`th iteration, not just the first.
+    pub fn with_lemma_interval(mut self, interval: usize) -> Self {
+        assert!(interval > 0, "Lemma interval must be positive");
+        self.lemma_interval = Some(interval);
+        self
+    }
+
+    /// Sets the most passes over the [lemmas](Runner::with_lemma) each
+    /// seeding makes, which stops lemmas that keep adding new terms.
+    /// Default: 10
+    pub fn with_lemma_pass_limit(self, lemma_pass_limit: usize) -> Self {
+        Self {
+            lemma_pass_limit,
+            ..self
         }
     }
 
@@ -339,11 +1102,90 @@ where
         Self { node_limit, ..self }
     }
 
+    /// Sets the egraph size limit in eclasses, checked like the enode
+    /// limit. Default: no limit
+    pub fn with_class_limit(self, class_limit: usize) -> Self {
+        Self {
+            class_limit,
+            ..self
+        }
+    }
+
     /// Sets the runner time limit. Default: 5 seconds
     pub fn with_time_limit(self, time_limit: Duration) -> Self {
         Self { time_limit, ..self }
     }
 
+    /// Measure the time limit with `clock` instead of the wall clock.
+    #[cfg(test)]
+    pub(crate) fn with_clock(self, clock: fn(&Instant) -> Duration) -> Self {
+        Self { clock, ..self }
+    }
+
+    /// Stop with [`StopReason::Converged`] once the total
+    /// [change](Iteration::change) over the last `window` iterations is
+    /// below `threshold`, even if the egraph hasn't saturated.
+    ///
+    /// This is checked after each iteration, once there have been
+    /// `window` of them, after the other limits.
+    /// Default: off
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is 0.
+    pub fn with_convergence(self, window: usize, threshold: usize) -> Self {
+        assert!(window > 0, "the convergence window can't be empty");
+        Self {
+            convergence: Some((window, threshold)),
+            ..self
+        }
+    }
+
+    /// Stop with [`StopReason::GoalsReached`] as soon as all of `goals`
+    /// are reached, i.e. each expression is in, or each pattern matches
+    /// in, the eclass of its [root](Runner::roots).
+    ///
+    /// The `i`th goal is for the `i`th root, or for the last one if
+    /// there are fewer roots than goals, so with one root they're all
+    /// for it.
+    /// The goals are checked before the first iteration, and after each
+    /// iteration's rebuild, before the limits, so the iteration that
+    /// proves them is the last.
+    /// Adding goals more than once adds to them.
+    ///
+    /// The last [`Iteration`] then has that
+    /// [`stop_reason`](Iteration::stop_reason) too.
+    /// If the goals already hold when the runner starts, it stops
+    /// before the first iteration, with no [`iterations`](Runner::iterations).
+    ///
+    /// # Panics
+    ///
+    /// [`run`](Runner::run()) panics if there are goals but no roots.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{rewrite as rw, *};
+    /// let rules: &[Rewrite<SymbolLang, ()>] = &[
+    ///     rw!("commute-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+    ///     rw!("add-0"; "(+ ?a 0)" => "?a"),
+    /// ];
+    /// let goal: RecExpr<SymbolLang> = "x".parse().unwrap();
+    /// let runner = Runner::default()
+    ///     .with_expr(&"(+ 0 x)".parse().unwrap())
+    ///     .with_goals(&[goal])
+    ///     .run(rules);
+    /// assert!(matches!(runner.stop_reason, Some(StopReason::GoalsReached)));
+    /// // one iteration to commute, one to drop the 0
+    /// assert_eq!(runner.iterations.len(), 2);
+    /// ```
+    pub fn with_goals<G>(mut self, goals: &[G]) -> Self
+    where
+        G: Clone + Into<Goal<L>>,
+    {
+        self.goals.extend(goals.iter().cloned().map(Into::into));
+        self
+    }
+
     /// Add a hook to instrument or modify the behavior of a [`Runner`].
     /// Each hook will run at the beginning of each iteration, i.e. before
     /// all the rewrites.
@@ -383,6 +1225,46 @@ where
         Self { scheduler, ..self }
     }
 
+    /// Set a function that finds new rules to add between iterations.
+    ///
+    /// After each iteration, `synthesizer` is called with a view of the
+    /// egraph and the rules that were run.
+    /// The rules it returns are run from the next iteration on, except
+    /// for ones that refer to variables their searcher doesn't bind or
+    /// that have the same name as another rule; those are dropped with
+    /// a warning.
+    /// The names of the added rules are recorded in
+    /// [`Iteration::synthesized`].
+    /// If the runner would have saturated, it keeps going when rules
+    /// were added.
+    ///
+    /// The [`BackoffScheduler`] tracks new rules from scratch, like any
+    /// rule it hasn't seen before.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{rewrite as rw, *};
+    /// let rules: &[Rewrite<SymbolLang, ()>] = &[rw!("double"; "(* ?x 2)" => "(+ ?x ?x)")];
+    /// let runner = Runner::default()
+    ///     .with_expr(&"(* a 2)".parse().unwrap())
+    ///     .with_rule_synthesizer(|_, rules: &[Rewrite<SymbolLang, ()>]| {
+    ///         if rules.len() == 1 {
+    ///             vec![rw!("half"; "(+ ?x ?x)" => "(* ?x 2)")]
+    ///         } else {
+    ///             vec![]
+    ///         }
+    ///     })
+    ///     .run(rules);
+    /// assert_eq!(runner.iterations[0].synthesized, vec![Symbol::from("half")]);
+    /// ```
+    pub fn with_rule_synthesizer<F>(mut self, synthesizer: F) -> Self
+    where
+        F: FnMut(&dyn EGraphView<L, N>, &[Rewrite<L, N>]) -> Vec<Rewrite<L, N>> + 'static,
+    {
+        self.synthesizer = Some(Box::new(synthesizer));
+        self
+    }
+
     /// Add an expression to the egraph to be run.
     ///
     /// The eclass id of this addition will be recorded in the
@@ -403,21 +1285,110 @@ where
     /// After this, the field
     /// [`stop_reason`](Runner::stop_reason) is guaranteed to be
     /// set.
+    ///
+    /// The rules are first checked with [`validate_rules`]; if that
+    /// fails, the runner stops immediately with [`StopReason::Other`]
+    /// and performs no iterations.
+    /// This is the same in debug and release builds, so a rule set runs
+    /// the same way in both.
+    ///
+    /// Rules that can never match (see [`Rewrite::is_applicable_to`])
+    /// are skipped, logged once before the first iteration, and recorded
+    /// in [`skipped_rules`](Runner::skipped_rules).
+    /// The other rules keep the order they were given in.
     pub fn run<'a, R>(mut self, rules: R) -> Self
     where
         R: IntoIterator<Item = &'a Rewrite<L, N>>,
         L: 'a,
         N: 'a,
     {
+        assert!(
+            self.goals.is_empty() || !self.roots.is_empty(),
+            "A runner with goals needs a root to reach them from"
+        );
         let rules: Vec<&Rewrite<L, N>> = rules.into_iter().collect();
-        check_rules(&rules);
+        if let Err(err) = validate_rules(rules.iter().copied()) {
+            let stop_reason = StopReason::Other(format!("Invalid rules: {}", err));
+            info!("Stopping: {:?}", stop_reason);
+            self.stop_reason = Some(stop_reason);
+            return self;
+        }
         self.egraph.rebuild();
+        if self.goals_reached() {
+            info!(
+                "Stopping before the first iteration: {:?}",
+                StopReason::GoalsReached
+            );
+            self.stop_reason = Some(StopReason::GoalsReached);
+            return self;
+        }
+        let mut rules = rules;
+        self.skipped_rules.clear();
+        rules.retain(|rw| {
+            let applicable = rw.is_applicable_to(&self.egraph);
+            if !applicable {
+                warn!("Skipping rule {}: it can never match", rw.name);
+                self.skipped_rules.push(rw.name);
+            }
+            applicable
+        });
+        let parallel_rules: Vec<Rewrite<L, N>>;
+        let rules: Vec<&Rewrite<L, N>> = match self.parallel_search {
+            Some(parallel_search) => {
+                parallel_rules = rules
+                    .iter()
+                    .map(|&rw| {
+                        let mut rw = rw.clone();
+                        rw.parallel_search = Some(parallel_search);
+                        rw
+                    })
+                    .collect();
+                parallel_rules.iter().collect()
+            }
+            None => rules,
+        };
+        let mut op_stats = None;
+        if let Some(history) = &mut self.history {
+            history.record(&mut self.egraph);
+            op_stats = Some(self.egraph.op_stats());
+        }
+        let mut synthesized: Vec<Rewrite<L, N>> = vec![];
         loop {
-            let iter = self.run_one(&rules);
+            let active: Vec<&Rewrite<L, N>> = rules.iter().copied().chain(&synthesized).collect();
+            let mut iter = self.run_one(&active);
+            let saturated = matches!(iter.stop_reason, Some(StopReason::Saturated));
+            if iter.stop_reason.is_none() || saturated {
+                let new_rules = self.synthesize_rules(&active);
+                if saturated && !new_rules.is_empty() {
+                    iter.stop_reason = None;
+                }
+                iter.synthesized = new_rules.iter().map(|rw| rw.name).collect();
+                let parallel_search = self.parallel_search;
+                synthesized.extend(new_rules.into_iter().map(|mut rw| {
+                    rw.parallel_search = parallel_search;
+                    rw
+                }));
+            }
+            if let Some(history) = &mut self.history {
+                history.record(&mut self.egraph);
+                let after = self.egraph.op_stats();
+                if let Some(before) = &op_stats {
+                    iter.op_deltas = OpDelta::between(before, &after);
+                }
+                op_stats = Some(after);
+            }
+            // proving the goals says more than saturating
+            let proved = matches!(iter.stop_reason, None | Some(StopReason::Saturated));
+            if proved && self.goals_reached() {
+                iter.stop_reason = Some(StopReason::GoalsReached);
+            }
+            let stop_reason = iter.stop_reason.clone();
             self.iterations.push(iter);
-            let stop_reason = self.iterations.last().unwrap().stop_reason.clone();
             // we need to check_limits after the iteration is complete to check for iter_limit
-            if let Some(stop_reason) = stop_reason.or_else(|| self.check_limits().err()) {
+            let stop_reason = stop_reason
+                .or_else(|| self.check_limits().err())
+                .or_else(|| self.check_convergence());
+            if let Some(stop_reason) = stop_reason {
                 info!("Stopping: {:?}", stop_reason);
                 self.stop_reason = Some(stop_reason);
                 break;
@@ -443,6 +1414,16 @@ where
         self
     }
 
+    /// Set whether this runner's egraph performs or only records its
+    /// unions; see [`EGraph::set_union_mode`].
+    ///
+    /// In [`UnionMode::Record`], the runner saturates when an iteration
+    /// adds no enodes, since its unions never change the egraph.
+    pub fn with_union_mode(mut self, mode: UnionMode) -> Self {
+        self.egraph.set_union_mode(mode);
+        self
+    }
+
     /// Calls [`EGraph::explain_equivalence`](EGraph::explain_equivalence()).
     pub fn explain_equivalence(&mut self, left: &RecExpr<L>, right: &RecExpr<L>) -> Explanation<L> {
         self.egraph.explain_equivalence(left, right)
@@ -472,6 +1453,20 @@ where
         self.egraph.explain_matches(left, right, subst)
     }
 
+    /// Fails with [`Error::LimitExceeded`] if this runner stopped
+    /// because it hit its iteration, node or time limit.
+    ///
+    /// Any other [`StopReason`], or not having run yet, is fine.
+    pub fn ensure_within_limits(&self) -> Result<(), Error> {
+        match &self.stop_reason {
+            Some(reason @ StopReason::IterationLimit(_))
+            | Some(reason @ StopReason::NodeLimit(_))
+            | Some(reason @ StopReason::ClassLimit(_))
+            | Some(reason @ StopReason::TimeLimit(_)) => Err(Error::LimitExceeded(reason.clone())),
+            _ => Ok(()),
+        }
+    }
+
     /// Prints some information about a runners run.
     pub fn print_report(&self) {
         println!("{}", self.report())
@@ -479,6 +1474,10 @@ where
 
     /// Creates a [`Report`] summarizing this `Runner`s run.
     pub fn report(&self) -> Report {
+        let seedings = self
+            .iterations
+            .iter()
+            .filter_map(|i| i.lemma_seeding.as_ref());
         Report {
             stop_reason: self.stop_reason.clone().unwrap(),
             iterations: self.iterations.len(),
@@ -490,7 +1489,69 @@ where
             apply_time: self.iterations.iter().map(|i| i.apply_time).sum(),
             rebuild_time: self.iterations.iter().map(|i| i.rebuild_time).sum(),
             total_time: self.iterations.iter().map(|i| i.total_time).sum(),
+            frozen_skips: self
+                .iterations
+                .iter()
+                .flat_map(|i| i.frozen_skipped.values())
+                .sum(),
+            lemma_seedings: seedings.clone().count(),
+            lemma_unions: seedings.clone().flat_map(|s| s.applied.values()).sum(),
+            lemma_time: seedings.map(|s| s.time).sum(),
+            top_ops: self.top_ops(10),
+            best_term_nodes: self.best_terms.len(),
+            top_wasteful_binders: self.top_wasteful_binders(),
+            root_terms: self
+                .roots
+                .iter()
+                .map(|&root| self.egraph.class_term(root, &TermBudget::default()))
+                .collect(),
+        }
+    }
+
+    /// The [`TOP_BINDERS`] binders with the most backtracks over all the
+    /// iterations.
+    fn top_wasteful_binders(&self) -> Vec<WastefulBinder> {
+        let mut binders: IndexMap<(Symbol, Id), WastefulBinder> = Default::default();
+        for b in self.iterations.iter().flat_map(|i| &i.wasteful_binders) {
+            if let Some(total) = binders.get_mut(&(b.rule, b.position)) {
+                total.candidates += b.candidates;
+                total.backtracks += b.backtracks;
+            } else {
+                binders.insert((b.rule, b.position), b.clone());
+            }
         }
+        let mut binders: Vec<WastefulBinder> = binders.into_iter().map(|(_, b)| b).collect();
+        sort_binders(&mut binders);
+        binders.truncate(TOP_BINDERS);
+        binders
+    }
+
+    /// Returns the statistics of each rule this runner has run, as a
+    /// snapshot of one run.
+    ///
+    /// Merge the snapshots of many runs with [`RuleStatsSnapshot::merge`]
+    /// to build a [`PrioritizedScheduler`].
+    pub fn export_rule_stats(&self) -> RuleStatsSnapshot {
+        RuleStatsSnapshot {
+            runs: 1,
+            ..self.rule_stats.clone()
+        }
+    }
+
+    /// Let a stopped runner run for at most `iterations` more
+    /// iterations, with a fresh time limit.
+    #[cfg_attr(not(feature = "shell"), allow(dead_code))]
+    pub(crate) fn resume(&mut self, iterations: usize) {
+        self.stop_reason = None;
+        self.start_time = None;
+        self.iter_limit = self.iterations.len() + iterations;
+    }
+
+    fn top_ops(&self, n: usize) -> Vec<(String, OpStats)> {
+        let mut ops: Vec<(String, OpStats)> = self.egraph.op_stats().into_iter().collect();
+        ops.sort_by(|(_, a), (_, b)| b.nodes.cmp(&a.nodes));
+        ops.truncate(n);
+        ops
     }
 
     fn run_one(&mut self, rules: &[&Rewrite<L, N>]) -> Iteration<IterData> {
@@ -500,16 +1561,32 @@ where
 
         self.try_start();
         let mut result = self.check_limits();
+        let isolate = self.checkpoint.is_some();
+        if let Some(checkpoint) = self.checkpoint {
+            checkpoint(&mut self.egraph);
+        }
+
+        let mut lemma_seeding = None;
+        if self.seeds_lemmas(self.iterations.len()) {
+            result = result.and_then(|_| {
+                let seeding =
+                    isolate_panic(isolate, None, RunPhase::Lemmas, || self.seed_lemmas())?;
+                lemma_seeding = Some(seeding);
+                Ok(())
+            });
+        }
 
         let egraph_nodes = self.egraph.total_size();
         let egraph_classes = self.egraph.number_of_classes();
+        let enodes_before = self.egraph.enode_classes.len();
 
         let hook_time = Instant::now();
         let mut hooks = std::mem::take(&mut self.hooks);
         result = result.and_then(|_| {
-            hooks
-                .iter_mut()
-                .try_for_each(|hook| hook(self).map_err(StopReason::Other))
+            hooks.iter_mut().try_for_each(|hook| {
+                isolate_panic(isolate, None, RunPhase::Hooks, || hook(self))?
+                    .map_err(StopReason::Other)
+            })
         });
         self.hooks = hooks;
         let hook_time = hook_time.elapsed().as_secs_f64();
@@ -518,31 +1595,144 @@ where
         let egraph_classes_after_hooks = self.egraph.number_of_classes();
 
         let i = self.iterations.len();
+        self.egraph.set_provenance_iteration(i);
         trace!("EGraph {:?}", self.egraph.dump());
 
         let start_time = Instant::now();
 
+        let mut rules = rules.to_vec();
+        self.scheduler.prioritize(&mut rules);
+        self.arena.clear();
+        self.arena.set_step_budget(self.search_step_limit);
+        self.arena.set_profiling(self.backtrack_profiling);
+
+        // the rules of each search group, by their index in `rules`
+        let mut groups: IndexMap<Symbol, Vec<usize>> = Default::default();
+        for (index, rule) in rules.iter().enumerate() {
+            if let Some(group) = rule.search_group {
+                groups.entry(group).or_default().push(index);
+            }
+        }
+        // the matches of the rules of groups searched so far
+        let mut grouped_matches: HashMap<usize, Vec<ArenaMatches<L>>> = Default::default();
+
+        let fused_apply = self.fused_apply;
         let mut matches = Vec::new();
+        let mut matched = IndexMap::default();
+        let mut top_fanout = vec![];
+        let mut search_truncated = vec![];
+        let mut wasteful_binders = vec![];
         result = result.and_then(|_| {
-            rules.iter().try_for_each(|rule| {
-                let ms = self.scheduler.search_rewrite(i, &self.egraph, rule);
+            rules.iter().enumerate().try_for_each(|(index, rule)| {
+                if fused_apply.is_some() {
+                    // searched while applying instead
+                    matches.push(vec![]);
+                    return Ok(());
+                }
+                let search_start = Instant::now();
+                let group = rule.search_group.and_then(|g| groups.get(&g));
+                let ms = match group {
+                    // the whole group is searched with its first rule, so
+                    // that rule's search time is the group's
+                    Some(group) if group.len() > 1 => {
+                        if !grouped_matches.contains_key(&index) {
+                            let members: Vec<&Rewrite<L, N>> =
+                                group.iter().map(|&j| rules[j]).collect();
+                            let found =
+                                isolate_panic(isolate, Some(rule.name), RunPhase::Search, || {
+                                    self.scheduler.search_group_into(
+                                        i,
+                                        &self.egraph,
+                                        &members,
+                                        &mut self.arena,
+                                    )
+                                })?;
+                            grouped_matches.extend(group.iter().copied().zip(found));
+                        }
+                        grouped_matches.remove(&index).unwrap_or_default()
+                    }
+                    _ => isolate_panic(isolate, Some(rule.name), RunPhase::Search, || {
+                        self.scheduler
+                            .search_rewrite_into(i, &self.egraph, rule, &mut self.arena)
+                    })?,
+                };
+                let stats = self.rule_stats.entry(rule.name);
+                stats.search_time += search_start.elapsed().as_secs_f64();
+                let n_matches: usize = ms.iter().map(|m| m.substs.len()).sum();
+                stats.matches += n_matches;
+                record_fanout(&mut top_fanout, rule.name, &ms);
+                if self.arena.step_budget_exhausted() {
+                    search_truncated.push(rule.name);
+                }
+                if self.backtrack_profiling {
+                    let profile = self.arena.take_profile();
+                    let ast = rule.searcher.get_pattern_ast();
+                    record_binders(&mut wasteful_binders, rule.name, ast, profile);
+                }
+                if n_matches > 0 {
+                    stats.iterations += 1;
+                    *matched.entry(rule.name).or_default() += n_matches;
+                }
                 matches.push(ms);
                 self.check_limits()
             })
         });
 
+        sort_binders(&mut wasteful_binders);
+
         let search_time = start_time.elapsed().as_secs_f64();
         info!("Search time: {}", search_time);
 
         let apply_time = Instant::now();
 
         let mut applied = IndexMap::default();
+        let mut skipped = IndexMap::default();
+        let mut frozen_skipped = IndexMap::default();
+        let mut deduped = IndexMap::default();
+        let mut redundant = IndexMap::default();
+        self.egraph.set_apply_dedup(self.apply_dedup);
+        self.egraph.set_skip_redundant(self.skip_redundant);
         result = result.and_then(|_| {
             rules.iter().zip(matches).try_for_each(|(rw, ms)| {
                 let total_matches: usize = ms.iter().map(|m| m.substs.len()).sum();
                 debug!("Applying {} {} times", rw.name, total_matches);
 
-                let actually_matched = self.scheduler.apply_rewrite(i, &mut self.egraph, rw, ms);
+                let apply_start = Instant::now();
+                let frozen_skips = self.egraph.frozen_skips();
+                let n_deduped = self.egraph.deduped();
+                let n_redundant = self.egraph.redundant_skips();
+                let (fused_matches, actually_matched) =
+                    isolate_panic(isolate, Some(rw.name), RunPhase::Apply, || {
+                        if let Some(batch_size) = fused_apply {
+                            let (n_matches, n_applied) = self.scheduler.search_apply_rewrite(
+                                i,
+                                &mut self.egraph,
+                                rw,
+                                batch_size,
+                            );
+                            (Some(n_matches), n_applied)
+                        } else {
+                            let n_applied = self.scheduler.apply_rewrite_from(
+                                i,
+                                &mut self.egraph,
+                                rw,
+                                &self.arena,
+                                ms,
+                            );
+                            (None, n_applied)
+                        }
+                    })?;
+                let stats = self.rule_stats.entry(rw.name);
+                stats.apply_time += apply_start.elapsed().as_secs_f64();
+                if let Some(n_matches) = fused_matches {
+                    debug!("Matched {} {} times", rw.name, n_matches);
+                    stats.matches += n_matches;
+                    if n_matches > 0 {
+                        stats.iterations += 1;
+                        *matched.entry(rw.name).or_default() += n_matches;
+                    }
+                }
+                stats.unions += actually_matched;
                 if actually_matched > 0 {
                     if let Some(count) = applied.get_mut(&rw.name) {
                         *count += actually_matched;
@@ -551,28 +1741,70 @@ where
                     }
                     debug!("Applied {} {} times", rw.name, actually_matched);
                 }
+                let n_skipped = rw.applier.take_skipped();
+                if n_skipped > 0 {
+                    *skipped.entry(rw.name).or_default() += n_skipped;
+                }
+                let n_frozen = self.egraph.frozen_skips() - frozen_skips;
+                if n_frozen > 0 {
+                    *frozen_skipped.entry(rw.name).or_default() += n_frozen;
+                }
+                let n_deduped = self.egraph.deduped() - n_deduped;
+                if n_deduped > 0 {
+                    *deduped.entry(rw.name).or_default() += n_deduped;
+                }
+                let n_redundant = self.egraph.redundant_skips() - n_redundant;
+                if n_redundant > 0 {
+                    *redundant.entry(rw.name).or_default() += n_redundant;
+                }
                 self.check_limits()
             })
         });
+        self.egraph.set_apply_dedup(false);
+        self.egraph.set_skip_redundant(false);
 
         let apply_time = apply_time.elapsed().as_secs_f64();
         info!("Apply time: {}", apply_time);
 
+        let apply_unions = self.egraph.dirty_unions_count();
         let rebuild_time = Instant::now();
-        let n_rebuilds = self.egraph.rebuild();
-        if self.egraph.are_explanations_enabled() {
-            debug_assert!(self.egraph.check_each_explain(rules));
+        let rebuild =
+            match isolate_panic(isolate, None, RunPhase::Rebuild, || self.egraph.rebuild()) {
+                Ok(rebuild) => rebuild,
+                Err(panicked) => {
+                    result = result.and(Err(panicked));
+                    RebuildReport::default()
+                }
+            };
+        let n_rebuilds = rebuild.unions;
+        let panicked = matches!(result, Err(StopReason::Panicked { .. }));
+        if panicked && self.egraph.roll_back() {
+            info!("Rolling back to the start of the iteration");
+        } else if self.egraph.are_explanations_enabled() {
+            debug_assert!(self.egraph.check_each_explain(&rules));
         }
+        self.egraph.discard_checkpoint();
 
         let rebuild_time = rebuild_time.elapsed().as_secs_f64();
         info!("Rebuild time: {}", rebuild_time);
-        info!(
-            "Size: n={}, e={}",
-            self.egraph.total_size(),
-            self.egraph.number_of_classes()
-        );
-
-        let can_be_saturated = applied.is_empty()
+        let rebuilt_nodes = self.egraph.total_size();
+        let rebuilt_classes = self.egraph.number_of_classes();
+        info!("Size: n={}, e={}", rebuilt_nodes, rebuilt_classes);
+        let new_nodes = self
+            .egraph
+            .enode_classes
+            .len()
+            .saturating_sub(enodes_before);
+
+        // recorded unions don't change the egraph, so only new enodes do
+        let changed = match self.egraph.union_mode() {
+            UnionMode::Perform => !applied.is_empty(),
+            UnionMode::Record => rebuilt_nodes != egraph_nodes_after_hooks,
+        };
+        // skipped and missed matches may still do something in a later iteration
+        let can_be_saturated = !changed
+            && skipped.is_empty()
+            && search_truncated.is_empty()
             && self.scheduler.can_stop(i)
             && (egraph_nodes == egraph_nodes_after_hooks)
             && (egraph_classes == egraph_classes_after_hooks);
@@ -581,27 +1813,127 @@ where
             result = result.and(Err(StopReason::Saturated))
         }
 
+        let best_terms = match &self.best_term_extractor {
+            Some(extractor) => {
+                let (egraph, arena) = (&self.egraph, &mut self.best_terms);
+                self.roots
+                    .iter()
+                    .map(|&root| extractor.extract(egraph, arena, root))
+                    .collect()
+            }
+            None => vec![],
+        };
+
         Iteration {
+            matched,
             applied,
+            skipped,
+            frozen_skipped,
+            deduped,
+            redundant,
+            synthesized: vec![],
+            lemma_seeding,
+            top_fanout,
+            search_truncated,
+            wasteful_binders,
+            op_deltas: Default::default(),
+            best_terms,
             egraph_nodes,
             egraph_classes,
+            rebuilt_nodes,
+            rebuilt_classes,
+            unions: apply_unions + rebuild.unions,
+            new_nodes,
+            changed_classes: rebuild.changed_classes,
             hook_time,
             search_time,
             apply_time,
             rebuild_time,
             n_rebuilds,
+            rebuild,
             data: IterData::make(&self),
             total_time: start_time.elapsed().as_secs_f64(),
             stop_reason: result.err(),
         }
     }
 
+    /// Call the rule synthesizer, if any, and return the new rules that
+    /// can be added to `rules`.
+    fn synthesize_rules(&mut self, rules: &[&Rewrite<L, N>]) -> Vec<Rewrite<L, N>> {
+        let synthesizer = match &mut self.synthesizer {
+            Some(synthesizer) => synthesizer,
+            None => return vec![],
+        };
+        let current: Vec<Rewrite<L, N>> = rules.iter().map(|&rw| rw.clone()).collect();
+        let candidates = synthesizer(&self.egraph, &current);
+
+        let mut names: HashSet<Symbol> = rules.iter().map(|rw| rw.name).collect();
+        let mut new_rules = vec![];
+        for rw in candidates {
+            let bound = rw.searcher.vars();
+            if let Some(v) = rw.applier.vars().into_iter().find(|v| !bound.contains(v)) {
+                warn!("Dropping synthesized rule {}: unbound var {}", rw.name, v);
+            } else if !names.insert(rw.name) {
+                warn!("Dropping synthesized rule {}: duplicate name", rw.name);
+            } else {
+                info!("Synthesized rule {}", rw.name);
+                new_rules.push(rw);
+            }
+        }
+        new_rules
+    }
+
+    /// Whether to seed the lemmas before iteration `i`.
+    fn seeds_lemmas(&self, i: usize) -> bool {
+        !self.lemmas.is_empty() && (i == 0 || self.lemma_interval.map_or(false, |k| i % k == 0))
+    }
+
+    /// Search and apply all the lemmas in passes until they reach their
+    /// fixpoint, or the pass or node limit is hit.
+    fn seed_lemmas(&mut self) -> LemmaSeeding {
+        let start = Instant::now();
+        let mut seeding = LemmaSeeding::default();
+        let (lemmas, egraph) = (&self.lemmas, &mut self.egraph);
+        while seeding.passes < self.lemma_pass_limit {
+            seeding.passes += 1;
+            egraph.rebuild();
+            let matches: Vec<_> = lemmas.iter().map(|lemma| lemma.search(egraph)).collect();
+            let (mut unions, size) = (0, egraph.total_size());
+            for (lemma, ms) in lemmas.iter().zip(matches) {
+                let n = lemma.apply(egraph, &ms).len();
+                if n > 0 {
+                    *seeding.applied.entry(lemma.name).or_default() += n;
+                    unions += n;
+                }
+            }
+            egraph.rebuild();
+            debug!("Lemma seeding pass {}: {} unions", seeding.passes, unions);
+            let recording = egraph.union_mode() == UnionMode::Record;
+            if unions == 0 || (recording && egraph.total_size() == size) {
+                seeding.saturated = true;
+                break;
+            }
+            if egraph.total_size() > self.node_limit
+                || egraph.number_of_classes() > self.class_limit
+            {
+                break;
+            }
+        }
+        seeding.time = start.elapsed().as_secs_f64();
+        info!(
+            "Seeded lemmas in {} passes, {} unions",
+            seeding.passes,
+            seeding.applied.values().sum::<usize>()
+        );
+        seeding
+    }
+
     fn try_start(&mut self) {
         self.start_time.get_or_insert_with(Instant::now);
     }
 
     fn check_limits(&self) -> RunnerResult<()> {
-        let elapsed = self.start_time.unwrap().elapsed();
+        let elapsed = (self.clock)(self.start_time.as_ref().unwrap());
         if elapsed > self.time_limit {
             return Err(StopReason::TimeLimit(elapsed.as_secs_f64()));
         }
@@ -611,28 +1943,55 @@ where
             return Err(StopReason::NodeLimit(size));
         }
 
+        let classes = self.egraph.number_of_classes();
+        if classes > self.class_limit {
+            return Err(StopReason::ClassLimit(classes));
+        }
+
         if self.iterations.len() >= self.iter_limit {
             return Err(StopReason::IterationLimit(self.iterations.len()));
         }
 
         Ok(())
     }
-}
 
-fn check_rules<L, N>(rules: &[&Rewrite<L, N>]) {
-    let mut name_counts = IndexMap::default();
-    for rw in rules {
-        *name_counts.entry(rw.name).or_default() += 1
+    fn goals_reached(&self) -> bool {
+        if self.goals.is_empty() {
+            return false;
+        }
+        let last = self.roots.len() - 1;
+        self.goals.iter().enumerate().all(|(i, goal)| {
+            let root = self.roots[i.min(last)];
+            goal.is_reached(&self.egraph, root)
+        })
     }
 
-    name_counts.retain(|_, count: &mut usize| *count > 1);
-    if !name_counts.is_empty() {
-        eprintln!("WARNING: Duplicated rule names may affect rule reporting and scheduling.");
-        log::warn!("Duplicated rule names may affect rule reporting and scheduling.");
-        for (name, &count) in name_counts.iter() {
-            assert!(count > 1);
-            eprintln!("Rule '{}' appears {} times", name, count);
-            log::warn!("Rule '{}' appears {} times", name, count);
+    fn check_convergence(&self) -> Option<StopReason> {
+        let (window, threshold) = self.convergence?;
+        let last = self.iterations.len().checked_sub(window)?;
+        let change: usize = self.iterations[last..].iter().map(|i| i.change()).sum();
+        if change < threshold {
+            Some(StopReason::Converged { window, change })
+        } else {
+            None
+        }
+    }
+}
+
+impl<L, N, IterData> Runner<L, N, IterData>
+where
+    L: Language,
+    N: Analysis<L> + Clone,
+    N::Data: Clone + PartialEq,
+    IterData: IterationData<L, N>,
+{
+    /// Record a snapshot of the egraph before the first iteration and
+    /// after each one in a [`HistoryRecorder`], stored in
+    /// [`history`](Runner::history).
+    pub fn with_history(self) -> Self {
+        Self {
+            history: Some(HistoryRecorder::new()),
+            ..self
         }
     }
 }
@@ -688,14 +2047,102 @@ where
     ) -> usize {
         rewrite.apply(egraph, &matches).len()
     }
+
+    /// Like [`search_rewrite`](RewriteScheduler::search_rewrite), but
+    /// storing the matches in `arena`.
+    /// This is what the [`Runner`] calls.
+    ///
+    /// Default implementation moves the results of
+    /// [`search_rewrite`](RewriteScheduler::search_rewrite) into the
+    /// arena, so schedulers that only customize that keep working.
+    fn search_rewrite_into<'a>(
+        &mut self,
+        iteration: usize,
+        egraph: &EGraph<L, N>,
+        rewrite: &'a Rewrite<L, N>,
+        arena: &mut MatchArena,
+    ) -> Vec<ArenaMatches<'a, L>> {
+        let mut matches = vec![];
+        for m in self.search_rewrite(iteration, egraph, rewrite) {
+            matches.extend(m.into_arena(arena));
+        }
+        matches
+    }
+
+    /// Search for the `rewrites` of a
+    /// [search group](Rewrite::with_search_group), storing the matches
+    /// in `arena`, and return the matches of each in order.
+    /// This is what the [`Runner`] calls, once per iteration, for the
+    /// rules of a group instead of
+    /// [`search_rewrite_into`](RewriteScheduler::search_rewrite_into).
+    ///
+    /// Default implementation calls
+    /// [`search_rewrite_into`](RewriteScheduler::search_rewrite_into)
+    /// on each rewrite, so schedulers that customize it, say to ban
+    /// rules, keep searching the rules of a group one by one.
+    fn search_group_into<'a>(
+        &mut self,
+        iteration: usize,
+        egraph: &EGraph<L, N>,
+        rewrites: &[&'a Rewrite<L, N>],
+        arena: &mut MatchArena,
+    ) -> Vec<Vec<ArenaMatches<'a, L>>> {
+        rewrites
+            .iter()
+            .map(|rw| self.search_rewrite_into(iteration, egraph, rw, arena))
+            .collect()
+    }
+
+    /// Like [`apply_rewrite`](RewriteScheduler::apply_rewrite), for
+    /// matches stored in `arena`.
+    /// This is what the [`Runner`] calls.
+    ///
+    /// Default implementation copies the matches out of the arena and
+    /// calls [`apply_rewrite`](RewriteScheduler::apply_rewrite).
+    fn apply_rewrite_from(
+        &mut self,
+        iteration: usize,
+        egraph: &mut EGraph<L, N>,
+        rewrite: &Rewrite<L, N>,
+        arena: &MatchArena,
+        matches: Vec<ArenaMatches<L>>,
+    ) -> usize {
+        let matches = matches.iter().map(|m| m.to_search_matches(arena)).collect();
+        self.apply_rewrite(iteration, egraph, rewrite, matches)
+    }
+
+    /// Search for `rewrite` and apply its matches in one pass, for a
+    /// [fused](Runner::with_fused_apply) runner.
+    /// This is what such a [`Runner`] calls instead of
+    /// [`search_rewrite_into`](RewriteScheduler::search_rewrite_into) and
+    /// [`apply_rewrite_from`](RewriteScheduler::apply_rewrite_from).
+    /// Returns the number of matches found and the number of
+    /// applications that changed the egraph.
+    ///
+    /// Default implementation calls [`Rewrite::search_apply`] without a
+    /// match limit.
+    fn search_apply_rewrite(
+        &mut self,
+        iteration: usize,
+        egraph: &mut EGraph<L, N>,
+        rewrite: &Rewrite<L, N>,
+        batch_size: usize,
+    ) -> (usize, usize) {
+        rewrite.search_apply(egraph, usize::MAX, batch_size)
+    }
+
+    /// A hook allowing you to reorder the rules before each iteration.
+    /// Rules are searched and applied in the resulting order.
+    ///
+    /// Default implementation leaves the order unchanged.
+    fn prioritize(&mut self, rules: &mut [&Rewrite<L, N>]) {}
 }
 
 /// A very simple [`RewriteScheduler`] that runs every rewrite every
 /// time.
 ///
 /// Using this is basically turning off rule scheduling.
-/// It uses the default implementation for all [`RewriteScheduler`]
-/// methods.
+/// It searches and applies every rewrite directly.
 ///
 /// This is not the default scheduler; choose it with the
 /// [`with_scheduler`](Runner::with_scheduler())
@@ -709,6 +2156,36 @@ where
     L: Language,
     N: Analysis<L>,
 {
+    fn search_rewrite_into<'a>(
+        &mut self,
+        _iteration: usize,
+        egraph: &EGraph<L, N>,
+        rewrite: &'a Rewrite<L, N>,
+        arena: &mut MatchArena,
+    ) -> Vec<ArenaMatches<'a, L>> {
+        rewrite.search_into(egraph, arena)
+    }
+
+    fn search_group_into<'a>(
+        &mut self,
+        _iteration: usize,
+        egraph: &EGraph<L, N>,
+        rewrites: &[&'a Rewrite<L, N>],
+        arena: &mut MatchArena,
+    ) -> Vec<Vec<ArenaMatches<'a, L>>> {
+        Rewrite::search_group_into(rewrites, egraph, arena)
+    }
+
+    fn apply_rewrite_from(
+        &mut self,
+        _iteration: usize,
+        egraph: &mut EGraph<L, N>,
+        rewrite: &Rewrite<L, N>,
+        arena: &MatchArena,
+        matches: Vec<ArenaMatches<L>>,
+    ) -> usize {
+        rewrite.apply_from(egraph, arena, &matches).len()
+    }
 }
 
 /// A [`RewriteScheduler`] that implements exponentional rule backoff.
@@ -850,18 +2327,89 @@ where
         egraph: &EGraph<L, N>,
         rewrite: &'a Rewrite<L, N>,
     ) -> Vec<SearchMatches<'a, L>> {
+        if self.is_banned(iteration, rewrite.name) {
+            return vec![];
+        }
+        let matches = rewrite.search(egraph);
+        let total_len: usize = matches.iter().map(|m| m.substs.len()).sum();
+        if self.admit(iteration, rewrite.name, total_len) {
+            matches
+        } else {
+            vec![]
+        }
+    }
+
+    fn search_rewrite_into<'a>(
+        &mut self,
+        iteration: usize,
+        egraph: &EGraph<L, N>,
+        rewrite: &'a Rewrite<L, N>,
+        arena: &mut MatchArena,
+    ) -> Vec<ArenaMatches<'a, L>> {
+        if self.is_banned(iteration, rewrite.name) {
+            return vec![];
+        }
+        let matches = rewrite.search_into(egraph, arena);
+        let total_len: usize = matches.iter().map(|m| m.substs.len()).sum();
+        if self.admit(iteration, rewrite.name, total_len) {
+            matches
+        } else {
+            vec![]
+        }
+    }
+
+    fn apply_rewrite_from(
+        &mut self,
+        _iteration: usize,
+        egraph: &mut EGraph<L, N>,
+        rewrite: &Rewrite<L, N>,
+        arena: &MatchArena,
+        matches: Vec<ArenaMatches<L>>,
+    ) -> usize {
+        rewrite.apply_from(egraph, arena, &matches).len()
+    }
+
+    /// Matches can't be dropped once they are applied, so a rule over
+    /// its limit still applies one more match than the limit before it
+    /// is banned.
+    fn search_apply_rewrite(
+        &mut self,
+        iteration: usize,
+        egraph: &mut EGraph<L, N>,
+        rewrite: &Rewrite<L, N>,
+        batch_size: usize,
+    ) -> (usize, usize) {
+        if self.is_banned(iteration, rewrite.name) {
+            return (0, 0);
+        }
         let stats = self.rule_stats(rewrite.name);
+        let threshold = stats.match_limit << stats.times_banned;
+        // probe one match past the threshold, to ban the rule if there's more
+        let found = rewrite.search_apply_probing(egraph, threshold, batch_size, true);
+        self.admit(iteration, rewrite.name, found.0);
+        found
+    }
+}
 
+impl BackoffScheduler {
+    /// Check if the rule `name` is banned in this iteration.
+    fn is_banned(&mut self, iteration: usize, name: Symbol) -> bool {
+        let stats = self.rule_stats(name);
         if iteration < stats.banned_until {
             debug!(
                 "Skipping {} ({}-{}), banned until {}...",
-                rewrite.name, stats.times_applied, stats.times_banned, stats.banned_until,
+                name, stats.times_applied, stats.times_banned, stats.banned_until,
             );
-            return vec![];
+            true
+        } else {
+            false
         }
+    }
 
-        let matches = rewrite.search(egraph);
-        let total_len: usize = matches.iter().map(|m| m.substs.len()).sum();
+    /// Check if the rule `name` may apply its `total_len` matches,
+    /// banning it if there are too many.
+    fn admit(&mut self, iteration: usize, name: Symbol, total_len: usize) -> bool {
+        let stats = self.rule_stats(name);
         let threshold = stats.match_limit << stats.times_banned;
         if total_len > threshold {
             let ban_length = stats.ban_length << stats.times_banned;
@@ -869,21 +2417,147 @@ where
             stats.banned_until = iteration + ban_length;
             info!(
                 "Banning {} ({}-{}) for {} iters: {} < {}",
-                rewrite.name,
-                stats.times_applied,
-                stats.times_banned,
-                ban_length,
-                threshold,
-                total_len,
+                name, stats.times_applied, stats.times_banned, ban_length, threshold, total_len,
             );
-            vec![]
+            false
         } else {
             stats.times_applied += 1;
-            matches
+            true
+        }
+    }
+}
+
+/// A [`RewriteScheduler`] that orders and budgets rules by how
+/// productive they were in earlier runs.
+///
+/// Build one from a [`RuleStatsSnapshot`] with
+/// [`from_stats`](PrioritizedScheduler::from_stats).
+/// Each iteration, rules the snapshot doesn't know are run first,
+/// followed by the rest in order of decreasing
+/// [productivity](RuleStatsEntry::productivity).
+/// Rules that never made a union get a smaller match limit; matches over
+/// a rule's limit are dropped for that iteration.
+///
+/// [`PrioritizedScheduler`] is configurable in the builder-pattern style.
+///
+#[derive(Debug)]
+pub struct PrioritizedScheduler {
+    default_match_limit: usize,
+    unproductive_match_limit: usize,
+    productivity: IndexMap<Symbol, f64>,
+}
+
+impl PrioritizedScheduler {
+    /// Create a scheduler from the statistics of earlier runs.
+    pub fn from_stats(stats: &RuleStatsSnapshot) -> Self {
+        let productivity = stats
+            .rules
+            .iter()
+            .map(|(name, entry)| (Symbol::from(name.as_str()), entry.productivity()))
+            .collect();
+        Self {
+            default_match_limit: 1_000,
+            unproductive_match_limit: 100,
+            productivity,
+        }
+    }
+
+    /// Set the match limit of rules that made unions before, or that
+    /// aren't in the snapshot.
+    /// Default: 1,000
+    pub fn with_match_limit(mut self, limit: usize) -> Self {
+        self.default_match_limit = limit;
+        self
+    }
+
+    /// Set the match limit of rules that never made a union.
+    /// Default: 100
+    pub fn with_unproductive_match_limit(mut self, limit: usize) -> Self {
+        self.unproductive_match_limit = limit;
+        self
+    }
+
+    /// Returns the number of matches the rule `name` may apply in an
+    /// iteration.
+    pub fn match_limit(&self, name: Symbol) -> usize {
+        match self.productivity.get(&name) {
+            Some(&p) if p <= 0.0 => self.unproductive_match_limit,
+            _ => self.default_match_limit,
         }
     }
 }
 
+impl<L, N> RewriteScheduler<L, N> for PrioritizedScheduler
+where
+    L: Language,
+    N: Analysis<L>,
+{
+    fn search_rewrite<'a>(
+        &mut self,
+        iteration: usize,
+        egraph: &EGraph<L, N>,
+        rewrite: &'a Rewrite<L, N>,
+    ) -> Vec<SearchMatches<'a, L>> {
+        let limit = self.match_limit(rewrite.name);
+        let mut matches = rewrite.search(egraph);
+        let mut remaining = limit;
+        for m in &mut matches {
+            m.substs.truncate(remaining);
+            remaining -= m.substs.len();
+        }
+        matches.retain(|m| !m.substs.is_empty());
+        matches
+    }
+
+    fn search_rewrite_into<'a>(
+        &mut self,
+        _iteration: usize,
+        egraph: &EGraph<L, N>,
+        rewrite: &'a Rewrite<L, N>,
+        arena: &mut MatchArena,
+    ) -> Vec<ArenaMatches<'a, L>> {
+        let limit = self.match_limit(rewrite.name);
+        let mut matches = rewrite.search_into(egraph, arena);
+        let mut remaining = limit;
+        for m in &mut matches {
+            m.substs.truncate(remaining);
+            remaining -= m.substs.len();
+        }
+        matches.retain(|m| !m.substs.is_empty());
+        matches
+    }
+
+    fn apply_rewrite_from(
+        &mut self,
+        _iteration: usize,
+        egraph: &mut EGraph<L, N>,
+        rewrite: &Rewrite<L, N>,
+        arena: &MatchArena,
+        matches: Vec<ArenaMatches<L>>,
+    ) -> usize {
+        rewrite.apply_from(egraph, arena, &matches).len()
+    }
+
+    fn search_apply_rewrite(
+        &mut self,
+        _iteration: usize,
+        egraph: &mut EGraph<L, N>,
+        rewrite: &Rewrite<L, N>,
+        batch_size: usize,
+    ) -> (usize, usize) {
+        let limit = self.match_limit(rewrite.name);
+        rewrite.search_apply(egraph, limit, batch_size)
+    }
+
+    fn prioritize(&mut self, rules: &mut [&Rewrite<L, N>]) {
+        let priority = |rw: &Rewrite<L, N>| -> f64 {
+            let p = self.productivity.get(&rw.name);
+            p.copied().unwrap_or(f64::INFINITY)
+        };
+        rules.sort_by(|a, b| priority(b).partial_cmp(&priority(a)).unwrap());
+    }
+}
+
 /// Custom data to inject into the [`Iteration`]s recorded by a [`Runner`]
 ///
 /// This trait allows you to add custom data to the [`Iteration`]s
@@ -0,0 +1,136 @@
+use crate::*;
+
+/// How many enodes with an operator there are, and in how many eclasses.
+#[derive(Debug, Clone, Copy, Default)]
+struct OpCount {
+    nodes: usize,
+    classes: usize,
+}
+
+/// A cost model for pattern matching, estimating how many candidates
+/// each step of a [compiled pattern](Pattern::explain) produces from an
+/// egraph's operator statistics.
+///
+/// Operators are keyed by enodes with their children zeroed, as in a
+/// `Bind`, so they are told apart exactly as matching does.
+/// The model assumes enodes are spread evenly over the eclasses, so its
+/// numbers are estimates for comparing plans, not predictions.
+#[derive(Debug, Clone)]
+pub(crate) struct Selectivity<L> {
+    ops: HashMap<L, OpCount>,
+    n_classes: usize,
+    n_nodes: usize,
+}
+
+impl<L: Language> Selectivity<L> {
+    /// Gather the statistics of the operators `ops` in `egraph`.
+    ///
+    /// Only the eclasses the op index has for each operator are scanned,
+    /// or every eclass if `egraph` isn't clean.
+    pub(crate) fn new<'a, N: Analysis<L>>(
+        egraph: &EGraph<L, N>,
+        ops: impl IntoIterator<Item = &'a L>,
+    ) -> Self
+    where
+        L: 'a,
+    {
+        let mut ops: HashMap<L, OpCount> = ops
+            .into_iter()
+            .map(|op| (zeroed(op), OpCount::default()))
+            .collect();
+        let mut keys: Vec<L> = vec![];
+        for op in ops.keys() {
+            if !keys.iter().any(|k| same_variant(k, op)) {
+                keys.push(op.clone());
+            }
+        }
+        let mut seen = vec![];
+        for key in &keys {
+            for class in egraph.classes_with_op(key) {
+                seen.clear();
+                for node in class.iter().filter(|n| same_variant(n, key)) {
+                    let op = zeroed(node);
+                    if let Some(count) = ops.get_mut(&op) {
+                        count.nodes += 1;
+                        if !seen.contains(&op) {
+                            seen.push(op);
+                        }
+                    }
+                }
+                for op in seen.drain(..) {
+                    ops.get_mut(&op).unwrap().classes += 1;
+                }
+            }
+        }
+        Self {
+            ops,
+            n_classes: egraph.number_of_classes(),
+            n_nodes: egraph.total_number_of_nodes(),
+        }
+    }
+
+    /// The number of eclasses.
+    pub(crate) fn n_classes(&self) -> usize {
+        self.n_classes
+    }
+
+    /// The number of enodes.
+    pub(crate) fn n_nodes(&self) -> usize {
+        self.n_nodes
+    }
+
+    fn count(&self, op: &L) -> OpCount {
+        self.ops.get(&zeroed(op)).copied().unwrap_or_default()
+    }
+
+    fn fraction(&self, n: usize) -> f64 {
+        if self.n_classes == 0 {
+            0.0
+        } else {
+            n as f64 / self.n_classes as f64
+        }
+    }
+
+    /// The expected number of enodes matching `op` in an eclass, that
+    /// is, the candidates a `Bind` of `op` finds per row.
+    pub(crate) fn fanout(&self, op: &L) -> f64 {
+        self.fraction(self.count(op).nodes)
+    }
+
+    /// The probability that two eclasses are the same, that is, that a
+    /// `Compare` passes.
+    pub(crate) fn compare(&self) -> f64 {
+        self.fraction(1)
+    }
+
+    /// The probability that a `Lookup` of a ground term with the given
+    /// enodes finds it in the eclass it checks.
+    ///
+    /// This is zero if one of the operators isn't in the egraph, and
+    /// otherwise the fraction of eclasses with each operator that has
+    /// children, multiplied together.
+    pub(crate) fn lookup<'a>(&self, nodes: impl IntoIterator<Item = &'a L>) -> f64
+    where
+        L: 'a,
+    {
+        let mut selectivity = 1.0;
+        for node in nodes {
+            let count = self.count(node);
+            if count.nodes == 0 {
+                return 0.0;
+            } else if !node.is_leaf() {
+                selectivity *= self.fraction(count.classes);
+            }
+        }
+        selectivity
+    }
+}
+
+#[allow(clippy::mem_discriminant_non_enum)]
+fn same_variant<L>(a: &L, b: &L) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b)
+}
+
+fn zeroed<L: Language>(node: &L) -> L {
+    node.clone().map_children(|_| Id::from(0))
+}
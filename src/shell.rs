@@ -0,0 +1,261 @@
+/*!
+Building blocks for interactive egraph exploration.
+
+A [`Session`] holds an egraph, the expressions added to it, and a set
+of rules, and exposes the usual steps of exploring an egraph as
+commands that each return a printable `String`.
+Any command line, notebook, or other frontend can wrap them uniformly.
+
+This module is only available with the `shell` feature.
+
+# Example
+
+```
+use egg::{shell::Session, *};
+
+let mut session: Session<SymbolLang, ()> = Session::default();
+session.add_rules("mul-1: (* ?x 1) => ?x").unwrap();
+assert_eq!(session.add_expr("(* a 1)").unwrap(), "root 0: eclass 2");
+session.run(5).unwrap();
+assert_eq!(session.search("(* ?x 1)").unwrap().lines().count(), 1);
+assert_eq!(session.extract(0, AstSize).unwrap(), "a (cost 1)");
+```
+!*/
+
+use std::fmt::{Debug, Display, Write};
+use std::path::Path;
+
+use crate::*;
+
+fn parse_error(e: impl Debug) -> Error {
+    Error::Parse(format!("{:?}", e))
+}
+
+/// The result of a [`Session`] command.
+pub type SessionResult = Result<String, Error>;
+
+/// The state of an interactive egraph exploration.
+///
+/// The session's [`Runner`] is kept between calls to
+/// [`run`](Session::run), so its egraph, iterations and rule scheduling
+/// carry over.
+/// Eclass ids are always displayed canonically, so the same eclass
+/// prints the same way in every command until it is merged.
+pub struct Session<L: Language, A: Analysis<L>> {
+    runner: Option<Runner<L, A>>,
+    rules: Vec<Rewrite<L, A>>,
+}
+
+impl<L, A> Default for Session<L, A>
+where
+    L: Language,
+    A: Analysis<L> + Default,
+{
+    fn default() -> Self {
+        Self::new(A::default())
+    }
+}
+
+impl<L: Language, A: Analysis<L>> Session<L, A> {
+    /// Create a session with an empty egraph with the given analysis,
+    /// and no rules.
+    pub fn new(analysis: A) -> Self {
+        Self {
+            runner: Some(Runner::new(analysis)),
+            rules: vec![],
+        }
+    }
+
+    /// Add rules to the session.
+    pub fn with_rules(mut self, rules: impl IntoIterator<Item = Rewrite<L, A>>) -> Self {
+        self.rules.extend(rules);
+        self
+    }
+
+    fn runner(&self) -> &Runner<L, A> {
+        self.runner.as_ref().unwrap()
+    }
+
+    fn runner_mut(&mut self) -> &mut Runner<L, A> {
+        self.runner.as_mut().unwrap()
+    }
+
+    /// The session's egraph.
+    pub fn egraph(&self) -> &EGraph<L, A> {
+        &self.runner().egraph
+    }
+
+    /// The session's rules.
+    pub fn rules(&self) -> &[Rewrite<L, A>] {
+        &self.rules
+    }
+
+    /// The eclasses of the expressions added with
+    /// [`add_expr`](Session::add_expr), in order.
+    pub fn roots(&self) -> &[Id] {
+        &self.runner().roots
+    }
+
+    fn root(&self, root: usize) -> Result<Id, Error> {
+        let roots = self.roots();
+        let id = roots.get(root).ok_or(Error::NoRoot {
+            index: root,
+            roots: roots.len(),
+        })?;
+        Ok(self.egraph().find(*id))
+    }
+
+    /// Run the rules for at most `iterations` more iterations, or until
+    /// the runner's other limits are hit.
+    pub fn run(&mut self, iterations: usize) -> SessionResult {
+        let mut runner = self.runner.take().unwrap();
+        let before = runner.iterations.len();
+        runner.resume(iterations);
+        let runner = runner.run(&self.rules);
+        let out = format!(
+            "ran {} iterations, stopped: {:?}, {} nodes, {} classes",
+            runner.iterations.len() - before,
+            runner.stop_reason.as_ref().unwrap(),
+            runner.egraph.total_number_of_nodes(),
+            runner.egraph.number_of_classes(),
+        );
+        self.runner = Some(runner);
+        Ok(out)
+    }
+
+    /// Print the number of nodes and classes, the iterations run so
+    /// far, and the nodes and classes of each operator.
+    pub fn stats(&self) -> SessionResult {
+        let runner = self.runner();
+        let egraph = &runner.egraph;
+        let mut out = format!(
+            "{} nodes, {} classes, {} roots, {} rules, {} iterations\n",
+            egraph.total_number_of_nodes(),
+            egraph.number_of_classes(),
+            runner.roots.len(),
+            self.rules.len(),
+            runner.iterations.len(),
+        );
+        for (op, stats) in egraph.op_stats() {
+            writeln!(
+                out,
+                "{}: {} nodes, {} classes",
+                op, stats.nodes, stats.classes
+            )
+            .unwrap();
+        }
+        Ok(out)
+    }
+}
+
+impl<L, A> Session<L, A>
+where
+    L: Language + FromOp + Display + Send + Sync + 'static,
+    A: Analysis<L>,
+{
+    /// Parse rules in the syntax of [`parse_rules`] and add them to the
+    /// session.
+    pub fn add_rules(&mut self, rules: &str) -> SessionResult {
+        let rules = parse_rules(rules)?;
+        let out = format!("added {} rules", rules.len());
+        self.rules.extend(rules);
+        Ok(out)
+    }
+
+    /// Parse an expression and add it to the egraph as a new root.
+    pub fn add_expr(&mut self, expr: &str) -> SessionResult {
+        let expr: RecExpr<L> = expr.parse().map_err(parse_error)?;
+        let runner = self.runner_mut();
+        let id = runner.egraph.add_expr(&expr);
+        runner.roots.push(id);
+        let id = runner.egraph.find(id);
+        Ok(format!("root {}: eclass {}", runner.roots.len() - 1, id))
+    }
+
+    /// Search for a pattern, printing each match on its own line.
+    pub fn search(&mut self, pattern: &str) -> SessionResult {
+        let pattern: Pattern<L> = pattern.parse().map_err(parse_error)?;
+        let egraph = &mut self.runner_mut().egraph;
+        egraph.rebuild();
+        let mut out = String::new();
+        for m in pattern.search(egraph) {
+            for subst in &m.substs {
+                write!(out, "eclass {}:", egraph.find(m.eclass)).unwrap();
+                for (var, id) in subst.iter() {
+                    write!(out, " {} = {}", var, egraph.find(id)).unwrap();
+                }
+                out.push('\n');
+            }
+        }
+        Ok(out)
+    }
+
+    /// Extract the best term for the root with the given index.
+    pub fn extract<CF>(&mut self, root: usize, cost: CF) -> SessionResult
+    where
+        CF: CostFunction<L>,
+        CF::Cost: Debug,
+    {
+        let id = self.root(root)?;
+        let egraph = &mut self.runner_mut().egraph;
+        egraph.rebuild();
+        let (cost, expr) = Extractor::new(egraph, cost).try_find_best(id)?;
+        Ok(format!("{} (cost {:?})", expr, cost))
+    }
+
+    /// Write the egraph as a GraphViz dot file.
+    pub fn dot(&mut self, path: impl AsRef<Path>) -> SessionResult {
+        let egraph = &mut self.runner_mut().egraph;
+        egraph.rebuild();
+        egraph.dot().to_dot(path.as_ref())?;
+        Ok(format!("wrote {}", path.as_ref().display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_continue_on_the_same_egraph() {
+        let mut session: Session<SymbolLang, ()> = Session::default();
+        session.add_rules("comm: (+ ?x ?y) => (+ ?y ?x)").unwrap();
+        session
+            .add_rules("assoc: (+ ?x (+ ?y ?z)) => (+ (+ ?x ?y) ?z)")
+            .unwrap();
+        // there's no *, so this one is skipped
+        session.add_rules("mul-0: (* ?x 0) => 0").unwrap();
+        session.add_expr("(+ a (+ b c))").unwrap();
+
+        let out = session.run(1).unwrap();
+        assert!(out.starts_with("ran 1 iterations"), "{}", out);
+        let out = session.run(2).unwrap();
+        assert!(out.starts_with("ran 2 iterations"), "{}", out);
+        assert_eq!(session.runner().iterations.len(), 3);
+        // once per run, not once for each run so far
+        assert_eq!(session.runner().skipped_rules.len(), 1);
+
+        // ids print canonically
+        let root = session.root(0).unwrap();
+        let found = session.search("(+ (+ a b) c)").unwrap();
+        assert_eq!(found, format!("eclass {}:\n", root));
+
+        let stats = session.stats().unwrap();
+        assert!(stats.contains("3 rules, 3 iterations"), "{}", stats);
+        assert!(stats.contains("\n+: "), "{}", stats);
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let mut session: Session<SymbolLang, ()> = Session::default();
+        assert!(matches!(session.add_expr("(+ a"), Err(Error::Parse(_))));
+        assert!(matches!(
+            session.add_rules("comm: (+ ?x ?y) =>"),
+            Err(Error::Parse(_))
+        ));
+        assert!(matches!(
+            session.extract(0, AstSize),
+            Err(Error::NoRoot { index: 0, roots: 0 })
+        ));
+    }
+}
@@ -0,0 +1,377 @@
+use std::fmt::{self, Debug, Display};
+use std::sync::Arc;
+
+use crate::*;
+
+/// The sort of an enode or eclass in a [`SortedLanguage`].
+///
+/// Sorts are plain numbers; give them names with constants:
+/// ```
+/// use egg::SortId;
+/// const VALUE: SortId = SortId(0);
+/// const MEM: SortId = SortId(1);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
+pub struct SortId(pub u32);
+
+/// A [`Language`] whose enodes are sorted, and whose children must
+/// have particular sorts.
+///
+/// An [`EGraph`] with [sort checking](EGraph::with_sort_checking)
+/// enabled uses this to reject ill-sorted enodes and unions.
+/// [`define_language!`] can implement this from a `sorts` block.
+pub trait SortedLanguage: Language {
+    /// Returns the sort of this enode, and so of its eclass.
+    fn sort(&self) -> SortId;
+
+    /// Returns the sorts this enode's children must have, in order.
+    ///
+    /// For enodes with a variable number of children, a single sort
+    /// applies to all of them, and they may have none.
+    fn child_sorts(&self) -> &[SortId];
+}
+
+/// What an [`EGraph`] with [sort checking](EGraph::with_sort_checking)
+/// does about ill-sorted enodes and unions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortPolicy {
+    /// Panic with the [`SortViolation`].
+    Panic,
+    /// Record the [`SortViolation`] and keep the egraph well-sorted:
+    /// an ill-sorted enode is still added, but its eclass (and any
+    /// eclass built on top of it) can't be unioned with anything, and
+    /// unions of eclasses of different sorts are skipped.
+    Refuse,
+}
+
+/// An ill-sorted enode or union, found by an [`EGraph`] with
+/// [sort checking](EGraph::with_sort_checking) enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortViolation {
+    /// The rule being applied when the violation happened, if any.
+    pub rule: Option<Symbol>,
+    /// What was ill-sorted.
+    pub message: String,
+}
+
+impl Display for SortViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(rule) = self.rule {
+            write!(f, " (applying rule '{}')", rule)?;
+        }
+        Ok(())
+    }
+}
+
+/// Looks up the sorts of enodes, so an [`EGraph`] over any [`Language`]
+/// can hold a [`SortChecker`] for a [`SortedLanguage`].
+trait Sorts<L>: Debug + Send + Sync {
+    fn sort(&self, enode: &L) -> SortId;
+    fn child_sorts<'a>(&self, enode: &'a L) -> &'a [SortId];
+}
+
+/// The [`Sorts`] of a [`SortedLanguage`].
+#[derive(Debug)]
+struct LanguageSorts;
+
+impl<L: SortedLanguage> Sorts<L> for LanguageSorts {
+    fn sort(&self, enode: &L) -> SortId {
+        enode.sort()
+    }
+
+    fn child_sorts<'a>(&self, enode: &'a L) -> &'a [SortId] {
+        enode.child_sorts()
+    }
+}
+
+/// The sort checking state of an [`EGraph`].
+#[derive(Debug, Clone)]
+pub(crate) struct SortChecker<L> {
+    sorts: Arc<dyn Sorts<L>>,
+    policy: SortPolicy,
+    /// The canonical ids of eclasses with an ill-sorted enode, or an
+    /// enode with such a child, under [`SortPolicy::Refuse`].
+    poisoned: HashSet<Id>,
+    violations: Vec<SortViolation>,
+}
+
+impl<L> SortChecker<L> {
+    /// Forget the poisoned eclasses and violations, keeping the policy.
+    pub(crate) fn clear(&mut self) {
+        self.poisoned.clear();
+        self.violations.clear();
+    }
+}
+
+impl<L: SortedLanguage, N: Analysis<L>> EGraph<L, N> {
+    /// Enable or disable checking the sorts of new enodes and unions,
+    /// panicking on violations.
+    ///
+    /// When enabled, [`add`](EGraph::add) checks that each child of a
+    /// new enode is in an eclass of the sort
+    /// [`SortedLanguage::child_sorts`] asks for, and
+    /// [`union`](EGraph::union) checks that both eclasses have the same
+    /// sort.
+    /// An eclass has the sort of its enodes.
+    /// Violations from applying a rewrite name the rule.
+    /// Use [`with_sort_policy`](EGraph::with_sort_policy) to record
+    /// violations instead of panicking.
+    /// This is off by default.
+    ///
+    /// Enodes already in the egraph aren't checked.
+    pub fn with_sort_checking(mut self, enabled: bool) -> Self {
+        if !enabled {
+            self.sorts = None;
+        } else if self.sorts.is_none() {
+            self = self.with_sort_policy(SortPolicy::Panic);
+        }
+        self
+    }
+
+    /// Enable sort checking (see
+    /// [`with_sort_checking`](EGraph::with_sort_checking)) with the given
+    /// [`SortPolicy`].
+    pub fn with_sort_policy(mut self, policy: SortPolicy) -> Self {
+        match &mut self.sorts {
+            Some(sorts) => sorts.policy = policy,
+            None => {
+                self.sorts = Some(SortChecker {
+                    sorts: Arc::new(LanguageSorts),
+                    policy,
+                    poisoned: Default::default(),
+                    violations: vec![],
+                })
+            }
+        }
+        self
+    }
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+    /// Check if [sort checking](EGraph::with_sort_checking) is enabled.
+    pub fn is_sort_checking_enabled(&self) -> bool {
+        self.sorts.is_some()
+    }
+
+    /// Returns the sort of eclass `id`, if sort checking is enabled.
+    pub fn class_sort(&self, id: Id) -> Option<SortId> {
+        let sorts = self.sorts.as_ref()?;
+        Some(sorts.sorts.sort(&self[id].nodes[0]))
+    }
+
+    /// Returns the violations recorded under [`SortPolicy::Refuse`].
+    pub fn sort_violations(&self) -> &[SortViolation] {
+        match &self.sorts {
+            Some(sorts) => &sorts.violations,
+            None => &[],
+        }
+    }
+
+    /// Check the sorts of the children of `enode`, which is about to be
+    /// added to a new eclass.
+    ///
+    /// Returns whether the new eclass must be poisoned.
+    pub(crate) fn check_sorts_add(&mut self, enode: &L) -> bool {
+        let sorts = match &self.sorts {
+            Some(sorts) => sorts,
+            None => return false,
+        };
+        let expected = sorts.sorts.child_sorts(enode);
+        let children = enode.children();
+        let mut problem = None;
+        // a single sort may be for any number of children, even none
+        if expected.len() != children.len() && expected.len() != 1 {
+            problem = Some(format!(
+                "Enode {:?} has {} children but {} child sorts",
+                enode,
+                children.len(),
+                expected.len()
+            ));
+        }
+        for (i, &child) in children.iter().enumerate() {
+            if problem.is_some() {
+                break;
+            }
+            let want = expected[i.min(expected.len() - 1)];
+            let got = sorts.sorts.sort(&self[child].nodes[0]);
+            if want != got {
+                problem = Some(format!(
+                    "Enode {:?} expects child {} of sort {:?}, but eclass {} has sort {:?}",
+                    enode, i, want, child, got
+                ));
+            }
+        }
+        match problem {
+            Some(message) => self.sort_violation(message),
+            None => children
+                .iter()
+                .any(|&c| sorts.poisoned.contains(&self.find(c))),
+        }
+    }
+
+    /// Check the sorts of the canonical eclasses `id1` and `id2`, which
+    /// are about to be unioned (not by congruence).
+    ///
+    /// Returns whether the union may go ahead.
+    pub(crate) fn check_sorts_union(&mut self, id1: Id, id2: Id) -> bool {
+        let sorts = match &self.sorts {
+            Some(sorts) => sorts,
+            None => return true,
+        };
+        let (sort1, sort2) = (
+            sorts.sorts.sort(&self.classes[&id1].nodes[0]),
+            sorts.sorts.sort(&self.classes[&id2].nodes[0]),
+        );
+        let message = if sort1 != sort2 {
+            format!(
+                "Union of eclass {} of sort {:?} with eclass {} of sort {:?}",
+                id1, sort1, id2, sort2
+            )
+        } else if let Some(id) = [id1, id2].iter().find(|&id| sorts.poisoned.contains(id)) {
+            format!("Union with ill-sorted eclass {}", id)
+        } else {
+            return true;
+        };
+        self.sort_violation(message);
+        false
+    }
+
+    /// Mark the new eclass `id` as poisoned.
+    pub(crate) fn poison_class(&mut self, id: Id) {
+        if let Some(sorts) = &mut self.sorts {
+            sorts.poisoned.insert(id);
+        }
+    }
+
+    /// Keep poison when `from` is merged into `into` by congruence.
+    pub(crate) fn merge_poison(&mut self, into: Id, from: Id) {
+        if let Some(sorts) = &mut self.sorts {
+            if sorts.poisoned.remove(&from) {
+                sorts.poisoned.insert(into);
+            }
+        }
+    }
+
+    /// Handle a violation according to the policy, returning true.
+    fn sort_violation(&mut self, message: String) -> bool {
+        let violation = SortViolation {
+            rule: self.current_rule,
+            message,
+        };
+        let sorts = self.sorts.as_mut().unwrap();
+        match sorts.policy {
+            SortPolicy::Panic => panic!("Ill-sorted: {}", violation),
+            SortPolicy::Refuse => {
+                log::warn!("Ill-sorted: {}", violation);
+                sorts.violations.push(violation);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{rewrite as rw, *};
+
+    const VALUE: SortId = SortId(0);
+    const MEM: SortId = SortId(1);
+
+    define_language! {
+        enum Ir {
+            "load" = Load([Id; 2]),
+            "store" = Store([Id; 3]),
+            "+" = Add([Id; 2]),
+            "mem0" = Mem0,
+            "seq" = Seq(Box<[Id]>),
+            Num(i32),
+        }
+        sorts {
+            Load => VALUE(MEM, VALUE),
+            Store => MEM(MEM, VALUE, VALUE),
+            Add => VALUE(VALUE, VALUE),
+            Mem0 => MEM(),
+            Seq => VALUE(VALUE),
+            Num => VALUE(),
+        }
+    }
+
+    fn rules() -> Vec<Rewrite<Ir, ()>> {
+        vec![
+            rw!("load-store"; "(load (store ?m ?a ?v) ?a)" => "?v"),
+            // ill-sorted: a load from a value
+            rw!("bad-load"; "(load (store ?m ?a ?v) ?a)" => "(load ?v ?a)"),
+            // ill-sorted: a memory is not a value
+            rw!("bad-store"; "(store ?m ?a ?v)" => "?v"),
+        ]
+    }
+
+    fn start() -> RecExpr<Ir> {
+        "(+ (load (store mem0 1 2) 1) 3)".parse().unwrap()
+    }
+
+    #[test]
+    fn well_sorted_rules_pass() {
+        let egraph = EGraph::default().with_sort_checking(true);
+        let runner: Runner<Ir, ()> = Runner::default()
+            .with_egraph(egraph)
+            .with_expr(&start())
+            .run(&rules()[..1]);
+        let egraph = &runner.egraph;
+        let two = egraph.lookup_expr(&"2".parse().unwrap()).unwrap();
+        let load = egraph.lookup_expr(&"(load (store mem0 1 2) 1)".parse().unwrap());
+        assert_eq!(load, Some(egraph.find(two)));
+        let store = egraph.lookup_expr(&"(store mem0 1 2)".parse().unwrap());
+        assert_eq!(egraph.class_sort(store.unwrap()), Some(MEM));
+        assert_eq!(egraph.class_sort(two), Some(VALUE));
+    }
+
+    #[test]
+    fn variadic_enodes_take_any_number_of_children() {
+        let mut egraph = EGraph::<Ir, ()>::default().with_sort_checking(true);
+        let empty = egraph.add(Ir::Seq(vec![].into()));
+        let one = egraph.add(Ir::Num(1));
+        let two = egraph.add(Ir::Num(2));
+        egraph.add(Ir::Seq(vec![empty, one, two].into()));
+        assert!(egraph.sort_violations().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "rule 'bad-load'")]
+    fn ill_sorted_rule_panics() {
+        let egraph = EGraph::default().with_sort_checking(true);
+        Runner::<Ir, ()>::default()
+            .with_egraph(egraph)
+            .with_expr(&start())
+            .run(&rules()[1..2]);
+    }
+
+    #[test]
+    fn ill_sorted_rules_are_refused() {
+        let egraph = EGraph::default().with_sort_policy(SortPolicy::Refuse);
+        let runner: Runner<Ir, ()> = Runner::default()
+            .with_egraph(egraph)
+            .with_expr(&start())
+            .run(&rules()[1..]);
+        let egraph = &runner.egraph;
+
+        let rules: Vec<Symbol> = egraph
+            .sort_violations()
+            .iter()
+            .filter_map(|v| v.rule)
+            .collect();
+        assert!(rules.contains(&"bad-load".into()));
+        assert!(rules.contains(&"bad-store".into()));
+
+        // the garbage was added, but never joined the well-sorted terms
+        let load = egraph.lookup_expr(&"(load (store mem0 1 2) 1)".parse().unwrap());
+        let garbage = egraph.lookup_expr(&"(load 2 1)".parse().unwrap());
+        assert!(garbage.is_some());
+        assert_ne!(load, garbage);
+        let store = egraph.lookup_expr(&"(store mem0 1 2)".parse().unwrap());
+        assert_eq!(egraph.class_sort(store.unwrap()), Some(MEM));
+        assert_eq!(egraph[store.unwrap()].len(), 1);
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use crate::*;
 use fmt::{Debug, Display, Formatter};
@@ -79,6 +80,56 @@ impl Subst {
             .iter()
             .find_map(|(v, id)| if *v == var { Some(id) } else { None })
     }
+
+    /// Iterate over the bound `Var`s and their `Id`s, in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (Var, Id)> + '_ {
+        self.vec.iter().copied()
+    }
+
+    /// Replace each `Id` with its canonical one in `egraph`.
+    pub fn canonicalize<L: Language, N: Analysis<L>>(&mut self, egraph: &EGraph<L, N>) {
+        for (_, id) in &mut self.vec {
+            *id = egraph.find(*id);
+        }
+    }
+
+    /// Check if `self` and `other` bind the same `Var`s to the same
+    /// eclasses of `egraph`, in any order.
+    ///
+    /// Unlike `==`, this doesn't need the `Id`s to be canonical.
+    pub fn equiv<L: Language, N: Analysis<L>>(&self, other: &Self, egraph: &EGraph<L, N>) -> bool {
+        self.vec.len() == other.vec.len()
+            && self.iter().all(|(var, id)| {
+                other
+                    .get(var)
+                    .map_or(false, |&o| egraph.find(o) == egraph.find(id))
+            })
+    }
+}
+
+/// A [`Subst`] with canonical `Id`s, in a fixed order, to use as a key
+/// when deduplicating matches.
+///
+/// Two of these are equal exactly when their [`Subst`]s were
+/// [equivalent](Subst::equiv) in the egraph they were made from, at the
+/// time they were made.
+/// Unions since then can make more of them equivalent, so keys made
+/// before and after a union shouldn't be mixed.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HashableCanonicalSubst {
+    vec: smallvec::SmallVec<[(Var, Id); 3]>,
+}
+
+impl HashableCanonicalSubst {
+    /// Canonicalize `subst` against `egraph`.
+    pub fn new<L: Language, N: Analysis<L>>(subst: &Subst, egraph: &EGraph<L, N>) -> Self {
+        let mut vec = subst.vec.clone();
+        for (_, id) in &mut vec {
+            *id = egraph.find(*id);
+        }
+        vec.sort_unstable_by_key(|&(var, _)| var);
+        Self { vec }
+    }
 }
 
 impl std::ops::Index<Var> for Subst {
@@ -107,6 +158,340 @@ impl Debug for Subst {
     }
 }
 
+/// A compact substitution for patterns with many variables.
+///
+/// A [`Subst`] stores each [`Var`] next to its [`Id`].
+/// A [`PackedSubst`] only stores the [`Id`]s, and shares the list of
+/// [`Var`]s (its schema) with every other match of the same pattern.
+/// Get them from [`Pattern::search_eclass_packed`], and convert them with
+/// [`Bindings::to_subst`] where a [`Subst`] is needed.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PackedSubst {
+    schema: Arc<[Var]>,
+    ids: Box<[Id]>,
+}
+
+impl PackedSubst {
+    /// Create a [`PackedSubst`] binding each [`Var`] in `schema` to the
+    /// [`Id`] at the same position in `ids`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `schema` and `ids` have different lengths.
+    pub fn new(schema: Arc<[Var]>, ids: Box<[Id]>) -> Self {
+        assert_eq!(schema.len(), ids.len(), "schema and ids differ in length");
+        Self { schema, ids }
+    }
+
+    /// The [`Var`]s this binds, shared with other [`PackedSubst`]s.
+    pub fn schema(&self) -> &Arc<[Var]> {
+        &self.schema
+    }
+
+    /// Retrieve a `Var`, returning `None` if not present.
+    pub fn get(&self, var: Var) -> Option<&Id> {
+        let i = self.schema.iter().position(|&v| v == var)?;
+        Some(&self.ids[i])
+    }
+
+    /// Iterate over the bound `Var`s and their `Id`s, in schema order.
+    pub fn iter(&self) -> impl Iterator<Item = (Var, Id)> + '_ {
+        self.schema.iter().copied().zip(self.ids.iter().copied())
+    }
+}
+
+impl std::ops::Index<Var> for PackedSubst {
+    type Output = Id;
+
+    fn index(&self, var: Var) -> &Self::Output {
+        match self.get(var) {
+            Some(id) => id,
+            None => panic!("Var '{}={}' not found in {:?}", var.0, var, self),
+        }
+    }
+}
+
+impl Debug for PackedSubst {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (var, id)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", var, id)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// Something that binds [`Var`]s to [`Id`]s: a [`Subst`], a
+/// [`PackedSubst`] or an [`ArenaSubst`].
+///
+/// Instantiating patterns, like [`EGraph::add_instantiation`] does,
+/// works with any of them, and so does a [`Pattern`] applying the
+/// matches of a [`Runner`], which are stored in a [`MatchArena`].
+///
+/// The other public hooks that see matches, [`Applier::apply_one`],
+/// [`Condition::check`] and [`MatchRanker::score`], still take a
+/// [`Subst`], so a match is copied with
+/// [`to_subst`](Bindings::to_subst) before they're called.
+pub trait Bindings: Debug {
+    /// Retrieve a `Var`, returning `None` if not present.
+    fn get(&self, var: Var) -> Option<&Id>;
+
+    /// Iterate over the bound `Var`s and their `Id`s.
+    fn iter(&self) -> Box<dyn Iterator<Item = (Var, Id)> + '_>;
+
+    /// Retrieve a `Var` like indexing a [`Subst`] does.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `var` isn't bound.
+    fn id_of(&self, var: Var) -> Id {
+        match self.get(var) {
+            Some(id) => *id,
+            None => panic!("Var '{}' not found in {:?}", var, self),
+        }
+    }
+
+    /// Copy these bindings into a [`Subst`].
+    fn to_subst(&self) -> Subst {
+        Subst {
+            vec: self.iter().collect(),
+        }
+    }
+}
+
+impl Bindings for Subst {
+    fn get(&self, var: Var) -> Option<&Id> {
+        Subst::get(self, var)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Var, Id)> + '_> {
+        Box::new(Subst::iter(self))
+    }
+
+    fn to_subst(&self) -> Subst {
+        self.clone()
+    }
+}
+
+impl Bindings for PackedSubst {
+    fn get(&self, var: Var) -> Option<&Id> {
+        PackedSubst::get(self, var)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Var, Id)> + '_> {
+        Box::new(PackedSubst::iter(self))
+    }
+}
+
+/// Contiguous storage for the substitutions found by searches.
+///
+/// Searching with [`Searcher::search_into`] appends the [`Id`]s of each
+/// match to one buffer, instead of allocating a [`Subst`] for each
+/// match and a `Vec` for each eclass.
+/// The matches of an eclass are a [`SubstRange`] of the arena; read
+/// them with [`iter`](MatchArena::iter), which yields [`ArenaSubst`]s.
+///
+/// A [`Runner`] keeps one arena, which it clears at the start of each
+/// iteration, so the buffer is reused from one iteration to the next.
+///
+/// An arena can also bound the work of the searches into it with a
+/// [step budget](MatchArena::set_step_budget).
+#[derive(Debug, Default)]
+pub struct MatchArena {
+    pub(crate) ids: Vec<Id>,
+    /// Reused by the programs of [`Pattern`]s searching into this arena.
+    pub(crate) machine: crate::machine::Machine,
+}
+
+/// A run of substitutions over the same [`Var`]s, stored in a
+/// [`MatchArena`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubstRange {
+    schema: Arc<[Var]>,
+    start: usize,
+    len: usize,
+}
+
+impl SubstRange {
+    /// The number of substitutions in this range.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if this range has no substitutions.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The [`Var`]s bound by each substitution in this range.
+    pub fn schema(&self) -> &Arc<[Var]> {
+        &self.schema
+    }
+
+    /// Keep only the first `len` substitutions of this range.
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+}
+
+impl MatchArena {
+    /// Create an empty arena.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget all stored substitutions, keeping the allocated memory.
+    ///
+    /// Any [`SubstRange`]s into this arena are invalidated.
+    pub fn clear(&mut self) {
+        self.ids.clear();
+    }
+
+    /// Limit the [`Pattern`] searches into this arena to `steps` more
+    /// steps between them, or lift the limit with `None`.
+    ///
+    /// A step is binding an enode, checking a subpattern or scanning an
+    /// eclass, so this bounds the work of patterns with exponentially
+    /// many matches, like those of associativity and commutativity.
+    /// Once the budget runs out, searches stop early with the matches
+    /// they found so far, and
+    /// [`step_budget_exhausted`](MatchArena::step_budget_exhausted)
+    /// returns `true`.
+    /// The budget isn't reset by [`clear`](MatchArena::clear).
+    /// Other [`Searcher`]s don't take steps, so they aren't limited.
+    pub fn set_step_budget(&mut self, steps: Option<usize>) {
+        self.machine.step_budget = steps;
+        self.machine.out_of_steps = false;
+    }
+
+    /// Start profiling where the [`Pattern`] searches into this arena
+    /// backtrack, or stop, forgetting the profile so far.
+    pub(crate) fn set_profiling(&mut self, enabled: bool) {
+        self.machine.bind_counts = if enabled { Some(vec![]) } else { None };
+        self.machine.bind_profile.clear();
+    }
+
+    /// Take the profile of the searches since the last take: for each
+    /// node of the searched pattern that a `Bind` matched, how many
+    /// candidate enodes it bound and backtracked from.
+    pub(crate) fn take_profile(&mut self) -> HashMap<Id, crate::machine::BindProfile> {
+        std::mem::take(&mut self.machine.bind_profile)
+    }
+
+    /// Whether a search into this arena stopped early because the
+    /// [step budget](MatchArena::set_step_budget) ran out, so its
+    /// matches, and those of any search since, may be incomplete.
+    pub fn step_budget_exhausted(&self) -> bool {
+        self.machine.out_of_steps
+    }
+
+    /// Start a new, empty range binding the [`Var`]s in `schema`, to be
+    /// filled with [`push`](MatchArena::push).
+    pub fn start_range(&self, schema: Arc<[Var]>) -> SubstRange {
+        SubstRange {
+            schema,
+            start: self.ids.len(),
+            len: 0,
+        }
+    }
+
+    /// Append a substitution to `range`, which must be the last range
+    /// started in this arena, binding the range's schema to `ids` in
+    /// order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` isn't the last range, or if `ids` doesn't have
+    /// one [`Id`] for each [`Var`] in the schema.
+    pub fn push(&mut self, range: &mut SubstRange, ids: impl IntoIterator<Item = Id>) {
+        assert_eq!(
+            range.start + range.len * range.schema.len(),
+            self.ids.len(),
+            "can only push to the last range of a MatchArena"
+        );
+        self.ids.extend(ids);
+        assert_eq!(
+            range.start + (range.len + 1) * range.schema.len(),
+            self.ids.len(),
+            "ids don't match the schema {:?}",
+            range.schema
+        );
+        range.len += 1;
+    }
+
+    /// Returns the `i`th substitution of `range`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `i` is out of bounds.
+    pub fn get<'a>(&'a self, range: &'a SubstRange, i: usize) -> ArenaSubst<'a> {
+        assert!(i < range.len, "no substitution {} in {:?}", i, range);
+        let width = range.schema.len();
+        let start = range.start + i * width;
+        ArenaSubst {
+            schema: &range.schema,
+            ids: &self.ids[start..start + width],
+        }
+    }
+
+    /// Iterate over the substitutions of `range`.
+    pub fn iter<'a>(&'a self, range: &'a SubstRange) -> impl Iterator<Item = ArenaSubst<'a>> {
+        (0..range.len).map(move |i| self.get(range, i))
+    }
+
+    /// Copy the substitutions of `range` out of this arena.
+    pub fn to_substs(&self, range: &SubstRange) -> Vec<Subst> {
+        self.iter(range).map(|subst| subst.to_subst()).collect()
+    }
+}
+
+/// A substitution borrowed from a [`MatchArena`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ArenaSubst<'a> {
+    schema: &'a [Var],
+    ids: &'a [Id],
+}
+
+impl<'a> ArenaSubst<'a> {
+    /// Retrieve a `Var`, returning `None` if not present.
+    pub fn get(&self, var: Var) -> Option<&'a Id> {
+        let i = self.schema.iter().position(|&v| v == var)?;
+        Some(&self.ids[i])
+    }
+
+    /// Iterate over the bound `Var`s and their `Id`s, in schema order.
+    pub fn iter(&self) -> impl Iterator<Item = (Var, Id)> + 'a {
+        let (schema, ids) = (self.schema, self.ids);
+        schema.iter().copied().zip(ids.iter().copied())
+    }
+}
+
+impl Debug for ArenaSubst<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (var, id)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", var, id)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl Bindings for ArenaSubst<'_> {
+    fn get(&self, var: Var) -> Option<&Id> {
+        ArenaSubst::get(self, var)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (Var, Id)> + '_> {
+        Box::new(ArenaSubst::iter(self))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -119,4 +504,57 @@ mod tests {
         assert!(Var::from_str("a?").is_err());
         assert!(Var::from_str("?").is_err());
     }
+
+    #[test]
+    fn substs_compare_through_unions() {
+        let mut egraph = EGraph::<SymbolLang, ()>::default();
+        let ids: Vec<Id> = ["a", "b", "c"]
+            .iter()
+            .map(|s| egraph.add(SymbolLang::leaf(*s)))
+            .collect();
+        let (a, b, c) = (ids[0], ids[1], ids[2]);
+        let x: Var = "?x".parse().unwrap();
+        let y: Var = "?y".parse().unwrap();
+        let subst = |pairs: &[(Var, Id)]| {
+            let mut subst = Subst::default();
+            for &(var, id) in pairs {
+                subst.insert(var, id);
+            }
+            subst
+        };
+
+        let xa_yc = subst(&[(x, a), (y, c)]);
+        let yc_xa = subst(&[(y, c), (x, a)]);
+        let xb_yc = subst(&[(x, b), (y, c)]);
+        assert_ne!(xa_yc, yc_xa);
+        assert!(xa_yc.equiv(&yc_xa, &egraph));
+        assert!(!xa_yc.equiv(&xb_yc, &egraph));
+        assert!(!xa_yc.equiv(&subst(&[(x, a)]), &egraph));
+        let before = HashableCanonicalSubst::new(&xb_yc, &egraph);
+        assert_eq!(
+            HashableCanonicalSubst::new(&xa_yc, &egraph),
+            HashableCanonicalSubst::new(&yc_xa, &egraph),
+        );
+        assert_ne!(HashableCanonicalSubst::new(&xa_yc, &egraph), before);
+
+        // a union makes them equivalent straight away, without a rebuild
+        egraph.union(a, b);
+        assert!(xa_yc.equiv(&xb_yc, &egraph));
+        let after = HashableCanonicalSubst::new(&xb_yc, &egraph);
+        assert_eq!(HashableCanonicalSubst::new(&xa_yc, &egraph), after);
+        // but keys from before the union are stale if b isn't canonical
+        assert_eq!(before == after, egraph.find(b) == b);
+
+        let mut canonical = xb_yc.clone();
+        canonical.canonicalize(&egraph);
+        assert_eq!(canonical[x], egraph.find(a));
+        assert_eq!(canonical[y], c);
+        assert!(canonical.equiv(&xa_yc, &egraph));
+        egraph.rebuild();
+        let keys: HashSet<_> = [xa_yc, yc_xa, xb_yc, canonical]
+            .iter()
+            .map(|s| HashableCanonicalSubst::new(s, &egraph))
+            .collect();
+        assert_eq!(keys.len(), 1);
+    }
 }
@@ -0,0 +1,84 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use crate::Id;
+
+/// The tag of the [`EGraph`](crate::EGraph) an [`Id`] came from.
+///
+/// With the `tagged-ids` feature in debug builds, each egraph gets a
+/// fresh tag, the ids it hands out carry it, and it panics when given
+/// an id with another egraph's tag.
+/// Otherwise this is zero-sized, so an [`Id`] is just its index.
+///
+/// Tags never take part in comparing or hashing ids.
+#[cfg(all(feature = "tagged-ids", debug_assertions))]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct IdTag(u32);
+
+#[cfg(not(all(feature = "tagged-ids", debug_assertions)))]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct IdTag;
+
+#[cfg(all(feature = "tagged-ids", debug_assertions))]
+impl IdTag {
+    /// The tag of ids that didn't come from an egraph, like those made
+    /// with `Id::from`, which every egraph accepts.
+    /// Egraphs with this tag accept every id.
+    pub(crate) const NONE: IdTag = IdTag(0);
+
+    /// A tag no other egraph has.
+    pub(crate) fn fresh() -> IdTag {
+        use std::sync::atomic::{self, AtomicU32};
+        static NEXT: AtomicU32 = AtomicU32::new(1);
+        IdTag(NEXT.fetch_add(1, atomic::Ordering::Relaxed))
+    }
+
+    /// Panics if `id` came from an egraph other than the one with this
+    /// tag.
+    pub(crate) fn check(self, id: Id) {
+        let tag = id.1 .0;
+        if self.0 != 0 && tag != 0 && tag != self.0 {
+            panic!(
+                "Id {} belongs to a different EGraph (tag {}) than this one (tag {})",
+                id, tag, self.0
+            );
+        }
+    }
+}
+
+#[cfg(not(all(feature = "tagged-ids", debug_assertions)))]
+impl IdTag {
+    pub(crate) const NONE: IdTag = IdTag;
+
+    #[inline(always)]
+    pub(crate) fn fresh() -> IdTag {
+        IdTag
+    }
+
+    #[inline(always)]
+    pub(crate) fn check(self, _id: Id) {}
+}
+
+impl PartialEq for IdTag {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for IdTag {}
+
+impl PartialOrd for IdTag {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IdTag {
+    fn cmp(&self, _other: &Self) -> Ordering {
+        Ordering::Equal
+    }
+}
+
+impl Hash for IdTag {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
@@ -0,0 +1,284 @@
+use std::fmt::Write;
+
+use crate::*;
+
+/// What [`EGraph::class_term`] writes when a term would contain itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CyclePolicy {
+    /// Write `<cycle e42>`, naming the eclass the term cycles back to.
+    Placeholder,
+    /// Write `(...)`, as if the budget ran out.
+    Elide,
+}
+
+/// How much of a term [`EGraph::class_term`] writes before eliding the
+/// rest as `(...)`, so printing a cyclic or huge eclass is bounded.
+///
+/// The default writes at most 100 enodes and placeholders, at most 16
+/// deep, with [`CyclePolicy::Placeholder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TermBudget {
+    /// The most enodes and placeholders to write.
+    pub max_nodes: usize,
+    /// The deepest enode to write, the root being at depth 0.
+    pub max_depth: usize,
+    /// What to write for an eclass inside itself.
+    pub on_cycle: CyclePolicy,
+}
+
+impl Default for TermBudget {
+    fn default() -> Self {
+        Self {
+            max_nodes: 100,
+            max_depth: 16,
+            on_cycle: CyclePolicy::Placeholder,
+        }
+    }
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+    /// Write a term of eclass `id` as an s-expression, within `budget`,
+    /// for debugging.
+    ///
+    /// Each eclass is written as its first enode whose children don't
+    /// lead straight back to an eclass being written, if any, so this
+    /// doesn't need an [`Extractor`] and works on a dirty egraph.
+    /// Past the budget's depth, subterms are written as `(...)`, and once
+    /// it has written as many enodes and placeholders as it allows, the
+    /// rest of the children as `...`.
+    /// An eclass inside itself is written as
+    /// [`on_cycle`](TermBudget::on_cycle) says.
+    ///
+    /// # Example
+    /// ```
+    /// use egg::{*, SymbolLang as S};
+    /// let mut egraph = EGraph::<S, ()>::default();
+    /// let x = egraph.add(S::leaf("x"));
+    /// let fx = egraph.add(S::new("f", vec![x]));
+    /// let gfx = egraph.add(S::new("g", vec![fx, fx]));
+    /// egraph.union(x, gfx);
+    /// egraph.rebuild();
+    ///
+    /// let budget = TermBudget::default();
+    /// assert_eq!(egraph.class_term(fx, &budget), "(f x)");
+    /// let shallow = TermBudget { max_depth: 0, ..budget };
+    /// assert_eq!(egraph.class_term(fx, &shallow), "(f (...))");
+    /// let tiny = TermBudget { max_nodes: 1, ..budget };
+    /// assert_eq!(egraph.class_term(fx, &tiny), "(f ...)");
+    /// ```
+    pub fn class_term(&self, id: Id, budget: &TermBudget) -> String {
+        // the ids on the path and the children are canonical
+        write_budgeted(self.find(id), budget, |id, path| {
+            if path.contains(&id) {
+                return match budget.on_cycle {
+                    CyclePolicy::Placeholder => Subterm::Placeholder(format!("<cycle e{}>", id)),
+                    CyclePolicy::Elide => Subterm::Placeholder("(...)".into()),
+                };
+            }
+            let nodes = &self[id].nodes;
+            let acyclic = |node: &&L| {
+                node.all(|child| {
+                    let child = self.find(child);
+                    child != id && !path.contains(&child)
+                })
+            };
+            match nodes.iter().find(acyclic).or_else(|| nodes.first()) {
+                Some(node) => {
+                    let children = node.children().iter().map(|&c| self.find(c)).collect();
+                    Subterm::Node(node.op_display(), children)
+                }
+                None => Subterm::Placeholder(format!("<empty e{}>", id)),
+            }
+        })
+    }
+}
+
+/// Write `expr` as an s-expression within `budget`, for messages that
+/// show a term.
+pub(crate) fn expr_term<L: Language>(expr: &RecExpr<L>, budget: &TermBudget) -> String {
+    let nodes = expr.as_ref();
+    match nodes.len().checked_sub(1) {
+        Some(root) => write_budgeted(root, budget, |i, _| {
+            let children = nodes[i]
+                .children()
+                .iter()
+                .map(|&c| usize::from(c))
+                .collect();
+            Subterm::Node(nodes[i].op_display(), children)
+        }),
+        None => String::new(),
+    }
+}
+
+/// Write `term`, without its rewrites, as an s-expression within
+/// `budget`, for messages that show a term of an explanation.
+pub(crate) fn flat_term<L: Language>(term: &FlatTerm<L>, budget: &TermBudget) -> String {
+    write_budgeted(term, budget, |t, _| {
+        Subterm::Node(t.node.op_display(), t.children.iter().collect())
+    })
+}
+
+/// What [`write_term`] writes for a subterm.
+enum Subterm<'a, T> {
+    /// An operator and its children.
+    Node(&'a str, Vec<T>),
+    /// Text to write instead, like `<cycle e42>`.
+    Placeholder(String),
+}
+
+/// Write the term at `root` within `budget`, where `expand` says what
+/// to write for a subterm, given the subterms it's inside.
+fn write_budgeted<'a, T: Copy>(
+    root: T,
+    budget: &TermBudget,
+    mut expand: impl FnMut(T, &[T]) -> Subterm<'a, T>,
+) -> String {
+    let mut out = String::new();
+    let mut nodes_left = budget.max_nodes;
+    write_term(
+        root,
+        budget,
+        &mut expand,
+        &mut vec![],
+        &mut nodes_left,
+        &mut out,
+    );
+    out
+}
+
+fn write_term<'a, T: Copy>(
+    t: T,
+    budget: &TermBudget,
+    expand: &mut impl FnMut(T, &[T]) -> Subterm<'a, T>,
+    path: &mut Vec<T>,
+    nodes_left: &mut usize,
+    out: &mut String,
+) {
+    // placeholders take from the budget too, so a node with many
+    // children is cut short
+    if *nodes_left == 0 {
+        out.push_str("(...)");
+        return;
+    }
+    *nodes_left -= 1;
+    let (op, children) = match expand(t, path) {
+        Subterm::Node(op, children) => (op, children),
+        Subterm::Placeholder(text) => {
+            out.push_str(&text);
+            return;
+        }
+    };
+    if path.len() > budget.max_depth {
+        out.push_str("(...)");
+        return;
+    }
+    if children.is_empty() {
+        out.push_str(op);
+        return;
+    }
+    write!(out, "({}", op).unwrap();
+    path.push(t);
+    for child in children {
+        if *nodes_left == 0 {
+            out.push_str(" ...");
+            break;
+        }
+        out.push(' ');
+        write_term(child, budget, expand, path, nodes_left, out);
+    }
+    path.pop();
+    out.push(')');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolLang as S, *};
+
+    #[test]
+    fn cyclic_classes_are_cut() {
+        // x = (f x)
+        let mut egraph = EGraph::<S, ()>::default();
+        let x = egraph.add(S::leaf("x"));
+        let fx = egraph.add(S::new("f", vec![x]));
+        let g = egraph.add(S::new("g", vec![fx, x]));
+        egraph.union(x, fx);
+        egraph.rebuild();
+
+        let budget = TermBudget::default();
+        assert_eq!(egraph.class_term(x, &budget), "x");
+        assert_eq!(egraph.class_term(g, &budget), "(g x x)");
+
+        // with no leaf to fall back on, the cycle is cut
+        egraph
+            .classes_mut()
+            .for_each(|c| c.nodes.retain(|n| !n.is_leaf()));
+        let x = egraph.find(x);
+        let cycle = format!("(f <cycle e{}>)", x);
+        assert_eq!(egraph.class_term(x, &budget), cycle);
+        let elide = TermBudget {
+            on_cycle: CyclePolicy::Elide,
+            ..budget
+        };
+        assert_eq!(egraph.class_term(x, &elide), "(f (...))");
+    }
+
+    #[test]
+    fn empty_classes_get_a_placeholder() {
+        let mut egraph = EGraph::<S, ()>::default();
+        let x = egraph.add(S::leaf("x"));
+        let fx = egraph.add(S::new("f", vec![x]));
+        egraph[x].nodes.clear();
+
+        let budget = TermBudget::default();
+        assert_eq!(egraph.class_term(x, &budget), format!("<empty e{}>", x));
+        assert_eq!(
+            egraph.class_term(fx, &budget),
+            format!("(f <empty e{}>)", x)
+        );
+    }
+
+    #[test]
+    fn exprs_and_explanation_terms_are_bounded() {
+        let mut expr = RecExpr::default();
+        let mut id = expr.add(S::leaf("x"));
+        for _ in 0..1000 {
+            id = expr.add(S::new("f", vec![id]));
+        }
+        let budget = TermBudget::default();
+        let term = super::expr_term(&expr, &budget);
+        assert_eq!(term.matches('f').count(), budget.max_depth + 1);
+        assert!(term.contains("(...)"));
+        assert_eq!(super::expr_term(&RecExpr::<S>::default(), &budget), "");
+
+        let mut flat = FlatTerm::new(S::leaf("x"), vec![]);
+        for _ in 0..1000 {
+            flat = FlatTerm::new(S::new("f", vec![0.into()]), vec![flat]);
+        }
+        assert_eq!(super::flat_term(&flat, &budget), term);
+    }
+
+    #[test]
+    fn huge_terms_are_bounded() {
+        // a chain of a million fs
+        let mut egraph = EGraph::<S, ()>::default();
+        let mut id = egraph.add(S::leaf("x"));
+        for _ in 0..1_000_000 {
+            id = egraph.add(S::new("f", vec![id]));
+        }
+        let budget = TermBudget::default();
+        let term = egraph.class_term(id, &budget);
+        assert!(term.len() < 100, "{}", term);
+        assert_eq!(term.matches('f').count(), budget.max_depth + 1);
+        assert!(term.contains("(...)"));
+
+        // and a node with a million children
+        let leaves: Vec<Id> = (0..1_000_000)
+            .map(|i| egraph.add(S::leaf(format!("x{}", i))))
+            .collect();
+        let wide = egraph.add(S::new("list", leaves));
+        let term = egraph.class_term(wide, &budget);
+        assert!(term.len() < 1000, "{}", term.len());
+        assert_eq!(term.matches(" x").count(), 99);
+        assert!(term.ends_with(" ...)"));
+    }
+}
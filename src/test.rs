@@ -104,6 +104,30 @@ pub fn test_runner<L, A>(
     }
 }
 
+/// Ten `(g a{i})`s in one eclass, and an `f` of it `arity` times, which
+/// [`explosive_pattern`] matches `10^arity` ways.
+pub fn explosive_egraph(arity: usize) -> (EGraph<SymbolLang, ()>, Id) {
+    let mut egraph = EGraph::default();
+    let gs: Vec<Id> = (0..10)
+        .map(|i| {
+            let a = egraph.add(SymbolLang::leaf(format!("a{}", i)));
+            egraph.add(SymbolLang::new("g", vec![a]))
+        })
+        .collect();
+    for &g in &gs[1..] {
+        egraph.union(gs[0], g);
+    }
+    let root = egraph.add(SymbolLang::new("f", vec![gs[0]; arity]));
+    egraph.rebuild();
+    (egraph, root)
+}
+
+/// `(f (g ?x0) (g ?x1) ...)`, with `arity` `g`s.
+pub fn explosive_pattern(arity: usize) -> Pattern<SymbolLang> {
+    let args: Vec<String> = (0..arity).map(|i| format!("(g ?x{})", i)).collect();
+    format!("(f {})", args.join(" ")).parse().unwrap()
+}
+
 fn percentile(k: f64, data: &[u128]) -> u128 {
     // assumes data is sorted
     assert!((0.0..=1.0).contains(&k));
@@ -1,32 +1,94 @@
-use crate::Id;
+use crate::{Id, IdTag};
 use std::fmt::Debug;
 
 #[derive(Debug, Clone, Default)]
 #[cfg_attr(feature = "serde-1", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnionFind {
     parents: Vec<Id>,
+    /// The tag of the ids this hands out and accepts, see [`Id`].
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    tag: IdTag,
+    /// The entries overwritten since [`start_undo`](UnionFind::start_undo),
+    /// with their old parents, if recording them.
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    undo: Option<Vec<(Id, Id)>>,
 }
 
 impl UnionFind {
+    /// An empty union-find whose ids carry a tag no other has.
+    pub fn tagged() -> Self {
+        Self {
+            parents: vec![],
+            tag: IdTag::fresh(),
+            undo: None,
+        }
+    }
+
+    /// Panics if `id` came from another union-find, see [`Id`].
+    pub fn check_tag(&self, id: Id) {
+        self.tag.check(id)
+    }
+
     pub fn make_set(&mut self) -> Id {
-        let id = Id::from(self.parents.len());
+        let id = Id::from(self.parents.len()).tagged(self.tag);
         self.parents.push(id);
         id
     }
 
+    /// Forget every set, keeping the tag.
+    pub fn clear(&mut self) {
+        self.parents.clear();
+    }
+
     pub fn size(&self) -> usize {
         self.parents.len()
     }
 
+    pub fn parents(&self) -> &[Id] {
+        &self.parents
+    }
+
+    pub fn from_parents(parents: Vec<Id>) -> Self {
+        Self {
+            parents,
+            tag: IdTag::NONE,
+            undo: None,
+        }
+    }
+
     fn parent(&self, query: Id) -> Id {
         self.parents[usize::from(query)]
     }
 
-    fn parent_mut(&mut self, query: Id) -> &mut Id {
-        &mut self.parents[usize::from(query)]
+    fn set_parent(&mut self, query: Id, parent: Id) {
+        let entry = &mut self.parents[usize::from(query)];
+        if let Some(undo) = &mut self.undo {
+            undo.push((query, *entry));
+        }
+        *entry = parent;
+    }
+
+    /// Start recording the entries that are overwritten, so that
+    /// [`roll_back`](UnionFind::roll_back) can undo them.
+    pub fn start_undo(&mut self) {
+        self.undo = Some(vec![]);
+    }
+
+    pub fn stop_undo(&mut self) {
+        self.undo = None;
+    }
+
+    /// Undo every change since [`start_undo`](UnionFind::start_undo),
+    /// given the size then, and stop recording.
+    pub fn roll_back(&mut self, size: usize) {
+        for (query, parent) in self.undo.take().unwrap_or_default().into_iter().rev() {
+            self.parents[usize::from(query)] = parent;
+        }
+        self.parents.truncate(size);
     }
 
     pub fn find(&self, mut current: Id) -> Id {
+        self.check_tag(current);
         while current != self.parent(current) {
             current = self.parent(current)
         }
@@ -34,9 +96,13 @@ impl UnionFind {
     }
 
     pub fn find_mut(&mut self, mut current: Id) -> Id {
+        self.check_tag(current);
         while current != self.parent(current) {
-            let grandparent = self.parent(self.parent(current));
-            *self.parent_mut(current) = grandparent;
+            let parent = self.parent(current);
+            let grandparent = self.parent(parent);
+            if grandparent != parent {
+                self.set_parent(current, grandparent);
+            }
             current = grandparent;
         }
         current
@@ -44,7 +110,7 @@ impl UnionFind {
 
     /// Given two leader ids, unions the two eclasses making root1 the leader.
     pub fn union(&mut self, root1: Id, root2: Id) -> Id {
-        *self.parent_mut(root2) = root1;
+        self.set_parent(root2, root1);
         root1
     }
 }
@@ -89,4 +155,25 @@ mod tests {
         let expected = vec![0, 0, 0, 0, 4, 5, 6, 6, 6, 6];
         assert_eq!(uf.parents, ids(expected));
     }
+
+    #[test]
+    fn rolling_back_undoes_unions_and_compression() {
+        let id = |u: usize| Id::from(u);
+        let mut uf = UnionFind::default();
+        for _ in 0..4 {
+            uf.make_set();
+        }
+        uf.union(id(1), id(2));
+        uf.union(id(0), id(1));
+        let before = uf.parents.clone();
+
+        uf.start_undo();
+        uf.make_set();
+        uf.union(id(3), id(0));
+        uf.find_mut(id(2));
+        assert_eq!(uf.find(id(2)), id(3));
+        uf.roll_back(4);
+        assert_eq!(uf.parents, before);
+        assert!(uf.undo.is_none());
+    }
 }
@@ -59,7 +59,9 @@ pub(crate) fn pretty_print(
     }
 }
 
-static STRINGS: Lazy<RwLock<IndexSet<&'static str>>> = Lazy::new(Default::default);
+/// The interned strings, each with the offset of its namespace
+/// separator, if it has a namespace.
+static STRINGS: Lazy<RwLock<IndexMap<&'static str, Option<u32>>>> = Lazy::new(Default::default);
 
 /// An interned string.
 ///
@@ -80,6 +82,17 @@ static STRINGS: Lazy<RwLock<IndexSet<&'static str>>> = Lazy::new(Default::defaul
 /// The internal symbol cache leaks the strings, which should be
 /// fine if you only put in things like variable names and identifiers.
 ///
+/// # Namespaces
+///
+/// A symbol with a `.` is qualified by a namespace, everything before
+/// the last `.`, as in `mem.load`, if that is one or more identifiers
+/// separated by `.`s.
+/// So numbers like `3.14` aren't qualified.
+/// The table keeps where the namespace ends, so
+/// [`namespace`](Symbol::namespace) and [`name`](Symbol::name) don't
+/// search the string.
+/// See [`define_language!`] for namespaced operators.
+///
 /// # Example
 /// ```rust
 /// use egg::Symbol;
@@ -88,6 +101,13 @@ static STRINGS: Lazy<RwLock<IndexSet<&'static str>>> = Lazy::new(Default::defaul
 /// assert_eq!(Symbol::from("foo"), "foo".parse().unwrap());
 ///
 /// assert_ne!(Symbol::from("foo"), Symbol::from("bar"));
+///
+/// let load = Symbol::from("mem.load");
+/// assert_eq!((load.namespace(), load.name()), (Some("mem"), "load"));
+/// assert_eq!(Symbol::from("a.b.c").namespace(), Some("a.b"));
+/// assert_eq!(Symbol::from("foo").namespace(), None);
+/// assert_eq!(Symbol::from("3.14").namespace(), None);
+/// assert_eq!(Symbol::from("3.14").name(), "3.14");
 /// ```
 ///
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -96,13 +116,34 @@ static STRINGS: Lazy<RwLock<IndexSet<&'static str>>> = Lazy::new(Default::defaul
 pub struct Symbol(u32);
 
 impl Symbol {
-    /// Get the string that this symbol represents
-    pub fn as_str(self) -> &'static str {
+    fn entry(self) -> (&'static str, Option<u32>) {
         let i = self.0 as usize;
         let strings = STRINGS
             .read()
             .unwrap_or_else(|err| panic!("Failed to acquire egg's global string cache: {}", err));
-        strings.get_index(i).unwrap()
+        let (s, split) = strings.get_index(i).unwrap();
+        (s, *split)
+    }
+
+    /// Get the string that this symbol represents
+    pub fn as_str(self) -> &'static str {
+        self.entry().0
+    }
+
+    /// Get the namespace of this symbol, everything before the last
+    /// `.`, if it has one.
+    pub fn namespace(self) -> Option<&'static str> {
+        let (s, split) = self.entry();
+        split.map(|i| &s[..i as usize])
+    }
+
+    /// Get the name of this symbol without its
+    /// [`namespace`](Symbol::namespace).
+    pub fn name(self) -> &'static str {
+        match self.entry() {
+            (s, Some(i)) => &s[i as usize + 1..],
+            (s, None) => s,
+        }
     }
 }
 
@@ -110,11 +151,26 @@ fn leak(s: &str) -> &'static str {
     Box::leak(s.to_owned().into_boxed_str())
 }
 
+/// The offset of the `.` separating the namespace of `s` from its
+/// name, if the name is non-empty and the namespace is made of
+/// identifiers, so numbers like `3.14` have no namespace.
+pub(crate) fn namespace_split(s: &str) -> Option<usize> {
+    let is_ident = |segment: &str| {
+        let mut chars = segment.chars();
+        chars
+            .next()
+            .map_or(false, |c| c.is_alphabetic() || c == '_')
+            && chars.all(|c| c.is_alphanumeric() || c == '_')
+    };
+    s.rfind('.')
+        .filter(|&i| i + 1 < s.len() && s[..i].split('.').all(is_ident))
+}
+
 fn intern(s: &str) -> Symbol {
     let strings = STRINGS
         .read()
         .unwrap_or_else(|err| panic!("Failed to acquire egg's global string cache: {}", err));
-    if let Some((i, _)) = strings.get_full(s) {
+    if let Some((i, _, _)) = strings.get_full(s) {
         return Symbol(i as u32);
     }
     // Release the read lock.
@@ -124,8 +180,11 @@ fn intern(s: &str) -> Symbol {
         .write()
         .unwrap_or_else(|err| panic!("Failed to acquire egg's global string cache: {}", err));
     let i = match strings.get_full(s) {
-        Some((i, _)) => i, // The string was inserted in the meantime.
-        None => strings.insert_full(leak(s)).0,
+        Some((i, _, _)) => i, // The string was inserted in the meantime.
+        None => {
+            let split = namespace_split(s).map(|i| i as u32);
+            strings.insert_full(leak(s), split).0
+        }
     };
     Symbol(i as u32)
 }
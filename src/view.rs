@@ -0,0 +1,297 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use crate::*;
+
+/// Read-only access to an [`EGraph`], for callbacks that only look.
+///
+/// [`EGraph`] implements this with its inherent methods, and a
+/// [`RecordingView`] wraps another view to log which eclasses are read
+/// through it.
+/// The library's read-only callbacks, like [`Condition::check`],
+/// [`MatchRanker::score`] and the
+/// [rule synthesizer](Runner::with_rule_synthesizer), take a
+/// `&dyn EGraphView`, so they work with either, or with another
+/// implementation.
+///
+/// Eclasses are read through [`ClassView`]s rather than [`EClass`]es,
+/// so an implementation only needs each eclass's enodes and data, not
+/// the egraph's own representation of them.
+///
+/// Like the inherent methods, these work while the egraph is dirty,
+/// canonicalizing ids with [`find`](EGraphView::find).
+pub trait EGraphView<L: Language, N: Analysis<L>> {
+    /// The canonical id of `id`'s eclass, like [`EGraph::find`].
+    fn find(&self, id: Id) -> Id;
+
+    /// The canonical id of the eclass of `enode`, if it's in the
+    /// egraph, like [`EGraph::lookup`].
+    fn lookup(&self, enode: L) -> Option<Id>;
+
+    /// The eclass `id`, like indexing an [`EGraph`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` isn't one of the egraph's.
+    fn class(&self, id: Id) -> ClassView<'_, L, N::Data>;
+
+    /// The analysis data of eclass `id`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` isn't one of the egraph's.
+    fn data(&self, id: Id) -> &N::Data {
+        self.class(id).data
+    }
+
+    /// Every eclass, like [`EGraph::classes`].
+    fn classes(&self) -> Box<dyn Iterator<Item = ClassView<'_, L, N::Data>> + '_>;
+
+    /// The number of eclasses, like [`EGraph::number_of_classes`].
+    fn number_of_classes(&self) -> usize;
+
+    /// The number of enodes, like [`EGraph::total_size`].
+    fn total_size(&self) -> usize;
+
+    /// The [`OpStats`] of each operator, like [`EGraph::op_stats`].
+    fn op_stats(&self) -> BTreeMap<String, OpStats>;
+
+    /// The egraph's [`Analysis`], like [`EGraph::analysis`].
+    fn analysis(&self) -> &N;
+}
+
+/// An eclass, as an [`EGraphView`] shows it.
+#[derive(Debug)]
+pub struct ClassView<'a, L, D> {
+    /// The eclass's canonical id.
+    pub id: Id,
+    /// The eclass's enodes.
+    pub nodes: &'a [L],
+    /// The eclass's analysis data.
+    pub data: &'a D,
+}
+
+impl<'a, L, D> Clone for ClassView<'a, L, D> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, L, D> Copy for ClassView<'a, L, D> {}
+
+impl<'a, L, D> ClassView<'a, L, D> {
+    /// View an [`EClass`].
+    pub fn new(class: &'a EClass<L, D>) -> Self {
+        Self {
+            id: class.id,
+            nodes: &class.nodes,
+            data: &class.data,
+        }
+    }
+}
+
+impl<L: Language, N: Analysis<L>> EGraphView<L, N> for EGraph<L, N> {
+    fn find(&self, id: Id) -> Id {
+        EGraph::find(self, id)
+    }
+
+    fn lookup(&self, enode: L) -> Option<Id> {
+        EGraph::lookup(self, enode)
+    }
+
+    fn class(&self, id: Id) -> ClassView<'_, L, N::Data> {
+        ClassView::new(&self[id])
+    }
+
+    fn classes(&self) -> Box<dyn Iterator<Item = ClassView<'_, L, N::Data>> + '_> {
+        Box::new(EGraph::classes(self).map(ClassView::new))
+    }
+
+    fn number_of_classes(&self) -> usize {
+        EGraph::number_of_classes(self)
+    }
+
+    fn total_size(&self) -> usize {
+        EGraph::total_size(self)
+    }
+
+    fn op_stats(&self) -> BTreeMap<String, OpStats> {
+        EGraph::op_stats(self)
+    }
+
+    fn analysis(&self) -> &N {
+        &self.analysis
+    }
+}
+
+/// An [`EGraphView`] that records which eclasses are read through it.
+///
+/// This is useful to cache the result of a callback, like a
+/// [`Condition`], until one of the eclasses it read changes.
+/// Each [`find`](EGraphView::find), [`lookup`](EGraphView::lookup) that
+/// finds something, or access to an eclass records its canonical id.
+/// Reading all of them, with [`classes`](EGraphView::classes),
+/// [`number_of_classes`](EGraphView::number_of_classes),
+/// [`total_size`](EGraphView::total_size) or
+/// [`op_stats`](EGraphView::op_stats), sets
+/// [`read_all`](RecordingView::read_all) instead.
+///
+/// # Example
+/// ```
+/// use egg::{*, SymbolLang as S};
+/// let mut egraph = EGraph::<S, ()>::default();
+/// let x = egraph.add(S::leaf("x"));
+/// let fx = egraph.add(S::new("f", vec![x]));
+/// egraph.rebuild();
+///
+/// let view = RecordingView::new(&egraph);
+/// assert_eq!(view.lookup(S::new("f", vec![x])), Some(fx));
+/// assert_eq!(view.touched(), vec![fx]);
+/// assert!(!view.read_all());
+/// ```
+pub struct RecordingView<'a, V: ?Sized> {
+    view: &'a V,
+    touched: RefCell<HashSet<Id>>,
+    read_all: Cell<bool>,
+}
+
+impl<'a, V: ?Sized> RecordingView<'a, V> {
+    /// Wrap `view`, with nothing recorded yet.
+    pub fn new(view: &'a V) -> Self {
+        Self {
+            view,
+            touched: Default::default(),
+            read_all: Cell::new(false),
+        }
+    }
+
+    /// The canonical ids of the eclasses read so far, sorted.
+    pub fn touched(&self) -> Vec<Id> {
+        let mut touched: Vec<Id> = self.touched.borrow().iter().copied().collect();
+        touched.sort_unstable();
+        touched
+    }
+
+    /// Whether every eclass may have been read.
+    pub fn read_all(&self) -> bool {
+        self.read_all.get()
+    }
+
+    /// Forget what was recorded.
+    pub fn clear(&self) {
+        self.touched.borrow_mut().clear();
+        self.read_all.set(false);
+    }
+
+    fn touch(&self, id: Id) -> Id {
+        self.touched.borrow_mut().insert(id);
+        id
+    }
+}
+
+impl<'a, L, N, V> EGraphView<L, N> for RecordingView<'a, V>
+where
+    L: Language,
+    N: Analysis<L>,
+    V: EGraphView<L, N> + ?Sized,
+{
+    fn find(&self, id: Id) -> Id {
+        self.touch(self.view.find(id))
+    }
+
+    fn lookup(&self, enode: L) -> Option<Id> {
+        self.view.lookup(enode).map(|id| self.touch(id))
+    }
+
+    fn class(&self, id: Id) -> ClassView<'_, L, N::Data> {
+        let class = self.view.class(id);
+        self.touch(class.id);
+        class
+    }
+
+    fn classes(&self) -> Box<dyn Iterator<Item = ClassView<'_, L, N::Data>> + '_> {
+        self.read_all.set(true);
+        self.view.classes()
+    }
+
+    fn number_of_classes(&self) -> usize {
+        self.read_all.set(true);
+        self.view.number_of_classes()
+    }
+
+    fn total_size(&self) -> usize {
+        self.read_all.set(true);
+        self.view.total_size()
+    }
+
+    fn op_stats(&self) -> BTreeMap<String, OpStats> {
+        self.read_all.set(true);
+        self.view.op_stats()
+    }
+
+    fn analysis(&self) -> &N {
+        self.view.analysis()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{SymbolLang as S, *};
+
+    #[test]
+    fn recording_a_condition() {
+        #[derive(Default)]
+        struct Leaves;
+        impl Analysis<S> for Leaves {
+            type Data = usize;
+            fn make(egraph: &EGraph<S, Self>, enode: &S) -> usize {
+                enode.fold(1, |n, child| n + egraph[child].data)
+            }
+            fn merge(&mut self, a: &mut usize, b: usize) -> DidMerge {
+                merge_min(a, b)
+            }
+        }
+
+        let mut egraph = EGraph::<S, Leaves>::default();
+        let root = egraph.add_expr(&"(+ (* a b) c)".parse().unwrap());
+        egraph.rebuild();
+        let lookup = |s: &str| egraph.lookup_expr(&s.parse().unwrap()).unwrap();
+        let (ab, c) = (lookup("(* a b)"), lookup("c"));
+
+        let a: Var = "?a".parse().unwrap();
+        let b: Var = "?b".parse().unwrap();
+        let smaller = move |egraph: &dyn EGraphView<S, Leaves>, _: Id, subst: &Subst| {
+            egraph.data(subst[a]) < egraph.data(subst[b])
+        };
+        let mut subst = Subst::default();
+        subst.insert(a, c);
+        subst.insert(b, ab);
+        let check = |view: &dyn EGraphView<S, Leaves>| {
+            Condition::<S, Leaves>::check(&smaller, view, root, &subst)
+        };
+
+        let view = RecordingView::new(&egraph);
+        assert!(check(&view));
+        assert_eq!(view.touched(), {
+            let mut ids = vec![ab, c];
+            ids.sort_unstable();
+            ids
+        });
+        assert!(!view.read_all());
+        view.clear();
+        assert!(view.touched().is_empty());
+        assert_eq!(view.number_of_classes(), egraph.number_of_classes());
+        assert!(view.read_all());
+
+        // a union changes what the condition read, and so its result
+        let mut egraph = egraph;
+        let d = egraph.add(S::leaf("d"));
+        let big = egraph.add_expr(&"(- d d)".parse().unwrap());
+        egraph.union(c, big);
+        egraph.union(ab, d);
+        let view = RecordingView::new(&egraph);
+        assert!(!check(&view));
+        assert!(view.touched().contains(&egraph.find(ab)));
+        assert!(!check(&egraph));
+    }
+}
@@ -0,0 +1,179 @@
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use egg::test::{explosive_egraph, explosive_pattern};
+use egg::{SymbolLang as S, *};
+
+/// Cancels `token` from another thread once something is sent.
+fn cancel_when_told(token: &CancellationToken) -> (Sender<()>, JoinHandle<()>) {
+    let (tx, rx) = channel();
+    let token = token.clone();
+    let handle = thread::spawn(move || {
+        rx.recv().unwrap();
+        token.cancel();
+    });
+    (tx, handle)
+}
+
+fn wait_until_cancelled(token: &CancellationToken) {
+    while !token.is_cancelled() {
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn explosive_program(arity: usize) -> Program<S> {
+    Program::compile_from_pat(&explosive_pattern(arity).ast)
+}
+
+#[test]
+fn cancel_a_search() {
+    let (egraph, root) = explosive_egraph(2);
+    let program = explosive_program(2);
+    let token = CancellationToken::new();
+    let matches = program.run_cancellable(&egraph, root, &token).unwrap();
+    assert_eq!(matches, program.run(&egraph, root));
+    assert_eq!(matches.len(), 100);
+
+    // 10^9 matches would take a long while
+    let (egraph, root) = explosive_egraph(9);
+    let program = explosive_program(9);
+    let canceller = {
+        let token = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        })
+    };
+    let result = program.run_cancellable(&egraph, root, &token);
+    assert!(matches!(result, Err(Error::Cancelled)));
+    canceller.join().unwrap();
+    egraph.check_invariants();
+
+    // a cancelled token cancels straight away
+    let (egraph, root) = explosive_egraph(2);
+    let result = explosive_program(2).run_cancellable(&egraph, root, &token);
+    assert!(matches!(result, Err(Error::Cancelled)));
+}
+
+/// Like [`AstSize`], but the first enode it costs waits for the token
+/// to be cancelled.
+struct Blocking {
+    token: CancellationToken,
+    started: Option<Sender<()>>,
+}
+
+impl CostFunction<S> for Blocking {
+    type Cost = usize;
+    fn cost<C>(&mut self, enode: &S, costs: C) -> usize
+    where
+        C: FnMut(Id) -> usize,
+    {
+        if let Some(started) = self.started.take() {
+            started.send(()).unwrap();
+            wait_until_cancelled(&self.token);
+        }
+        AstSize.cost(enode, costs)
+    }
+}
+
+#[test]
+fn cancel_an_extraction() {
+    let mut egraph = EGraph::<S, ()>::default();
+    let root = egraph.add_expr(&"(+ (* x y) (- z 1))".parse().unwrap());
+    egraph.rebuild();
+
+    let token = CancellationToken::new();
+    let extractor = Extractor::new_cancellable(&egraph, AstSize, &token).unwrap();
+    assert_eq!(extractor.find_best_cost(root), 7);
+
+    let (started, canceller) = cancel_when_told(&token);
+    let cost_function = Blocking {
+        token: token.clone(),
+        started: Some(started),
+    };
+    let result = Extractor::new_cancellable(&egraph, cost_function, &token);
+    assert!(matches!(result, Err(Error::Cancelled)));
+    canceller.join().unwrap();
+    egraph.check_invariants();
+}
+
+/// An analysis without data whose first merge waits for the token to be
+/// cancelled.
+struct BlockingMerge {
+    token: CancellationToken,
+    started: Option<Sender<()>>,
+}
+
+impl Analysis<S> for BlockingMerge {
+    type Data = ();
+    fn make(_: &EGraph<S, Self>, _: &S) {}
+    fn merge(&mut self, _: &mut (), _: ()) -> DidMerge {
+        if let Some(started) = self.started.take() {
+            started.send(()).unwrap();
+            wait_until_cancelled(&self.token);
+        }
+        DidMerge(false, false)
+    }
+}
+
+/// `x` and `y` under 2000 `f`s each, with `x` and `y` unioned, so
+/// rebuilding merges the two towers level by level.
+fn towers(analysis: BlockingMerge) -> EGraph<S, BlockingMerge> {
+    let mut egraph = EGraph::new(analysis);
+    let mut x = egraph.add(S::leaf("x"));
+    let mut y = egraph.add(S::leaf("y"));
+    egraph.rebuild();
+    let (bottom_x, bottom_y) = (x, y);
+    for _ in 0..2000 {
+        x = egraph.add(S::new("f", vec![x]));
+        y = egraph.add(S::new("f", vec![y]));
+    }
+    egraph.union(bottom_x, bottom_y);
+    egraph
+}
+
+#[test]
+fn cancel_a_rebuild() {
+    let token = CancellationToken::new();
+    let mut egraph = towers(BlockingMerge {
+        token: token.clone(),
+        started: None,
+    });
+    egraph.rebuild_cancellable(&token).unwrap();
+    assert!(egraph.clean);
+    assert_eq!(egraph.number_of_classes(), 2001);
+    egraph.check_invariants();
+
+    let mut egraph = towers(BlockingMerge {
+        token: token.clone(),
+        started: None,
+    });
+    let (started, canceller) = cancel_when_told(&token);
+    egraph.analysis.started = Some(started);
+    let result = egraph.rebuild_cancellable(&token);
+    assert!(matches!(result, Err(Error::Cancelled)));
+    canceller.join().unwrap();
+    assert!(!egraph.clean);
+    assert!(egraph.number_of_classes() > 2001);
+    egraph.check_invariants();
+
+    // the next rebuild finishes the job
+    egraph.rebuild();
+    assert_eq!(egraph.number_of_classes(), 2001);
+    egraph.check_invariants();
+    let x = egraph.lookup_expr(&"(f (f x))".parse().unwrap());
+    let y = egraph.lookup_expr(&"(f (f y))".parse().unwrap());
+    assert_eq!(x, y);
+
+    // and a cancelled token stops a rebuild before it starts
+    let mut egraph = towers(BlockingMerge {
+        token: token.clone(),
+        started: None,
+    });
+    assert!(matches!(
+        egraph.rebuild_cancellable(&token),
+        Err(Error::Cancelled)
+    ));
+    egraph.check_invariants();
+}
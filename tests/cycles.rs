@@ -0,0 +1,121 @@
+use egg::{rewrite as rw, SymbolLang as S, *};
+
+fn var(s: &str) -> Var {
+    s.parse().unwrap()
+}
+
+#[test]
+fn self_union_is_a_noop() {
+    let mut egraph = EGraph::<S, ()>::default();
+    let a = egraph.add(S::leaf("a"));
+    let fa = egraph.add(S::new("f", vec![a]));
+    egraph.rebuild();
+    egraph.check_invariants();
+
+    assert!(!egraph.union(fa, fa));
+    assert!(!egraph.union(a, egraph.find(a)));
+    assert!(egraph.clean);
+    assert_eq!(egraph.dirty_unions_count(), 0);
+    assert_eq!(egraph.number_of_classes(), 2);
+    egraph.check_invariants();
+
+    // and likewise once they're equal
+    assert!(egraph.union(a, fa));
+    egraph.rebuild();
+    assert!(!egraph.union(fa, a));
+    assert!(egraph.clean);
+    egraph.check_invariants();
+}
+
+#[test]
+fn cyclic_adds_keep_the_invariants() {
+    let mut egraph = EGraph::<S, ()>::default();
+    let a = egraph.add(S::leaf("a"));
+    let fa = egraph.add(S::new("f", vec![a]));
+    assert!(egraph.union(a, fa));
+    // (f a) is now a parent of its own eclass
+    egraph.check_invariants();
+    egraph.rebuild();
+    egraph.check_invariants();
+    assert_eq!(egraph.number_of_classes(), 1);
+
+    // adding into the cycle finds the enode that's already there
+    let a = egraph.find(a);
+    assert_eq!(egraph.add(S::new("f", vec![a])), a);
+    let ffa = egraph.add_expr(&"(f (f a))".parse().unwrap());
+    assert_eq!(ffa, a);
+    assert_eq!(egraph.total_size(), 2);
+    egraph.check_invariants();
+
+    for pattern in &["(f ?x)", "(f (f (f ?x)))"] {
+        let pattern: Pattern<S> = pattern.parse().unwrap();
+        let matches = pattern.search(&egraph);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].eclass, a);
+        assert!(matches[0].substs.iter().all(|s| s[var("?x")] == a));
+    }
+
+    let (cost, best) = Extractor::new(&egraph, AstSize).find_best(a);
+    assert_eq!((cost, best.to_string()), (1, "a".to_string()));
+
+    // without the leaf, all that's left is the cycle, which has no term
+    let leaf = S::leaf("a");
+    let extractor = Extractor::new(&egraph, AstSize)
+        .with_forbidden_choice(a, leaf)
+        .unwrap();
+    assert!(matches!(
+        extractor.try_find_best(a),
+        Err(Error::Unextractable(id)) if id == a
+    ));
+}
+
+#[test]
+fn unioning_with_an_ancestor() {
+    let mut egraph = EGraph::<S, ()>::default();
+    let a = egraph.add(S::leaf("a"));
+    let gab = egraph.add_expr(&"(g a b)".parse().unwrap());
+    let h = egraph.add_expr(&"(h (g a b))".parse().unwrap());
+    egraph.rebuild();
+
+    // a is a descendant of (h (g a b)), both ways round
+    assert!(egraph.union(a, h));
+    egraph.check_invariants();
+    egraph.rebuild();
+    egraph.check_invariants();
+    assert_eq!(egraph.number_of_classes(), 3);
+
+    let mut other = EGraph::<S, ()>::default();
+    let a2 = other.add(S::leaf("a"));
+    let h2 = other.add_expr(&"(h (g a b))".parse().unwrap());
+    assert!(other.union(h2, a2));
+    other.check_invariants();
+    other.rebuild();
+    other.check_invariants();
+
+    let a = egraph.find(a);
+    let pattern: Pattern<S> = "(h (g ?x b))".parse().unwrap();
+    let matches = pattern.search(&egraph);
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].eclass, a);
+    assert_eq!(matches[0].substs[0][var("?x")], a);
+
+    let extractor = Extractor::new(&egraph, AstSize);
+    assert_eq!(extractor.find_best(a).1.to_string(), "a");
+    assert_eq!(extractor.find_best(gab).1.to_string(), "(g a b)");
+}
+
+#[test]
+fn rules_that_make_cycles() {
+    let rules: Vec<Rewrite<S, ()>> = vec![
+        rw!("mul-1"; "(* ?a 1)" => "?a"),
+        rw!("one-mul"; "?a" => "(* ?a 1)"),
+    ];
+    let runner = Runner::default()
+        .with_expr(&"(+ x (* y 1))".parse().unwrap())
+        .with_iter_limit(5)
+        .run(&rules);
+    runner.egraph.check_invariants();
+    let root = runner.roots[0];
+    let (cost, best) = Extractor::new(&runner.egraph, AstSize).find_best(root);
+    assert_eq!((cost, best.to_string()), (3, "(+ x y)".to_string()));
+}
@@ -0,0 +1,129 @@
+use egg::{rewrite as rw, SymbolLang as S, *};
+
+fn rules() -> Vec<Rewrite<S, ()>> {
+    vec![
+        rw!("comm"; "(+ ?x ?y)" => "(+ ?y ?x)"),
+        rw!("zero"; "(+ ?x 0)" => "?x"),
+    ]
+}
+
+#[test]
+fn runner_without_roots() {
+    let runner = Runner::<S, ()>::default().run(&rules());
+    assert!(runner.roots.is_empty());
+    assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+    assert_eq!(runner.iterations.len(), 1);
+    assert_eq!(runner.iterations[0].egraph_classes, 0);
+    assert!(runner.ensure_within_limits().is_ok());
+
+    let runner = Runner::<S, ()>::default().run(&[]);
+    assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+}
+
+#[test]
+fn search_empty_egraph() {
+    let egraph = EGraph::<S, ()>::default();
+    assert!(egraph.clean);
+    for pattern in &["(+ ?x ?y)", "?x", "x", "(+ ?x ?x)", "(+ ?x (f ?x))"] {
+        let pattern: Pattern<S> = pattern.parse().unwrap();
+        assert!(pattern.search(&egraph).is_empty());
+        assert!(pattern.explain(&egraph).ends_with("0.0 matches"));
+    }
+    let set = ProgramSet::<S>::compile(&["(+ ?x ?y)".parse().unwrap()]);
+    assert!(egraph.classes().all(|c| set.run(&egraph, c.id).is_empty()));
+}
+
+#[test]
+fn empty_pattern_matches_nothing() {
+    let pattern = Pattern::<S>::new(PatternAst::default());
+    assert!(pattern.vars().is_empty());
+
+    let mut egraph = EGraph::<S, ()>::default();
+    let x = egraph.add_expr(&"(+ x 0)".parse().unwrap());
+    egraph.rebuild();
+    assert!(pattern.search(&egraph).is_empty());
+    assert!(pattern.search_eclass(&egraph, x).is_none());
+    assert!(pattern.search_eclass_packed(&egraph, x).is_empty());
+    assert!(pattern
+        .explain(&egraph)
+        .contains("fail: the pattern is empty"));
+
+    let set = ProgramSet::<S>::compile(&[PatternAst::default(), "?x".parse().unwrap()]);
+    let matches = set.run(&egraph, x);
+    assert!(matches.iter().all(|(i, _)| *i == 1));
+    assert_eq!(matches.len(), 1);
+
+    // and it never fires as a rule
+    let rules = vec![Rewrite::new("never", pattern, "x".parse::<Pattern<S>>().unwrap()).unwrap()];
+    let runner = Runner::default().with_egraph(egraph).run(&rules);
+    assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+}
+
+#[test]
+#[should_panic(expected = "Can't add an empty expression")]
+fn adding_empty_expr_panics() {
+    EGraph::<S, ()>::default().add_expr(&RecExpr::default());
+}
+
+#[test]
+#[should_panic(expected = "Can't instantiate an empty pattern")]
+fn instantiating_empty_pattern_panics() {
+    EGraph::<S, ()>::default().add_instantiation(&PatternAst::default(), &Subst::default());
+}
+
+#[test]
+fn extract_from_empty_egraph() {
+    let egraph = EGraph::<S, ()>::default();
+    let bogus = Id::from(0);
+    let extractor = Extractor::new(&egraph, AstSize);
+    assert!(matches!(
+        extractor.try_find_best(bogus),
+        Err(Error::InvalidId(id)) if id == bogus
+    ));
+    assert!(matches!(
+        extractor.try_find_best_cost(bogus),
+        Err(Error::InvalidId(_))
+    ));
+    assert!(egraph.try_get(bogus).is_err());
+    assert_eq!(egraph.lookup_expr(&"x".parse().unwrap()), None);
+}
+
+#[test]
+#[should_panic(expected = "Invalid id 0")]
+fn shared_cost_extract_from_empty_egraph_panics() {
+    let egraph = EGraph::<S, ()>::default();
+    SharedCostExtractor::new(&egraph, |_: &S| 1.0, 0.5, 10).find_best(Id::from(0));
+}
+
+#[test]
+fn dot_of_empty_egraph() {
+    let egraph = EGraph::<S, ()>::default();
+    let dot = egraph.dot().to_string();
+    assert!(dot.starts_with("digraph egraph {"), "{}", dot);
+    assert!(!dot.contains("cluster_"), "{}", dot);
+}
+
+#[test]
+fn binary_round_trip_of_empty_egraph() {
+    let egraph = EGraph::<S, ()>::default();
+    let mut bytes = vec![];
+    egraph.write_binary(&mut bytes).unwrap();
+    let loaded = EGraph::<S, ()>::read_binary(&bytes[..], ()).unwrap();
+    assert_eq!(loaded.number_of_classes(), 0);
+    assert!(loaded.clean);
+}
+
+#[cfg(all(feature = "serde-1", feature = "serde_json"))]
+#[test]
+fn serde_round_trip_of_empty_egraph() {
+    let egraph = EGraph::<S, ()>::default();
+    let json = serde_json::to_string(&egraph).unwrap();
+    let mut loaded: EGraph<S, ()> = serde_json::from_str(&json).unwrap();
+    loaded.rebuild();
+    assert_eq!(loaded.number_of_classes(), 0);
+    assert!("?x"
+        .parse::<Pattern<S>>()
+        .unwrap()
+        .search(&loaded)
+        .is_empty());
+}
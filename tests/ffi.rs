@@ -0,0 +1,136 @@
+#![cfg(feature = "ffi")]
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+
+use egg::ffi::*;
+
+fn c(s: &str) -> CString {
+    CString::new(s).unwrap()
+}
+
+/// Take ownership of a string returned by the API.
+unsafe fn take(s: *mut c_char) -> String {
+    assert!(!s.is_null());
+    let owned = CStr::from_ptr(s).to_str().unwrap().to_string();
+    egg_string_free(s);
+    owned
+}
+
+fn last_error() -> String {
+    unsafe { CStr::from_ptr(egg_last_error()) }
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+unsafe fn add(egraph: *mut EggEGraph, expr: &str) -> u32 {
+    let mut id = u32::MAX;
+    assert_eq!(
+        egg_add_expr(egraph, c(expr).as_ptr(), &mut id),
+        EggStatus::Ok
+    );
+    id
+}
+
+unsafe fn extract(egraph: *mut EggEGraph, id: u32) -> (String, usize) {
+    let mut best = ptr::null_mut();
+    let mut cost = 0;
+    let status = egg_extract_best(egraph, id, &mut best, &mut cost);
+    assert_eq!(status, EggStatus::Ok, "{}", last_error());
+    (take(best), cost)
+}
+
+#[test]
+fn round_trip() {
+    unsafe {
+        let egraph = egg_egraph_new();
+        let root = add(egraph, "(* (+ a 0) 1)");
+        let b = add(egraph, "b");
+        assert_eq!(extract(egraph, root), ("(* (+ a 0) 1)".into(), 5));
+
+        let rules = c("
+            # identities
+            add-0: (+ ?a 0) => ?a
+            mul-1: (* ?a 1) => ?a
+        ");
+        assert_eq!(egg_run_rules(egraph, rules.as_ptr(), 10), EggStatus::Ok);
+        assert_eq!(extract(egraph, root), ("a".into(), 1));
+
+        let mut json = ptr::null_mut();
+        assert_eq!(egg_run_report_json(egraph, &mut json), EggStatus::Ok);
+        let json = take(json);
+        assert!(json.starts_with('{'));
+        assert!(json.contains("\"stop_reason\":\"Saturated\""), "{}", json);
+
+        // unioning and rebuilding, with and without an out-pointer
+        let mut changed = false;
+        assert_eq!(egg_union(egraph, root, b, &mut changed), EggStatus::Ok);
+        assert!(changed);
+        assert_eq!(egg_union(egraph, b, root, ptr::null_mut()), EggStatus::Ok);
+        assert_eq!(egg_rebuild(egraph), EggStatus::Ok);
+        let mut changed = true;
+        assert_eq!(egg_union(egraph, root, b, &mut changed), EggStatus::Ok);
+        assert!(!changed);
+        let (best, cost) = extract(egraph, b);
+        assert!(best == "a" || best == "b");
+        assert_eq!(cost, 1);
+
+        egg_egraph_free(egraph);
+        egg_egraph_free(ptr::null_mut());
+        egg_string_free(ptr::null_mut());
+    }
+}
+
+#[test]
+fn errors_have_codes_and_messages() {
+    unsafe {
+        let egraph = egg_egraph_new();
+        let mut id = 0;
+
+        let status = egg_add_expr(egraph, c("(+ a").as_ptr(), &mut id);
+        assert_eq!(status, EggStatus::Parse);
+        assert!(last_error().starts_with("Parse error"), "{}", last_error());
+
+        let status = egg_add_expr(ptr::null_mut(), c("a").as_ptr(), &mut id);
+        assert_eq!(status, EggStatus::NullPointer);
+        assert_eq!(last_error(), "egraph is null");
+        let status = egg_add_expr(egraph, ptr::null(), &mut id);
+        assert_eq!(status, EggStatus::NullPointer);
+        assert_eq!(last_error(), "expr is null");
+
+        let bad_utf8 = [0xffu8, 0];
+        let status = egg_add_expr(egraph, bad_utf8.as_ptr() as *const c_char, &mut id);
+        assert_eq!(status, EggStatus::InvalidString);
+
+        let rules = c("bad: (+ ?a ?b) => ?c");
+        assert_eq!(egg_run_rules(egraph, rules.as_ptr(), 10), EggStatus::Parse);
+        assert!(
+            last_error().starts_with("Parse error: line 1"),
+            "{}",
+            last_error()
+        );
+
+        let mut json = ptr::null_mut();
+        let status = egg_run_report_json(egraph, &mut json);
+        assert_eq!(status, EggStatus::NoReport);
+        assert!(json.is_null());
+
+        let a = add(egraph, "a");
+        assert_eq!(
+            egg_union(egraph, a, 1000, ptr::null_mut()),
+            EggStatus::InvalidId
+        );
+        let mut best = ptr::null_mut();
+        let status = egg_extract_best(egraph, 1000, &mut best, ptr::null_mut());
+        assert_eq!(status, EggStatus::InvalidId);
+        assert!(best.is_null());
+
+        // errors stay until the next one, even after a success
+        assert_eq!(extract(egraph, a), ("a".into(), 1));
+        assert!(!last_error().is_empty());
+
+        egg_egraph_free(egraph);
+    }
+}
@@ -0,0 +1,161 @@
+use egg::{SymbolLang as S, *};
+
+fn flattened() -> EGraph<S, ()> {
+    EGraph::default().with_flattening(vec!["+", "*"])
+}
+
+fn expr(s: &str) -> RecExpr<S> {
+    s.parse().unwrap()
+}
+
+#[test]
+fn ac_terms_share_an_enode() {
+    let mut egraph = flattened();
+    let ids: Vec<Id> = ["(+ a (+ b c))", "(+ (+ c b) a)", "(+ b (+ a c))"]
+        .iter()
+        .map(|s| egraph.add_expr(&expr(s)))
+        .collect();
+    egraph.rebuild();
+    egraph.check_invariants();
+    assert!(ids.iter().all(|&id| id == ids[0]));
+    assert_eq!(egraph[ids[0]].nodes.len(), 1);
+    let sum = &egraph[ids[0]].nodes[0];
+    assert_eq!(sum.len(), 3);
+    assert!(sum.children().windows(2).all(|w| w[0] <= w[1]));
+    assert_eq!(egraph.lookup_expr(&expr("(+ (+ a c) b)")), Some(ids[0]));
+
+    // other operators are left alone
+    let f = egraph.add_expr(&expr("(f a (f b c))"));
+    let g = egraph.add_expr(&expr("(f (f b c) a)"));
+    egraph.rebuild();
+    assert_ne!(f, g);
+}
+
+#[test]
+fn rebuilding_resorts_children() {
+    let mut egraph = flattened();
+    let x = egraph.add(S::leaf("x"));
+    let z = egraph.add(S::leaf("z"));
+    let xy = egraph.add_expr(&expr("(+ x y)"));
+    let yz = egraph.add_expr(&expr("(+ y z)"));
+    egraph.rebuild();
+    assert_ne!(xy, yz);
+
+    // (+ y z) is (+ y x), which sorts to the same enode as (+ x y)
+    egraph.union(x, z);
+    egraph.rebuild();
+    egraph.check_invariants();
+    assert_eq!(egraph.find(xy), egraph.find(yz));
+    assert_eq!(egraph[xy].nodes.len(), 1);
+}
+
+#[test]
+fn rebuilding_flattens_merged_sums() {
+    let mut egraph = flattened();
+    let x = egraph.add(S::leaf("x"));
+    let sum = egraph.add_expr(&expr("(+ a x)"));
+    let bc = egraph.add_expr(&expr("(+ b c)"));
+    egraph.rebuild();
+    assert_eq!(egraph.lookup_expr(&expr("(+ a b c)")), None);
+
+    egraph.union(x, bc);
+    egraph.rebuild();
+    egraph.check_invariants();
+    let flat = egraph.lookup_expr(&expr("(+ a b c)"));
+    assert_eq!(flat, Some(egraph.find(sum)));
+    assert!(egraph[sum].nodes.iter().any(|n| n.len() == 3));
+
+    // a sum with itself in it isn't spliced into its parents
+    let zero = egraph.add_expr(&expr("(+ y 0)"));
+    let y = egraph.add(S::leaf("y"));
+    egraph.union(y, zero);
+    egraph.rebuild();
+    let parent = egraph.add_expr(&expr("(+ d y)"));
+    egraph.rebuild();
+    let size = egraph.total_size();
+    egraph.union(y, x);
+    egraph.rebuild();
+    egraph.check_invariants();
+    assert!(egraph[parent].nodes.iter().all(|n| n.len() <= 4));
+    assert!(egraph.total_size() <= size + 2);
+}
+
+fn distribute(searcher: impl Searcher<S, ()> + Send + Sync + 'static) -> Rewrite<S, ()> {
+    let applier: Pattern<S> = "(+ (* ?a ?b) (* ?a ?c))".parse().unwrap();
+    Rewrite::new("distribute", searcher, applier).unwrap()
+}
+
+#[test]
+fn equalities_without_ac_rules() {
+    let lhs = expr("(* (+ y (+ x w)) z)");
+    let rhs = expr("(+ (+ (* x z) (* w z)) (* z y))");
+    let pattern: Pattern<S> = "(* ?a (+ ?b ?c))".parse().unwrap();
+
+    // the sum is flattened, so the rule is written over three terms
+    let n_ary = AcPattern::new("(* ?a (+ ?b ?c ?d))".parse().unwrap(), vec!["+", "*"]);
+    let runner = Runner::default()
+        .with_egraph(flattened())
+        .with_expr(&lhs)
+        .with_expr(&rhs)
+        .with_iter_limit(5)
+        .run(&[Rewrite::new(
+            "distribute",
+            n_ary,
+            "(+ (* ?a ?b) (* ?a ?c) (* ?a ?d))"
+                .parse::<Pattern<S>>()
+                .unwrap(),
+        )
+        .unwrap()]);
+    runner.egraph.check_invariants();
+    assert_eq!(
+        runner.egraph.find(runner.roots[0]),
+        runner.egraph.find(runner.roots[1])
+    );
+
+    // a binary pattern only matches binary sums, in either order
+    let runner = Runner::default()
+        .with_egraph(flattened())
+        .with_expr(&expr("(* (+ y x) z)"))
+        .with_expr(&expr("(+ (* x z) (* z y))"))
+        .run(&[distribute(AcPattern::new(pattern.clone(), vec!["+", "*"]))]);
+    assert_eq!(
+        runner.egraph.find(runner.roots[0]),
+        runner.egraph.find(runner.roots[1])
+    );
+
+    // without flattening, the same rule can't do it alone
+    let runner = Runner::<S, ()>::default()
+        .with_expr(&expr("(* (+ y x) z)"))
+        .with_expr(&expr("(+ (* x z) (* z y))"))
+        .run(&[distribute(pattern)]);
+    assert_ne!(
+        runner.egraph.find(runner.roots[0]),
+        runner.egraph.find(runner.roots[1])
+    );
+}
+
+define_language! {
+    enum Binary {
+        "+" = Add([Id; 2]),
+        Symbol(Symbol),
+    }
+}
+
+#[test]
+fn fixed_arity_operators_are_only_sorted() {
+    let mut egraph = EGraph::<Binary, ()>::default().with_flattening(vec!["+"]);
+    let ab = egraph.add_expr(&"(+ a b)".parse().unwrap());
+    let ba = egraph.add_expr(&"(+ b a)".parse().unwrap());
+    let left = egraph.add_expr(&"(+ (+ a b) c)".parse().unwrap());
+    let right = egraph.add_expr(&"(+ a (+ b c))".parse().unwrap());
+    egraph.rebuild();
+    egraph.check_invariants();
+    assert_eq!(ab, ba);
+    assert_ne!(left, right);
+}
+
+#[test]
+#[should_panic(expected = "Explanations can't be used with flattening.")]
+fn flattening_rules_out_explanations() {
+    let _ = flattened().with_explanations_enabled();
+}
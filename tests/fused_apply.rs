@@ -0,0 +1,119 @@
+//! Tests of [`Runner::with_fused_apply`].
+//!
+//! These measure memory with a counting global allocator, so they live
+//! in their own test binary, apart from the tests it would slow down.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use egg::{rewrite as rw, SymbolLang as S, *};
+
+/// Counts the bytes each thread has allocated, so a test can measure
+/// its peak memory while other tests run.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = Cell::new(0);
+    static PEAK: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|allocated| {
+            let now = allocated.get() + layout.size();
+            allocated.set(now);
+            PEAK.try_with(|peak| peak.set(peak.get().max(now)))
+        });
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _ = ALLOCATED
+            .try_with(|allocated| allocated.set(allocated.get().saturating_sub(layout.size())));
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+/// Returns how many bytes more than before this thread had allocated
+/// at the peak of `f`.
+fn peak_bytes(f: impl FnOnce()) -> usize {
+    let base = ALLOCATED.with(Cell::get);
+    PEAK.with(|peak| peak.set(base));
+    f();
+    PEAK.with(Cell::get) - base
+}
+
+#[test]
+fn fused_apply_holds_fewer_matches() {
+    let rules: Vec<Rewrite<S, ()>> = vec![
+        rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+        rw!("assoc-add"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+    ];
+    let saturated = Runner::default()
+        .with_scheduler(SimpleScheduler)
+        .with_node_limit(100_000)
+        .with_time_limit(std::time::Duration::from_secs(60))
+        .with_expr(&"(+ a (+ b (+ c (+ d (+ e (+ f g))))))".parse().unwrap())
+        .run(&rules);
+    assert!(matches!(saturated.stop_reason, Some(StopReason::Saturated)));
+
+    // every match is redundant now, so the egraph doesn't grow and the
+    // peak is all matches
+    let run = |fused| {
+        let egraph = saturated.egraph.clone();
+        let mut runner = None;
+        let peak = peak_bytes(|| {
+            let r = Runner::default()
+                .with_egraph(egraph)
+                .with_scheduler(SimpleScheduler)
+                .with_iter_limit(1);
+            let r = if fused {
+                r.with_fused_batch_size(100)
+            } else {
+                r
+            };
+            runner = Some(r.run(&rules));
+        });
+        (runner.unwrap(), peak)
+    };
+    let (two_phase, two_phase_peak) = run(false);
+    let (fused, fused_peak) = run(true);
+
+    for runner in &[&two_phase, &fused] {
+        assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+        assert!(runner.iterations[0].applied.is_empty());
+        assert_eq!(runner.egraph.total_size(), saturated.egraph.total_size());
+    }
+    let (two_phase, fused) = (two_phase.export_rule_stats(), fused.export_rule_stats());
+    for rw in &rules {
+        let name = rw.name.as_str();
+        assert_eq!(
+            two_phase.get(name).unwrap().matches,
+            fused.get(name).unwrap().matches
+        );
+        assert_eq!(fused.get(name).unwrap().iterations, 1);
+    }
+    assert!(fused_peak < two_phase_peak);
+}
+
+#[test]
+fn fused_backoff_applies_at_most_the_match_limit() {
+    let rules: Vec<Rewrite<S, ()>> = vec![rw!("f-to-g"; "(f ?a)" => "(g ?a)")];
+    let mut runner = Runner::default()
+        .with_scheduler(BackoffScheduler::default().with_initial_match_limit(2))
+        .with_fused_batch_size(1)
+        .with_iter_limit(1);
+    for x in &["a", "b", "c", "d"] {
+        runner = runner.with_expr(&format!("(f {})", x).parse().unwrap());
+    }
+    let runner = runner.run(&rules);
+
+    // the third match only shows there are too many, and bans the rule
+    let applied = runner.iterations[0].applied.get(&Symbol::from("f-to-g"));
+    assert_eq!(applied, Some(&2));
+    let stats = runner.export_rule_stats();
+    assert_eq!(stats.get("f-to-g").unwrap().matches, 3);
+}
@@ -116,12 +116,15 @@ fn var(s: &str) -> Var {
     s.parse().unwrap()
 }
 
-fn is_not_same_var(v1: Var, v2: Var) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+fn is_not_same_var(
+    v1: Var,
+    v2: Var,
+) -> impl Fn(&dyn EGraphView<Lambda, LambdaAnalysis>, Id, &Subst) -> bool {
     move |egraph, _, subst| egraph.find(subst[v1]) != egraph.find(subst[v2])
 }
 
-fn is_const(v: Var) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
-    move |egraph, _, subst| egraph[subst[v]].data.constant.is_some()
+fn is_const(v: Var) -> impl Fn(&dyn EGraphView<Lambda, LambdaAnalysis>, Id, &Subst) -> bool {
+    move |egraph, _, subst| egraph.data(subst[v]).constant.is_some()
 }
 
 fn rules() -> Vec<Rewrite<Lambda, LambdaAnalysis>> {
@@ -1,5 +1,6 @@
 use egg::{rewrite as rw, *};
 use ordered_float::NotNan;
+use std::collections::BTreeMap;
 
 pub type EGraph = egg::EGraph<Math, ConstantFold>;
 pub type Rewrite = egg::Rewrite<Math, ConstantFold>;
@@ -45,106 +46,140 @@ impl egg::CostFunction<Math> for MathCostFn {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ConstantFold;
-impl Analysis<Math> for ConstantFold {
-    type Data = Option<(Constant, PatternAst<Math>)>;
-
-    fn make(egraph: &EGraph, enode: &Math) -> Self::Data {
-        let x = |i: &Id| egraph[*i].data.as_ref().map(|d| d.0);
-        Some(match enode {
-            Math::Constant(c) => (*c, format!("{}", c).parse().unwrap()),
-            Math::Add([a, b]) => (
-                x(a)? + x(b)?,
-                format!("(+ {} {})", x(a)?, x(b)?).parse().unwrap(),
-            ),
-            Math::Sub([a, b]) => (
-                x(a)? - x(b)?,
-                format!("(- {} {})", x(a)?, x(b)?).parse().unwrap(),
-            ),
-            Math::Mul([a, b]) => (
-                x(a)? * x(b)?,
-                format!("(* {} {})", x(a)?, x(b)?).parse().unwrap(),
-            ),
-            Math::Div([a, b]) if x(b) != Some(NotNan::new(0.0).unwrap()) => (
-                x(a)? / x(b)?,
-                format!("(/ {} {})", x(a)?, x(b)?).parse().unwrap(),
-            ),
-            _ => return None,
-        })
+
+/// A constant and the expression it was folded from, which are equal
+/// if the constants are, so joining the data of two eclasses that fold
+/// to the same constant from different expressions keeps one of them.
+#[derive(Debug, Clone)]
+pub struct Folded(pub Constant, pub PatternAst<Math>);
+
+impl PartialEq for Folded {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
     }
+}
 
-    fn merge(&mut self, a: &mut Self::Data, b: Self::Data) -> DidMerge {
-        match (a.as_mut(), &b) {
-            (None, None) => DidMerge(false, false),
-            (None, Some(_)) => {
-                *a = b;
-                DidMerge(true, false)
+impl Eq for Folded {}
+
+define_analysis! {
+    #[derive(Debug, Clone)]
+    pub struct FoldData {
+        /// The constant an eclass folds to, and the expression it folded.
+        pub constant: Option<Folded> = make_constant, fold_constant;
+    }
+    impl Analysis<Math> for ConstantFold {
+        fn distinct(a: &FoldData, b: &FoldData) -> bool {
+            matches!((&a.constant, &b.constant), (Some(x), Some(y)) if x != y)
+        }
+
+        fn canonicalize_node(egraph: &EGraph, enode: &Math) -> Option<Math> {
+            // + and * are commutative, so put the smaller child first
+            let swap = |[a, b]: &[Id; 2]| {
+                let (a, b) = (egraph.find(*a), egraph.find(*b));
+                if b < a {
+                    Some([b, a])
+                } else {
+                    None
+                }
+            };
+            match enode {
+                Math::Add(children) => swap(children).map(Math::Add),
+                Math::Mul(children) => swap(children).map(Math::Mul),
+                _ => None,
             }
-            (Some(_), None) => DidMerge(false, true),
-            (Some(_), Some(_)) => DidMerge(false, false),
         }
-        // if a.is_none() && b.is_some() {
-        //     *a = b
-        // }
-        // cmp
     }
+}
 
-    fn modify(egraph: &mut EGraph, id: Id) {
-        let class = egraph[id].clone();
-        if let Some((c, pat)) = class.data {
-            if egraph.are_explanations_enabled() {
-                egraph.union_instantiations(
-                    &pat,
-                    &format!("{}", c).parse().unwrap(),
-                    &Default::default(),
-                    "constant_fold".to_string(),
-                );
-            } else {
-                let added = egraph.add(Math::Constant(c));
-                egraph.union(id, added);
-            }
-            // to not prune, comment this out
-            egraph[id].nodes.retain(|n| n.is_leaf());
+fn make_constant(egraph: &EGraph, enode: &Math) -> Option<Folded> {
+    let x = |i: &Id| egraph[*i].data.constant.as_ref().map(|d| d.0);
+    let (c, folded) = match enode {
+        Math::Constant(c) => (*c, format!("{}", c).parse().unwrap()),
+        Math::Add([a, b]) => (
+            x(a)? + x(b)?,
+            format!("(+ {} {})", x(a)?, x(b)?).parse().unwrap(),
+        ),
+        Math::Sub([a, b]) => (
+            x(a)? - x(b)?,
+            format!("(- {} {})", x(a)?, x(b)?).parse().unwrap(),
+        ),
+        Math::Mul([a, b]) => (
+            x(a)? * x(b)?,
+            format!("(* {} {})", x(a)?, x(b)?).parse().unwrap(),
+        ),
+        Math::Div([a, b]) if x(b) != Some(NotNan::new(0.0).unwrap()) => (
+            x(a)? / x(b)?,
+            format!("(/ {} {})", x(a)?, x(b)?).parse().unwrap(),
+        ),
+        _ => return None,
+    };
+    Some(Folded(c, folded))
+}
 
-            #[cfg(debug_assertions)]
-            egraph[id].assert_unique_leaves();
+fn fold_constant(egraph: &mut EGraph, id: Id) {
+    let class = egraph[id].clone();
+    if let Some(Folded(c, pat)) = class.data.constant {
+        if egraph.are_explanations_enabled() {
+            egraph.union_instantiations(
+                &pat,
+                &format!("{}", c).parse().unwrap(),
+                &Default::default(),
+                "constant_fold".to_string(),
+            );
+        } else {
+            let added = egraph.add(Math::Constant(c));
+            egraph.union(id, added);
         }
+        // to not prune, comment this out; a frozen eclass keeps out the
+        // constant, and then it has no leaf to keep
+        if egraph.lookup(Math::Constant(c)) == Some(egraph.find(id)) {
+            egraph.retain_nodes(id, |n| n.is_leaf());
+        }
+
+        #[cfg(debug_assertions)]
+        egraph[id].assert_unique_leaves();
     }
 }
 
-fn is_const_or_distinct_var(v: &str, w: &str) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+fn is_const_or_distinct_var(
+    v: &str,
+    w: &str,
+) -> impl Fn(&dyn EGraphView<Math, ConstantFold>, Id, &Subst) -> bool {
     let v = v.parse().unwrap();
     let w = w.parse().unwrap();
     move |egraph, _, subst| {
         egraph.find(subst[v]) != egraph.find(subst[w])
-            && (egraph[subst[v]].data.is_some()
-                || egraph[subst[v]]
+            && (egraph.data(subst[v]).constant.is_some()
+                || egraph
+                    .class(subst[v])
                     .nodes
                     .iter()
                     .any(|n| matches!(n, Math::Symbol(..))))
     }
 }
 
-fn is_const(var: &str) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+fn is_const(var: &str) -> impl Fn(&dyn EGraphView<Math, ConstantFold>, Id, &Subst) -> bool {
     let var = var.parse().unwrap();
-    move |egraph, _, subst| egraph[subst[var]].data.is_some()
+    move |egraph, _, subst| egraph.data(subst[var]).constant.is_some()
 }
 
-fn is_sym(var: &str) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+fn is_sym(var: &str) -> impl Fn(&dyn EGraphView<Math, ConstantFold>, Id, &Subst) -> bool {
     let var = var.parse().unwrap();
     move |egraph, _, subst| {
-        egraph[subst[var]]
+        egraph
+            .class(subst[var])
             .nodes
             .iter()
             .any(|n| matches!(n, Math::Symbol(..)))
     }
 }
 
-fn is_not_zero(var: &str) -> impl Fn(&mut EGraph, Id, &Subst) -> bool {
+fn is_not_zero(var: &str) -> impl Fn(&dyn EGraphView<Math, ConstantFold>, Id, &Subst) -> bool {
     let var = var.parse().unwrap();
     move |egraph, _, subst| {
-        if let Some(n) = &egraph[subst[var]].data {
+        if let Some(n) = &egraph.data(subst[var]).constant {
             *(n.0) != 0.0
         } else {
             true
@@ -219,6 +254,94 @@ pub fn rules() -> Vec<Rewrite> { vec![
         "(- (* ?a (i ?b ?x)) (i (* (d ?x ?a) (i ?b ?x)) ?x))"),
 ]}
 
+// the unconditional rules from `rules`, in the rule file format
+const RULES_FILE: &str = "
+# algebra
+comm-add:  (+ ?a ?b)        => (+ ?b ?a)
+comm-mul:  (* ?a ?b)        => (* ?b ?a)
+assoc-add: (+ ?a (+ ?b ?c)) => (+ (+ ?a ?b) ?c)
+assoc-mul: (* ?a (* ?b ?c)) => (* (* ?a ?b) ?c)
+sub-canon: (- ?a ?b)        => (+ ?a (* -1 ?b))
+
+zero-add: (+ ?a 0) => ?a
+zero-mul: (* ?a 0) => 0
+one-mul:  (* ?a 1) => ?a
+add-zero: ?a => (+ ?a 0)
+mul-one:  ?a => (* ?a 1)
+
+cancel-sub: (- ?a ?a) => 0
+distribute: (* ?a (+ ?b ?c))        => (+ (* ?a ?b) (* ?a ?c))
+factor:     (+ (* ?a ?b) (* ?a ?c)) => (* ?a (+ ?b ?c))
+
+pow-mul: (* (pow ?a ?b) (pow ?a ?c)) => (pow ?a (+ ?b ?c))
+pow1: (pow ?x 1) => ?x
+pow2: (pow ?x 2) => (* ?x ?x)
+
+# calculus
+d-add: (d ?x (+ ?a ?b)) => (+ (d ?x ?a) (d ?x ?b))
+d-mul: (d ?x (* ?a ?b)) => (+ (* ?a (d ?x ?b)) (* ?b (d ?x ?a)))
+d-sin: (d ?x (sin ?x)) => (cos ?x)
+d-cos: (d ?x (cos ?x)) => (* -1 (sin ?x))
+
+i-one: (i 1 ?x) => ?x
+i-cos: (i (cos ?x) ?x) => (sin ?x)
+i-sin: (i (sin ?x) ?x) => (* -1 (cos ?x))
+i-sum: (i (+ ?f ?g) ?x) => (+ (i ?f ?x) (i ?g ?x))
+i-dif: (i (- ?f ?g) ?x) => (- (i ?f ?x) (i ?g ?x))
+i-parts: (i (* ?a ?b) ?x) => (- (* ?a (i ?b ?x)) (i (* (d ?x ?a) (i ?b ?x)) ?x))
+";
+
+fn file_rules() -> Vec<Rewrite> {
+    parse_rules(RULES_FILE).unwrap()
+}
+
+egg::test_fn! {file_math_simplify_add, file_rules(), "(+ x (+ x (+ x x)))" => "(* 4 x)" }
+egg::test_fn! {file_math_powers, file_rules(), "(* (pow 2 x) (pow 2 y))" => "(pow 2 (+ x y))"}
+egg::test_fn! {file_integ_one, file_rules(), "(i 1 x)" => "x"}
+
+#[test]
+fn rules_file_matches_macro_rules() {
+    let parsed = file_rules();
+    let names: Vec<Symbol> = parsed.iter().map(|rw| rw.name).collect();
+    let from_macro: Vec<Rewrite> = rules()
+        .into_iter()
+        .filter(|rw| names.contains(&rw.name))
+        .collect();
+    assert_eq!(
+        format_rules(&parsed).unwrap(),
+        format_rules(&from_macro).unwrap()
+    );
+
+    let expr: RecExpr<Math> = "(* (+ x 3) (+ x 1))".parse().unwrap();
+    let run = |rules: &[Rewrite]| {
+        Runner::default()
+            .with_iter_limit(5)
+            .with_expr(&expr)
+            .run(rules)
+            .egraph
+    };
+    let (a, b) = (run(&parsed), run(&from_macro));
+    assert_eq!(a.total_size(), b.total_size());
+    assert_eq!(a.number_of_classes(), b.number_of_classes());
+
+    let text = format_rules(&parsed).unwrap();
+    let reparsed: Vec<Rewrite> = parse_rules(&text).unwrap();
+    assert_eq!(format_rules(&reparsed).unwrap(), text);
+}
+
+#[test]
+fn rules_file_errors() {
+    let err = parse_rules::<Math, ConstantFold>("ok: (+ ?a 0) => ?a\nbad: (+ ?a 0) => (foo ?a)")
+        .unwrap_err();
+    assert_eq!((err.line, err.column), (2, 18));
+
+    let err = parse_rules::<Math, ConstantFold>("bad: (+ ?a 0) => ?a if cond-equal").unwrap_err();
+    assert_eq!((err.line, err.column), (1, 34));
+
+    let err = parse_rules::<Math, ConstantFold>("bad: (+ ?a 0 == ?a").unwrap_err();
+    assert_eq!((err.line, err.column), (1, 6));
+}
+
 egg::test_fn! {
     math_associate_adds, [
         rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
@@ -233,6 +356,36 @@ egg::test_fn! {
     @check |r: Runner<Math, ()>| assert_eq!(r.egraph.number_of_classes(), 127)
 }
 
+#[test]
+fn math_backoff_tames_assoc_comm() {
+    let rules: Vec<Rewrite> = vec![
+        rw!("comm-add"; "(+ ?a ?b)" => "(+ ?b ?a)"),
+        rw!("assoc-add"; "(+ ?a (+ ?b ?c))" => "(+ (+ ?a ?b) ?c)"),
+        rw!("zero-add"; "(+ ?a 0)" => "?a"),
+    ];
+    let expr = "(+ a (+ b (+ 0 (+ c (+ d (+ e (+ f (+ g (+ h 0)))))))))";
+    let run = |runner: Runner<Math, ConstantFold>| {
+        runner
+            .with_iter_limit(10)
+            .with_node_limit(100_000)
+            .with_time_limit(std::time::Duration::from_secs(60))
+            .with_expr(&expr.parse().unwrap())
+            .run(&rules)
+    };
+    let simple = run(Runner::default().with_scheduler(SimpleScheduler));
+    let backoff = run(Runner::default().with_scheduler(BackoffScheduler::default()));
+
+    // both drop the zeros, and the rest is the same size however it's
+    // associated
+    for runner in &[&simple, &backoff] {
+        let extractor = Extractor::new(&runner.egraph, AstSize);
+        let (cost, best) = extractor.find_best(runner.roots[0]);
+        assert_eq!(cost, 15, "{}", best);
+    }
+    // but backoff stops the explosion early
+    assert!(2 * backoff.egraph.total_size() < simple.egraph.total_size());
+}
+
 egg::test_fn! {
     #[should_panic(expected = "Could not prove goal 0")]
     math_fail, rules(),
@@ -329,6 +482,300 @@ fn assoc_mul_saturates() {
     assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
 }
 
+#[test]
+fn canonical_reuse_creates_fewer_classes() {
+    let exprs = &[
+        "(+ (* y (+ x y)) (- (+ x 2) (+ x x)))",
+        "(d x (- (pow x 3) (* 7 (pow x 2))))",
+        "(+ a (+ b (+ c (* d (* e f)))))",
+    ];
+    let run = |expr: &str, canonical_reuse: bool, iters: usize| {
+        Runner::default()
+            .with_egraph(EGraph::default().with_canonical_reuse(canonical_reuse))
+            .with_iter_limit(iters)
+            .with_expr(&expr.parse().unwrap())
+            .run(&rules())
+    };
+    // every new enode gets the next ENodeId, so this is about how many
+    // classes were ever created
+    let created = |runner: &Runner<Math, ConstantFold>| {
+        let egraph = &runner.egraph;
+        let ids = egraph
+            .classes()
+            .flat_map(|c| c.nodes_with_ids().map(|(id, _)| id));
+        usize::from(ids.max().unwrap()) + 1
+    };
+
+    for expr in exprs {
+        let mut saved = vec![];
+        for iters in 1..=5 {
+            let plain = run(expr, false, iters);
+            let reuse = run(expr, true, iters);
+            assert!(created(&reuse) <= created(&plain));
+            saved.push(created(&plain) - created(&reuse));
+
+            // merging the fresh classes would have found the same thing
+            let last = |r: &Runner<Math, ConstantFold>| r.iterations.last().unwrap().egraph_classes;
+            assert_eq!(last(&plain), last(&reuse));
+        }
+        assert!(saved.iter().any(|&n| n > 0));
+    }
+
+    // the same equivalences are found
+    let goals = &[
+        ("(+ x (+ x (+ x x)))", "(* 4 x)"),
+        ("(* (pow 2 x) (pow 2 y))", "(pow 2 (+ x y))"),
+        ("(d x (+ 1 (* 2 x)))", "2"),
+        ("(i (cos x) x)", "(sin x)"),
+    ];
+    for (start, goal) in goals {
+        let start = start.parse().unwrap();
+        let goal = goal.parse().unwrap();
+        let runner = Runner::default()
+            .with_egraph(EGraph::default().with_canonical_reuse(true))
+            .with_expr(&start)
+            .run(&rules());
+        let (egraph, root) = (&runner.egraph, runner.roots[0]);
+        assert_eq!(egraph.lookup_expr(&goal), Some(egraph.find(root)));
+    }
+}
+
+#[test]
+fn folded_constants_are_distinct() {
+    let runner: Runner<Math, ConstantFold> = Runner::default()
+        .with_egraph(EGraph::default().with_distinctness_checks(true))
+        .with_expr(&"(+ 1 2)".parse().unwrap())
+        .with_expr(&"(* 2 2)".parse().unwrap())
+        .with_expr(&"(+ x 3)".parse().unwrap())
+        .run(&rules());
+    let prove = |a: &str, b: &str| {
+        let (a, b) = (a.parse().unwrap(), b.parse().unwrap());
+        runner.egraph.prove_equiv(&a, &b)
+    };
+
+    assert_eq!(prove("(+ x 3)", "(+ 3 x)"), Equivalence::Equal);
+    assert_eq!(prove("(+ x 3)", "3"), Equivalence::Unknown);
+    assert_eq!(prove("(+ x 3)", "(+ y 3)"), Equivalence::Unknown);
+    match prove("3", "4") {
+        Equivalence::Distinct(witness) => {
+            assert_eq!(*witness.a_data.constant.unwrap().0, 3.0);
+            assert_eq!(*witness.b_data.constant.unwrap().0, 4.0);
+        }
+        other => panic!("3 and 4 should be distinct, got {:?}", other),
+    }
+}
+
+#[test]
+#[should_panic(expected = "rule 'one-is-two'")]
+fn distinctness_checks_catch_unsound_rules() {
+    let unsound: Rewrite = rw!("one-is-two"; "1" => "2");
+    Runner::<Math, ConstantFold>::default()
+        .with_egraph(EGraph::default().with_distinctness_checks(true))
+        .with_expr(&"(+ x 1)".parse().unwrap())
+        .run(&[unsound]);
+}
+
+#[test]
+fn history_grows_with_changes() {
+    for (start, goal) in &[
+        ("(d x (ln x))", "(/ 1 x)"),
+        ("(+ x (+ x (+ x x)))", "(* 4 x)"),
+    ] {
+        let runner: Runner<Math, ConstantFold> = Runner::default()
+            .with_history()
+            .with_expr(&start.parse().unwrap())
+            .run(&rules());
+        assert!(matches!(runner.stop_reason, Some(StopReason::Saturated)));
+        let history = runner.history.as_ref().unwrap();
+        let n = history.len();
+        assert_eq!(n, runner.iterations.len() + 1);
+
+        let sizes: Vec<(usize, usize)> = (0..n)
+            .map(|i| (history.snapshot_size(i), history.full_size(i)))
+            .collect();
+        let stored: usize = sizes.iter().map(|s| s.0).sum();
+        let full: usize = sizes.iter().map(|s| s.1).sum();
+        assert!(stored < full);
+        // the last iteration changes little
+        let (snapshot, full) = sizes[n - 1];
+        assert!(2 * snapshot < full);
+
+        let end = history.egraph_at(n - 1);
+        assert_eq!(end.number_of_classes(), runner.egraph.number_of_classes());
+        let goal = end.lookup_expr(&goal.parse().unwrap());
+        assert_eq!(goal, Some(end.find(runner.roots[0])));
+    }
+}
+
+#[test]
+fn op_stats_group_by_operator() {
+    let mut egraph = egg::EGraph::<Math, ()>::default();
+    let a = egraph.add_expr(&"(+ x (* 2 y))".parse().unwrap());
+    let b = egraph.add_expr(&"(+ (* 2 y) x)".parse().unwrap());
+    egraph.add_expr(&"(+ x 1)".parse().unwrap());
+    egraph.union(a, b);
+    egraph.rebuild();
+
+    let stats = egraph.op_stats();
+    let counts: Vec<(&str, usize, usize)> = stats
+        .iter()
+        .map(|(op, s)| (op.as_str(), s.nodes, s.classes))
+        .collect();
+    assert_eq!(
+        counts,
+        vec![
+            ("*", 1, 1),
+            ("+", 3, 2),
+            ("Constant", 2, 2),
+            ("Symbol", 2, 2),
+        ]
+    );
+}
+
+#[test]
+fn op_deltas_add_up_to_final_op_stats() {
+    let start: RecExpr<Math> = "(+ x (+ x (+ x x)))".parse().unwrap();
+    let mut initial = EGraph::default();
+    initial.add_expr(&start);
+    initial.rebuild();
+    let initial = initial.op_stats();
+
+    let runner: Runner<Math, ConstantFold> = Runner::default()
+        .with_history()
+        .with_expr(&start)
+        .run(&rules());
+    assert!(!runner.iterations[0].op_deltas.is_empty());
+
+    let mut totals: BTreeMap<String, isize> = initial
+        .iter()
+        .map(|(op, s)| (op.clone(), s.nodes as isize))
+        .collect();
+    for iter in &runner.iterations {
+        for (op, delta) in &iter.op_deltas {
+            *totals.entry(op.clone()).or_default() += delta.nodes;
+        }
+    }
+    totals.retain(|_, n| *n != 0);
+    let last = runner.egraph.op_stats();
+    let expected: BTreeMap<String, isize> = last
+        .iter()
+        .map(|(op, s)| (op.clone(), s.nodes as isize))
+        .collect();
+    assert_eq!(totals, expected);
+
+    let report = runner.report();
+    assert_eq!(report.top_ops.len(), last.len().min(10));
+    assert!(report
+        .top_ops
+        .windows(2)
+        .all(|w| w[0].1.nodes >= w[1].1.nodes));
+    assert!(report.to_string().contains("Top operators"));
+
+    let runner: Runner<Math, ConstantFold> = Runner::default().with_expr(&start).run(&rules());
+    assert!(runner.iterations.iter().all(|i| i.op_deltas.is_empty()));
+}
+
+#[test]
+fn exported_rule_stats_match_iterations() {
+    let runner: Runner<Math, ConstantFold> = Runner::default()
+        .with_expr(&"(+ x (+ x (+ x x)))".parse().unwrap())
+        .run(&rules());
+    let stats = runner.export_rule_stats();
+    assert_eq!(stats.runs, 1);
+    for rw in rules() {
+        let unions: usize = runner
+            .iterations
+            .iter()
+            .filter_map(|i| i.applied.get(&rw.name))
+            .sum();
+        let entry = stats.get(rw.name.as_str()).copied().unwrap_or_default();
+        assert_eq!(entry.unions, unions, "{}", rw.name);
+        assert!(entry.unions <= entry.matches);
+    }
+}
+
+#[test]
+fn math_redundant_matches_are_skipped() {
+    let ac = ["comm-add", "assoc-add", "comm-mul", "assoc-mul"];
+    let rules: Vec<Rewrite> = rules()
+        .into_iter()
+        .filter(|rw| ac.contains(&rw.name.as_str()))
+        .collect();
+    let start = "(+ a (+ b (* c (+ d (* e f)))))".parse().unwrap();
+    let run = |skip_redundant| {
+        Runner::default()
+            .with_explanations_disabled()
+            .with_scheduler(SimpleScheduler)
+            .with_redundant_match_skipping(skip_redundant)
+            .with_expr(&start)
+            .run(&rules)
+    };
+    let (plain, skipping) = (run(false), run(true));
+
+    assert!(matches!(skipping.stop_reason, Some(StopReason::Saturated)));
+    assert_eq!(plain.iterations.len(), skipping.iterations.len());
+    assert_eq!(plain.egraph.total_size(), skipping.egraph.total_size());
+    assert_eq!(
+        plain.egraph.number_of_classes(),
+        skipping.egraph.number_of_classes()
+    );
+    for (p, s) in plain.iterations.iter().zip(&skipping.iterations) {
+        assert_eq!(p.applied, s.applied);
+        assert!(p.redundant.is_empty());
+    }
+    // nothing is new in the saturating iteration, so every match is skipped
+    let last = skipping.iterations.last().unwrap();
+    assert!(last.applied.is_empty());
+    assert!(!last.redundant.is_empty());
+}
+
+#[test]
+fn math_match_arena_agrees_with_owned_matches() {
+    let rules = rules();
+    let egraph = Runner::default()
+        .with_iter_limit(egg::test::env_var("EGG_ITER_LIMIT").unwrap_or(4))
+        .with_expr(&"(d x (- (pow x 3) (* 7 (pow x 2))))".parse().unwrap())
+        .with_expr(&"(+ (* y (+ x y)) (- (+ x 2) (+ x x)))".parse().unwrap())
+        .run(&rules)
+        .egraph;
+    let (mut owned, mut arena_egraph) = (egraph.clone(), egraph);
+    let mut arena = MatchArena::new();
+
+    let owned_matches: Vec<_> = rules.iter().map(|rw| rw.search(&owned)).collect();
+    let arena_matches: Vec<_> = rules
+        .iter()
+        .map(|rw| rw.search_into(&arena_egraph, &mut arena))
+        .collect();
+
+    for (owned, in_arena) in owned_matches.iter().zip(&arena_matches) {
+        let copied: Vec<_> = in_arena
+            .iter()
+            .map(|m| m.to_search_matches(&arena))
+            .collect();
+        assert_eq!(owned.len(), copied.len());
+        for (a, b) in owned.iter().zip(&copied) {
+            assert_eq!((a.eclass, &a.substs), (b.eclass, &b.substs));
+        }
+    }
+
+    let owned_ids: Vec<Vec<Id>> = rules
+        .iter()
+        .zip(&owned_matches)
+        .map(|(rw, ms)| rw.apply(&mut owned, ms))
+        .collect();
+    let arena_ids: Vec<Vec<Id>> = rules
+        .iter()
+        .zip(&arena_matches)
+        .map(|(rw, ms)| rw.apply_from(&mut arena_egraph, &arena, ms))
+        .collect();
+
+    assert_eq!(owned_ids, arena_ids);
+    owned.rebuild();
+    arena_egraph.rebuild();
+    assert_eq!(owned.total_size(), arena_egraph.total_size());
+    assert_eq!(owned.number_of_classes(), arena_egraph.number_of_classes());
+}
+
 #[test]
 fn math_ematching_bench() {
     let exprs = &[
@@ -0,0 +1,78 @@
+use egg::{SymbolLang as S, *};
+
+#[cfg(not(all(feature = "tagged-ids", debug_assertions)))]
+#[test]
+fn untagged_ids_are_just_an_index() {
+    assert_eq!(std::mem::size_of::<Id>(), std::mem::size_of::<u32>());
+    assert_eq!(std::mem::size_of::<Option<Id>>(), 8);
+}
+
+#[cfg(all(feature = "tagged-ids", debug_assertions))]
+mod tagged {
+    use super::*;
+
+    /// Two egraphs with the same shape, and an id from the first.
+    fn two_egraphs() -> (EGraph<S, ()>, EGraph<S, ()>, Id) {
+        let mut a = EGraph::default();
+        let mut b = EGraph::default();
+        let x = a.add_expr(&"(f x)".parse().unwrap());
+        b.add_expr(&"(f x)".parse().unwrap());
+        a.rebuild();
+        b.rebuild();
+        (a, b, x)
+    }
+
+    #[test]
+    fn ids_work_in_their_own_egraph() {
+        let (mut a, b, x) = two_egraphs();
+        let y = a.add(S::leaf("y"));
+        assert!(a.union(x, y));
+        a.rebuild();
+        assert_eq!(a.find(x), a.find(y));
+        assert_eq!(a[x].len(), 2);
+
+        // tags don't change what an id is
+        assert_eq!(x, Id::from(usize::from(x)));
+        // untagged ids and clones are accepted
+        assert_eq!(b[Id::from(usize::from(x))].len(), 1);
+        let c = a.clone();
+        assert_eq!(c.find(y), a.find(y));
+    }
+
+    #[test]
+    #[should_panic(expected = "belongs to a different EGraph")]
+    fn indexing_with_another_egraphs_id_panics() {
+        let (_a, b, x) = two_egraphs();
+        let _ = &b[x];
+    }
+
+    #[test]
+    #[should_panic(expected = "belongs to a different EGraph")]
+    fn union_with_another_egraphs_id_panics() {
+        let (_a, mut b, x) = two_egraphs();
+        let y = b.add(S::leaf("y"));
+        b.union(y, x);
+    }
+
+    #[test]
+    #[should_panic(expected = "belongs to a different EGraph")]
+    fn adding_a_child_from_another_egraph_panics() {
+        let (_a, mut b, x) = two_egraphs();
+        b.add(S::new("g", vec![x]));
+    }
+
+    #[test]
+    #[should_panic(expected = "belongs to a different EGraph")]
+    fn searching_another_egraphs_id_panics() {
+        let (_a, b, x) = two_egraphs();
+        let pattern: Pattern<S> = "(f ?x)".parse().unwrap();
+        pattern.search_eclass(&b, x);
+    }
+
+    #[test]
+    #[should_panic(expected = "belongs to a different EGraph")]
+    fn extracting_another_egraphs_id_panics() {
+        let (_a, b, x) = two_egraphs();
+        Extractor::new(&b, AstSize).find_best(x);
+    }
+}